@@ -17,6 +17,7 @@ use crate::{
         paging::{flags::Flags, PageSize, PageTable, PhysicalAddress, VirtualAddress, SATP_MODE},
         phys::{PhysicalMemoryAllocator, PHYSICAL_MEMORY_ALLOCATOR},
     },
+    platform::devicetree::ReservedRegion,
     utils::{LinkerSymbol, Units},
 };
 
@@ -77,6 +78,29 @@ pub unsafe extern "C" fn early_paging(hart_id: usize, fdt: *const u8) -> ! {
         }
     }
 
+    // Firmware and devices can own memory inside of the usable region, so
+    // make sure it never gets handed out
+    for region in crate::platform::devicetree::reserved_memory(&fdt_struct).filter(ReservedRegion::is_exclusive) {
+        let region_start = (region.address as usize & !(4.kib() - 1)).max(kernel_end);
+        let region_end = ((region.address + region.size) as usize).min(start + size);
+
+        for page in (region_start..region_end).step_by(4.kib()) {
+            pf_alloc.set_used(crate::mem::phys::PhysicalPage::from_ptr(page as *mut u8));
+        }
+    }
+
+    // The initrd has to stay intact until it's been copied into `init`
+    if let Some(initrd) = crate::platform::devicetree::initrd(&fdt_struct) {
+        let initrd_start = (initrd.start & !(4.kib() - 1)).max(kernel_end);
+        let initrd_end = initrd.end.min(start + size);
+
+        for page in (initrd_start..initrd_end).step_by(4.kib()) {
+            pf_alloc.set_used(crate::mem::phys::PhysicalPage::from_ptr(page as *mut u8));
+        }
+
+        super::initrd::reserved(initrd_start..initrd_end);
+    }
+
     drop(pf_alloc);
 
     let mut root_page_table = PageTable::new_raw();
@@ -155,6 +179,12 @@ pub unsafe extern "C" fn early_paging(hart_id: usize, fdt: *const u8) -> ! {
         );
     }
 
+    // Kernel stacks are mapped after other page tables have copied the
+    // kernel's top level entries, so those entries need to exist up front
+    for addr in crate::mem::KERNEL_STACK_REGION.step_by(PageSize::top_level().to_byte_size()) {
+        root_page_table.static_reserve_branch(VirtualAddress::new(addr));
+    }
+
     // Need to leak the root page table here so it doesn't drop
     let root_pt_phys = root_page_table.physical_address();
     core::mem::forget(root_page_table);
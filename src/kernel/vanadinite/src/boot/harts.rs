@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::sync::SpinMutex;
+use core::sync::atomic::{AtomicU64, Ordering};
+use librust::task::HartMask;
+
+/// How long the boot hart waits for the secondary harts it started to reach
+/// `kalt` before giving up on them
+pub const HART_BOOT_TIMEOUT_US: u64 = 500_000;
+
+/// The most harts vanadinite can run on, since the set of running harts is
+/// tracked with a [`HartMask`]. Harts with higher IDs are never started.
+pub const MAX_HARTS: usize = 64;
+
+/// The secondary harts which have made it into `kalt`, and once the boot hart
+/// is done waiting on them, the harts which are running
+pub static BOOTED_HARTS: BootedHarts = BootedHarts::new();
+
+/// One more than the highest hart ID in `hart_ids` that vanadinite can run on,
+/// which is what per-hart state is sized by. Hart IDs don't have to be
+/// contiguous, so this can be larger than the number of harts.
+pub fn hart_id_bound(hart_ids: impl IntoIterator<Item = usize>) -> usize {
+    hart_ids.into_iter().filter(|id| *id < MAX_HARTS).max().map_or(1, |id| id + 1)
+}
+
+/// Tracks which secondary harts have booted, so the boot hart can tell a hart
+/// that failed to start apart from one that's running
+pub struct BootedHarts {
+    booted: AtomicU64,
+    /// The harts which are running, which only changes while booting
+    live: AtomicU64,
+    /// Whether the boot hart has stopped waiting, after which any harts that
+    /// show up are turned away
+    finished: SpinMutex<bool>,
+}
+
+impl BootedHarts {
+    pub const fn new() -> Self {
+        Self { booted: AtomicU64::new(0), live: AtomicU64::new(0), finished: SpinMutex::new(false) }
+    }
+
+    /// Mark `hart` as booted, returning `false` if it showed up too late and
+    /// has already been given up on, in which case it mustn't start scheduling
+    pub fn mark_booted(&self, hart: usize) -> bool {
+        let finished = self.finished.lock();
+        if *finished {
+            return false;
+        }
+
+        self.booted.fetch_or(HartMask::single(hart).value(), Ordering::Release);
+        self.live.fetch_or(HartMask::single(hart).value(), Ordering::Release);
+        true
+    }
+
+    pub fn booted(&self) -> HartMask {
+        HartMask::new(self.booted.load(Ordering::Acquire))
+    }
+
+    /// The harts which are running, including the boot hart once it's done
+    /// waiting for the others
+    pub fn live(&self) -> HartMask {
+        HartMask::new(self.live.load(Ordering::Acquire))
+    }
+
+    /// Stop waiting for harts to boot, returning the harts which are running
+    /// alongside `boot_hart`
+    pub fn finish(&self, boot_hart: usize) -> HartMask {
+        let mut finished = self.finished.lock();
+        *finished = true;
+        self.live.fetch_or(HartMask::single(boot_hart).value(), Ordering::Release);
+
+        self.live()
+    }
+
+    /// Wait for every hart in `started` to boot, or until `timeout` ticks of
+    /// `now` have passed, returning the harts which never showed up
+    pub fn wait_for(&self, started: HartMask, timeout: u64, mut now: impl FnMut() -> u64) -> HartMask {
+        let deadline = now().saturating_add(timeout);
+
+        loop {
+            let missing = HartMask::new(started.value() & !self.booted().value());
+            if missing.is_empty() || now() >= deadline {
+                return missing;
+            }
+
+            core::hint::spin_loop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_hart_is_reported_missing() {
+        let harts = BootedHarts::new();
+        let started = HartMask::empty().with(1).with(2).with(3);
+
+        // Hart 2 never makes it to `kalt`
+        harts.mark_booted(1);
+        harts.mark_booted(3);
+
+        let mut ticks = 0;
+        let missing = harts.wait_for(started, 100, || {
+            ticks += 1;
+            ticks
+        });
+
+        assert_eq!(missing, HartMask::single(2));
+        assert!(ticks > 100);
+        assert_eq!(harts.booted(), HartMask::empty().with(1).with(3));
+    }
+
+    #[test]
+    fn booted_harts_dont_wait_for_timeout() {
+        let harts = BootedHarts::new();
+        let started = HartMask::empty().with(1).with(2);
+        harts.mark_booted(1);
+        harts.mark_booted(2);
+
+        let mut ticks = 0;
+        let missing = harts.wait_for(started, u64::MAX, || {
+            ticks += 1;
+            ticks
+        });
+
+        assert!(missing.is_empty());
+        assert_eq!(ticks, 1);
+    }
+
+    #[test]
+    fn late_harts_are_turned_away() {
+        let harts = BootedHarts::new();
+        assert!(harts.mark_booted(3));
+        assert_eq!(harts.finish(0), HartMask::empty().with(0).with(3));
+
+        // Hart 5 only shows up after the boot hart gave up on it
+        assert!(!harts.mark_booted(5));
+        assert_eq!(harts.live(), HartMask::empty().with(0).with(3));
+        assert_eq!(harts.booted(), HartMask::single(3));
+    }
+
+    #[test]
+    fn hart_id_bounds() {
+        // Sparse hart IDs are bounded by the highest one, not the count
+        assert_eq!(hart_id_bound([0, 4, 2]), 5);
+        assert_eq!(hart_id_bound([0, 63, 64, 100]), 64);
+        assert_eq!(hart_id_bound([]), 1);
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An initrd loaded by the bootloader (e.g. QEMU's `-initrd`), which `init`
+//! uses in place of the servers it was built with, so userspace changes don't
+//! need a rebuild of `init` and the kernel along with it
+
+use crate::{
+    mem::{
+        paging::PhysicalAddress,
+        phys::{PhysicalMemoryAllocator, PhysicalPage, PHYSICAL_MEMORY_ALLOCATOR},
+        phys2virt,
+    },
+    sync::SpinMutex,
+    utils::{round_up_to_next, Units},
+};
+use core::ops::Range;
+use fdt::Fdt;
+
+const USTAR_MAGIC_OFFSET: usize = 257;
+const USTAR_MAGIC: &[u8] = b"ustar\0";
+
+/// The physical memory `early_paging` kept the allocator from handing out
+/// because it holds the initrd, until [`release`] gives it back
+static RESERVED: SpinMutex<Option<Range<usize>>> = SpinMutex::new(None);
+
+/// The initrd described by `/chosen`, if there is one and it's a tar archive
+pub fn find(fdt: &Fdt<'_>) -> Option<&'static [u8]> {
+    let range = crate::platform::devicetree::initrd(fdt)?;
+    let initrd = unsafe { from_physical(range.clone()) };
+
+    match is_tar_archive(initrd) {
+        true => Some(initrd),
+        false => {
+            log::warn!("Ignoring initrd @ {:#x}..{:#x}, it isn't a tar archive", range.start, range.end);
+            None
+        }
+    }
+}
+
+/// # Safety
+///
+/// `range` must be memory which isn't handed out by the physical memory
+/// allocator, which `early_paging` makes sure of for the initrd
+unsafe fn from_physical(range: Range<usize>) -> &'static [u8] {
+    let start = phys2virt(PhysicalAddress::new(range.start));
+    unsafe { core::slice::from_raw_parts(start.as_ptr(), range.end - range.start) }
+}
+
+/// Record that the pages in `range` were marked as used to keep the initrd
+/// intact
+pub(super) fn reserved(range: Range<usize>) {
+    *RESERVED.lock() = Some(range);
+}
+
+/// Give the pages holding the initrd back to the physical memory allocator,
+/// once `init` has its own copy of it. Pages the initrd only partially covers
+/// can be shared with something else, so they stay reserved.
+///
+/// # Safety
+///
+/// Nothing returned by [`find`] can be used after this
+pub unsafe fn release() {
+    let Some(range) = RESERVED.lock().take() else { return };
+
+    let mut pf_alloc = PHYSICAL_MEMORY_ALLOCATOR.lock();
+    for page in whole_pages(range).step_by(4.kib()) {
+        unsafe { pf_alloc.set_unused(PhysicalPage::from_ptr(page as *mut u8)) };
+    }
+}
+
+/// The pages which lie entirely within `range`
+fn whole_pages(range: Range<usize>) -> Range<usize> {
+    let start = round_up_to_next(range.start, 4.kib());
+    let end = range.end & !(4.kib() - 1);
+
+    start..end.max(start)
+}
+
+fn is_tar_archive(bytes: &[u8]) -> bool {
+    bytes.get(USTAR_MAGIC_OFFSET..USTAR_MAGIC_OFFSET + USTAR_MAGIC.len()) == Some(USTAR_MAGIC)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tar_magic() {
+        let mut header = [0u8; 512];
+        assert!(!is_tar_archive(&header));
+        assert!(!is_tar_archive(&header[..260]));
+
+        header[USTAR_MAGIC_OFFSET..][..USTAR_MAGIC.len()].copy_from_slice(USTAR_MAGIC);
+        assert!(is_tar_archive(&header));
+
+        // The old GNU format isn't understood by `init`
+        header[USTAR_MAGIC_OFFSET..][..8].copy_from_slice(b"ustar  \0");
+        assert!(!is_tar_archive(&header));
+    }
+
+    #[test]
+    fn only_whole_pages_are_released() {
+        assert_eq!(whole_pages(0x8400_0000..0x8420_0000), 0x8400_0000..0x8420_0000);
+        assert_eq!(whole_pages(0x8400_0800..0x8400_3800), 0x8400_1000..0x8400_3000);
+
+        // An initrd within a single page doesn't take up any page by itself
+        assert!(whole_pages(0x8400_0100..0x8400_0200).is_empty());
+        assert!(whole_pages(0x8400_0800..0x8400_1800).is_empty());
+    }
+}
@@ -7,3 +7,5 @@
 
 pub mod early_paging;
 pub mod entry;
+pub mod harts;
+pub mod initrd;
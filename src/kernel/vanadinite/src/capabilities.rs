@@ -14,8 +14,12 @@ use crate::{
     syscall::channel::UserspaceChannel,
 };
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 use core::ops::Range;
-use librust::capabilities::{CapabilityPtr, CapabilityRights};
+use librust::{
+    capabilities::{CapabilityDescription, CapabilityPtr, CapabilityRights, CapabilityWithDescription},
+    syscalls::mem::MemoryPermissions,
+};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Occupied;
@@ -76,6 +80,18 @@ impl CapabilitySpace {
     pub fn all(&self) -> impl Iterator<Item = (&CapabilityPtr, &Capability)> {
         self.inner.iter()
     }
+
+    /// A snapshot of every capability in the space along with its
+    /// description, in ascending [`CapabilityPtr`] order
+    pub fn describe_all(&self) -> Vec<CapabilityWithDescription> {
+        self.inner
+            .iter()
+            .map(|(&cptr, capability)| CapabilityWithDescription {
+                capability: librust::capabilities::Capability { cptr, rights: capability.rights },
+                description: capability.description(),
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -84,9 +100,116 @@ pub struct Capability {
     pub rights: CapabilityRights,
 }
 
+impl Capability {
+    /// Describe the capability in the form userspace receives it, as the
+    /// address range for memory capabilities or the kind of resource otherwise
+    pub fn description(&self) -> CapabilityDescription {
+        match &self.resource {
+            CapabilityResource::Channel(_) => CapabilityDescription::Channel,
+            CapabilityResource::SharedMemory(_, range, _) => {
+                let mut permissions = MemoryPermissions::new(0);
+
+                if self.rights & CapabilityRights::READ {
+                    permissions |= MemoryPermissions::READ;
+                }
+
+                if self.rights & CapabilityRights::WRITE {
+                    permissions |= MemoryPermissions::WRITE;
+                }
+
+                if self.rights & CapabilityRights::EXECUTE {
+                    permissions |= MemoryPermissions::EXECUTE;
+                }
+
+                CapabilityDescription::Memory {
+                    ptr: range.start.as_mut_ptr(),
+                    len: range.end.as_usize() - range.start.as_usize(),
+                    permissions,
+                }
+            }
+            CapabilityResource::Mmio(_, range, interrupts) => CapabilityDescription::MappedMmio {
+                ptr: range.start.as_mut_ptr(),
+                len: range.end.as_usize() - range.start.as_usize(),
+                n_interrupts: interrupts.len(),
+            },
+            CapabilityResource::SystemReset => CapabilityDescription::SystemReset,
+            CapabilityResource::KernelLog => CapabilityDescription::KernelLog,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum CapabilityResource {
     Channel(UserspaceChannel),
     SharedMemory(SharedPhysicalRegion, Range<VirtualAddress>, AddressRegionKind),
     Mmio(Range<PhysicalAddress>, Range<VirtualAddress>, alloc::vec::Vec<usize>),
+    /// Permission to reboot or power off the system, which only `init` starts
+    /// out with
+    SystemReset,
+    /// Permission to read the kernel log, which only `init` starts out with
+    KernelLog,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::{
+        manager::{FillOption, RegionDescription, UserspaceMemoryManager},
+        paging::{flags::Flags, PageSize},
+    };
+
+    #[test]
+    fn enumerate_capabilities() {
+        let mut memory_manager = UserspaceMemoryManager::new();
+        let mut cspace = CapabilitySpace::new();
+        let mut other_cspace = CapabilitySpace::new();
+
+        let (range, region) = memory_manager.alloc_shared_region(
+            None,
+            RegionDescription {
+                size: PageSize::Kilopage,
+                count: 2,
+                contiguous: false,
+                flags: Flags::VALID | Flags::USER | Flags::READ,
+                fill: FillOption::Zeroed,
+                kind: AddressRegionKind::UserSharedMemory,
+            },
+        );
+
+        let (channel, other_channel) = UserspaceChannel::new(1);
+        let channel_cptr =
+            cspace.mint(Capability { resource: CapabilityResource::Channel(channel), rights: CapabilityRights::READ });
+        let memory_cptr = cspace.mint(Capability {
+            resource: CapabilityResource::SharedMemory(region, range.clone(), AddressRegionKind::UserSharedMemory),
+            rights: CapabilityRights::READ,
+        });
+        other_cspace.mint(Capability {
+            resource: CapabilityResource::Channel(other_channel),
+            rights: CapabilityRights::READ | CapabilityRights::WRITE,
+        });
+
+        let described = cspace.describe_all();
+        assert_eq!(described.len(), 2);
+
+        assert_eq!(described[0].capability.cptr, channel_cptr);
+        assert_eq!(described[0].capability.rights, CapabilityRights::READ);
+        assert!(matches!(described[0].description, CapabilityDescription::Channel));
+
+        assert_eq!(described[1].capability.cptr, memory_cptr);
+        match described[1].description {
+            CapabilityDescription::Memory { ptr, len, permissions } => {
+                assert_eq!(ptr, range.start.as_mut_ptr());
+                assert_eq!(len, 2 * 4096);
+                assert_eq!(permissions, MemoryPermissions::READ);
+            }
+            description => panic!("unexpected description: {description:?}"),
+        }
+
+        // Another task's capabilities are never included
+        assert_eq!(other_cspace.describe_all().len(), 1);
+
+        core::mem::forget(memory_manager);
+        core::mem::forget(cspace);
+        core::mem::forget(other_cspace);
+    }
 }
@@ -40,6 +40,12 @@ pub mod sip {
 
         val
     }
+
+    /// Acknowledge a pending supervisor software interrupt (IPI)
+    #[inline(always)]
+    pub fn clear_ssip() {
+        unsafe { asm!("csrci sip, 2") };
+    }
 }
 
 pub mod sstatus {
@@ -128,6 +134,33 @@ pub mod cycle {
     }
 }
 
+/// Controls which of the hardware performance counters can be read from
+/// U-mode. The counters are per-hart, so this needs to be set on every hart.
+pub mod scounteren {
+    use core::arch::asm;
+
+    /// Allow reading the `cycle` CSR
+    pub const CYCLE: usize = 1 << 0;
+    /// Allow reading the `time` CSR
+    pub const TIME: usize = 1 << 1;
+    /// Allow reading the `instret` CSR
+    pub const INSTRET: usize = 1 << 2;
+
+    #[inline(always)]
+    pub fn read() -> usize {
+        let value: usize;
+
+        unsafe { asm!("csrr {}, scounteren", out(reg) value) };
+
+        value
+    }
+
+    #[inline(always)]
+    pub fn write(value: usize) {
+        unsafe { asm!("csrw scounteren, {}", in(reg) value) };
+    }
+}
+
 pub mod sscratch {
     use core::arch::asm;
     pub fn read() -> usize {
@@ -8,22 +8,32 @@
 use crate::{
     drivers::{generic::uart16550::Uart16550, sifive::fu540_c000::uart::SifiveUart, CompatibleWith},
     interrupts::isr::register_isr,
+    mem::{
+        paging::{PhysicalAddress, VirtualAddress},
+        virt2phys,
+    },
+    platform::debug_console,
     sync::SpinMutex,
     utils::SameHartDeadlockDetection,
 };
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
 
 pub trait ConsoleDevice: 'static {
     fn init(&mut self);
     fn read(&self) -> u8;
     fn write(&mut self, n: u8);
-}
 
-impl core::fmt::Write for dyn ConsoleDevice {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        for byte in s.as_bytes() {
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
             self.write(*byte);
         }
+    }
+}
 
+impl core::fmt::Write for dyn ConsoleDevice {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
         Ok(())
     }
 }
@@ -32,9 +42,16 @@ pub struct StaticConsoleDevice(Option<&'static mut dyn ConsoleDevice>);
 
 impl core::fmt::Write for StaticConsoleDevice {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        if let Some(console) = &mut self.0 {
-            for byte in s.as_bytes() {
-                console.write(*byte);
+        match &mut self.0 {
+            Some(console) => console.write_bytes(s.as_bytes()),
+            // Before a console has been set up, send early boot output to the
+            // SBI debug console, if there is one
+            None => {
+                for byte in s.as_bytes() {
+                    if debug_console::console_write_byte(*byte).is_err() {
+                        break;
+                    }
+                }
             }
         }
 
@@ -62,6 +79,12 @@ impl ConsoleDevice for StaticConsoleDevice {
             inner.write(n);
         }
     }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if let Some(inner) = &mut self.0 {
+            inner.write_bytes(bytes);
+        }
+    }
 }
 
 unsafe impl Send for StaticConsoleDevice {}
@@ -88,9 +111,14 @@ pub fn set_console(device: &'static mut dyn ConsoleDevice) {
     *CONSOLE.lock() = StaticConsoleDevice(Some(device));
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConsoleDevices {
     Uart16550,
     SifiveUart,
+    /// The SBI Debug Console extension
+    SbiDebugConsole,
+    /// The legacy SBI `console_putchar` and `console_getchar` calls
+    LegacySbi,
 }
 
 impl ConsoleDevices {
@@ -104,13 +132,37 @@ impl ConsoleDevices {
         }
     }
 
+    /// Select the SBI console for the `console=` boot argument, where
+    /// `extension_available` probes whether the SBI implementation provides the
+    /// given extension ID. `dbcn` falls back to the legacy SBI console if the
+    /// Debug Console extension isn't available.
+    pub fn from_boot_arg(arg: &str, extension_available: impl Fn(usize) -> bool) -> Option<Self> {
+        match arg {
+            "dbcn" if extension_available(debug_console::EXTENSION_ID) => Some(ConsoleDevices::SbiDebugConsole),
+            "dbcn" | "sbi" if extension_available(sbi::legacy::CONSOLE_PUTCHAR_EID) => Some(ConsoleDevices::LegacySbi),
+            _ => None,
+        }
+    }
+
     /// # Safety
     ///
-    /// `ptr` must be a valid instance of the device described by the variant in `self`
+    /// `ptr` must be a valid instance of the device described by the variant in
+    /// `self`. The SBI consoles don't use `ptr`.
     pub unsafe fn set_raw_console(&self, ptr: *mut u8) {
         match self {
             ConsoleDevices::Uart16550 => set_raw_console(ptr as *mut Uart16550),
             ConsoleDevices::SifiveUart => set_raw_console(ptr as *mut SifiveUart),
+            ConsoleDevices::SbiDebugConsole | ConsoleDevices::LegacySbi => self.set_sbi_console(),
+        }
+    }
+
+    /// Set up one of the SBI consoles, which don't need any device memory to
+    /// be mapped. Does nothing for the other console devices.
+    pub fn set_sbi_console(&self) {
+        match self {
+            ConsoleDevices::SbiDebugConsole => set_console(Box::leak(Box::new(DebugConsole::new()))),
+            ConsoleDevices::LegacySbi => set_console(Box::leak(Box::new(LegacySbiConsoleOut))),
+            ConsoleDevices::Uart16550 | ConsoleDevices::SifiveUart => {}
         }
     }
 
@@ -118,6 +170,8 @@ impl ConsoleDevices {
         match self {
             ConsoleDevices::Uart16550 => register_isr(interrupt_id, console_interrupt),
             ConsoleDevices::SifiveUart => register_isr(interrupt_id, console_interrupt),
+            // Input is only ever polled for the SBI consoles
+            ConsoleDevices::SbiDebugConsole | ConsoleDevices::LegacySbi => return,
         }
 
         if let Some(plic) = &*crate::interrupts::PLIC.lock() {
@@ -150,3 +204,78 @@ impl ConsoleDevice for LegacySbiConsoleOut {
         sbi::legacy::console_putchar(n)
     }
 }
+
+/// Console output through the SBI Debug Console extension. The extension reads
+/// output from physical memory, so it's staged in a buffer that lives on the
+/// kernel heap, which is always mapped at a fixed offset from physical memory.
+pub struct DebugConsole {
+    buffer: UnsafeCell<[u8; 64]>,
+}
+
+impl DebugConsole {
+    pub fn new() -> Self {
+        Self { buffer: UnsafeCell::new([0; 64]) }
+    }
+
+    fn buffer_phys(&self) -> PhysicalAddress {
+        virt2phys(VirtualAddress::from_ptr(self.buffer.get()))
+    }
+}
+
+impl Default for DebugConsole {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConsoleDevice for DebugConsole {
+    fn init(&mut self) {}
+
+    fn read(&self) -> u8 {
+        match unsafe { debug_console::console_read(self.buffer_phys(), 1) } {
+            Ok(1) => unsafe { (*self.buffer.get())[0] },
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, n: u8) {
+        let _ = debug_console::console_write_byte(n);
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let buffer_len = self.buffer.get_mut().len();
+        for chunk in bytes.chunks(buffer_len) {
+            self.buffer.get_mut()[..chunk.len()].copy_from_slice(chunk);
+
+            // The SBI implementation is allowed to write fewer bytes than
+            // requested, so keep going until the whole chunk is out
+            let mut written = 0;
+            while written < chunk.len() {
+                let base = self.buffer_phys().offset(written);
+                match unsafe { debug_console::console_write(base, chunk.len() - written) } {
+                    Ok(n) => written += n,
+                    Err(_) => return,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sbi_console_boot_arg() {
+        let all = |_| true;
+        let legacy_only = |eid| eid == sbi::legacy::CONSOLE_PUTCHAR_EID;
+        let none = |_| false;
+
+        assert_eq!(ConsoleDevices::from_boot_arg("dbcn", all), Some(ConsoleDevices::SbiDebugConsole));
+        assert_eq!(ConsoleDevices::from_boot_arg("dbcn", legacy_only), Some(ConsoleDevices::LegacySbi));
+        assert_eq!(ConsoleDevices::from_boot_arg("dbcn", none), None);
+        assert_eq!(ConsoleDevices::from_boot_arg("sbi", all), Some(ConsoleDevices::LegacySbi));
+        assert_eq!(ConsoleDevices::from_boot_arg("sbi", none), None);
+        assert_eq!(ConsoleDevices::from_boot_arg("/soc/serial@10000000", all), None);
+    }
+}
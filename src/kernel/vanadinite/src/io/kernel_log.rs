@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{sync::SpinMutex, utils::SameHartDeadlockDetection};
+
+/// The size of the kernel log ring in bytes
+pub const KERNEL_LOG_SIZE: usize = 16 * 1024;
+/// The longest an entry in the kernel log can be, including its newline. Longer
+/// entries are truncated.
+pub const MAX_ENTRY_SIZE: usize = 256;
+
+/// The most recent kernel log entries, which userspace can read back with the
+/// `ReadKernelLog` syscall
+pub static KERNEL_LOG: SpinMutex<LogRing<KERNEL_LOG_SIZE>, SameHartDeadlockDetection> = SpinMutex::new(LogRing::new());
+
+/// A ring buffer of newline-terminated log entries. Once it fills up the oldest
+/// entries are evicted whole to make room for new ones, so readers never see
+/// part of an entry.
+pub struct LogRing<const N: usize> {
+    buffer: [u8; N],
+    /// Index of the oldest byte in the ring
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> Default for LogRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        Self { buffer: [0; N], start: 0, len: 0 }
+    }
+
+    /// Append an entry, which is expected to end with a newline, evicting the
+    /// oldest entries as needed. Entries larger than the ring are truncated.
+    pub fn push(&mut self, entry: &[u8]) {
+        let entry = &entry[..entry.len().min(N)];
+
+        while N - self.len < entry.len() {
+            self.evict_oldest();
+        }
+
+        for &byte in entry {
+            self.buffer[(self.start + self.len) % N] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Copy as many of the most recent entries as fit into `buf`, oldest first,
+    /// returning the number of bytes written
+    pub fn read(&self, buf: &mut [u8]) -> usize {
+        let mut skip = self.len.saturating_sub(buf.len());
+
+        // Leave out the entry that only partially fits
+        if skip > 0 && self.byte(skip - 1) != b'\n' {
+            while skip < self.len && self.byte(skip) != b'\n' {
+                skip += 1;
+            }
+
+            skip = (skip + 1).min(self.len);
+        }
+
+        let len = self.len - skip;
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = self.byte(skip + i);
+        }
+
+        len
+    }
+
+    fn byte(&self, index: usize) -> u8 {
+        self.buffer[(self.start + index) % N]
+    }
+
+    fn evict_oldest(&mut self) {
+        while self.len > 0 {
+            let byte = self.buffer[self.start];
+            self.start = (self.start + 1) % N;
+            self.len -= 1;
+
+            if byte == b'\n' {
+                break;
+            }
+        }
+    }
+}
+
+/// Formats a single log entry on the stack, truncating it to
+/// [`MAX_ENTRY_SIZE`]
+pub struct EntryWriter {
+    buffer: [u8; MAX_ENTRY_SIZE],
+    len: usize,
+}
+
+impl EntryWriter {
+    pub fn new() -> Self {
+        Self { buffer: [0; MAX_ENTRY_SIZE], len: 0 }
+    }
+
+    /// The newline-terminated entry
+    pub fn finish(&mut self) -> &[u8] {
+        self.buffer[self.len] = b'\n';
+        &self.buffer[..=self.len]
+    }
+}
+
+impl Default for EntryWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl core::fmt::Write for EntryWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // Leave room for the newline
+        let len = s.len().min(MAX_ENTRY_SIZE - 1 - self.len);
+        self.buffer[self.len..][..len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    fn contents<const N: usize>(ring: &LogRing<N>, buf_len: usize) -> alloc::vec::Vec<u8> {
+        let mut buf = alloc::vec![0; buf_len];
+        let len = ring.read(&mut buf);
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn ring_wraparound() {
+        let mut ring = LogRing::<16>::new();
+        ring.push(b"one\n");
+        ring.push(b"two\n");
+        ring.push(b"three\n");
+        assert_eq!(contents(&ring, 16), b"one\ntwo\nthree\n");
+
+        // Evicts `one` to make room, wrapping around the end of the buffer
+        ring.push(b"four!\n");
+        assert_eq!(contents(&ring, 16), b"two\nthree\nfour!\n");
+        ring.push(b"five\n");
+        assert_eq!(contents(&ring, 16), b"four!\nfive\n");
+
+        // Only the entries that fit entirely are read
+        assert_eq!(contents(&ring, 8), b"five\n");
+        assert_eq!(contents(&ring, 5), b"five\n");
+        assert_eq!(contents(&ring, 4), b"");
+    }
+
+    #[test]
+    fn logged_entries_can_be_read_back() {
+        log::info!("kernel log test marker");
+
+        let mut buf = alloc::vec![0; KERNEL_LOG_SIZE];
+        let len = KERNEL_LOG.lock().read(&mut buf);
+        let last = buf[..len].split(|&b| b == b'\n').filter(|entry| !entry.is_empty()).last().unwrap();
+        assert!(last.ends_with(b"kernel log test marker"));
+
+        let mut entry = EntryWriter::new();
+        write!(entry, "{}", "a".repeat(2 * MAX_ENTRY_SIZE)).unwrap();
+        assert_eq!(entry.finish().len(), MAX_ENTRY_SIZE);
+        assert_eq!(entry.finish().last(), Some(&b'\n'));
+    }
+}
@@ -5,9 +5,13 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+use super::kernel_log::{EntryWriter, KERNEL_LOG};
 use crate::sync::SpinRwLock;
 use alloc::{collections::BTreeMap, string::String};
-use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::{
+    fmt::Write,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
 use log::LevelFilter;
 
 static HART_FILTER: AtomicUsize = AtomicUsize::new(usize::MAX);
@@ -133,6 +137,22 @@ impl log::Log for Logger {
                 mod_path,
                 record.args()
             );
+
+            if record.level() <= log::Level::Info {
+                let mut entry = EntryWriter::new();
+                let _ = write!(
+                    entry,
+                    "[{:>5}.{:<03}] [ {:>5} ] [HART {}] [{}] {}",
+                    secs,
+                    ms,
+                    record.level(),
+                    crate::HART_ID.get(),
+                    mod_path,
+                    record.args()
+                );
+
+                KERNEL_LOG.lock().push(entry.finish());
+            }
         }
     }
 
@@ -7,6 +7,7 @@
 
 pub mod block_device;
 pub mod console;
+pub mod kernel_log;
 pub mod logging;
 pub mod terminal;
 
@@ -70,14 +70,19 @@ use {
 
 use core::sync::atomic::AtomicU64;
 
-use alloc::boxed::Box;
 use fdt::Fdt;
 use mem::kernel_patching::kernel_section_v2p;
 use sbi::{base::probe_extension, base::ExtensionAvailability, hart_state_management::hart_start};
 pub use vanadinite_macros::{debug, error, info, trace, warn};
 
+/// One more than the highest hart ID vanadinite runs on, which per-hart state
+/// is sized by. Not every hart below it is necessarily running, see
+/// [`boot::harts::BootedHarts::live`].
 static N_CPUS: AtomicUsize = AtomicUsize::new(1);
 static TIMER_FREQ: AtomicU64 = AtomicU64::new(0);
+/// The counters userspace is allowed to read, see [`csr::scounteren`]
+static USER_COUNTERS: AtomicUsize =
+    AtomicUsize::new(csr::scounteren::CYCLE | csr::scounteren::TIME | csr::scounteren::INSTRET);
 static INIT: &[u8] = include_bytes!(env!("CARGO_BIN_FILE_INIT"));
 
 #[thread_local]
@@ -101,7 +106,7 @@ extern "C" fn kmain(hart_id: usize, fdt: *const u8) -> ! {
         Err(e) => platform::exit(platform::ExitStatus::Error(&e)),
     };
 
-    let current_cpu = fdt.cpus().find(|cpu| cpu.ids().first() == hart_id).unwrap();
+    let current_cpu = fdt.cpus().find(|cpu| platform::devicetree::hart_id(&fdt, cpu) == Some(hart_id)).unwrap();
     let timebase_frequency = current_cpu.timebase_frequency();
     TIMER_FREQ.store(timebase_frequency as u64, Ordering::Relaxed);
 
@@ -140,11 +145,29 @@ extern "C" fn kmain(hart_id: usize, fdt: *const u8) -> ! {
                     None => log::warn!("No path provided for init process! Defaulting to `init`"),
                 },
                 "no-color" | "no-colour" => io::logging::USE_COLOR.store(false, Ordering::Relaxed),
+                "user-counters" => {
+                    let mut counters = 0;
+                    for counter in value.unwrap_or_default().split(',') {
+                        match counter {
+                            "cycle" => counters |= csr::scounteren::CYCLE,
+                            "time" => counters |= csr::scounteren::TIME,
+                            "instret" => counters |= csr::scounteren::INSTRET,
+                            "none" | "" => {}
+                            _ => log::warn!("Unknown counter `{}` in `user-counters`", counter),
+                        }
+                    }
+
+                    USER_COUNTERS.store(counters, Ordering::Relaxed);
+                }
+                "watchdog-ticks" => match value.map(str::parse) {
+                    Some(Ok(ticks)) => scheduler::watchdog::WATCHDOG.set_threshold(ticks),
+                    _ => log::warn!("Invalid `watchdog-ticks` value, expected a number of scheduler ticks"),
+                },
                 "console" => match value {
-                    Some("sbi") => {
-                        if let ExtensionAvailability::Available(_) = probe_extension(sbi::legacy::CONSOLE_PUTCHAR_EID) {
-                            let this_is_awful = Box::leak(Box::new(io::LegacySbiConsoleOut));
-                            io::set_console(this_is_awful);
+                    Some(sbi_console @ ("sbi" | "dbcn")) => {
+                        let available = |eid| matches!(probe_extension(eid), ExtensionAvailability::Available(_));
+                        if let Some(device) = io::ConsoleDevices::from_boot_arg(sbi_console, available) {
+                            device.set_sbi_console();
                         }
                     }
                     Some(fdt_node) => {
@@ -202,13 +225,11 @@ extern "C" fn kmain(hart_id: usize, fdt: *const u8) -> ! {
         (version >> 16, version & 0xFFFF)
     };
 
-    let (spec_major, spec_minor) = {
-        let version = sbi::base::spec_version();
-        (version.major, version.minor)
-    };
+    let spec_version = platform::base::spec_version();
 
     let n_cpus = fdt.cpus().count();
-    N_CPUS.store(n_cpus, Ordering::Release);
+    let hart_ids = fdt.cpus().filter_map(|cpu| platform::devicetree::hart_id(&fdt, &cpu));
+    N_CPUS.store(boot::harts::hart_id_bound(hart_ids.chain([hart_id])), Ordering::Release);
     let mut first_mem_resv = true;
 
     info!("vanadinite version {#brightgreen}", env!("CARGO_PKG_VERSION"));
@@ -228,9 +249,31 @@ extern "C" fn kmain(hart_id: usize, fdt: *const u8) -> ! {
         let end = start + size;
         info!("   {:#p}..{:#p} ({} KiB)", start as *const u8, end as *const u8, size / 4.kib());
     }
+    for region in platform::devicetree::reserved_memory(&fdt) {
+        if first_mem_resv {
+            info!(" Reserved Memory Regions:");
+            first_mem_resv = false;
+        }
+
+        let (start, size) = (region.address as usize, region.size as usize);
+        let end = start + size;
+        let flags = match (region.no_map, region.reusable) {
+            (true, _) => " no-map",
+            (false, true) => " reusable",
+            (false, false) => "",
+        };
+        info!(
+            "   {:#p}..{:#p} ({} KiB) {}{}",
+            start as *const u8,
+            end as *const u8,
+            size / 4.kib(),
+            region.name,
+            flags
+        );
+    }
     info!(blue, "=== SBI Implementation ===");
-    info!(" Implementor: {:?} (version: {#green'{}.{}})", sbi::base::impl_id(), impl_major, impl_minor);
-    info!(" Spec Version: {#green'{}.{}}", spec_major, spec_minor);
+    info!(" Implementor: {:?} (version: {#green'{}.{}})", platform::base::impl_id(), impl_major, impl_minor);
+    info!(" Spec Version: {#green'{}.{}}", spec_version.major, spec_version.minor);
 
     info!(blue, "=== Vanadinite Info ===");
     info!(" stvec_trap_shim: {:#p}", trap::stvec_trap_shim as *const u8);
@@ -258,7 +301,8 @@ extern "C" fn kmain(hart_id: usize, fdt: *const u8) -> ! {
                     .and_then(|p| p.as_str()?.chars().find(|c| *c == 's'))
                     .is_some()
             })
-            .map(|cpu| platform::plic_context_for(cpu.ids().first()));
+            .filter_map(|cpu| platform::devicetree::hart_id(&fdt, &cpu))
+            .map(platform::plic_context_for);
 
         let plic = unsafe { &*ic_virt.as_ptr().cast::<Plic>() };
 
@@ -283,22 +327,50 @@ extern "C" fn kmain(hart_id: usize, fdt: *const u8) -> ! {
         test_main();
     }
 
-    csr::sstatus::set_fs(csr::sstatus::FloatingPointStatus::Initial);
+    csr::sstatus::set_fs(csr::sstatus::FloatingPointStatus::Off);
+    csr::scounteren::write(USER_COUNTERS.load(Ordering::Relaxed));
     csr::sie::enable();
 
-    scheduler::SCHEDULER.enqueue(task::Task::load_init(INIT, init_args.into_iter().flatten()));
+    let initrd = boot::initrd::find(&fdt);
+    if let Some(initrd) = initrd {
+        info!("Using the {} KiB initrd for init's servers", initrd.len() / 1024);
+    }
+
+    scheduler::SCHEDULER.enqueue(task::Task::load_init(INIT, initrd, init_args.into_iter().flatten()));
+    // SAFETY: `init` was given a copy of the initrd, which isn't used again
+    unsafe { boot::initrd::release() };
 
     let other_hart_boot_phys = unsafe { kernel_section_v2p(VirtualAddress::from_ptr(other_hart_boot as *const u8)) };
 
-    for cpu in fdt.cpus().filter(|cpu| cpu.ids().first() != hart_id) {
-        let hart_id = cpu.ids().first();
+    let other_harts =
+        fdt.cpus().filter_map(|cpu| platform::devicetree::hart_id(&fdt, &cpu)).filter(|id| *id != hart_id);
+    let mut started = librust::task::HartMask::empty();
+    for hart_id in other_harts {
+        if hart_id >= boot::harts::MAX_HARTS {
+            error!(red, "Not starting hart {}, only hart IDs below {} are supported", hart_id, boot::harts::MAX_HARTS);
+            continue;
+        }
+
         let hart_sp = mem::alloc_kernel_stack(8.kib()) as usize;
 
-        if let Err(e) = hart_start(hart_id, other_hart_boot_phys.as_usize(), hart_sp) {
-            error!(red, "Failed to start hart {}: {:?}", hart_id, e);
+        match hart_start(hart_id, other_hart_boot_phys.as_usize(), hart_sp) {
+            Ok(()) => started = started.with(hart_id),
+            Err(e) => error!(red, "Failed to start hart {}: {:?}", hart_id, e),
         }
     }
 
+    let timeout = utils::ticks_per_us(boot::harts::HART_BOOT_TIMEOUT_US, timebase_frequency as u64);
+    let missing = boot::harts::BOOTED_HARTS.wait_for(started, timeout, csr::time::read);
+    for hart_id in missing.iter() {
+        error!(red, "Hart {} was started but never booted", hart_id);
+    }
+
+    // Harts which boot after this are parked instead of joining in
+    let running = boot::harts::BOOTED_HARTS.finish(hart_id).iter().count();
+    if running != n_cpus {
+        warn!("Only {} of {} CPUs are running", running, n_cpus);
+    }
+
     info!(brightgreen, "Scheduling init process!");
     unsafe { scheduler::SCHEDULER.begin_scheduling() }
 }
@@ -311,13 +383,21 @@ extern "C" fn kalt(hart_id: usize) -> ! {
     unsafe { crate::cpu_local::init_thread_locals() };
     HART_ID.set(hart_id);
 
+    if !boot::harts::BOOTED_HARTS.mark_booted(hart_id) {
+        warn!("Hart {} booted after it was given up on, parking it", hart_id);
+        loop {
+            unsafe { core::arch::asm!("wfi") };
+        }
+    }
+
     info!(brightgreen, "Hart {} successfully booted", HART_ID.get());
 
     if let Some(plic) = &*PLIC.lock() {
         plic.set_context_threshold(platform::current_plic_context(), 0);
     }
 
-    csr::sstatus::set_fs(csr::sstatus::FloatingPointStatus::Initial);
+    csr::sstatus::set_fs(csr::sstatus::FloatingPointStatus::Off);
+    csr::scounteren::write(USER_COUNTERS.load(Ordering::Relaxed));
     csr::sie::enable();
 
     unsafe { scheduler::SCHEDULER.begin_scheduling() }
@@ -442,6 +522,9 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
     }
 
     error!("{}", info);
+    if let Some(task) = scheduler::CURRENT_TASK.try_get() {
+        error!("Current task: {} [{}]", task.name.try_display(), task.tid);
+    }
     error!("Shutting hart down");
 
     sbi::hart_state_management::hart_stop().unwrap();
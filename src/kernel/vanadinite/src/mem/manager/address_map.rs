@@ -224,6 +224,12 @@ impl<A: AddressSpace> AddressMap<A> {
         self.map.range(address..).next().map(|(_, r)| r)
     }
 
+    /// Find the region containing the given [`VirtualAddress`], returning a
+    /// mutable reference to it
+    pub fn find_mut(&mut self, address: VirtualAddress) -> Option<&mut AddressRegion> {
+        self.map.range_mut(address..).next().map(|(_, r)| r)
+    }
+
     /// Returns the unoccupied regions in the address space
     pub fn unoccupied_regions(&self) -> impl Iterator<Item = &AddressRegion> {
         self.map.values().filter(|v| v.region.is_none())
@@ -234,6 +240,11 @@ impl<A: AddressSpace> AddressMap<A> {
         self.map.values().filter(|v| v.region.is_some())
     }
 
+    /// Returns mutable references to the occupied regions in the address space
+    pub fn occupied_regions_mut(&mut self) -> impl Iterator<Item = &mut AddressRegion> {
+        self.map.values_mut().filter(|v| v.region.is_some())
+    }
+
     pub fn debug(&self, addr: Option<VirtualAddress>) -> impl core::fmt::Debug + '_ {
         AddressMapDebug(self, addr)
     }
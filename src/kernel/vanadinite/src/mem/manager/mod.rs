@@ -9,16 +9,21 @@ mod address_map;
 pub mod kernel;
 
 use crate::{
+    boot::harts::BOOTED_HARTS,
     mem::{
         paging::{flags::Flags, PageSize, PageTable, PageTableDebug, PhysicalAddress, Rsw, VirtualAddress},
-        region::{MemoryRegion, PhysicalRegion, UniquePhysicalRegion},
+        region::{CopyOnWriteRegion, DetachedRegion, LazyRegion, MemoryRegion, PhysicalRegion, UniquePhysicalRegion},
         sfence,
+        tlb::{self, ResidentHarts},
     },
     utils::{self, Units},
+    HART_ID,
 };
 use address_map::{AddressMap, Userspace};
 pub use address_map::{AddressRegion, AddressRegionKind};
+use alloc::{collections::BTreeMap, vec::Vec};
 use core::ops::Range;
+use librust::task::HartMask;
 
 use super::region::SharedPhysicalRegion;
 
@@ -42,15 +47,66 @@ pub struct RegionDescription<'a> {
     pub kind: AddressRegionKind,
 }
 
+/// The physical memory allocated for an address space, which is what its
+/// memory limit applies to. Shared memory mapped from elsewhere and MMIO
+/// regions don't count towards it, since they weren't allocated on its behalf,
+/// and neither do copy-on-write pages until they're copied.
+#[derive(Debug, Default)]
+struct MemoryUsage {
+    limit: Option<usize>,
+    resident: usize,
+    /// The bytes charged for each region, keyed by the start of the region
+    charges: BTreeMap<VirtualAddress, usize>,
+    /// Regions which were unmapped while their memory was still shared with
+    /// something else, such as a capability to it, along with the bytes
+    /// charged for them. They stay charged until the memory is freed, since
+    /// unmapping them would otherwise free up room under the limit without
+    /// freeing anything.
+    detached: Vec<(DetachedRegion, usize)>,
+}
+
+impl MemoryUsage {
+    fn resident(&self) -> usize {
+        let detached = self.detached.iter().filter(|(region, _)| region.is_allocated()).map(|(_, bytes)| bytes);
+        self.resident + detached.sum::<usize>()
+    }
+
+    fn within_limit(&self, additional: usize) -> bool {
+        self.limit.map_or(true, |limit| self.resident().saturating_add(additional) <= limit)
+    }
+
+    fn charge(&mut self, region: VirtualAddress, bytes: usize) {
+        self.resident += bytes;
+        *self.charges.entry(region).or_default() += bytes;
+    }
+
+    fn refund(&mut self, region: VirtualAddress, detached: Option<DetachedRegion>) {
+        let bytes = self.charges.remove(&region).unwrap_or(0);
+        self.resident -= bytes;
+
+        self.detached.retain(|(region, _)| region.is_allocated());
+        if let Some(detached) = detached.filter(|_| bytes != 0) {
+            self.detached.push((detached, bytes));
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct UserspaceMemoryManager {
     table: PageTable,
     address_map: AddressMap<Userspace>,
+    resident_harts: ResidentHarts,
+    usage: MemoryUsage,
 }
 
 impl UserspaceMemoryManager {
     pub fn new() -> Self {
-        let mut this = Self { table: PageTable::new(), address_map: AddressMap::new() };
+        let mut this = Self {
+            table: PageTable::new(),
+            address_map: AddressMap::new(),
+            resident_harts: ResidentHarts::new(),
+            usage: MemoryUsage::default(),
+        };
 
         this.guard(VirtualAddress::new(0));
 
@@ -67,16 +123,28 @@ impl UserspaceMemoryManager {
         description: RegionDescription,
     ) -> Range<VirtualAddress> {
         let RegionDescription { size, count, contiguous, flags, fill, kind } = description;
+        let backing = alloc_backing(size, count, contiguous);
+
+        self.apply_region(at, flags, fill, backing, kind)
+    }
+
+    /// Map already allocated physical memory into the address space at an
+    /// optionally specified address, taking ownership of it. This is useful
+    /// when the backing memory has requirements [`Self::alloc_region`] can't
+    /// express, such as the alignment of DMA buffers.
+    pub fn apply_region(
+        &mut self,
+        at: Option<VirtualAddress>,
+        flags: Flags,
+        fill: FillOption<'_>,
+        mut backing: UniquePhysicalRegion,
+        kind: AddressRegionKind,
+    ) -> Range<VirtualAddress> {
+        let (size, count) = (backing.page_size(), backing.n_pages());
         let at = at.unwrap_or_else(|| self.find_free_region(size, count));
 
         log::debug!("Allocating region at {:#p}: size={:?} n_pages={} flags={:?}", at, size, count, flags);
 
-        let mut backing = if contiguous {
-            UniquePhysicalRegion::alloc_contiguous(size, count)
-        } else {
-            UniquePhysicalRegion::alloc_sparse(size, count)
-        };
-
         match fill {
             FillOption::Data(data) => backing.copy_data_into(data),
             FillOption::Zeroed => backing.zero(),
@@ -93,6 +161,7 @@ impl UserspaceMemoryManager {
         self.address_map
             .alloc(range.clone(), MemoryRegion::Backed(PhysicalRegion::Unique(backing)), kind, flags)
             .expect("bad address mapping");
+        self.usage.charge(at, size.to_byte_size() * count);
 
         range
     }
@@ -112,6 +181,47 @@ impl UserspaceMemoryManager {
         at
     }
 
+    /// Reserve a zero-filled region of `count` pages of the given [`PageSize`]
+    /// without allocating any physical memory for it. Pages are mapped the
+    /// first time they're accessed, see [`Self::resolve_lazy`].
+    pub fn alloc_lazy_region(
+        &mut self,
+        at: Option<VirtualAddress>,
+        size: PageSize,
+        count: usize,
+        flags: Flags,
+        kind: AddressRegionKind,
+    ) -> Range<VirtualAddress> {
+        let at = at.unwrap_or_else(|| self.find_free_region(size, count));
+
+        log::debug!("Allocating lazy region at {:#p}: size={:?} n_pages={} flags={:?}", at, size, count, flags);
+
+        let range = at..at.add(size.to_byte_size() * count);
+        self.address_map
+            .alloc(range.clone(), MemoryRegion::Lazy(LazyRegion::new(size, count)), kind, flags)
+            .expect("bad address mapping");
+
+        range
+    }
+
+    /// Same as [`Self::alloc_lazy_region`] except the region is surrounded by
+    /// guard pages, see [`Self::alloc_guarded_region`]
+    pub fn alloc_guarded_lazy_region(
+        &mut self,
+        size: PageSize,
+        count: usize,
+        flags: Flags,
+        kind: AddressRegionKind,
+    ) -> Range<VirtualAddress> {
+        let at = self.find_free_region_with_guards(size, count);
+
+        self.guard(VirtualAddress::new(at.as_usize() - 4.kib()));
+        let range = self.alloc_lazy_region(Some(at), size, count, flags, kind);
+        self.guard(range.end);
+
+        range
+    }
+
     /// Same as [`Self::alloc_region`] except produces a
     /// [`crate::mem::region::SharedPhysicalRegion`] which can be cheaply shared
     /// between tasks
@@ -121,12 +231,9 @@ impl UserspaceMemoryManager {
         description: RegionDescription,
     ) -> (Range<VirtualAddress>, SharedPhysicalRegion) {
         let RegionDescription { size, count, contiguous, flags, fill, kind } = description;
+        let mut backing = alloc_backing(size, count, contiguous);
+        let (size, count) = (backing.page_size(), backing.n_pages());
         let at = at.unwrap_or_else(|| self.find_free_region(size, count));
-        let mut backing = if contiguous {
-            UniquePhysicalRegion::alloc_contiguous(size, count)
-        } else {
-            UniquePhysicalRegion::alloc_sparse(size, count)
-        };
 
         match fill {
             FillOption::Data(data) => backing.copy_data_into(data),
@@ -146,10 +253,26 @@ impl UserspaceMemoryManager {
         self.address_map
             .alloc(range.clone(), MemoryRegion::Backed(PhysicalRegion::Shared(shared.clone())), kind, flags)
             .unwrap();
+        self.usage.charge(at, size.to_byte_size() * count);
 
         (range, shared)
     }
 
+    /// Same as [`Self::alloc_shared_region`] except the region is surrounded by
+    /// guard pages, see [`Self::alloc_guarded_region`]
+    pub fn alloc_guarded_shared_region(
+        &mut self,
+        description: RegionDescription,
+    ) -> (Range<VirtualAddress>, SharedPhysicalRegion) {
+        let at = self.find_free_region_with_guards(description.size, description.count);
+
+        self.guard(VirtualAddress::new(at.as_usize() - 4.kib()));
+        let (range, region) = self.alloc_shared_region(Some(at), description);
+        self.guard(range.end);
+
+        (range, region)
+    }
+
     /// # Safety
     /// This function is meant to map MMIO devices into userspace processes, and
     /// will allow aliasing physical memory if used incorrectly.
@@ -162,7 +285,20 @@ impl UserspaceMemoryManager {
         to: Option<VirtualAddress>,
         len: usize,
     ) -> Range<VirtualAddress> {
-        let n_pages = crate::utils::round_up_to_next(4.kib(), len) / 4.kib();
+        self.map_mmio_device_with_flags(from, to, len, Flags::READ | Flags::WRITE)
+    }
+
+    /// # Safety
+    /// Same as [`UserspaceMemoryManager::map_mmio_device`], but only maps the
+    /// region with the access flags in `flags`
+    pub unsafe fn map_mmio_device_with_flags(
+        &mut self,
+        from: PhysicalAddress,
+        to: Option<VirtualAddress>,
+        len: usize,
+        flags: Flags,
+    ) -> Range<VirtualAddress> {
+        let n_pages = crate::utils::round_up_to_next(len, 4.kib()) / 4.kib();
         let at = to.unwrap_or_else(|| self.find_free_region(PageSize::Kilopage, n_pages));
 
         log::debug!(
@@ -175,7 +311,7 @@ impl UserspaceMemoryManager {
 
         let backing = UniquePhysicalRegion::mmio(from, PageSize::Kilopage, n_pages);
 
-        let flags = Flags::READ | Flags::WRITE | Flags::USER | Flags::VALID;
+        let flags = flags | Flags::USER | Flags::VALID;
         let iter = backing
             .physical_addresses()
             .enumerate()
@@ -249,19 +385,261 @@ impl UserspaceMemoryManager {
         assert!(region.region.is_some(), "trying to dealloc an unallocated region");
 
         let span = region.span.clone();
-        let region = self.address_map.free(span).expect("tried deallocing an unmapped region");
+        let region = self.address_map.free(span.clone()).expect("tried deallocing an unmapped region");
+        let detached = match &region {
+            MemoryRegion::Backed(backing) => backing.detach(),
+            _ => None,
+        };
+        self.usage.refund(span.start, detached);
 
-        let iter = (0..region.page_count()).map(|i| at.add(i * region.page_size().to_byte_size()));
+        // Only the resident pages of lazy regions have been mapped
+        let (resident, all) = match &region {
+            MemoryRegion::Lazy(lazy) => (Some(lazy.resident_pages().map(|(index, _)| index)), None),
+            _ => (None, Some(0..region.page_count())),
+        };
+
+        let pages = resident.into_iter().flatten().chain(all.into_iter().flatten());
+        let iter = pages.map(|i| at.add(i * region.page_size().to_byte_size()));
         for virt_addr in iter {
             self.table.unmap(virt_addr);
-            // FIXME: this is unnecessary when unmapping from other tasks than
-            // the current one? need IPIs for that?
             sfence(Some(virt_addr), None);
         }
 
+        self.shootdown(span);
+
         region
     }
 
+    /// Create a new address space containing the same mappings as this one.
+    /// Shared memory regions remain shared between both address spaces, while
+    /// all other memory is shared copy-on-write: writable pages are remapped
+    /// read-only in both address spaces, and the first write to one of them
+    /// gives the writing address space its own copy of the page (see
+    /// [`Self::resolve_copy_on_write`]). MMIO and DMA regions are tied to the
+    /// device owned by this address space, so they aren't cloned.
+    ///
+    /// Previous TLB entries for this address space may still allow writes, so
+    /// the caller must flush them if this address space is active, and shoot
+    /// them down on other harts with [`Self::shootdown_all`].
+    ///
+    /// The new address space has the same memory limit as this one. It's
+    /// charged for the lazy pages copied into it, while copy-on-write pages
+    /// stay charged to the address space that allocated them until they're
+    /// copied.
+    pub fn clone_copy_on_write(&mut self) -> Self {
+        let mut other = Self {
+            table: PageTable::new(),
+            address_map: AddressMap::new(),
+            resident_harts: ResidentHarts::new(),
+            usage: MemoryUsage { limit: self.usage.limit, ..Default::default() },
+        };
+
+        for region in self.address_map.occupied_regions_mut() {
+            let start = region.span.start;
+
+            if let AddressRegionKind::Mmio | AddressRegionKind::Dma = region.kind {
+                log::debug!("Not cloning {:?} region at {:#p}", region.kind, start);
+                continue;
+            }
+
+            // Uniquely owned memory now needs to be shared between both address
+            // spaces, so convert it into a copy-on-write region
+            region.region = match region.region.take() {
+                Some(MemoryRegion::Backed(PhysicalRegion::Unique(unique))) => Some(MemoryRegion::Backed(
+                    PhysicalRegion::CopyOnWrite(CopyOnWriteRegion::new(unique.into_shared_region())),
+                )),
+                region => region,
+            };
+
+            let cloned = match region.region.as_ref().unwrap() {
+                MemoryRegion::GuardPage => {
+                    other.guard(start);
+                    continue;
+                }
+                // Pages which haven't been touched yet stay lazy in both
+                // address spaces, while resident ones are copied eagerly
+                MemoryRegion::Lazy(lazy) => {
+                    let lazy = lazy.duplicate();
+                    for (index, phys_addr) in lazy.resident_pages() {
+                        let virt_addr = start.add(index * lazy.page_size().to_byte_size());
+                        other.table.map(phys_addr, virt_addr, region.permissions, lazy.page_size(), Rsw::NONE);
+                    }
+
+                    MemoryRegion::Lazy(lazy)
+                }
+                MemoryRegion::Backed(PhysicalRegion::Shared(shared)) => {
+                    let iter = shared
+                        .physical_addresses()
+                        .enumerate()
+                        .map(|(i, phys)| (phys, start.add(i * shared.page_size().to_byte_size())));
+                    for (phys_addr, virt_addr) in iter {
+                        other.table.map(
+                            phys_addr,
+                            virt_addr,
+                            region.permissions,
+                            shared.page_size(),
+                            Rsw::SHARED_MEMORY,
+                        );
+                    }
+
+                    MemoryRegion::Backed(PhysicalRegion::Shared(shared.clone()))
+                }
+                MemoryRegion::Backed(PhysicalRegion::CopyOnWrite(cow)) => {
+                    let flags = read_only(region.permissions);
+                    let iter = cow
+                        .physical_addresses()
+                        .enumerate()
+                        .map(|(i, phys)| (phys, start.add(i * cow.page_size().to_byte_size())));
+                    for (phys_addr, virt_addr) in iter {
+                        self.table.modify_page_flags(virt_addr, read_only);
+                        self.table.modify_page_rsw(virt_addr, |_| Rsw::COPY_ON_WRITE);
+                        other.table.map(phys_addr, virt_addr, flags, cow.page_size(), Rsw::COPY_ON_WRITE);
+                    }
+
+                    MemoryRegion::Backed(PhysicalRegion::CopyOnWrite(cow.clone()))
+                }
+                MemoryRegion::Backed(PhysicalRegion::Unique(_)) => unreachable!(),
+            };
+
+            if let (MemoryRegion::Lazy(_), Some(&charge)) = (&cloned, self.usage.charges.get(&start)) {
+                other.usage.charge(start, charge);
+            }
+
+            other
+                .address_map
+                .alloc(region.span.clone(), cloned, region.kind, region.permissions)
+                .expect("bad address mapping");
+        }
+
+        other
+    }
+
+    /// Give this address space its own writable copy of the copy-on-write page
+    /// containing the given [`VirtualAddress`], returning whether the page was
+    /// a copy-on-write page in a writable region. Also returns `false` if the
+    /// copy would put the address space over its memory limit.
+    pub fn resolve_copy_on_write(&mut self, at: VirtualAddress) -> bool {
+        let Some(region) = self.address_map.find_mut(at) else { return false };
+        let Some(MemoryRegion::Backed(PhysicalRegion::CopyOnWrite(cow))) = &mut region.region else { return false };
+
+        let page_size = cow.page_size();
+        let index = (at.as_usize() - region.span.start.as_usize()) / page_size.to_byte_size();
+        let page = region.span.start.add(index * page_size.to_byte_size());
+
+        let pending = self.table.page_rsw(page) == Some(Rsw::COPY_ON_WRITE)
+            && self.table.page_flags(page).map_or(false, |flags| !(flags & Flags::WRITE));
+        if !(region.permissions & Flags::WRITE) || !pending {
+            return false;
+        }
+
+        let copied = cow.is_page_shared(index);
+        if copied && !self.usage.within_limit(page_size.to_byte_size()) {
+            log::debug!("Not copying copy-on-write page {:#p}, the address space is at its memory limit", page);
+            return false;
+        }
+
+        let phys = cow.copy_page(index);
+        if copied {
+            self.usage.charge(region.span.start, page_size.to_byte_size());
+        }
+
+        log::trace!("Copied copy-on-write page {:#p} to {:#p}", page, phys);
+
+        self.table.unmap(page);
+        self.table.map(phys, page, region.permissions | Flags::ACCESSED | Flags::DIRTY, page_size, Rsw::COPY_ON_WRITE);
+        sfence(Some(page), None);
+        // Other harts could otherwise keep reading the shared page
+        self.shootdown(page..page.add(page_size.to_byte_size()));
+
+        true
+    }
+
+    /// Map the page of the lazy region containing the given [`VirtualAddress`]
+    /// to a newly allocated, zeroed frame, returning whether the page belonged
+    /// to a lazy region and wasn't already resident. Also returns `false` if
+    /// there's no physical memory left to back the page, or the page would put
+    /// the address space over its memory limit.
+    pub fn resolve_lazy(&mut self, at: VirtualAddress) -> bool {
+        let Some(region) = self.address_map.find_mut(at) else { return false };
+        let Some(MemoryRegion::Lazy(lazy)) = &mut region.region else { return false };
+
+        let page_size = lazy.page_size();
+        let index = (at.as_usize() - region.span.start.as_usize()) / page_size.to_byte_size();
+        let page = region.span.start.add(index * page_size.to_byte_size());
+
+        if lazy.resident_page(index).is_some() {
+            return false;
+        } else if !self.usage.within_limit(page_size.to_byte_size()) {
+            log::debug!("Not populating lazy page {:#p}, the address space is at its memory limit", page);
+            return false;
+        }
+
+        let Some(phys) = lazy.populate(index) else { return false };
+        self.usage.charge(region.span.start, page_size.to_byte_size());
+
+        log::trace!("Populated lazy page {:#p} with {:#p}", page, phys);
+
+        self.table.map(phys, page, region.permissions, page_size, Rsw::NONE);
+        sfence(Some(page), None);
+
+        true
+    }
+
+    /// Resolve any pending copy-on-write pages and populate any untouched lazy
+    /// pages in the given range so that the kernel can write to them on behalf
+    /// of the task
+    pub fn resolve_copy_on_write_range(&mut self, range: Range<VirtualAddress>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let start = range.start.align_down_to(PageSize::Kilopage);
+        let end = range.end.align_to_next(PageSize::Kilopage);
+
+        for page in (start.as_usize()..end.as_usize()).step_by(4.kib()) {
+            let page = VirtualAddress::new(page);
+            if page.is_kernel_region() {
+                continue;
+            }
+
+            match self.table.page_flags(page) {
+                Some(_) if self.table.page_rsw(page) == Some(Rsw::COPY_ON_WRITE) => {
+                    self.resolve_copy_on_write(page);
+                }
+                Some(_) => {}
+                None => {
+                    self.resolve_lazy(page);
+                }
+            }
+        }
+    }
+
+    /// Limit the physical memory allocated for this address space to `limit`
+    /// bytes, or remove the limit with `None`. Memory that's already allocated
+    /// stays allocated even if it's over the new limit.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.usage.limit = limit;
+    }
+
+    /// The limit set with [`Self::set_memory_limit`], if any
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.usage.limit
+    }
+
+    /// The number of bytes of physical memory allocated for this address space
+    /// which count towards its memory limit
+    pub fn resident_memory(&self) -> usize {
+        self.usage.resident()
+    }
+
+    /// Whether `bytes` more memory can be allocated for this address space
+    /// without going over its memory limit. The allocation methods don't check
+    /// this themselves, since the kernel needs to be able to allocate memory
+    /// for a task in ways it can't refuse.
+    pub fn within_memory_limit(&self, bytes: usize) -> bool {
+        self.usage.within_limit(bytes)
+    }
+
     /// Returns the [`AddressRegion`] that contains the given
     /// [`VirtualAddress`], if it exists
     pub fn region_for(&self, at: VirtualAddress) -> Option<&AddressRegion> {
@@ -303,6 +681,12 @@ impl UserspaceMemoryManager {
         Ok(())
     }
 
+    /// Returns the [`PageSize`] of the page mapping the given
+    /// [`VirtualAddress`], if it's mapped
+    pub fn page_size(&self, virt: VirtualAddress) -> Option<PageSize> {
+        self.table.page_size(virt)
+    }
+
     /// Returns the [`Flags`] of the given [`VirtualAddress`], if it's mapped
     pub fn page_flags(&self, virt: VirtualAddress) -> Option<Flags> {
         self.table.page_flags(virt)
@@ -331,6 +715,30 @@ impl UserspaceMemoryManager {
         self.table.resolve(virt)
     }
 
+    /// Record that the current hart is about to run this address space, so
+    /// that it's included in any future TLB shootdowns
+    pub fn mark_resident(&mut self, hart: usize) {
+        self.resident_harts.mark(hart);
+    }
+
+    /// Flush the TLB entries for `range` on the other harts that have run this
+    /// address space, after mappings in it were removed or had permissions
+    /// taken away. The current hart must still do a local `sfence.vma`.
+    pub fn shootdown(&self, range: Range<VirtualAddress>) {
+        tlb::shootdown(self.remote_harts(), range.start.as_usize()..range.end.as_usize());
+    }
+
+    /// Like [`Self::shootdown`], but for the whole address space
+    pub fn shootdown_all(&self) {
+        tlb::shootdown(self.remote_harts(), 0..usize::MAX);
+    }
+
+    /// The other running harts which may have TLB entries cached for this
+    /// address space
+    fn remote_harts(&self) -> HartMask {
+        self.resident_harts.remote(HART_ID.get(), BOOTED_HARTS.live())
+    }
+
     /// The [`PhysicalAddress`] of the contained [`PageTable`]
     pub fn table_phys_address(&self) -> PhysicalAddress {
         self.table.physical_address()
@@ -420,7 +828,10 @@ impl UserspaceMemoryManager {
             }
 
             let above_avail = self.address_map.find(aligned_start.add(total_bytes)).unwrap().is_unoccupied();
-            let below_avail = self.address_map.find(aligned_start.add(total_bytes)).unwrap().is_unoccupied();
+            let below_avail = match aligned_start.checked_offset(-(4.kib() as isize)) {
+                Some(below) => self.address_map.find(below).map_or(false, AddressRegion::is_unoccupied),
+                None => false,
+            };
 
             if !above_avail || !below_avail {
                 continue;
@@ -460,3 +871,312 @@ impl UserspaceMemoryManager {
         todo!("exhausted address space -- this should be an `Err(...)` in the future")
     }
 }
+
+/// Allocate the physical memory backing a region of `count` pages of the given
+/// [`PageSize`]. If there isn't enough physically contiguous memory available
+/// for pages larger than a kilopage, the region is backed by the equivalent
+/// number of kilopages instead, so callers must use the page size and count of
+/// the returned region.
+#[track_caller]
+fn alloc_backing(size: PageSize, count: usize, contiguous: bool) -> UniquePhysicalRegion {
+    let backing = match contiguous {
+        true => UniquePhysicalRegion::try_alloc_contiguous(size, count),
+        false => UniquePhysicalRegion::try_alloc_sparse(size, count),
+    };
+
+    match backing {
+        Some(backing) => backing,
+        None if size != PageSize::Kilopage => {
+            log::debug!("Couldn't allocate {} {:?}s, falling back to kilopages", count, size);
+            let count = count * (size.to_byte_size() / 4.kib());
+            alloc_backing(PageSize::Kilopage, count, contiguous)
+        }
+        None if contiguous => panic!("couldn't alloc contiguous region"),
+        None => panic!("couldn't alloc sparse region"),
+    }
+}
+
+fn read_only(flags: Flags) -> Flags {
+    Flags::new(flags.value() & !Flags::WRITE.value())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::{
+        phys::{PhysicalMemoryAllocator, PHYSICAL_MEMORY_ALLOCATOR},
+        phys2virt,
+    };
+
+    fn page(manager: &UserspaceMemoryManager, at: VirtualAddress) -> &'static mut [u8] {
+        let phys = manager.resolve(at).unwrap();
+        unsafe { core::slice::from_raw_parts_mut(phys2virt(phys).as_mut_ptr(), 4.kib()) }
+    }
+
+    #[test]
+    fn copy_on_write_clone() {
+        let flags = Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE;
+        let mut parent = UserspaceMemoryManager::new();
+
+        let data = parent.alloc_region(
+            Some(VirtualAddress::new(0x1_0000)),
+            RegionDescription {
+                size: PageSize::Kilopage,
+                count: 2,
+                contiguous: false,
+                flags,
+                fill: FillOption::Data(&[0xAA; 8192]),
+                kind: AddressRegionKind::Data,
+            },
+        );
+        let rodata = parent.alloc_region(
+            Some(VirtualAddress::new(0x2_0000)),
+            RegionDescription {
+                size: PageSize::Kilopage,
+                count: 1,
+                contiguous: false,
+                flags: Flags::VALID | Flags::USER | Flags::READ,
+                fill: FillOption::Zeroed,
+                kind: AddressRegionKind::ReadOnly,
+            },
+        );
+        let (shared, _) = parent.alloc_shared_region(
+            Some(VirtualAddress::new(0x3_0000)),
+            RegionDescription {
+                size: PageSize::Kilopage,
+                count: 1,
+                contiguous: false,
+                flags,
+                fill: FillOption::Zeroed,
+                kind: AddressRegionKind::UserSharedMemory,
+            },
+        );
+
+        let mut child = parent.clone_copy_on_write();
+
+        // Both address spaces start out sharing the same read-only frames
+        assert_eq!(parent.resolve(data.start), child.resolve(data.start));
+        assert!(!(parent.page_flags(data.start).unwrap() & Flags::WRITE));
+        assert!(!(child.page_flags(data.start).unwrap() & Flags::WRITE));
+
+        // Writing in the child gives it its own copy, leaving the parent alone
+        assert!(child.resolve_copy_on_write(data.start));
+        assert!(child.page_flags(data.start).unwrap() & Flags::WRITE);
+        assert_ne!(parent.resolve(data.start), child.resolve(data.start));
+        page(&child, data.start).fill(0x55);
+        assert!(page(&parent, data.start).iter().all(|b| *b == 0xAA));
+
+        // Pages which haven't been written to are still shared
+        let second_page = data.start.add(4.kib());
+        assert_eq!(parent.resolve(second_page), child.resolve(second_page));
+
+        // The parent is the only one left using its first page, so writing to
+        // it keeps the original frame, while the second page is still shared
+        // and has to be copied
+        let first_frame = parent.resolve(data.start);
+        assert!(parent.resolve_copy_on_write(data.start));
+        assert_eq!(parent.resolve(data.start), first_frame);
+        assert!(parent.resolve_copy_on_write(second_page));
+        assert_ne!(parent.resolve(second_page), child.resolve(second_page));
+
+        // Which leaves the child as the only one using the original second page
+        let second_frame = child.resolve(second_page);
+        assert!(child.resolve_copy_on_write(second_page));
+        assert_eq!(child.resolve(second_page), second_frame);
+
+        // Resolving an already writable page or a read-only region does nothing
+        assert!(!child.resolve_copy_on_write(data.start));
+        assert!(!child.resolve_copy_on_write(rodata.start));
+
+        // Shared memory stays shared and writable in both
+        assert_eq!(parent.resolve(shared.start), child.resolve(shared.start));
+        assert!(child.page_flags(shared.start).unwrap() & Flags::WRITE);
+        page(&child, shared.start)[0] = 1;
+        assert_eq!(page(&parent, shared.start)[0], 1);
+
+        // Page tables containing guard pages can't be torn down yet
+        core::mem::forget(parent);
+        core::mem::forget(child);
+    }
+
+    #[test]
+    fn megapage_region() {
+        let mut manager = UserspaceMemoryManager::new();
+
+        let region = manager.alloc_region(
+            Some(VirtualAddress::new(0x20_0000)),
+            RegionDescription {
+                size: PageSize::Megapage,
+                count: 1,
+                contiguous: false,
+                flags: Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE,
+                fill: FillOption::Zeroed,
+                kind: AddressRegionKind::Data,
+            },
+        );
+
+        assert_eq!(region, VirtualAddress::new(0x20_0000)..VirtualAddress::new(0x40_0000));
+
+        // The whole region is covered by a single leaf entry
+        let phys = manager.resolve(region.start).unwrap();
+        assert_eq!(phys.offset_into_page(PageSize::Megapage), 0);
+        for offset in (0..2.mib()).step_by(4.kib()) {
+            assert_eq!(manager.page_size(region.start.add(offset)), Some(PageSize::Megapage));
+            assert_eq!(manager.resolve(region.start.add(offset)), Some(phys));
+        }
+
+        assert_eq!(manager.page_size(region.end), None);
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn guarded_stack() {
+        let mut manager = UserspaceMemoryManager::new();
+        let (stack, _) = manager.alloc_guarded_shared_region(RegionDescription {
+            size: PageSize::Kilopage,
+            count: 4,
+            contiguous: false,
+            flags: Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE,
+            fill: FillOption::Zeroed,
+            kind: AddressRegionKind::Stack,
+        });
+
+        // The lowest byte of the stack is usable, but the byte just past the
+        // stack limit is in a guard page, so writing to it faults and the trap
+        // handler finds the stack above it
+        page(&manager, stack.start)[0] = 0xAA;
+
+        let past_limit = VirtualAddress::new(stack.start.as_usize() - 1);
+        assert!(matches!(
+            manager.region_for(past_limit),
+            Some(AddressRegion { region: Some(MemoryRegion::GuardPage), .. })
+        ));
+        assert_eq!(
+            manager.region_for(past_limit.add(4.kib())).map(|region| region.kind),
+            Some(AddressRegionKind::Stack)
+        );
+
+        assert!(matches!(
+            manager.region_for(stack.end),
+            Some(AddressRegion { region: Some(MemoryRegion::GuardPage), .. })
+        ));
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn lazy_region() {
+        let flags = Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE;
+        let mut manager = UserspaceMemoryManager::new();
+
+        let used_before = PHYSICAL_MEMORY_ALLOCATOR.lock().used_pages();
+        let region = manager.alloc_guarded_lazy_region(PageSize::Kilopage, 1024, flags, AddressRegionKind::Stack);
+
+        // Reserving 4 MiB should only need the page tables for the guard
+        // pages, since nothing else is mapped yet
+        assert!(PHYSICAL_MEMORY_ALLOCATOR.lock().used_pages() - used_before < 8);
+        assert_eq!(manager.page_flags(region.start), None);
+
+        // The first access maps a zeroed frame with the region's permissions
+        let top = VirtualAddress::new(region.end.as_usize() - 1);
+        assert!(manager.resolve_lazy(top));
+        assert!(!manager.resolve_lazy(top));
+        assert!(manager.page_flags(top).unwrap() & Flags::WRITE);
+        assert!(page(&manager, top.align_down_to(PageSize::Kilopage)).iter().all(|b| *b == 0));
+
+        // Kernel writes populate untouched pages in the range too
+        manager.resolve_copy_on_write_range(region.start..region.start.add(8.kib()));
+        assert!(manager.resolve(region.start.add(4.kib())).is_some());
+        assert_eq!(manager.resolve(region.start.add(8.kib())), None);
+
+        match manager.region_for(region.start) {
+            Some(AddressRegion { region: Some(MemoryRegion::Lazy(lazy)), .. }) => {
+                assert!(lazy.resident_pages().map(|(index, _)| index).eq([0, 1, 1023]));
+            }
+            region => panic!("expected a lazy region, got {:?}", region),
+        }
+
+        // Guard pages are never populated
+        assert!(!manager.resolve_lazy(region.end));
+
+        let used_after_touch = PHYSICAL_MEMORY_ALLOCATOR.lock().used_pages();
+        drop(manager.dealloc_region(region.start));
+        assert_eq!(PHYSICAL_MEMORY_ALLOCATOR.lock().used_pages(), used_after_touch - 3);
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn dealloc_on_multiple_harts() {
+        let flags = Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE;
+        let mut manager = UserspaceMemoryManager::new();
+        let region = manager.alloc_lazy_region(None, PageSize::Kilopage, 2, flags, AddressRegionKind::Data);
+        assert!(manager.resolve_lazy(region.start));
+
+        // Only the boot hart runs tests, so the other harts this address space
+        // has been on aren't running and don't need to be shot down, and a
+        // hart outside of a `HartMask` falls back to shooting down every
+        // running hart instead of panicking
+        manager.mark_resident(HART_ID.get());
+        manager.mark_resident(HART_ID.get() + 1);
+        manager.mark_resident(70);
+        assert_eq!(manager.remote_harts(), HartMask::empty());
+
+        // The mapping is gone from the page table every hart walks
+        drop(manager.dealloc_region(region.start));
+        assert_eq!(manager.resolve(region.start), None);
+        assert_eq!(manager.page_flags(region.start), None);
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let flags = Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE;
+        let mut manager = UserspaceMemoryManager::new();
+        manager.set_memory_limit(Some(12.kib()));
+
+        let data = manager.alloc_region(
+            None,
+            RegionDescription {
+                size: PageSize::Kilopage,
+                count: 1,
+                contiguous: false,
+                flags,
+                fill: FillOption::Zeroed,
+                kind: AddressRegionKind::Data,
+            },
+        );
+        let lazy = manager.alloc_lazy_region(None, PageSize::Kilopage, 4, flags, AddressRegionKind::Data);
+        assert_eq!(manager.resident_memory(), 4.kib());
+
+        // Lazy pages stop being populated once the limit is reached
+        assert!(manager.resolve_lazy(lazy.start));
+        assert!(manager.resolve_lazy(lazy.start.add(4.kib())));
+        assert!(!manager.resolve_lazy(lazy.start.add(8.kib())));
+        assert_eq!(manager.resolve(lazy.start.add(8.kib())), None);
+        assert!(!manager.within_memory_limit(1));
+
+        // Clones are held to the same limit, but are only charged for the
+        // copy-on-write pages once they copy them
+        let mut clone = manager.clone_copy_on_write();
+        assert_eq!((clone.memory_limit(), clone.resident_memory()), (Some(12.kib()), 8.kib()));
+        assert!(clone.resolve_copy_on_write(data.start));
+        assert_eq!(clone.resident_memory(), 12.kib());
+        assert!(!clone.resolve_lazy(lazy.start.add(8.kib())));
+
+        // Freeing memory makes room for more, but only in the address space
+        // it's freed from
+        drop(manager.dealloc_region(data.start));
+        assert_eq!(manager.resident_memory(), 8.kib());
+        assert!(manager.resolve_lazy(lazy.start.add(8.kib())));
+        assert!(!clone.resolve_lazy(lazy.start.add(8.kib())));
+
+        drop(manager.dealloc_region(lazy.start));
+        assert_eq!(manager.resident_memory(), 0);
+
+        core::mem::forget(manager);
+        core::mem::forget(clone);
+    }
+}
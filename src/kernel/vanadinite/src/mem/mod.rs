@@ -6,12 +6,16 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use {
+    crate::sync::SpinMutex,
+    alloc::vec::Vec,
     core::{
         arch::asm,
+        ops::Range,
         sync::atomic::{AtomicUsize, Ordering},
     },
-    paging::{PageSize, PhysicalAddress, VirtualAddress},
-    phys::{PhysicalMemoryAllocator, PHYSICAL_MEMORY_ALLOCATOR},
+    librust::task::HartMask,
+    paging::{flags::Flags, PageSize, PhysicalAddress, VirtualAddress},
+    phys::{PhysicalMemoryAllocator, PhysicalPage, PHYSICAL_MEMORY_ALLOCATOR},
 };
 
 // pub static KERNEL_MEMORY_MANAGER: SpinMutex<manager::UserspaceMemoryManager> =
@@ -21,7 +25,9 @@ pub mod heap;
 pub mod manager;
 pub mod phys;
 pub mod region;
+pub mod tlb;
 pub mod user;
+pub mod usercopy;
 pub mod paging {
     mod table;
     #[cfg(test)]
@@ -55,17 +61,105 @@ pub fn sfence(vaddr: Option<paging::VirtualAddress>, asid: Option<u16>) {
     }
 }
 
+/// The virtual address range kernel stacks are mapped into. Each stack is
+/// placed at the top of its own [`KERNEL_STACK_SLOT_SIZE`] slot, leaving the
+/// rest of the slot unmapped to catch stack overflows.
+pub const KERNEL_STACK_REGION: Range<usize> = 0xFFFF_FFE0_0000_0000..0xFFFF_FFE4_0000_0000;
+pub const KERNEL_STACK_SLOT_SIZE: usize = 4 * 1024 * 1024;
+
+static NEXT_KERNEL_STACK_SLOT: AtomicUsize = AtomicUsize::new(KERNEL_STACK_REGION.start);
+/// Slots whose stacks were freed with [`free_kernel_stack`], which are reused
+/// before any new slots are taken
+static FREE_KERNEL_STACK_SLOTS: SpinMutex<Vec<usize>> = SpinMutex::new(Vec::new());
+
+/// Allocate a kernel stack with at least one unmapped guard page below it,
+/// returning a pointer to the top of the stack
 pub fn alloc_kernel_stack(size: usize) -> *mut u8 {
     assert!(size.is_power_of_two());
     assert_eq!(size % 4096, 0);
+    assert!(size < KERNEL_STACK_SLOT_SIZE, "kernel stack too large for a guarded slot");
+
+    let slot = match FREE_KERNEL_STACK_SLOTS.lock().pop() {
+        Some(slot) => slot,
+        None => NEXT_KERNEL_STACK_SLOT.fetch_add(KERNEL_STACK_SLOT_SIZE, Ordering::Relaxed),
+    };
+    assert!(slot < KERNEL_STACK_REGION.end, "exhausted kernel stack address space");
 
     let total_pages = size / 4096;
     let phys_start =
         unsafe { PHYSICAL_MEMORY_ALLOCATOR.lock().alloc_contiguous(PageSize::Kilopage, total_pages) }.expect("oom :(");
 
-    // FIXME: Eventually make these proper virtual address ranges so we can add
-    // guard pages which will detect stack overflowing
-    phys2virt(phys_start.as_phys_address().offset(total_pages * 4096)).as_mut_ptr()
+    let stack_top = slot + KERNEL_STACK_SLOT_SIZE;
+    let stack_bottom = stack_top - size;
+    for i in 0..total_pages {
+        let virt = VirtualAddress::new(stack_bottom + i * 4096);
+        paging::PageTable::map_shared_kernel_page(
+            phys_start.as_phys_address().offset(i * 4096),
+            virt,
+            Flags::VALID | Flags::READ | Flags::WRITE | Flags::ACCESSED | Flags::DIRTY,
+        );
+        sfence(Some(virt), None);
+    }
+
+    VirtualAddress::new(stack_top).as_mut_ptr()
+}
+
+/// Unmap and free a kernel stack allocated with [`alloc_kernel_stack`], given
+/// the pointer to its top, and make its slot available for reuse
+///
+/// # Safety
+///
+/// The stack must not be in use, or used again afterwards, by any hart
+pub unsafe fn free_kernel_stack(top: *mut u8) {
+    let top = VirtualAddress::from_ptr(top);
+    let slot = top.as_usize().wrapping_sub(KERNEL_STACK_SLOT_SIZE);
+    assert!(
+        KERNEL_STACK_REGION.contains(&slot) && slot % KERNEL_STACK_SLOT_SIZE == 0,
+        "attempted to free a kernel stack that wasn't allocated as one: {:#p}",
+        top
+    );
+
+    // The stack is mapped downwards from the top of the slot, and the guard
+    // area below it is never mapped, so the first unmapped page is its end
+    let (bottom, phys) = paging::PageTable::with_active(|table| {
+        let mut bottom = top;
+        let mut phys = None;
+        while let Some(page) = table.resolve(bottom.offset(-4096)) {
+            bottom = bottom.offset(-4096);
+            table.unmap(bottom);
+            phys = Some(page);
+        }
+
+        (bottom, phys)
+    });
+
+    sfence(None, None);
+    let current = crate::HART_ID.get();
+    let others = crate::boot::harts::BOOTED_HARTS
+        .live()
+        .iter()
+        .filter(|hart| *hart != current)
+        .fold(HartMask::empty(), HartMask::with);
+    tlb::shootdown(others, bottom.as_usize()..top.as_usize());
+
+    if let Some(phys) = phys {
+        let n_pages = (top.as_usize() - bottom.as_usize()) / 4096;
+        PHYSICAL_MEMORY_ALLOCATOR.lock().dealloc_contiguous(
+            PhysicalPage::from_ptr(phys.as_mut_ptr()),
+            PageSize::Kilopage,
+            n_pages,
+        );
+    }
+
+    FREE_KERNEL_STACK_SLOTS.lock().push(slot);
+}
+
+/// Whether a kernel page fault on `addr` was caused by overflowing a kernel
+/// stack. Stack pages are mapped accessed and dirty, so the only addresses in
+/// an allocated slot which can fault are those in its unmapped guard area.
+pub fn is_kernel_stack_guard(addr: VirtualAddress) -> bool {
+    let addr = addr.as_usize();
+    KERNEL_STACK_REGION.contains(&addr) && addr < NEXT_KERNEL_STACK_SLOT.load(Ordering::Relaxed)
 }
 
 #[track_caller]
@@ -80,6 +174,54 @@ pub fn virt2phys(virt: VirtualAddress) -> PhysicalAddress {
 
 pub static PHYSICAL_OFFSET: AtomicUsize = AtomicUsize::new(0);
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::Units;
+
+    #[test]
+    fn kernel_stack_guard() {
+        let top = VirtualAddress::from_ptr(alloc_kernel_stack(8.kib()));
+        let bottom = VirtualAddress::new(top.as_usize() - 8.kib());
+        let past_limit = VirtualAddress::new(bottom.as_usize() - 1);
+
+        // The whole stack is usable...
+        unsafe { *bottom.as_mut_ptr() = 0xAA_u8 };
+        assert!(paging::PageTable::with_active(|table| table.resolve(bottom)).is_some());
+
+        // ...but the byte just past its limit is unmapped, so writing it faults
+        // and the fault is reported as a kernel stack overflow
+        assert!(paging::PageTable::with_active(|table| table.resolve(past_limit)).is_none());
+        assert!(is_kernel_stack_guard(past_limit));
+        assert!(!is_kernel_stack_guard(VirtualAddress::new(KERNEL_STACK_REGION.end)));
+    }
+
+    #[test]
+    fn kernel_stack_overflow_faults() {
+        let top = alloc_kernel_stack(8.kib());
+        let past_limit = VirtualAddress::new(top.addr() - 8.kib() - 1);
+
+        let caught = crate::trap::catch_kernel_stack_overflow(|| unsafe {
+            // Uncompressed, so the trap handler can skip over it
+            asm!(".option push", ".option norvc", "sb zero, 0({})", ".option pop", in(reg) past_limit.as_usize());
+        });
+        assert_eq!(caught, Some(past_limit));
+
+        unsafe { free_kernel_stack(top) };
+    }
+
+    #[test]
+    fn kernel_stack_slots_are_reused() {
+        let top = alloc_kernel_stack(8.kib());
+        let last_byte = VirtualAddress::new(top.addr() - 1);
+        unsafe { free_kernel_stack(top) };
+
+        // The stack is unmapped, and the next stack goes in the same slot
+        assert!(paging::PageTable::with_active(|table| table.resolve(last_byte)).is_none());
+        assert_eq!(alloc_kernel_stack(16.kib()), top);
+    }
+}
+
 pub mod kernel_patching {
     use crate::utils;
     use core::cell::UnsafeCell;
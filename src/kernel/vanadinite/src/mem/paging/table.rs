@@ -134,6 +134,12 @@ impl PageTable {
         self.with_entry(address, |e, _| e.ppn()).flatten()
     }
 
+    /// The [`PageSize`] of the leaf entry mapping the given address, if it's
+    /// mapped
+    pub fn page_size(&self, address: VirtualAddress) -> Option<PageSize> {
+        self.with_entry(address, |_, size| size)
+    }
+
     pub fn physical_address(&self) -> PhysicalAddress {
         virt2phys(VirtualAddress::from_ptr(&*self.root))
     }
@@ -184,6 +190,48 @@ impl PageTable {
         }
     }
 
+    /// Create the top level branch covering `at` if it doesn't exist yet. Page
+    /// tables copy the kernel's top level entries when they're created, so
+    /// mappings later made beneath a reserved branch are visible from every
+    /// address space, see [`PageTable::map_shared_kernel_page`]
+    #[doc(hidden)]
+    pub fn static_reserve_branch(&mut self, at: VirtualAddress) {
+        let entry = &mut self.root.entries[*at.vpns().last().unwrap()];
+
+        match entry.kind() {
+            EntryKind::Branch(_) => {}
+            EntryKind::Leaf => panic!("attempted to reserve a branch over a mapped page: {:#p}", at),
+            EntryKind::NotValid => {
+                let new_subtable = Box::leak(Self::new_table());
+                entry.set_flags(Flags::VALID);
+                entry.set_ppn(virt2phys(VirtualAddress::from_ptr(new_subtable)));
+            }
+        }
+    }
+
+    /// Map a kilopage beneath a branch created with
+    /// [`PageTable::static_reserve_branch`] through the active page table,
+    /// which makes the mapping visible from every address space
+    #[track_caller]
+    pub fn map_shared_kernel_page(from: PhysicalAddress, to: VirtualAddress, flags: Flags) {
+        Self::with_active(|active| match active.root.entries[*to.vpns().last().unwrap()].kind() {
+            EntryKind::Branch(_) => active.map(from, to, flags, PageSize::Kilopage, Rsw::NONE),
+            _ => panic!("attempted to map a shared kernel page outside of a reserved branch: {:#p}", to),
+        })
+    }
+
+    /// Run `f` with the page table currently loaded in `satp`
+    pub fn with_active<T>(f: impl FnOnce(&mut Self) -> T) -> T {
+        let root: *mut repr::PageTable = phys2virt(crate::csr::satp::read().root_page_table).as_mut_ptr().cast();
+
+        // Safety: the active root page table is valid, and is owned elsewhere
+        // so must never be dropped here
+        let mut active =
+            core::mem::ManuallyDrop::new(Self { root: unsafe { Box::from_raw_in(root, PageTableAllocator) } });
+
+        f(&mut active)
+    }
+
     fn with_entry_mut<T>(
         &mut self,
         address: VirtualAddress,
@@ -252,31 +300,23 @@ impl PageTable {
         unsafe { Box::new_uninit_in(PageTableAllocator).assume_init() }
     }
 
+    /// Free the page tables below `entry`, which is an entry of a table
+    /// mapping pages of `page_size`. The pages mapped by leaf entries belong to
+    /// the memory regions they're part of, which free them instead.
     fn deallocate(
         phys_mem_allocator: &mut dyn PhysicalMemoryAllocator,
-        page_table: *mut repr::PageTable,
+        entry: repr::PageTableEntry,
         page_size: PageSize,
     ) {
-        for entry in unsafe { (*page_table).entries.iter_mut() } {
-            match entry.kind() {
-                EntryKind::Branch(branch) => {
-                    let virt = crate::mem::phys2virt(branch);
-                    Self::deallocate(
-                        phys_mem_allocator,
-                        virt.as_mut_ptr().cast(),
-                        page_size.next().expect("Branch found on lowest level page table!"),
-                    );
-
-                    unsafe {
-                        phys_mem_allocator.dealloc(PhysicalPage::from_ptr(branch.as_mut_ptr()), PageSize::Kilopage)
-                    };
-                }
-                EntryKind::Leaf if entry.rsw() == Rsw::NONE => unsafe {
-                    phys_mem_allocator.dealloc(PhysicalPage::from_ptr(entry.ppn().unwrap().as_mut_ptr()), page_size)
-                },
-                _ => {}
-            }
+        let EntryKind::Branch(branch) = entry.kind() else { return };
+        let table: *const repr::PageTable = crate::mem::phys2virt(branch).as_ptr().cast();
+        let next = page_size.next().expect("Branch found on lowest level page table!");
+
+        for &entry in unsafe { (*table).entries.iter() } {
+            Self::deallocate(phys_mem_allocator, entry, next);
         }
+
+        unsafe { phys_mem_allocator.dealloc(PhysicalPage::from_ptr(branch.as_mut_ptr()), PageSize::Kilopage) };
     }
 }
 
@@ -286,7 +326,12 @@ unsafe impl Sync for PageTable {}
 impl Drop for PageTable {
     fn drop(&mut self) {
         let mut lock = crate::mem::phys::PHYSICAL_MEMORY_ALLOCATOR.lock();
-        Self::deallocate(&mut *lock, &mut *self.root, PageSize::top_level());
+        // The kernel half of the table is shared with every other address
+        // space, see `copy_kernel_regions`
+        let kernel_start = *VirtualAddress::kernelspace_range().start.vpns().last().unwrap();
+        for &entry in &self.root.entries[..kernel_start] {
+            Self::deallocate(&mut *lock, entry, PageSize::top_level());
+        }
     }
 }
 
@@ -130,6 +130,10 @@ impl Rsw {
     pub const NONE: Self = Self(0);
     pub const SHARED_MEMORY: Self = Self(1);
     pub const DIRECT: Self = Self(2);
+    /// The page belongs to a copy-on-write region. Writable regions have their
+    /// pages mapped read-only until the first write gives the address space its
+    /// own copy of the page.
+    pub const COPY_ON_WRITE: Self = Self(3);
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -130,7 +130,7 @@ unsafe impl PhysicalMemoryAllocator for BitmapAllocator {
     #[track_caller]
     unsafe fn alloc(&mut self, align_to: PageSize) -> Option<PhysicalPage> {
         match align_to {
-            PageSize::Megapage => self.alloc_contiguous(align_to, 1),
+            PageSize::Megapage | PageSize::Gigapage => self.alloc_contiguous(align_to, 1),
             PageSize::Kilopage => {
                 log::trace!("attempting to allocate a single page");
                 if let Some((index, entry)) = self.bitmap_slice().iter_mut().enumerate().find(|(_, e)| **e != u64::MAX)
@@ -149,7 +149,8 @@ unsafe impl PhysicalMemoryAllocator for BitmapAllocator {
 
                 None
             }
-            _ => todo!("[pmalloc.allocator] BitmapAllocator::alloc: >megapage alloc"),
+            #[cfg(any(feature = "paging.sv48", feature = "paging.sv57"))]
+            PageSize::Terapage => todo!("[pmalloc.allocator] BitmapAllocator::alloc: >gigapage alloc"),
         }
     }
 
@@ -184,6 +185,34 @@ unsafe impl PhysicalMemoryAllocator for BitmapAllocator {
         Some(PhysicalPage::from_ptr(page_ptr as *mut u8))
     }
 
+    #[track_caller]
+    unsafe fn alloc_contiguous_aligned(&mut self, pages: usize, align_pages: usize) -> Option<PhysicalPage> {
+        assert!(align_pages.is_power_of_two(), "[pmalloc.allocator] alignment must be a power of two");
+
+        let align = align_pages.checked_mul(4.kib())?;
+        let total_pages = (self.mem_end as usize - self.mem_start as usize) / 4.kib();
+        let bitmap = self.bitmap_slice();
+
+        // Page indices are relative to the start of memory, which isn't
+        // necessarily aligned itself
+        let mut start = ((align - self.mem_start as usize % align) % align) / 4.kib();
+        while start.checked_add(pages)? <= total_pages {
+            match (start..start + pages).rev().find(|&page| page_used(bitmap, page)) {
+                // Skip to the first aligned start which is past the used page
+                Some(used) => start += ((used - start) / align_pages + 1) * align_pages,
+                None => {
+                    for page in start..start + pages {
+                        bitmap[page / 64] |= 1 << (page % 64);
+                    }
+
+                    return Some(PhysicalPage::from_ptr(self.mem_start.add(start * 4.kib())));
+                }
+            }
+        }
+
+        None
+    }
+
     #[track_caller]
     unsafe fn dealloc(&mut self, page: PhysicalPage, size: PageSize) {
         match size {
@@ -222,36 +251,20 @@ unsafe impl PhysicalMemoryAllocator for BitmapAllocator {
         let start_index = (page.as_phys_address().as_usize() - self.mem_start as usize) / SINGLE_ENTRY_SIZE_BYTES;
 
         match size {
-            PageSize::Kilopage => match n {
-                0..=64 => {
-                    let start_bit = ((page.as_phys_address().as_usize() - self.mem_start as usize) / 4.kib()) % 64;
-
-                    if n == 64 {
-                        self.bitmap_slice()[start_index] = 0;
-                    } else {
-                        let mask = (u64::MAX << n).rotate_left(start_bit as u32);
-                        self.bitmap_slice()[start_index] &= mask;
-                    }
-                }
-                _ => {
-                    let whole_entries_needed = n / 64;
-                    let last_bits_needed = (n % 64) as u32;
-                    let range = start_index..(start_index + whole_entries_needed);
-
-                    for entry in &mut self.bitmap_slice()[range.clone()] {
-                        assert_eq!(
-                            *entry,
-                            u64::MAX,
-                            "[pmalloc.allocator] BitmapAllocator::dealloc: double free in contiguous page region!"
-                        );
-                        *entry = 0;
-                    }
+            // Ranges from `alloc_contiguous_aligned` can start anywhere within
+            // an entry, so free page by page rather than by whole entries
+            PageSize::Kilopage => {
+                let first_page = (page.as_phys_address().as_usize() - self.mem_start as usize) / 4.kib();
+                let bitmap = self.bitmap_slice();
 
-                    if last_bits_needed > 0 {
-                        self.bitmap_slice()[range.end] &= u64::MAX << last_bits_needed;
-                    }
+                for page in first_page..first_page + n {
+                    assert!(
+                        page_used(bitmap, page),
+                        "[pmalloc.allocator] BitmapAllocator::dealloc: double free in contiguous page region!"
+                    );
+                    bitmap[page / 64] &= !(1 << (page % 64));
                 }
-            },
+            }
             _ => {
                 let n_entries = (((size.to_byte_size() / 4.kib()) * n) / 64).max(1);
                 let end_index = start_index + n_entries;
@@ -287,6 +300,14 @@ unsafe impl PhysicalMemoryAllocator for BitmapAllocator {
 
         *entry &= !(1 << bit);
     }
+
+    fn used_pages(&mut self) -> usize {
+        self.bitmap_slice().iter().map(|entry| entry.count_ones() as usize).sum()
+    }
+}
+
+fn page_used(bitmap: &[u64], page: usize) -> bool {
+    bitmap[page / 64] & (1 << (page % 64)) != 0
 }
 
 unsafe impl Send for BitmapAllocator {}
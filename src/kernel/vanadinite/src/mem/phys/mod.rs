@@ -35,6 +35,15 @@ pub unsafe trait PhysicalMemoryAllocator {
     /// the entire range returned
     unsafe fn alloc_contiguous(&mut self, align_to: PageSize, n: usize) -> Option<PhysicalPage>;
 
+    /// # Safety
+    ///
+    /// The requirements for this method are the same as [`alloc_contiguous`].
+    /// The returned range of `pages` kilopages must start at a physical
+    /// address which is a multiple of `align_pages` kilopages, and only the
+    /// pages in the range may be marked as used so that freeing it with
+    /// [`dealloc_contiguous`] leaves no leftover fragmentation.
+    unsafe fn alloc_contiguous_aligned(&mut self, pages: usize, align_pages: usize) -> Option<PhysicalPage>;
+
     /// # Safety
     ///
     /// See the memory safety requirements of [`set_unused`]
@@ -61,6 +70,9 @@ pub unsafe trait PhysicalMemoryAllocator {
     /// requirement could result in undefined behavior if the freed page is then
     /// reallocated to another object in memory, resulting in memory corruption
     unsafe fn set_unused(&mut self, page: PhysicalPage);
+
+    /// The number of kilopages which are currently marked as used
+    fn used_pages(&mut self) -> usize;
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -94,3 +106,31 @@ pub fn zalloc_page() -> PhysicalPage {
 
     page
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Units;
+
+    #[test]
+    fn contiguous_aligned() {
+        let align_pages = 2.mib() / 4.kib();
+        let mut allocator = PHYSICAL_MEMORY_ALLOCATOR.lock();
+
+        // Make it less likely that the next free page happens to be aligned
+        let unaligned = unsafe { allocator.alloc(PageSize::Kilopage) }.unwrap();
+        let block = unsafe { allocator.alloc_contiguous_aligned(16, align_pages) }.unwrap();
+        assert_eq!(block.as_phys_address().as_usize() % 2.mib(), 0);
+
+        unsafe { allocator.dealloc_contiguous(block, PageSize::Kilopage, 16) };
+        unsafe { allocator.dealloc(unaligned, PageSize::Kilopage) };
+
+        // Only the block itself was marked as used, so freeing it makes the
+        // same block available again
+        let again = unsafe { allocator.alloc_contiguous_aligned(16, align_pages) }.unwrap();
+        assert_eq!(again, block);
+        unsafe { allocator.dealloc_contiguous(again, PageSize::Kilopage, 16) };
+
+        assert!(unsafe { allocator.alloc_contiguous_aligned(usize::MAX / 4.kib(), align_pages) }.is_none());
+    }
+}
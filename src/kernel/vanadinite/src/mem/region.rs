@@ -10,12 +10,16 @@ use crate::mem::{
     phys::{PhysicalMemoryAllocator, PhysicalPage, PHYSICAL_MEMORY_ALLOCATOR},
     phys2virt,
 };
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 
 #[derive(Debug, PartialEq)]
 pub enum MemoryRegion {
     Backed(PhysicalRegion),
-    Lazy { page_size: PageSize, n_pages: usize },
+    Lazy(LazyRegion),
     GuardPage,
 }
 
@@ -23,7 +27,7 @@ impl MemoryRegion {
     pub fn page_size(&self) -> PageSize {
         match self {
             MemoryRegion::GuardPage => PageSize::Kilopage,
-            MemoryRegion::Lazy { page_size, .. } => *page_size,
+            MemoryRegion::Lazy(lazy) => lazy.page_size,
             MemoryRegion::Backed(backing) => backing.page_size(),
         }
     }
@@ -31,7 +35,7 @@ impl MemoryRegion {
     pub fn page_count(&self) -> usize {
         match self {
             MemoryRegion::GuardPage => 1,
-            MemoryRegion::Lazy { n_pages, .. } => *n_pages,
+            MemoryRegion::Lazy(lazy) => lazy.n_pages,
             MemoryRegion::Backed(backing) => backing.page_count(),
         }
     }
@@ -39,6 +43,7 @@ impl MemoryRegion {
 
 #[derive(Debug, PartialEq)]
 pub enum PhysicalRegion {
+    CopyOnWrite(CopyOnWriteRegion),
     Shared(SharedPhysicalRegion),
     Unique(UniquePhysicalRegion),
 }
@@ -47,24 +52,59 @@ impl PhysicalRegion {
     /// Returns the number of pages contained within the region
     pub fn page_count(&self) -> usize {
         match self {
+            PhysicalRegion::CopyOnWrite(cow) => cow.n_pages(),
             PhysicalRegion::Shared(shared) => shared.n_pages,
             PhysicalRegion::Unique(unique) => unique.n_pages,
         }
     }
 
     pub fn physical_addresses(&self) -> impl Iterator<Item = PhysicalAddress> + '_ {
-        match self {
-            PhysicalRegion::Shared(shared) => shared.physical_addresses(),
-            PhysicalRegion::Unique(unique) => unique.physical_addresses(),
-        }
+        let (backed, cow) = match self {
+            PhysicalRegion::CopyOnWrite(cow) => (None, Some(cow.physical_addresses())),
+            PhysicalRegion::Shared(shared) => (Some(shared.physical_addresses()), None),
+            PhysicalRegion::Unique(unique) => (Some(unique.physical_addresses()), None),
+        };
+
+        backed.into_iter().flatten().chain(cow.into_iter().flatten())
     }
 
     pub fn page_size(&self) -> PageSize {
         match self {
+            PhysicalRegion::CopyOnWrite(cow) => cow.page_size,
             PhysicalRegion::Shared(shared) => shared.page_size,
             PhysicalRegion::Unique(unique) => unique.page_size,
         }
     }
+
+    /// A [`DetachedRegion`] which tracks whether the physical memory behind
+    /// this region is still allocated once this region is dropped, or `None`
+    /// if the memory can't be referenced from anywhere else
+    pub fn detach(&self) -> Option<DetachedRegion> {
+        let backings = match self {
+            PhysicalRegion::CopyOnWrite(cow) => {
+                cow.pages.iter().map(|page| Arc::downgrade(&page.backing.region)).collect()
+            }
+            PhysicalRegion::Shared(shared) => alloc::vec![Arc::downgrade(&shared.region)],
+            PhysicalRegion::Unique(_) => return None,
+        };
+
+        Some(DetachedRegion { backings })
+    }
+}
+
+/// Physical memory which was shared with other regions, without keeping it
+/// allocated itself. See [`PhysicalRegion::detach`].
+#[derive(Debug)]
+pub struct DetachedRegion {
+    backings: Vec<Weak<UniquePhysicalRegion>>,
+}
+
+impl DetachedRegion {
+    /// Whether any of the memory is still allocated, because another region
+    /// still holds on to it
+    pub fn is_allocated(&self) -> bool {
+        self.backings.iter().any(|backing| backing.strong_count() > 0)
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -91,35 +131,62 @@ impl UniquePhysicalRegion {
 
     #[track_caller]
     pub fn alloc_contiguous(page_size: PageSize, n_pages: usize) -> Self {
+        Self::try_alloc_contiguous(page_size, n_pages).expect("couldn't alloc contiguous region")
+    }
+
+    /// Same as [`Self::alloc_contiguous`], except returns `None` if there isn't
+    /// a large enough physically contiguous range of memory available
+    pub fn try_alloc_contiguous(page_size: PageSize, n_pages: usize) -> Option<Self> {
         // log::trace!("Allocating page for contiguous region");
         let mut lock = PHYSICAL_MEMORY_ALLOCATOR.lock();
 
-        let kind = PhysicalRegionKind::Contiguous(unsafe {
-            lock.alloc_contiguous(page_size, n_pages).expect("couldn't alloc contiguous region")
-        });
+        let kind = PhysicalRegionKind::Contiguous(unsafe { lock.alloc_contiguous(page_size, n_pages)? });
+
+        Some(Self { kind, page_size, n_pages })
+    }
+
+    /// Same as [`Self::try_alloc_contiguous`] for kilopages, except the
+    /// physical address of the first page is a multiple of `align_pages`
+    /// kilopages
+    pub fn try_alloc_contiguous_aligned(n_pages: usize, align_pages: usize) -> Option<Self> {
+        let mut lock = PHYSICAL_MEMORY_ALLOCATOR.lock();
+
+        let kind = PhysicalRegionKind::Contiguous(unsafe { lock.alloc_contiguous_aligned(n_pages, align_pages)? });
 
-        Self { kind, page_size, n_pages }
+        Some(Self { kind, page_size: PageSize::Kilopage, n_pages })
     }
 
     #[track_caller]
     pub fn alloc_sparse(page_size: PageSize, n_pages: usize) -> Self {
+        Self::try_alloc_sparse(page_size, n_pages).expect("couldn't alloc sparse region")
+    }
+
+    /// Same as [`Self::alloc_sparse`], except returns `None` if there aren't
+    /// enough free pages of the given [`PageSize`] available. Any pages which
+    /// were allocated before running out are freed.
+    pub fn try_alloc_sparse(page_size: PageSize, n_pages: usize) -> Option<Self> {
         if n_pages == 1 {
-            return Self::alloc_contiguous(page_size, 1);
+            return Self::try_alloc_contiguous(page_size, 1);
         }
 
-        let kind = PhysicalRegionKind::Sparse(unsafe {
-            let mut allocator = PHYSICAL_MEMORY_ALLOCATOR.lock();
-            let mut pages = Vec::with_capacity(n_pages);
+        let mut allocator = PHYSICAL_MEMORY_ALLOCATOR.lock();
+        let mut pages = Vec::with_capacity(n_pages);
 
-            for _ in 0..n_pages {
-                // log::trace!("Allocating page for sparse region");
-                pages.push(allocator.alloc(page_size).expect("couldn't alloc sparse region"));
-            }
+        for _ in 0..n_pages {
+            // log::trace!("Allocating page for sparse region");
+            match unsafe { allocator.alloc(page_size) } {
+                Some(page) => pages.push(page),
+                None => {
+                    for page in pages {
+                        unsafe { allocator.dealloc(page, page_size) };
+                    }
 
-            pages
-        });
+                    return None;
+                }
+            }
+        }
 
-        Self { kind, page_size, n_pages }
+        Some(Self { kind: PhysicalRegionKind::Sparse(pages), page_size, n_pages })
     }
 
     pub fn physical_addresses(&self) -> impl Iterator<Item = PhysicalAddress> + '_ {
@@ -138,6 +205,19 @@ impl UniquePhysicalRegion {
         contig.into_iter().flatten().chain(sparse.into_iter().flatten())
     }
 
+    /// The [`PhysicalAddress`] of the page at the given index in the region
+    #[track_caller]
+    pub fn page_address(&self, index: usize) -> PhysicalAddress {
+        assert!(index < self.n_pages, "page index out of bounds");
+
+        match &self.kind {
+            PhysicalRegionKind::Contiguous(start) | PhysicalRegionKind::Mmio(start) => {
+                start.as_phys_address().offset(index * self.page_size.to_byte_size())
+            }
+            PhysicalRegionKind::Sparse(pages) => pages[index].as_phys_address(),
+        }
+    }
+
     pub fn copy_data_into(&mut self, data: &[u8]) {
         for (phys_addr, data) in self.physical_addresses().zip(data.chunks(self.page_size.to_byte_size())) {
             let virt_addr = phys2virt(phys_addr).as_mut_ptr();
@@ -206,3 +286,157 @@ impl core::ops::Deref for SharedPhysicalRegion {
         &self.region
     }
 }
+
+/// A region whose pages are shared between address spaces until they're
+/// written to, at which point the writing address space is given its own copy
+/// of the page
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyOnWriteRegion {
+    page_size: PageSize,
+    pages: Vec<Arc<CopyOnWritePage>>,
+}
+
+/// The page at `index` within `backing`. Every region sharing the page holds a
+/// reference to the same [`Arc`], so its strong count is the number of address
+/// spaces the page is shared between.
+#[derive(Debug, PartialEq)]
+struct CopyOnWritePage {
+    backing: SharedPhysicalRegion,
+    index: usize,
+}
+
+impl CopyOnWritePage {
+    fn address(&self) -> PhysicalAddress {
+        self.backing.page_address(self.index)
+    }
+}
+
+impl CopyOnWriteRegion {
+    pub fn new(backing: SharedPhysicalRegion) -> Self {
+        let pages =
+            (0..backing.n_pages()).map(|index| Arc::new(CopyOnWritePage { backing: backing.clone(), index })).collect();
+        Self { page_size: backing.page_size(), pages }
+    }
+
+    pub fn physical_addresses(&self) -> impl Iterator<Item = PhysicalAddress> + '_ {
+        self.pages.iter().map(|page| page.address())
+    }
+
+    /// Whether the page at the given index is still shared with another region,
+    /// so that [`Self::copy_page`] needs to allocate a copy of it
+    pub fn is_page_shared(&self, index: usize) -> bool {
+        Arc::strong_count(&self.pages[index]) > 1
+    }
+
+    /// Give this region its own copy of the page at the given index, returning
+    /// the [`PhysicalAddress`] of the copy. Pages which are no longer shared
+    /// with any other region are reused as-is.
+    #[track_caller]
+    pub fn copy_page(&mut self, index: usize) -> PhysicalAddress {
+        let page_size = self.page_size;
+        let page = &mut self.pages[index];
+
+        if Arc::strong_count(page) == 1 {
+            return page.address();
+        }
+
+        let mut copy = UniquePhysicalRegion::alloc_contiguous(page_size, 1);
+        let original =
+            unsafe { core::slice::from_raw_parts(phys2virt(page.address()).as_ptr(), page_size.to_byte_size()) };
+        copy.copy_data_into(original);
+
+        *page = Arc::new(CopyOnWritePage { backing: copy.into_shared_region(), index: 0 });
+        page.address()
+    }
+
+    pub fn page_size(&self) -> PageSize {
+        self.page_size
+    }
+
+    pub fn n_pages(&self) -> usize {
+        self.pages.len()
+    }
+}
+
+/// A region which is only backed by physical memory once its pages are first
+/// accessed, at which point each page is given its own zeroed frame. This
+/// keeps large mappings which are mostly never touched, such as stacks, from
+/// consuming physical memory up front.
+#[derive(Debug, PartialEq)]
+pub struct LazyRegion {
+    page_size: PageSize,
+    n_pages: usize,
+    /// The frames backing the pages that have been accessed, keyed by their
+    /// index within the region
+    resident: BTreeMap<usize, UniquePhysicalRegion>,
+}
+
+impl LazyRegion {
+    pub fn new(page_size: PageSize, n_pages: usize) -> Self {
+        Self { page_size, n_pages, resident: BTreeMap::new() }
+    }
+
+    /// The [`PhysicalAddress`] of the page at the given index, if it's resident
+    pub fn resident_page(&self, index: usize) -> Option<PhysicalAddress> {
+        self.resident.get(&index).map(|frame| frame.page_address(0))
+    }
+
+    /// Allocate and zero a frame for the page at the given index, returning its
+    /// [`PhysicalAddress`], or `None` if the page is already resident or there
+    /// is no free physical memory left
+    #[track_caller]
+    pub fn populate(&mut self, index: usize) -> Option<PhysicalAddress> {
+        assert!(index < self.n_pages, "page index out of bounds");
+
+        if self.resident.contains_key(&index) {
+            return None;
+        }
+
+        let mut frame = UniquePhysicalRegion::try_alloc_contiguous(self.page_size, 1)?;
+        frame.zero();
+
+        let phys = frame.page_address(0);
+        self.resident.insert(index, frame);
+
+        Some(phys)
+    }
+
+    /// The resident pages as `(index, physical address)` pairs, in ascending
+    /// order of index
+    pub fn resident_pages(&self) -> impl Iterator<Item = (usize, PhysicalAddress)> + '_ {
+        self.resident.iter().map(|(index, frame)| (*index, frame.page_address(0)))
+    }
+
+    /// Create a new lazy region of the same size containing copies of every
+    /// resident page, with the rest of the pages left to be allocated on first
+    /// access
+    #[track_caller]
+    pub fn duplicate(&self) -> Self {
+        let resident = self
+            .resident
+            .iter()
+            .map(|(index, frame)| {
+                let mut copy = UniquePhysicalRegion::alloc_contiguous(self.page_size, 1);
+                let original = unsafe {
+                    core::slice::from_raw_parts(
+                        phys2virt(frame.page_address(0)).as_ptr(),
+                        self.page_size.to_byte_size(),
+                    )
+                };
+                copy.copy_data_into(original);
+
+                (*index, copy)
+            })
+            .collect();
+
+        Self { page_size: self.page_size, n_pages: self.n_pages, resident }
+    }
+
+    pub fn page_size(&self) -> PageSize {
+        self.page_size
+    }
+
+    pub fn n_pages(&self) -> usize {
+        self.n_pages
+    }
+}
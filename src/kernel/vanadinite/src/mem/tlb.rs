@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{boot::harts::MAX_HARTS, platform::rfence};
+use alloc::vec::Vec;
+use core::ops::Range;
+use librust::task::HartMask;
+
+/// The harts which may still have TLB entries cached for an address space.
+/// Address spaces are tagged with their task's ASID, so a hart's entries stick
+/// around after it switches away, and a hart is never removed once it has run
+/// the address space.
+#[derive(Debug, Clone, Copy)]
+pub struct ResidentHarts {
+    harts: HartMask,
+    /// Whether the address space has run on a hart which doesn't fit in a
+    /// [`HartMask`], in which case every running hart is shot down instead
+    untracked: bool,
+}
+
+impl ResidentHarts {
+    pub const fn new() -> Self {
+        Self { harts: HartMask::empty(), untracked: false }
+    }
+
+    /// Record that `hart` is about to run the address space
+    pub fn mark(&mut self, hart: usize) {
+        if hart < MAX_HARTS {
+            self.harts = self.harts.with(hart);
+        } else {
+            self.untracked = true;
+        }
+    }
+
+    /// The harts in `live` other than `current` which need to be sent a
+    /// shootdown. The current hart flushes its own TLB with a local
+    /// `sfence.vma` instead.
+    pub fn remote(&self, current: usize, live: HartMask) -> HartMask {
+        let resident = if self.untracked { live.value() } else { self.harts.value() & live.value() };
+
+        let remote = HartMask::new(resident).iter().filter(|hart| *hart != current);
+        remote.fold(HartMask::empty(), HartMask::with)
+    }
+}
+
+/// Flush the TLB entries for the virtual addresses in `range` on every hart in
+/// `harts`, after their mappings were removed or had permissions taken away
+pub fn shootdown(harts: HartMask, range: Range<usize>) {
+    if harts.is_empty() {
+        return;
+    }
+
+    let hart_ids = harts.iter().collect::<Vec<_>>();
+    if let Err(e) = rfence::remote_sfence_vma_for(&hart_ids, range.clone()) {
+        log::error!("Failed to shoot down TLB entries for {:#x?} on harts {:?}: {:?}", range, harts, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIVE: HartMask = HartMask::new(0b1111);
+
+    #[test]
+    fn only_other_resident_harts_are_targeted() {
+        let mut resident = ResidentHarts::new();
+        assert!(resident.remote(0, LIVE).is_empty());
+
+        // The address space has only run on the current hart, which flushes
+        // its own TLB
+        resident.mark(0);
+        assert!(resident.remote(0, LIVE).is_empty());
+
+        // Once it has migrated, the hart it left still has entries cached
+        resident.mark(2);
+        assert_eq!(resident.remote(2, LIVE), HartMask::single(0));
+        assert_eq!(resident.remote(0, LIVE), HartMask::single(2));
+        assert_eq!(resident.remote(1, LIVE), HartMask::empty().with(0).with(2));
+
+        // Harts which aren't running don't need to be shot down
+        assert_eq!(resident.remote(0, HartMask::single(0)), HartMask::empty());
+    }
+
+    #[test]
+    fn untracked_harts_shoot_down_every_live_hart() {
+        let mut resident = ResidentHarts::new();
+        resident.mark(1);
+        resident.mark(70);
+
+        assert_eq!(resident.remote(1, LIVE), HartMask::empty().with(0).with(2).with(3));
+        assert_eq!(resident.remote(70, LIVE), LIVE);
+    }
+
+    #[test]
+    fn shootdown_without_targets_is_skipped() {
+        // No SBI call is made, so this works even without the RFENCE extension
+        shootdown(HartMask::empty(), 0x1000..0x2000);
+    }
+}
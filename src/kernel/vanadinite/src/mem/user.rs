@@ -67,6 +67,21 @@ impl<T> RawUserPtr<ReadWrite, T> {
     pub fn writable(addr: VirtualAddress) -> Self {
         Self { addr, typë: PhantomData, mode: PhantomData }
     }
+
+    /// # Safety
+    /// Same as [`Self::validate`]
+    ///
+    /// Gives the task its own copy of any copy-on-write pages and populates any
+    /// untouched lazy pages the pointer covers before validating it, so that
+    /// writes through the pointer never land in memory shared with another
+    /// address space
+    pub unsafe fn validate_writable(
+        self,
+        manager: &mut UserspaceMemoryManager,
+    ) -> Result<ValidatedUserPtr<ReadWrite, T>, InvalidUserPtr> {
+        manager.resolve_copy_on_write_range(self.addr..self.addr.add(core::mem::size_of::<T>()));
+        self.validate(manager)
+    }
 }
 
 #[derive(Debug)]
@@ -91,7 +106,7 @@ impl<T> ValidatedUserPtr<Read, T> {
     }
 }
 
-impl<T: Copy> ValidatedUserPtr<Read, T> {
+impl<T: Copy> ValidatedUserPtr<ReadWrite, T> {
     pub fn write(&mut self, value: T) {
         let _guard = TemporaryUserMemoryAccess::new();
         unsafe { *self.addr.as_mut_ptr().cast() = value };
@@ -208,6 +223,11 @@ impl<Mode: UserPtrMode, T> RawUserSlice<Mode, T> {
         self.addr
     }
 
+    /// The range of addresses covered by the slice
+    pub fn range(&self) -> core::ops::Range<VirtualAddress> {
+        self.addr..self.addr.add(core::mem::size_of::<T>() * self.len)
+    }
+
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -223,6 +243,21 @@ impl<T> RawUserSlice<ReadWrite, T> {
     pub fn writable(addr: VirtualAddress, len: usize) -> Self {
         Self { addr, len, typë: PhantomData, mode: PhantomData }
     }
+
+    /// # Safety
+    /// Same as [`Self::validate`]
+    ///
+    /// Gives the task its own copy of any copy-on-write pages and populates any
+    /// untouched lazy pages the slice covers before validating it, so that
+    /// writes through the slice never land in memory shared with another
+    /// address space
+    pub unsafe fn validate_writable(
+        self,
+        manager: &mut UserspaceMemoryManager,
+    ) -> Result<ValidatedUserSlice<ReadWrite, T>, (VirtualAddress, InvalidUserPtr)> {
+        manager.resolve_copy_on_write_range(self.range());
+        self.validate(manager)
+    }
 }
 
 unsafe impl<Mode: UserPtrMode, T> Send for RawUserSlice<Mode, T> {}
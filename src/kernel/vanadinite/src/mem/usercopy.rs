@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Copying to and from a task's memory for syscalls which take user pointers
+//!
+//! Every page the copy touches is looked up in the task's page tables and must
+//! be mapped as user memory with the required permissions before anything is
+//! copied, so a bad pointer fails the copy without touching any memory. The
+//! copy itself goes through the physical memory map instead of the user
+//! mapping, so it works on any address space, not just the current one.
+//! Pages of lazy regions which haven't been touched yet read as zeroes, the
+//! same as they would for the task, without allocating frames for them.
+
+use super::{
+    manager::UserspaceMemoryManager,
+    paging::{flags::Flags, PageSize, VirtualAddress},
+    phys2virt,
+    region::MemoryRegion,
+    user::InvalidUserPtr,
+};
+
+/// Why a copy to or from user memory failed
+#[derive(Debug, Clone, Copy)]
+pub enum KError {
+    /// The address isn't user memory with the permissions the copy needs
+    InvalidAddress(VirtualAddress, InvalidUserPtr),
+}
+
+/// Copy `buffer.len()` bytes of readable user memory starting at `from` into
+/// `buffer`, failing with the first invalid address if any part of it isn't.
+/// Untouched lazy pages are copied as zeroes.
+pub fn copy_from_user(manager: &UserspaceMemoryManager, from: VirtualAddress, buffer: &mut [u8]) -> Result<(), KError> {
+    validate(manager, from, buffer.len(), Flags::READ)?;
+
+    let mut copied = 0;
+    for_each_page(manager, from, buffer.len(), |src, len| {
+        match src {
+            // Safety: the page was validated above and `len` doesn't go past
+            // its end
+            Some(src) => buffer[copied..][..len].copy_from_slice(unsafe { core::slice::from_raw_parts(src, len) }),
+            None => buffer[copied..][..len].fill(0),
+        }
+        copied += len;
+    });
+
+    Ok(())
+}
+
+/// Copy `data` into writable user memory starting at `to`, failing with the
+/// first invalid address if any part of it isn't. Pending copy-on-write and lazy
+/// pages in the range are resolved first, like a write from the task would.
+pub fn copy_to_user(manager: &mut UserspaceMemoryManager, to: VirtualAddress, data: &[u8]) -> Result<(), KError> {
+    let end = to.checked_add(data.len()).ok_or(KError::InvalidAddress(to, InvalidUserPtr::InvalidAccess))?;
+    manager.resolve_copy_on_write_range(to..end);
+
+    validate(manager, to, data.len(), Flags::READ | Flags::WRITE)?;
+
+    let mut copied = 0;
+    for_each_page(manager, to, data.len(), |dst, len| {
+        // Safety: the page was validated above, which means it's resident since
+        // untouched lazy pages aren't writable, and `len` doesn't go past its
+        // end
+        let dst = unsafe { core::slice::from_raw_parts_mut(dst.unwrap(), len) };
+        dst.copy_from_slice(&data[copied..][..len]);
+        copied += len;
+    });
+
+    Ok(())
+}
+
+fn validate(
+    manager: &UserspaceMemoryManager,
+    start: VirtualAddress,
+    len: usize,
+    required: Flags,
+) -> Result<(), KError> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let end = start.checked_add(len).ok_or(KError::InvalidAddress(start, InvalidUserPtr::InvalidAccess))?;
+    let start_page = start.align_down_to(PageSize::Kilopage);
+    let end_page = end.align_to_next(PageSize::Kilopage);
+
+    for page in (start_page.as_usize()..end_page.as_usize()).step_by(PageSize::Kilopage.to_byte_size()) {
+        let page = VirtualAddress::new(page);
+        let error_at = page.max(start);
+
+        if page.is_kernel_region() {
+            return Err(KError::InvalidAddress(error_at, InvalidUserPtr::InvalidAccess));
+        }
+
+        let (flags, readable) = match manager.page_flags(page) {
+            Some(flags) => (Some(flags), manager.resolve(page).is_some()),
+            // Writes need the page to be resident, which `copy_to_user` has
+            // already tried to make it
+            None if !(required & Flags::WRITE) => (untouched_lazy_page(manager, page).map(|(_, flags)| flags), true),
+            None => (None, false),
+        };
+
+        match flags {
+            Some(flags) if !(flags & (Flags::VALID | Flags::USER | required)) => {
+                return Err(KError::InvalidAddress(error_at, InvalidUserPtr::InvalidAccess))
+            }
+            Some(_) if readable => {}
+            _ => return Err(KError::InvalidAddress(error_at, InvalidUserPtr::NotMapped)),
+        }
+    }
+
+    Ok(())
+}
+
+/// The page size and permissions of the lazy region containing `at`, if the
+/// page containing `at` hasn't been touched yet and so has no frame allocated
+/// for it
+fn untouched_lazy_page(manager: &UserspaceMemoryManager, at: VirtualAddress) -> Option<(PageSize, Flags)> {
+    let region = manager.region_for(at)?;
+    let Some(MemoryRegion::Lazy(lazy)) = &region.region else { return None };
+    let index = (at.as_usize() - region.span.start.as_usize()) / lazy.page_size().to_byte_size();
+
+    lazy.resident_page(index).is_none().then_some((lazy.page_size(), region.permissions))
+}
+
+/// Call `f` with a kernel pointer to, and the number of bytes in, each piece of
+/// the already validated range which is contiguous in physical memory. Pieces
+/// which are part of untouched lazy pages get `None` instead of a pointer.
+fn for_each_page(
+    manager: &UserspaceMemoryManager,
+    start: VirtualAddress,
+    len: usize,
+    mut f: impl FnMut(Option<*mut u8>, usize),
+) {
+    let mut done = 0;
+    while done < len {
+        let at = start.add(done);
+        let page_size =
+            manager.page_size(at).or_else(|| untouched_lazy_page(manager, at).map(|(page_size, _)| page_size)).unwrap();
+        let offset = at.offset_into_page(page_size);
+        let chunk = (page_size.to_byte_size() - offset).min(len - done);
+
+        f(manager.resolve(at).map(|phys| phys2virt(phys).add(offset).as_mut_ptr()), chunk);
+        done += chunk;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mem::manager::{AddressRegionKind, FillOption, RegionDescription},
+        utils::Units,
+    };
+
+    fn region(manager: &mut UserspaceMemoryManager, at: usize, flags: Flags, fill: FillOption<'_>) -> VirtualAddress {
+        manager
+            .alloc_region(
+                Some(VirtualAddress::new(at)),
+                RegionDescription {
+                    size: PageSize::Kilopage,
+                    count: 2,
+                    contiguous: false,
+                    flags: Flags::VALID | Flags::USER | flags,
+                    fill,
+                    kind: AddressRegionKind::Data,
+                },
+            )
+            .start
+    }
+
+    #[test]
+    fn valid_buffer() {
+        let mut manager = UserspaceMemoryManager::new();
+        let mut data = [0u8; 8192];
+        data.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let start = region(&mut manager, 0x1_0000, Flags::READ | Flags::WRITE, FillOption::Data(&data));
+
+        // Crossing the page boundary, which isn't contiguous in physical memory
+        let at = start.add(4.kib() - 3);
+        let mut buffer = [0u8; 6];
+        copy_from_user(&manager, at, &mut buffer).unwrap();
+        assert_eq!(buffer, data[4.kib() - 3..][..6]);
+
+        copy_to_user(&mut manager, at, b"kernel").unwrap();
+        copy_from_user(&manager, start.add(4.kib() - 4), &mut buffer).unwrap();
+        assert_eq!(&buffer, &[data[4.kib() - 4], b'k', b'e', b'r', b'n', b'e']);
+
+        // Empty copies never fail
+        copy_from_user(&manager, VirtualAddress::new(0), &mut []).unwrap();
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn unmapped_buffer() {
+        let mut manager = UserspaceMemoryManager::new();
+        let start = region(&mut manager, 0x1_0000, Flags::READ | Flags::WRITE, FillOption::Zeroed);
+        let end = start.add(8.kib());
+
+        let mut buffer = [0xAAu8; 16];
+        assert!(matches!(
+            copy_from_user(&manager, VirtualAddress::new(0x4_0000), &mut buffer),
+            Err(KError::InvalidAddress(addr, InvalidUserPtr::NotMapped)) if addr == VirtualAddress::new(0x4_0000)
+        ));
+
+        // Running off the end of the region fails before anything is copied
+        let at = VirtualAddress::new(end.as_usize() - 8);
+        assert!(matches!(
+            copy_to_user(&mut manager, at, &buffer),
+            Err(KError::InvalidAddress(addr, InvalidUserPtr::NotMapped)) if addr == end
+        ));
+        copy_from_user(&manager, at, &mut buffer[..8]).unwrap();
+        assert_eq!(buffer[..8], [0; 8]);
+
+        // Kernel addresses are never user memory
+        let kernel = VirtualAddress::from_ptr(buffer.as_ptr());
+        assert!(matches!(
+            copy_from_user(&manager, kernel, &mut [0; 4]),
+            Err(KError::InvalidAddress(_, InvalidUserPtr::InvalidAccess))
+        ));
+        assert!(matches!(
+            copy_to_user(&mut manager, kernel, &[0; 4]),
+            Err(KError::InvalidAddress(_, InvalidUserPtr::InvalidAccess))
+        ));
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn wrong_permissions() {
+        let mut manager = UserspaceMemoryManager::new();
+        let read_only = region(&mut manager, 0x1_0000, Flags::READ, FillOption::Data(b"read only"));
+
+        assert!(matches!(
+            copy_to_user(&mut manager, read_only, b"overwritten"),
+            Err(KError::InvalidAddress(addr, InvalidUserPtr::InvalidAccess)) if addr == read_only
+        ));
+
+        let mut buffer = [0u8; 9];
+        copy_from_user(&manager, read_only, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"read only");
+
+        // Mapped, but not accessible from userspace
+        let supervisor = manager
+            .alloc_region(
+                Some(VirtualAddress::new(0x4_0000)),
+                RegionDescription {
+                    size: PageSize::Kilopage,
+                    count: 1,
+                    contiguous: false,
+                    flags: Flags::VALID | Flags::READ | Flags::WRITE,
+                    fill: FillOption::Zeroed,
+                    kind: AddressRegionKind::Data,
+                },
+            )
+            .start;
+        assert!(matches!(
+            copy_from_user(&manager, supervisor, &mut buffer),
+            Err(KError::InvalidAddress(_, InvalidUserPtr::InvalidAccess))
+        ));
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn lazy_pages() {
+        let mut manager = UserspaceMemoryManager::new();
+        let flags = Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE;
+        let at = Some(VirtualAddress::new(0x1_0000));
+        let start = manager.alloc_lazy_region(at, PageSize::Kilopage, 2, flags, AddressRegionKind::Data).start;
+
+        // Untouched pages read as zeroes without being populated
+        let mut buffer = [0xAAu8; 8];
+        copy_from_user(&manager, start.add(4.kib() - 4), &mut buffer).unwrap();
+        assert_eq!(buffer, [0; 8]);
+        assert_eq!(manager.resolve(start), None);
+        assert_eq!(manager.resident_memory(), 0);
+
+        // Writing populates the page, after which the rest still reads as
+        // zeroes
+        copy_to_user(&mut manager, start.add(4.kib() - 2), b"lazy").unwrap();
+        assert!(manager.resolve(start).is_some());
+        copy_from_user(&manager, start.add(4.kib() - 4), &mut buffer).unwrap();
+        assert_eq!(&buffer, b"\0\0lazy\0\0");
+
+        core::mem::forget(manager);
+    }
+}
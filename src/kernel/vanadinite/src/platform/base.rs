@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2021 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+/// The SBI Base extension ID
+pub const EXTENSION_ID: usize = 0x10;
+
+const SPEC_VERSION_FID: usize = 0;
+const IMPL_ID_FID: usize = 1;
+
+/// A version of the SBI specification, ordered so that later versions compare
+/// greater than earlier ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpecVersion {
+    pub major: u16,
+    pub minor: u32,
+}
+
+impl SpecVersion {
+    pub const fn new(major: u16, minor: u32) -> Self {
+        Self { major, minor }
+    }
+
+    /// Decode the value returned by `sbi_get_spec_version`, which holds the
+    /// major version in bits 30:24 and the minor version in bits 23:0
+    pub const fn from_raw(raw: usize) -> Self {
+        Self { major: ((raw >> 24) & 0x7F) as u16, minor: (raw & 0xFF_FFFF) as u32 }
+    }
+}
+
+impl core::fmt::Display for SpecVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// The known SBI implementations, as returned by `sbi_get_impl_id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImplementationId {
+    BerkeleyBootLoader,
+    OpenSbi,
+    Xvisor,
+    Kvm,
+    RustSbi,
+    Diosix,
+    Coffer,
+    XenProject,
+    PolarFireHartSoftwareServices,
+    Coreboot,
+    Oreboot,
+    Other(usize),
+}
+
+impl From<usize> for ImplementationId {
+    fn from(id: usize) -> Self {
+        match id {
+            0 => Self::BerkeleyBootLoader,
+            1 => Self::OpenSbi,
+            2 => Self::Xvisor,
+            3 => Self::Kvm,
+            4 => Self::RustSbi,
+            5 => Self::Diosix,
+            6 => Self::Coffer,
+            7 => Self::XenProject,
+            8 => Self::PolarFireHartSoftwareServices,
+            9 => Self::Coreboot,
+            10 => Self::Oreboot,
+            id => Self::Other(id),
+        }
+    }
+}
+
+/// The version of the SBI specification implemented by the SBI implementation
+pub fn spec_version() -> SpecVersion {
+    SpecVersion::from_raw(unsafe { ecall(SPEC_VERSION_FID) })
+}
+
+/// Whether the SBI implementation implements at least version `major.minor`
+/// of the SBI specification, e.g. for gating extensions which were introduced
+/// in a later version
+pub fn spec_version_at_least(major: u16, minor: u32) -> bool {
+    spec_version() >= SpecVersion::new(major, minor)
+}
+
+/// The implementor of the SBI implementation
+pub fn impl_id() -> ImplementationId {
+    ImplementationId::from(unsafe { ecall(IMPL_ID_FID) })
+}
+
+/// The functions of the base extension are required to always succeed, so
+/// only the value is returned
+unsafe fn ecall(fid: usize) -> usize {
+    let value: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            lateout("a0") _,
+            lateout("a1") value,
+            in("a6") fid,
+            in("a7") EXTENSION_ID,
+        );
+    }
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_version_ordering() {
+        assert!(SpecVersion::new(2, 0) > SpecVersion::new(1, 0));
+        assert!(SpecVersion::new(1, 0) > SpecVersion::new(0, 3));
+        assert!(SpecVersion::new(0, 3) > SpecVersion::new(0, 2));
+        assert!(SpecVersion::new(0, 10) > SpecVersion::new(0, 3));
+        assert_eq!(SpecVersion::new(2, 0).max(SpecVersion::new(1, 5)), SpecVersion::new(2, 0));
+    }
+
+    #[test]
+    fn spec_version_from_raw() {
+        assert_eq!(SpecVersion::from_raw(0x0200_0000), SpecVersion::new(2, 0));
+        assert_eq!(SpecVersion::from_raw(0x0000_0003), SpecVersion::new(0, 3));
+        // Bit 31 is reserved
+        assert_eq!(SpecVersion::from_raw(0x8100_0001), SpecVersion::new(1, 1));
+        // The minor version is 24 bits wide
+        assert_eq!(SpecVersion::from_raw(0x0012_3456), SpecVersion::new(0, 0x12_3456));
+        assert_eq!(SpecVersion::from_raw(0x7FFF_FFFF), SpecVersion::new(0x7F, 0xFF_FFFF));
+    }
+
+    #[test]
+    fn known_impl_ids() {
+        assert_eq!(ImplementationId::from(1), ImplementationId::OpenSbi);
+        assert_eq!(ImplementationId::from(4), ImplementationId::RustSbi);
+        assert_eq!(ImplementationId::from(0x1234), ImplementationId::Other(0x1234));
+    }
+}
@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2021 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use sbi::{probe_extension, ExtensionAvailability, SbiError};
+
+/// The SBI Collaborative Processor Performance Control (CPPC) extension ID
+/// (`"CPPC"`), which gives access to the performance registers of the current
+/// hart
+pub const EXTENSION_ID: usize = 0x43505043;
+
+const PROBE_FID: usize = 0;
+const READ_FID: usize = 1;
+const WRITE_FID: usize = 3;
+
+pub type SbiResult<T> = Result<T, SbiError>;
+
+/// The standard CPPC register IDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CppcRegister {
+    HighestPerformance,
+    NominalPerformance,
+    LowestNonlinearPerformance,
+    LowestPerformance,
+    GuaranteedPerformance,
+    DesiredPerformance,
+    MinimumPerformance,
+    MaximumPerformance,
+    PerformanceReductionTolerance,
+    TimeWindow,
+    CounterWraparoundTime,
+    ReferencePerformanceCounter,
+    DeliveredPerformanceCounter,
+    PerformanceLimited,
+    CppcEnable,
+    AutonomousSelectionEnable,
+    AutonomousActivityWindow,
+    EnergyPerformancePreference,
+    ReferencePerformance,
+    LowestFrequency,
+    NominalFrequency,
+    TransitionLatency,
+}
+
+impl CppcRegister {
+    /// The register ID passed to the SBI implementation
+    pub const fn id(self) -> u32 {
+        match self {
+            Self::HighestPerformance => 0x00,
+            Self::NominalPerformance => 0x01,
+            Self::LowestNonlinearPerformance => 0x02,
+            Self::LowestPerformance => 0x03,
+            Self::GuaranteedPerformance => 0x04,
+            Self::DesiredPerformance => 0x05,
+            Self::MinimumPerformance => 0x06,
+            Self::MaximumPerformance => 0x07,
+            Self::PerformanceReductionTolerance => 0x08,
+            Self::TimeWindow => 0x09,
+            Self::CounterWraparoundTime => 0x0A,
+            Self::ReferencePerformanceCounter => 0x0B,
+            Self::DeliveredPerformanceCounter => 0x0C,
+            Self::PerformanceLimited => 0x0D,
+            Self::CppcEnable => 0x0E,
+            Self::AutonomousSelectionEnable => 0x0F,
+            Self::AutonomousActivityWindow => 0x10,
+            Self::EnergyPerformancePreference => 0x11,
+            Self::ReferencePerformance => 0x12,
+            Self::LowestFrequency => 0x13,
+            Self::NominalFrequency => 0x14,
+            Self::TransitionLatency => 0x8000_0000,
+        }
+    }
+
+    /// Look up the register with the given ID, returning `None` for reserved
+    /// IDs
+    pub const fn from_id(id: u32) -> Option<Self> {
+        Some(match id {
+            0x00 => Self::HighestPerformance,
+            0x01 => Self::NominalPerformance,
+            0x02 => Self::LowestNonlinearPerformance,
+            0x03 => Self::LowestPerformance,
+            0x04 => Self::GuaranteedPerformance,
+            0x05 => Self::DesiredPerformance,
+            0x06 => Self::MinimumPerformance,
+            0x07 => Self::MaximumPerformance,
+            0x08 => Self::PerformanceReductionTolerance,
+            0x09 => Self::TimeWindow,
+            0x0A => Self::CounterWraparoundTime,
+            0x0B => Self::ReferencePerformanceCounter,
+            0x0C => Self::DeliveredPerformanceCounter,
+            0x0D => Self::PerformanceLimited,
+            0x0E => Self::CppcEnable,
+            0x0F => Self::AutonomousSelectionEnable,
+            0x10 => Self::AutonomousActivityWindow,
+            0x11 => Self::EnergyPerformancePreference,
+            0x12 => Self::ReferencePerformance,
+            0x13 => Self::LowestFrequency,
+            0x14 => Self::NominalFrequency,
+            0x8000_0000 => Self::TransitionLatency,
+            _ => return None,
+        })
+    }
+}
+
+/// Whether the SBI implementation provides the CPPC extension
+pub fn available() -> bool {
+    matches!(probe_extension(EXTENSION_ID), ExtensionAvailability::Available(_))
+}
+
+/// Probe whether the given register is implemented, returning its width in
+/// bits
+pub fn probe(register: CppcRegister) -> SbiResult<u32> {
+    ensure_available()?;
+    let width = unsafe { ecall(PROBE_FID, register.id() as usize, 0)? };
+
+    // A width of zero means the register isn't implemented on this platform
+    match width {
+        0 => Err(SbiError::NotSupported),
+        width => Ok(width as u32),
+    }
+}
+
+/// Read the value of the given register
+pub fn read(register: CppcRegister) -> SbiResult<u64> {
+    ensure_available()?;
+    unsafe { ecall(READ_FID, register.id() as usize, 0).map(|value| value as u64) }
+}
+
+/// Write a new value to the given register
+pub fn write(register: CppcRegister, value: u64) -> SbiResult<()> {
+    ensure_available()?;
+    unsafe { ecall(WRITE_FID, register.id() as usize, value as usize).map(drop) }
+}
+
+fn ensure_available() -> SbiResult<()> {
+    match available() {
+        true => Ok(()),
+        false => Err(SbiError::NotSupported),
+    }
+}
+
+/// Map an SBI error code to an [`SbiError`]
+fn sbi_error(code: isize) -> SbiError {
+    match code {
+        -2 => SbiError::NotSupported,
+        -3 => SbiError::InvalidParameter,
+        -4 => SbiError::Denied,
+        -5 => SbiError::InvalidAddress,
+        -6 => SbiError::AlreadyAvailable,
+        -7 => SbiError::AlreadyStarted,
+        -8 => SbiError::AlreadyStopped,
+        _ => SbiError::Failed,
+    }
+}
+
+unsafe fn ecall(fid: usize, arg0: usize, arg1: usize) -> SbiResult<usize> {
+    let error: isize;
+    let value: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a6") fid,
+            in("a7") EXTENSION_ID,
+        );
+    }
+
+    match error {
+        0 => Ok(value),
+        code => Err(sbi_error(code)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_ids() {
+        assert_eq!(CppcRegister::HighestPerformance.id(), 0x00);
+        assert_eq!(CppcRegister::DesiredPerformance.id(), 0x05);
+        assert_eq!(CppcRegister::NominalFrequency.id(), 0x14);
+        assert_eq!(CppcRegister::TransitionLatency.id(), 0x8000_0000);
+
+        for id in (0x00..=0x14).chain([0x8000_0000]) {
+            assert_eq!(CppcRegister::from_id(id).map(CppcRegister::id), Some(id));
+        }
+
+        assert_eq!(CppcRegister::from_id(0x15), None);
+        assert_eq!(CppcRegister::from_id(0x8000_0001), None);
+    }
+
+    #[test]
+    fn error_mapping() {
+        assert!(matches!(sbi_error(-1), SbiError::Failed));
+        assert!(matches!(sbi_error(-2), SbiError::NotSupported));
+        assert!(matches!(sbi_error(-3), SbiError::InvalidParameter));
+        assert!(matches!(sbi_error(-4), SbiError::Denied));
+        assert!(matches!(sbi_error(-100), SbiError::Failed));
+    }
+}
@@ -0,0 +1,112 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2021 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::mem::paging::PhysicalAddress;
+use core::sync::atomic::{AtomicU8, Ordering};
+use sbi::{probe_extension, ExtensionAvailability, SbiError};
+
+/// The SBI Debug Console extension ID (`"DBCN"`)
+pub const EXTENSION_ID: usize = 0x4442434E;
+
+const WRITE_FID: usize = 0;
+const READ_FID: usize = 1;
+const WRITE_BYTE_FID: usize = 2;
+
+const UNKNOWN: u8 = 0;
+const AVAILABLE: u8 = 1;
+const UNAVAILABLE: u8 = 2;
+
+/// Cached result of probing for the extension, since the early boot console
+/// checks it for every write
+static AVAILABILITY: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+pub type SbiResult<T> = Result<T, SbiError>;
+
+/// Whether the SBI implementation provides the Debug Console extension
+pub fn available() -> bool {
+    match AVAILABILITY.load(Ordering::Relaxed) {
+        AVAILABLE => true,
+        UNAVAILABLE => false,
+        _ => {
+            let available = matches!(probe_extension(EXTENSION_ID), ExtensionAvailability::Available(_));
+            AVAILABILITY.store(if available { AVAILABLE } else { UNAVAILABLE }, Ordering::Relaxed);
+            available
+        }
+    }
+}
+
+/// Write up to `len` bytes starting at the physical address `base` to the
+/// debug console, returning the number of bytes which were written
+///
+/// # Safety
+///
+/// `base..base + len` must be readable memory
+pub unsafe fn console_write(base: PhysicalAddress, len: usize) -> SbiResult<usize> {
+    ensure_available()?;
+    unsafe { ecall(WRITE_FID, [len, base.as_usize(), 0]) }
+}
+
+/// Read up to `len` bytes from the debug console into the physical address
+/// `base`, returning the number of bytes which were read
+///
+/// # Safety
+///
+/// `base..base + len` must be writable memory
+pub unsafe fn console_read(base: PhysicalAddress, len: usize) -> SbiResult<usize> {
+    ensure_available()?;
+    unsafe { ecall(READ_FID, [len, base.as_usize(), 0]) }
+}
+
+/// Write a single byte to the debug console. Unlike [`console_write`], this
+/// doesn't need any memory to be shared with the SBI implementation, so it can
+/// be used before the heap is set up.
+pub fn console_write_byte(byte: u8) -> SbiResult<()> {
+    ensure_available()?;
+    unsafe { ecall(WRITE_BYTE_FID, [byte as usize, 0, 0]).map(drop) }
+}
+
+fn ensure_available() -> SbiResult<()> {
+    match available() {
+        true => Ok(()),
+        false => Err(SbiError::NotSupported),
+    }
+}
+
+/// Map an SBI error code to an [`SbiError`]
+fn sbi_error(code: isize) -> SbiError {
+    match code {
+        -2 => SbiError::NotSupported,
+        -3 => SbiError::InvalidParameter,
+        -4 => SbiError::Denied,
+        -5 => SbiError::InvalidAddress,
+        -6 => SbiError::AlreadyAvailable,
+        -7 => SbiError::AlreadyStarted,
+        -8 => SbiError::AlreadyStopped,
+        _ => SbiError::Failed,
+    }
+}
+
+unsafe fn ecall(fid: usize, [arg0, arg1, arg2]: [usize; 3]) -> SbiResult<usize> {
+    let error: isize;
+    let value: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") arg0 => error,
+            inlateout("a1") arg1 => value,
+            in("a2") arg2,
+            in("a6") fid,
+            in("a7") EXTENSION_ID,
+        );
+    }
+
+    match error {
+        0 => Ok(value),
+        code => Err(sbi_error(code)),
+    }
+}
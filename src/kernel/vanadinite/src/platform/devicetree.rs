@@ -0,0 +1,373 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::ops::Range;
+use fdt::{standard_nodes::Cpu, Fdt};
+
+/// Combine a value made up of any number of 32-bit big endian cells, keeping
+/// the low 128 bits if there are more than four cells
+pub fn read_cells(bytes: &[u8]) -> u128 {
+    bytes
+        .chunks_exact(4)
+        .fold(0, |value, cell| (value << 32) | u128::from(u32::from_be_bytes(cell.try_into().unwrap())))
+}
+
+/// A single `(address, size)` pair from a `reg` property
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegEntry {
+    pub address: u128,
+    pub size: u128,
+}
+
+/// Decodes a `reg` property with an arbitrary number of address and size
+/// cells, unlike [`fdt::node::FdtNode::reg`] which only handles one or two
+pub struct Reg<'a> {
+    bytes: &'a [u8],
+    address_cells: usize,
+    size_cells: usize,
+}
+
+impl<'a> Reg<'a> {
+    pub fn new(bytes: &'a [u8], address_cells: usize, size_cells: usize) -> Self {
+        Self { bytes, address_cells, size_cells }
+    }
+}
+
+impl Iterator for Reg<'_> {
+    type Item = RegEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry_len = (self.address_cells + self.size_cells) * 4;
+        if entry_len == 0 || self.bytes.len() < entry_len {
+            return None;
+        }
+
+        let (entry, rest) = self.bytes.split_at(entry_len);
+        let (address, size) = entry.split_at(self.address_cells * 4);
+        self.bytes = rest;
+
+        Some(RegEntry { address: read_cells(address), size: read_cells(size) })
+    }
+}
+
+/// The hart IDs from a CPU node's `reg` property, which replaces
+/// [`fdt::standard_nodes::CpuIds`] since that panics on `#address-cells`
+/// values other than one or two
+pub struct HartIds<'a> {
+    reg: &'a [u8],
+    address_cells: usize,
+}
+
+impl<'a> HartIds<'a> {
+    pub fn new(fdt: &Fdt<'a>, cpu: &Cpu<'_, 'a>) -> Self {
+        let address_cells = fdt.find_node("/cpus").map(|cpus| cpus.cell_sizes().address_cells).unwrap_or(1);
+        let reg = cpu.properties().find(|p| p.name == "reg").map(|reg| reg.value).unwrap_or(&[]);
+
+        Self { reg, address_cells }
+    }
+
+    pub fn first(&self) -> Option<u128> {
+        self.all().next()
+    }
+
+    /// The low 64 bits of the first hart ID
+    pub fn first_u64(&self) -> Option<u64> {
+        self.first().map(|id| id as u64)
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = u128> + 'a {
+        Reg::new(self.reg, self.address_cells, 0).map(|entry| entry.address)
+    }
+}
+
+/// The ID of the first hart described by `cpu`, if it has one
+pub fn hart_id(fdt: &Fdt<'_>, cpu: &Cpu<'_, '_>) -> Option<usize> {
+    HartIds::new(fdt, cpu).first_u64().map(|id| id as usize)
+}
+
+/// A region of memory set aside by a child of the `/reserved-memory` node, e.g.
+/// for firmware or a device's DMA pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReservedRegion<'a> {
+    /// The name of the node describing the region
+    pub name: &'a str,
+    pub address: u128,
+    pub size: u128,
+    /// The region must not be mapped by the OS at all, since e.g. speculative
+    /// accesses to it could fault
+    pub no_map: bool,
+    /// The OS may use the region for its own allocations as long as it can
+    /// give the memory back to the region's owner
+    pub reusable: bool,
+}
+
+impl ReservedRegion<'_> {
+    /// Whether the region has to be kept out of the physical memory
+    /// allocator, which is the case for every region that isn't `reusable`
+    pub fn is_exclusive(&self) -> bool {
+        !self.reusable
+    }
+}
+
+/// The statically placed regions described by the children of the
+/// `/reserved-memory` node, yielding one [`ReservedRegion`] per `reg` entry.
+/// Dynamically placed regions, which have a `size` but no `reg`, are left for
+/// the OS to allocate and so aren't included. This is separate from
+/// [`Fdt::memory_reservations`], which only covers the memory reservation
+/// block in the FDT header.
+pub fn reserved_memory<'b, 'a>(fdt: &'b Fdt<'a>) -> impl Iterator<Item = ReservedRegion<'a>> + 'b {
+    fdt.find_node("/reserved-memory").into_iter().flat_map(|node| {
+        let cells = node.cell_sizes();
+
+        node.children().flat_map(move |child| {
+            let reg = child.properties().find(|p| p.name == "reg").map(|reg| reg.value).unwrap_or(&[]);
+            let no_map = child.properties().any(|p| p.name == "no-map");
+            let reusable = child.properties().any(|p| p.name == "reusable");
+
+            Reg::new(reg, cells.address_cells, cells.size_cells).map(move |entry| ReservedRegion {
+                name: child.name,
+                address: entry.address,
+                size: entry.size,
+                no_map,
+                reusable,
+            })
+        })
+    })
+}
+
+/// The physical memory holding an initrd loaded by the bootloader, from the
+/// `linux,initrd-start` and `linux,initrd-end` properties of `/chosen`. Each is
+/// either one or two cells, regardless of `#address-cells`.
+pub fn initrd(fdt: &Fdt<'_>) -> Option<Range<usize>> {
+    let chosen = fdt.find_node("/chosen")?;
+    let address = |name| {
+        let value = chosen.properties().find(|p| p.name == name)?.value;
+        match value.len() {
+            4 | 8 => usize::try_from(read_cells(value)).ok(),
+            _ => None,
+        }
+    };
+
+    let (start, end) = (address("linux,initrd-start")?, address("linux,initrd-end")?);
+    (start <= end).then_some(start..end)
+}
+
+/// Entropy provided by the bootloader in the `rng-seed` property of `/chosen`,
+/// for seeding random number generation before any devices are up
+pub fn rng_seed<'a>(fdt: &Fdt<'a>) -> Option<&'a [u8]> {
+    fdt.find_node("/chosen")?.properties().find(|p| p.name == "rng-seed").map(|seed| seed.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn entries(bytes: &[u8], address_cells: usize, size_cells: usize) -> Vec<RegEntry> {
+        Reg::new(bytes, address_cells, size_cells).collect()
+    }
+
+    #[test]
+    fn one_cell_reg() {
+        let bytes = [0, 0, 0, 1, 0, 0, 0, 3];
+        assert_eq!(entries(&bytes, 1, 0), [RegEntry { address: 1, size: 0 }, RegEntry { address: 3, size: 0 }]);
+        assert_eq!(entries(&bytes, 1, 1), [RegEntry { address: 1, size: 3 }]);
+    }
+
+    #[test]
+    fn two_cell_reg() {
+        let bytes = [0, 0, 0, 1, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0x10, 0, 0];
+        assert_eq!(entries(&bytes, 2, 2), [RegEntry { address: 0x1_8000_0000, size: 0x10_0000 }]);
+    }
+
+    #[test]
+    fn three_cell_reg() {
+        let bytes = [0x02, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0, 0];
+        assert_eq!(entries(&bytes, 3, 2), [RegEntry { address: 0x0200_0000_0000_0000_4000_0000, size: 0x1_0000 }]);
+
+        // Trailing bytes which don't make up a whole entry are ignored
+        assert_eq!(entries(&bytes[..12], 3, 0), [RegEntry { address: 0x0200_0000_0000_0000_4000_0000, size: 0 }]);
+        assert_eq!(entries(&bytes[..8], 3, 0), []);
+    }
+
+    /// Builds a flattened devicetree blob, just enough of one for `fdt` to
+    /// parse the nodes and properties the tests need
+    #[derive(Default)]
+    struct DtbBuilder {
+        structure: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl DtbBuilder {
+        fn word(&mut self, word: u32) -> &mut Self {
+            self.structure.extend_from_slice(&word.to_be_bytes());
+            self
+        }
+
+        fn padded(&mut self, bytes: &[u8]) -> &mut Self {
+            self.structure.extend_from_slice(bytes);
+            while self.structure.len() % 4 != 0 {
+                self.structure.push(0);
+            }
+            self
+        }
+
+        fn begin_node(&mut self, name: &str) -> &mut Self {
+            self.word(1).padded(&[name.as_bytes(), &[0]].concat())
+        }
+
+        fn end_node(&mut self) -> &mut Self {
+            self.word(2)
+        }
+
+        fn prop(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+
+            let value: Vec<u8> = cells.iter().flat_map(|cell| cell.to_be_bytes()).collect();
+            self.word(3).word(value.len() as u32).word(name_offset).padded(&value)
+        }
+
+        fn finish(&mut self) -> Vec<u8> {
+            self.word(9);
+
+            // Header, then an empty memory reservation block, then the
+            // structure and strings blocks
+            let struct_offset = 40 + 16;
+            let strings_offset = struct_offset + self.structure.len();
+            let total_size = strings_offset + self.strings.len();
+            let header = [
+                0xD00D_FEED,
+                total_size,
+                struct_offset,
+                strings_offset,
+                40,
+                17,
+                16,
+                0,
+                self.strings.len(),
+                self.structure.len(),
+            ];
+
+            let mut blob: Vec<u8> = header.iter().flat_map(|field| (*field as u32).to_be_bytes()).collect();
+            blob.extend_from_slice(&[0; 16]);
+            blob.extend_from_slice(&self.structure);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    #[test]
+    fn reserved_memory_children() {
+        let blob = DtbBuilder::default()
+            .begin_node("")
+            .prop("#address-cells", &[2])
+            .prop("#size-cells", &[2])
+            .begin_node("reserved-memory")
+            .prop("#address-cells", &[2])
+            .prop("#size-cells", &[1])
+            .prop("ranges", &[])
+            .begin_node("mmode_resv0@80000000")
+            .prop("reg", &[0, 0x8000_0000, 0x4_0000])
+            .prop("no-map", &[])
+            .end_node()
+            .begin_node("dma-pool@90000000")
+            .prop("reg", &[0, 0x9000_0000, 0x10_0000, 0x1, 0x0, 0x1000])
+            .prop("reusable", &[])
+            .end_node()
+            .begin_node("framebuffer")
+            .prop("size", &[0, 0x80_0000])
+            .end_node()
+            .end_node()
+            .end_node()
+            .finish();
+
+        let fdt = Fdt::new(&blob).unwrap();
+        let regions: Vec<_> = reserved_memory(&fdt).collect();
+
+        let firmware = ReservedRegion {
+            name: "mmode_resv0@80000000",
+            address: 0x8000_0000,
+            size: 0x4_0000,
+            no_map: true,
+            reusable: false,
+        };
+        let dma_pool = ReservedRegion {
+            name: "dma-pool@90000000",
+            address: 0x9000_0000,
+            size: 0x10_0000,
+            no_map: false,
+            reusable: true,
+        };
+
+        // The dynamically placed framebuffer has no address yet, so it's skipped
+        assert_eq!(regions, [firmware, dma_pool, ReservedRegion { address: 0x1_0000_0000, size: 0x1000, ..dma_pool }]);
+        assert!(firmware.is_exclusive());
+        assert!(!dma_pool.is_exclusive());
+    }
+
+    #[test]
+    fn chosen_initrd_and_rng_seed() {
+        let blob = DtbBuilder::default()
+            .begin_node("")
+            .begin_node("chosen")
+            .prop("linux,initrd-start", &[0x8400_0000])
+            .prop("linux,initrd-end", &[0x8420_0000])
+            .prop("rng-seed", &[0xDEAD_BEEF, 0x0102_0304])
+            .end_node()
+            .end_node()
+            .finish();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        assert_eq!(initrd(&fdt), Some(0x8400_0000..0x8420_0000));
+        assert_eq!(rng_seed(&fdt), Some(&[0xDE, 0xAD, 0xBE, 0xEF, 1, 2, 3, 4][..]));
+
+        // QEMU uses two cells for RV64
+        let blob = DtbBuilder::default()
+            .begin_node("")
+            .begin_node("chosen")
+            .prop("linux,initrd-start", &[0x1, 0x8000_0000])
+            .prop("linux,initrd-end", &[0x1, 0x8010_0000])
+            .end_node()
+            .end_node()
+            .finish();
+        assert_eq!(initrd(&Fdt::new(&blob).unwrap()), Some(0x1_8000_0000..0x1_8010_0000));
+    }
+
+    #[test]
+    fn chosen_without_initrd() {
+        let blob = DtbBuilder::default().begin_node("").begin_node("chosen").end_node().end_node().finish();
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(initrd(&fdt), None);
+        assert_eq!(rng_seed(&fdt), None);
+
+        // Half a range, a range ending before it starts, or a value which isn't
+        // one or two cells is no range at all
+        for (start, end) in [(&[0x8400_0000][..], &[][..]), (&[0x8420_0000], &[0x8400_0000]), (&[0, 0, 1], &[0, 0, 2])]
+        {
+            let mut builder = DtbBuilder::default();
+            builder.begin_node("").begin_node("chosen").prop("linux,initrd-start", start);
+            if !end.is_empty() {
+                builder.prop("linux,initrd-end", end);
+            }
+
+            let blob = builder.end_node().end_node().finish();
+            assert_eq!(initrd(&Fdt::new(&blob).unwrap()), None);
+        }
+
+        // No `/chosen` at all
+        let blob = DtbBuilder::default().begin_node("").end_node().finish();
+        assert_eq!(initrd(&Fdt::new(&blob).unwrap()), None);
+    }
+
+    #[test]
+    fn no_reserved_memory() {
+        let blob = DtbBuilder::default().begin_node("").end_node().finish();
+        assert_eq!(reserved_memory(&Fdt::new(&blob).unwrap()).count(), 0);
+    }
+}
@@ -7,6 +7,12 @@
 
 use crate::sync::AtomicConstPtr;
 
+pub mod base;
+pub mod cppc;
+pub mod debug_console;
+pub mod devicetree;
+pub mod rfence;
+
 pub static FDT: AtomicConstPtr<u8> = AtomicConstPtr::new(core::ptr::null());
 
 #[cfg(feature = "platform.virt")]
@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2021 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use sbi::{HartMask, SbiError};
+
+/// Compute the `(hart_mask, hart_mask_base)` pairs required to select every
+/// hart ID in `harts`. Hart IDs which are more than `usize::BITS` apart from
+/// the current base are split into a new pair, so the number of pairs returned
+/// is the number of SBI calls required to reach every hart.
+pub fn hart_mask_pairs(harts: &[usize]) -> Vec<(usize, usize)> {
+    let mut harts = harts.to_vec();
+    harts.sort_unstable();
+    harts.dedup();
+
+    let mut pairs: Vec<(usize, usize)> = Vec::new();
+    for hart_id in harts {
+        match pairs.last_mut() {
+            Some((mask, base)) if hart_id - *base < usize::BITS as usize => *mask |= 1 << (hart_id - *base),
+            _ => pairs.push((1, hart_id)),
+        }
+    }
+
+    pairs
+}
+
+/// Execute a remote `sfence.vma` for the given virtual address range on every
+/// hart in `harts`, splitting the request into multiple SBI calls if the hart
+/// IDs can't be covered by a single hart mask
+pub fn remote_sfence_vma_for(harts: &[usize], range: Range<usize>) -> Result<(), SbiError> {
+    for (mask, base) in hart_mask_pairs(harts) {
+        let hart_mask = (0..usize::BITS as usize)
+            .filter(|bit| mask & (1 << bit) != 0)
+            .fold(HartMask::new(base), |hart_mask, bit| hart_mask.with(base + bit));
+
+        sbi::rfence::remote_sfence_vma(hart_mask, range.start, range.end - range.start)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_mask() {
+        assert_eq!(hart_mask_pairs(&[0, 3, 2]), alloc::vec![(0b1101, 0)]);
+        assert_eq!(hart_mask_pairs(&[5, 5, 6]), alloc::vec![(0b11, 5)]);
+        assert_eq!(hart_mask_pairs(&[]), alloc::vec![]);
+    }
+
+    #[test]
+    fn split_masks() {
+        assert_eq!(hart_mask_pairs(&[0, 3, 70]), alloc::vec![(0b1001, 0), (0b1, 70)]);
+        assert_eq!(hart_mask_pairs(&[64, 0, 63]), alloc::vec![(1 << 63 | 1, 0), (0b1, 64)]);
+    }
+}
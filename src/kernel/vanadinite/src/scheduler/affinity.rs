@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use librust::task::HartMask;
+
+/// Whether a task with the affinity `mask` can run on any of the `live` harts
+pub fn is_satisfiable(mask: HartMask, live: HartMask) -> bool {
+    mask.value() & live.value() != 0
+}
+
+/// The hart a task with the affinity `mask` needs to be moved to when it's
+/// scheduled out on `hart`, or `None` if it's allowed to stay where it is.
+/// Tasks are moved to the lowest numbered `live` hart they're allowed on.
+pub fn migration_target(mask: HartMask, hart: usize, live: HartMask) -> Option<usize> {
+    match mask.contains(hart) {
+        true => None,
+        false => mask.iter().find(|&hart| live.contains(hart)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scheduler::{Scheduler, SchedulerInner, SchedulerPolicy, TaskMetadata, TASKS},
+        sync::{Lazy, SpinMutex},
+        task::Task,
+    };
+    use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+    use core::sync::atomic::Ordering;
+
+    #[test]
+    fn pinned_task_stays_put() {
+        let scheduler = Scheduler {
+            inner: Lazy::new(|| (0..4).map(|_| SpinMutex::new(SchedulerInner::new())).collect()),
+            wait_queue: SpinMutex::new(BTreeMap::new()),
+        };
+
+        let mut idle_tids = Vec::new();
+        for hart in 0..2 {
+            let (tid, idle) = TASKS.insert(Task::idle());
+            let mut inner = scheduler.inner[hart].lock();
+            inner.run_queue.insert(tid, (Arc::clone(&idle), TaskMetadata::new()));
+            inner.policy.task_enqueued(idle, TaskMetadata::new());
+            inner.policy.idle_task(tid);
+            idle_tids.push(tid);
+        }
+
+        // The task starts out on hart 0, and is moved over the first time it's
+        // scheduled out
+        let (pinned, task) = TASKS.insert(Task::idle());
+        task.mutable_state.lock().affinity = HartMask::single(1);
+        let mut inner = scheduler.inner[0].lock();
+        inner.run_queue.insert(pinned, (Arc::clone(&task), TaskMetadata::new()));
+        inner.policy.task_enqueued(Arc::clone(&task), TaskMetadata::new());
+        assert_eq!(inner.policy.next(), pinned);
+
+        let live = HartMask::new(0xF);
+        let (inner, next) = scheduler.pick_next_on(inner, 0, pinned, live);
+        assert_eq!(next, idle_tids[0]);
+        assert!(!inner.run_queue.contains_key(&pinned));
+        assert_eq!(task.hart.load(Ordering::Acquire), 1);
+        drop(inner);
+
+        // From then on hart 1 keeps picking it, and it isn't moved again
+        let mut current = idle_tids[1];
+        for _ in 0..4 {
+            let (inner, next) = scheduler.pick_next_on(scheduler.inner[1].lock(), 1, current, live);
+            assert_eq!(next, pinned);
+            assert!(inner.run_queue.contains_key(&pinned));
+            current = next;
+        }
+        assert_eq!(task.hart.load(Ordering::Acquire), 1);
+        assert!(!scheduler.inner[0].lock().run_queue.contains_key(&pinned));
+
+        // The tasks' address spaces share the kernel's mappings, so they're
+        // leaked rather than torn down
+        for tid in idle_tids.into_iter().chain([pinned]) {
+            core::mem::forget(TASKS.remove(tid));
+        }
+        core::mem::forget(task);
+        core::mem::forget(scheduler);
+    }
+
+    #[test]
+    fn migration_targets() {
+        let mask = HartMask::empty().with(2).with(5);
+        let live = HartMask::new(0xFF);
+        assert_eq!(migration_target(mask, 2, live), None);
+        assert_eq!(migration_target(mask, 5, live), None);
+        assert_eq!(migration_target(mask, 0, live), Some(2));
+        assert_eq!(migration_target(HartMask::ALL, 3, HartMask::new(0xF)), None);
+
+        // Harts which aren't running are never picked, even below the highest
+        // one that is
+        let live = HartMask::empty().with(0).with(1).with(6);
+        assert_eq!(migration_target(HartMask::empty().with(3).with(6), 0, live), Some(6));
+        assert_eq!(migration_target(HartMask::empty().with(1).with(6), 0, HartMask::new(0xF)), Some(1));
+        assert!(is_satisfiable(mask, HartMask::new(0b111)));
+        assert!(!is_satisfiable(mask, HartMask::new(0b11)));
+        assert!(!is_satisfiable(mask, HartMask::empty().with(0).with(4)));
+        assert!(!is_satisfiable(HartMask::empty(), HartMask::new(0xF)));
+    }
+}
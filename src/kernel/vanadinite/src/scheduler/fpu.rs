@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Lazy switching of the floating point registers
+//!
+//! Tasks start out with the FPU turned off, and their first floating point
+//! instruction traps so the FPU can be turned on for them. Only tasks which
+//! have done that have FPU state to swap, and their registers are only saved
+//! when switching away if they've actually been modified, which the hardware
+//! tracks by marking `sstatus.FS` dirty. The kernel itself never touches the
+//! floating point registers, so they don't need to be saved on trap entry.
+
+use crate::{
+    csr::sstatus::{self, FloatingPointStatus},
+    trap::FloatingPointRegisters,
+};
+
+/// Save the floating point registers of the task being switched away from into
+/// `registers`, if it's modified them since they were last loaded
+pub fn save_outgoing(registers: &mut Option<FloatingPointRegisters>) {
+    if let FloatingPointStatus::Dirty = sstatus::fs() {
+        // Safety: the FPU is on, otherwise its registers couldn't be dirty
+        unsafe { save(registers.get_or_insert_with(Default::default)) };
+    }
+}
+
+/// Load the floating point registers of the task being switched to, or turn
+/// the FPU off if the task hasn't used it yet
+pub fn load_incoming(registers: &Option<FloatingPointRegisters>) {
+    match registers {
+        Some(registers) => restore(registers),
+        None => sstatus::set_fs(FloatingPointStatus::Off),
+    }
+}
+
+/// Turn the FPU on for the current task after its first floating point
+/// instruction trapped, starting it off with zeroed registers so it doesn't
+/// see whatever the last task to use the FPU left behind
+pub fn enable(registers: &mut Option<FloatingPointRegisters>) {
+    restore(registers.get_or_insert_with(Default::default));
+}
+
+/// Save the floating point registers into `registers`, leaving the FPU clean
+///
+/// # Safety
+/// The FPU must be on
+unsafe fn save(registers: &mut FloatingPointRegisters) {
+    #[rustfmt::skip]
+    core::arch::asm!(
+        "
+            .attribute arch, \"rv64imafdc\"
+            fsd f0, 0({0})
+            fsd f1, 8({0})
+            fsd f2, 16({0})
+            fsd f3, 24({0})
+            fsd f4, 32({0})
+            fsd f5, 40({0})
+            fsd f6, 48({0})
+            fsd f7, 56({0})
+            fsd f8, 64({0})
+            fsd f9, 72({0})
+            fsd f10, 80({0})
+            fsd f11, 88({0})
+            fsd f12, 96({0})
+            fsd f13, 104({0})
+            fsd f14, 112({0})
+            fsd f15, 120({0})
+            fsd f16, 128({0})
+            fsd f17, 136({0})
+            fsd f18, 144({0})
+            fsd f19, 152({0})
+            fsd f20, 160({0})
+            fsd f21, 168({0})
+            fsd f22, 176({0})
+            fsd f23, 184({0})
+            fsd f24, 192({0})
+            fsd f25, 200({0})
+            fsd f26, 208({0})
+            fsd f27, 216({0})
+            fsd f28, 224({0})
+            fsd f29, 232({0})
+            fsd f30, 240({0})
+            fsd f31, 248({0})
+            frcsr {1}
+            sd {1}, 256({0})
+            .attribute arch, \"rv64imac\"
+        ",
+        in(reg) registers as *mut FloatingPointRegisters,
+        out(reg) _,
+    );
+
+    sstatus::set_fs(FloatingPointStatus::Clean);
+}
+
+/// Turn the FPU on and load the floating point registers from `registers`,
+/// leaving the FPU clean
+fn restore(registers: &FloatingPointRegisters) {
+    sstatus::set_fs(FloatingPointStatus::Clean);
+
+    // Safety: the FPU was just turned on, and `registers` is valid to read
+    unsafe {
+        #[rustfmt::skip]
+        core::arch::asm!(
+            "
+                .attribute arch, \"rv64imafdc\"
+                fld f0, 0({0})
+                fld f1, 8({0})
+                fld f2, 16({0})
+                fld f3, 24({0})
+                fld f4, 32({0})
+                fld f5, 40({0})
+                fld f6, 48({0})
+                fld f7, 56({0})
+                fld f8, 64({0})
+                fld f9, 72({0})
+                fld f10, 80({0})
+                fld f11, 88({0})
+                fld f12, 96({0})
+                fld f13, 104({0})
+                fld f14, 112({0})
+                fld f15, 120({0})
+                fld f16, 128({0})
+                fld f17, 136({0})
+                fld f18, 144({0})
+                fld f19, 152({0})
+                fld f20, 160({0})
+                fld f21, 168({0})
+                fld f22, 176({0})
+                fld f23, 184({0})
+                fld f24, 192({0})
+                fld f25, 200({0})
+                fld f26, 208({0})
+                fld f27, 216({0})
+                fld f28, 224({0})
+                fld f29, 232({0})
+                fld f30, 240({0})
+                fld f31, 248({0})
+                ld {1}, 256({0})
+                fscsr {1}
+                .attribute arch, \"rv64imac\"
+            ",
+            in(reg) registers as *const FloatingPointRegisters,
+            out(reg) _,
+        );
+    }
+
+    sstatus::set_fs(FloatingPointStatus::Clean);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registers(value: usize) -> FloatingPointRegisters {
+        let mut registers = FloatingPointRegisters::default();
+        registers.f1 = value;
+        registers.f31 = !value;
+        registers
+    }
+
+    fn current_registers() -> FloatingPointRegisters {
+        let mut current = Default::default();
+        sstatus::set_fs(FloatingPointStatus::Clean);
+        unsafe { save(&mut current) };
+        current
+    }
+
+    #[test]
+    fn integer_task_fpu_state_is_untouched() {
+        let mut integer_task = None;
+        let mut fp_task = Some(registers(0x4000_0000));
+
+        // Switching from a task that never used the FPU doesn't save anything
+        // for it, and turns the FPU on for the task that did
+        sstatus::set_fs(FloatingPointStatus::Off);
+        save_outgoing(&mut integer_task);
+        load_incoming(&fp_task);
+        assert!(integer_task.is_none());
+        assert!(matches!(sstatus::fs(), FloatingPointStatus::Clean));
+        assert_eq!(current_registers().f1, 0x4000_0000);
+
+        // And switching back turns it off again
+        save_outgoing(&mut fp_task);
+        load_incoming(&integer_task);
+        assert!(integer_task.is_none());
+        assert!(matches!(sstatus::fs(), FloatingPointStatus::Off));
+    }
+
+    #[test]
+    fn fp_task_registers_are_saved_when_dirty() {
+        let mut fp_task = None;
+        enable(&mut fp_task);
+        assert_eq!(current_registers().f1, 0);
+
+        // The task modifies its registers, which marks them dirty
+        restore(&registers(0x1234));
+        sstatus::set_fs(FloatingPointStatus::Dirty);
+        let mut other = Some(registers(0x5678));
+        save_outgoing(&mut fp_task);
+        load_incoming(&other);
+        assert_eq!(fp_task.map(|r| (r.f1, r.f31)), Some((0x1234, !0x1234)));
+
+        // Clean registers aren't saved again, so the other task's copy keeps
+        // its old value even though the hardware registers differ
+        restore(&registers(0x9999));
+        save_outgoing(&mut other);
+        assert_eq!(other.map(|r| r.f1), Some(0x5678));
+
+        load_incoming(&fp_task);
+        assert_eq!(current_registers().f31, !0x1234);
+        sstatus::set_fs(FloatingPointStatus::Off);
+    }
+}
@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::task::Task;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// The harts which are currently running their idle task
+pub static IDLE_HARTS: IdleHarts = IdleHarts::new();
+
+/// Tracks which harts have nothing to run and are parked in their idle task's
+/// `wfi` loop, so they can be sent an IPI when there's work for them instead of
+/// waiting for their next timer interrupt. Only the first 64 harts are tracked,
+/// any others always wait for their timer interrupt.
+pub struct IdleHarts(AtomicU64);
+
+impl IdleHarts {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn set_idle(&self, hart: usize, idle: bool) {
+        let Some(bit) = bit(hart) else { return };
+        match idle {
+            true => self.0.fetch_or(bit, Ordering::AcqRel),
+            false => self.0.fetch_and(!bit, Ordering::AcqRel),
+        };
+    }
+
+    /// Take `hart` out of the idle set, returning whether it was idle and needs
+    /// to be woken. `current` is never woken, since it's about to schedule
+    /// anyway, and stays idle until it does.
+    pub fn take(&self, hart: usize, current: usize) -> bool {
+        match bit(hart) {
+            Some(bit) if hart != current => self.0.fetch_and(!bit, Ordering::AcqRel) & bit != 0,
+            _ => false,
+        }
+    }
+}
+
+fn bit(hart: usize) -> Option<u64> {
+    1u64.checked_shl(u32::try_from(hart).ok()?)
+}
+
+/// Wake the hart whose run queue `task` is in if it's parked in its idle task,
+/// called whenever a task becomes ready to run. If the hart had already found
+/// something else to run, the task waits for its turn as usual.
+pub fn wake_hart_of(task: &Task) {
+    wake_hart(task.hart.load(Ordering::Acquire));
+}
+
+/// Wake `hart` if it's parked in its idle task so that it reschedules
+pub fn wake_hart(hart: usize) {
+    if !IDLE_HARTS.take(hart, crate::HART_ID.get()) {
+        return;
+    }
+
+    if let Err(e) = sbi::ipi::send_ipi(sbi::HartMask::new(0).with(hart)) {
+        // It'll still wake up on its next timer interrupt
+        log::warn!("Failed to wake idle hart {}: {:?}", hart, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_harts_are_woken_once() {
+        let harts = IdleHarts::new();
+        assert!(!harts.take(1, 0));
+
+        // Harts 1 and 3 run out of work and park themselves
+        harts.set_idle(1, true);
+        harts.set_idle(3, true);
+
+        // A task on hart 1 becoming ready only wakes hart 1, and it isn't
+        // signalled again until it parks again
+        assert!(harts.take(1, 0));
+        assert!(!harts.take(1, 0));
+        assert!(!harts.take(1, 2));
+
+        // Hart 3 is left asleep until it has something to run
+        assert!(harts.take(3, 2));
+        harts.set_idle(1, true);
+        harts.set_idle(1, false);
+        assert!(!harts.take(1, 0));
+    }
+
+    #[test]
+    fn current_hart_is_never_woken() {
+        let harts = IdleHarts::new();
+        harts.set_idle(2, true);
+
+        // Hart 2 is the one making the task ready, so it's about to schedule
+        // anyway, and stays marked idle until it does
+        assert!(!harts.take(2, 2));
+        assert!(harts.take(2, 0));
+    }
+
+    #[test]
+    fn untracked_harts() {
+        let harts = IdleHarts::new();
+        harts.set_idle(63, true);
+
+        // Harts past the 64th can't be tracked, so they're never woken
+        harts.set_idle(64, true);
+        harts.set_idle(usize::MAX, true);
+        assert!(!harts.take(64, 0));
+        assert!(!harts.take(usize::MAX, 0));
+        assert!(harts.take(63, 64));
+    }
+}
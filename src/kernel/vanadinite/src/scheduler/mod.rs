@@ -5,12 +5,18 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+pub mod affinity;
+pub mod fpu;
+pub mod idle;
+pub mod priority;
 pub mod round_robin;
+pub mod timers;
 pub mod waitqueue;
+pub mod watchdog;
 
 use crate::csr::satp::Satp;
 use crate::mem::paging::SATP_MODE;
-use crate::sync::{Lazy, SpinMutex, SpinRwLock};
+use crate::sync::{mutex::SpinMutexGuard, Lazy, SpinMutex, SpinRwLock};
 use crate::task::{Sscratch, TaskState, HART_SSCRATCH};
 use crate::N_CPUS;
 use crate::{
@@ -26,7 +32,7 @@ use core::{
     num::NonZeroUsize,
     sync::atomic::{AtomicUsize, Ordering},
 };
-use librust::task::Tid;
+use librust::task::{HartMask, Tid};
 
 use self::round_robin::RoundRobinPolicy;
 
@@ -63,6 +69,14 @@ impl CurrentTask {
         unsafe { (*self.inner.get()).tid }
     }
 
+    /// The current task, or `None` if the hart hasn't started scheduling yet
+    pub fn try_get(&self) -> Option<Arc<Task>> {
+        match self.inner.get().is_null() {
+            true => None,
+            false => Some(self.get()),
+        }
+    }
+
     pub fn get(&self) -> Arc<Task> {
         assert!(!self.inner.get().is_null(), "`CurrentTask::get` called while still empty");
 
@@ -106,6 +120,7 @@ impl Scheduler {
 
     pub fn enqueue(&self, task: Task) {
         let (tid, task) = TASKS.insert(task);
+        task.hart.store(crate::HART_ID.get(), Ordering::Release);
         let mut inner = self.queue_for_hart().lock();
         inner.run_queue.insert(tid, (Arc::clone(&task), TaskMetadata::new()));
         inner.policy.task_enqueued(task, TaskMetadata::new());
@@ -113,6 +128,7 @@ impl Scheduler {
 
     pub fn enqueue_with(&self, f: impl FnOnce(Tid) -> Task) {
         let (tid, task) = TASKS.insert_with(f);
+        task.hart.store(crate::HART_ID.get(), Ordering::Release);
         let mut inner = self.queue_for_hart().lock();
         inner.run_queue.insert(tid, (Arc::clone(&task), TaskMetadata::new()));
         inner.policy.task_enqueued(task, TaskMetadata::new());
@@ -142,32 +158,40 @@ impl Scheduler {
     #[inline(never)]
     pub fn schedule(&self) {
         log::trace!("Scheduling!");
+        // Dropping the tasks can wake others, so it's done without holding the
+        // run queue lock
+        drop(self.queue_for_hart().lock().reap_exited());
+
         let mut inner = self.queue_for_hart().lock();
-        let SchedulerInner { policy, run_queue, .. } = &mut *inner;
         let current_tid = CURRENT_TASK.tid();
 
-        let (task, metadata) = run_queue.get_mut(&current_tid).expect("TID not in runqueue");
+        let (task, metadata) = inner.run_queue.get_mut(&current_tid).expect("TID not in runqueue");
         let (switch_out, out_lock) = unsafe { task.context.raw_locked_parts() };
 
         log::trace!("[OUT] Task {} [{}] metadata: {:?}", task.name, task.tid, metadata);
-        metadata.run_time += csr::time::read() - metadata.last_scheduled_at;
 
-        let tid = policy.next();
+        let live = crate::boot::harts::BOOTED_HARTS.live();
+        let (mut inner, tid) = self.pick_next_on(inner, crate::HART_ID.get(), current_tid, live);
+        let SchedulerInner { policy, run_queue, .. } = &mut *inner;
         let (to_task, metadata) = run_queue.get_mut(&tid).expect("TID not in runqueue");
+        watchdog::WATCHDOG.scheduled(tid);
+        idle::IDLE_HARTS.set_idle(crate::HART_ID.get(), policy.is_idle(tid));
 
         log::trace!("[IN] Task {} [{}] metadata: {:?}", to_task.name, to_task.tid, metadata);
 
         if tid != current_tid {
-            metadata.last_scheduled_at = csr::time::read();
-
             // Safety: `context_switch` unlocks the mutex
             let (switch_in, in_lock) = unsafe { to_task.context.raw_locked_parts() };
-            let satp = Satp {
-                root_page_table: to_task.mutable_state.lock().memory_manager.table_phys_address(),
-                asid: tid.value() as u16,
-                mode: SATP_MODE,
-            }
-            .as_usize();
+            let satp = {
+                let mut to_state = to_task.mutable_state.lock();
+                to_state.memory_manager.mark_resident(crate::HART_ID.get());
+                Satp {
+                    root_page_table: to_state.memory_manager.table_phys_address(),
+                    asid: tid.value() as u16,
+                    mode: SATP_MODE,
+                }
+                .as_usize()
+            };
 
             HART_SSCRATCH.set(Sscratch {
                 kernel_global_ptr: crate::asm::gp(),
@@ -176,7 +200,15 @@ impl Scheduler {
                 scratch_sp: 0,
             });
 
-            drop(CURRENT_TASK.replace(Arc::clone(to_task)));
+            // The outgoing task's context stays locked until the switch, so
+            // it can't be switched in elsewhere before its time is counted
+            let now = csr::time::read();
+            let from_task = CURRENT_TASK.replace(Arc::clone(to_task));
+            from_task.cpu_time.switched_out(now);
+            to_task.cpu_time.switched_in(now);
+            fpu::save_outgoing(&mut from_task.mutable_state.lock().fpu_registers);
+            fpu::load_incoming(&to_task.mutable_state.lock().fpu_registers);
+            drop(from_task);
             drop(inner);
 
             // Safety: `context_switch` unlocks the mutex
@@ -187,6 +219,16 @@ impl Scheduler {
         }
     }
 
+    /// Give up the remainder of the current task's time slice, placing it at
+    /// the back of the run queue. If no other task is ready to run, this
+    /// returns immediately without rescheduling.
+    pub fn yield_now(&self) {
+        let other_ready = self.queue_for_hart().lock().policy.other_task_ready(CURRENT_TASK.tid());
+        if other_ready {
+            self.schedule();
+        }
+    }
+
     /// Begin scheduling on this hart. Requires hart locals to be set up. Automatically spawns an idle task.
     ///
     /// # Safety
@@ -203,7 +245,9 @@ impl Scheduler {
         inner.policy.idle_task(idle_tid);
 
         let next = inner.policy.next();
+        idle::IDLE_HARTS.set_idle(crate::HART_ID.get(), inner.policy.is_idle(next));
         let (to_task, _) = inner.run_queue.get_mut(&next).unwrap();
+        watchdog::WATCHDOG.scheduled(next);
 
         let to_task = Arc::clone(to_task);
         CURRENT_TASK.set(Arc::clone(&to_task));
@@ -212,12 +256,16 @@ impl Scheduler {
 
         // Safety: we promise to be nice
         let (switch_in, in_lock) = unsafe { to_task.context.raw_locked_parts() };
-        let satp = Satp {
-            root_page_table: to_task.mutable_state.lock().memory_manager.table_phys_address(),
-            asid: to_task.tid.value() as u16,
-            mode: SATP_MODE,
-        }
-        .as_usize();
+        let satp = {
+            let mut to_state = to_task.mutable_state.lock();
+            to_state.memory_manager.mark_resident(crate::HART_ID.get());
+            Satp {
+                root_page_table: to_state.memory_manager.table_phys_address(),
+                asid: to_task.tid.value() as u16,
+                mode: SATP_MODE,
+            }
+            .as_usize()
+        };
 
         HART_SSCRATCH.set(Sscratch {
             kernel_global_ptr: crate::asm::gp(),
@@ -227,6 +275,8 @@ impl Scheduler {
         });
 
         crate::csr::sscratch::write(core::ptr::addr_of!(HART_SSCRATCH) as usize);
+        fpu::load_incoming(&to_task.mutable_state.lock().fpu_registers);
+        to_task.cpu_time.switched_in(csr::time::read());
 
         log::debug!("Scheduling first process: {}", to_task.name);
 
@@ -238,19 +288,90 @@ impl Scheduler {
         unreachable!()
     }
 
+    /// Pick the task for `hart` to switch to from `current`, whose run queue
+    /// is `inner`. If `current` isn't allowed on `hart` anymore, it's first
+    /// handed over to one of the `live` harts it is allowed on, unlocking
+    /// `inner` in the meantime.
+    fn pick_next_on<'a>(
+        &'a self,
+        mut inner: RunQueueGuard<'a>,
+        hart: usize,
+        current: Tid,
+        live: HartMask,
+    ) -> (RunQueueGuard<'a>, Tid) {
+        let (exited, migration_target) = {
+            let task_state = inner.run_queue[&current].0.mutable_state.lock();
+            match task_state.state.is_dead() {
+                true => (true, None),
+                false => (false, affinity::migration_target(task_state.affinity, hart, live)),
+            }
+        };
+
+        // The task's context stays locked until it's been switched out, so the
+        // other hart can't pick it up before then
+        if let Some(target) = migration_target {
+            let (task, metadata) = inner.run_queue.remove(&current).unwrap();
+            inner.policy.task_dequeued(current);
+            drop(inner);
+
+            log::debug!("Moving task {} [{}] to hart {}", task.name, task.tid, target);
+            task.hart.store(target, Ordering::Release);
+            let mut queue = self.inner[target].lock();
+            queue.run_queue.insert(current, (Arc::clone(&task), metadata));
+            queue.policy.task_enqueued(task, metadata);
+            drop(queue);
+            idle::wake_hart(target);
+
+            inner = self.inner[hart].lock();
+        }
+
+        let tid = inner.pick_next(current, exited);
+        (inner, tid)
+    }
+
     fn queue_for_hart(&self) -> &SpinMutex<SchedulerInner, SameHartDeadlockDetection> {
         &self.inner[crate::HART_ID.get()]
     }
 }
 
+type RunQueueGuard<'a> = SpinMutexGuard<'a, SchedulerInner, SameHartDeadlockDetection>;
+
 struct SchedulerInner {
     policy: round_robin::RoundRobinPolicy,
     run_queue: BTreeMap<Tid, (Arc<Task>, TaskMetadata)>,
+    /// Tasks which exited and were switched away from for the last time the
+    /// last time this hart scheduled
+    exited_tasks: Vec<Tid>,
 }
 
 impl SchedulerInner {
     fn new() -> Self {
-        Self { policy: RoundRobinPolicy::new(), run_queue: BTreeMap::new() }
+        Self { policy: RoundRobinPolicy::new(), run_queue: BTreeMap::new(), exited_tasks: Vec::new() }
+    }
+
+    /// Pick the task to switch to from `current`, which is reaped the next
+    /// time this hart schedules if it `exited`
+    fn pick_next(&mut self, current: Tid, exited: bool) -> Tid {
+        if exited {
+            self.exited_tasks.push(current);
+        }
+
+        self.policy.next()
+    }
+
+    /// Remove the tasks that exited from the scheduler. Their kernel stacks
+    /// aren't in use anymore, and are freed once the returned tasks are
+    /// dropped.
+    fn reap_exited(&mut self) -> Vec<Arc<Task>> {
+        let exited = core::mem::take(&mut self.exited_tasks);
+        exited
+            .into_iter()
+            .filter_map(|tid| {
+                self.policy.task_dequeued(tid);
+                TASKS.remove(tid);
+                self.run_queue.remove(&tid).map(|(task, _)| task)
+            })
+            .collect()
     }
 }
 
@@ -296,6 +417,13 @@ impl TaskList {
     pub fn get(&self, tid: Tid) -> Option<Arc<Task>> {
         self.map.read().get(&tid).cloned()
     }
+
+    /// Like [`TaskList::get`], but gives up instead of spinning if the list is
+    /// being modified, for places like the watchdog which can't risk waiting
+    /// on a stalled hart
+    pub fn try_get(&self, tid: Tid) -> Option<Arc<Task>> {
+        self.map.try_read()?.get(&tid).cloned()
+    }
 }
 
 pub trait SchedulerPolicy {
@@ -304,21 +432,123 @@ pub trait SchedulerPolicy {
     fn task_dequeued(&mut self, tid: Tid);
     fn task_priority_changed(&mut self, tid: Tid, priority: u16);
     fn task_preempted(&mut self, tid: Tid);
+    /// Whether a task other than `tid` and the idle task is ready to run
+    fn other_task_ready(&self, tid: Tid) -> bool;
 
     fn idle_task(&mut self, tid: Tid);
+    /// Whether `tid` is the idle task, which is only picked by
+    /// [`SchedulerPolicy::next`] when no other task is ready to run
+    fn is_idle(&self, tid: Tid) -> bool;
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct TaskMetadata {
     pub priority: u16,
-    pub run_time: u64,
-    pub last_scheduled_at: u64,
     pub run_state: TaskState,
 }
 
 impl TaskMetadata {
     pub fn new() -> Self {
-        Self { priority: 1, run_time: 0, last_scheduled_at: 0, run_state: TaskState::Ready }
+        Self { priority: 1, run_state: TaskState::Ready }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yielding_tasks_take_turns() {
+        let scheduler = Scheduler::new();
+        let mut tids = Vec::new();
+        for _ in 0..3 {
+            scheduler.enqueue_with(|tid| {
+                tids.push(tid);
+                Task::idle()
+            });
+        }
+        let (ping, pong, idle) = (tids[0], tids[1], tids[2]);
+
+        let mut inner = scheduler.queue_for_hart().lock();
+        inner.policy.idle_task(idle);
+
+        // Mirrors `Scheduler::yield_now`: the running task only gives up the
+        // hart when someone else is ready to take it
+        let mut current = inner.policy.next();
+        let mut ran = Vec::new();
+        for _ in 0..6 {
+            ran.push(current);
+            assert!(inner.policy.other_task_ready(current));
+            current = inner.pick_next(current, false);
+        }
+
+        let first = ran[0];
+        let second = if first == ping { pong } else { ping };
+        assert!(first == ping || first == pong);
+        assert_eq!(ran, [first, second, first, second, first, second]);
+
+        // Once `current` exits, only the other task and then the idle task
+        // are picked, and the exited one is reaped
+        let other = if current == ping { pong } else { ping };
+        inner.run_queue[&current].0.mutable_state.lock().state = TaskState::Dead;
+        assert_eq!(inner.pick_next(current, true), other);
+
+        let reaped = inner.reap_exited();
+        assert_eq!(reaped.len(), 1);
+        assert_eq!(reaped[0].tid, current);
+        assert!(TASKS.get(current).is_none());
+        assert!(!inner.run_queue.contains_key(&current));
+
+        assert!(!inner.policy.other_task_ready(other));
+        inner.run_queue[&other].0.mutable_state.lock().state = TaskState::Blocked;
+        assert_eq!(inner.pick_next(other, false), idle);
+
+        // The tasks' address spaces share the kernel's mappings, so they're
+        // leaked rather than torn down
+        drop(inner);
+        core::mem::forget(reaped);
+        for tid in [other, idle] {
+            core::mem::forget(TASKS.remove(tid));
+        }
+        core::mem::forget(scheduler);
+    }
+
+    #[test]
+    fn reaping_leaves_kernel_mappings_intact() {
+        use crate::mem::{
+            paging::{PageTable, VirtualAddress},
+            phys::{PhysicalMemoryAllocator, PHYSICAL_MEMORY_ALLOCATOR},
+        };
+
+        static KERNEL_DATA: usize = 0xC0FF_EE00;
+        let kernel_data = VirtualAddress::from_ptr(&KERNEL_DATA);
+        let phys = PageTable::with_active(|table| table.resolve(kernel_data));
+        let used = PHYSICAL_MEMORY_ALLOCATOR.lock().used_pages();
+
+        let scheduler = Scheduler::new();
+        let mut tid = None;
+        scheduler.enqueue_with(|t| {
+            tid = Some(t);
+            Task::idle()
+        });
+        let tid = tid.unwrap();
+
+        let mut inner = scheduler.queue_for_hart().lock();
+        inner.run_queue[&tid].0.mutable_state.lock().state = TaskState::Dead;
+        inner.exited_tasks.push(tid);
+        let reaped = inner.reap_exited();
+        drop(inner);
+
+        assert_eq!(reaped.len(), 1);
+        drop(reaped);
+
+        // Tearing down the task's address space only frees what it owned, so
+        // the kernel half it shared is untouched
+        assert!(PHYSICAL_MEMORY_ALLOCATOR.lock().used_pages() >= used);
+        assert_eq!(PageTable::with_active(|table| table.resolve(kernel_data)), phys);
+        assert_eq!(unsafe { core::ptr::read_volatile(&KERNEL_DATA) }, 0xC0FF_EE00);
+
+        core::mem::forget(scheduler);
     }
 }
 
@@ -474,38 +704,6 @@ pub unsafe extern "C" fn return_to_usermode() -> ! {
         // Restore `sp`
         ld sp, 16(sp)
 
-        // FIXME: Restore floating point registers
-        // fld f1, 0(a1)
-        // fld f2, 8(a1)
-        // fld f3, 16(a1)
-        // fld f4, 24(a1)
-        // fld f5, 32(a1)
-        // fld f6, 40(a1)
-        // fld f7, 48(a1)
-        // fld f8, 56(a1)
-        // fld f9, 64(a1)
-        // fld f11, 80(a1)
-        // fld f12, 88(a1)
-        // fld f13, 96(a1)
-        // fld f14, 104(a1)
-        // fld f15, 112(a1)
-        // fld f16, 120(a1)
-        // fld f17, 128(a1)
-        // fld f18, 136(a1)
-        // fld f19, 144(a1)
-        // fld f20, 152(a1)
-        // fld f21, 160(a1)
-        // fld f22, 168(a1)
-        // fld f23, 176(a1)
-        // fld f24, 184(a1)
-        // fld f25, 192(a1)
-        // fld f26, 200(a1)
-        // fld f27, 208(a1)
-        // fld f28, 216(a1)
-        // fld f29, 224(a1)
-        // fld f30, 232(a1)
-        // fld f31, 240(a1)
-
         sret
     ", options(noreturn));
 }
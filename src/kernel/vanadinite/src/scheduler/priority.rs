@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{sync::SpinMutex, utils::SameHartDeadlockDetection};
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU16, Ordering};
+use librust::task::Tid;
+
+/// The priority tasks are given when they're created. Higher values are
+/// scheduled first.
+pub const DEFAULT_PRIORITY: u16 = 1;
+
+/// A task's scheduling priority, which is made up of the base priority the task
+/// was given and any priority lent to it by tasks blocked waiting on it over a
+/// channel. The scheduler only looks at the effective priority, the larger of
+/// the two, so a low priority server can't be starved by medium priority tasks
+/// while a high priority client is waiting for it to reply.
+#[derive(Debug)]
+pub struct Priority {
+    base: AtomicU16,
+    /// The priorities lent by each task waiting on this one
+    inherited: SpinMutex<BTreeMap<Tid, u16>, SameHartDeadlockDetection>,
+}
+
+impl Priority {
+    pub fn new(base: u16) -> Self {
+        Self { base: AtomicU16::new(base), inherited: SpinMutex::new(BTreeMap::new()) }
+    }
+
+    pub fn base(&self) -> u16 {
+        self.base.load(Ordering::Relaxed)
+    }
+
+    /// The priority the task is scheduled with, taking into account anything
+    /// lent to it by waiting tasks
+    pub fn effective(&self) -> u16 {
+        let inherited = self.inherited.lock().values().copied().max();
+        inherited.map_or(self.base(), |inherited| inherited.max(self.base()))
+    }
+
+    /// Lend `priority` to this task on behalf of `lender`, which is blocked
+    /// waiting on it, replacing anything `lender` had previously lent
+    pub fn inherit(&self, lender: Tid, priority: u16) {
+        self.inherited.lock().insert(lender, priority);
+    }
+
+    /// Return the priority lent by `lender` now that it's no longer waiting,
+    /// returning `false` if it hadn't lent any
+    pub fn revert(&self, lender: Tid) -> bool {
+        self.inherited.lock().remove(&lender).is_some()
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Self::new(DEFAULT_PRIORITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::round_robin::select;
+    use alloc::{collections::VecDeque, vec::Vec};
+    use core::{cell::Cell, num::NonZeroUsize};
+
+    fn tid(tid: usize) -> Tid {
+        Tid::new(NonZeroUsize::new(tid).unwrap())
+    }
+
+    struct TestTask {
+        tid: Tid,
+        priority: Priority,
+        ready: Cell<bool>,
+    }
+
+    fn schedule(tasks: &mut VecDeque<TestTask>) -> Option<Tid> {
+        select(tasks, |task| task.ready.get().then(|| task.priority.effective())).map(|task| task.tid)
+    }
+
+    fn task<'a>(tasks: &'a VecDeque<TestTask>, id: Tid) -> &'a TestTask {
+        tasks.iter().find(|task| task.tid == id).unwrap()
+    }
+
+    #[test]
+    fn effective_priority() {
+        let priority = Priority::new(2);
+        assert_eq!(priority.effective(), 2);
+
+        // Lower priorities don't lower the effective priority
+        priority.inherit(tid(1), 1);
+        priority.inherit(tid(2), 5);
+        priority.inherit(tid(3), 4);
+        assert_eq!(priority.effective(), 5);
+        assert_eq!(priority.base(), 2);
+
+        assert!(priority.revert(tid(2)));
+        assert!(!priority.revert(tid(2)));
+        assert_eq!(priority.effective(), 4);
+
+        assert!(priority.revert(tid(3)));
+        assert!(priority.revert(tid(1)));
+        assert_eq!(priority.effective(), 2);
+    }
+
+    #[test]
+    fn inversion_doesnt_starve_waiter() {
+        let (high, medium, low) = (tid(1), tid(2), tid(3));
+        let mut tasks: VecDeque<_> = [(high, 3), (medium, 2), (low, 1)]
+            .into_iter()
+            .map(|(tid, priority)| TestTask { tid, priority: Priority::new(priority), ready: Cell::new(true) })
+            .collect();
+
+        // The high priority task makes a call to the low priority server and
+        // blocks waiting for the reply, while the medium priority task spins
+        task(&tasks, high).ready.set(false);
+
+        // Without inheritance the server never runs, so neither does the task
+        // waiting on it
+        assert!((0..10).map(|_| schedule(&mut tasks)).all(|tid| tid == Some(medium)));
+
+        // Lending the waiter's priority lets the server run ahead of the
+        // medium priority task until it replies
+        let lent = task(&tasks, high).priority.effective();
+        task(&tasks, low).priority.inherit(high, lent);
+        assert!((0..10).map(|_| schedule(&mut tasks)).all(|tid| tid == Some(low)));
+
+        // Replying wakes the waiter and hands the server back its own priority
+        task(&tasks, high).ready.set(true);
+        assert!(task(&tasks, low).priority.revert(high));
+        assert_eq!(schedule(&mut tasks), Some(high));
+
+        task(&tasks, high).ready.set(false);
+        let order = (0..4).map(|_| schedule(&mut tasks)).collect::<Vec<_>>();
+        assert_eq!(order, [Some(medium); 4]);
+    }
+
+    #[test]
+    fn equal_priorities_round_robin() {
+        let mut tasks: VecDeque<_> =
+            (1..=3).map(|n| TestTask { tid: tid(n), priority: Priority::default(), ready: Cell::new(true) }).collect();
+        task(&tasks, tid(2)).ready.set(false);
+
+        let order = (0..4).map(|_| schedule(&mut tasks)).collect::<Vec<_>>();
+        assert_eq!(order, [Some(tid(3)), Some(tid(1)), Some(tid(3)), Some(tid(1))]);
+
+        task(&tasks, tid(1)).ready.set(false);
+        task(&tasks, tid(3)).ready.set(false);
+        assert_eq!(schedule(&mut tasks), None);
+    }
+}
@@ -25,22 +25,12 @@ impl RoundRobinPolicy {
 
 impl SchedulerPolicy for RoundRobinPolicy {
     fn next(&mut self) -> Tid {
-        match self.tasks.is_empty() {
-            true => self.idle_tid,
-            false => {
-                for _ in 0..self.tasks.len() {
-                    self.tasks.rotate_left(1);
-
-                    if !matches!(self.tasks.front().unwrap().mutable_state.lock().state, TaskState::Ready) {
-                        continue;
-                    }
-
-                    return self.tasks.front().unwrap().tid;
-                }
-
-                self.idle_tid
-            }
-        }
+        let idle_tid = self.idle_tid;
+        select(&mut self.tasks, |task| {
+            let ready = task.tid != idle_tid && matches!(task.mutable_state.lock().state, TaskState::Ready);
+            ready.then(|| task.priority.effective())
+        })
+        .map_or(idle_tid, |task| task.tid)
     }
 
     fn task_enqueued(&mut self, tid: Arc<Task>, _metadata: super::TaskMetadata) {
@@ -57,7 +47,35 @@ impl SchedulerPolicy for RoundRobinPolicy {
     fn task_priority_changed(&mut self, _: Tid, _: u16) {}
     fn task_preempted(&mut self, _: Tid) {}
 
+    fn other_task_ready(&self, tid: Tid) -> bool {
+        self.tasks.iter().any(|task| {
+            task.tid != tid && task.tid != self.idle_tid && matches!(task.mutable_state.lock().state, TaskState::Ready)
+        })
+    }
+
     fn idle_task(&mut self, tid: Tid) {
         self.idle_tid = tid;
     }
+
+    fn is_idle(&self, tid: Tid) -> bool {
+        tid == self.idle_tid
+    }
+}
+
+/// Rotate `tasks` until the next task to run is at the front, returning it.
+/// `runnable` gives the effective priority of a ready task, and the ready tasks
+/// sharing the highest priority take turns in round robin order, so with equal
+/// priorities every ready task gets a turn.
+pub(super) fn select<T>(tasks: &mut VecDeque<T>, mut runnable: impl FnMut(&T) -> Option<u16>) -> Option<&T> {
+    let highest = tasks.iter().filter_map(&mut runnable).max()?;
+
+    for _ in 0..tasks.len() {
+        tasks.rotate_left(1);
+
+        if runnable(tasks.front().unwrap()) == Some(highest) {
+            return tasks.front();
+        }
+    }
+
+    None
 }
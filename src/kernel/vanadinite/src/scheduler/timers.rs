@@ -0,0 +1,113 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::{idle, TASKS};
+use crate::{sync::SpinMutex, syscall::channel::ChannelMessage, task::TaskState, utils::SameHartDeadlockDetection};
+use alloc::{collections::BTreeMap, vec::Vec};
+use librust::{syscalls::channel::KernelMessage, task::Tid};
+
+pub static TIMERS: Timers = Timers::new();
+
+/// One-shot timers which notify a task over its kernel channel with
+/// [`KernelMessage::TimerExpired`] once their deadline passes. Each task has at
+/// most one timer, and deadlines are only checked from the timer interrupt, so
+/// they have the resolution of a scheduler tick.
+///
+/// Separately, blocked tasks can ask to be woken up at a deadline, e.g. to time
+/// out waiting on channels, without touching their timer.
+pub struct Timers {
+    deadlines: SpinMutex<BTreeMap<Tid, u64>, SameHartDeadlockDetection>,
+    wakeups: SpinMutex<BTreeMap<Tid, u64>, SameHartDeadlockDetection>,
+}
+
+impl Timers {
+    pub const fn new() -> Self {
+        Self { deadlines: SpinMutex::new(BTreeMap::new()), wakeups: SpinMutex::new(BTreeMap::new()) }
+    }
+
+    /// Set the deadline in timer ticks for `tid`, replacing any existing one,
+    /// or cancel it with a deadline of zero
+    pub fn set(&self, tid: Tid, deadline: u64) {
+        set(&mut self.deadlines.lock(), tid, deadline)
+    }
+
+    /// Make `tid` ready to run once `deadline` passes, replacing any existing
+    /// wakeup, or cancel it with a deadline of zero
+    pub fn wake_at(&self, tid: Tid, deadline: u64) {
+        set(&mut self.wakeups.lock(), tid, deadline)
+    }
+
+    /// Remove and return the tasks whose deadlines are at or before `now`
+    pub fn take_expired(&self, now: u64) -> Vec<Tid> {
+        take_expired(&mut self.deadlines.lock(), now)
+    }
+
+    /// Notify every task whose deadline is at or before `now`
+    pub fn fire_expired(&self, now: u64) {
+        for tid in self.take_expired(now) {
+            // The task may have exited since setting its timer
+            let Some(task) = TASKS.get(tid) else { continue };
+            let sender = task.mutable_state.lock().kernel_channel.sender.clone();
+
+            let _ = sender.send(ChannelMessage {
+                data: KernelMessage::TimerExpired.into_parts(),
+                caps: Vec::new(),
+                segments: Vec::new(),
+            });
+        }
+
+        let woken = take_expired(&mut self.wakeups.lock(), now);
+        for tid in &woken {
+            let Some(task) = TASKS.get(*tid) else { continue };
+            task.mutable_state.lock().state = TaskState::Ready;
+            idle::wake_hart_of(&task);
+        }
+    }
+}
+
+fn set(deadlines: &mut BTreeMap<Tid, u64>, tid: Tid, deadline: u64) {
+    match deadline {
+        0 => drop(deadlines.remove(&tid)),
+        _ => drop(deadlines.insert(tid, deadline)),
+    }
+}
+
+fn take_expired(deadlines: &mut BTreeMap<Tid, u64>, now: u64) -> Vec<Tid> {
+    let expired = deadlines.iter().filter(|(_, deadline)| **deadline <= now).map(|(tid, _)| *tid).collect::<Vec<_>>();
+
+    for tid in &expired {
+        deadlines.remove(tid);
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::num::NonZeroUsize;
+
+    fn tid(n: usize) -> Tid {
+        Tid::new(NonZeroUsize::new(n).unwrap())
+    }
+
+    #[test]
+    fn expired_deadlines_are_taken_once() {
+        let timers = Timers::new();
+        timers.set(tid(1), 100);
+        timers.set(tid(2), 200);
+        timers.set(tid(3), 300);
+
+        // Resetting a timer replaces its deadline, and zero cancels it
+        timers.set(tid(2), 50);
+        timers.set(tid(3), 0);
+
+        assert!(timers.take_expired(10).is_empty());
+        assert_eq!(timers.take_expired(100), [tid(1), tid(2)]);
+        assert!(timers.take_expired(1000).is_empty());
+    }
+}
@@ -11,8 +11,9 @@ use crate::{
     utils::SameHartDeadlockDetection,
 };
 use alloc::{collections::VecDeque, sync::Arc};
+use librust::task::Tid;
 
-use super::{CURRENT_TASK, SCHEDULER};
+use super::{idle, CURRENT_TASK, SCHEDULER};
 
 #[derive(Debug)]
 pub struct WaitQueue {
@@ -37,20 +38,38 @@ impl WaitQueue {
         SCHEDULER.schedule();
     }
 
+    /// Add `task` to the queue without blocking it, for waiting on several
+    /// queues at once. The caller is responsible for blocking the task and for
+    /// taking it back out of the queues it wasn't woken by with
+    /// [`WaitQueue::remove`].
+    pub fn enqueue(&self, task: Arc<Task>) {
+        self.queue.lock().push_back(task);
+    }
+
+    /// Remove the task with the given [`Tid`], returning whether it was waiting
+    pub fn remove(&self, tid: Tid) -> bool {
+        let mut queue = self.queue.lock();
+        let len = queue.len();
+        queue.retain(|task| task.tid != tid);
+
+        queue.len() != len
+    }
+
     #[track_caller]
     pub fn wake_one(&self) {
         if let Some(task) = self.queue.lock().pop_front() {
             log::debug!("Waking task in waitqueue: [{:?}] {}", task.tid, task.name);
             task.mutable_state.lock().state = TaskState::Ready;
+            idle::wake_hart_of(&task);
         }
     }
 
     #[track_caller]
     pub fn wake_all(&self) {
-        let mut queue = self.queue.lock();
-        for task in queue.drain(..) {
+        for task in self.queue.lock().drain(..) {
             log::debug!("Waking task in waitqueue: [{:?}] {}", task.tid, task.name);
             task.mutable_state.lock().state = TaskState::Ready;
+            idle::wake_hart_of(&task);
         }
     }
 }
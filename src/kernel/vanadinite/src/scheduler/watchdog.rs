@@ -0,0 +1,198 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::TASKS;
+use crate::{csr, sync::Lazy, utils::ticks_per_us, HART_ID, N_CPUS, TIMER_FREQ};
+use alloc::{string::String, vec::Vec};
+use core::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+use librust::task::Tid;
+
+pub static WATCHDOG: Watchdog = Watchdog::new();
+
+/// The number of scheduler ticks a hart can go without making a scheduling
+/// decision before it's reported as stalled
+pub const DEFAULT_STALL_THRESHOLD: u64 = 100;
+
+/// The length of a scheduler tick in microseconds, which is the interval the
+/// timer interrupt is set for
+const TICK_US: u64 = 10_000;
+
+/// Detects harts which have stopped scheduling, e.g. because they're stuck
+/// spinning in the kernel with interrupts disabled. A stalled hart can't
+/// notice this itself, so each hart checks on the others from its timer
+/// interrupt, which means stalls can only be detected with more than one hart.
+/// Stalled harts are only reported, and are left running in case they recover.
+pub struct Watchdog {
+    harts: Lazy<Vec<Heartbeat>>,
+    /// The stall threshold in scheduler ticks, or zero if the watchdog is
+    /// disabled
+    threshold: AtomicU64,
+}
+
+impl Watchdog {
+    pub const fn new() -> Self {
+        Self {
+            harts: Lazy::new(|| (0..N_CPUS.load(Ordering::Relaxed)).map(|_| Heartbeat::new()).collect()),
+            threshold: AtomicU64::new(DEFAULT_STALL_THRESHOLD),
+        }
+    }
+
+    /// Set the number of scheduler ticks a hart can go without scheduling
+    /// before it's reported, with zero disabling the watchdog
+    pub fn set_threshold(&self, ticks: u64) {
+        self.threshold.store(ticks, Ordering::Relaxed);
+    }
+
+    /// Record the PC the current hart trapped into the kernel from, so it can
+    /// be reported if the hart stalls before it next schedules
+    pub fn record_trap(&self, pc: usize) {
+        if let Some(heartbeat) = self.harts.get(HART_ID.get()) {
+            heartbeat.pc.store(pc, Ordering::Relaxed);
+        }
+    }
+
+    /// Feed the watchdog for the current hart after it has decided to run the
+    /// task `tid`
+    pub fn scheduled(&self, tid: Tid) {
+        let hart_id = HART_ID.get();
+        if self.harts.get(hart_id).map_or(false, |heartbeat| heartbeat.feed(csr::time::read(), tid)) {
+            log::info!("Hart {} has resumed scheduling", hart_id);
+        }
+    }
+
+    /// Check whether any other harts have stalled, logging the ones which
+    /// haven't already been reported. This is called from the timer interrupt.
+    pub fn check(&self) {
+        let threshold = self.threshold.load(Ordering::Relaxed);
+        if threshold == 0 {
+            return;
+        }
+
+        let tick = ticks_per_us(TICK_US, TIMER_FREQ.load(Ordering::Relaxed)).max(1);
+        for stall in stalls(&self.harts, HART_ID.get(), csr::time::read(), threshold * tick) {
+            let name = NonZeroUsize::new(stall.tid)
+                .and_then(|tid| TASKS.try_get(Tid::new(tid)))
+                .map_or_else(|| String::from("<unknown>"), |task| task.name.get());
+            log::error!(
+                "Hart {} hasn't scheduled in {} ticks, it last trapped from task {} [{}] at pc {:#x}",
+                stall.hart_id,
+                stall.elapsed / tick,
+                name,
+                stall.tid,
+                stall.pc,
+            );
+        }
+    }
+}
+
+/// The state of a single hart as last seen by the [`Watchdog`]
+struct Heartbeat {
+    /// The time the hart last made a scheduling decision, or zero if it hasn't
+    /// started scheduling yet
+    last_fed: AtomicU64,
+    tid: AtomicUsize,
+    pc: AtomicUsize,
+    reported: AtomicBool,
+}
+
+impl Heartbeat {
+    fn new() -> Self {
+        Self {
+            last_fed: AtomicU64::new(0),
+            tid: AtomicUsize::new(0),
+            pc: AtomicUsize::new(0),
+            reported: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns whether the hart was previously reported as stalled
+    fn feed(&self, now: u64, tid: Tid) -> bool {
+        self.tid.store(tid.value(), Ordering::Relaxed);
+        self.last_fed.store(now.max(1), Ordering::Relaxed);
+        self.reported.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Stall {
+    hart_id: usize,
+    elapsed: u64,
+    tid: usize,
+    pc: usize,
+}
+
+/// The harts other than `current_hart` which haven't been fed within
+/// `threshold` time units of `now` and haven't been reported yet, marking them
+/// as reported
+fn stalls(harts: &[Heartbeat], current_hart: usize, now: u64, threshold: u64) -> impl Iterator<Item = Stall> + '_ {
+    harts.iter().enumerate().filter(move |(hart_id, _)| *hart_id != current_hart).filter_map(
+        move |(hart_id, heartbeat)| {
+            let last_fed = heartbeat.last_fed.load(Ordering::Relaxed);
+            let elapsed = now.saturating_sub(last_fed);
+
+            if last_fed == 0 || elapsed <= threshold || heartbeat.reported.swap(true, Ordering::Relaxed) {
+                return None;
+            }
+
+            Some(Stall {
+                hart_id,
+                elapsed,
+                tid: heartbeat.tid.load(Ordering::Relaxed),
+                pc: heartbeat.pc.load(Ordering::Relaxed),
+            })
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tid(tid: usize) -> Tid {
+        Tid::new(NonZeroUsize::new(tid).unwrap())
+    }
+
+    #[test]
+    fn spinning_hart_is_reported_once() {
+        let harts = [Heartbeat::new(), Heartbeat::new(), Heartbeat::new()];
+
+        // Hart 2 hasn't started scheduling yet, so is never reported
+        harts[0].feed(10, tid(1));
+        harts[1].feed(10, tid(2));
+        harts[1].pc.store(0x1_0000, Ordering::Relaxed);
+
+        // Hart 1 starts spinning in task 2 while hart 0 keeps scheduling
+        for now in (20..=100).step_by(10) {
+            harts[0].feed(now, tid(1));
+            assert_eq!(stalls(&harts, 0, now, 100).count(), 0);
+        }
+
+        harts[0].feed(120, tid(1));
+        let reported = stalls(&harts, 0, 120, 100).collect::<Vec<_>>();
+        assert_eq!(reported, [Stall { hart_id: 1, elapsed: 110, tid: 2, pc: 0x1_0000 }]);
+
+        // The stall is only reported once
+        assert_eq!(stalls(&harts, 0, 200, 100).count(), 0);
+
+        // Once the hart recovers, it can be reported again
+        assert!(harts[1].feed(210, tid(3)));
+        assert!(!harts[1].feed(220, tid(3)));
+        assert_eq!(stalls(&harts, 0, 300, 100).count(), 0);
+        assert_eq!(stalls(&harts, 0, 400, 100).map(|stall| stall.tid).collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn harts_dont_check_themselves() {
+        let harts = [Heartbeat::new()];
+        harts[0].feed(10, tid(1));
+
+        assert_eq!(stalls(&harts, 0, 1000, 100).count(), 0);
+    }
+}
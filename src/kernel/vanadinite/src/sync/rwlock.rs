@@ -26,6 +26,13 @@ impl<T: Send> SpinRwLock<T> {
         ReadGuard { lock: self }
     }
 
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        match self.try_lock_shared() {
+            true => Some(ReadGuard { lock: self }),
+            false => None,
+        }
+    }
+
     pub fn write(&self) -> WriteGuard<'_, T> {
         self.lock_exclusive();
         WriteGuard { lock: self }
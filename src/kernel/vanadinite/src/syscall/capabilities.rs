@@ -6,10 +6,20 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    capabilities::CapabilityResource, interrupts::PLIC, mem::manager::AddressRegionKind, task::Task,
+    capabilities::CapabilityResource,
+    interrupts::PLIC,
+    mem::{
+        manager::AddressRegionKind,
+        paging::VirtualAddress,
+        user::{self, RawUserSlice},
+    },
+    task::Task,
     trap::GeneralRegisters,
 };
-use librust::{capabilities::CapabilityPtr, error::SyscallError};
+use librust::{
+    capabilities::{CapabilityPtr, CapabilityWithDescription},
+    error::SyscallError,
+};
 
 /// Delete a capability from a task
 pub fn delete(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
@@ -22,6 +32,7 @@ pub fn delete(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallEr
 
     match capability.resource {
         CapabilityResource::Channel(channel) => drop(channel),
+        CapabilityResource::SystemReset | CapabilityResource::KernelLog => {}
         CapabilityResource::SharedMemory(_, range, kind) => {
             log::debug!("Freeing virtual memory @ {:?}", range);
             assert_eq!(kind, AddressRegionKind::UserSharedMemory);
@@ -47,3 +58,35 @@ pub fn delete(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallEr
 
     Ok(())
 }
+
+/// Write a snapshot of the task's capabilities into the user buffer, returning
+/// the total number of capabilities in `a1` and the number written in `a2`.
+/// A buffer too small to hold every capability is filled with the ones with
+/// the lowest [`CapabilityPtr`] values.
+pub fn enumerate(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let mut task = task.mutable_state.lock();
+    let buffer =
+        RawUserSlice::<user::ReadWrite, CapabilityWithDescription>::new(VirtualAddress::new(frame.a1), frame.a2);
+
+    // The lock is held until the buffer has been written, so the snapshot
+    // can't observe a capability being minted or deleted part way through
+    let described = task.cspace.describe_all();
+    let written = match buffer.len() {
+        0 => 0,
+        len => {
+            let buffer = match unsafe { buffer.validate_writable(&mut task.memory_manager) } {
+                Ok(buffer) => buffer,
+                Err(_) => return Err(SyscallError::InvalidArgument(0)),
+            };
+
+            let written = len.min(described.len());
+            buffer.guarded()[..written].copy_from_slice(&described[..written]);
+            written
+        }
+    };
+
+    frame.a1 = described.len();
+    frame.a2 = written;
+
+    Ok(())
+}
@@ -6,31 +6,42 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    capabilities::{Capability, CapabilityResource},
+    capabilities::{Capability, CapabilityResource, CapabilitySpace},
+    csr,
     interrupts::PLIC,
     mem::{
+        manager::{AddressRegionKind, UserspaceMemoryManager},
         paging::{flags::Flags, VirtualAddress},
+        region::SharedPhysicalRegion,
         user::{self, RawUserSlice},
+        usercopy::{copy_from_user, KError},
     },
-    scheduler::{waitqueue::WaitQueue, TASKS},
-    sync::SpinMutex,
-    task::Task,
+    scheduler::{timers::TIMERS, waitqueue::WaitQueue, CURRENT_TASK, SCHEDULER, TASKS},
+    sync::{mutex::SpinMutexGuard, SpinMutex},
+    task::{Task, TaskState},
     trap::GeneralRegisters,
     utils::SameHartDeadlockDetection,
     HART_ID,
 };
 use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+};
 use librust::{
     capabilities::{CapabilityPtr, CapabilityRights},
     error::SyscallError,
     syscalls::{
-        channel::{ChannelReadFlags, KernelMessage},
+        channel::{ChannelReadFlags, ChannelWriteFlags, KernelMessage, Segment, MAX_WAIT_CHANNELS},
         mem::MemoryPermissions,
     },
     task::Tid,
 };
 
+/// The number of messages that can be queued on a userspace channel before
+/// senders are made to wait for the receiver to catch up
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 64;
+
 #[derive(Debug, Clone)]
 pub struct UserspaceChannel {
     pub(super) sender: Sender,
@@ -38,52 +49,62 @@ pub struct UserspaceChannel {
 }
 
 impl UserspaceChannel {
-    pub fn new() -> (Self, Self) {
-        let (sender1, receiver1) = {
-            let message_queue = Arc::new(SpinMutex::new(VecDeque::new()));
-            let alive = Arc::new(AtomicBool::new(true));
-            let waitqueue = Arc::new(WaitQueue::new());
-
-            let sender = Sender {
-                inner: Arc::clone(&message_queue),
-                alive: Arc::clone(&alive),
-                waitqueue: Arc::clone(&waitqueue),
-                other_tid: None,
-                other_cptr: CapabilityPtr::new(usize::MAX),
-            };
-            let receiver = Receiver { inner: message_queue, alive, waitqueue };
-
-            (sender, receiver)
-        };
-
-        let (sender2, receiver2) = {
-            let message_queue = Arc::new(SpinMutex::new(VecDeque::new()));
-            let alive = Arc::new(AtomicBool::new(true));
-            let waitqueue = Arc::new(WaitQueue::new());
-
-            let sender = Sender {
-                inner: Arc::clone(&message_queue),
-                alive: Arc::clone(&alive),
-                waitqueue: Arc::clone(&waitqueue),
-                other_tid: None,
-                other_cptr: CapabilityPtr::new(usize::MAX),
-            };
-            let receiver = Receiver { inner: message_queue, alive, waitqueue };
-
-            (sender, receiver)
-        };
+    /// Create a pair of connected channel endpoints, each of which can have up
+    /// to `capacity` messages waiting to be received before sending to it
+    /// blocks
+    pub fn new(capacity: usize) -> (Self, Self) {
+        let (sender1, receiver1) = Self::half(capacity);
+        let (sender2, receiver2) = Self::half(capacity);
 
         let first = Self { sender: sender1, receiver: receiver2 };
         let second = Self { sender: sender2, receiver: receiver1 };
 
         (first, second)
     }
+
+    /// Create a pair of connected channel endpoints with no limit on the number
+    /// of queued messages. Used for channels the kernel sends on, since the
+    /// kernel can't wait for the receiver when sending from interrupt context.
+    pub fn unbounded() -> (Self, Self) {
+        Self::new(usize::MAX)
+    }
+
+    fn half(capacity: usize) -> (Sender, Receiver) {
+        let message_queue = Arc::new(SpinMutex::new(VecDeque::new()));
+        let alive = Arc::new(AtomicBool::new(true));
+        let waitqueue = Arc::new(WaitQueue::new());
+        let senders = Arc::new(WaitQueue::new());
+
+        let sender = Sender {
+            inner: Arc::clone(&message_queue),
+            alive: Arc::clone(&alive),
+            waitqueue: Arc::clone(&waitqueue),
+            senders: Arc::clone(&senders),
+            capacity,
+            other_tid: None,
+            other_cptr: CapabilityPtr::new(usize::MAX),
+        };
+        let lifetime = Arc::new(ReceiverLifetime {
+            inner: Arc::clone(&message_queue),
+            alive: Arc::clone(&alive),
+            senders: Arc::clone(&senders),
+        });
+        let receiver = Receiver { inner: message_queue, alive, waitqueue, senders, _lifetime: lifetime };
+
+        (sender, receiver)
+    }
 }
 
+type MessageQueueGuard<'a> = SpinMutexGuard<'a, VecDeque<ChannelMessage>, SameHartDeadlockDetection>;
+
 #[derive(Debug)]
 pub struct ChannelMessage {
     pub data: [usize; 7],
     pub caps: Vec<Capability>,
+    /// The byte range of each leading memory capability in a scatter-gather
+    /// message, which are described to the receiver as just those bytes. Empty
+    /// for other messages.
+    pub segments: Vec<Range<usize>>,
 }
 
 #[derive(Debug, Clone)]
@@ -92,6 +113,9 @@ pub(super) struct Receiver {
     pub(super) inner: Arc<SpinMutex<VecDeque<ChannelMessage>, SameHartDeadlockDetection>>,
     pub(super) alive: Arc<AtomicBool>,
     pub(super) waitqueue: Arc<WaitQueue>,
+    /// Senders waiting for room in the message queue
+    pub(super) senders: Arc<WaitQueue>,
+    _lifetime: Arc<ReceiverLifetime>,
 }
 
 impl Receiver {
@@ -100,7 +124,10 @@ impl Receiver {
             let mut lock = self.inner.lock();
             let msg = lock.pop_front();
             match msg {
-                Some(message) => break Ok(message),
+                Some(message) => {
+                    self.senders.wake_one();
+                    break Ok(message);
+                }
                 // FIXME: should we check `alive` here?
                 None => self.waitqueue.wait(move || drop(lock)),
             }
@@ -109,7 +136,10 @@ impl Receiver {
 
     pub fn try_recv(&self) -> Result<Option<ChannelMessage>, ()> {
         match self.inner.lock().pop_front() {
-            Some(message) => Ok(Some(message)),
+            Some(message) => {
+                self.senders.wake_one();
+                Ok(Some(message))
+            }
             None => match self.alive.load(Ordering::Acquire) {
                 true => Ok(None),
                 false => Err(()),
@@ -118,10 +148,24 @@ impl Receiver {
     }
 }
 
-impl Drop for Receiver {
+/// Shared between every clone of a [`Receiver`], marking the channel as dead
+/// and waking any senders waiting for room once the last of them is dropped
+#[derive(Debug)]
+struct ReceiverLifetime {
+    inner: Arc<SpinMutex<VecDeque<ChannelMessage>, SameHartDeadlockDetection>>,
+    alive: Arc<AtomicBool>,
+    senders: Arc<WaitQueue>,
+}
+
+impl Drop for ReceiverLifetime {
     fn drop(&mut self) {
-        // FIXME: this currently breaks sending messages...
-        // self.alive.store(false, Ordering::Release);
+        // Senders check whether the channel is alive and start waiting while
+        // holding the message queue lock, so none of them can miss the wake up
+        let lock = self.inner.lock();
+        self.alive.store(false, Ordering::Release);
+        drop(lock);
+
+        self.senders.wake_all();
     }
 }
 
@@ -131,31 +175,65 @@ pub struct Sender {
     pub(super) inner: Arc<SpinMutex<VecDeque<ChannelMessage>, SameHartDeadlockDetection>>,
     pub(super) alive: Arc<AtomicBool>,
     pub(super) waitqueue: Arc<WaitQueue>,
+    pub(super) senders: Arc<WaitQueue>,
+    pub(super) capacity: usize,
     pub(super) other_tid: Option<Tid>,
     pub(super) other_cptr: CapabilityPtr,
 }
 
 impl Sender {
+    /// Send a message, waiting for the receiver to make room for it if the
+    /// channel is full. Fails without waiting, or stops waiting, once the
+    /// receiver is gone.
     #[track_caller]
     pub fn send(&self, message: ChannelMessage) -> Result<(), ChannelMessage> {
+        let mut lock = self.inner.lock();
+        while lock.len() >= self.capacity && self.alive.load(Ordering::Acquire) {
+            log::debug!("Channel to {:?}:{:?} is full, waiting", self.other_tid, self.other_cptr);
+            self.senders.wait(move || drop(lock));
+            lock = self.inner.lock();
+        }
+
+        self.push(lock, message)
+    }
+
+    /// Send a message, failing with [`TrySendError::Full`] instead of waiting
+    /// if the channel is full
+    #[track_caller]
+    pub fn try_send(&self, message: ChannelMessage) -> Result<(), TrySendError> {
+        let lock = self.inner.lock();
+        if lock.len() >= self.capacity {
+            return Err(TrySendError::Full(message));
+        }
+
+        self.push(lock, message).map_err(TrySendError::Disconnected)
+    }
+
+    #[track_caller]
+    fn push(&self, mut lock: MessageQueueGuard<'_>, message: ChannelMessage) -> Result<(), ChannelMessage> {
         if !self.alive.load(Ordering::Acquire) {
             log::debug!("Channel to {:?}:{:?} is dead", self.other_tid, self.other_cptr);
             return Err(message);
         }
 
-        // FIXME: set a buffer limit at some point
-        let mut lock = self.inner.lock();
-
         if let Some(task) = self.other_tid.and_then(|tid| TASKS.get(tid)) {
             log::debug!("Enqueuing kernel message for other cptr [{}:{:?}]", task.name, self.other_cptr);
             let task_state = task.mutable_state.lock();
             if task_state.subscribes_to_events {
                 let sender = task_state.kernel_channel.sender.clone();
                 drop(task_state);
-                sender.send(ChannelMessage {
-                    data: KernelMessage::into_parts(KernelMessage::NewChannelMessage(self.other_cptr)),
-                    caps: Vec::new(),
-                })?;
+                // Kernel channels are unbounded, so this can only fail if the
+                // other task is gone
+                if sender
+                    .try_send(ChannelMessage {
+                        data: KernelMessage::into_parts(KernelMessage::NewChannelMessage(self.other_cptr)),
+                        caps: Vec::new(),
+                        segments: Vec::new(),
+                    })
+                    .is_err()
+                {
+                    return Err(message);
+                }
             }
         }
 
@@ -166,6 +244,16 @@ impl Sender {
     }
 }
 
+/// The reason a [`Sender::try_send`] failed, containing the message which
+/// couldn't be sent
+#[derive(Debug)]
+pub enum TrySendError {
+    /// The channel already contains as many messages as it can hold
+    Full(ChannelMessage),
+    /// The receiving end of the channel no longer exists
+    Disconnected(ChannelMessage),
+}
+
 impl Drop for Sender {
     fn drop(&mut self) {
         // FIXME: this currently breaks sending messages
@@ -173,12 +261,37 @@ impl Drop for Sender {
     }
 }
 
+/// The task on the other end of `channel`, if it's still around
+fn peer(task: &Task, channel: &UserspaceChannel) -> Option<Arc<Task>> {
+    channel.sender.other_tid.filter(|tid| *tid != task.tid).and_then(|tid| TASKS.get(tid))
+}
+
+/// Lend `task`'s priority to the task on the other end of `channel`, which
+/// `task` sent a request to and is going to wait on for the reply, so medium
+/// priority tasks can't keep a lower priority server from getting to it. This
+/// only covers the server `task` is waiting on directly: if the server is itself
+/// waiting on some other task, the loan isn't passed along.
+fn lend_priority(task: &Task, channel: &UserspaceChannel) {
+    if let Some(other) = peer(task, channel) {
+        other.priority.inherit(task.tid, task.priority.effective());
+    }
+}
+
+/// Return anything `task` lent to the task on the other end of `channel` now
+/// that it's done waiting on it
+fn return_priority(task: &Task, channel: &UserspaceChannel) {
+    if let Some(other) = peer(task, channel) {
+        other.priority.revert(task.tid);
+    }
+}
+
 pub fn send_message(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
     let task_state = task.mutable_state.lock();
 
     let cptr = CapabilityPtr::new(frame.a1);
     let caps =
         RawUserSlice::<user::Read, librust::capabilities::Capability>::new(VirtualAddress::new(frame.a2), frame.a3);
+    let flags = ChannelWriteFlags::new(frame.a4);
     let data = [frame.t0, frame.t1, frame.t2, frame.t3, frame.t4, frame.t5, frame.t6];
 
     let channel = match task_state.cspace.resolve(cptr) {
@@ -192,8 +305,18 @@ pub fn send_message(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sys
 
     // Fixup caps here so we can error on any invalid caps/slice and not dealloc
     // the message region
-    let caps = match caps.len() {
-        0 => Vec::new(),
+    let (caps, segments) = match caps.len() {
+        0 => (Vec::new(), Vec::new()),
+        _ if flags & ChannelWriteFlags::SEGMENTED => {
+            let segments = RawUserSlice::<user::Read, Segment>::new(VirtualAddress::new(frame.a2), frame.a3);
+            let segment_slice = match unsafe { segments.validate(&task_state.memory_manager) } {
+                Ok(segment_slice) => segment_slice,
+                Err(_) => return Err(SyscallError::InvalidArgument(3)),
+            };
+
+            let segment_slice = segment_slice.guarded();
+            resolve_segments(&task_state.cspace, &segment_slice)?
+        }
         _ => {
             let cap_slice = match unsafe { caps.validate(&task_state.memory_manager) } {
                 Ok(cap_slice) => cap_slice,
@@ -210,31 +333,130 @@ pub fn send_message(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sys
             // preallocation amount.
             let mut cloned_caps = Vec::with_capacity(2);
             for librust::capabilities::Capability { cptr, rights } in cap_slice.iter().copied() {
-                match task_state.cspace.resolve(cptr) {
-                    Some(cap) if cap.rights.is_superset(rights) && cap.rights & CapabilityRights::GRANT => {
-                        // Can't allow sending invalid memory permissions
-                        if let CapabilityResource::SharedMemory(..) = &cap.resource {
-                            if cap.rights & CapabilityRights::WRITE && !(cap.rights & CapabilityRights::READ) {
-                                return Err(SyscallError::InvalidArgument(2));
-                            }
-                        }
-
-                        cloned_caps.push(cap.clone())
-                    }
-                    _ => return Err(SyscallError::InvalidArgument(2)),
-                }
+                cloned_caps.push(resolve_granted(&task_state.cspace, cptr, rights)?);
             }
 
-            cloned_caps
+            (cloned_caps, Vec::new())
         }
     };
 
     log::debug!("[{}:{}] Sending channel message", task.name, task.tid);
     drop(task_state);
-    // FIXME: this should notify the sender the channel is dead if it is
-    channel.sender.send(ChannelMessage { data, caps }).unwrap();
+    // The loan starts before sending so the server already has it if the
+    // channel is full and this has to wait for it to catch up
+    let expects_reply = flags & ChannelWriteFlags::EXPECTS_REPLY;
+    if expects_reply {
+        lend_priority(task, &channel);
+    }
 
-    Ok(())
+    // Writing to a channel whose other end is gone is an error for the writer,
+    // whether or not it would have blocked
+    let result = if flags & ChannelWriteFlags::NONBLOCKING {
+        match channel.sender.try_send(ChannelMessage { data, caps, segments }) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(SyscallError::WouldBlock),
+            Err(TrySendError::Disconnected(_)) => Err(SyscallError::InvalidOperation(0)),
+        }
+    } else {
+        channel.sender.send(ChannelMessage { data, caps, segments }).map_err(|_| SyscallError::InvalidOperation(0))
+    };
+
+    if expects_reply && result.is_err() {
+        return_priority(task, &channel);
+    }
+
+    result
+}
+
+/// Clone the capability at `cptr` to be sent to another task, which requires it
+/// to have the `GRANT` right as well as the `rights` being sent
+pub(super) fn resolve_granted(
+    cspace: &CapabilitySpace,
+    cptr: CapabilityPtr,
+    rights: CapabilityRights,
+) -> Result<Capability, SyscallError> {
+    match cspace.resolve(cptr) {
+        Some(cap) if cap.rights.is_superset(rights) && cap.rights & CapabilityRights::GRANT => {
+            // Can't allow sending invalid memory permissions
+            if let CapabilityResource::SharedMemory(..) = &cap.resource {
+                if cap.rights & CapabilityRights::WRITE && !(cap.rights & CapabilityRights::READ) {
+                    return Err(SyscallError::InvalidArgument(2));
+                }
+            }
+
+            Ok(cap.clone())
+        }
+        _ => Err(SyscallError::InvalidArgument(2)),
+    }
+}
+
+/// Resolve the memory capabilities making up a scatter-gather message, along
+/// with the byte range of each. Every segment must be readable memory and lie
+/// within its capability's memory.
+fn resolve_segments(
+    cspace: &CapabilitySpace,
+    segments: &[Segment],
+) -> Result<(Vec<Capability>, Vec<Range<usize>>), SyscallError> {
+    let mut caps = Vec::with_capacity(2);
+    let mut ranges = Vec::with_capacity(2);
+    for Segment { capability, offset, len } in segments.iter().copied() {
+        let cap = resolve_granted(cspace, capability.cptr, capability.rights)?;
+        let range = match &cap.resource {
+            CapabilityResource::SharedMemory(_, memory, _) if capability.rights & CapabilityRights::READ => {
+                let memory_len = memory.end.as_usize() - memory.start.as_usize();
+                match offset.checked_add(len) {
+                    Some(end) if end <= memory_len => offset..end,
+                    _ => return Err(SyscallError::InvalidArgument(2)),
+                }
+            }
+            _ => return Err(SyscallError::InvalidArgument(2)),
+        };
+
+        caps.push(cap);
+        ranges.push(range);
+    }
+
+    Ok((caps, ranges))
+}
+
+/// Map shared memory received over a channel into the receiver's address space
+/// and give it a capability to it, describing either all of the memory or just
+/// the bytes in `segment`
+pub(super) fn map_shared_memory(
+    memory_manager: &mut UserspaceMemoryManager,
+    cspace: &mut CapabilitySpace,
+    region: SharedPhysicalRegion,
+    kind: AddressRegionKind,
+    rights: CapabilityRights,
+    segment: Option<Range<usize>>,
+) -> (CapabilityPtr, librust::capabilities::CapabilityDescription) {
+    let mut permissions = MemoryPermissions::new(0);
+    let mut memflags = Flags::VALID | Flags::USER;
+
+    if rights & CapabilityRights::READ {
+        permissions |= MemoryPermissions::READ;
+        memflags |= Flags::READ;
+    }
+
+    if rights & CapabilityRights::WRITE {
+        permissions |= MemoryPermissions::WRITE;
+        memflags |= Flags::WRITE;
+    }
+
+    if rights & CapabilityRights::EXECUTE {
+        permissions |= MemoryPermissions::EXECUTE;
+        memflags |= Flags::EXECUTE;
+    }
+
+    let addr = memory_manager.apply_shared_region(None, memflags, region.clone(), kind);
+    let (ptr, len) = match segment {
+        Some(segment) => (addr.start.add(segment.start).as_mut_ptr(), segment.len()),
+        None => (addr.start.as_mut_ptr(), addr.end.as_usize() - addr.start.as_usize()),
+    };
+
+    let cptr = cspace.mint(Capability { resource: CapabilityResource::SharedMemory(region, addr, kind), rights });
+
+    (cptr, librust::capabilities::CapabilityDescription::Memory { ptr, len, permissions })
 }
 
 pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), SyscallError> {
@@ -262,7 +484,7 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
     // FIXME: check for broken channel
 
     drop(task_state);
-    let ChannelMessage { data, mut caps } = if flags & ChannelReadFlags::NONBLOCKING {
+    let ChannelMessage { data, mut caps, mut segments } = if flags & ChannelReadFlags::NONBLOCKING {
         match channel.receiver.try_recv() {
             Ok(Some(msg)) => msg,
             Ok(None) => return Err(SyscallError::WouldBlock),
@@ -275,19 +497,23 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
         }
     };
 
+    // If this is the reply to a request, the server doesn't need the task's
+    // priority anymore
+    return_priority(task, &channel);
+
     let mut task_state = task.mutable_state.lock();
 
     let (caps_written, caps_remaining) = match cap_buffer.len() {
         0 => (0, caps.len()),
         len => {
-            let cap_slice = match unsafe { cap_buffer.validate(&task_state.memory_manager) } {
+            let cap_slice = match unsafe { cap_buffer.validate_writable(&mut task_state.memory_manager) } {
                 Ok(cap_slice) => cap_slice,
                 Err(_) => return Err(SyscallError::InvalidArgument(3)),
             };
 
             let n_caps_to_write = len.min(caps.len());
             let mut cap_slice = cap_slice.guarded();
-            for (target, cap) in cap_slice.iter_mut().zip(caps.drain(..n_caps_to_write)) {
+            for (i, (target, cap)) in cap_slice.iter_mut().zip(caps.drain(..n_caps_to_write)).enumerate() {
                 let rights = cap.rights;
                 let (cptr, description) = match cap.resource {
                     CapabilityResource::Channel(channel) => {
@@ -305,7 +531,7 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
                             None => continue, // Task is... gone? hmm..
                         };
 
-                        let (mut c1, mut c2) = UserspaceChannel::new();
+                        let (mut c1, mut c2) = UserspaceChannel::new(channel.sender.capacity);
                         c1.sender.other_tid = Some(task.tid);
                         c2.sender.other_tid = Some(other_tid);
 
@@ -323,38 +549,14 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
                         (cptr, librust::capabilities::CapabilityDescription::Channel)
                     }
                     CapabilityResource::SharedMemory(region, _, kind) => {
-                        let mut permissions = MemoryPermissions::new(0);
-                        let mut memflags = Flags::VALID | Flags::USER;
-
-                        if rights & CapabilityRights::READ {
-                            permissions |= MemoryPermissions::READ;
-                            memflags |= Flags::READ;
-                        }
-
-                        if rights & CapabilityRights::WRITE {
-                            permissions |= MemoryPermissions::WRITE;
-                            memflags |= Flags::WRITE;
-                        }
-
-                        if rights & CapabilityRights::EXECUTE {
-                            permissions |= MemoryPermissions::EXECUTE;
-                            memflags |= Flags::EXECUTE;
-                        }
-
-                        let addr = task_state.memory_manager.apply_shared_region(None, memflags, region.clone(), kind);
-
-                        let cptr = task_state.cspace.mint(Capability {
-                            resource: CapabilityResource::SharedMemory(region, addr.clone(), kind),
+                        let task_state = &mut *task_state;
+                        map_shared_memory(
+                            &mut task_state.memory_manager,
+                            &mut task_state.cspace,
+                            region,
+                            kind,
                             rights,
-                        });
-
-                        (
-                            cptr,
-                            librust::capabilities::CapabilityDescription::Memory {
-                                ptr: addr.start.as_mut_ptr(),
-                                len: addr.end.as_usize() - addr.start.as_usize(),
-                                permissions,
-                            },
+                            segments.get(i).cloned(),
                         )
                     }
                     CapabilityResource::Mmio(phys, _, interrupts) => {
@@ -397,12 +599,14 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
 
                                 drop(task_state);
 
-                                sender
-                                    .send(ChannelMessage {
-                                        data: Into::into(KernelMessage::InterruptOccurred(id)),
-                                        caps: Vec::new(),
-                                    })
-                                    .expect("handle kernel channel send failure");
+                                // Fails if the task has closed its kernel
+                                // channel, in which case it isn't interested
+                                // in the interrupt anymore
+                                let _ = sender.send(ChannelMessage {
+                                    data: Into::into(KernelMessage::InterruptOccurred(id)),
+                                    caps: Vec::new(),
+                                    segments: Vec::new(),
+                                });
 
                                 Ok(())
                             });
@@ -422,6 +626,14 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
                             },
                         )
                     }
+                    CapabilityResource::SystemReset => (
+                        task_state.cspace.mint(Capability { resource: CapabilityResource::SystemReset, rights }),
+                        librust::capabilities::CapabilityDescription::SystemReset,
+                    ),
+                    CapabilityResource::KernelLog => (
+                        task_state.cspace.mint(Capability { resource: CapabilityResource::KernelLog, rights }),
+                        librust::capabilities::CapabilityDescription::KernelLog,
+                    ),
                 };
 
                 *target = librust::capabilities::CapabilityWithDescription {
@@ -430,12 +642,16 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
                 };
             }
 
+            // Any segments past the ones written belong to the caps that are
+            // left over
+            segments = segments.split_off(n_caps_to_write.min(segments.len()));
+
             (n_caps_to_write, caps.len())
         }
     };
 
     if caps_remaining != 0 {
-        channel.receiver.inner.lock().push_front(ChannelMessage { data: [0; 7], caps });
+        channel.receiver.inner.lock().push_front(ChannelMessage { data: [0; 7], caps, segments });
     }
 
     regs.a1 = caps_written;
@@ -451,3 +667,388 @@ pub fn read_message(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
     log::debug!("[{}:{}:{:?}] Read channel message! ra={:#p}", task.name, task.tid, cptr, crate::asm::ra());
     Ok(())
 }
+
+pub fn wait_any(task: &Task, regs: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let len = regs.a2;
+    if len == 0 || len > MAX_WAIT_CHANNELS {
+        return Err(SyscallError::InvalidArgument(1));
+    }
+
+    let task_state = task.mutable_state.lock();
+    let mut bytes = [0; MAX_WAIT_CHANNELS * core::mem::size_of::<usize>()];
+    let bytes = &mut bytes[..len * core::mem::size_of::<usize>()];
+    if let Err(KError::InvalidAddress(addr, e)) =
+        copy_from_user(&task_state.memory_manager, VirtualAddress::new(regs.a1), bytes)
+    {
+        log::debug!("Bad channel list @ {:#p}: {:?}", addr, e);
+        return Err(SyscallError::InvalidArgument(0));
+    }
+
+    let mut channels = Vec::with_capacity(len);
+    for cptr in bytes.chunks_exact(core::mem::size_of::<usize>()) {
+        let cptr = CapabilityPtr::new(usize::from_ne_bytes(cptr.try_into().unwrap()));
+        match task_state.cspace.resolve(cptr) {
+            Some(Capability { resource: CapabilityResource::Channel(channel), rights })
+                if *rights & CapabilityRights::READ =>
+            {
+                channels.push(channel.clone())
+            }
+            _ => return Err(SyscallError::InvalidArgument(0)),
+        }
+    }
+
+    drop(task_state);
+
+    let deadline = match regs.a3 {
+        0 => None,
+        deadline => Some(deadline as u64),
+    };
+
+    match wait_for_message(&CURRENT_TASK.get(), &channels, deadline) {
+        Some(index) => {
+            regs.a1 = index;
+            Ok(())
+        }
+        None => Err(SyscallError::WouldBlock),
+    }
+}
+
+/// Block `task` until one of `channels` has a message waiting, returning the
+/// index of the first one which does, or `None` if the timer reaches `deadline`
+/// first
+fn wait_for_message(task: &Arc<Task>, channels: &[UserspaceChannel], deadline: Option<u64>) -> Option<usize> {
+    let waiter = ChannelWaiter { task, channels };
+    if let Some(deadline) = deadline {
+        TIMERS.wake_at(task.tid, deadline);
+    }
+
+    let ready = loop {
+        // Checking for messages only after being placed in every waitqueue
+        // means anything sent after the check wakes the task back up
+        waiter.register();
+        task.mutable_state.lock().state = TaskState::Blocked;
+
+        match (waiter.ready(), deadline) {
+            (Some(index), _) => break Some(index),
+            (None, Some(deadline)) if csr::time::read() >= deadline => break None,
+            _ => {
+                SCHEDULER.schedule();
+                waiter.unregister();
+            }
+        }
+    };
+
+    task.mutable_state.lock().state = TaskState::Ready;
+    waiter.unregister();
+    TIMERS.wake_at(task.tid, 0);
+
+    ready
+}
+
+/// A task waiting on any of several channels to receive a message
+struct ChannelWaiter<'a> {
+    task: &'a Arc<Task>,
+    channels: &'a [UserspaceChannel],
+}
+
+impl ChannelWaiter<'_> {
+    fn register(&self) {
+        for channel in self.channels {
+            channel.receiver.waitqueue.enqueue(Arc::clone(self.task));
+        }
+    }
+
+    fn unregister(&self) {
+        for channel in self.channels {
+            channel.receiver.waitqueue.remove(self.task.tid);
+        }
+    }
+
+    /// The index of the first channel with a message waiting
+    fn ready(&self) -> Option<usize> {
+        self.channels.iter().position(|channel| !channel.receiver.inner.lock().is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::{
+        manager::{FillOption, RegionDescription},
+        paging::PageSize,
+        phys2virt,
+    };
+
+    fn message(n: usize) -> ChannelMessage {
+        ChannelMessage { data: [n, 0, 0, 0, 0, 0, 0], caps: Vec::new(), segments: Vec::new() }
+    }
+
+    #[test]
+    fn full_channel_applies_backpressure() {
+        let (sender, receiver) = UserspaceChannel::new(4);
+
+        // A fast sender fills up the channel and is then told to back off
+        // instead of the message being dropped
+        let mut sent = 0;
+        loop {
+            match sender.sender.try_send(message(sent)) {
+                Ok(()) => sent += 1,
+                Err(TrySendError::Full(returned)) => {
+                    assert_eq!(returned.data[0], sent);
+                    break;
+                }
+                Err(TrySendError::Disconnected(_)) => panic!("channel disconnected"),
+            }
+        }
+        assert_eq!(sent, 4);
+
+        // Once the slow receiver catches up there's room for another message
+        assert_eq!(receiver.receiver.try_recv().unwrap().unwrap().data[0], 0);
+        sender.sender.try_send(message(sent)).unwrap();
+        assert!(matches!(sender.sender.try_send(message(sent + 1)), Err(TrySendError::Full(_))));
+
+        for n in 1..=4 {
+            assert_eq!(receiver.receiver.try_recv().unwrap().unwrap().data[0], n);
+        }
+        assert!(receiver.receiver.try_recv().unwrap().is_none());
+    }
+
+    #[test]
+    fn write_to_dead_channel_fails() {
+        let task = Task::idle();
+        let (sender, receiver) = UserspaceChannel::new(4);
+
+        // The channel only dies once every clone of the receiving end is gone
+        let clone = receiver.clone();
+        drop(receiver);
+        assert!(sender.sender.alive.load(Ordering::Acquire));
+        drop(clone);
+        assert!(!sender.sender.alive.load(Ordering::Acquire));
+
+        let cptr = task
+            .mutable_state
+            .lock()
+            .cspace
+            .mint(Capability { resource: CapabilityResource::Channel(sender), rights: CapabilityRights::WRITE });
+
+        let mut frame =
+            GeneralRegisters { a1: cptr.value(), a4: ChannelWriteFlags::NONBLOCKING.value(), ..Default::default() };
+        assert_eq!(send_message(&task, &mut frame), Err(SyscallError::InvalidOperation(0)));
+
+        // Blocking writes don't wait on a channel that will never be read
+        frame.a4 = ChannelWriteFlags::NONE.value();
+        assert_eq!(send_message(&task, &mut frame), Err(SyscallError::InvalidOperation(0)));
+
+        core::mem::forget(task);
+    }
+
+    #[test]
+    fn dropping_receiver_wakes_blocked_senders() {
+        let (sender, receiver) = UserspaceChannel::new(1);
+        sender.sender.try_send(message(0)).unwrap();
+
+        // Mirrors `Sender::send` blocking on the full channel
+        let task = Arc::new(Task::idle());
+        sender.sender.senders.enqueue(Arc::clone(&task));
+        task.mutable_state.lock().state = TaskState::Blocked;
+
+        drop(receiver);
+        assert_eq!(task.mutable_state.lock().state, TaskState::Ready);
+        assert!(!sender.sender.senders.remove(task.tid));
+
+        // Once the sender runs again it gives up instead of waiting some more
+        assert_eq!(sender.sender.send(message(1)).unwrap_err().data[0], 1);
+
+        core::mem::forget(task);
+    }
+
+    #[test]
+    fn requests_lend_priority_until_the_reply() {
+        use crate::scheduler::priority::{Priority, DEFAULT_PRIORITY};
+
+        let mut client = Task::idle();
+        client.priority = Priority::new(3);
+        let (client_tid, client) = TASKS.insert(client);
+        let (server_tid, server) = TASKS.insert(Task::idle());
+
+        let (mut client_end, mut server_end) = UserspaceChannel::new(4);
+        client_end.sender.other_tid = Some(server_tid);
+        server_end.sender.other_tid = Some(client_tid);
+
+        let rights = CapabilityRights::READ | CapabilityRights::WRITE;
+        let client_cptr = client
+            .mutable_state
+            .lock()
+            .cspace
+            .mint(Capability { resource: CapabilityResource::Channel(client_end), rights });
+        let server_cptr = server
+            .mutable_state
+            .lock()
+            .cspace
+            .mint(Capability { resource: CapabilityResource::Channel(server_end), rights });
+
+        fn send(task: &Task, cptr: CapabilityPtr, flags: ChannelWriteFlags) -> Result<(), SyscallError> {
+            send_message(task, &mut GeneralRegisters { a1: cptr.value(), a4: flags.value(), ..Default::default() })
+        }
+
+        fn read(task: &Task, cptr: CapabilityPtr) -> Result<(), SyscallError> {
+            read_message(task, &mut GeneralRegisters { a1: cptr.value(), ..Default::default() })
+        }
+
+        // Plain messages don't lend anything, so a server waiting for its next
+        // request doesn't boost its clients
+        send(&client, client_cptr, ChannelWriteFlags::NONE).unwrap();
+        assert_eq!(server.priority.effective(), DEFAULT_PRIORITY);
+        read(&server, server_cptr).unwrap();
+        send(&server, server_cptr, ChannelWriteFlags::NONE).unwrap();
+        read(&client, client_cptr).unwrap();
+        assert_eq!(client.priority.effective(), 3);
+
+        // A request lends the client's priority to the server for as long as
+        // it's waiting on the reply
+        send(&client, client_cptr, ChannelWriteFlags::EXPECTS_REPLY).unwrap();
+        assert_eq!(server.priority.effective(), 3);
+        read(&server, server_cptr).unwrap();
+        send(&server, server_cptr, ChannelWriteFlags::NONE).unwrap();
+        assert_eq!(server.priority.effective(), 3);
+
+        // Reading the reply gives the server back its own priority
+        read(&client, client_cptr).unwrap();
+        assert_eq!(server.priority.effective(), DEFAULT_PRIORITY);
+        assert_eq!(server.priority.base(), DEFAULT_PRIORITY);
+
+        for tid in [client_tid, server_tid] {
+            core::mem::forget(TASKS.remove(tid));
+        }
+        core::mem::forget(client);
+        core::mem::forget(server);
+    }
+
+    fn shared_page(
+        manager: &mut UserspaceMemoryManager,
+        cspace: &mut CapabilitySpace,
+        contents: &[u8],
+    ) -> CapabilityPtr {
+        let (range, region) = manager.alloc_shared_region(
+            None,
+            RegionDescription {
+                size: PageSize::Kilopage,
+                count: 1,
+                contiguous: false,
+                flags: Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE,
+                fill: FillOption::Zeroed,
+                kind: AddressRegionKind::UserSharedMemory,
+            },
+        );
+
+        let phys = manager.resolve(range.start).unwrap();
+        unsafe { core::slice::from_raw_parts_mut(phys2virt(phys).as_mut_ptr(), contents.len()) }
+            .copy_from_slice(contents);
+
+        cspace.mint(Capability {
+            resource: CapabilityResource::SharedMemory(region, range, AddressRegionKind::UserSharedMemory),
+            rights: CapabilityRights::READ | CapabilityRights::WRITE | CapabilityRights::GRANT,
+        })
+    }
+
+    fn segment(cptr: CapabilityPtr, offset: usize, len: usize) -> Segment {
+        Segment { capability: librust::capabilities::Capability { cptr, rights: CapabilityRights::READ }, offset, len }
+    }
+
+    #[test]
+    fn segmented_message_is_reassembled() {
+        let mut sender_memory = UserspaceMemoryManager::new();
+        let mut sender_cspace = CapabilitySpace::new();
+        let first = shared_page(&mut sender_memory, &mut sender_cspace, b"..hello, ..");
+        let second = shared_page(&mut sender_memory, &mut sender_cspace, b"world!");
+
+        // Segments have to lie within their memory
+        assert_eq!(
+            resolve_segments(&sender_cspace, &[segment(first, 4000, 97)]).unwrap_err(),
+            SyscallError::InvalidArgument(2)
+        );
+        assert_eq!(
+            resolve_segments(&sender_cspace, &[segment(first, usize::MAX, 2)]).unwrap_err(),
+            SyscallError::InvalidArgument(2)
+        );
+
+        let (caps, segments) =
+            resolve_segments(&sender_cspace, &[segment(first, 2, 7), segment(second, 0, 6)]).unwrap();
+        assert_eq!(segments, [2..9, 0..6]);
+
+        let (sender, receiver) = UserspaceChannel::new(1);
+        sender.sender.try_send(ChannelMessage { data: [0; 7], caps, segments }).unwrap();
+        let message = receiver.receiver.try_recv().unwrap().unwrap();
+
+        let mut receiver_memory = UserspaceMemoryManager::new();
+        let mut receiver_cspace = CapabilitySpace::new();
+        let mut payload = Vec::new();
+        for (cap, segment) in message.caps.into_iter().zip(message.segments) {
+            let (region, kind) = match cap.resource {
+                CapabilityResource::SharedMemory(region, _, kind) => (region, kind),
+                resource => panic!("unexpected resource: {resource:?}"),
+            };
+
+            let (_, description) = map_shared_memory(
+                &mut receiver_memory,
+                &mut receiver_cspace,
+                region,
+                kind,
+                CapabilityRights::READ,
+                Some(segment),
+            );
+
+            // The receiver is only told about the bytes in the segment, which
+            // are the sender's memory and not a copy
+            match description {
+                librust::capabilities::CapabilityDescription::Memory { ptr, len, .. } => {
+                    let phys = receiver_memory.resolve(VirtualAddress::from_ptr(ptr)).unwrap();
+                    payload.extend_from_slice(unsafe { core::slice::from_raw_parts(phys2virt(phys).as_ptr(), len) });
+                }
+                description => panic!("unexpected description: {description:?}"),
+            }
+        }
+
+        assert_eq!(payload, b"hello, world!");
+
+        core::mem::forget(sender_memory);
+        core::mem::forget(receiver_memory);
+    }
+
+    #[test]
+    fn waiting_on_several_channels() {
+        let (first_sender, first) = UserspaceChannel::new(4);
+        let (second_sender, second) = UserspaceChannel::new(4);
+        let channels = [first, second];
+        let task = Arc::new(Task::idle());
+        let waiter = ChannelWaiter { task: &task, channels: &channels };
+
+        waiter.register();
+        task.mutable_state.lock().state = TaskState::Blocked;
+        assert_eq!(waiter.ready(), None);
+
+        // A message on the second channel wakes the waiter, which finds it
+        second_sender.sender.try_send(message(2)).unwrap();
+        assert!(matches!(task.mutable_state.lock().state, TaskState::Ready));
+        assert_eq!(waiter.ready(), Some(1));
+
+        // Nothing is left behind in the other waitqueue
+        waiter.unregister();
+        assert!(!channels[0].receiver.waitqueue.remove(task.tid));
+        assert!(!channels[1].receiver.waitqueue.remove(task.tid));
+
+        // The lowest index wins when several are ready, and waiting doesn't
+        // receive anything
+        first_sender.sender.try_send(message(1)).unwrap();
+        assert_eq!(wait_for_message(&task, &channels, None), Some(0));
+        assert_eq!(channels[1].receiver.try_recv().unwrap().unwrap().data[0], 2);
+        assert_eq!(wait_for_message(&task, &channels, None), Some(0));
+        assert_eq!(channels[0].receiver.try_recv().unwrap().unwrap().data[0], 1);
+
+        // An already passed deadline times out without blocking
+        assert_eq!(wait_for_message(&task, &channels, Some(1)), None);
+        assert!(matches!(task.mutable_state.lock().state, TaskState::Ready));
+
+        core::mem::forget(task);
+    }
+}
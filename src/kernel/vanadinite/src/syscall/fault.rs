@@ -0,0 +1,269 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Userspace fault handlers
+//!
+//! A task can register a handler to be run instead of being killed when it
+//! faults. The interrupted state is pushed onto the task's stack as a
+//! [`FaultInfo`], which the handler passes back to resume, so only the floating
+//! point registers need to be kept by the kernel in the meantime.
+
+use crate::{
+    mem::{
+        manager::UserspaceMemoryManager,
+        paging::VirtualAddress,
+        usercopy::{copy_from_user, copy_to_user, KError},
+    },
+    scheduler::fpu,
+    task::Task,
+    trap::{FloatingPointRegisters, GeneralRegisters, TrapFrame},
+};
+use librust::{error::SyscallError, task::FaultInfo};
+
+/// The fault handler a task has registered, if any
+#[derive(Debug, Default)]
+pub struct FaultHandler {
+    entry: Option<VirtualAddress>,
+    /// Set while the handler is running, holding the task's floating point
+    /// registers from when it faulted
+    handling: Option<Option<FloatingPointRegisters>>,
+}
+
+impl FaultHandler {
+    /// Run the handler for a fault with the given `scause` and `stval`,
+    /// pushing the faulting state onto the task's stack and pointing `frame` at
+    /// the handler. Returns `false` if the task should be killed instead, which
+    /// is the case if there's no handler, it's the handler itself which
+    /// faulted, or the stack can't fit the [`FaultInfo`].
+    pub fn deliver(
+        &mut self,
+        memory_manager: &mut UserspaceMemoryManager,
+        fpu_registers: &mut Option<FloatingPointRegisters>,
+        frame: &mut TrapFrame,
+        scause: usize,
+        stval: usize,
+    ) -> bool {
+        let entry = match (self.entry, &self.handling) {
+            (Some(entry), None) => entry,
+            _ => return false,
+        };
+
+        let info = FaultInfo {
+            cause: scause,
+            address: stval,
+            pc: frame.sepc,
+            // Safety: `GeneralRegisters` is `repr(C)` and holds `x1` through
+            // `x31` in order
+            registers: unsafe { core::mem::transmute::<GeneralRegisters, [usize; 31]>(frame.registers) },
+        };
+
+        // Keeping the stack aligned the way the calling convention expects
+        let at = VirtualAddress::new(frame.sp.wrapping_sub(core::mem::size_of::<FaultInfo>()) & !15);
+        // Safety: `FaultInfo` is `repr(C)` and made up of `usize`s only
+        let bytes = unsafe {
+            core::slice::from_raw_parts((&info as *const FaultInfo).cast::<u8>(), core::mem::size_of::<FaultInfo>())
+        };
+
+        if let Err(KError::InvalidAddress(addr, e)) = copy_to_user(memory_manager, at, bytes) {
+            log::debug!("No room for the fault info on the stack @ {:#p}: {:?}", addr, e);
+            return false;
+        }
+
+        // The state in hardware and the task's saved state are the same after
+        // this, so either can be restored later
+        fpu::save_outgoing(fpu_registers);
+        self.handling = Some(*fpu_registers);
+
+        frame.sepc = entry.as_usize();
+        frame.sp = at.as_usize();
+        frame.a0 = at.as_usize();
+        // Falling off the end of the handler faults again, killing the task
+        frame.ra = 0;
+
+        true
+    }
+
+    /// Leave the fault handler, restoring the state in the [`FaultInfo`] at
+    /// `info` into `registers` and `sepc`. `trap_handler` steps over the
+    /// `ecall` after a syscall returns, so `sepc` is left pointing at the
+    /// instruction before where the task resumes.
+    pub fn resume(
+        &mut self,
+        memory_manager: &UserspaceMemoryManager,
+        fpu_registers: &mut Option<FloatingPointRegisters>,
+        registers: &mut GeneralRegisters,
+        sepc: &mut usize,
+        info: VirtualAddress,
+    ) -> Result<(), SyscallError> {
+        if self.handling.is_none() {
+            return Err(SyscallError::InvalidOperation(0));
+        }
+
+        let mut bytes = [0; core::mem::size_of::<FaultInfo>()];
+        if let Err(KError::InvalidAddress(addr, e)) = copy_from_user(memory_manager, info, &mut bytes) {
+            log::debug!("Bad fault info @ {:#p}: {:?}", addr, e);
+            return Err(SyscallError::InvalidArgument(0));
+        }
+
+        // Safety: any bytes are a valid `FaultInfo`
+        let info = unsafe { core::ptr::read_unaligned(bytes.as_ptr().cast::<FaultInfo>()) };
+        if VirtualAddress::new(info.pc).is_kernel_region() {
+            return Err(SyscallError::InvalidArgument(0));
+        }
+
+        *registers = unsafe { core::mem::transmute::<[usize; 31], GeneralRegisters>(info.registers) };
+        *sepc = info.pc.wrapping_sub(4);
+
+        *fpu_registers = self.handling.take().flatten();
+        fpu::load_incoming(fpu_registers);
+
+        Ok(())
+    }
+}
+
+pub fn set_handler(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let entry = match frame.a1 {
+        0 => None,
+        entry if VirtualAddress::new(entry).is_kernel_region() => return Err(SyscallError::InvalidArgument(0)),
+        entry => Some(VirtualAddress::new(entry)),
+    };
+
+    task.mutable_state.lock().fault_handler.entry = entry;
+
+    Ok(())
+}
+
+pub fn resume(task: &Task, registers: &mut GeneralRegisters, sepc: &mut usize) -> Result<(), SyscallError> {
+    let state = &mut *task.mutable_state.lock();
+    let info = VirtualAddress::new(registers.a1);
+
+    state.fault_handler.resume(&state.memory_manager, &mut state.fpu_registers, registers, sepc, info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mem::{
+            manager::{AddressRegionKind, FillOption, RegionDescription},
+            paging::{flags::Flags, PageSize},
+        },
+        trap::Trap,
+        utils::Units,
+    };
+
+    fn stack(manager: &mut UserspaceMemoryManager) -> VirtualAddress {
+        manager
+            .alloc_region(
+                Some(VirtualAddress::new(0x10_0000)),
+                RegionDescription {
+                    size: PageSize::Kilopage,
+                    count: 1,
+                    contiguous: false,
+                    flags: Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE,
+                    fill: FillOption::Zeroed,
+                    kind: AddressRegionKind::Stack,
+                },
+            )
+            .end
+    }
+
+    fn read_info(manager: &UserspaceMemoryManager, at: usize) -> FaultInfo {
+        let mut bytes = [0; core::mem::size_of::<FaultInfo>()];
+        copy_from_user(manager, VirtualAddress::new(at), &mut bytes).unwrap();
+        unsafe { core::ptr::read_unaligned(bytes.as_ptr().cast()) }
+    }
+
+    #[test]
+    fn page_fault_is_handled_and_resumed() {
+        let mut manager = UserspaceMemoryManager::new();
+        let stack_top = stack(&mut manager);
+        let mut fpu_registers = None;
+
+        let mut frame = TrapFrame::default();
+        frame.sepc = 0x4000;
+        frame.sp = stack_top.as_usize() - 8;
+        frame.a5 = 55;
+        let faulted = frame;
+
+        // Tasks without a handler are killed
+        let mut handler = FaultHandler::default();
+        assert!(!handler.deliver(&mut manager, &mut fpu_registers, &mut frame, Trap::LoadPageFault as usize, 0xD000));
+
+        handler.entry = Some(VirtualAddress::new(0x8000));
+        assert!(handler.deliver(&mut manager, &mut fpu_registers, &mut frame, Trap::LoadPageFault as usize, 0xD000));
+        assert_eq!((frame.sepc, frame.a0, frame.ra), (0x8000, frame.sp, 0));
+        assert_eq!(frame.sp % 16, 0);
+        assert!(frame.sp + core::mem::size_of::<FaultInfo>() <= faulted.sp);
+
+        let mut info = read_info(&manager, frame.a0);
+        assert_eq!(
+            (info.kind(), info.address, info.pc),
+            (Some(librust::task::FaultKind::LoadPageFault), 0xD000, 0x4000)
+        );
+        assert_eq!((info.registers[1], info.registers[14]), (faulted.sp, 55));
+
+        // The handler faulting kills the task
+        let mut nested = frame;
+        assert!(!handler.deliver(&mut manager, &mut fpu_registers, &mut nested, Trap::StorePageFault as usize, 0));
+
+        // The handler skips the faulting instruction and changes a register
+        info.pc += 4;
+        info.registers[14] = 66;
+        let info_at = VirtualAddress::new(frame.a0);
+        let bytes = unsafe {
+            core::slice::from_raw_parts((&info as *const FaultInfo).cast::<u8>(), core::mem::size_of::<FaultInfo>())
+        };
+        copy_to_user(&mut manager, info_at, bytes).unwrap();
+
+        handler.resume(&manager, &mut fpu_registers, &mut frame.registers, &mut frame.sepc, info_at).unwrap();
+        assert_eq!(frame.sepc + 4, 0x4004);
+        assert_eq!((frame.sp, frame.a5, frame.ra), (faulted.sp, 66, faulted.ra));
+
+        // Only a task in its fault handler can resume, and the next fault is
+        // handled again
+        assert_eq!(
+            handler.resume(&manager, &mut fpu_registers, &mut frame.registers, &mut frame.sepc, info_at),
+            Err(SyscallError::InvalidOperation(0))
+        );
+        frame.sepc = 0x4004;
+        assert!(handler.deliver(&mut manager, &mut fpu_registers, &mut frame, Trap::IllegalInstruction as usize, 0));
+
+        core::mem::forget(manager);
+    }
+
+    #[test]
+    fn unusable_stack_kills() {
+        let mut manager = UserspaceMemoryManager::new();
+        let stack_top = stack(&mut manager);
+        let mut handler = FaultHandler { entry: Some(VirtualAddress::new(0x8000)), handling: None };
+        let mut fpu_registers = None;
+
+        // Overflowed into the page below the stack
+        let mut frame = TrapFrame::default();
+        frame.sp = stack_top.as_usize() - 4.kib() + 8;
+        assert!(!handler.deliver(&mut manager, &mut fpu_registers, &mut frame, Trap::StorePageFault as usize, 0));
+        assert_eq!(frame.sepc, 0);
+
+        // A resumed PC has to be in userspace
+        frame.sp = stack_top.as_usize();
+        assert!(handler.deliver(&mut manager, &mut fpu_registers, &mut frame, Trap::StorePageFault as usize, 0));
+        let info_at = VirtualAddress::new(frame.a0);
+        let mut info = read_info(&manager, info_at.as_usize());
+        info.pc = usize::MAX;
+        let bytes = unsafe {
+            core::slice::from_raw_parts((&info as *const FaultInfo).cast::<u8>(), core::mem::size_of::<FaultInfo>())
+        };
+        copy_to_user(&mut manager, info_at, bytes).unwrap();
+        assert_eq!(
+            handler.resume(&manager, &mut fpu_registers, &mut frame.registers, &mut frame.sepc, info_at),
+            Err(SyscallError::InvalidArgument(0))
+        );
+
+        core::mem::forget(manager);
+    }
+}
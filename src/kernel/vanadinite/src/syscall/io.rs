@@ -113,12 +113,14 @@ pub fn claim_device(task: &Task, regs: &mut GeneralRegisters) -> Result<(), Sysc
                             let sender = task_state.kernel_channel.sender.clone();
                             drop(task_state);
 
-                            sender
-                                .send(ChannelMessage {
-                                    data: Into::into(KernelMessage::InterruptOccurred(id)),
-                                    caps: Vec::new(),
-                                })
-                                .expect("handle kernel channel send failure");
+                            // Fails if the task has closed its kernel
+                            // channel, in which case it isn't interested
+                            // in the interrupt anymore
+                            let _ = sender.send(ChannelMessage {
+                                data: Into::into(KernelMessage::InterruptOccurred(id)),
+                                caps: Vec::new(),
+                                segments: Vec::new(),
+                            });
 
                             Ok(())
                         });
@@ -6,16 +6,18 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    capabilities::{Capability, CapabilityResource},
+    capabilities::{Capability, CapabilityResource, CapabilitySpace},
     mem::{
-        manager::{AddressRegion, AddressRegionKind, FillOption, RegionDescription},
+        manager::{AddressRegion, AddressRegionKind, FillOption, RegionDescription, UserspaceMemoryManager},
         paging::{flags::Flags, PageSize, VirtualAddress},
+        region::UniquePhysicalRegion,
         user::{RawUserSlice, ReadWrite, ValidatedUserSlice},
     },
     task::Task,
     trap::GeneralRegisters,
     utils::{self, Units},
 };
+use core::ops::Range;
 use librust::{
     capabilities::{CapabilityPtr, CapabilityRights},
     error::SyscallError,
@@ -23,75 +25,75 @@ use librust::{
 };
 
 pub fn allocate_shared_memory(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
-    let mut task = task.mutable_state.lock();
+    let task = &mut *task.mutable_state.lock();
 
-    let size = frame.a1;
-    let permissions = MemoryPermissions::new(frame.a2);
+    let (allocated_at, cptr) =
+        alloc_shared(&mut task.memory_manager, &mut task.cspace, frame.a1, MemoryPermissions::new(frame.a2))?;
 
-    if permissions & MemoryPermissions::WRITE && !(permissions & MemoryPermissions::READ) {
-        return Err(SyscallError::InvalidArgument(1));
-    }
+    frame.a1 = cptr.value();
+    frame.a2 = allocated_at.start.as_usize();
+    frame.a3 = allocated_at.end.as_usize() - allocated_at.start.as_usize();
 
-    let mut flags = Flags::VALID | Flags::USER;
+    Ok(())
+}
 
-    if permissions & MemoryPermissions::READ {
-        flags |= Flags::READ;
+/// Allocate `size` bytes of zeroed memory which can be shared with other tasks,
+/// returning where it was mapped along with a capability to it. The capability
+/// has the rights matching `permissions` plus `GRANT`, and sending it can only
+/// hand out a subset of those, so other tasks never get more access to the
+/// memory than the allocating task has.
+pub(super) fn alloc_shared(
+    memory_manager: &mut UserspaceMemoryManager,
+    cspace: &mut CapabilitySpace,
+    size: usize,
+    permissions: MemoryPermissions,
+) -> Result<(Range<VirtualAddress>, CapabilityPtr), SyscallError> {
+    // Memory capabilities are always readable, see `query_mem_cap`
+    if !(permissions & MemoryPermissions::READ) {
+        return Err(SyscallError::InvalidArgument(1));
+    } else if size == 0 {
+        return Err(SyscallError::InvalidArgument(0));
     }
 
+    let mut flags = Flags::VALID | Flags::USER | Flags::READ;
+    let mut rights = CapabilityRights::READ | CapabilityRights::GRANT;
+
     if permissions & MemoryPermissions::WRITE {
         flags |= Flags::WRITE;
+        rights |= CapabilityRights::WRITE;
     }
 
     if permissions & MemoryPermissions::EXECUTE {
         flags |= Flags::EXECUTE;
+        rights |= CapabilityRights::EXECUTE;
     }
 
     let page_size = if size >= 2.mib() { PageSize::Megapage } else { PageSize::Kilopage };
-
-    match size {
-        0 => Err(SyscallError::InvalidArgument(0)),
-        _ => {
-            let (allocated_at, region) = task.memory_manager.alloc_shared_region(
-                None,
-                RegionDescription {
-                    size: page_size,
-                    count: utils::round_up_to_next(size, page_size.to_byte_size()) / page_size.to_byte_size(),
-                    contiguous: false,
-                    flags,
-                    fill: FillOption::Zeroed,
-                    kind: AddressRegionKind::UserAllocated,
-                },
-            );
-
-            let rights = match (
-                permissions & MemoryPermissions::READ,
-                permissions & MemoryPermissions::WRITE,
-                permissions & MemoryPermissions::EXECUTE,
-            ) {
-                (true, true, true) => CapabilityRights::READ | CapabilityRights::WRITE | CapabilityRights::EXECUTE,
-                (true, true, false) => CapabilityRights::READ | CapabilityRights::WRITE,
-                (true, false, false) => CapabilityRights::READ,
-                (r, w, x) => unreachable!("read={r} write={w} execute={x}"),
-            };
-
-            let cptr = task.cspace.mint(Capability {
-                resource: CapabilityResource::SharedMemory(
-                    region,
-                    allocated_at.clone(),
-                    AddressRegionKind::UserSharedMemory,
-                ),
-                rights: rights | CapabilityRights::GRANT,
-            });
-
-            log::trace!("Allocated shared memory at {:#p} ({:?}) for user process", allocated_at.start, page_size);
-
-            frame.a1 = cptr.value();
-            frame.a2 = allocated_at.start.as_usize();
-            frame.a3 = allocated_at.end.as_usize() - allocated_at.start.as_usize();
-
-            Ok(())
-        }
+    let count = utils::round_up_to_next(size, page_size.to_byte_size()) / page_size.to_byte_size();
+    if !memory_manager.within_memory_limit(count * page_size.to_byte_size()) {
+        return Err(SyscallError::OutOfMemory);
     }
+
+    let (allocated_at, region) = memory_manager.alloc_shared_region(
+        None,
+        RegionDescription {
+            size: page_size,
+            count,
+            contiguous: false,
+            flags,
+            fill: FillOption::Zeroed,
+            kind: AddressRegionKind::UserAllocated,
+        },
+    );
+
+    let cptr = cspace.mint(Capability {
+        resource: CapabilityResource::SharedMemory(region, allocated_at.clone(), AddressRegionKind::UserSharedMemory),
+        rights,
+    });
+
+    log::trace!("Allocated shared memory at {:#p} ({:?}) for user process", allocated_at.start, page_size);
+
+    Ok((allocated_at, cptr))
 }
 
 pub fn allocate_virtual_memory(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
@@ -123,11 +125,16 @@ pub fn allocate_virtual_memory(task: &Task, frame: &mut GeneralRegisters) -> Res
     match size {
         0 => Err(SyscallError::InvalidArgument(0)),
         _ => {
+            let count = utils::round_up_to_next(size, page_size.to_byte_size()) / page_size.to_byte_size();
+            if !task.memory_manager.within_memory_limit(count * page_size.to_byte_size()) {
+                return Err(SyscallError::OutOfMemory);
+            }
+
             let allocated_at = task.memory_manager.alloc_region(
                 None,
                 RegionDescription {
                     size: page_size,
-                    count: utils::round_up_to_next(size, page_size.to_byte_size()) / page_size.to_byte_size(),
+                    count,
                     contiguous: false,
                     flags,
                     fill: FillOption::Zeroed,
@@ -162,37 +169,62 @@ pub fn deallocate_virtual_memory(task: &Task, frame: &mut GeneralRegisters) -> R
 pub fn allocate_device_addressable_memory(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
     let size = frame.a1;
     let options = DmaAllocationOptions::new(frame.a2);
+    let alignment = frame.a3;
     let page_size = PageSize::Kilopage;
     let mut task = task.mutable_state.lock();
 
-    match size {
-        0 => Err(SyscallError::InvalidArgument(0)),
-        _ => {
-            let allocated_at = task.memory_manager.alloc_region(
-                None,
-                RegionDescription {
-                    size: page_size,
-                    count: utils::round_up_to_next(size, page_size.to_byte_size()) / page_size.to_byte_size(),
-                    contiguous: true,
-                    flags: Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE,
-                    fill: if options & DmaAllocationOptions::ZERO {
-                        FillOption::Zeroed
-                    } else {
-                        FillOption::Unitialized
-                    },
-                    kind: AddressRegionKind::Dma,
-                },
-            );
+    if size == 0 {
+        return Err(SyscallError::InvalidArgument(0));
+    } else if alignment != 0 && (!alignment.is_power_of_two() || alignment < page_size.to_byte_size()) {
+        return Err(SyscallError::InvalidArgument(2));
+    }
 
-            let phys = task.memory_manager.resolve(allocated_at.start).unwrap();
+    let count = utils::round_up_to_next(size, page_size.to_byte_size()) / page_size.to_byte_size();
+    if !task.memory_manager.within_memory_limit(count * page_size.to_byte_size()) {
+        return Err(SyscallError::OutOfMemory);
+    }
 
-            log::debug!("Allocated DMA memory at {:#p} for user process", allocated_at.start);
+    let flags = Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE;
+    let fill = if options & DmaAllocationOptions::ZERO { FillOption::Zeroed } else { FillOption::Unitialized };
 
-            frame.a1 = phys.as_usize();
-            frame.a2 = allocated_at.start.as_usize();
-            Ok(())
+    let allocated_at = match alignment {
+        // Page aligned, which every allocation already is
+        0..=4096 => task.memory_manager.alloc_region(
+            None,
+            RegionDescription { size: page_size, count, contiguous: true, flags, fill, kind: AddressRegionKind::Dma },
+        ),
+        _ => {
+            let align_pages = alignment / page_size.to_byte_size();
+            let backing = UniquePhysicalRegion::try_alloc_contiguous_aligned(count, align_pages)
+                .ok_or(SyscallError::OutOfMemory)?;
+
+            task.memory_manager.apply_region(None, flags, fill, backing, AddressRegionKind::Dma)
         }
+    };
+
+    let phys = task.memory_manager.resolve(allocated_at.start).unwrap();
+
+    log::debug!("Allocated DMA memory at {:#p} for user process", allocated_at.start);
+
+    frame.a1 = phys.as_usize();
+    frame.a2 = allocated_at.start.as_usize();
+    Ok(())
+}
+
+/// Lower the current task's memory limit to `a1` bytes. A task can't raise
+/// its limit or remove it once it has one, otherwise the limit would be
+/// pointless.
+pub fn set_memory_limit(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let mut task = task.mutable_state.lock();
+    let limit = frame.a1;
+
+    match task.memory_manager.memory_limit() {
+        _ if limit == 0 => return Err(SyscallError::InvalidArgument(0)),
+        Some(current) if limit > current => return Err(SyscallError::InvalidOperation(0)),
+        _ => task.memory_manager.set_memory_limit(Some(limit)),
     }
+
+    Ok(())
 }
 
 pub fn query_mem_cap(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
@@ -216,13 +248,14 @@ pub fn query_mem_cap(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sy
 }
 
 pub fn query_mmio_cap(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
-    let task = task.mutable_state.lock();
+    let mut task = task.mutable_state.lock();
 
     let cptr = CapabilityPtr::new(frame.a1);
     let buffer_ptr = VirtualAddress::new(frame.a2);
     let buffer_len = frame.a3;
+    let buffer = RawUserSlice::<ReadWrite, usize>::new(buffer_ptr, buffer_len);
     let buffer: ValidatedUserSlice<ReadWrite, usize> =
-        match unsafe { RawUserSlice::new(buffer_ptr, buffer_len).validate(&task.memory_manager) } {
+        match unsafe { buffer.validate_writable(&mut task.memory_manager) } {
             Ok(slice) => slice,
             Err((_, e)) => {
                 log::debug!("Bad interrupt buffer @ {:#p}: {:?}", buffer_ptr, e);
@@ -247,3 +280,177 @@ pub fn query_mmio_cap(task: &Task, frame: &mut GeneralRegisters) -> Result<(), S
         _ => Err(SyscallError::InvalidArgument(0)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mem::{
+            user::InvalidUserPtr,
+            usercopy::{copy_from_user, copy_to_user, KError},
+        },
+        syscall::channel::{map_shared_memory, resolve_granted},
+    };
+    use librust::capabilities::CapabilityDescription;
+
+    #[test]
+    fn shared_read_only() {
+        let mut owner_memory = UserspaceMemoryManager::new();
+        let mut owner_cspace = CapabilitySpace::new();
+        let (range, cptr) =
+            alloc_shared(&mut owner_memory, &mut owner_cspace, 100, MemoryPermissions::READ_WRITE).unwrap();
+        assert_eq!(range.end.as_usize() - range.start.as_usize(), 4.kib());
+        copy_to_user(&mut owner_memory, range.start, b"shared").unwrap();
+
+        // Nothing can be granted beyond what the owner has
+        assert!(resolve_granted(&owner_cspace, cptr, CapabilityRights::READ | CapabilityRights::EXECUTE).is_err());
+
+        let cap = resolve_granted(&owner_cspace, cptr, CapabilityRights::READ).unwrap();
+        let CapabilityResource::SharedMemory(region, _, kind) = cap.resource else { panic!("not shared memory") };
+
+        let mut grantee_memory = UserspaceMemoryManager::new();
+        let mut grantee_cspace = CapabilitySpace::new();
+        let rights = CapabilityRights::READ | CapabilityRights::GRANT;
+        let (grantee_cptr, description) =
+            map_shared_memory(&mut grantee_memory, &mut grantee_cspace, region, kind, rights, None);
+        let CapabilityDescription::Memory { ptr, len, permissions } = description else { panic!("not memory") };
+        assert_eq!((len, permissions), (4.kib(), MemoryPermissions::READ));
+
+        // The grantee sees the owner's writes, but can't write itself
+        let at = VirtualAddress::from_ptr(ptr);
+        let mut buffer = [0; 6];
+        copy_from_user(&grantee_memory, at, &mut buffer).unwrap();
+        assert_eq!(&buffer, b"shared");
+        assert!(matches!(
+            copy_to_user(&mut grantee_memory, at, b"nope"),
+            Err(KError::InvalidAddress(addr, InvalidUserPtr::InvalidAccess)) if addr == at
+        ));
+
+        // Or pass write access on, even though it can pass on what it has
+        assert!(resolve_granted(&grantee_cspace, grantee_cptr, CapabilityRights::READ).is_ok());
+        assert!(
+            resolve_granted(&grantee_cspace, grantee_cptr, CapabilityRights::READ | CapabilityRights::WRITE).is_err()
+        );
+
+        core::mem::forget(owner_memory);
+        core::mem::forget(grantee_memory);
+    }
+
+    #[test]
+    fn shared_permissions() {
+        let mut memory = UserspaceMemoryManager::new();
+        let mut cspace = CapabilitySpace::new();
+
+        for permissions in [MemoryPermissions::new(0), MemoryPermissions::WRITE, MemoryPermissions::EXECUTE] {
+            assert_eq!(
+                alloc_shared(&mut memory, &mut cspace, 4.kib(), permissions).unwrap_err(),
+                SyscallError::InvalidArgument(1)
+            );
+        }
+        assert_eq!(
+            alloc_shared(&mut memory, &mut cspace, 0, MemoryPermissions::READ).unwrap_err(),
+            SyscallError::InvalidArgument(0)
+        );
+
+        let (_, cptr) =
+            alloc_shared(&mut memory, &mut cspace, 4.kib(), MemoryPermissions::READ | MemoryPermissions::EXECUTE)
+                .unwrap();
+        assert_eq!(
+            cspace.resolve(cptr).unwrap().rights,
+            CapabilityRights::READ | CapabilityRights::EXECUTE | CapabilityRights::GRANT
+        );
+
+        core::mem::forget(memory);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let limited = Task::idle();
+        let other = Task::idle();
+        let base = limited.mutable_state.lock().memory_manager.resident_memory();
+        limited.mutable_state.lock().memory_manager.set_memory_limit(Some(base + 16.kib()));
+
+        let alloc = |task: &Task, size: usize| {
+            let mut frame =
+                GeneralRegisters { a1: size, a2: MemoryPermissions::READ_WRITE.value(), ..Default::default() };
+            allocate_virtual_memory(task, &mut frame).map(|_| frame.a1)
+        };
+
+        let allocated = alloc(&limited, 12.kib()).unwrap();
+        assert_eq!(limited.mutable_state.lock().memory_manager.resident_memory(), base + 12.kib());
+
+        // Going over the limit fails without allocating anything, while other
+        // tasks can still allocate as much as they like
+        assert_eq!(alloc(&limited, 8.kib()), Err(SyscallError::OutOfMemory));
+        assert_eq!(limited.mutable_state.lock().memory_manager.resident_memory(), base + 12.kib());
+        alloc(&other, 8.kib()).unwrap();
+        alloc(&other, 2.mib()).unwrap();
+
+        {
+            let state = &mut *limited.mutable_state.lock();
+            let error = alloc_shared(&mut state.memory_manager, &mut state.cspace, 8.kib(), MemoryPermissions::READ);
+            assert_eq!(error.unwrap_err(), SyscallError::OutOfMemory);
+        }
+
+        // Freeing memory makes room for more
+        let mut frame = GeneralRegisters { a1: allocated, ..Default::default() };
+        deallocate_virtual_memory(&limited, &mut frame).unwrap();
+        alloc(&limited, 16.kib()).unwrap();
+
+        // The limit can only ever be lowered
+        let mut frame = GeneralRegisters { a1: base + 32.kib(), ..Default::default() };
+        assert_eq!(set_memory_limit(&limited, &mut frame), Err(SyscallError::InvalidOperation(0)));
+        frame.a1 = 0;
+        assert_eq!(set_memory_limit(&limited, &mut frame), Err(SyscallError::InvalidArgument(0)));
+        frame.a1 = 4.kib();
+        set_memory_limit(&limited, &mut frame).unwrap();
+        assert_eq!(alloc(&limited, 4.kib()), Err(SyscallError::OutOfMemory));
+
+        core::mem::forget(limited);
+        core::mem::forget(other);
+    }
+
+    #[test]
+    fn unmapped_shared_memory_stays_charged() {
+        let task = Task::idle();
+
+        {
+            let state = &mut *task.mutable_state.lock();
+            let base = state.memory_manager.resident_memory();
+            state.memory_manager.set_memory_limit(Some(base + 16.kib()));
+
+            // Unmapping shared memory doesn't free it while there's still a
+            // capability to it, so it can't be used to get around the limit
+            let cptrs: [CapabilityPtr; 4] = core::array::from_fn(|_| {
+                let (range, cptr) =
+                    alloc_shared(&mut state.memory_manager, &mut state.cspace, 4.kib(), MemoryPermissions::READ)
+                        .unwrap();
+                drop(state.memory_manager.dealloc_region(range.start));
+                cptr
+            });
+
+            assert_eq!(state.memory_manager.resident_memory(), base + 16.kib());
+            let error = alloc_shared(&mut state.memory_manager, &mut state.cspace, 4.kib(), MemoryPermissions::READ);
+            assert_eq!(error.unwrap_err(), SyscallError::OutOfMemory);
+
+            // Until the capability is gone too
+            drop(state.cspace.remove(cptrs[0]));
+            assert_eq!(state.memory_manager.resident_memory(), base + 12.kib());
+            alloc_shared(&mut state.memory_manager, &mut state.cspace, 4.kib(), MemoryPermissions::READ).unwrap();
+        }
+
+        core::mem::forget(task);
+    }
+
+    #[test]
+    fn unsatisfiable_alignment() {
+        let task = Task::idle();
+
+        // No physical memory is aligned this strictly, which is the same as
+        // running out of memory
+        let mut frame = GeneralRegisters { a1: 4.kib(), a3: 1 << 62, ..Default::default() };
+        assert_eq!(allocate_device_addressable_memory(&task, &mut frame), Err(SyscallError::OutOfMemory));
+
+        core::mem::forget(task);
+    }
+}
@@ -6,11 +6,35 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    io::ConsoleDevice,
-    mem::{paging::VirtualAddress, user::RawUserSlice},
+    boot::harts::BOOTED_HARTS,
+    capabilities::{Capability, CapabilityResource},
+    csr,
+    io::{
+        kernel_log::{KERNEL_LOG, KERNEL_LOG_SIZE},
+        ConsoleDevice,
+    },
+    mem::{
+        paging::VirtualAddress,
+        user::RawUserSlice,
+        usercopy::{copy_to_user, KError},
+    },
+    scheduler::{affinity, SCHEDULER, TASKS},
     task::Task,
+    trap::GeneralRegisters,
+    HART_ID, N_CPUS,
+};
+use core::{num::NonZeroUsize, sync::atomic::Ordering};
+use librust::{
+    capabilities::CapabilityPtr,
+    error::SyscallError,
+    syscalls::misc::{ResetKind, ResetReason},
+    task::{HartMask, Tid},
+};
+use sbi::{
+    probe_extension,
+    system_reset::{ResetReason as SbiResetReason, ResetType, EXTENSION_ID},
+    ExtensionAvailability,
 };
-use librust::error::SyscallError;
 
 pub fn print(task: &Task, start: VirtualAddress, len: usize) -> Result<(), SyscallError> {
     let user_slice = RawUserSlice::readable(start, len);
@@ -29,3 +53,224 @@ pub fn print(task: &Task, start: VirtualAddress, len: usize) -> Result<(), Sysca
 
     Ok(())
 }
+
+pub fn read_kernel_log(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let mut task = task.mutable_state.lock();
+    match task.cspace.resolve(CapabilityPtr::new(frame.a1)) {
+        Some(Capability { resource: CapabilityResource::KernelLog, .. }) => {}
+        _ => return Err(SyscallError::InsufficientRights(0)),
+    }
+
+    // The ring never holds more than this, so there's no point in a bigger
+    // intermediate buffer
+    let mut buffer = alloc::vec![0; frame.a3.min(KERNEL_LOG_SIZE)];
+    let len = KERNEL_LOG.lock().read(&mut buffer);
+
+    if let Err(KError::InvalidAddress(addr, e)) =
+        copy_to_user(&mut task.memory_manager, VirtualAddress::new(frame.a2), &buffer[..len])
+    {
+        log::debug!("Bad kernel log buffer @ {:#p}: {:?}", addr, e);
+        return Err(SyscallError::InvalidArgument(1));
+    }
+
+    frame.a1 = len;
+
+    Ok(())
+}
+
+pub fn set_affinity(task: &Task, mask: HartMask) -> Result<(), SyscallError> {
+    if !affinity::is_satisfiable(mask, BOOTED_HARTS.live()) {
+        return Err(SyscallError::InvalidArgument(0));
+    }
+
+    task.mutable_state.lock().affinity = mask;
+
+    // The task is moved the next time it's scheduled out, so do that now
+    // instead of letting it run out the rest of its time slice here
+    if !mask.contains(HART_ID.get()) {
+        SCHEDULER.schedule();
+    }
+
+    Ok(())
+}
+
+pub fn set_task_name(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let state = task.mutable_state.lock();
+    let name = match unsafe {
+        RawUserSlice::readable(VirtualAddress::new(frame.a1), frame.a2).validate(&state.memory_manager)
+    } {
+        Ok(slice) => slice,
+        Err((_, e)) => {
+            log::debug!("Bad task name @ {:#x}: {:?}", frame.a1, e);
+            return Err(SyscallError::InvalidArgument(0));
+        }
+    };
+
+    let name = name.guarded();
+    let name = core::str::from_utf8(&name).map_err(|_| SyscallError::InvalidArgument(0))?;
+    log::debug!("Task {} [{}] renamed itself to {:?}", task.name, task.tid, name);
+    task.name.set(name);
+
+    Ok(())
+}
+
+pub fn get_task_name(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let named =
+        NonZeroUsize::new(frame.a1).and_then(|tid| TASKS.get(Tid::new(tid))).ok_or(SyscallError::InvalidArgument(0))?;
+    let name = named.name.get();
+
+    let len = name.len().min(frame.a3);
+    let mut state = task.mutable_state.lock();
+    if let Err(KError::InvalidAddress(addr, e)) =
+        copy_to_user(&mut state.memory_manager, VirtualAddress::new(frame.a2), &name.as_bytes()[..len])
+    {
+        log::debug!("Bad task name buffer @ {:#p}: {:?}", addr, e);
+        return Err(SyscallError::InvalidArgument(1));
+    }
+
+    frame.a1 = len;
+
+    Ok(())
+}
+
+pub fn cpu_time(frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let task =
+        NonZeroUsize::new(frame.a1).and_then(|tid| TASKS.get(Tid::new(tid))).ok_or(SyscallError::InvalidArgument(0))?;
+    frame.a1 = task.cpu_time.get(csr::time::read()) as usize;
+
+    Ok(())
+}
+
+pub fn hart_info(frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    frame.a1 = BOOTED_HARTS.live().iter().count();
+    frame.a2 = HART_ID.get();
+    frame.a3 = N_CPUS.load(Ordering::Relaxed);
+
+    Ok(())
+}
+
+pub fn system_reset(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    system_reset_with(task, frame, |kind, reason| {
+        if !matches!(probe_extension(EXTENSION_ID), ExtensionAvailability::Available(_)) {
+            return;
+        }
+
+        if let Err(e) = sbi::system_reset::system_reset(kind, reason) {
+            log::error!("SBI system reset failed: {:?}", e);
+        }
+    })
+}
+
+/// Check that the task is allowed to reset the system before calling `reset`,
+/// which only returns if the reset couldn't be done
+fn system_reset_with(
+    task: &Task,
+    frame: &GeneralRegisters,
+    reset: impl FnOnce(ResetType, SbiResetReason),
+) -> Result<(), SyscallError> {
+    match task.mutable_state.lock().cspace.resolve(CapabilityPtr::new(frame.a1)) {
+        Some(Capability { resource: CapabilityResource::SystemReset, .. }) => {}
+        _ => return Err(SyscallError::InsufficientRights(0)),
+    }
+
+    let kind = match ResetKind::from_usize(frame.a2).ok_or(SyscallError::InvalidArgument(1))? {
+        ResetKind::Shutdown => ResetType::Shutdown,
+        ResetKind::ColdReboot => ResetType::ColdReboot,
+        ResetKind::WarmReboot => ResetType::WarmReboot,
+    };
+    let reason = match ResetReason::from_usize(frame.a3).ok_or(SyscallError::InvalidArgument(2))? {
+        ResetReason::NoReason => SbiResetReason::NoReason,
+        ResetReason::SystemFailure => SbiResetReason::SystemFailure,
+    };
+
+    log::info!("Task {} [{}] requested a system reset ({:?})", task.name, task.tid, kind);
+    reset(kind, reason);
+
+    Err(SyscallError::InvalidOperation(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::FDT;
+    use librust::capabilities::CapabilityRights;
+
+    fn never_reset(_: ResetType, _: SbiResetReason) {
+        panic!("reset without the capability");
+    }
+
+    #[test]
+    fn hart_info_matches_devicetree() {
+        let fdt = unsafe { fdt::Fdt::from_ptr(FDT.load(Ordering::Acquire)) }.unwrap();
+
+        let mut frame = GeneralRegisters::default();
+        hart_info(&mut frame).unwrap();
+
+        // Only the boot hart is started for the tests
+        assert!((1..=fdt.cpus().count()).contains(&frame.a1));
+        assert_eq!(frame.a1, BOOTED_HARTS.live().iter().count());
+        assert_eq!(frame.a2, HART_ID.get());
+
+        // Every hart in the devicetree has an ID below the bound
+        let highest = fdt.cpus().map(|cpu| cpu.ids().first()).max().unwrap();
+        assert_eq!(frame.a3, highest + 1);
+        assert!(frame.a2 < frame.a3);
+    }
+
+    #[test]
+    fn system_reset_requires_capability() {
+        let task = Task::idle();
+        let cptr = task
+            .mutable_state
+            .lock()
+            .cspace
+            .mint(Capability { resource: CapabilityResource::SystemReset, rights: CapabilityRights::READ });
+
+        let mut requested = None;
+        let mut frame = GeneralRegisters { a1: cptr.value(), a2: ResetKind::ColdReboot as usize, ..Default::default() };
+        assert_eq!(
+            system_reset_with(&task, &frame, |kind, reason| requested = Some((kind, reason))),
+            Err(SyscallError::InvalidOperation(0))
+        );
+        assert!(matches!(requested, Some((ResetType::ColdReboot, SbiResetReason::NoReason))));
+
+        // Unauthorized callers never reach the firmware
+        frame.a1 = cptr.value() + 1;
+        assert_eq!(system_reset_with(&task, &frame, never_reset), Err(SyscallError::InsufficientRights(0)));
+        frame.a1 = librust::syscalls::channel::KERNEL_CHANNEL.value();
+        assert_eq!(system_reset_with(&task, &frame, never_reset), Err(SyscallError::InsufficientRights(0)));
+
+        frame.a1 = cptr.value();
+        frame.a3 = 7;
+        assert_eq!(system_reset_with(&task, &frame, never_reset), Err(SyscallError::InvalidArgument(2)));
+
+        core::mem::forget(task);
+    }
+
+    #[test]
+    fn kernel_log_requires_capability() {
+        let task = Task::idle();
+        let (log_cptr, reset_cptr) = {
+            let mut state = task.mutable_state.lock();
+            let log = state
+                .cspace
+                .mint(Capability { resource: CapabilityResource::KernelLog, rights: CapabilityRights::READ });
+            let reset = state
+                .cspace
+                .mint(Capability { resource: CapabilityResource::SystemReset, rights: CapabilityRights::READ });
+            (log, reset)
+        };
+
+        let mut frame = GeneralRegisters { a1: log_cptr.value(), ..Default::default() };
+        assert_eq!(read_kernel_log(&task, &mut frame), Ok(()));
+        assert_eq!(frame.a1, 0);
+
+        // Other capabilities don't grant access, and neither does having none
+        for cptr in [reset_cptr, CapabilityPtr::new(log_cptr.value() + 1)] {
+            let mut frame = GeneralRegisters { a1: cptr.value(), ..Default::default() };
+            assert_eq!(read_kernel_log(&task, &mut frame), Err(SyscallError::InsufficientRights(0)));
+        }
+
+        core::mem::forget(task);
+    }
+}
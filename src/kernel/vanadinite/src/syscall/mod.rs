@@ -7,17 +7,22 @@
 
 pub mod capabilities;
 pub mod channel;
+pub mod fault;
 pub mod io;
 pub mod mem;
 pub mod misc;
+pub mod shm;
 pub mod vmspace;
 
 use crate::{
+    csr,
     mem::paging::VirtualAddress,
-    scheduler::{CURRENT_TASK, SCHEDULER},
+    scheduler::{timers::TIMERS, CURRENT_TASK, SCHEDULER},
     task::TaskState,
     trap::TrapFrame,
+    TIMER_FREQ,
 };
+use core::sync::atomic::Ordering;
 use librust::{error::SyscallError, syscalls::Syscall};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -30,7 +35,7 @@ pub fn handle(frame: &mut TrapFrame) {
     let task = CURRENT_TASK.get();
     let task = &*task;
 
-    let mut regs = &mut frame.registers;
+    let TrapFrame { sepc, registers: regs } = frame;
 
     let syscall = match Syscall::from_usize(regs.a0) {
         Some(syscall) => syscall,
@@ -59,16 +64,44 @@ pub fn handle(frame: &mut TrapFrame) {
         Syscall::CreateVmspace => vmspace::create_vmspace(task, regs),
         Syscall::AllocVmspaceObject => vmspace::alloc_vmspace_object(task, regs),
         Syscall::SpawnVmspace => vmspace::spawn_vmspace(task, regs),
+        Syscall::CloneVmspace => vmspace::clone_vmspace(task, regs),
+        Syscall::MapVmspaceMmio => vmspace::map_vmspace_mmio(task, regs),
         Syscall::QueryMemoryCapability => mem::query_mem_cap(task, regs),
         Syscall::QueryMmioCapability => mem::query_mmio_cap(task, regs),
         Syscall::ReadChannel => channel::read_message(task, regs),
         Syscall::WriteChannel => channel::send_message(task, regs),
+        Syscall::WaitAnyChannel => channel::wait_any(task, regs),
         Syscall::MintCapability => todo!(),
         Syscall::RevokeCapability => todo!(),
         Syscall::EnableNotifications => Ok(task.mutable_state.lock().subscribes_to_events = true),
         Syscall::DeleteCapability => capabilities::delete(task, regs),
+        Syscall::EnumerateCapabilities => capabilities::enumerate(task, regs),
         Syscall::AllocateSharedMemory => mem::allocate_shared_memory(task, regs),
+        Syscall::PublishSharedMemory => shm::publish(task, regs),
+        Syscall::UnpublishSharedMemory => shm::unpublish(task, regs),
+        Syscall::LookupSharedMemory => shm::lookup(task, regs),
         Syscall::DeallocateVirtualMemory => mem::deallocate_virtual_memory(task, regs),
+        Syscall::YieldNow => Ok(SCHEDULER.yield_now()),
+        Syscall::SetAffinity => misc::set_affinity(task, librust::task::HartMask::new(regs.a1 as u64)),
+        Syscall::ReadKernelLog => misc::read_kernel_log(task, regs),
+        Syscall::SetTaskName => misc::set_task_name(task, regs),
+        Syscall::GetTaskName => misc::get_task_name(task, regs),
+        Syscall::GetCpuTime => misc::cpu_time(regs),
+        Syscall::SystemReset => misc::system_reset(task, regs),
+        Syscall::SetMemoryLimit => mem::set_memory_limit(task, regs),
+        Syscall::GetHartInfo => misc::hart_info(regs),
+        Syscall::SetFaultHandler => fault::set_handler(task, regs),
+        Syscall::ResumeFromFault => match fault::resume(task, regs, sepc) {
+            // The restored registers include `a0`, so there's no return value
+            Ok(()) => return,
+            Err(e) => Err(e),
+        },
+        Syscall::SetTimer => Ok(TIMERS.set(task.tid, regs.a1 as u64)),
+        Syscall::ReadTime => {
+            regs.a1 = csr::time::read() as usize;
+            regs.a2 = TIMER_FREQ.load(Ordering::Relaxed) as usize;
+            Ok(())
+        }
     };
 
     match res {
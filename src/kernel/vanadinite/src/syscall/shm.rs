@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{
+    capabilities::{Capability, CapabilityResource},
+    mem::{
+        manager::{AddressRegionKind, UserspaceMemoryManager},
+        paging::{flags::Flags, VirtualAddress},
+        region::SharedPhysicalRegion,
+        user::RawUserSlice,
+    },
+    sync::SpinMutex,
+    task::Task,
+    trap::GeneralRegisters,
+    utils::SameHartDeadlockDetection,
+};
+use alloc::{collections::BTreeMap, string::String};
+use core::ops::Range;
+use librust::{
+    capabilities::{CapabilityPtr, CapabilityRights},
+    error::SyscallError,
+    syscalls::mem::MAX_SHARED_MEMORY_NAME_LENGTH,
+    task::Tid,
+};
+
+pub static SHARED_MEMORY_REGISTRY: SpinMutex<SharedMemoryRegistry, SameHartDeadlockDetection> =
+    SpinMutex::new(SharedMemoryRegistry::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    InvalidName,
+    NameTaken,
+    NotFound,
+    NotPublisher,
+}
+
+impl From<RegistryError> for SyscallError {
+    fn from(error: RegistryError) -> Self {
+        match error {
+            RegistryError::InvalidName | RegistryError::NotFound => SyscallError::InvalidArgument(0),
+            RegistryError::NameTaken => SyscallError::InvalidOperation(0),
+            RegistryError::NotPublisher => SyscallError::InsufficientRights(0),
+        }
+    }
+}
+
+/// A shared memory region published under a name
+#[derive(Debug, Clone)]
+pub struct PublishedRegion {
+    pub publisher: Tid,
+    pub region: SharedPhysicalRegion,
+    pub kind: AddressRegionKind,
+    pub rights: CapabilityRights,
+}
+
+/// A mapping of names to shared memory regions, allowing tasks which don't
+/// share a channel to rendezvous on a region
+pub struct SharedMemoryRegistry {
+    names: BTreeMap<String, PublishedRegion>,
+}
+
+impl SharedMemoryRegistry {
+    pub const fn new() -> Self {
+        Self { names: BTreeMap::new() }
+    }
+
+    pub fn publish(&mut self, name: &str, region: PublishedRegion) -> Result<(), RegistryError> {
+        if name.is_empty() || name.len() > MAX_SHARED_MEMORY_NAME_LENGTH {
+            return Err(RegistryError::InvalidName);
+        }
+
+        if self.names.contains_key(name) {
+            return Err(RegistryError::NameTaken);
+        }
+
+        self.names.insert(String::from(name), region);
+        Ok(())
+    }
+
+    /// Remove `name` from the registry if it was published by `tid`
+    pub fn unpublish(&mut self, name: &str, tid: Tid) -> Result<PublishedRegion, RegistryError> {
+        match self.names.get(name) {
+            Some(published) if published.publisher == tid => Ok(self.names.remove(name).unwrap()),
+            Some(_) => Err(RegistryError::NotPublisher),
+            None => Err(RegistryError::NotFound),
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<&PublishedRegion, RegistryError> {
+        self.names.get(name).ok_or(RegistryError::NotFound)
+    }
+}
+
+/// Map a published region into the given address space with the rights it was
+/// published with
+fn map_published(manager: &mut UserspaceMemoryManager, published: &PublishedRegion) -> Range<VirtualAddress> {
+    let mut flags = Flags::VALID | Flags::USER;
+
+    if published.rights & CapabilityRights::READ {
+        flags |= Flags::READ;
+    }
+
+    if published.rights & CapabilityRights::WRITE {
+        flags |= Flags::WRITE;
+    }
+
+    if published.rights & CapabilityRights::EXECUTE {
+        flags |= Flags::EXECUTE;
+    }
+
+    manager.apply_shared_region(None, flags, published.region.clone(), published.kind)
+}
+
+fn read_name(manager: &UserspaceMemoryManager, address: VirtualAddress, len: usize) -> Result<String, SyscallError> {
+    if len == 0 || len > MAX_SHARED_MEMORY_NAME_LENGTH {
+        return Err(SyscallError::InvalidArgument(0));
+    }
+
+    let user_slice = RawUserSlice::readable(address, len);
+    let user_slice = match unsafe { user_slice.validate(manager) } {
+        Ok(slice) => slice,
+        Err((_, e)) => {
+            log::error!("Bad memory from process: {:?}", e);
+            return Err(SyscallError::InvalidArgument(0));
+        }
+    };
+
+    let slice = user_slice.guarded();
+    match core::str::from_utf8(&slice) {
+        Ok(name) => Ok(String::from(name)),
+        Err(_) => {
+            log::error!("Invalid UTF-8 in shared memory name from process");
+            Err(SyscallError::InvalidArgument(0))
+        }
+    }
+}
+
+pub fn publish(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let task_state = task.mutable_state.lock();
+    let name = read_name(&task_state.memory_manager, VirtualAddress::new(frame.a1), frame.a2)?;
+    let cptr = CapabilityPtr::new(frame.a3);
+
+    let published = match task_state.cspace.resolve(cptr) {
+        Some(Capability { resource: CapabilityResource::SharedMemory(region, _, kind), rights }) => {
+            if !(*rights & CapabilityRights::GRANT) {
+                return Err(SyscallError::InsufficientRights(1));
+            }
+
+            PublishedRegion { publisher: task.tid, region: region.clone(), kind: *kind, rights: *rights }
+        }
+        _ => return Err(SyscallError::InvalidArgument(1)),
+    };
+
+    log::debug!("[{}:{}] Publishing shared memory as {:?}", task.name, task.tid, name);
+    SHARED_MEMORY_REGISTRY.lock().publish(&name, published)?;
+
+    Ok(())
+}
+
+pub fn unpublish(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let task_state = task.mutable_state.lock();
+    let name = read_name(&task_state.memory_manager, VirtualAddress::new(frame.a1), frame.a2)?;
+
+    SHARED_MEMORY_REGISTRY.lock().unpublish(&name, task.tid)?;
+
+    Ok(())
+}
+
+pub fn lookup(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let mut task_state = task.mutable_state.lock();
+    let name = read_name(&task_state.memory_manager, VirtualAddress::new(frame.a1), frame.a2)?;
+    let published = SHARED_MEMORY_REGISTRY.lock().lookup(&name)?.clone();
+
+    let addr = map_published(&mut task_state.memory_manager, &published);
+    let cptr = task_state.cspace.mint(Capability {
+        resource: CapabilityResource::SharedMemory(published.region, addr.clone(), published.kind),
+        rights: published.rights,
+    });
+
+    frame.a1 = cptr.value();
+    frame.a2 = addr.start.as_usize();
+    frame.a3 = addr.end.as_usize() - addr.start.as_usize();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::{
+        manager::{FillOption, RegionDescription},
+        paging::PageSize,
+        phys2virt,
+    };
+    use core::num::NonZeroUsize;
+
+    fn tid(n: usize) -> Tid {
+        Tid::new(NonZeroUsize::new(n).unwrap())
+    }
+
+    fn bytes(manager: &UserspaceMemoryManager, at: VirtualAddress) -> &'static mut [u8] {
+        let phys = manager.resolve(at).unwrap();
+        unsafe { core::slice::from_raw_parts_mut(phys2virt(phys).as_mut_ptr(), 16) }
+    }
+
+    #[test]
+    fn publish_and_lookup() {
+        let mut registry = SharedMemoryRegistry::new();
+        let mut task_a = UserspaceMemoryManager::new();
+        let mut task_b = UserspaceMemoryManager::new();
+
+        let (a_range, region) = task_a.alloc_shared_region(
+            None,
+            RegionDescription {
+                size: PageSize::Kilopage,
+                count: 1,
+                contiguous: false,
+                flags: Flags::VALID | Flags::USER | Flags::READ | Flags::WRITE,
+                fill: FillOption::Zeroed,
+                kind: AddressRegionKind::UserSharedMemory,
+            },
+        );
+        bytes(&task_a, a_range.start)[..5].copy_from_slice(b"hello");
+
+        let published = PublishedRegion {
+            publisher: tid(1),
+            region,
+            kind: AddressRegionKind::UserSharedMemory,
+            rights: CapabilityRights::READ | CapabilityRights::GRANT,
+        };
+        registry.publish("greeting", published.clone()).unwrap();
+
+        // Task B finds the region by name and sees task A's data
+        let b_range = map_published(&mut task_b, registry.lookup("greeting").unwrap());
+        assert_eq!(&bytes(&task_b, b_range.start)[..5], b"hello");
+        assert!(!(task_b.page_flags(b_range.start).unwrap() & Flags::WRITE));
+
+        // Names are unique and bounded
+        assert_eq!(registry.publish("greeting", published.clone()).unwrap_err(), RegistryError::NameTaken);
+        assert_eq!(registry.publish("", published.clone()).unwrap_err(), RegistryError::InvalidName);
+        let long_name = "a".repeat(MAX_SHARED_MEMORY_NAME_LENGTH + 1);
+        assert_eq!(registry.publish(&long_name, published).unwrap_err(), RegistryError::InvalidName);
+
+        // Only the publisher can unpublish
+        assert_eq!(registry.unpublish("greeting", tid(2)).unwrap_err(), RegistryError::NotPublisher);
+        registry.unpublish("greeting", tid(1)).unwrap();
+        assert_eq!(registry.lookup("greeting").unwrap_err(), RegistryError::NotFound);
+
+        // Existing mappings outlive the name
+        assert_eq!(&bytes(&task_b, b_range.start)[..5], b"hello");
+
+        core::mem::forget(task_a);
+        core::mem::forget(task_b);
+    }
+}
@@ -12,22 +12,30 @@ use crate::{
     mem::{
         alloc_kernel_stack,
         manager::{AddressRegionKind, FillOption, RegionDescription, UserspaceMemoryManager},
-        paging::{flags::Flags, PageSize, VirtualAddress},
+        paging::{flags::Flags, PageSize, PhysicalAddress, VirtualAddress},
         user::RawUserSlice,
     },
-    scheduler::{return_to_usermode, SCHEDULER},
+    platform::FDT,
+    scheduler::{priority::Priority, return_to_usermode, SCHEDULER},
     sync::SpinMutex,
-    syscall::channel::UserspaceChannel,
-    task::{Context, MutableState, Task, TaskState},
+    syscall::{
+        channel::{UserspaceChannel, DEFAULT_CHANNEL_CAPACITY},
+        fault::FaultHandler,
+    },
+    task::{Context, CpuTime, MutableState, Task, TaskName, TaskState},
     trap::{GeneralRegisters, TrapFrame},
     utils::{self, Units},
 };
 use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use librust::{
-    capabilities::CapabilityRights,
+    capabilities::{CapabilityPtr, CapabilityRights},
     error::SyscallError,
     syscalls::{
-        channel::{KERNEL_CHANNEL, PARENT_CHANNEL},
+        channel::{EXIT_CHANNEL, KERNEL_CHANNEL, PARENT_CHANNEL},
         mem::MemoryPermissions,
         vmspace::VmspaceObjectId,
     },
@@ -54,7 +62,34 @@ pub fn create_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), S
     let mut task = task.mutable_state.lock();
     let id = task.vmspace_next_id;
     task.vmspace_next_id += 1;
-    task.vmspace_objects.insert(VmspaceObjectId::new(id), VmspaceObject::new());
+
+    // Memory allocated for the vmspace isn't charged to the current task, so
+    // it's held to the same limit to keep the limit from being sidestepped
+    let mut object = VmspaceObject::new();
+    object.memory_manager.set_memory_limit(task.memory_manager.memory_limit());
+    task.vmspace_objects.insert(VmspaceObjectId::new(id), object);
+
+    frame.a1 = id;
+    Ok(())
+}
+
+pub fn clone_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let mut task = task.mutable_state.lock();
+    let MutableState { vmspace_objects, vmspace_next_id, .. } = &mut *task;
+
+    // The source vmspace hasn't been spawned yet, so there's no TLB entries
+    // for its newly read-only pages to flush
+    let memory_manager = match vmspace_objects.get_mut(&VmspaceObjectId::new(frame.a1)) {
+        Some(object) => object.memory_manager.clone_copy_on_write(),
+        None => return Err(SyscallError::InvalidArgument(0)),
+    };
+
+    let id = *vmspace_next_id;
+    *vmspace_next_id += 1;
+    vmspace_objects.insert(
+        VmspaceObjectId::new(id),
+        VmspaceObject { memory_manager, inprocess_mappings: Vec::new(), cspace: CapabilitySpace::new() },
+    );
 
     frame.a1 = id;
     Ok(())
@@ -68,6 +103,14 @@ pub fn alloc_vmspace_object(task: &Task, frame: &mut GeneralRegisters) -> Result
     let address = VirtualAddress::new(frame.a2);
     let size = frame.a3;
     let permissions = MemoryPermissions::new(frame.a4);
+    let page_size = match frame.a5 {
+        0 => PageSize::Kilopage,
+        1 => PageSize::Megapage,
+        2 => PageSize::Gigapage,
+        _ => return Err(SyscallError::InvalidArgument(4)),
+    };
+    let guarded = frame.a6 != 0;
+    let lazy = frame.a7 != 0;
 
     let object = match vmspace_objects.get_mut(&VmspaceObjectId::new(id)) {
         Some(map) => map,
@@ -78,6 +121,10 @@ pub fn alloc_vmspace_object(task: &Task, frame: &mut GeneralRegisters) -> Result
         return Err(SyscallError::InvalidArgument(1));
     } else if size == 0 {
         return Err(SyscallError::InvalidArgument(2));
+    } else if guarded && !address.is_null() {
+        // The kernel picks the address of guarded objects to make sure there's
+        // room for the guard pages
+        return Err(SyscallError::InvalidArgument(5));
     }
 
     let mut flags = Flags::VALID | Flags::USER;
@@ -96,6 +143,7 @@ pub fn alloc_vmspace_object(task: &Task, frame: &mut GeneralRegisters) -> Result
 
     let kind = match (flags & Flags::READ, flags & Flags::WRITE, flags & Flags::EXECUTE) {
         (true, true, true) => AddressRegionKind::UserAllocated,
+        (true, true, false) if guarded => AddressRegionKind::Stack,
         (true, true, false) => AddressRegionKind::Data,
         (true, false, false) => AddressRegionKind::ReadOnly,
         (true, false, true) | (false, false, true) => AddressRegionKind::Text,
@@ -105,22 +153,49 @@ pub fn alloc_vmspace_object(task: &Task, frame: &mut GeneralRegisters) -> Result
     };
 
     let size = utils::round_up_to_next(size, 4.kib());
+    if !lazy && !object.memory_manager.within_memory_limit(size) {
+        return Err(SyscallError::OutOfMemory);
+    }
+
     let at = match address.is_null() {
         true => None,
         false => Some(address),
     };
 
-    let (at, region) = object.memory_manager.alloc_shared_region(
-        at,
-        RegionDescription {
-            size: PageSize::Kilopage,
-            count: size / 4.kib(),
-            contiguous: false,
-            flags,
-            fill: FillOption::Zeroed,
-            kind,
-        },
-    );
+    // Larger pages can only be used if they exactly cover the object,
+    // otherwise the object is mapped with kilopages
+    let page_size = match address.is_aligned(page_size) && size % page_size.to_byte_size() == 0 {
+        true => page_size,
+        false => PageSize::Kilopage,
+    };
+
+    // Lazy objects have no memory to share with the calling task yet, so
+    // they're only mapped into the vmspace and populated as the spawned task
+    // touches them
+    if lazy {
+        let count = size / page_size.to_byte_size();
+        let at = match guarded {
+            true => object.memory_manager.alloc_guarded_lazy_region(page_size, count, flags, kind),
+            false => object.memory_manager.alloc_lazy_region(at, page_size, count, flags, kind),
+        };
+
+        frame.a1 = 0;
+        frame.a2 = at.start.as_usize();
+        return Ok(());
+    }
+
+    let description = RegionDescription {
+        size: page_size,
+        count: size / page_size.to_byte_size(),
+        contiguous: false,
+        flags,
+        fill: FillOption::Zeroed,
+        kind,
+    };
+    let (at, region) = match guarded {
+        true => object.memory_manager.alloc_guarded_shared_region(description),
+        false => object.memory_manager.alloc_shared_region(at, description),
+    };
 
     // log::info!("Mapping region at {:#p} for task {}", at.start, task.name);
     let range = memory_manager.apply_shared_region(
@@ -138,6 +213,99 @@ pub fn alloc_vmspace_object(task: &Task, frame: &mut GeneralRegisters) -> Result
     Ok(())
 }
 
+pub fn map_vmspace_mmio(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
+    let mut task = task.mutable_state.lock();
+    let MutableState { cspace, vmspace_objects, .. } = &mut *task;
+
+    let id = VmspaceObjectId::new(frame.a1);
+    let cptr = CapabilityPtr::new(frame.a2);
+    let start = PhysicalAddress::new(frame.a3);
+    let len = frame.a4;
+    let permissions = MemoryPermissions::new(frame.a5);
+
+    if start.offset_into_page(PageSize::Kilopage) != 0 {
+        return Err(SyscallError::InvalidArgument(2));
+    }
+
+    // The mapping is made in whole pages, so the whole of the last page is
+    // what needs to be authorized
+    let phys = match frame.a3.checked_add(len).and_then(|end| end.checked_next_multiple_of(4.kib())) {
+        Some(end) if len != 0 => start..PhysicalAddress::new(end),
+        _ => return Err(SyscallError::InvalidArgument(3)),
+    };
+
+    let flags = mmio_flags(permissions)?;
+    authorize_mmio(cspace.resolve(cptr), &phys, flags)?;
+
+    let fdt = unsafe { fdt::Fdt::from_ptr(FDT.load(Ordering::Acquire)) }.unwrap();
+    let ram = fdt.memory().regions().filter_map(|region| {
+        let start = PhysicalAddress::from_ptr(region.starting_address);
+        Some(start..start.offset(region.size?))
+    });
+
+    if overlaps_ram(&phys, ram) {
+        log::warn!("Refusing to map RAM at {:#p}-{:#p} as MMIO", phys.start, phys.end);
+        return Err(SyscallError::InvalidArgument(2));
+    }
+
+    let object = match vmspace_objects.get_mut(&id) {
+        Some(object) => object,
+        None => return Err(SyscallError::InvalidArgument(0)),
+    };
+
+    // SAFETY: the range was authorized by an MMIO capability and isn't RAM, so
+    // this can't be used to alias the memory of any other task
+    let at = unsafe { object.memory_manager.map_mmio_device_with_flags(phys.start, None, len, flags) };
+
+    frame.a1 = at.start.as_usize();
+    Ok(())
+}
+
+/// The access flags to map MMIO with, which can't be executable
+fn mmio_flags(permissions: MemoryPermissions) -> Result<Flags, SyscallError> {
+    let mut flags = Flags::NONE;
+
+    if permissions & MemoryPermissions::READ {
+        flags |= Flags::READ;
+    }
+
+    if permissions & MemoryPermissions::WRITE {
+        flags |= Flags::WRITE;
+    }
+
+    match flags & Flags::READ && !(permissions & MemoryPermissions::EXECUTE) {
+        true => Ok(flags),
+        false => Err(SyscallError::InvalidArgument(4)),
+    }
+}
+
+/// Check that `cap` is an MMIO capability which covers all of `phys`, and
+/// grants the rights needed to map it with `flags`. Capabilities are minted
+/// with a device's `reg` range, which can be smaller than a page, so they
+/// authorize every page their range touches.
+fn authorize_mmio(cap: Option<&Capability>, phys: &Range<PhysicalAddress>, flags: Flags) -> Result<(), SyscallError> {
+    let Some(Capability { resource: CapabilityResource::Mmio(authorized, _, _), rights }) = cap else {
+        return Err(SyscallError::InvalidArgument(1));
+    };
+
+    if (flags & Flags::READ && !(*rights & CapabilityRights::READ))
+        || (flags & Flags::WRITE && !(*rights & CapabilityRights::WRITE))
+    {
+        return Err(SyscallError::InvalidArgument(1));
+    }
+
+    let authorized = PhysicalAddress::new(authorized.start.as_usize() & !(4.kib() - 1))
+        ..PhysicalAddress::new(utils::round_up_to_next(authorized.end.as_usize(), 4.kib()));
+    match authorized.start <= phys.start && phys.end <= authorized.end {
+        true => Ok(()),
+        false => Err(SyscallError::InvalidArgument(2)),
+    }
+}
+
+fn overlaps_ram(phys: &Range<PhysicalAddress>, mut ram: impl Iterator<Item = Range<PhysicalAddress>>) -> bool {
+    ram.any(|ram| phys.start < ram.end && ram.start < phys.end)
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn spawn_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), SyscallError> {
     let mut task_state = task.mutable_state.lock();
@@ -145,18 +313,29 @@ pub fn spawn_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sy
     let id: VmspaceObjectId = VmspaceObjectId::new(frame.a1);
     let name: VirtualAddress = VirtualAddress::new(frame.a2);
     let len: usize = frame.a3;
+    let memory_limit: Option<usize> = NonZeroUsize::new(frame.a4).map(NonZeroUsize::get);
     let pc: usize = frame.t0;
     let a0: usize = frame.t1;
     let a1: usize = frame.t2;
     let a2: usize = frame.t3;
     let sp: usize = frame.t4;
     let tp: usize = frame.t5;
+    let channel_capacity: usize = match frame.t6 {
+        0 => DEFAULT_CHANNEL_CAPACITY,
+        capacity => capacity,
+    };
 
-    let object = match task_state.vmspace_objects.remove(&id) {
+    let mut object = match task_state.vmspace_objects.remove(&id) {
         Some(map) => map,
         None => return Err(SyscallError::InvalidArgument(0)),
     };
 
+    let memory_limit = match (memory_limit, object.memory_manager.memory_limit()) {
+        (Some(requested), Some(limit)) => Some(requested.min(limit)),
+        (requested, limit) => requested.or(limit),
+    };
+    object.memory_manager.set_memory_limit(memory_limit);
+
     let user_slice = RawUserSlice::readable(name, len);
     let user_slice = match unsafe { user_slice.validate(&task_state.memory_manager) } {
         Ok(slice) => slice,
@@ -192,10 +371,10 @@ pub fn spawn_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sy
         *trap_frame = TrapFrame { sepc: pc, registers: GeneralRegisters { sp, tp, a0, a1, a2, ..Default::default() } }
     };
 
-    let (kernel_channel, user_read) = UserspaceChannel::new();
+    let (kernel_channel, user_read) = UserspaceChannel::unbounded();
     let mut new_task = Task {
         tid: Tid::new(NonZeroUsize::new(usize::MAX).unwrap()),
-        name: alloc::string::String::from(task_name).into_boxed_str(),
+        name: TaskName::new(task_name),
         context: SpinMutex::new(Context {
             ra: return_to_usermode as usize,
             sp: kernel_stack.addr() - core::mem::size_of::<TrapFrame>(),
@@ -211,10 +390,21 @@ pub fn spawn_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sy
             claimed_interrupts: BTreeMap::new(),
             subscribes_to_events: true,
             state: TaskState::Ready,
+            affinity: task_state.affinity,
+            fpu_registers: None,
+            fault_handler: FaultHandler::default(),
         }),
+        // Children start out with their parent's own priority, but not
+        // anything it has been lent
+        priority: Priority::new(task.priority.base()),
+        cpu_time: CpuTime::new(),
+        hart: AtomicUsize::new(0),
     };
 
-    let (mut channel1, mut channel2) = UserspaceChannel::new();
+    let (mut channel1, mut channel2) = UserspaceChannel::new(channel_capacity);
+    // Exit statuses get their own channel so they can't be mixed up with
+    // anything else sent over the parent channel
+    let (mut exit_read, mut exit_write) = UserspaceChannel::new(1);
 
     new_task
         .mutable_state
@@ -229,6 +419,8 @@ pub fn spawn_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sy
     SCHEDULER.enqueue_with(|tid| {
         channel1.sender.other_tid = Some(tid);
         channel2.sender.other_tid = Some(task.tid);
+        exit_read.sender.other_tid = Some(tid);
+        exit_write.sender.other_tid = Some(task.tid);
 
         for region in object.inprocess_mappings {
             task_state.memory_manager.dealloc_region(region);
@@ -257,10 +449,189 @@ pub fn spawn_vmspace(task: &Task, frame: &mut GeneralRegisters) -> Result<(), Sy
             }
         });
 
+        let exit_cptr = task_state.cspace.mint_with(|cptr| {
+            exit_read.sender.other_cptr = EXIT_CHANNEL;
+            exit_write.sender.other_cptr = cptr;
+
+            new_task
+                .mutable_state
+                .get_mut()
+                .cspace
+                .mint_with_id(
+                    EXIT_CHANNEL,
+                    Capability { resource: CapabilityResource::Channel(exit_write), rights: CapabilityRights::WRITE },
+                )
+                .expect("[BUG] exit channel cap already created?");
+
+            Capability { resource: CapabilityResource::Channel(exit_read), rights: CapabilityRights::READ }
+        });
+
         frame.a1 = cptr.value();
+        frame.a2 = exit_cptr.value();
 
         new_task
     });
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The UART on QEMU's `virt` machine, whose `reg` is smaller than a page
+    const UART: Range<PhysicalAddress> = PhysicalAddress::new(0x1000_0000)..PhysicalAddress::new(0x1000_0100);
+
+    fn mmio_cap(rights: CapabilityRights) -> Capability {
+        Capability {
+            resource: CapabilityResource::Mmio(UART, VirtualAddress::new(0)..VirtualAddress::new(0), Vec::new()),
+            rights,
+        }
+    }
+
+    #[test]
+    fn authorized_mmio_range() {
+        let cap = mmio_cap(CapabilityRights::READ | CapabilityRights::WRITE);
+        let rw = Flags::READ | Flags::WRITE;
+        assert_eq!(authorize_mmio(Some(&cap), &UART, rw), Ok(()));
+
+        let first_half = UART.start..UART.start.offset(0x800);
+        assert_eq!(authorize_mmio(Some(&cap), &first_half, Flags::READ), Ok(()));
+
+        let read_only = mmio_cap(CapabilityRights::READ);
+        assert_eq!(authorize_mmio(Some(&read_only), &UART, Flags::READ), Ok(()));
+        assert_eq!(authorize_mmio(Some(&read_only), &UART, rw), Err(SyscallError::InvalidArgument(1)));
+    }
+
+    #[test]
+    fn unauthorized_mmio_range() {
+        let cap = mmio_cap(CapabilityRights::READ | CapabilityRights::WRITE);
+
+        // Past the end of the range the capability was created for
+        let beyond = UART.start..UART.end.offset(0x1000);
+        assert_eq!(authorize_mmio(Some(&cap), &beyond, Flags::READ), Err(SyscallError::InvalidArgument(2)));
+
+        // Somewhere else entirely
+        let plic = PhysicalAddress::new(0x0C00_0000)..PhysicalAddress::new(0x0C00_1000);
+        assert_eq!(authorize_mmio(Some(&cap), &plic, Flags::READ), Err(SyscallError::InvalidArgument(2)));
+
+        // Only MMIO capabilities can authorize a physical range
+        let (channel, _) = UserspaceChannel::new(1);
+        let channel = Capability { resource: CapabilityResource::Channel(channel), rights: CapabilityRights::READ };
+        assert_eq!(authorize_mmio(Some(&channel), &UART, Flags::READ), Err(SyscallError::InvalidArgument(1)));
+        assert_eq!(authorize_mmio(None, &UART, Flags::READ), Err(SyscallError::InvalidArgument(1)));
+    }
+
+    #[test]
+    fn mmio_permissions() {
+        assert!(matches!(mmio_flags(MemoryPermissions::READ_WRITE), Ok(flags) if flags & (Flags::READ | Flags::WRITE)));
+        assert!(mmio_flags(MemoryPermissions::WRITE).is_err());
+        assert!(mmio_flags(MemoryPermissions::READ | MemoryPermissions::EXECUTE).is_err());
+    }
+
+    #[test]
+    fn ram_is_rejected() {
+        let ram = || [PhysicalAddress::new(0x8000_0000)..PhysicalAddress::new(0x8800_0000)].into_iter();
+
+        assert!(!overlaps_ram(&UART, ram()));
+        assert!(overlaps_ram(&(PhysicalAddress::new(0x8000_0000)..PhysicalAddress::new(0x8000_1000)), ram()));
+
+        // Straddling the start of RAM counts too
+        assert!(overlaps_ram(&(PhysicalAddress::new(0x7FFF_F000)..PhysicalAddress::new(0x8000_1000)), ram()));
+        assert!(!overlaps_ram(&(PhysicalAddress::new(0x7FFF_F000)..PhysicalAddress::new(0x8000_0000)), ram()));
+    }
+
+    #[test]
+    fn clone_source_vmspace() {
+        use crate::mem::usercopy::{copy_from_user, copy_to_user};
+
+        let task = Task::idle();
+        let mut frame = GeneralRegisters::default();
+        create_vmspace(&task, &mut frame).unwrap();
+        let src = frame.a1;
+
+        let object = |lazy: bool| GeneralRegisters {
+            a1: src,
+            a3: 4.kib(),
+            a4: MemoryPermissions::READ_WRITE.value(),
+            a7: lazy as usize,
+            ..Default::default()
+        };
+        let mut frame = object(false);
+        alloc_vmspace_object(&task, &mut frame).unwrap();
+        let (ours, shared) = (VirtualAddress::new(frame.a1), VirtualAddress::new(frame.a2));
+        let mut frame = object(true);
+        alloc_vmspace_object(&task, &mut frame).unwrap();
+        let private = VirtualAddress::new(frame.a2);
+
+        {
+            let mut state = task.mutable_state.lock();
+            let src = state.vmspace_objects.get_mut(&VmspaceObjectId::new(src)).unwrap();
+            copy_to_user(&mut src.memory_manager, private, b"parent").unwrap();
+        }
+
+        let mut frame = GeneralRegisters { a1: src, ..Default::default() };
+        clone_vmspace(&task, &mut frame).unwrap();
+        let clone = frame.a1;
+        assert_ne!(clone, src);
+
+        let mut state = task.mutable_state.lock();
+        copy_to_user(&mut state.memory_manager, ours, b"shared").unwrap();
+        let clone = state.vmspace_objects.get_mut(&VmspaceObjectId::new(clone)).unwrap();
+        copy_to_user(&mut clone.memory_manager, private, b"child!").unwrap();
+
+        // Shared memory is still shared, everything else is copied on write
+        let mut buffer = [0; 6];
+        for (id, expected) in [(src, b"parent"), (frame.a1, b"child!")] {
+            let object = &state.vmspace_objects[&VmspaceObjectId::new(id)];
+            copy_from_user(&object.memory_manager, private, &mut buffer).unwrap();
+            assert_eq!(&buffer, expected);
+            copy_from_user(&object.memory_manager, shared, &mut buffer).unwrap();
+            assert_eq!(&buffer, b"shared");
+        }
+
+        drop(state);
+        core::mem::forget(task);
+    }
+
+    #[test]
+    fn clone_unknown_vmspace() {
+        let task = Task::idle();
+        let mut frame = GeneralRegisters { a1: 42, ..Default::default() };
+        assert_eq!(clone_vmspace(&task, &mut frame), Err(SyscallError::InvalidArgument(0)));
+
+        core::mem::forget(task);
+    }
+
+    #[test]
+    fn map_sub_page_mmio_device() {
+        let task = Task::idle();
+        let mut frame = GeneralRegisters::default();
+        create_vmspace(&task, &mut frame).unwrap();
+        let id = frame.a1;
+
+        let cptr = task.mutable_state.lock().cspace.mint(mmio_cap(CapabilityRights::READ | CapabilityRights::WRITE));
+        let mmio = |len: usize| GeneralRegisters {
+            a1: id,
+            a2: cptr.value(),
+            a3: UART.start.as_usize(),
+            a4: len,
+            a5: MemoryPermissions::READ_WRITE.value(),
+            ..Default::default()
+        };
+
+        // The page containing the registers is mapped, as the cap covers it
+        let mut frame = mmio(0x100);
+        map_vmspace_mmio(&task, &mut frame).unwrap();
+        let state = task.mutable_state.lock();
+        let object = &state.vmspace_objects[&VmspaceObjectId::new(id)];
+        assert_eq!(object.memory_manager.resolve(VirtualAddress::new(frame.a1)), Some(UART.start));
+        drop(state);
+
+        // But not the page after it
+        let mut frame = mmio(0x1100);
+        assert_eq!(map_vmspace_mmio(&task, &mut frame), Err(SyscallError::InvalidArgument(2)));
+
+        core::mem::forget(task);
+    }
+}
@@ -5,27 +5,28 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
-use core::{cell::Cell, num::NonZeroUsize};
+use core::{cell::Cell, num::NonZeroUsize, sync::atomic::AtomicUsize};
 
 use crate::{
     capabilities::{Capability, CapabilityResource, CapabilitySpace},
     mem::{
-        alloc_kernel_stack,
+        alloc_kernel_stack, free_kernel_stack,
         manager::{AddressRegionKind, FillOption, RegionDescription, UserspaceMemoryManager},
         paging::{flags::Flags, PageSize, VirtualAddress},
     },
     platform::FDT,
+    scheduler::priority::Priority,
     sync::SpinMutex,
-    syscall::{channel::UserspaceChannel, vmspace::VmspaceObject},
-    trap::{GeneralRegisters, TrapFrame},
+    syscall::{channel::UserspaceChannel, fault::FaultHandler, vmspace::VmspaceObject},
+    trap::{FloatingPointRegisters, GeneralRegisters, TrapFrame},
     utils::{round_up_to_next, SameHartDeadlockDetection, Units},
 };
-use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
 use fdt::Fdt;
 use librust::{
     capabilities::CapabilityRights,
-    syscalls::{channel::KERNEL_CHANNEL, vmspace::VmspaceObjectId},
-    task::Tid,
+    syscalls::{channel::KERNEL_CHANNEL, io::INIT_KERNEL_LOG, misc::INIT_SYSTEM_RESET, vmspace::VmspaceObjectId},
+    task::{HartMask, Tid, MAX_TASK_NAME_LEN},
 };
 
 #[thread_local]
@@ -71,19 +72,134 @@ pub struct MutableState {
     pub claimed_interrupts: BTreeMap<usize, usize>,
     pub subscribes_to_events: bool,
     pub state: TaskState,
+    /// The harts this task is allowed to run on
+    pub affinity: HartMask,
+    /// The task's saved floating point registers, or `None` if it hasn't used
+    /// the FPU yet. See [`crate::scheduler::fpu`].
+    pub fpu_registers: Option<FloatingPointRegisters>,
+    /// Run instead of killing the task when it faults, see
+    /// [`crate::syscall::fault`]
+    pub fault_handler: FaultHandler,
 }
 
 #[derive(Debug)]
 pub struct Task {
     pub tid: Tid,
-    pub name: Box<str>,
+    pub name: TaskName,
     pub kernel_stack: *mut u8,
     pub context: SpinMutex<Context>,
     pub mutable_state: SpinMutex<MutableState, SameHartDeadlockDetection>,
+    pub priority: Priority,
+    pub cpu_time: CpuTime,
+    /// The hart whose run queue the task is in, which is the hart that's woken
+    /// up when the task becomes ready to run
+    pub hart: AtomicUsize,
+}
+
+/// The name a task shows up as in logs, which the task can change itself.
+/// Names are at most [`MAX_TASK_NAME_LEN`] bytes and longer ones are truncated.
+pub struct TaskName(SpinMutex<String>);
+
+impl TaskName {
+    pub fn new(name: &str) -> Self {
+        Self(SpinMutex::new(String::from(truncate(name))))
+    }
+
+    pub fn set(&self, name: &str) {
+        let mut current = self.0.lock();
+        current.clear();
+        current.push_str(truncate(name));
+    }
+
+    pub fn get(&self) -> String {
+        self.0.lock().clone()
+    }
+
+    /// Display the name without waiting on its lock, showing `<locked>` if
+    /// it's held, for use when panicking since the panic may have happened
+    /// while the lock was held
+    pub fn try_display(&self) -> impl core::fmt::Display + '_ {
+        TryDisplay(self)
+    }
+}
+
+struct TryDisplay<'a>(&'a TaskName);
+
+impl core::fmt::Display for TryDisplay<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 .0.try_lock() {
+            Some(name) => f.write_str(&name),
+            None => f.write_str("<locked>"),
+        }
+    }
+}
+
+impl core::fmt::Display for TaskName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0.lock())
+    }
+}
+
+impl core::fmt::Debug for TaskName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(&**self.0.lock(), f)
+    }
+}
+
+/// The CPU time a task has used, in ticks of the `time` CSR. Every hart reads
+/// the same `time`, so a task which migrates is switched out on one hart and in
+/// on another without losing or double counting any time.
+#[derive(Debug)]
+pub struct CpuTime(SpinMutex<CpuTimeInner>);
+
+#[derive(Debug)]
+struct CpuTimeInner {
+    total: u64,
+    /// When the task was switched in, if it's running
+    running_since: Option<u64>,
+}
+
+impl CpuTime {
+    pub const fn new() -> Self {
+        Self(SpinMutex::new(CpuTimeInner { total: 0, running_since: None }))
+    }
+
+    pub fn switched_in(&self, now: u64) {
+        self.0.lock().running_since = Some(now);
+    }
+
+    pub fn switched_out(&self, now: u64) {
+        let mut inner = self.0.lock();
+        if let Some(since) = inner.running_since.take() {
+            inner.total += now.saturating_sub(since);
+        }
+    }
+
+    /// The time used as of `now`, including the current time slice if the
+    /// task is running
+    pub fn get(&self, now: u64) -> u64 {
+        let inner = self.0.lock();
+        inner.total + inner.running_since.map_or(0, |since| now.saturating_sub(since))
+    }
+}
+
+/// Truncate `name` to [`MAX_TASK_NAME_LEN`] bytes without splitting a
+/// character
+fn truncate(name: &str) -> &str {
+    match name.len() <= MAX_TASK_NAME_LEN {
+        true => name,
+        false => {
+            let end = (0..=MAX_TASK_NAME_LEN).rev().find(|&i| name.is_char_boundary(i)).unwrap_or(0);
+            &name[..end]
+        }
+    }
 }
 
 impl Task {
-    pub fn load_init<'a>(bin: &[u8], args: impl Iterator<Item = &'a str> + Clone) -> Self {
+    /// Create the `init` task from the flat `bin` image, mapping in `initrd` if
+    /// the bootloader provided one. Its address and length are passed in `a3`
+    /// and `a4`, or zero if there isn't an initrd.
+    pub fn load_init<'a>(bin: &[u8], initrd: Option<&[u8]>, args: impl Iterator<Item = &'a str> + Clone) -> Self {
         let mut memory_manager = UserspaceMemoryManager::new();
         let mut cspace = CapabilitySpace::new();
 
@@ -127,6 +243,22 @@ impl Task {
             )
         };
 
+        let (a3, a4) = match initrd {
+            Some(initrd) => {
+                let start = memory_manager.alloc_guarded_region(RegionDescription {
+                    size: PageSize::Kilopage,
+                    count: round_up_to_next(initrd.len(), 4.kib()) / 4.kib(),
+                    contiguous: false,
+                    flags: Flags::USER | Flags::READ | Flags::VALID,
+                    fill: FillOption::Data(initrd),
+                    kind: AddressRegionKind::ReadOnly,
+                });
+
+                (start.as_usize(), initrd.len())
+            }
+            None => (0, 0),
+        };
+
         let arg_count = args.clone().count();
         let (a0, a1) = match arg_count {
             0 => (0, 0),
@@ -160,13 +292,25 @@ impl Task {
             }
         };
 
-        let (kernel_channel, user_read) = UserspaceChannel::new();
+        let (kernel_channel, user_read) = UserspaceChannel::unbounded();
         cspace
             .mint_with_id(
                 KERNEL_CHANNEL,
                 Capability { resource: CapabilityResource::Channel(user_read), rights: CapabilityRights::READ },
             )
             .expect("[BUG] kernel channel cap already created?");
+        cspace
+            .mint_with_id(
+                INIT_SYSTEM_RESET,
+                Capability { resource: CapabilityResource::SystemReset, rights: CapabilityRights::READ },
+            )
+            .expect("[BUG] system reset cap already created?");
+        cspace
+            .mint_with_id(
+                INIT_KERNEL_LOG,
+                Capability { resource: CapabilityResource::KernelLog, rights: CapabilityRights::READ },
+            )
+            .expect("[BUG] kernel log cap already created?");
 
         let kernel_stack = alloc_kernel_stack(2.mib());
         let trap_frame = unsafe { kernel_stack.sub(core::mem::size_of::<TrapFrame>()).cast::<TrapFrame>() };
@@ -178,6 +322,8 @@ impl Task {
                     a0,
                     a1,
                     a2: fdt_loc.start.as_usize(),
+                    a3,
+                    a4,
                     ..Default::default()
                 },
             }
@@ -185,7 +331,7 @@ impl Task {
 
         Self {
             tid: Tid::new(NonZeroUsize::new(1).unwrap()),
-            name: Box::from("init"),
+            name: TaskName::new("init"),
             context: SpinMutex::new(Context {
                 ra: crate::scheduler::return_to_usermode as usize,
                 sp: kernel_stack.addr() - core::mem::size_of::<TrapFrame>(),
@@ -201,7 +347,13 @@ impl Task {
                 claimed_interrupts: BTreeMap::new(),
                 subscribes_to_events: false,
                 state: TaskState::Ready,
+                affinity: HartMask::ALL,
+                fpu_registers: None,
+                fault_handler: FaultHandler::default(),
             }),
+            priority: Priority::default(),
+            cpu_time: CpuTime::new(),
+            hart: AtomicUsize::new(0),
         }
     }
 
@@ -231,7 +383,7 @@ impl Task {
         let trap_frame = unsafe { kernel_stack.sub(core::mem::size_of::<TrapFrame>()).cast::<TrapFrame>() };
         unsafe { *trap_frame = TrapFrame { sepc: 0xF00D_0000, registers: GeneralRegisters { ..Default::default() } } };
 
-        let (kernel_channel, user_read) = UserspaceChannel::new();
+        let (kernel_channel, user_read) = UserspaceChannel::unbounded();
         cspace
             .mint_with_id(
                 KERNEL_CHANNEL,
@@ -243,7 +395,7 @@ impl Task {
 
         Self {
             tid: Tid::new(NonZeroUsize::new(usize::MAX).unwrap()),
-            name: Box::from("<idle>"),
+            name: TaskName::new("<idle>"),
             context: SpinMutex::new(Context {
                 ra: crate::scheduler::return_to_usermode as usize,
                 sp: kernel_stack.addr() - core::mem::size_of::<TrapFrame>(),
@@ -259,11 +411,25 @@ impl Task {
                 claimed_interrupts: BTreeMap::new(),
                 subscribes_to_events: false,
                 state: TaskState::Ready,
+                affinity: HartMask::ALL,
+                fpu_registers: None,
+                fault_handler: FaultHandler::default(),
             }),
+            priority: Priority::default(),
+            cpu_time: CpuTime::new(),
+            hart: AtomicUsize::new(0),
         }
     }
 }
 
+impl Drop for Task {
+    fn drop(&mut self) {
+        // Safety: running tasks are always referenced by the scheduler, which
+        // only lets go of them once they've exited and been switched away from
+        unsafe { free_kernel_stack(self.kernel_stack) };
+    }
+}
+
 unsafe impl Send for Task {}
 unsafe impl Sync for Task {}
 
@@ -280,3 +446,76 @@ impl TaskState {
         matches!(self, TaskState::Dead)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn task_name_is_set_and_read_back() {
+        let name = TaskName::new("init");
+        assert_eq!(name.get(), "init");
+
+        name.set("virtio-blkd");
+        assert_eq!(name.get(), "virtio-blkd");
+        assert_eq!(alloc::format!("{}", name), "virtio-blkd");
+    }
+
+    #[test]
+    fn long_task_names_are_truncated() {
+        let name = TaskName::new("a-task-name-which-is-much-longer-than-allowed");
+        assert_eq!(name.get(), "a-task-name-which-is-much-longer");
+
+        // Multi-byte characters aren't split in half
+        name.set("0123456789012345678901234567890é");
+        assert_eq!(name.get(), "0123456789012345678901234567890");
+    }
+
+    #[test]
+    fn locked_task_names_are_displayed_without_waiting() {
+        let name = TaskName::new("init");
+        assert_eq!(alloc::format!("{}", name.try_display()), "init");
+
+        let _guard = name.0.lock();
+        assert_eq!(alloc::format!("{}", name.try_display()), "<locked>");
+    }
+
+    #[test]
+    fn busy_tasks_accrue_more_cpu_time() {
+        let (busy, idle) = (CpuTime::new(), CpuTime::new());
+
+        // Over 1000 ticks the busy task gets 90 ticks of every 100, and the
+        // idle one wakes up for the other 10
+        for slice in (0..1000).step_by(100) {
+            busy.switched_in(slice);
+            busy.switched_out(slice + 90);
+            idle.switched_in(slice + 90);
+            idle.switched_out(slice + 100);
+        }
+
+        assert_eq!(busy.get(1000), 900);
+        assert_eq!(idle.get(1000), 100);
+
+        // Time isn't counted while switched out, only while running
+        assert_eq!(busy.get(5000), 900);
+        busy.switched_in(5000);
+        assert_eq!(busy.get(5050), 950);
+    }
+
+    #[test]
+    fn cpu_time_survives_migration() {
+        let time = CpuTime::new();
+
+        // Switched out on one hart and picked up by another later on, with the
+        // time in between not counted
+        time.switched_in(100);
+        time.switched_out(150);
+        time.switched_in(400);
+        time.switched_out(425);
+        assert_eq!(time.get(1000), 75);
+
+        // A second switch out without running again changes nothing
+        time.switched_out(2000);
+        assert_eq!(time.get(2000), 75);
+    }
+}
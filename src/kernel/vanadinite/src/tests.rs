@@ -10,7 +10,7 @@
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 
 use crate::{
-    cpu_local, csr,
+    boot, cpu_local, csr,
     drivers::{generic::plic::Plic, CompatibleWith},
     interrupts,
     mem::{self, paging::PhysicalAddress, phys2virt},
@@ -34,12 +34,13 @@ pub extern "C" fn ktest(hart_id: usize, fdt: *const u8) -> ! {
 
     mem::heap::HEAP_ALLOCATOR.init(64.mib());
 
+    platform::FDT.store(fdt, Ordering::Release);
     let fdt: Fdt<'static> = match unsafe { Fdt::from_ptr(fdt) } {
         Ok(fdt) => fdt,
         Err(e) => crate::platform::exit(crate::platform::ExitStatus::Error(&e)),
     };
 
-    let current_cpu = fdt.cpus().find(|cpu| cpu.ids().first() == hart_id).unwrap();
+    let current_cpu = fdt.cpus().find(|cpu| platform::devicetree::hart_id(&fdt, cpu) == Some(hart_id)).unwrap();
     let timebase_frequency = current_cpu.timebase_frequency();
     TIMER_FREQ.store(timebase_frequency as u64, Ordering::Relaxed);
 
@@ -55,8 +56,10 @@ pub extern "C" fn ktest(hart_id: usize, fdt: *const u8) -> ! {
         }
     }
 
-    let n_cpus = fdt.cpus().count();
-    N_CPUS.store(n_cpus, Ordering::Release);
+    let hart_ids = fdt.cpus().filter_map(|cpu| platform::devicetree::hart_id(&fdt, &cpu));
+    N_CPUS.store(boot::harts::hart_id_bound(hart_ids.chain([hart_id])), Ordering::Release);
+    // Only the boot hart runs the tests
+    boot::harts::BOOTED_HARTS.finish(hart_id);
 
     if let Some(ic) = fdt.find_compatible(Plic::compatible_with()) {
         let reg = ic.reg().unwrap().next().unwrap();
@@ -79,7 +82,8 @@ pub extern "C" fn ktest(hart_id: usize, fdt: *const u8) -> ! {
                     .and_then(|p| p.as_str()?.chars().find(|c| *c == 's'))
                     .is_some()
             })
-            .map(|cpu| platform::plic_context_for(cpu.ids().first()));
+            .filter_map(|cpu| platform::devicetree::hart_id(&fdt, &cpu))
+            .map(platform::plic_context_for);
 
         let plic = unsafe { &*ic_virt.as_ptr().cast::<Plic>() };
 
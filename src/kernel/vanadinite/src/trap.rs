@@ -11,14 +11,14 @@ use crate::{
     csr::{self},
     interrupts::{isr::invoke_isr, PLIC},
     mem::{
-        manager::AddressRegion,
+        manager::{AddressRegion, AddressRegionKind},
         paging::{flags::Flags, VirtualAddress},
         region::MemoryRegion,
     },
-    scheduler::{CURRENT_TASK, SCHEDULER},
+    scheduler::{fpu, timers::TIMERS, watchdog::WATCHDOG, CURRENT_TASK, SCHEDULER},
     syscall,
     task::TaskState,
-    utils::ticks_per_us,
+    utils::{ticks_per_us, Units},
 };
 
 #[derive(Clone, Copy, Default)]
@@ -262,13 +262,35 @@ pub extern "C" fn trap_handler(regs: &mut TrapFrame, scause: usize, stval: usize
         );
     }
 
+    WATCHDOG.record_trap(regs.sepc);
+
     let trap_kind = Trap::from_cause(scause);
     match trap_kind {
-        Trap::SupervisorTimerInterrupt => SCHEDULER.schedule(),
+        Trap::SupervisorTimerInterrupt => {
+            WATCHDOG.check();
+            TIMERS.fire_expired(csr::time::read());
+            SCHEDULER.schedule()
+        }
+        // Sent by another hart to wake this one up from its idle task when a
+        // task it might be able to run has become ready
+        Trap::SupervisorSoftwareInterrupt => {
+            csr::sip::clear_ssip();
+            SCHEDULER.schedule()
+        }
         Trap::UserModeEnvironmentCall => {
             syscall::handle(regs);
             regs.sepc += 4;
         }
+        // The first floating point instruction a task runs traps since the FPU
+        // starts out off, so turn it on and retry the instruction. Anything
+        // that's still illegal with the FPU on traps again and ends up below.
+        Trap::IllegalInstruction
+            if !VirtualAddress::new(regs.sepc).is_kernel_region()
+                && matches!(csr::sstatus::fs(), csr::sstatus::FloatingPointStatus::Off) =>
+        {
+            let task = CURRENT_TASK.get();
+            fpu::enable(&mut task.mutable_state.lock().fpu_registers);
+        }
         Trap::SupervisorExternalInterrupt => {
             // FIXME: there has to be a better way
             if let Some(plic) = &*PLIC.lock() {
@@ -289,6 +311,17 @@ pub extern "C" fn trap_handler(regs: &mut TrapFrame, scause: usize, stval: usize
             match sepc.is_kernel_region() {
                 // We should always have marked memory regions up front from the initial mapping
                 true => {
+                    if crate::mem::is_kernel_stack_guard(stval) {
+                        #[cfg(test)]
+                        if CATCH_STACK_OVERFLOWS.get() {
+                            CAUGHT_STACK_OVERFLOW.set(Some(stval));
+                            regs.sepc += 4;
+                            return;
+                        }
+
+                        panic!("[KERNEL BUG] Kernel stack overflow @ pc={:#p}: stval={:#p}", sepc, stval);
+                    }
+
                     let active = CURRENT_TASK.get();
 
                     match active.mutable_state.try_lock() {
@@ -310,7 +343,12 @@ pub extern "C" fn trap_handler(regs: &mut TrapFrame, scause: usize, stval: usize
                     let valid = match memory_manager.region_for(stval) {
                         None | Some(AddressRegion { region: None, .. }) => false,
                         Some(AddressRegion { region: Some(MemoryRegion::GuardPage), .. }) => {
-                            log::error!("Process hit a guard page, stack overflow?");
+                            match memory_manager.region_for(stval.add(4.kib())) {
+                                Some(AddressRegion { kind: AddressRegionKind::Stack, .. }) => {
+                                    log::error!("Process {} overflowed its stack", active_task_lock.name)
+                                }
+                                _ => log::error!("Process hit a guard page"),
+                            }
                             false
                         }
                         _ => match trap_kind {
@@ -320,16 +358,17 @@ pub extern "C" fn trap_handler(regs: &mut TrapFrame, scause: usize, stval: usize
                                         (flags & Flags::READ)
                                             && memory_manager.modify_page_flags(stval, |f| f | Flags::ACCESSED)
                                     }
-                                    None => false,
+                                    // The first access to a page of a lazy
+                                    // region, if it is one
+                                    None => memory_manager.resolve_lazy(stval),
                                 }
                             }
                             Trap::StorePageFault => match memory_manager.page_flags(stval) {
-                                Some(flags) => {
-                                    (flags & Flags::WRITE)
-                                        && memory_manager
-                                            .modify_page_flags(stval, |f| f | Flags::DIRTY | Flags::ACCESSED)
+                                Some(flags) if flags & Flags::WRITE => {
+                                    memory_manager.modify_page_flags(stval, |f| f | Flags::DIRTY | Flags::ACCESSED)
                                 }
-                                None => false,
+                                Some(_) => memory_manager.resolve_copy_on_write(stval),
+                                None => memory_manager.resolve_lazy(stval),
                             },
                             _ => unreachable!(),
                         },
@@ -338,36 +377,28 @@ pub extern "C" fn trap_handler(regs: &mut TrapFrame, scause: usize, stval: usize
                     match valid {
                         true => crate::mem::sfence(Some(stval), None),
                         false => {
-                            log::error!(
-                                "Process {} died to a {:?} @ {:#p} (PC: {:#p})",
-                                active_task_lock.name,
-                                trap_kind,
-                                stval,
-                                sepc,
-                            );
-                            log::error!("Register dump:\n{:?}", regs);
-                            // log::error!("Stack dump (last 32 values):\n");
-                            // let mut sp = regs.registers.sp as *const u64;
-                            // for _ in 0..32 {
-                            //     log::error!("{:#p}: {:#x}", sp, unsafe { *sp });
-                            //     sp = unsafe { sp.offset(1) };
-                            // }
-                            log::error!(
-                                "Memory map:\n{:#?}",
-                                active_task.memory_manager.address_map_debug(Some(stval))
-                            );
-                            log::error!("Phys addr (if any): {:?}", active_task.memory_manager.resolve(stval));
-
-                            active_task.state = TaskState::Dead;
                             drop(active_task);
                             drop(active_task_lock);
 
-                            SCHEDULER.schedule()
+                            user_fault(regs, trap_kind, scause, stval)
                         }
                     }
                 }
             }
         }
+        // Faults from userspace which the kernel has no way of fixing up
+        Trap::InstructionAddressMisaligned
+        | Trap::InstructionAccessFault
+        | Trap::IllegalInstruction
+        | Trap::Breakpoint
+        | Trap::LoadAddressMisaligned
+        | Trap::LoadAccessFault
+        | Trap::StoreAddressMisaligned
+        | Trap::StoreAccessFault
+            if !VirtualAddress::new(regs.sepc).is_kernel_region() =>
+        {
+            user_fault(regs, trap_kind, scause, VirtualAddress::new(stval))
+        }
         trap => panic!("Ignoring trap: {:?}, sepc: {:#x}, stval: {:#x}", trap, regs.sepc, stval),
     }
 
@@ -379,6 +410,86 @@ pub extern "C" fn trap_handler(regs: &mut TrapFrame, scause: usize, stval: usize
     sbi::timer::set_timer(csr::time::read() + ticks_per_us(10_000, crate::TIMER_FREQ.load(Ordering::Relaxed))).unwrap();
 }
 
+#[cfg(test)]
+#[thread_local]
+static CATCH_STACK_OVERFLOWS: core::cell::Cell<bool> = core::cell::Cell::new(false);
+#[cfg(test)]
+#[thread_local]
+static CAUGHT_STACK_OVERFLOW: core::cell::Cell<Option<VirtualAddress>> = core::cell::Cell::new(None);
+
+/// Run `f`, skipping over any instruction in it which faults on a kernel stack
+/// guard page instead of panicking, and return the address of the last such
+/// fault. Those instructions must be 4 bytes long. The test harness doesn't set
+/// up a stack for traps, so `f` gets its own.
+#[cfg(test)]
+pub fn catch_kernel_stack_overflow(f: impl FnOnce()) -> Option<VirtualAddress> {
+    use crate::task::{Sscratch, HART_SSCRATCH};
+
+    let trap_stack = crate::mem::alloc_kernel_stack(256.kib());
+    let sscratch = HART_SSCRATCH.replace(Sscratch {
+        kernel_stack_top: trap_stack,
+        kernel_thread_local: crate::cpu_local::tp(),
+        kernel_global_ptr: crate::asm::gp(),
+        scratch_sp: 0,
+    });
+    let previous = csr::sscratch::read();
+    let interrupts_enabled = csr::sstatus::read() & 2 == 2;
+    csr::sscratch::write(core::ptr::addr_of!(HART_SSCRATCH) as usize);
+
+    CATCH_STACK_OVERFLOWS.set(true);
+    f();
+    CATCH_STACK_OVERFLOWS.set(false);
+
+    // Returning from a trap always enables interrupts
+    if !interrupts_enabled {
+        csr::sstatus::disable_interrupts();
+    }
+
+    csr::sscratch::write(previous);
+    HART_SSCRATCH.set(sscratch);
+    // Safety: the trap stack was only used by the traps taken in `f`
+    unsafe { crate::mem::free_kernel_stack(trap_stack) };
+
+    CAUGHT_STACK_OVERFLOW.take()
+}
+
+/// Handle a fault caused by the current task which the kernel can't resolve, by
+/// running the task's fault handler if it has one, or otherwise killing it
+fn user_fault(regs: &mut TrapFrame, trap_kind: Trap, scause: usize, stval: VirtualAddress) {
+    let active_task_lock = CURRENT_TASK.get();
+    let mut active_task = active_task_lock.mutable_state.lock();
+    let sepc = VirtualAddress::new(regs.sepc);
+
+    let task = &mut *active_task;
+    if task.fault_handler.deliver(&mut task.memory_manager, &mut task.fpu_registers, regs, scause, stval.as_usize()) {
+        log::debug!(
+            "Running the fault handler of process {} for a {:?} @ {:#p} (PC: {:#p})",
+            active_task_lock.name,
+            trap_kind,
+            stval,
+            sepc,
+        );
+        return;
+    }
+
+    log::error!("Process {} died to a {:?} @ {:#p} (PC: {:#p})", active_task_lock.name, trap_kind, stval, sepc);
+    log::error!("Register dump:\n{:?}", regs);
+    // log::error!("Stack dump (last 32 values):\n");
+    // let mut sp = regs.registers.sp as *const u64;
+    // for _ in 0..32 {
+    //     log::error!("{:#p}: {:#x}", sp, unsafe { *sp });
+    //     sp = unsafe { sp.offset(1) };
+    // }
+    log::error!("Memory map:\n{:#?}", active_task.memory_manager.address_map_debug(Some(stval)));
+    log::error!("Phys addr (if any): {:?}", active_task.memory_manager.resolve(stval));
+
+    active_task.state = TaskState::Dead;
+    drop(active_task);
+    drop(active_task_lock);
+
+    SCHEDULER.schedule()
+}
+
 /// # Safety
 /// nice try
 #[naked]
@@ -453,108 +564,8 @@ pub unsafe extern "C" fn stvec_trap_shim() -> ! {
         csrr a1, scause
         csrr a2, stval
 
-        // Check if floating point registers are dirty
-        csrr s0, sstatus
-        srli s0, s0, 13
-        andi s0, s0, 3
-        li s1, 3
-        
-        // Skip FP reg saving if they're clean
-        bne s0, s1, 1f
-
-        addi sp, sp, -264
-
-        .attribute arch, "rv64imafdc"
-        fsd f0, 0(sp)
-        fsd f1, 8(sp)
-        fsd f2, 16(sp)
-        fsd f3, 24(sp)
-        fsd f4, 32(sp)
-        fsd f5, 40(sp)
-        fsd f6, 48(sp)
-        fsd f7, 56(sp)
-        fsd f8, 64(sp)
-        fsd f9, 72(sp)
-        fsd f10, 80(sp)
-        fsd f11, 88(sp)
-        fsd f12, 96(sp)
-        fsd f13, 104(sp)
-        fsd f14, 112(sp)
-        fsd f15, 120(sp)
-        fsd f16, 128(sp)
-        fsd f17, 136(sp)
-        fsd f18, 144(sp)
-        fsd f19, 152(sp)
-        fsd f20, 160(sp)
-        fsd f21, 168(sp)
-        fsd f22, 176(sp)
-        fsd f23, 184(sp)
-        fsd f24, 192(sp)
-        fsd f25, 200(sp)
-        fsd f26, 208(sp)
-        fsd f27, 216(sp)
-        fsd f28, 224(sp)
-        fsd f29, 232(sp)
-        fsd f30, 240(sp)
-        fsd f31, 248(sp)
-        frcsr t1
-        sd t1, 256(sp)
-        .attribute arch, "rv64imac"
-
-        li t1, (0b01 << 13)
-        csrc sstatus, t1
-
-        // FP registers clean
-        1:
-
         call trap_handler
 
-        // Check FP register status again
-        bne s0, s1, 2f
-
-        // Restore if they were dirty
-        .attribute arch, "rv64imafdc"
-        fld f0, 0(sp)
-        fld f1, 8(sp)
-        fld f2, 16(sp)
-        fld f3, 24(sp)
-        fld f4, 32(sp)
-        fld f5, 40(sp)
-        fld f6, 48(sp)
-        fld f7, 56(sp)
-        fld f8, 64(sp)
-        fld f9, 72(sp)
-        fld f10, 80(sp)
-        fld f11, 88(sp)
-        fld f12, 96(sp)
-        fld f13, 104(sp)
-        fld f14, 112(sp)
-        fld f15, 120(sp)
-        fld f16, 128(sp)
-        fld f17, 136(sp)
-        fld f18, 144(sp)
-        fld f19, 152(sp)
-        fld f20, 160(sp)
-        fld f21, 168(sp)
-        fld f22, 176(sp)
-        fld f23, 184(sp)
-        fld f24, 192(sp)
-        fld f25, 200(sp)
-        fld f26, 208(sp)
-        fld f27, 216(sp)
-        fld f28, 224(sp)
-        fld f29, 232(sp)
-        fld f30, 240(sp)
-        fld f31, 248(sp)
-        ld t1, 256(sp)
-        fscsr t1
-        .attribute arch, "rv64imac"
-
-        addi sp, sp, 264
-
-        // FP registers clean
-        2:
-
         // Restore `sepc`
         ld t6, 0(sp)
         csrw sepc, t6
@@ -0,0 +1,252 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign};
+
+/// A fixed-size set of small integers, such as hart IDs or interrupt IDs,
+/// stored inline as `WORDS` 64-bit words. Bit `n` of word `w` represents the
+/// value `w * 64 + n`, which matches the layout SBI hart masks and PLIC enable
+/// registers expect.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BitSet<const WORDS: usize> {
+    words: [u64; WORDS],
+}
+
+impl<const WORDS: usize> BitSet<WORDS> {
+    /// Create a new, empty [`BitSet`]
+    pub const fn new() -> Self {
+        Self { words: [0; WORDS] }
+    }
+
+    /// The number of values the set can hold, i.e. one past the largest value
+    /// that can be inserted
+    pub const fn capacity(&self) -> usize {
+        WORDS * u64::BITS as usize
+    }
+
+    /// Insert `value` into the set, returning `true` if it wasn't already
+    /// present
+    ///
+    /// # Panics
+    ///
+    /// Panics if `value` is greater than or equal to [`BitSet::capacity`]
+    pub fn insert(&mut self, value: usize) -> bool {
+        assert!(value < self.capacity(), "value {value} out of range for bit set of capacity {}", self.capacity());
+
+        let (word, bit) = Self::split(value);
+        let was_present = self.words[word] & bit != 0;
+        self.words[word] |= bit;
+
+        !was_present
+    }
+
+    /// Remove `value` from the set, returning `true` if it was present
+    pub fn remove(&mut self, value: usize) -> bool {
+        if !self.contains(value) {
+            return false;
+        }
+
+        let (word, bit) = Self::split(value);
+        self.words[word] &= !bit;
+
+        true
+    }
+
+    /// Returns `true` if `value` is in the set
+    pub fn contains(&self, value: usize) -> bool {
+        let (word, bit) = Self::split(value);
+        matches!(self.words.get(word), Some(word) if word & bit != 0)
+    }
+
+    /// Remove every value from the set
+    pub fn clear(&mut self) {
+        self.words = [0; WORDS];
+    }
+
+    /// Returns `true` if the set contains no values
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|word| *word == 0)
+    }
+
+    /// The number of values in the set
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    /// Iterate over the values in the set in ascending order
+    pub fn iter_ones(&self) -> IterOnes<'_> {
+        IterOnes { words: &self.words, index: 0, current: self.words.first().copied().unwrap_or(0) }
+    }
+
+    /// The values which are in either set
+    pub fn union(&self, other: &Self) -> Self {
+        let mut this = *self;
+        this |= *other;
+        this
+    }
+
+    /// The values which are in both sets
+    pub fn intersection(&self, other: &Self) -> Self {
+        let mut this = *self;
+        this &= *other;
+        this
+    }
+
+    /// The values which are in `self` but not in `other`
+    pub fn difference(&self, other: &Self) -> Self {
+        let mut this = *self;
+        this.words.iter_mut().zip(other.words).for_each(|(word, other)| *word &= !other);
+        this
+    }
+
+    /// The raw words backing the set, e.g. for handing a 64-hart window to an
+    /// SBI call
+    pub const fn words(&self) -> &[u64; WORDS] {
+        &self.words
+    }
+
+    fn split(value: usize) -> (usize, u64) {
+        (value / u64::BITS as usize, 1 << (value % u64::BITS as usize))
+    }
+}
+
+impl<const WORDS: usize> Default for BitSet<WORDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const WORDS: usize> core::fmt::Debug for BitSet<WORDS> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.iter_ones()).finish()
+    }
+}
+
+impl<const WORDS: usize> FromIterator<usize> for BitSet<WORDS> {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut this = Self::new();
+        for value in iter {
+            this.insert(value);
+        }
+        this
+    }
+}
+
+impl<const WORDS: usize> BitOrAssign for BitSet<WORDS> {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.words.iter_mut().zip(rhs.words).for_each(|(word, other)| *word |= other);
+    }
+}
+
+impl<const WORDS: usize> BitOr for BitSet<WORDS> {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        self.union(&rhs)
+    }
+}
+
+impl<const WORDS: usize> BitAndAssign for BitSet<WORDS> {
+    fn bitand_assign(&mut self, rhs: Self) {
+        self.words.iter_mut().zip(rhs.words).for_each(|(word, other)| *word &= other);
+    }
+}
+
+impl<const WORDS: usize> BitAnd for BitSet<WORDS> {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        self.intersection(&rhs)
+    }
+}
+
+/// An iterator over the values in a [`BitSet`] in ascending order, see
+/// [`BitSet::iter_ones`]
+#[derive(Debug, Clone)]
+pub struct IterOnes<'a> {
+    words: &'a [u64],
+    index: usize,
+    /// The bits of `words[index]` which haven't been yielded yet
+    current: u64,
+}
+
+impl Iterator for IterOnes<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.current == 0 {
+            self.index += 1;
+            self.current = *self.words.get(self.index)?;
+        }
+
+        let bit = self.current.trailing_zeros() as usize;
+        self.current &= self.current - 1;
+
+        Some(self.index * u64::BITS as usize + bit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn insert_remove() {
+        let mut set = BitSet::<2>::new();
+        assert_eq!(set.capacity(), 128);
+        assert!(set.is_empty());
+
+        assert!(set.insert(3));
+        assert!(!set.insert(3));
+        assert!(set.insert(127));
+        assert!(set.contains(3));
+        assert!(set.contains(127));
+        assert!(!set.contains(4));
+        assert!(!set.contains(1000));
+        assert_eq!(set.count_ones(), 2);
+
+        assert!(set.remove(3));
+        assert!(!set.remove(3));
+        assert!(!set.remove(1000));
+        assert_eq!(set.count_ones(), 1);
+        assert_eq!(set.words(), &[0, 1 << 63]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_out_of_range() {
+        BitSet::<1>::new().insert(64);
+    }
+
+    #[test]
+    fn set_operations() {
+        let a: BitSet<2> = [0, 5, 64, 100].into_iter().collect();
+        let b: BitSet<2> = [5, 6, 100, 127].into_iter().collect();
+
+        assert_eq!(a.union(&b).iter_ones().collect::<Vec<_>>(), [0, 5, 6, 64, 100, 127]);
+        assert_eq!(a.intersection(&b).iter_ones().collect::<Vec<_>>(), [5, 100]);
+        assert_eq!(a.difference(&b).iter_ones().collect::<Vec<_>>(), [0, 64]);
+        assert_eq!(a | b, a.union(&b));
+        assert_eq!(a & b, a.intersection(&b));
+
+        let mut c = a;
+        c &= b;
+        c |= [1].into_iter().collect();
+        assert_eq!(c.iter_ones().collect::<Vec<_>>(), [1, 5, 100]);
+    }
+
+    #[test]
+    fn iter_ones_in_order() {
+        let set: BitSet<3> = [190, 0, 63, 64, 129, 128].into_iter().collect();
+        assert_eq!(set.iter_ones().collect::<Vec<_>>(), [0, 63, 64, 128, 129, 190]);
+
+        let empty = BitSet::<3>::new();
+        assert_eq!(empty.iter_ones().next(), None);
+        assert_eq!(BitSet::<0>::new().iter_ones().next(), None);
+    }
+}
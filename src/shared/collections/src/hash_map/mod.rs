@@ -100,6 +100,12 @@ where
         self.len
     }
 
+    /// Whether the [`HashMap`] has no entries
+    #[inline(always)]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
     /// Insert a new key and value into the [`HashMap`], returning the previous
     /// entry's value, if the key was previously inserted
     #[inline]
@@ -183,6 +189,11 @@ where
         }
     }
 
+    /// Iterate over the entries of the [`HashMap`] in an unspecified order
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { bucket: self.bucket, index: 0, remaining: self.len, _p: core::marker::PhantomData }
+    }
+
     /// Retrieve the [`Entry`] for the given key, allowing modification &
     /// insertion of a value at the same time
     #[inline]
@@ -374,6 +385,38 @@ where
     }
 }
 
+/// An iterator over the entries of a [`HashMap`], created by [`HashMap::iter`]
+pub struct Iter<'a, K, V> {
+    bucket: NonNull<[Slot<K, V>]>,
+    index: usize,
+    remaining: usize,
+    _p: core::marker::PhantomData<&'a (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.remaining > 0 {
+            let slot = unsafe { self.bucket.get_unchecked_mut(self.index) };
+            self.index += 1;
+
+            if unsafe { Slot::occupied(slot) } {
+                self.remaining -= 1;
+                return Some(unsafe { (Slot::key(slot), Slot::value(slot)) });
+            }
+        }
+
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
 /// A [`HashMap`] entry, which is either occupied or vacant
 #[allow(missing_docs)]
 pub enum Entry<'a, K: 'a, V: 'a> {
@@ -914,6 +957,23 @@ mod tests {
         assert!(hashmap.get_mut("fraz").is_none());
     }
 
+    #[test]
+    fn iter() {
+        let mut hashmap: HashMap<Global, u32, u32, FxBuildHasher> = HashMap::new(Global);
+        assert!(hashmap.is_empty());
+        assert_eq!(hashmap.iter().next(), None);
+
+        for n in 0..100 {
+            hashmap.insert(n, n * 2).unwrap();
+        }
+        hashmap.remove(&50);
+
+        let mut entries: std::vec::Vec<_> = hashmap.iter().map(|(k, v)| (*k, *v)).collect();
+        entries.sort_unstable();
+        assert_eq!(entries, (0..100).filter(|&n| n != 50).map(|n| (n, n * 2)).collect::<std::vec::Vec<_>>());
+        assert_eq!(hashmap.iter().len(), 99);
+    }
+
     #[test]
     fn reserve() {
         let mut hashmap: HashMap<Global, String, u32, FxBuildHasher> = HashMap::new(Global);
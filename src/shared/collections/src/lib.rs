@@ -23,6 +23,8 @@
 #[cfg(any(test, feature = "std"))]
 extern crate std;
 
+/// Fixed-size sets of small integers
+pub mod bitset;
 /// Hash functions
 pub mod hash;
 /// An open-addressed with quadratic probing hash table implementation
@@ -31,3 +33,7 @@ pub mod hash_map;
 pub mod linked_list;
 /// Least-Recently-Used cache
 pub mod lru;
+/// Intrusive lock-free multi-producer single-consumer queue
+pub mod mpsc_queue;
+/// Generational-index arena
+pub mod slab;
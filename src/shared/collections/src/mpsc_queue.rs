@@ -0,0 +1,258 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::{
+    cell::UnsafeCell,
+    ptr::{addr_of, NonNull},
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+/// A node which can be linked into a [`MpscQueue`]
+///
+/// # Ownership
+///
+/// Once a node has been pushed onto a queue, the queue logically owns it: the
+/// node must not be moved, freed, or pushed onto any queue again until it has
+/// been returned by [`MpscQueue::pop`] or [`MpscQueue::try_pop`], at which
+/// point ownership is given back to the caller.
+#[repr(C)]
+pub struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    /// The value contained in the node
+    pub value: T,
+}
+
+impl<T> Node<T> {
+    /// Create a new, unlinked [`Node`] containing the given value
+    pub const fn new(value: T) -> Self {
+        Self { next: AtomicPtr::new(core::ptr::null_mut()), value }
+    }
+}
+
+/// The stub node only ever has its `next` field accessed, so it doesn't need
+/// to contain a `T`. `#[repr(C)]` on both it and [`Node`] guarantees `next` is
+/// at the same offset in each.
+#[repr(C)]
+struct Stub<T> {
+    next: AtomicPtr<Node<T>>,
+}
+
+/// The result of a non-blocking pop from a [`MpscQueue`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryPop<T> {
+    /// A node was removed from the queue
+    Node(NonNull<Node<T>>),
+    /// The queue contained no nodes
+    Empty,
+    /// A producer is in the middle of pushing a node, and the queue cannot be
+    /// popped from until that push completes
+    Inconsistent,
+}
+
+/// An intrusive, lock-free multi-producer single-consumer queue based on
+/// Dmitry Vyukov's MPSC node-based queue design. Pushing never blocks or
+/// allocates, which makes it suitable for enqueuing from interrupt context.
+///
+/// The queue contains its own stub node, so it must not be moved after the
+/// first node has been pushed onto it. Placing it in a `static` or behind a
+/// pointer before use satisfies this.
+pub struct MpscQueue<T> {
+    /// The most recently pushed node, which producers swap themselves into
+    head: AtomicPtr<Node<T>>,
+    /// The oldest node in the queue, only ever accessed by the consumer
+    tail: UnsafeCell<*mut Node<T>>,
+    stub: Stub<T>,
+}
+
+impl<T> MpscQueue<T> {
+    /// Create a new, empty [`MpscQueue`]
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(core::ptr::null_mut()),
+            tail: UnsafeCell::new(core::ptr::null_mut()),
+            stub: Stub { next: AtomicPtr::new(core::ptr::null_mut()) },
+        }
+    }
+
+    /// Push a node onto the end of the queue. This may be called concurrently
+    /// from any number of producers.
+    ///
+    /// # Safety
+    ///
+    /// The node must be valid for the entire time it is contained within the
+    /// queue and must not already be contained within any queue. See the
+    /// ownership rules on [`Node`].
+    pub unsafe fn push(&self, node: NonNull<Node<T>>) {
+        unsafe { self.push_raw(node.as_ptr()) }
+    }
+
+    /// Attempt to pop the oldest node from the queue. If a producer is midway
+    /// through pushing, [`TryPop::Inconsistent`] is returned and the pop may be
+    /// retried later.
+    ///
+    /// # Safety
+    ///
+    /// There must only ever be a single consumer of the queue, that is,
+    /// [`MpscQueue::try_pop`] and [`MpscQueue::pop`] must never be called
+    /// concurrently with each other.
+    pub unsafe fn try_pop(&self) -> TryPop<T> {
+        let stub = self.stub_ptr();
+        // SAFETY: only the single consumer accesses `tail`
+        let tail_ptr = unsafe { &mut *self.tail.get() };
+        if tail_ptr.is_null() {
+            *tail_ptr = stub;
+        }
+
+        let mut tail = *tail_ptr;
+        let mut next = unsafe { Self::next(tail) }.load(Ordering::Acquire);
+
+        if tail == stub {
+            if next.is_null() {
+                return TryPop::Empty;
+            }
+
+            *tail_ptr = next;
+            tail = next;
+            next = unsafe { Self::next(next) }.load(Ordering::Acquire);
+        }
+
+        if !next.is_null() {
+            *tail_ptr = next;
+            // SAFETY: `tail` is not the stub node, so it came from `push`
+            return TryPop::Node(unsafe { NonNull::new_unchecked(tail) });
+        }
+
+        if tail != self.head.load(Ordering::Acquire) {
+            return TryPop::Inconsistent;
+        }
+
+        // `tail` is the only node left in the queue, so push the stub back on
+        // so that `tail` can be unlinked
+        unsafe { self.push_raw(stub) };
+
+        next = unsafe { Self::next(tail) }.load(Ordering::Acquire);
+        match next.is_null() {
+            true => TryPop::Inconsistent,
+            false => {
+                *tail_ptr = next;
+                TryPop::Node(unsafe { NonNull::new_unchecked(tail) })
+            }
+        }
+    }
+
+    /// Pop the oldest node from the queue, returning `None` if the queue is
+    /// empty. If a producer is midway through pushing, this will spin until
+    /// the push completes.
+    ///
+    /// # Safety
+    ///
+    /// See [`MpscQueue::try_pop`]
+    pub unsafe fn pop(&self) -> Option<NonNull<Node<T>>> {
+        loop {
+            match unsafe { self.try_pop() } {
+                TryPop::Node(node) => return Some(node),
+                TryPop::Empty => return None,
+                TryPop::Inconsistent => core::hint::spin_loop(),
+            }
+        }
+    }
+
+    unsafe fn push_raw(&self, node: *mut Node<T>) {
+        unsafe { Self::next(node) }.store(core::ptr::null_mut(), Ordering::Relaxed);
+
+        let prev = match self.head.swap(node, Ordering::AcqRel) {
+            prev if prev.is_null() => self.stub_ptr(),
+            prev => prev,
+        };
+
+        // Between the swap and this store the queue is briefly disconnected,
+        // which the consumer observes as `TryPop::Inconsistent`
+        unsafe { Self::next(prev) }.store(node, Ordering::Release);
+    }
+
+    fn stub_ptr(&self) -> *mut Node<T> {
+        addr_of!(self.stub) as *mut Node<T>
+    }
+
+    /// Access `next` without creating a reference to the entire node, since
+    /// the pointer may be to the stub instead of a full [`Node`]
+    unsafe fn next<'a>(node: *const Node<T>) -> &'a AtomicPtr<Node<T>> {
+        unsafe { &*addr_of!((*node).next) }
+    }
+}
+
+impl<T> Default for MpscQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<T: Send> Send for MpscQueue<T> {}
+unsafe impl<T: Send> Sync for MpscQueue<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{boxed::Box, sync::Arc, vec, vec::Vec};
+
+    #[test]
+    fn fifo_order() {
+        let queue = MpscQueue::new();
+        let nodes: Vec<_> = (0..4).map(|i| NonNull::from(Box::leak(Box::new(Node::new(i))))).collect();
+
+        for node in &nodes {
+            unsafe { queue.push(*node) };
+        }
+
+        for i in 0..4 {
+            let node = unsafe { queue.pop() }.unwrap();
+            assert_eq!(unsafe { Box::from_raw(node.as_ptr()) }.value, i);
+        }
+
+        assert_eq!(unsafe { queue.try_pop() }, TryPop::Empty);
+    }
+
+    #[test]
+    fn multiple_producers() {
+        const PRODUCERS: usize = 4;
+        const PER_PRODUCER: usize = 10_000;
+
+        let queue = Arc::new(MpscQueue::new());
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let queue = Arc::clone(&queue);
+                std::thread::spawn(move || {
+                    for i in 0..PER_PRODUCER {
+                        let node = NonNull::from(Box::leak(Box::new(Node::new((producer, i)))));
+                        unsafe { queue.push(node) };
+                    }
+                })
+            })
+            .collect();
+
+        let mut seen = vec![vec![false; PER_PRODUCER]; PRODUCERS];
+        let mut last_seen = [None; PRODUCERS];
+        let mut received = 0;
+        while received < PRODUCERS * PER_PRODUCER {
+            let Some(node) = (unsafe { queue.pop() }) else { continue };
+            let (producer, i) = unsafe { Box::from_raw(node.as_ptr()) }.value;
+
+            assert!(!seen[producer][i], "node ({producer}, {i}) received twice");
+            assert!(last_seen[producer] < Some(i), "nodes from producer {producer} out of order");
+            seen[producer][i] = true;
+            last_seen[producer] = Some(i);
+            received += 1;
+        }
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        assert!(seen.iter().flatten().all(|seen| *seen));
+        assert_eq!(unsafe { queue.pop() }, None);
+    }
+}
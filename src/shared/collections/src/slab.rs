@@ -0,0 +1,360 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+
+/// A handle to a value stored in a [`Slab`], made up of the index of the slot
+/// the value lives in and the generation of that slot when the value was
+/// inserted. Once the value is removed, the slot's generation changes, so any
+/// outstanding keys for it will no longer resolve to a value, even if the slot
+/// is reused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Key(u64);
+
+impl Key {
+    const fn new(index: u32, generation: u32) -> Self {
+        Self(((generation as u64) << 32) | index as u64)
+    }
+
+    /// Recreate a [`Key`] from the value previously returned by
+    /// [`Key::into_raw`]
+    pub const fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    /// The packed representation of the key, suitable for handing out as an
+    /// opaque ID
+    pub const fn into_raw(self) -> u64 {
+        self.0
+    }
+
+    /// The index of the slot the key refers to
+    pub const fn index(self) -> u32 {
+        self.0 as u32
+    }
+
+    /// The generation of the slot the key refers to
+    pub const fn generation(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+}
+
+enum Slot<T> {
+    Occupied { generation: u32, value: T },
+    Vacant { generation: u32, next_free: Option<u32> },
+}
+
+/// A growable collection of values which are addressed by generational
+/// [`Key`]s, allowing stale keys to be detected after their value has been
+/// removed
+pub struct Slab<A: Allocator, T> {
+    allocator: A,
+    slots: NonNull<Slot<T>>,
+    /// The number of slots which have been initialized, whether occupied or
+    /// vacant
+    initialized: usize,
+    capacity: usize,
+    /// The most recently vacated slot, which links to the next vacant slot
+    free_head: Option<u32>,
+    len: usize,
+}
+
+impl<A: Allocator, T> Slab<A, T> {
+    /// Create a new, empty [`Slab`] which will allocate from the given
+    /// allocator
+    pub const fn new(allocator: A) -> Self {
+        Self { allocator, slots: NonNull::dangling(), initialized: 0, capacity: 0, free_head: None, len: 0 }
+    }
+
+    /// Create a new, empty [`Slab`] with space for at least `capacity` values
+    pub fn with_capacity(allocator: A, capacity: usize) -> Result<Self, AllocError> {
+        let mut this = Self::new(allocator);
+        this.reserve(capacity)?;
+
+        Ok(this)
+    }
+
+    /// The number of values contained within the slab
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the slab contains no values
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of values the slab can contain before it needs to allocate
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Insert a value into the slab, returning the [`Key`] it can be accessed
+    /// with
+    pub fn insert(&mut self, value: T) -> Result<Key, AllocError> {
+        if let Some(index) = self.free_head {
+            let slot = unsafe { &mut *self.slots.as_ptr().add(index as usize) };
+            let Slot::Vacant { generation, next_free } = *slot else { unreachable!("free slot was occupied") };
+
+            self.free_head = next_free;
+            *slot = Slot::Occupied { generation, value };
+            self.len += 1;
+
+            return Ok(Key::new(index, generation));
+        }
+
+        self.reserve(1)?;
+
+        let index = self.initialized;
+        unsafe { self.slots.as_ptr().add(index).write(Slot::Occupied { generation: 0, value }) };
+        self.initialized += 1;
+        self.len += 1;
+
+        Ok(Key::new(index as u32, 0))
+    }
+
+    /// Get a shared reference to the value for `key`, if it's still present
+    pub fn get(&self, key: Key) -> Option<&T> {
+        match self.slot(key.index())? {
+            Slot::Occupied { generation, value } if *generation == key.generation() => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get a unique reference to the value for `key`, if it's still present
+    pub fn get_mut(&mut self, key: Key) -> Option<&mut T> {
+        match self.slot_mut(key.index())? {
+            Slot::Occupied { generation, value } if *generation == key.generation() => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns `true` if the value for `key` is still present
+    pub fn contains_key(&self, key: Key) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Remove the value for `key`, if it's still present, returning it. Any
+    /// copies of `key` will no longer resolve to a value.
+    pub fn remove(&mut self, key: Key) -> Option<T> {
+        let free_head = self.free_head;
+        let slot = self.slot_mut(key.index())?;
+
+        match slot {
+            Slot::Occupied { generation, .. } if *generation == key.generation() => {
+                let vacant = Slot::Vacant { generation: generation.wrapping_add(1), next_free: free_head };
+                let Slot::Occupied { value, .. } = core::mem::replace(slot, vacant) else { unreachable!() };
+
+                self.free_head = Some(key.index());
+                self.len -= 1;
+
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    /// Reserve space for at least `additional` more values
+    pub fn reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        let vacant = self.capacity - self.len;
+        if vacant >= additional {
+            return Ok(());
+        }
+
+        let required = self.len.checked_add(additional).ok_or(AllocError)?;
+        // Slot indices need to fit in a `Key`
+        if required > u32::MAX as usize {
+            return Err(AllocError);
+        }
+
+        let new_capacity = required.max(self.capacity * 2).max(4).min(u32::MAX as usize);
+        let new_layout = Layout::array::<Slot<T>>(new_capacity).map_err(|_| AllocError)?;
+        let new_slots = match self.capacity {
+            0 => self.allocator.allocate(new_layout)?,
+            _ => unsafe { self.allocator.grow(self.slots.cast(), self.layout(), new_layout)? },
+        };
+
+        self.slots = new_slots.cast();
+        self.capacity = new_capacity;
+
+        Ok(())
+    }
+
+    /// Iterate over the keys and values for all of the values in the slab
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { slots: self.initialized_slots().iter(), index: 0 }
+    }
+
+    /// Iterate over the keys and unique references to the values for all of
+    /// the values in the slab
+    pub fn iter_mut(&mut self) -> IterMut<'_, T> {
+        // SAFETY: the first `initialized` slots are always initialized
+        let slots = unsafe { core::slice::from_raw_parts_mut(self.slots.as_ptr(), self.initialized) };
+        IterMut { slots: slots.iter_mut(), index: 0 }
+    }
+
+    fn initialized_slots(&self) -> &[Slot<T>] {
+        // SAFETY: the first `initialized` slots are always initialized
+        unsafe { core::slice::from_raw_parts(self.slots.as_ptr(), self.initialized) }
+    }
+
+    fn slot(&self, index: u32) -> Option<&Slot<T>> {
+        self.initialized_slots().get(index as usize)
+    }
+
+    fn slot_mut(&mut self, index: u32) -> Option<&mut Slot<T>> {
+        match (index as usize) < self.initialized {
+            true => Some(unsafe { &mut *self.slots.as_ptr().add(index as usize) }),
+            false => None,
+        }
+    }
+
+    fn layout(&self) -> Layout {
+        Layout::array::<Slot<T>>(self.capacity).unwrap()
+    }
+}
+
+impl<A: Allocator, T> Drop for Slab<A, T> {
+    fn drop(&mut self) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        unsafe {
+            core::ptr::drop_in_place(core::ptr::slice_from_raw_parts_mut(self.slots.as_ptr(), self.initialized));
+            self.allocator.deallocate(self.slots.cast(), self.layout());
+        }
+    }
+}
+
+unsafe impl<A: Allocator + Send, T: Send> Send for Slab<A, T> {}
+unsafe impl<A: Allocator + Sync, T: Sync> Sync for Slab<A, T> {}
+
+/// See [`Slab::iter`]
+pub struct Iter<'a, T> {
+    slots: core::slice::Iter<'a, Slot<T>>,
+    index: u32,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (Key, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for slot in self.slots.by_ref() {
+            let index = self.index;
+            self.index += 1;
+
+            if let Slot::Occupied { generation, value } = slot {
+                return Some((Key::new(index, *generation), value));
+            }
+        }
+
+        None
+    }
+}
+
+/// See [`Slab::iter_mut`]
+pub struct IterMut<'a, T> {
+    slots: core::slice::IterMut<'a, Slot<T>>,
+    index: u32,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+    type Item = (Key, &'a mut T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for slot in self.slots.by_ref() {
+            let index = self.index;
+            self.index += 1;
+
+            if let Slot::Occupied { generation, value } = slot {
+                return Some((Key::new(index, *generation), value));
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{alloc::Global, rc::Rc, string::String, vec::Vec};
+
+    #[test]
+    fn insert_get_remove() {
+        let mut slab = Slab::new(Global);
+        let a = slab.insert(String::from("a")).unwrap();
+        let b = slab.insert(String::from("b")).unwrap();
+
+        assert_eq!(slab.len(), 2);
+        assert_eq!(slab.get(a).map(String::as_str), Some("a"));
+        slab.get_mut(b).unwrap().push('!');
+        assert_eq!(slab.get(b).map(String::as_str), Some("b!"));
+
+        assert_eq!(slab.remove(a).as_deref(), Some("a"));
+        assert_eq!(slab.remove(a), None);
+        assert_eq!(slab.len(), 1);
+    }
+
+    #[test]
+    fn stale_keys_are_detected() {
+        let mut slab = Slab::new(Global);
+        let first = slab.insert(1).unwrap();
+        assert_eq!(slab.remove(first), Some(1));
+
+        // The slot is reused, but with a new generation
+        let second = slab.insert(2).unwrap();
+        assert_eq!(first.index(), second.index());
+        assert_ne!(first.generation(), second.generation());
+
+        assert_eq!(slab.get(first), None);
+        assert_eq!(slab.get_mut(first), None);
+        assert_eq!(slab.remove(first), None);
+        assert_eq!(slab.get(second), Some(&2));
+        assert_eq!(slab.get(Key::from_raw(second.into_raw())), Some(&2));
+    }
+
+    #[test]
+    fn iterates_live_entries() {
+        let mut slab = Slab::with_capacity(Global, 2).unwrap();
+        let keys: Vec<_> = (0..10).map(|i| slab.insert(i).unwrap()).collect();
+
+        for key in keys.iter().step_by(2) {
+            slab.remove(*key);
+        }
+
+        let live: Vec<_> = slab.iter().map(|(key, value)| (key, *value)).collect();
+        assert_eq!(live, keys.iter().copied().zip(0..10).skip(1).step_by(2).collect::<Vec<_>>());
+
+        for (_, value) in slab.iter_mut() {
+            *value *= 10;
+        }
+
+        assert_eq!(slab.iter().map(|(_, value)| *value).collect::<Vec<_>>(), [10, 30, 50, 70, 90]);
+    }
+
+    #[test]
+    fn drops_values() {
+        let value = Rc::new(());
+        let mut slab = Slab::new(Global);
+        for _ in 0..8 {
+            slab.insert(Rc::clone(&value)).unwrap();
+        }
+
+        let key = slab.insert(Rc::clone(&value)).unwrap();
+        slab.remove(key);
+        assert_eq!(Rc::strong_count(&value), 9);
+
+        drop(slab);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -100,8 +100,22 @@ pub struct CapabilityWithDescription {
 #[repr(C, usize)]
 pub enum CapabilityDescription {
     Channel = 0,
-    Memory { ptr: *mut u8, len: usize, permissions: MemoryPermissions } = 1,
-    MappedMmio { ptr: *mut u8, len: usize, n_interrupts: usize } = 2,
+    Memory {
+        ptr: *mut u8,
+        len: usize,
+        permissions: MemoryPermissions,
+    } = 1,
+    MappedMmio {
+        ptr: *mut u8,
+        len: usize,
+        n_interrupts: usize,
+    } = 2,
+    /// Allows rebooting or powering off the system, see
+    /// [`system_reset`](crate::syscalls::misc::system_reset)
+    SystemReset = 3,
+    /// Allows reading the kernel log, see
+    /// [`read_kernel_log`](crate::syscalls::io::read_kernel_log)
+    KernelLog = 4,
 }
 
 impl Default for CapabilityDescription {
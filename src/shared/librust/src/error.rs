@@ -12,6 +12,7 @@ pub const INVALID_OPERATION: usize = 2;
 pub const INVALID_ARGUMENT: usize = 3;
 pub const WOULD_BLOCK: usize = 4;
 pub const UNKNOWN_SYSCALL: usize = 5;
+pub const OUT_OF_MEMORY: usize = 6;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SyscallError {
@@ -20,6 +21,8 @@ pub enum SyscallError {
     InvalidArgument(u32),
     UnknownSyscall,
     WouldBlock,
+    /// The allocation would put the task over its memory limit
+    OutOfMemory,
 }
 
 impl SyscallError {
@@ -38,6 +41,7 @@ impl SyscallError {
                 RawSyscallError::new(NonZeroUsize::new(UNKNOWN_SYSCALL).unwrap())
             }
             Self::WouldBlock => RawSyscallError::new(NonZeroUsize::new(WOULD_BLOCK).unwrap()),
+            Self::OutOfMemory => RawSyscallError::new(NonZeroUsize::new(OUT_OF_MEMORY).unwrap()),
         }
     }
 }
@@ -83,6 +87,7 @@ impl RawSyscallError {
             INVALID_ARGUMENT => SyscallError::InvalidArgument(self.context() as u32),
             UNKNOWN_SYSCALL => SyscallError::UnknownSyscall,
             WOULD_BLOCK => SyscallError::WouldBlock,
+            OUT_OF_MEMORY => SyscallError::OutOfMemory,
             _ => panic!("invalid syscall error kind"),
         }
     }
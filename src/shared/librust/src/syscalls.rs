@@ -9,7 +9,9 @@ pub mod capabilities;
 pub mod channel;
 pub mod io;
 pub mod mem;
+pub mod misc;
 pub mod task;
+pub mod time;
 pub mod vmspace;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -35,6 +37,26 @@ pub enum Syscall {
     DeleteCapability = 26,
     AllocateSharedMemory = 27,
     DeallocateVirtualMemory = 28,
+    YieldNow = 29,
+    ReadTime = 30,
+    CloneVmspace = 31,
+    PublishSharedMemory = 32,
+    UnpublishSharedMemory = 33,
+    LookupSharedMemory = 34,
+    SetTimer = 35,
+    EnumerateCapabilities = 36,
+    SetAffinity = 37,
+    ReadKernelLog = 38,
+    SetTaskName = 39,
+    GetTaskName = 40,
+    MapVmspaceMmio = 41,
+    GetCpuTime = 42,
+    SetFaultHandler = 43,
+    ResumeFromFault = 44,
+    WaitAnyChannel = 45,
+    SystemReset = 46,
+    SetMemoryLimit = 47,
+    GetHartInfo = 48,
 }
 
 impl Syscall {
@@ -60,6 +82,26 @@ impl Syscall {
             26 => Some(Self::DeleteCapability),
             27 => Some(Self::AllocateSharedMemory),
             28 => Some(Self::DeallocateVirtualMemory),
+            29 => Some(Self::YieldNow),
+            30 => Some(Self::ReadTime),
+            31 => Some(Self::CloneVmspace),
+            32 => Some(Self::PublishSharedMemory),
+            33 => Some(Self::UnpublishSharedMemory),
+            34 => Some(Self::LookupSharedMemory),
+            35 => Some(Self::SetTimer),
+            36 => Some(Self::EnumerateCapabilities),
+            37 => Some(Self::SetAffinity),
+            38 => Some(Self::ReadKernelLog),
+            39 => Some(Self::SetTaskName),
+            40 => Some(Self::GetTaskName),
+            41 => Some(Self::MapVmspaceMmio),
+            42 => Some(Self::GetCpuTime),
+            43 => Some(Self::SetFaultHandler),
+            44 => Some(Self::ResumeFromFault),
+            45 => Some(Self::WaitAnyChannel),
+            46 => Some(Self::SystemReset),
+            47 => Some(Self::SetMemoryLimit),
+            48 => Some(Self::GetHartInfo),
             _ => None,
         }
     }
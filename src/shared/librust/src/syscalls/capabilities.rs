@@ -6,7 +6,7 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{
-    capabilities::CapabilityPtr,
+    capabilities::{CapabilityPtr, CapabilityWithDescription},
     error::{RawSyscallError, SyscallError},
     syscalls::Syscall,
 };
@@ -38,3 +38,47 @@ pub unsafe fn delete(cptr: CapabilityPtr) -> Result<(), SyscallError> {
         None => Ok(()),
     }
 }
+
+/// Fill `buffer` with a snapshot of the calling task's capabilities in
+/// ascending [`CapabilityPtr`] order, returning the total number of
+/// capabilities the task holds. If that's larger than `buffer`, only the first
+/// `buffer.len()` capabilities are written. Passing an empty buffer can be used
+/// to query the number of capabilities.
+#[inline]
+pub fn enumerate_into(buffer: &mut [CapabilityWithDescription]) -> Result<usize, SyscallError> {
+    let error: usize;
+    let total: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::EnumerateCapabilities as usize => error,
+            inlateout("a1") buffer.as_mut_ptr() => total,
+            inlateout("a2") buffer.len() => _,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(total),
+    }
+}
+
+/// Get a snapshot of every capability the calling task holds, see
+/// [`enumerate_into`]
+#[cfg(feature = "alloc")]
+pub fn enumerate() -> Result<alloc::vec::Vec<CapabilityWithDescription>, SyscallError> {
+    let mut capabilities = alloc::vec::Vec::new();
+
+    // Capabilities can be minted by other tasks between the two calls, so
+    // retry until the buffer was large enough
+    loop {
+        let total = enumerate_into(&mut capabilities)?;
+        if total <= capabilities.len() {
+            capabilities.truncate(total);
+            return Ok(capabilities);
+        }
+
+        capabilities.resize(total, CapabilityWithDescription::default());
+    }
+}
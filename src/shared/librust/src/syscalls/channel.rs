@@ -48,9 +48,59 @@ impl core::ops::BitAnd for ChannelReadFlags {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(transparent)]
+pub struct ChannelWriteFlags(usize);
+
+impl ChannelWriteFlags {
+    pub const NONE: Self = Self(0);
+    /// Fail with [`SyscallError::WouldBlock`] instead of waiting for the
+    /// receiver if the channel is full
+    pub const NONBLOCKING: Self = Self(1);
+    /// The capabilities are [`Segment`]s instead of [`Capability`]s, set by
+    /// [`send_segmented`]
+    pub const SEGMENTED: Self = Self(2);
+    /// The message is a request the sender is going to wait for a reply to.
+    /// Until the sender next reads from the channel, its priority is lent to
+    /// the task on the other end so lower priority tasks can't keep that task
+    /// from replying.
+    pub const EXPECTS_REPLY: Self = Self(4);
+
+    pub const fn new(flags: usize) -> Self {
+        Self(flags)
+    }
+
+    pub const fn value(self) -> usize {
+        self.0
+    }
+}
+
+impl core::ops::BitOr for ChannelWriteFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl core::ops::BitAnd for ChannelWriteFlags {
+    type Output = bool;
+
+    fn bitand(self, rhs: Self) -> Self::Output {
+        self.0 & rhs.0 == rhs.0
+    }
+}
+
 /// Attempt to send a message and/or capabilitires on the IPC channel
-/// represented by the given [`CapabilityPtr`]
-pub fn send_message(cptr: CapabilityPtr, message: ChannelMessage, caps: &[Capability]) -> Result<(), SyscallError> {
+/// represented by the given [`CapabilityPtr`]. If the channel is full, this
+/// waits for the receiver to catch up unless [`ChannelWriteFlags::NONBLOCKING`]
+/// is given.
+pub fn send_message(
+    cptr: CapabilityPtr,
+    message: ChannelMessage,
+    caps: &[Capability],
+    flags: ChannelWriteFlags,
+) -> Result<(), SyscallError> {
     let error: usize;
 
     unsafe {
@@ -60,6 +110,55 @@ pub fn send_message(cptr: CapabilityPtr, message: ChannelMessage, caps: &[Capabi
             in("a1") cptr.value(),
             in("a2") caps.as_ptr(),
             in("a3") caps.len(),
+            in("a4") flags.0,
+            in("t0") message.0[0],
+            in("t1") message.0[1],
+            in("t2") message.0[2],
+            in("t3") message.0[3],
+            in("t4") message.0[4],
+            in("t5") message.0[5],
+            in("t6") message.0[6],
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
+/// A byte range of a memory capability, sent as one segment of a
+/// scatter-gather message
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Segment {
+    pub capability: Capability,
+    /// Offset of the segment from the start of the memory
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// Send a message made up of existing memory, without copying it into a single
+/// buffer first. The receiver gets one memory capability for each of the
+/// `segments`, in order, each described as exactly its segment's bytes, and
+/// concatenating them gives the full payload. Every segment must refer to
+/// memory the capability allows reading.
+pub fn send_segmented(
+    cptr: CapabilityPtr,
+    message: ChannelMessage,
+    segments: &[Segment],
+    flags: ChannelWriteFlags,
+) -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::WriteChannel as usize => error,
+            in("a1") cptr.value(),
+            in("a2") segments.as_ptr(),
+            in("a3") segments.len(),
+            in("a4") (flags | ChannelWriteFlags::SEGMENTED).0,
             in("t0") message.0[0],
             in("t1") message.0[1],
             in("t2") message.0[2],
@@ -123,15 +222,52 @@ pub fn read_message(
     }
 }
 
+/// The most channels [`wait_any`] can wait on at once
+pub const MAX_WAIT_CHANNELS: usize = 32;
+
+/// Block until at least one of the channels in `cptrs` has a message waiting,
+/// returning the index of the first one that does. The message isn't read, so
+/// follow up with [`read_message`] on that channel. If `deadline` is given and
+/// the platform timer reaches it first, this fails with
+/// [`SyscallError::WouldBlock`], with the same up-to-a-tick resolution as
+/// [`set_timer`](crate::syscalls::time::set_timer). Like with `set_timer`, a
+/// deadline of zero means there isn't one.
+pub fn wait_any(cptrs: &[CapabilityPtr], deadline: Option<u64>) -> Result<usize, SyscallError> {
+    let error: usize;
+    let index: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::WaitAnyChannel as usize => error,
+            inlateout("a1") cptrs.as_ptr() => index,
+            in("a2") cptrs.len(),
+            in("a3") deadline.unwrap_or(0) as usize,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(index),
+    }
+}
+
 /// A [`CapabilityPtr`] representing the IPC channel to the kernel
 pub const KERNEL_CHANNEL: CapabilityPtr = CapabilityPtr::new(0);
 /// A [`CapabilityPtr`] representing the IPC channel to the parent process
 pub const PARENT_CHANNEL: CapabilityPtr = CapabilityPtr::new(1);
+/// A [`CapabilityPtr`] representing the write-only IPC channel a spawned task
+/// reports its exit status to its parent on, see
+/// [`super::vmspace::spawn_vmspace`]. Tasks started by the kernel don't have
+/// one.
+pub const EXIT_CHANNEL: CapabilityPtr = CapabilityPtr::new(2);
 
 /// See [`KernelMessage::InterruptOccurred`]
 pub const KMSG_INTERRUPT_OCCURRED: usize = 0;
 /// See [`KernelMessage::NewChannelMessage`]
 pub const KMSG_NEW_CHANNEL_MESSAGE: usize = 1;
+/// See [`KernelMessage::TimerExpired`]
+pub const KMSG_TIMER_EXPIRED: usize = 2;
 
 /// A received kernel IPC channel message
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -140,6 +276,8 @@ pub enum KernelMessage {
     InterruptOccurred(usize),
     /// A new channel message is
     NewChannelMessage(CapabilityPtr),
+    /// The deadline set with [`crate::syscalls::time::set_timer`] has passed
+    TimerExpired,
 }
 
 impl KernelMessage {
@@ -148,6 +286,7 @@ impl KernelMessage {
         match self {
             Self::InterruptOccurred(n) => [KMSG_INTERRUPT_OCCURRED, n, 0, 0, 0, 0, 0],
             Self::NewChannelMessage(cptr) => [KMSG_NEW_CHANNEL_MESSAGE, cptr.value(), 0, 0, 0, 0, 0],
+            Self::TimerExpired => [KMSG_TIMER_EXPIRED, 0, 0, 0, 0, 0, 0],
         }
     }
 
@@ -157,6 +296,7 @@ impl KernelMessage {
         match parts[0] {
             KMSG_INTERRUPT_OCCURRED => Self::InterruptOccurred(parts[1]),
             KMSG_NEW_CHANNEL_MESSAGE => Self::NewChannelMessage(CapabilityPtr::new(parts[1])),
+            KMSG_TIMER_EXPIRED => Self::TimerExpired,
             _ => unreachable!(),
         }
     }
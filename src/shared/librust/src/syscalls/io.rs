@@ -132,3 +132,33 @@ pub fn debug_print(value: &[u8]) -> Result<(), SyscallError> {
         None => Ok(()),
     }
 }
+
+/// The [`CapabilityPtr`] of the kernel log capability in `init`, which it can
+/// hand on to the tasks allowed to read the kernel log
+pub const INIT_KERNEL_LOG: CapabilityPtr = CapabilityPtr::new(3);
+
+/// Copy the most recent entries in the kernel log into `buffer`, oldest first,
+/// returning the number of bytes written. Entries are newline terminated and
+/// only entries which fit entirely are copied. Requires the kernel log
+/// capability `cptr` (see [`INIT_KERNEL_LOG`]), failing with
+/// [`SyscallError::InsufficientRights`] otherwise.
+#[inline]
+pub fn read_kernel_log(cptr: CapabilityPtr, buffer: &mut [u8]) -> Result<usize, SyscallError> {
+    let error: usize;
+    let read: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::ReadKernelLog as usize => error,
+            inlateout("a1") cptr.value() => read,
+            in("a2") buffer.as_mut_ptr(),
+            in("a3") buffer.len(),
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(read),
+    }
+}
@@ -130,6 +130,14 @@ pub unsafe fn deallocate_virtual_memory(at: *mut u8) -> Result<(), SyscallError>
     }
 }
 
+/// Allocate a region of zeroed memory which can be shared with other tasks by
+/// sending them the returned [`CapabilityPtr`]. The capability has the rights
+/// matching `perms` along with
+/// [`CapabilityRights::GRANT`](crate::capabilities::CapabilityRights::GRANT),
+/// and can be sent with fewer rights than that (e.g. only
+/// [`CapabilityRights::READ`](crate::capabilities::CapabilityRights::READ)) to
+/// share the memory read-only, but never with more. `perms` must include
+/// [`MemoryPermissions::READ`].
 #[inline]
 pub fn allocate_shared_memory(
     size: Bytes,
@@ -156,6 +164,105 @@ pub fn allocate_shared_memory(
     }
 }
 
+/// The maximum length in bytes of a name shared memory can be published under
+pub const MAX_SHARED_MEMORY_NAME_LENGTH: usize = 64;
+
+/// Publish the shared memory region represented by `cptr` under `name`, so that
+/// other tasks can map it with [`lookup_shared_memory`] without needing to be
+/// sent the capability. The capability must have the
+/// [`CapabilityRights::GRANT`](crate::capabilities::CapabilityRights::GRANT)
+/// right, and tasks looking the region up receive the same rights. Fails if
+/// `name` is empty, longer than [`MAX_SHARED_MEMORY_NAME_LENGTH`], or already
+/// in use.
+#[inline]
+pub fn publish_shared_memory(name: &str, cptr: CapabilityPtr) -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::PublishSharedMemory as usize => error,
+            in("a1") name.as_ptr(),
+            in("a2") name.len(),
+            in("a3") cptr.value(),
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
+/// Remove a name previously published with [`publish_shared_memory`]. Only the
+/// task that published the name can unpublish it. Tasks which have already
+/// looked up the region keep their mapping.
+#[inline]
+pub fn unpublish_shared_memory(name: &str) -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::UnpublishSharedMemory as usize => error,
+            in("a1") name.as_ptr(),
+            in("a2") name.len(),
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
+/// Map the shared memory region published under `name` into the current task,
+/// returning a [`CapabilityPtr`] for it and the mapped memory
+#[inline]
+pub fn lookup_shared_memory(name: &str) -> Result<(CapabilityPtr, *mut [u8]), SyscallError> {
+    let error: usize;
+    let cptr: usize;
+    let virt: *mut u8;
+    let real_size: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::LookupSharedMemory as usize => error,
+            inlateout("a1") name.as_ptr() => cptr,
+            inlateout("a2") name.len() => virt,
+            lateout("a3") real_size,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok((CapabilityPtr::new(cptr), core::ptr::slice_from_raw_parts_mut(virt, real_size))),
+    }
+}
+
+/// Limit the physical memory allocated for the current task to `limit`,
+/// after which allocations fail with [`SyscallError::OutOfMemory`] and touching
+/// memory that hasn't been allocated yet (e.g. more of the stack) faults. The
+/// limit can only be lowered, not raised or removed.
+#[inline]
+pub fn set_memory_limit(limit: Bytes) -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::SetMemoryLimit as usize => error,
+            in("a1") limit.0,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
 /// Allocation options when attempting to allocate a region of
 /// device-addressable memory
 pub struct DmaAllocationOptions(usize);
@@ -200,6 +307,21 @@ impl const core::ops::BitAnd for DmaAllocationOptions {
 pub fn allocate_device_addressable_memory(
     size: Bytes,
     options: DmaAllocationOptions,
+) -> Result<(PhysicalAddress, NonNull<u8>), SyscallError> {
+    allocate_aligned_device_addressable_memory(size, Bytes(0), options)
+}
+
+/// Same as [`allocate_device_addressable_memory`], except the physical address
+/// of the region is a multiple of `alignment`, which must be a power of two no
+/// smaller than a page (or zero, for no extra alignment). This is needed by
+/// devices with stricter requirements on their buffers, e.g. 2 MiB aligned
+/// framebuffers. Fails with [`SyscallError::OutOfMemory`] if there's no free
+/// physical memory with that alignment.
+#[inline]
+pub fn allocate_aligned_device_addressable_memory(
+    size: Bytes,
+    alignment: Bytes,
+    options: DmaAllocationOptions,
 ) -> Result<(PhysicalAddress, NonNull<u8>), SyscallError> {
     let error: usize;
     let phys: usize;
@@ -211,6 +333,7 @@ pub fn allocate_device_addressable_memory(
             inlateout("a0") Syscall::AllocateDeviceAddressableMemory as usize => error,
             inlateout("a1") size.0 => phys,
             inlateout("a2") options.0 => virt,
+            in("a3") alignment.0,
         );
     }
 
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::Syscall;
+use crate::{
+    capabilities::CapabilityPtr,
+    error::{RawSyscallError, SyscallError},
+};
+
+/// The [`CapabilityPtr`] of the system reset capability in `init`, which it
+/// can hand on to the tasks allowed to reboot or power off the system
+pub const INIT_SYSTEM_RESET: CapabilityPtr = CapabilityPtr::new(2);
+
+/// What a [`system_reset`] does to the system
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetKind {
+    Shutdown = 0,
+    ColdReboot = 1,
+    WarmReboot = 2,
+}
+
+impl ResetKind {
+    pub fn from_usize(n: usize) -> Option<Self> {
+        match n {
+            0 => Some(Self::Shutdown),
+            1 => Some(Self::ColdReboot),
+            2 => Some(Self::WarmReboot),
+            _ => None,
+        }
+    }
+}
+
+/// Why a [`system_reset`] happened, which is passed on to the firmware
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum ResetReason {
+    NoReason = 0,
+    SystemFailure = 1,
+}
+
+impl ResetReason {
+    pub fn from_usize(n: usize) -> Option<Self> {
+        match n {
+            0 => Some(Self::NoReason),
+            1 => Some(Self::SystemFailure),
+            _ => None,
+        }
+    }
+}
+
+/// Reboot or power off the system, which requires the system reset capability
+/// `cptr` (see [`INIT_SYSTEM_RESET`]). Only returns if the capability isn't a
+/// system reset capability, with [`SyscallError::InsufficientRights`], or if
+/// the firmware doesn't support the reset.
+#[inline]
+pub fn system_reset(cptr: CapabilityPtr, kind: ResetKind, reason: ResetReason) -> Result<!, SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::SystemReset as usize => error,
+            in("a1") cptr.value(),
+            in("a2") kind as usize,
+            in("a3") reason as usize,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => unreachable!("system reset returned successfully"),
+    }
+}
+
+fn hart_info() -> (usize, usize, usize) {
+    let error: usize;
+    let count: usize;
+    let current: usize;
+    let id_bound: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::GetHartInfo as usize => error,
+            lateout("a1") count,
+            lateout("a2") current,
+            lateout("a3") id_bound,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(_) => unreachable!(),
+        None => (count, current, id_bound),
+    }
+}
+
+/// The number of harts the kernel is running on. Hart IDs don't have to be
+/// contiguous, so use [`hart_id_bound`] for the range they fall in.
+#[inline]
+pub fn hart_count() -> usize {
+    hart_info().0
+}
+
+/// One more than the highest hart ID the kernel can run on, so every hart ID is
+/// in the range `0..hart_id_bound()`. This can be more than [`hart_count`].
+#[inline]
+pub fn hart_id_bound() -> usize {
+    hart_info().2
+}
+
+/// The ID of the hart the current task was running on when it made the
+/// syscall. Tasks can be moved to another hart at any point, including before
+/// this even returns, so this is only a hint unless the task's affinity (see
+/// [`super::task::set_affinity`]) only allows the one hart.
+#[inline]
+pub fn current_hart() -> usize {
+    hart_info().1
+}
@@ -5,7 +5,11 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::{error::RawSyscallError, syscalls::Syscall, task::Tid};
+use crate::{
+    error::{RawSyscallError, SyscallError},
+    syscalls::Syscall,
+    task::{FaultInfo, HartMask, Tid, MAX_TASK_NAME_LEN},
+};
 use core::num::NonZeroUsize;
 
 #[inline(always)]
@@ -44,3 +48,171 @@ pub fn enable_notifications() {
         );
     }
 }
+
+/// Give up the remainder of the current task's time slice without blocking,
+/// returning once the task is scheduled again. If no other task is ready to
+/// run, this returns immediately.
+#[inline]
+pub fn yield_now() -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::YieldNow as usize => error,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
+/// Restrict the current task to only run on the harts in `mask`, moving it to
+/// one of them if it isn't already running on one. Fails with
+/// [`SyscallError::InvalidArgument`] if `mask` doesn't contain any of the
+/// system's harts.
+#[inline]
+pub fn set_affinity(mask: HartMask) -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::SetAffinity as usize => error,
+            in("a1") mask.value() as usize,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
+/// Set the current task's name, which is shown in kernel logs to make it
+/// easier to tell tasks apart. Names longer than [`MAX_TASK_NAME_LEN`] bytes
+/// are truncated.
+#[inline]
+pub fn set_name(name: &str) -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::SetTaskName as usize => error,
+            in("a1") name.as_ptr(),
+            in("a2") name.len(),
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
+/// The name of the task `tid`. Fails with [`SyscallError::InvalidArgument`] if
+/// there's no task with that ID.
+#[inline]
+#[cfg(feature = "alloc")]
+pub fn task_name(tid: Tid) -> Result<alloc::string::String, SyscallError> {
+    let error: usize;
+    let len: usize;
+    let mut buffer = [0u8; MAX_TASK_NAME_LEN];
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::GetTaskName as usize => error,
+            inlateout("a1") tid.value() => len,
+            in("a2") buffer.as_mut_ptr(),
+            in("a3") buffer.len(),
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(alloc::string::String::from_utf8_lossy(&buffer[..len.min(MAX_TASK_NAME_LEN)]).into_owned()),
+    }
+}
+
+/// The CPU time the task `tid` has used so far, in ticks of the platform timer
+/// like [`super::time::read_time`]. Includes the current time slice if the
+/// task is running. Fails with [`SyscallError::InvalidArgument`] if there's no
+/// task with that ID.
+#[inline]
+pub fn cpu_time(tid: Tid) -> Result<u64, SyscallError> {
+    let error: usize;
+    let ticks: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::GetCpuTime as usize => error,
+            inlateout("a1") tid.value() => ticks,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(ticks as u64),
+    }
+}
+
+/// Register `entry` to be run when the task hits a fault it would otherwise be
+/// killed for, such as a page fault on unmapped memory or an illegal
+/// instruction, or unregister the current handler with `None`.
+///
+/// The handler is entered with a [`FaultInfo`] pushed onto the faulting stack,
+/// and `a0` and `sp` both pointing to it. It should either fix the cause of the
+/// fault and return to the task with [`resume_from_fault`], or exit. Returning
+/// from the handler jumps to address zero and faults again, and any fault
+/// while the handler is running kills the task, as does there not being room
+/// on the stack for the [`FaultInfo`] (so stack overflows can't be handled).
+#[inline]
+pub fn set_fault_handler(entry: Option<extern "C" fn(&mut FaultInfo) -> !>) -> Result<(), SyscallError> {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::SetFaultHandler as usize => error,
+            in("a1") entry.map_or(0, |entry| entry as usize),
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(()),
+    }
+}
+
+/// Return from the task's fault handler, restoring the registers in `info` and
+/// continuing at `info.pc`. The floating point registers are restored to what
+/// they were when the fault happened. Only returns if `info` isn't readable or
+/// `info.pc` isn't a userspace address, or if the task isn't handling a fault.
+///
+/// # Safety
+///
+/// This continues execution with arbitrary register values, which must be
+/// valid for the code at `info.pc`, e.g. the unmodified [`FaultInfo`] given to
+/// the handler, after fixing whatever caused the fault.
+#[inline]
+pub unsafe fn resume_from_fault(info: &FaultInfo) -> SyscallError {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::ResumeFromFault as usize => error,
+            in("a1") info as *const FaultInfo,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => error.cook(),
+        None => unreachable!("resumed from a fault without leaving the fault handler"),
+    }
+}
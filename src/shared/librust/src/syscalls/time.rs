@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{error::RawSyscallError, syscalls::Syscall};
+
+/// A reading of the hart's `time` CSR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeReading {
+    /// The number of timer ticks elapsed since an arbitrary point in the past
+    pub ticks: u64,
+    /// The number of timer ticks per second
+    pub frequency: u64,
+}
+
+/// Read the current value of the monotonic platform timer along with its
+/// timebase frequency
+#[inline]
+pub fn read_time() -> TimeReading {
+    let error: usize;
+    let ticks: usize;
+    let frequency: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::ReadTime as usize => error,
+            lateout("a1") ticks,
+            lateout("a2") frequency,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(_) => unreachable!(),
+        None => TimeReading { ticks: ticks as u64, frequency: frequency as u64 },
+    }
+}
+
+/// Request a [`KernelMessage::TimerExpired`] on the kernel channel once the
+/// platform timer reaches `deadline` ticks, replacing any previously set
+/// deadline. Timers are checked on each scheduler tick, so the message may
+/// arrive up to a tick late. A deadline of zero cancels the timer.
+///
+/// [`KernelMessage::TimerExpired`]: crate::syscalls::channel::KernelMessage::TimerExpired
+#[inline]
+pub fn set_timer(deadline: u64) {
+    let error: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::SetTimer as usize => error,
+            in("a1") deadline as usize,
+        );
+    }
+
+    if RawSyscallError::optional(error).is_some() {
+        unreachable!()
+    }
+}
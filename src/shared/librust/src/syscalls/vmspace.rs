@@ -9,6 +9,7 @@ use super::{mem::MemoryPermissions, Syscall};
 use crate::{
     capabilities::CapabilityPtr,
     error::{RawSyscallError, SyscallError},
+    mem::PhysicalAddress,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -25,11 +26,36 @@ impl VmspaceObjectId {
     }
 }
 
+/// The size of the pages used to map a vmspace object
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum PageSize {
+    /// 4 KiB pages
+    Kilo = 0,
+    /// 2 MiB pages
+    Mega = 1,
+    /// 1 GiB pages
+    Giga = 2,
+}
+
 pub struct VmspaceObjectMapping {
     /// The aligned address for the
     pub address: *const u8,
     pub size: usize,
     pub permissions: MemoryPermissions,
+    /// The preferred size of the pages to map the object with. Pages larger
+    /// than [`PageSize::Kilo`] are only used when both the address and size
+    /// of the object are aligned to the page size, and the kernel falls back
+    /// to kilopages if there isn't enough physically contiguous memory
+    /// available for them.
+    pub page_size: PageSize,
+    /// Surround the object with unmapped guard pages, which fault when
+    /// accessed. Guarded objects must let the kernel choose their address.
+    pub guarded: bool,
+    /// Don't allocate any memory for the object until the spawned task first
+    /// accesses each of its pages, which are then zeroed. Lazy objects aren't
+    /// mapped into the calling task, so the returned local address is null.
+    pub lazy: bool,
 }
 
 pub fn create_vmspace() -> Result<VmspaceObjectId, SyscallError> {
@@ -50,6 +76,29 @@ pub fn create_vmspace() -> Result<VmspaceObjectId, SyscallError> {
     }
 }
 
+/// Create a new vmspace containing a copy-on-write clone of the `src`
+/// vmspace. Memory written in either vmspace after the clone is not visible to
+/// the other, with the exception of shared memory, which remains shared
+/// between both. MMIO and DMA regions are not cloned, and the new vmspace
+/// starts out with no capabilities.
+pub fn clone_cow(src: VmspaceObjectId) -> Result<VmspaceObjectId, SyscallError> {
+    let error: usize;
+    let id: usize;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::CloneVmspace as usize => error,
+            inlateout("a1") src.value() => id,
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(VmspaceObjectId::new(id)),
+    }
+}
+
 pub fn alloc_vmspace_object(
     id: VmspaceObjectId,
     mapping: VmspaceObjectMapping,
@@ -66,6 +115,9 @@ pub fn alloc_vmspace_object(
             inlateout("a2") mapping.address => theirs,
             in("a3") mapping.size,
             in("a4") mapping.permissions.value(),
+            in("a5") mapping.page_size as usize,
+            in("a6") mapping.guarded as usize,
+            in("a7") mapping.lazy as usize,
         );
     }
 
@@ -75,6 +127,42 @@ pub fn alloc_vmspace_object(
     }
 }
 
+/// Map the device memory at `phys..phys + len` into the vmspace, returning
+/// the address it was mapped at there. `cptr` must be an MMIO capability which
+/// covers the whole range, such as one returned by
+/// [`super::io::claim_device`], and grants the rights needed for
+/// `permissions`, which must include [`MemoryPermissions::READ`] and can't
+/// include [`MemoryPermissions::EXECUTE`]. `phys` must be page aligned, and
+/// the mapping is rounded up to whole pages, which a capability for a device
+/// smaller than a page covers. Ranges that overlap RAM are always rejected.
+pub fn map_mmio(
+    id: VmspaceObjectId,
+    cptr: CapabilityPtr,
+    phys: PhysicalAddress,
+    len: usize,
+    permissions: MemoryPermissions,
+) -> Result<*mut u8, SyscallError> {
+    let error: usize;
+    let theirs: *mut u8;
+
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("a0") Syscall::MapVmspaceMmio as usize => error,
+            inlateout("a1") id.value() => theirs,
+            in("a2") cptr.value(),
+            in("a3") phys.as_usize(),
+            in("a4") len,
+            in("a5") permissions.value(),
+        );
+    }
+
+    match RawSyscallError::optional(error) {
+        Some(error) => Err(error.cook()),
+        None => Ok(theirs),
+    }
+}
+
 pub struct VmspaceSpawnEnv {
     pub pc: usize,
     pub a0: usize,
@@ -82,30 +170,49 @@ pub struct VmspaceSpawnEnv {
     pub a2: usize,
     pub sp: usize,
     pub tp: usize,
+    /// The number of messages which can be queued in each direction on the
+    /// channel between the parent and the spawned task before sends block,
+    /// or zero to use the kernel's default
+    pub channel_capacity: usize,
+    /// The most physical memory the spawned task can allocate in bytes, see
+    /// [`super::mem::set_memory_limit`], or zero for no limit. Tasks with a
+    /// limit can't spawn tasks with a higher one, so zero gives the spawned
+    /// task the same limit as the current task in that case.
+    pub memory_limit: usize,
 }
 
-pub fn spawn_vmspace(id: VmspaceObjectId, name: &str, env: VmspaceSpawnEnv) -> Result<CapabilityPtr, SyscallError> {
+/// Start a task running in the vmspace, returning the channel to it and the
+/// read-only channel it reports its exit status on, which it sees as
+/// [`super::channel::EXIT_CHANNEL`]
+pub fn spawn_vmspace(
+    id: VmspaceObjectId,
+    name: &str,
+    env: VmspaceSpawnEnv,
+) -> Result<(CapabilityPtr, CapabilityPtr), SyscallError> {
     let error: usize;
     let cptr: usize;
+    let exit_cptr: usize;
 
     unsafe {
         core::arch::asm!(
             "ecall",
             inlateout("a0") Syscall::SpawnVmspace as usize => error,
             inlateout("a1") id.value() => cptr,
-            in("a2") name.as_ptr(),
+            inlateout("a2") name.as_ptr() => exit_cptr,
             in("a3") name.len(),
+            in("a4") env.memory_limit,
             in("t0") env.pc,
             in("t1") env.a0,
             in("t2") env.a1,
             in("t3") env.a2,
             in("t4") env.sp,
             in("t5") env.tp,
+            in("t6") env.channel_capacity,
         );
     }
 
     match RawSyscallError::optional(error) {
         Some(error) => Err(error.cook()),
-        None => Ok(CapabilityPtr::new(cptr)),
+        None => Ok((CapabilityPtr::new(cptr), CapabilityPtr::new(exit_cptr))),
     }
 }
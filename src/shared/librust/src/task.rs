@@ -7,6 +7,9 @@
 
 use core::num::NonZeroUsize;
 
+/// The longest a task's name can be in bytes, longer names are truncated
+pub const MAX_TASK_NAME_LEN: usize = 32;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Tid(usize);
 
@@ -24,4 +27,114 @@ impl core::fmt::Display for Tid {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.0)
     }
-}
\ No newline at end of file
+}
+
+/// A set of harts, e.g. the harts a task is allowed to run on. Bit `n`
+/// represents hart ID `n`, so only harts 0 through 63 can be included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct HartMask(u64);
+
+impl HartMask {
+    /// Every hart, which is the affinity tasks start out with
+    pub const ALL: Self = Self(u64::MAX);
+
+    pub const fn new(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// A mask containing only `hart`
+    pub const fn single(hart: usize) -> Self {
+        Self::empty().with(hart)
+    }
+
+    /// Add `hart` to the mask
+    pub const fn with(self, hart: usize) -> Self {
+        assert!(hart < 64, "hart ID out of range for `HartMask`");
+        Self(self.0 | 1 << hart)
+    }
+
+    pub const fn contains(self, hart: usize) -> bool {
+        hart < 64 && self.0 & (1 << hart) != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The hart IDs in the mask, in ascending order
+    pub fn iter(self) -> impl Iterator<Item = usize> {
+        (0..64).filter(move |hart| self.contains(*hart))
+    }
+
+    pub const fn value(self) -> u64 {
+        self.0
+    }
+}
+
+/// The state of a task when it faulted, which the kernel pushes onto the
+/// task's stack before running the handler registered with
+/// [`set_fault_handler`](crate::syscalls::task::set_fault_handler). The
+/// handler can change any of it, e.g. stepping `pc` past the faulting
+/// instruction, before resuming with
+/// [`resume_from_fault`](crate::syscalls::task::resume_from_fault).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct FaultInfo {
+    /// The raw `scause` value of the fault, see [`FaultInfo::kind`]
+    pub cause: usize,
+    /// The faulting address for page faults, access faults, and misaligned
+    /// accesses, or the instruction bits for illegal instructions (if the
+    /// hardware provides them)
+    pub address: usize,
+    /// The address of the faulting instruction, which is where the task
+    /// resumes
+    pub pc: usize,
+    /// `x1` through `x31`, so register `xN` is at index `N - 1`
+    pub registers: [usize; 31],
+}
+
+impl FaultInfo {
+    pub fn kind(&self) -> Option<FaultKind> {
+        FaultKind::from_cause(self.cause)
+    }
+}
+
+/// The kinds of faults which can be handled by a task's fault handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    InstructionAddressMisaligned,
+    InstructionAccessFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadAddressMisaligned,
+    LoadAccessFault,
+    StoreAddressMisaligned,
+    StoreAccessFault,
+    InstructionPageFault,
+    LoadPageFault,
+    StorePageFault,
+}
+
+impl FaultKind {
+    pub fn from_cause(cause: usize) -> Option<Self> {
+        match cause {
+            0 => Some(Self::InstructionAddressMisaligned),
+            1 => Some(Self::InstructionAccessFault),
+            2 => Some(Self::IllegalInstruction),
+            3 => Some(Self::Breakpoint),
+            4 => Some(Self::LoadAddressMisaligned),
+            5 => Some(Self::LoadAccessFault),
+            6 => Some(Self::StoreAddressMisaligned),
+            7 => Some(Self::StoreAccessFault),
+            12 => Some(Self::InstructionPageFault),
+            13 => Some(Self::LoadPageFault),
+            15 => Some(Self::StorePageFault),
+            _ => None,
+        }
+    }
+}
@@ -8,8 +8,12 @@
 use librust::{
     self,
     capabilities::{CapabilityPtr, CapabilityRights},
-    syscalls::mem::MemoryPermissions,
+    syscalls::{
+        mem::MemoryPermissions,
+        misc::{system_reset, ResetKind, ResetReason, INIT_SYSTEM_RESET},
+    },
 };
+use std::collections::BTreeMap;
 
 static SERVERS: &[u8] = include_bytes!("../../../../build/initfs.tar");
 
@@ -22,8 +26,13 @@ static INIT_ORDER: &[Service] = &[
     // Service { name: "servicemgr", caps: &["devicemgr", "stdio"] },
     // Service { name: "echonet", caps: &["network", "stdio"] },
     Service { name: "fstest", caps: &["filesystem", "stdio"] },
+    // Service { name: "spawntest", caps: &["filesystem", "stdio"] },
 ];
 
+/// Services which claim devices the userspace tests need for themselves, so
+/// they aren't started when running the tests
+static NOT_STARTED_FOR_TESTS: &[&str] = &["filesystem", "fstest"];
+
 struct Service {
     name: &'static str,
     caps: &'static [&'static str],
@@ -33,11 +42,24 @@ fn main() {
     let fdt_ptr = std::env::a2() as *const u8;
     let fdt = unsafe { fdt::Fdt::from_ptr(fdt_ptr).unwrap() };
     let fdt_size = fdt.total_size();
-    let tar = tar::Archive::new(SERVERS).unwrap();
+    // The kernel only passes along an initrd that looks like a tar archive, but
+    // fall back to the built in servers if it's still unusable
+    let tar = match std::env::initrd().map(tar::Archive::new) {
+        Some(Ok(tar)) => {
+            println!("[init] Loading servers from the initrd");
+            tar
+        }
+        Some(Err(e)) => {
+            println!("[init] Bad initrd, using the built in servers: {:?}", e);
+            tar::Archive::new(SERVERS).unwrap()
+        }
+        None => tar::Archive::new(SERVERS).unwrap(),
+    };
 
-    let mut caps = std::collections::BTreeMap::<&'static str, CapabilityPtr>::new();
+    let mut caps = BTreeMap::<&'static str, CapabilityPtr>::new();
+    let testing = std::env::args().any(|arg| arg == "test");
 
-    for server in INIT_ORDER {
+    for server in INIT_ORDER.iter().filter(|server| !(testing && NOT_STARTED_FOR_TESTS.contains(&server.name))) {
         let Some(file) = tar.file(server.name) else { panic!("Couldn't find service: {}", server.name) };
         let (mut space, mut env) = loadelf::load_elf(server.name, &loadelf::Elf::new(file.contents).unwrap()).unwrap();
 
@@ -57,7 +79,79 @@ fn main() {
         env.a0 = 0;
         env.a1 = 0;
 
-        let cap = space.spawn(env).unwrap();
-        caps.insert(server.name, cap);
+        match space.spawn(env) {
+            Ok(cap) => {
+                caps.insert(server.name, cap);
+            }
+            Err(e) => println!("[init] Couldn't spawn {}: {:?}", server.name, e),
+        }
+    }
+
+    if testing {
+        run_tests(&tar, &caps);
+    }
+}
+
+/// Run every `test-*` binary in the archive one after another, which
+/// `cargo xtask test_userspace` packs in with the servers, then power off the
+/// system
+fn run_tests(tar: &tar::Archive<'_>, caps: &BTreeMap<&'static str, CapabilityPtr>) -> ! {
+    let mut failed = 0;
+    let mut total = 1;
+
+    // `cargo xtask test_userspace` packs this in along with the tests, to check
+    // that files are read back from the initrd intact instead of the tests
+    // just not being found
+    match tar.file("initrd-check").map(|file| file.contents) {
+        Some(b"vanadinite initrd check\n") => println!("[init] initrd-check: ok"),
+        _ => {
+            println!("[init] initrd-check: FAILED, the file wasn't read back from the initrd");
+            failed += 1;
+        }
+    }
+
+    for file in tar.files().filter(|file| file.metadata.filename.starts_with("test-")) {
+        let name = file.metadata.filename;
+        total += 1;
+
+        let loaded = loadelf::Elf::new(file.contents).ok().and_then(|elf| loadelf::load_elf(name, &elf).ok());
+        let Some((mut space, mut env)) = loaded else {
+            println!("[init] {}: couldn't load the test binary", name);
+            failed += 1;
+            continue;
+        };
+
+        for cap in ["stdio", "virtiomgr"] {
+            if let Some(&cptr) = caps.get(cap) {
+                space.grant(cap, cptr, CapabilityRights::READ | CapabilityRights::WRITE);
+            }
+        }
+
+        env.a0 = 0;
+        env.a1 = 0;
+
+        match space.spawn_child(env).and_then(std::process::Child::wait) {
+            Ok(status) if status.success() => println!("[init] {}: ok", name),
+            Ok(status) => {
+                println!("[init] {}: FAILED with exit code {}", name, status.code());
+                failed += 1;
+            }
+            Err(e) => {
+                println!("[init] {}: couldn't run the test binary: {:?}", name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("[init] test result: {} passed, {} failed", total - failed, failed);
+
+    let reason = match failed {
+        0 => ResetReason::NoReason,
+        _ => ResetReason::SystemFailure,
+    };
+
+    match system_reset(INIT_SYSTEM_RESET, ResetKind::Shutdown, reason) {
+        Ok(never) => never,
+        Err(e) => panic!("[init] Couldn't power off after running the tests: {:?}", e),
     }
 }
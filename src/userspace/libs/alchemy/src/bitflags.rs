@@ -0,0 +1,232 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+/// Generate a `#[repr(transparent)]` integer newtype with a set of named bit
+/// flags, along with the usual set operations and a [`core::fmt::Debug`] impl
+/// which lists the flags that are set. The generated type also implements
+/// [`crate::PackedStruct`] so it can be used as a field in other packed
+/// structures.
+///
+/// ```rust,ignore
+/// alchemy::bitflags! {
+///     pub struct Permissions: u8 {
+///         const READ = 1 << 0;
+///         const WRITE = 1 << 1;
+///         const EXECUTE = 1 << 2;
+///     }
+/// }
+///
+/// let rw = Permissions::READ | Permissions::WRITE;
+/// assert!(rw.contains(Permissions::READ));
+/// ```
+#[macro_export]
+macro_rules! bitflags {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident: $int:ty {
+            $(
+                $(#[$flag_attr:meta])*
+                const $flag:ident = $value:expr;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, PartialEq, Eq, Hash)]
+        #[repr(transparent)]
+        $vis struct $name($int);
+
+        impl $name {
+            $(
+                $(#[$flag_attr])*
+                pub const $flag: Self = Self($value);
+            )*
+
+            /// A flag set with no flags set
+            #[inline(always)]
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+
+            /// A flag set with every named flag set
+            #[inline(always)]
+            pub const fn all() -> Self {
+                Self(0 $(| Self::$flag.0)*)
+            }
+
+            /// Create a new flag set from the raw bits, retaining any bits
+            /// which don't correspond to a named flag
+            #[inline(always)]
+            pub const fn new(bits: $int) -> Self {
+                Self(bits)
+            }
+
+            /// The raw bits of the flag set
+            #[inline(always)]
+            pub const fn bits(self) -> $int {
+                self.0
+            }
+
+            /// Whether no flags are set
+            #[inline(always)]
+            pub const fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Whether all of the flags in `other` are also set in `self`
+            #[inline(always)]
+            pub const fn contains(self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            /// Whether any of the flags in `other` are also set in `self`
+            #[inline(always)]
+            pub const fn intersects(self, other: Self) -> bool {
+                self.0 & other.0 != 0
+            }
+
+            /// Set all of the flags in `other`
+            #[inline(always)]
+            pub fn insert(&mut self, other: Self) {
+                self.0 |= other.0;
+            }
+
+            /// Clear all of the flags in `other`
+            #[inline(always)]
+            pub fn remove(&mut self, other: Self) {
+                self.0 &= !other.0;
+            }
+
+            /// Set or clear all of the flags in `other` depending on `value`
+            #[inline(always)]
+            pub fn set(&mut self, other: Self, value: bool) {
+                match value {
+                    true => self.insert(other),
+                    false => self.remove(other),
+                }
+            }
+        }
+
+        impl core::ops::BitOr for $name {
+            type Output = Self;
+
+            #[inline(always)]
+            fn bitor(self, rhs: Self) -> Self::Output {
+                Self(self.0 | rhs.0)
+            }
+        }
+
+        impl core::ops::BitOrAssign for $name {
+            #[inline(always)]
+            fn bitor_assign(&mut self, rhs: Self) {
+                self.0 |= rhs.0;
+            }
+        }
+
+        impl core::ops::BitAnd for $name {
+            type Output = Self;
+
+            #[inline(always)]
+            fn bitand(self, rhs: Self) -> Self::Output {
+                Self(self.0 & rhs.0)
+            }
+        }
+
+        impl core::ops::BitAndAssign for $name {
+            #[inline(always)]
+            fn bitand_assign(&mut self, rhs: Self) {
+                self.0 &= rhs.0;
+            }
+        }
+
+        impl core::ops::Not for $name {
+            type Output = Self;
+
+            #[inline(always)]
+            fn not(self) -> Self::Output {
+                Self(!self.0)
+            }
+        }
+
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                let mut remaining = self.0;
+                let mut first = true;
+
+                core::write!(f, "{}(", core::stringify!($name))?;
+                $(
+                    let bits = Self::$flag.0;
+                    if bits != 0 && remaining & bits == bits {
+                        if !first {
+                            core::write!(f, " | ")?;
+                        }
+
+                        core::write!(f, "{}", core::stringify!($flag))?;
+                        remaining &= !bits;
+                        first = false;
+                    }
+                )*
+
+                match (first, remaining) {
+                    (true, remaining) => core::write!(f, "{:#x}", remaining)?,
+                    (false, 0) => {}
+                    (false, remaining) => core::write!(f, " | {:#x}", remaining)?,
+                }
+
+                core::write!(f, ")")
+            }
+        }
+
+        // SAFETY: the type is `#[repr(transparent)]` over a single integer,
+        // which has no padding and for which all bit patterns are valid
+        unsafe impl $crate::OnlyValidBitPatterns for $name {}
+        unsafe impl $crate::PackedStruct for $name {}
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use crate::PackedStruct;
+    use std::format;
+
+    crate::bitflags! {
+        struct TestFlags: u8 {
+            const A = 1 << 0;
+            const B = 1 << 1;
+            const C = 1 << 4;
+        }
+    }
+
+    #[test]
+    fn flag_set_operations() {
+        let mut flags = TestFlags::A | TestFlags::C;
+        assert!(flags.contains(TestFlags::A));
+        assert!(!flags.contains(TestFlags::B));
+        assert!(!flags.contains(TestFlags::A | TestFlags::B));
+        assert!(flags.intersects(TestFlags::A | TestFlags::B));
+
+        flags.insert(TestFlags::B);
+        assert_eq!(flags, TestFlags::all());
+
+        flags.remove(TestFlags::A | TestFlags::C);
+        assert_eq!(flags, TestFlags::B);
+        assert_eq!(flags & TestFlags::A, TestFlags::empty());
+        assert!((flags & TestFlags::A).is_empty());
+
+        flags.set(TestFlags::C, true);
+        assert_eq!(flags.bits(), 0b1_0010);
+        assert_eq!(flags.into_bytes(), [0b1_0010]);
+    }
+
+    #[test]
+    fn debug_lists_set_flags() {
+        assert_eq!(format!("{:?}", TestFlags::A | TestFlags::C), "TestFlags(A | C)");
+        assert_eq!(format!("{:?}", TestFlags::empty()), "TestFlags(0x0)");
+        assert_eq!(format!("{:?}", TestFlags::new(0b1000_0010)), "TestFlags(B | 0x80)");
+        assert_eq!(format!("{:?}", TestFlags::new(0b0100_0000)), "TestFlags(0x40)");
+    }
+}
@@ -9,9 +9,14 @@
 #![allow(incomplete_features)]
 #![feature(arbitrary_self_types, generic_const_exprs)]
 
+#[cfg(test)]
+extern crate self as alchemy;
+
+mod bitflags;
+
 pub use alchemy_derive::PackedStruct;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TryCastError {
     NotLongEnough,
     Underaligned,
@@ -77,6 +82,24 @@ pub unsafe trait PackedStruct: OnlyValidBitPatterns {
         unsafe { &*(self as *const _ as *const U) }
     }
 
+    /// Same as [`PackedStruct::cast_ref`], except that the size of `Self` and
+    /// the alignment of `self` are checked at runtime instead of being
+    /// required by a bound. Prefer [`PackedStruct::cast_ref`] when both types
+    /// are concrete, since the cast can't fail at runtime, and use this when
+    /// the bound can't be expressed, e.g. in generic code where the types
+    /// depend on runtime data.
+    fn try_cast_ref_runtime<U: PackedStruct>(&self) -> Result<&U, TryCastError> {
+        if core::mem::size_of::<Self>() < core::mem::size_of::<U>() {
+            return Err(TryCastError::NotLongEnough);
+        }
+
+        if self as *const _ as usize % core::mem::align_of::<U>() != 0 {
+            return Err(TryCastError::Underaligned);
+        }
+
+        Ok(unsafe { &*(self as *const _ as *const U) })
+    }
+
     fn cast_mut_ref<U: PackedStruct>(&mut self) -> &mut U
     where
         If<
@@ -219,3 +242,63 @@ unsafe impl PackedStruct for i32 {}
 unsafe impl PackedStruct for i64 {}
 unsafe impl PackedStruct for isize {}
 unsafe impl<T: PackedStruct, const N: usize> PackedStruct for [T; N] {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(4))]
+    struct Aligned([u8; 8]);
+
+    #[derive(Debug, Clone, Copy, PackedStruct)]
+    #[repr(C)]
+    struct MixedEndian {
+        #[packed(be)]
+        length: u16,
+        flags: u16,
+        #[packed(le)]
+        checksum: u32,
+    }
+
+    #[test]
+    fn try_cast_ref_runtime() {
+        let aligned = Aligned([0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
+        let bytes: &[u8; 4] = aligned.0[..4].try_into().unwrap();
+
+        assert_eq!(bytes.try_cast_ref_runtime::<u32>(), Ok(&u32::from_ne_bytes([0x01, 0x02, 0x03, 0x04])));
+        assert_eq!(aligned.0.try_cast_ref_runtime::<[u8; 4]>(), Ok(&[0x01, 0x02, 0x03, 0x04]));
+    }
+
+    #[test]
+    fn try_cast_ref_runtime_too_small() {
+        let aligned = Aligned([0; 8]);
+        let bytes: &[u8; 2] = aligned.0[..2].try_into().unwrap();
+
+        assert_eq!(bytes.try_cast_ref_runtime::<u32>(), Err(TryCastError::NotLongEnough));
+        assert_eq!(aligned.0.try_cast_ref_runtime::<[u8; 9]>(), Err(TryCastError::NotLongEnough));
+    }
+
+    #[test]
+    fn try_cast_ref_runtime_underaligned() {
+        let aligned = Aligned([0; 8]);
+        let bytes: &[u8; 4] = aligned.0[1..5].try_into().unwrap();
+
+        assert_eq!(bytes.try_cast_ref_runtime::<u32>(), Err(TryCastError::Underaligned));
+        assert_eq!(bytes.try_cast_ref_runtime::<u8>(), Ok(&0));
+    }
+
+    #[test]
+    fn endian_accessors() {
+        let header = MixedEndian::from_bytes([0x01, 0x02, 0xAA, 0xBB, 0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(header.length(), 0x0102);
+        assert_eq!(header.flags, u16::from_ne_bytes([0xAA, 0xBB]));
+        assert_eq!(header.checksum(), 0x0102_0304);
+
+        // The stored bytes stay in the declared endianness
+        let mut header = MixedEndian::zeroed();
+        header.set_length(0x0506);
+        header.set_checksum(0x0A0B_0C0D);
+        assert_eq!(header.length, 0x0506u16.to_be());
+        assert_eq!(header.into_bytes(), [0x05, 0x06, 0x00, 0x00, 0x0D, 0x0C, 0x0B, 0x0A]);
+    }
+}
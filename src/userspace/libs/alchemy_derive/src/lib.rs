@@ -6,9 +6,9 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use proc_macro2::Span;
-use syn::{Attribute, GenericParam, Generics, ItemStruct, Meta};
+use syn::{Attribute, Field, Fields, GenericParam, Generics, ItemStruct, Meta, Type};
 
-#[proc_macro_derive(PackedStruct)]
+#[proc_macro_derive(PackedStruct, attributes(packed))]
 pub fn derive_packed_struct(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ItemStruct { attrs, ident, mut generics, fields, .. } = syn::parse_macro_input!(input as ItemStruct);
     add_packed_struct_generic_bounds(&mut generics);
@@ -20,6 +20,18 @@ pub fn derive_packed_struct(input: proc_macro::TokenStream) -> proc_macro::Token
         );
     }
 
+    let accessors = match endian_accessors(&fields) {
+        Ok(accessors) => accessors,
+        Err(e) => return proc_macro::TokenStream::from(e.into_compile_error()),
+    };
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let accessors = quote::quote! {
+        impl #impl_generics #ident #ty_generics #where_clause {
+            #accessors
+        }
+    };
+
     let field_asserts = fields.into_iter().enumerate().fold(quote::quote!(), |mut tkns, (i, field)| {
         let ty = &field.ty;
         let name = field.ident.map(|i| format!("`{i}`")).unwrap_or_else(|| format!("#{i}"));
@@ -48,9 +60,94 @@ pub fn derive_packed_struct(input: proc_macro::TokenStream) -> proc_macro::Token
                 panic!(concat!("struct `", stringify!(#ident), "` contains end padding"));
             }
         };
+
+        #accessors
     })
 }
 
+#[derive(Clone, Copy)]
+enum Endian {
+    Big,
+    Little,
+}
+
+/// Generate a getter and setter for each field marked `#[packed(be)]` or
+/// `#[packed(le)]`, which convert to and from the native endianness while the
+/// field itself keeps the bytes in the declared one
+fn endian_accessors(fields: &Fields) -> Result<proc_macro2::TokenStream, syn::Error> {
+    let mut accessors = quote::quote!();
+    for field in fields {
+        let Some((endian, attr)) = field_endian(field)? else { continue };
+        let Some(name) = &field.ident else {
+            return Err(syn::Error::new_spanned(attr, "`#[packed(be)]` and `#[packed(le)]` require a named field"));
+        };
+
+        let ty = &field.ty;
+        if !is_integer(ty) {
+            return Err(syn::Error::new_spanned(ty, "`#[packed(be)]` and `#[packed(le)]` fields must be integers"));
+        }
+
+        let (from, to, which) = match endian {
+            Endian::Big => (quote::quote!(from_be), quote::quote!(to_be), "big"),
+            Endian::Little => (quote::quote!(from_le), quote::quote!(to_le), "little"),
+        };
+        let vis = &field.vis;
+        let setter = quote::format_ident!("set_{}", name);
+        let getter_doc = format!("The value of `{name}`, which is stored as {which} endian");
+        let setter_doc = format!("Set `{name}`, storing it as {which} endian");
+
+        accessors.extend(quote::quote! {
+            #[doc = #getter_doc]
+            #[inline(always)]
+            #vis fn #name(&self) -> #ty {
+                <#ty>::#from(self.#name)
+            }
+
+            #[doc = #setter_doc]
+            #[inline(always)]
+            #vis fn #setter(&mut self, value: #ty) {
+                self.#name = value.#to();
+            }
+        });
+    }
+
+    Ok(accessors)
+}
+
+fn field_endian(field: &Field) -> Result<Option<(Endian, &Attribute)>, syn::Error> {
+    let mut found = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("packed")) {
+        attr.parse_nested_meta(|meta| {
+            let endian = if meta.path.is_ident("be") {
+                Endian::Big
+            } else if meta.path.is_ident("le") {
+                Endian::Little
+            } else {
+                return Err(meta.error("expected `be` or `le`"));
+            };
+
+            match found {
+                Some(_) => Err(meta.error("field endianness specified more than once")),
+                None => {
+                    found = Some((endian, attr));
+                    Ok(())
+                }
+            }
+        })?;
+    }
+
+    Ok(found)
+}
+
+fn is_integer(ty: &Type) -> bool {
+    const INTEGERS: &[&str] = &["u8", "u16", "u32", "u64", "usize", "i8", "i16", "i32", "i64", "isize"];
+
+    match ty {
+        Type::Path(path) if path.qself.is_none() => INTEGERS.iter().any(|int| path.path.is_ident(int)),
+        _ => false,
+    }
+}
+
 fn add_packed_struct_generic_bounds(generics: &mut Generics) {
     for generic in &mut generics.params {
         if let GenericParam::Type(ty) = generic {
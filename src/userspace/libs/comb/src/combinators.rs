@@ -622,3 +622,55 @@ where
         Ok(values)
     }
 }
+
+/// Consume everything up to, but not including, the first `delimiter`,
+/// returning what was consumed. If the delimiter never appears, this consumes
+/// the rest of the input instead of failing, so it also works for the last
+/// field of a line-oriented format that doesn't end in a delimiter. Unlike
+/// [`until`], there's no parser for the values in between, they're taken as
+/// they are.
+pub fn take_until<E, I>(delimiter: I) -> TakeUntil<E, I>
+where
+    I: core::fmt::Debug + Clone + PartialEq,
+    E: Error,
+{
+    TakeUntil { delimiter, _e: core::marker::PhantomData }
+}
+
+pub struct TakeUntil<E, I> {
+    delimiter: I,
+    _e: core::marker::PhantomData<fn() -> E>,
+}
+
+impl<E, I> Clone for TakeUntil<E, I>
+where
+    I: Clone,
+{
+    fn clone(&self) -> Self {
+        Self { delimiter: self.delimiter.clone(), _e: core::marker::PhantomData }
+    }
+}
+
+impl<E, I> Parser for TakeUntil<E, I>
+where
+    I: core::fmt::Debug + Clone + PartialEq,
+    E: Error,
+{
+    type Error = E;
+    type Input = I;
+    type Output = alloc::vec::Vec<I>;
+
+    fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let mut values = alloc::vec::Vec::new();
+
+        while let Some((peek, _)) = stream.peek() {
+            if *peek == self.delimiter {
+                break;
+            }
+
+            values.extend(stream.next().map(|(value, _)| value));
+        }
+
+        Ok(values)
+    }
+}
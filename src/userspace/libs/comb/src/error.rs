@@ -38,6 +38,12 @@ pub trait Error: Sized + core::fmt::Debug {
         Self::custom(format!("unexpected value `{:?}`", value), span)
     }
 
+    /// Attach a description of what was being parsed when the error occurred,
+    /// where `span` is the span of the first input the labelled parser saw
+    fn label(self, label: &'static str, span: Option<Span>) -> Self {
+        Self::custom(format!("{}: {:?}", label, self), span)
+    }
+
     #[doc(hidden)]
     fn hopefully_cheap() -> Self {
         Self::custom("", None)
@@ -60,4 +66,9 @@ impl Error for alloc::string::String {
 
         s
     }
+
+    fn label(self, label: &'static str, _: Option<Span>) -> Self {
+        // The error already says where it happened
+        format!("{}: {}", label, self)
+    }
 }
@@ -100,12 +100,41 @@ pub trait Parser {
         PaddedBy { padding, parser: self }
     }
 
+    /// Skip whitespace and `//` line comments on both sides of this parser,
+    /// including any trailing comment at the end of the input. See
+    /// [`text::padding`].
+    fn ws_padded(self) -> WsPadded<Self, Self::Error, Self::Output>
+    where
+        Self: Sized + Parser<Input = char>,
+    {
+        WsPadded { parser: self, padding: text::padding() }
+    }
+
     fn separated_by<O, P>(self, separator: P) -> SeparatedBy<Self, P, O, Self::Error, Self::Output, Self::Input>
     where
         Self: Sized,
         P: Parser<Error = Self::Error, Output = O, Input = Self::Input>,
     {
-        SeparatedBy { separator, parser: self, trailing: false }
+        SeparatedBy { separator, parser: self, trailing: false, min: 1 }
+    }
+
+    /// Run this parser exactly `n` times, failing with its error if it can't
+    /// be parsed that many times. Nothing past the `n`th repetition is
+    /// consumed, even if more would parse.
+    fn repeated_exactly(self, n: usize) -> Repeated<Self, Self::Error, Self::Output, Self::Input>
+    where
+        Self: Sized,
+    {
+        Repeated { parser: self, min: n, max: Some(n) }
+    }
+
+    /// Run this parser as many times as possible, failing with its error if it
+    /// can't be parsed at least `n` times
+    fn repeated_at_least(self, n: usize) -> Repeated<Self, Self::Error, Self::Output, Self::Input>
+    where
+        Self: Sized,
+    {
+        Repeated { parser: self, min: n, max: None }
     }
 
     fn with_span(self) -> WithSpan<Self, Self::Error, Self::Output, Self::Input>
@@ -114,6 +143,51 @@ pub trait Parser {
     {
         WithSpan { parser: self }
     }
+
+    /// Describe what this parser is parsing, so that if it fails the error
+    /// says what it was in the middle of, e.g. "expected type after `:`"
+    fn label(self, label: &'static str) -> Label<Self, Self::Error, Self::Output, Self::Input>
+    where
+        Self: Sized,
+    {
+        Label { parser: self, label }
+    }
+
+    fn map_err<E, F: Fn(Self::Error) -> E>(self, f: F) -> MapErr<Self, E, F, Self::Error, Self::Output, Self::Input>
+    where
+        Self: Sized,
+        E: Error,
+    {
+        MapErr { parser: self, f }
+    }
+
+    /// Reject values which parsed fine but aren't valid, e.g. a number which is
+    /// out of range, with an error of `message` spanning the rejected input.
+    /// A rejection is rolled back like any other error, so the other branch of
+    /// an [`Parser::or`] can still try the input.
+    fn filter<F: Fn(&Self::Output) -> bool>(
+        self,
+        predicate: F,
+        message: &'static str,
+    ) -> Filter<Self, F, Self::Error, Self::Output, Self::Input>
+    where
+        Self: Sized,
+    {
+        Filter { parser: self, predicate, message }
+    }
+
+    /// Like [`Parser::map`], but `f` can fail, in which case its error becomes
+    /// a parse error spanning the input the value was parsed from
+    fn try_map<U, E, F: Fn(Self::Output) -> Result<U, E>>(
+        self,
+        f: F,
+    ) -> TryMap<Self, U, E, F, Self::Error, Self::Output, Self::Input>
+    where
+        Self: Sized,
+        E: core::fmt::Display,
+    {
+        TryMap { parser: self, f }
+    }
 }
 
 impl<P: Parser> Parser for &'_ P {
@@ -282,7 +356,18 @@ where
 
     #[inline]
     fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
-        self.left.try_parse(stream).or_else(|_| self.right.parse(stream))
+        let (left, left_furthest) = stream.track_furthest(|stream| self.left.try_parse(stream));
+        let left_error = match left {
+            Ok(output) => return Ok(output),
+            Err(error) => error,
+        };
+
+        // Report the error from whichever branch made it further into the
+        // input, since that's most likely the one that was intended
+        match stream.track_furthest(|stream| self.right.parse(stream)) {
+            (Err(_), right_furthest) if left_furthest > right_furthest => Err(left_error),
+            (right, _) => right,
+        }
     }
 }
 
@@ -345,6 +430,33 @@ where
     }
 }
 
+pub struct WsPadded<P, E, O>
+where
+    P: Parser<Error = E, Output = O, Input = char>,
+    E: Error,
+{
+    parser: P,
+    padding: text::Padding<E>,
+}
+
+impl<P, E, O> Parser for WsPadded<P, E, O>
+where
+    P: Parser<Error = E, Output = O, Input = char>,
+    E: Error,
+{
+    type Error = E;
+    type Output = O;
+    type Input = char;
+
+    #[inline]
+    fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        self.padding.parse(stream)?;
+        let parsed = self.parser.parse(stream)?;
+        self.padding.parse(stream)?;
+        Ok(parsed)
+    }
+}
+
 pub struct SeparatedBy<P, S, O2, E, O, I>
 where
     P: Parser<Error = E, Output = O, Input = I>,
@@ -355,6 +467,7 @@ where
     parser: P,
     separator: S,
     trailing: bool,
+    min: usize,
 }
 
 impl<P, S, O2, E, O, I> SeparatedBy<P, S, O2, E, O, I>
@@ -368,6 +481,19 @@ where
         self.trailing = true;
         self
     }
+
+    /// Parse an empty `Vec` instead of failing when the first element doesn't
+    /// parse, without consuming any input. Same as `min(0)`.
+    pub fn allow_empty(self) -> Self {
+        self.min(0)
+    }
+
+    /// Require at least `n` elements, failing with the error of the separator
+    /// or element that's missing otherwise. The default is one.
+    pub fn min(mut self, n: usize) -> Self {
+        self.min = n;
+        self
+    }
 }
 
 impl<P, PD, O2, E, O, I> Parser for SeparatedBy<P, PD, O2, E, O, I>
@@ -383,12 +509,21 @@ where
 
     #[inline]
     fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
-        let mut values = alloc::vec![self.parser.parse(stream)?];
-        if self.separator.try_parse(stream).is_err() {
-            return Ok(values);
+        let mut values = alloc::vec::Vec::with_capacity(self.min);
+        match self.min {
+            0 => match self.parser.try_parse(stream) {
+                Ok(value) => values.push(value),
+                Err(_) => return Ok(values),
+            },
+            _ => values.push(self.parser.parse(stream)?),
+        }
+
+        while values.len() < self.min {
+            self.separator.parse(stream)?;
+            values.push(self.parser.parse(stream)?);
         }
 
-        loop {
+        while self.separator.try_parse(stream).is_ok() {
             match self.trailing {
                 false => values.push(self.parser.parse(stream)?),
                 true => match self.parser.try_parse(stream) {
@@ -396,9 +531,47 @@ where
                     Err(_) => break,
                 },
             }
+        }
+
+        Ok(values)
+    }
+}
+
+pub struct Repeated<P, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    E: Error,
+    I: core::fmt::Debug,
+{
+    parser: P,
+    min: usize,
+    max: Option<usize>,
+}
+
+impl<P, E, O, I> Parser for Repeated<P, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    E: Error,
+    I: core::fmt::Debug + Clone,
+{
+    type Error = E;
+    type Output = alloc::vec::Vec<O>;
+    type Input = I;
+
+    #[inline]
+    fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let mut values = alloc::vec::Vec::with_capacity(self.min);
+        for _ in 0..self.min {
+            values.push(self.parser.parse(stream)?);
+        }
 
-            if self.separator.try_parse(stream).is_err() {
-                break;
+        while !matches!(self.max, Some(max) if values.len() >= max) {
+            let start = stream.position();
+            match self.parser.try_parse(stream) {
+                // A parser which didn't consume anything would match forever
+                Ok(_) if stream.position() == start => break,
+                Ok(value) => values.push(value),
+                Err(_) => break,
             }
         }
 
@@ -427,20 +600,137 @@ where
 
     #[inline]
     fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
-        stream.begin_record_span();
-        match self.parser.parse(stream) {
-            Ok(val) => {
-                let span = stream.end_record_span().unwrap_or_default();
-                Ok((val, span))
-            }
-            Err(e) => {
-                stream.end_record_span();
-                Err(e)
-            }
+        let start = stream.position();
+        let val = self.parser.parse(stream)?;
+        // Anything consumed by a failed `try_parse` has been rolled back by now,
+        // so this is the end of the last element the parser kept
+        let end = stream.previous_end().filter(|&end| end > start).unwrap_or(start);
+
+        Ok((val, Span { start, end }))
+    }
+}
+
+pub struct Label<P, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    E: Error,
+    I: core::fmt::Debug,
+{
+    parser: P,
+    label: &'static str,
+}
+
+impl<P, E, O, I> Parser for Label<P, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    E: Error,
+    I: core::fmt::Debug + Clone,
+{
+    type Error = E;
+    type Output = O;
+    type Input = I;
+
+    #[inline]
+    fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let span = stream.peek().map(|(_, span)| span);
+        self.parser.parse(stream).map_err(|error| error.label(self.label, span))
+    }
+}
+
+pub struct MapErr<P, E2, F, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    F: Fn(E) -> E2,
+    E: Error,
+    E2: Error,
+    I: core::fmt::Debug,
+{
+    parser: P,
+    f: F,
+}
+
+impl<P, E2, F, E, O, I> Parser for MapErr<P, E2, F, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    F: Fn(E) -> E2,
+    E: Error,
+    E2: Error,
+    I: core::fmt::Debug,
+{
+    type Error = E2;
+    type Output = O;
+    type Input = I;
+
+    #[inline]
+    fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        self.parser.parse(stream).map_err(&self.f)
+    }
+}
+
+pub struct Filter<P, F, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    F: Fn(&O) -> bool,
+    E: Error,
+    I: core::fmt::Debug,
+{
+    parser: P,
+    predicate: F,
+    message: &'static str,
+}
+
+impl<P, F, E, O, I> Parser for Filter<P, F, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    F: Fn(&O) -> bool,
+    E: Error,
+    I: core::fmt::Debug + Clone,
+{
+    type Error = E;
+    type Output = O;
+    type Input = I;
+
+    #[inline]
+    fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let (value, span) = (&self.parser).with_span().parse(stream)?;
+        match (self.predicate)(&value) {
+            true => Ok(value),
+            false => Err(E::custom(self.message, Some(span))),
         }
     }
 }
 
+pub struct TryMap<P, U, E2, F, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    F: Fn(O) -> Result<U, E2>,
+    E: Error,
+    E2: core::fmt::Display,
+    I: core::fmt::Debug,
+{
+    parser: P,
+    f: F,
+}
+
+impl<P, U, E2, F, E, O, I> Parser for TryMap<P, U, E2, F, E, O, I>
+where
+    P: Parser<Error = E, Output = O, Input = I>,
+    F: Fn(O) -> Result<U, E2>,
+    E: Error,
+    E2: core::fmt::Display,
+    I: core::fmt::Debug + Clone,
+{
+    type Error = E;
+    type Output = U;
+    type Input = I;
+
+    #[inline]
+    fn parse(&self, stream: &mut Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let (value, span) = (&self.parser).with_span().parse(stream)?;
+        (self.f)(value).map_err(|error| E::custom(error, Some(span)))
+    }
+}
+
 // #[inline]
 // pub fn custom<E, I, O, F>(f: F) -> Custom<E, I, O, F>
 // where
@@ -478,3 +768,209 @@ where
 //         (self.f)(stream)
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::combinators::{many1, sequence, single};
+    use alloc::{string::String, vec::Vec};
+
+    #[test]
+    fn or_reports_furthest_error() {
+        let field = || sequence::<char, String>(&['l', 'e', 't', ' ']).then(single('x')).to(());
+        let keyword = || sequence::<char, String>(&['i', 'f']).to(());
+
+        // The `let` branch gets further before failing, so its error is the one
+        // that's reported even though it's tried first
+        let error = field().or(keyword()).parse(&mut Stream::from_str("let y")).unwrap_err();
+        assert_eq!(error, field().parse(&mut Stream::from_str("let y")).unwrap_err());
+        assert!(error.starts_with("expected one of `'x'` @ 4..5"), "{}", error);
+
+        let error = keyword().or(field()).parse(&mut Stream::from_str("let y")).unwrap_err();
+        assert!(error.starts_with("expected one of `'x'` @ 4..5"), "{}", error);
+
+        // When both branches fail at the same point, the last branch's error is
+        // reported, as before
+        let error = keyword().or(single('z').to(())).parse(&mut Stream::from_str("q")).unwrap_err();
+        assert!(error.starts_with("expected one of `'z'` @ 0..1"), "{}", error);
+
+        assert_eq!(field().or(keyword()).parse(&mut Stream::from_str("if")), Ok(()));
+    }
+
+    #[test]
+    fn label_adds_context() {
+        let field = || sequence::<char, String>(&['l', 'e', 't', ' ']).then(single('x')).to(());
+
+        let error = field().label("while parsing a binding").parse(&mut Stream::from_str("let y")).unwrap_err();
+        assert!(error.starts_with("while parsing a binding: expected one of `'x'` @ 4..5"), "{}", error);
+
+        assert_eq!(field().label("unused").parse(&mut Stream::from_str("let x")), Ok(()));
+    }
+
+    #[test]
+    fn map_err_transforms_errors() {
+        let shouting = single::<char, String>('a').map_err(|error| error.to_uppercase());
+        let error = shouting.parse(&mut Stream::from_str("b")).unwrap_err();
+        assert!(error.starts_with("EXPECTED ONE OF `'A'` @ 0..1"), "{}", error);
+
+        let error = single::<char, String>('a').map_err(|_| ()).parse(&mut Stream::from_str("b"));
+        assert_eq!(error, Err(()));
+    }
+
+    #[test]
+    fn with_span_excludes_rolled_back_input() {
+        // `many1` tries to parse another `a` and rolls back when it sees `b`,
+        // which shouldn't make it into the span
+        let mut stream = Stream::from_str("aab");
+        let (_, span) = many1(single::<char, String>('a')).with_span().parse(&mut stream).unwrap();
+        assert_eq!(span, Span { start: 0, end: 2 });
+        assert_eq!(stream.position(), 2);
+
+        let (_, span) = single::<char, String>('b').with_span().parse(&mut stream).unwrap();
+        assert_eq!(span, Span { start: 2, end: 3 });
+        assert_eq!(stream.position(), 3);
+    }
+
+    #[test]
+    fn repeated_exactly() {
+        let mut stream = Stream::from_str("aaaa");
+        let three = single::<char, String>('a').repeated_exactly(3);
+        assert_eq!(three.parse(&mut stream), Ok(alloc::vec!['a', 'a', 'a']));
+
+        // The fourth `a` is left for whatever comes next
+        assert_eq!(stream.position(), 3);
+        assert_eq!(single::<char, String>('a').parse(&mut stream), Ok('a'));
+
+        let error = three.parse(&mut Stream::from_str("aab")).unwrap_err();
+        assert!(error.starts_with("expected one of `'a'` @ 2..3"), "{}", error);
+        assert!(three.parse(&mut Stream::from_str("")).is_err());
+
+        let none = single::<char, String>('a').repeated_exactly(0);
+        let mut stream = Stream::from_str("a");
+        assert_eq!(none.parse(&mut stream), Ok(Vec::new()));
+        assert_eq!(stream.position(), 0);
+    }
+
+    #[test]
+    fn repeated_at_least() {
+        let two = single::<char, String>('a').repeated_at_least(2);
+
+        let mut stream = Stream::from_str("aaaab");
+        assert_eq!(two.parse(&mut stream), Ok(alloc::vec!['a'; 4]));
+        assert_eq!(stream.position(), 4);
+
+        assert_eq!(two.parse(&mut Stream::from_str("aa")), Ok(alloc::vec!['a', 'a']));
+
+        let error = two.parse(&mut Stream::from_str("ab")).unwrap_err();
+        assert!(error.starts_with("expected one of `'a'` @ 1..2"), "{}", error);
+    }
+
+    #[test]
+    fn repeated_empty_matches() {
+        // The inner parser matches without consuming anything on "b"
+        let nested = single::<char, String>('a').repeated_at_least(0).repeated_at_least(1);
+
+        let mut stream = Stream::from_str("b");
+        assert_eq!(nested.parse(&mut stream), Ok(alloc::vec![Vec::new()]));
+        assert_eq!(stream.position(), 0);
+
+        let mut stream = Stream::from_str("aab");
+        assert_eq!(nested.parse(&mut stream), Ok(alloc::vec![alloc::vec!['a'; 2]]));
+        assert_eq!(stream.position(), 2);
+    }
+
+    #[test]
+    fn separated_by_allows_empty() {
+        let list = || single::<char, String>('a').separated_by(single(',')).allow_empty();
+
+        let mut stream = Stream::from_str("]");
+        assert_eq!(list().parse(&mut stream), Ok(Vec::new()));
+        assert_eq!(stream.position(), 0);
+
+        assert_eq!(list().parse(&mut Stream::from_str("")), Ok(Vec::new()));
+        assert!(single::<char, String>('a').separated_by(single(',')).parse(&mut Stream::from_str("]")).is_err());
+    }
+
+    #[test]
+    fn separated_by_elements() {
+        let list = || single::<char, String>('a').separated_by(single(','));
+
+        let mut stream = Stream::from_str("a]");
+        assert_eq!(list().parse(&mut stream), Ok(alloc::vec!['a']));
+        assert_eq!(stream.position(), 1);
+
+        let mut stream = Stream::from_str("a,a,a]");
+        assert_eq!(list().allow_empty().parse(&mut stream), Ok(alloc::vec!['a'; 3]));
+        assert_eq!(stream.position(), 5);
+
+        let mut stream = Stream::from_str("a,a,]");
+        assert_eq!(list().allow_trailing().parse(&mut stream), Ok(alloc::vec!['a'; 2]));
+        assert_eq!(stream.position(), 4);
+    }
+
+    #[test]
+    fn separated_by_min() {
+        let three = || single::<char, String>('a').separated_by(single(',')).min(3);
+
+        assert_eq!(three().parse(&mut Stream::from_str("a,a,a,a")), Ok(alloc::vec!['a'; 4]));
+
+        let error = three().parse(&mut Stream::from_str("a,a]")).unwrap_err();
+        assert!(error.starts_with("expected one of `','` @ 3..4"), "{}", error);
+
+        let error = three().allow_trailing().parse(&mut Stream::from_str("a,a,]")).unwrap_err();
+        assert!(error.starts_with("expected one of `'a'` @ 4..5"), "{}", error);
+    }
+
+    #[test]
+    fn position_uses_source_spans() {
+        // Tokens from an earlier stage with gaps between them, as if separated
+        // by whitespace
+        let tokens: Vec<(char, Span)> = alloc::vec![
+            ('x', Span { start: 0, end: 3 }),
+            ('y', Span { start: 5, end: 6 }),
+            ('z', Span { start: 9, end: 12 })
+        ];
+        let mut stream = Stream::new(tokens.into_iter());
+
+        assert_eq!(stream.position(), 0);
+        stream.next();
+        assert_eq!(stream.position(), 5);
+
+        let (_, span) = single::<char, String>('y').then(single('z')).with_span().parse(&mut stream).unwrap();
+        assert_eq!(span, Span { start: 5, end: 12 });
+        assert_eq!(stream.position(), 12);
+    }
+
+    #[test]
+    fn filter_rejects_values() {
+        let byte = || text::unsigned::<String>(10).filter(|&n| n <= 255, "value doesn't fit in a `u8`");
+
+        assert_eq!(byte().parse(&mut Stream::from_str("255")), Ok(255));
+
+        let error = byte().parse(&mut Stream::from_str("1024;")).unwrap_err();
+        assert_eq!(error, "value doesn't fit in a `u8` @ 0..4");
+
+        // The rejected input is still there for the other branch
+        let mut stream = Stream::from_str("1024;");
+        assert_eq!(byte().or(text::unsigned(10).map(|n| n / 4)).parse(&mut stream), Ok(256));
+        assert_eq!(stream.position(), 4);
+    }
+
+    #[test]
+    fn try_map_converts_values() {
+        let byte = || text::unsigned::<String>(10).try_map(u8::try_from);
+
+        let mut stream = Stream::from_str("12 300");
+        assert_eq!(byte().parse(&mut stream), Ok(12u8));
+        assert_eq!(stream.position(), 2);
+
+        single::<char, String>(' ').parse(&mut stream).unwrap();
+        let error = byte().parse(&mut stream).unwrap_err();
+        assert_eq!(error, "out of range integral type conversion attempted @ 3..6");
+
+        // Errors from the parser itself are passed through as-is
+        let error = byte().parse(&mut Stream::from_str("x")).unwrap_err();
+        assert_eq!(error, text::unsigned::<String>(10).parse(&mut Stream::from_str("x")).unwrap_err());
+    }
+}
@@ -12,6 +12,19 @@ use alloc::{
 };
 use core::cell::RefCell;
 
+/// Define a parser in terms of itself, for grammars with nested constructs
+/// like arrays of arrays. `f` is given a handle to the parser being defined,
+/// which can be used inside of its own definition, and the returned parser
+/// owns the definition built by `f`.
+///
+/// The handle must only be reached after the definition has consumed some
+/// input, as is the case for bracketed or right-recursive grammars. A
+/// left-recursive definition, where the handle is the first thing that gets
+/// parsed, will recurse forever without consuming anything.
+///
+/// ```rust,ignore
+/// let nested = recursive(|this| delimited(single('['), many0(this), single(']')));
+/// ```
 pub fn recursive<E, I, O, P>(f: impl FnOnce(Recursive<E, I, O>) -> P) -> Recursive<E, I, O>
 where
     E: Error,
@@ -25,6 +38,9 @@ where
     recursive
 }
 
+/// A parser which can refer to itself, see [`recursive`]. The original value
+/// owns the parser definition, while clones of it are weak handles that panic
+/// if they're used after the original is dropped.
 pub struct Recursive<E, I, O> {
     parser: RecursiveInner<E, I, O>,
 }
@@ -35,6 +51,7 @@ impl<E, I, O> Recursive<E, I, O> {
         Self { parser: RecursiveInner::Owner(Rc::new(RefCell::new(None))) }
     }
 
+    /// Set the definition of the parser, replacing any previous definition
     pub fn set<P>(&self, parser: P)
     where
         P: Parser<Error = E, Input = I, Output = O> + 'static,
@@ -75,11 +92,56 @@ where
 
     fn parse(&self, stream: &mut crate::stream::Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
         match &self.parser {
-            RecursiveInner::Owner(owner) => owner.borrow().as_ref().expect("no parser set!").parse(stream),
+            RecursiveInner::Owner(owner) => {
+                owner.borrow().as_ref().expect("recursive parser used before it was defined").parse(stream)
+            }
             RecursiveInner::Borrower(borrowed) => match Weak::upgrade(borrowed) {
-                Some(parser) => parser.borrow().as_ref().expect("no parser set!").parse(stream),
+                Some(parser) => {
+                    parser.borrow().as_ref().expect("recursive parser used before it was defined").parse(stream)
+                }
                 None => panic!("parser called after owner was dropped!"),
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::{
+        combinators::{delimited, many0, single},
+        stream::Stream,
+    };
+    use alloc::{string::String, vec, vec::Vec};
+
+    #[derive(Debug, PartialEq)]
+    struct Nested(Vec<Nested>);
+
+    fn nested() -> Recursive<String, char, Nested> {
+        recursive(|this| delimited(single('['), many0(this), single(']')).map(Nested))
+    }
+
+    #[test]
+    fn arbitrarily_nested_brackets() {
+        let parser = nested();
+
+        assert_eq!(parser.parse(&mut Stream::from_str("[]")), Ok(Nested(vec![])));
+        assert_eq!(parser.parse(&mut Stream::from_str("[[[]]]")), Ok(Nested(vec![Nested(vec![Nested(vec![])])])));
+        assert_eq!(
+            parser.parse(&mut Stream::from_str("[[][[]]]")),
+            Ok(Nested(vec![Nested(vec![]), Nested(vec![Nested(vec![])])]))
+        );
+
+        let deep = "[".repeat(64) + &"]".repeat(64);
+        let mut depth = 0;
+        let mut current = parser.parse(&mut Stream::from_str(&deep)).unwrap();
+        while let Some(inner) = current.0.pop() {
+            depth += 1;
+            current = inner;
+        }
+        assert_eq!(depth, 63);
+
+        assert!(parser.parse(&mut Stream::from_str("[[]")).is_err());
+    }
+}
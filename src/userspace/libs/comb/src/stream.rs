@@ -12,7 +12,12 @@ pub struct Stream<'a, T> {
     pub(crate) buffer: alloc::collections::VecDeque<(T, Span)>,
     pub(crate) mode: StreamMode,
     debug: Option<DebugState>,
-    span: (bool, Option<Span>),
+    /// The span of the last element consumed outside of a transaction, or by
+    /// a transaction which has since been committed
+    consumed: Option<Span>,
+    /// The end of the furthest element consumed, including elements consumed
+    /// by transactions which were later rolled back
+    furthest: Option<usize>,
 }
 
 impl<'a, T> Stream<'a, T>
@@ -30,7 +35,8 @@ where
             buffer: alloc::collections::VecDeque::new(),
             mode: StreamMode::Normal,
             debug: None,
-            span: (false, None),
+            consumed: None,
+            furthest: None,
         }
     }
 
@@ -46,7 +52,8 @@ where
             buffer: alloc::collections::VecDeque::new(),
             mode: StreamMode::Normal,
             debug: Some(DebugState { writer: alloc::boxed::Box::new(writer), try_depth: 0 }),
-            span: (false, None),
+            consumed: None,
+            furthest: None,
         }
     }
 
@@ -63,28 +70,20 @@ where
                     let value = <(T, Span)>::clone(value);
 
                     self.debug_action(DebugAction::TransactionConsume { item: &value.0 }, Some(caller));
-                    if self.span.0 {
-                        match &mut self.span.1 {
-                            Some(span) => span.end = value.1.end,
-                            this @ None => *this = Some(value.1),
-                        }
-                    }
+                    self.record_consumed(value.1);
 
                     Some(value)
                 }
                 None => {
-                    *current += 1;
+                    // Only advance once there's an element, so a transaction
+                    // that runs into the end of the input can still commit
                     self.buffer.push_back(self.source.next()?);
+                    *current += 1;
 
                     let value = self.buffer.back().cloned().unwrap();
 
                     self.debug_action(DebugAction::TransactionConsume { item: &value.0 }, Some(caller));
-                    if self.span.0 {
-                        match &mut self.span.1 {
-                            Some(span) => span.end = value.1.end,
-                            this @ None => *this = Some(value.1),
-                        }
-                    }
+                    self.record_consumed(value.1);
 
                     Some(value)
                 }
@@ -92,24 +91,16 @@ where
             StreamMode::Normal => {
                 if let Some(next) = self.buffer.pop_front() {
                     self.debug_action(DebugAction::NormalConsume { item: &next.0 }, Some(caller));
-                    if self.span.0 {
-                        match &mut self.span.1 {
-                            Some(span) => span.end = next.1.end,
-                            this @ None => *this = Some(next.1),
-                        }
-                    }
+                    self.record_consumed(next.1);
+                    self.consumed = Some(next.1);
                     return Some(next);
                 }
 
                 let value = self.source.next();
                 if let Some(value) = &value {
                     self.debug_action(DebugAction::NormalConsume { item: &value.0 }, Some(caller));
-                    if self.span.0 {
-                        match &mut self.span.1 {
-                            Some(span) => span.end = value.1.end,
-                            this @ None => *this = Some(value.1),
-                        }
-                    }
+                    self.record_consumed(value.1);
+                    self.consumed = Some(value.1);
                 }
 
                 value
@@ -133,22 +124,49 @@ where
                     return self.buffer.front().map(|(peek, span)| (peek, *span));
                 }
 
-                let next = self.next()?;
+                // Pull straight from the source, peeking doesn't consume
+                let next = self.source.next()?;
                 self.buffer.push_front(next);
                 self.buffer.front().map(|(next, span)| (next, *span))
             }
         }
     }
 
+    /// The position of the next element in the original source, or the end of
+    /// the last element if the stream has been exhausted. Positions come from
+    /// the elements' spans, so for a stream of tokens produced by an earlier
+    /// parsing stage this is a byte offset into the text the tokens were lexed
+    /// from, not the number of tokens consumed so far.
+    #[inline]
+    pub fn position(&mut self) -> usize {
+        match self.peek() {
+            Some((_, span)) => span.start,
+            None => self.previous_end().unwrap_or_default(),
+        }
+    }
+
+    /// The end of the last element consumed, taking into account transactions
+    /// which have been rolled back
     #[inline]
-    pub fn begin_record_span(&mut self) {
-        self.span.0 = true;
+    pub(crate) fn previous_end(&self) -> Option<usize> {
+        match self.mode {
+            StreamMode::Transaction { current: current @ 1.., .. } => {
+                self.buffer.get(current - 1).map(|(_, span)| span.end)
+            }
+            _ => self.consumed.map(|span| span.end),
+        }
     }
 
+    /// Run `f`, returning its output along with the end of the furthest element
+    /// it consumed, or `None` if it didn't consume anything
     #[inline]
-    pub fn end_record_span(&mut self) -> Option<Span> {
-        self.span.0 = false;
-        self.span.1.take()
+    pub(crate) fn track_furthest<O>(&mut self, f: impl FnOnce(&mut Self) -> O) -> (O, Option<usize>) {
+        let outer = self.furthest.take();
+        let output = f(self);
+        let furthest = self.furthest;
+        self.furthest = outer.max(furthest);
+
+        (output, furthest)
     }
 
     #[inline]
@@ -196,6 +214,9 @@ where
                     let StreamMode::Transaction { current, .. } = self.mode else { unreachable!() };
                     // Commit to having gotten this far and free memory
                     // associated with already processed elements
+                    if let Some((_, span)) = current.checked_sub(1).and_then(|last| self.buffer.get(last)) {
+                        self.consumed = Some(*span);
+                    }
                     self.buffer.drain(..current);
                     self.debug_action(DebugAction::Commit, None);
                 }
@@ -206,6 +227,11 @@ where
         }
     }
 
+    #[inline]
+    fn record_consumed(&mut self, consumed: Span) {
+        self.furthest = self.furthest.max(Some(consumed.end));
+    }
+
     pub(crate) fn in_try_mode(&self) -> bool {
         matches!(self.mode, StreamMode::Transaction { .. })
     }
@@ -34,6 +34,121 @@ where
     }
 }
 
+/// Parse a line comment starting with `prefix`, e.g. `//`, up to and including
+/// the end of the line. A comment on the last line doesn't need a newline, and
+/// the `\r` of a CRLF line ending is consumed as part of the comment.
+pub fn line_comment<E: Error>(prefix: &'static str) -> LineComment<E> {
+    assert!(!prefix.is_empty(), "line comment prefix can't be empty");
+    LineComment { prefix, _e: core::marker::PhantomData }
+}
+
+pub struct LineComment<E> {
+    prefix: &'static str,
+    _e: core::marker::PhantomData<fn() -> E>,
+}
+
+impl<E> Parser for LineComment<E>
+where
+    E: Error,
+{
+    type Error = E;
+    type Input = char;
+    type Output = ();
+
+    fn parse(&self, stream: &mut crate::stream::Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        for expected in self.prefix.chars() {
+            let (c, span) = stream.next().ok_or_else(|| E::unexpected_end_of_input())?;
+
+            if c != expected {
+                return match stream.in_try_mode() {
+                    false => Err(E::expected_one_of(c, [expected], Some(span))),
+                    true => Err(E::hopefully_cheap()),
+                };
+            }
+        }
+
+        while let Some((c, _)) = stream.next() {
+            if c == '\n' {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse the end of a line, which is a `\n`, a `\r\n`, or the end of the input
+/// so the last line doesn't need a trailing newline. Pairs with
+/// [`take_until`](crate::combinators::take_until) for line-oriented formats.
+pub fn end_of_line<E: Error>() -> EndOfLine<E> {
+    EndOfLine(core::marker::PhantomData)
+}
+
+pub struct EndOfLine<E>(core::marker::PhantomData<fn() -> E>);
+
+impl<E> Parser for EndOfLine<E>
+where
+    E: Error,
+{
+    type Error = E;
+    type Input = char;
+    type Output = ();
+
+    fn parse(&self, stream: &mut crate::stream::Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let (c, span) = match stream.next() {
+            Some(next) => next,
+            None => return Ok(()),
+        };
+
+        let c = match c {
+            '\r' => match stream.next() {
+                Some((c, _)) => c,
+                None => return Err(E::unexpected_end_of_input()),
+            },
+            c => c,
+        };
+
+        match c {
+            '\n' => Ok(()),
+            c => match stream.in_try_mode() {
+                false => Err(E::expected_one_of(c, ['\n'], Some(span))),
+                true => Err(E::hopefully_cheap()),
+            },
+        }
+    }
+}
+
+/// Skip any amount of whitespace and `//` line comments, including none at all.
+/// Since this never fails, it shouldn't be used with
+/// [`Parser::padded_by`](crate::Parser::padded_by), see
+/// [`Parser::ws_padded`](crate::Parser::ws_padded) instead.
+pub fn padding<E: Error>() -> Padding<E> {
+    Padding { comment: line_comment("//") }
+}
+
+pub struct Padding<E> {
+    comment: LineComment<E>,
+}
+
+impl<E> Parser for Padding<E>
+where
+    E: Error,
+{
+    type Error = E;
+    type Input = char;
+    type Output = ();
+
+    fn parse(&self, stream: &mut crate::stream::Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        loop {
+            match stream.peek().map(|(c, _)| *c) {
+                Some(c) if c.is_ascii_whitespace() => drop(stream.next()),
+                Some('/') if self.comment.try_parse(stream).is_ok() => {}
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
 pub fn ascii_alphabetic<E: Error>() -> AsciiAlphabetic<E> {
     AsciiAlphabetic(core::marker::PhantomData)
 }
@@ -195,3 +310,304 @@ where
         }
     }
 }
+
+/// Parse a signed integer literal in the given radix, consisting of an optional
+/// `+` or `-` sign followed by a run of digits. Radix 2, 8, and 16 literals may
+/// also be prefixed with `0b`, `0o`, and `0x` respectively.
+pub fn integer<E: Error>(radix: u32) -> Integer<E> {
+    assert!((2..=36).contains(&radix), "radix must be in the range 2..=36");
+    Integer { radix, allow_underscores: false, _e: core::marker::PhantomData }
+}
+
+pub struct Integer<E> {
+    radix: u32,
+    allow_underscores: bool,
+    _e: core::marker::PhantomData<fn() -> E>,
+}
+
+impl<E> Integer<E> {
+    /// Allow `_` to be used as a separator between digits
+    pub fn allow_underscores(mut self) -> Self {
+        self.allow_underscores = true;
+        self
+    }
+}
+
+impl<E> Parser for Integer<E>
+where
+    E: Error,
+{
+    type Error = E;
+    type Input = char;
+    type Output = i64;
+
+    fn parse(&self, stream: &mut crate::stream::Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let (negative, sign_span) = match stream.peek() {
+            Some((c @ ('-' | '+'), span)) => {
+                let negative = *c == '-';
+                stream.next();
+                (negative, Some(span))
+            }
+            _ => (false, None),
+        };
+
+        let (magnitude, mut span) = digits(stream, self.radix, self.allow_underscores)?;
+        if let Some(sign_span) = sign_span {
+            span.start = sign_span.start;
+        }
+
+        let value = match (negative, magnitude) {
+            (false, Some(magnitude)) => i64::try_from(magnitude).ok(),
+            (true, Some(magnitude)) if magnitude == i64::MIN.unsigned_abs() => Some(i64::MIN),
+            (true, Some(magnitude)) => i64::try_from(magnitude).ok().map(|n| -n),
+            (_, None) => None,
+        };
+
+        value.ok_or_else(|| E::custom("integer literal out of range for `i64`", Some(span)))
+    }
+}
+
+/// Parse an unsigned integer literal in the given radix, consisting of a run of
+/// digits. Radix 2, 8, and 16 literals may also be prefixed with `0b`, `0o`,
+/// and `0x` respectively.
+pub fn unsigned<E: Error>(radix: u32) -> Unsigned<E> {
+    assert!((2..=36).contains(&radix), "radix must be in the range 2..=36");
+    Unsigned { radix, allow_underscores: false, _e: core::marker::PhantomData }
+}
+
+pub struct Unsigned<E> {
+    radix: u32,
+    allow_underscores: bool,
+    _e: core::marker::PhantomData<fn() -> E>,
+}
+
+impl<E> Unsigned<E> {
+    /// Allow `_` to be used as a separator between digits
+    pub fn allow_underscores(mut self) -> Self {
+        self.allow_underscores = true;
+        self
+    }
+}
+
+impl<E> Parser for Unsigned<E>
+where
+    E: Error,
+{
+    type Error = E;
+    type Input = char;
+    type Output = u64;
+
+    fn parse(&self, stream: &mut crate::stream::Stream<'_, Self::Input>) -> Result<Self::Output, Self::Error> {
+        let (value, span) = digits(stream, self.radix, self.allow_underscores)?;
+        value.ok_or_else(|| E::custom("integer literal out of range for `u64`", Some(span)))
+    }
+}
+
+/// Consume an optional radix prefix and a run of digits, returning the value
+/// (or `None` if it overflowed a `u64`) and the span of the literal. Digits are
+/// consumed past an overflow so the span covers the entire literal.
+fn digits<E: Error>(
+    stream: &mut crate::stream::Stream<'_, char>,
+    radix: u32,
+    allow_underscores: bool,
+) -> Result<(Option<u64>, crate::Span), E> {
+    let (first, mut span) = stream.next().ok_or_else(|| E::unexpected_end_of_input())?;
+    let mut value = match first.to_digit(radix) {
+        Some(digit) => Some(u64::from(digit)),
+        None => match stream.in_try_mode() {
+            false => return Err(E::unexpected_value(first, Some(span))),
+            true => return Err(E::hopefully_cheap()),
+        },
+    };
+
+    let prefix = match radix {
+        2 => Some(('b', "binary")),
+        8 => Some(('o', "octal")),
+        16 => Some(('x', "hexadecimal")),
+        _ => None,
+    };
+
+    if let (Some((prefix, name)), '0') = (prefix, first) {
+        if stream.peek().filter(|(c, _)| c.to_ascii_lowercase() == prefix).is_some() {
+            stream.next();
+
+            let (next, next_span) = stream.next().ok_or_else(|| E::unexpected_end_of_input())?;
+            match next.to_digit(radix) {
+                Some(digit) => value = Some(u64::from(digit)),
+                None => {
+                    return Err(E::custom(
+                        alloc::format!("expected a {} digit, found {:?}", name, next),
+                        Some(next_span),
+                    ))
+                }
+            }
+            span.end = next_span.end;
+        }
+    }
+
+    while let Some((&c, c_span)) = stream.peek() {
+        match c.to_digit(radix) {
+            Some(digit) => {
+                value = value
+                    .and_then(|value| value.checked_mul(u64::from(radix)))
+                    .and_then(|value| value.checked_add(u64::from(digit)))
+            }
+            None if c == '_' && allow_underscores => {}
+            None => break,
+        }
+
+        stream.next();
+        span.end = c_span.end;
+    }
+
+    Ok((value, span))
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::stream::Stream;
+    use alloc::string::String;
+
+    fn parse<P: Parser<Input = char>>(parser: P, s: &str) -> Result<P::Output, P::Error> {
+        parser.parse(&mut Stream::from_str(s))
+    }
+
+    #[test]
+    fn line_comments() {
+        let mut stream = Stream::from_str("// a comment\r\nx// trailing");
+        assert_eq!(line_comment::<String>("//").parse(&mut stream), Ok(()));
+        assert_eq!(stream.next().map(|(c, _)| c), Some('x'));
+        assert_eq!(line_comment::<String>("//").parse(&mut stream), Ok(()));
+        assert!(stream.next().is_none());
+
+        assert!(parse(line_comment::<String>("//"), "/ not a comment").is_err());
+        assert!(parse(line_comment::<String>("#"), "// wrong prefix").is_err());
+        assert_eq!(parse(line_comment::<String>("#"), "#"), Ok(()));
+    }
+
+    fn key_value<E: Error>() -> impl Parser<Input = char, Error = E, Output = (String, String)> {
+        use crate::combinators::{single, take_until};
+
+        let key = take_until('=').map(|key| key.into_iter().collect::<String>());
+        let value = take_until('\n').map(|value| value.into_iter().collect::<String>());
+        key.then_assert(single('=')).then(value).then_assert(end_of_line())
+    }
+
+    #[test]
+    fn take_until_delimiter() {
+        use crate::combinators::take_until;
+
+        let mut stream = Stream::from_str("console=ttyS0 debug");
+        let key = take_until::<String, _>('=').parse(&mut stream).unwrap();
+        assert_eq!(key.into_iter().collect::<String>(), "console");
+        assert_eq!(stream.next().map(|(c, _)| c), Some('='));
+
+        // Without the delimiter, the rest of the input is consumed
+        let value = take_until::<String, _>('=').parse(&mut stream).unwrap();
+        assert_eq!(value.into_iter().collect::<String>(), "ttyS0 debug");
+        assert!(stream.next().is_none());
+
+        // And at the delimiter already, nothing is
+        assert_eq!(parse(take_until::<String, _>('='), "=x"), Ok(alloc::vec![]));
+        assert_eq!(parse(take_until::<String, _>('='), ""), Ok(alloc::vec![]));
+    }
+
+    #[test]
+    fn key_value_lines() {
+        let mut stream = Stream::from_str("init=/bin/init\nlog=debug\r\nwatchdog-ticks=100");
+        let lines = crate::combinators::many0(key_value::<String>()).parse(&mut stream).unwrap();
+        assert_eq!(
+            lines,
+            [
+                (String::from("init"), String::from("/bin/init")),
+                (String::from("log"), String::from("debug\r")),
+                (String::from("watchdog-ticks"), String::from("100")),
+            ]
+        );
+        assert!(stream.next().is_none());
+
+        assert!(parse(key_value::<String>(), "no-equals-sign\n").is_err());
+        assert_eq!(parse(key_value::<String>(), "empty="), Ok((String::from("empty"), String::new())));
+    }
+
+    #[test]
+    fn ends_of_lines() {
+        let mut stream = Stream::from_str("\n\r\n");
+        assert_eq!(end_of_line::<String>().parse(&mut stream), Ok(()));
+        assert_eq!(end_of_line::<String>().parse(&mut stream), Ok(()));
+        assert_eq!(end_of_line::<String>().parse(&mut stream), Ok(()));
+
+        assert!(parse(end_of_line::<String>(), "x").is_err());
+        assert!(parse(end_of_line::<String>(), "\rx").is_err());
+    }
+
+    #[test]
+    fn ws_padded() {
+        let token = || crate::combinators::single::<char, String>('x').ws_padded();
+        assert_eq!(parse(token(), "x"), Ok('x'));
+
+        let mut stream = Stream::from_str(" \t\r\n  // leading\r\n\tx  \t\r\n// trailing comment");
+        assert_eq!(token().parse(&mut stream), Ok('x'));
+        assert!(stream.next().is_none());
+
+        let mut stream = Stream::from_str("x / y");
+        assert_eq!(token().parse(&mut stream), Ok('x'));
+        assert_eq!(stream.next().map(|(c, _)| c), Some('/'));
+
+        assert!(parse(token(), "  // x").is_err());
+        assert!(parse(token(), "\n\n").is_err());
+    }
+
+    #[test]
+    fn decimal() {
+        assert_eq!(parse(integer::<String>(10), "12345"), Ok(12345));
+        assert_eq!(parse(integer::<String>(10), "+7 "), Ok(7));
+        assert_eq!(parse(unsigned::<String>(10), "0"), Ok(0));
+        assert_eq!(parse(unsigned::<String>(10), "18446744073709551615"), Ok(u64::MAX));
+        assert_eq!(parse(unsigned::<String>(10).allow_underscores(), "1_000_000"), Ok(1_000_000));
+        assert_eq!(parse(unsigned::<String>(10), "1_000_000"), Ok(1));
+        assert!(parse(unsigned::<String>(10), "_1").is_err());
+        assert!(parse(unsigned::<String>(10), "a").is_err());
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(parse(unsigned::<String>(16), "0xDEADbeef"), Ok(0xDEADBEEF));
+        assert_eq!(parse(unsigned::<String>(16), "ff"), Ok(0xFF));
+        assert_eq!(parse(unsigned::<String>(16).allow_underscores(), "0xFFFF_0000"), Ok(0xFFFF_0000));
+        assert_eq!(parse(integer::<String>(16), "-0x80"), Ok(-0x80));
+        assert!(parse(unsigned::<String>(16), "0x").is_err());
+        assert_eq!(
+            parse(unsigned::<String>(16), "0xg"),
+            Err(String::from("expected a hexadecimal digit, found 'g' @ 2..3"))
+        );
+        assert_eq!(parse(unsigned::<String>(2), "0b2"), Err(String::from("expected a binary digit, found '2' @ 2..3")));
+    }
+
+    #[test]
+    fn negative() {
+        assert_eq!(parse(integer::<String>(10), "-42"), Ok(-42));
+        assert_eq!(parse(integer::<String>(10), "-9223372036854775808"), Ok(i64::MIN));
+        assert_eq!(parse(integer::<String>(10), "9223372036854775807"), Ok(i64::MAX));
+        assert!(parse(unsigned::<String>(10), "-42").is_err());
+        assert!(parse(integer::<String>(10), "-").is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        assert_eq!(
+            parse(unsigned::<String>(10), "18446744073709551616"),
+            Err(String::from("integer literal out of range for `u64` @ 0..20"))
+        );
+        assert_eq!(
+            parse(integer::<String>(10), "-9223372036854775809"),
+            Err(String::from("integer literal out of range for `i64` @ 0..20"))
+        );
+        assert_eq!(
+            parse(integer::<String>(16), "0x8000000000000000"),
+            Err(String::from("integer literal out of range for `i64` @ 0..18"))
+        );
+    }
+}
@@ -6,11 +6,13 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 #![feature(split_array)]
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
 
 pub mod options;
 
 use alchemy::PackedStruct;
-use netstack::ipv4::IpV4Address;
+use core::time::Duration;
+use netstack::{ipv4::IpV4Address, Configuration};
 
 #[derive(Debug, Clone, Copy, PackedStruct)]
 #[repr(C)]
@@ -142,8 +144,12 @@ impl Default for MagicCookie {
 }
 
 pub enum DhcpOption<'a> {
+    SubnetMask(IpV4Address),
     Router(IpV4Address),
     DomainNameServer(options::DomainNameServerList<'a>),
+    /// The lease time in seconds, where `u32::MAX` means the lease never
+    /// expires
+    IpAddressLeaseTime(u32),
     DhcpMessageType(options::DhcpMessageType),
     DhcpServerIdentifier(IpV4Address),
     ParameterRequestList(&'a [u8]),
@@ -152,8 +158,11 @@ pub enum DhcpOption<'a> {
 }
 
 impl DhcpOption<'_> {
+    pub const PAD: u8 = 0;
+    pub const SUBNET_MASK: u8 = 1;
     pub const ROUTER: u8 = 3;
     pub const DOMAIN_NAME_SERVER: u8 = 6;
+    pub const IP_ADDRESS_LEASE_TIME: u8 = 51;
     pub const DHCP_MESSAGE_TYPE: u8 = 53;
     pub const DHCP_SERVER_IDENTIFIER: u8 = 54;
     pub const PARAMETER_REQUEST_LIST: u8 = 55;
@@ -161,8 +170,10 @@ impl DhcpOption<'_> {
 
     pub fn option_id(&self) -> u8 {
         match self {
+            Self::SubnetMask(_) => Self::SUBNET_MASK,
             Self::Router(_) => Self::ROUTER,
             Self::DomainNameServer(_) => Self::DOMAIN_NAME_SERVER,
+            Self::IpAddressLeaseTime(_) => Self::IP_ADDRESS_LEASE_TIME,
             Self::DhcpMessageType(_) => Self::DHCP_MESSAGE_TYPE,
             Self::DhcpServerIdentifier(_) => Self::DHCP_SERVER_IDENTIFIER,
             Self::ParameterRequestList(_) => Self::PARAMETER_REQUEST_LIST,
@@ -210,7 +221,7 @@ impl<'a> DhcpMessageBuilder<'a> {
     pub fn try_push_option(&mut self, option: DhcpOption<'_>) -> Result<(), TryPushOptionError> {
         let option_id = option.option_id();
         match option {
-            DhcpOption::Router(ip) => {
+            DhcpOption::SubnetMask(ip) | DhcpOption::Router(ip) => {
                 self.push_bytes(&[option_id, 4])?;
                 self.push_bytes(ip.as_bytes())?;
             }
@@ -220,6 +231,10 @@ impl<'a> DhcpMessageBuilder<'a> {
                 self.push_bytes(&[option_id, servers_len])?;
                 self.push_bytes(IpV4Address::bytes_of_slice(servers.0))?;
             }
+            DhcpOption::IpAddressLeaseTime(seconds) => {
+                self.push_bytes(&[option_id, 4])?;
+                self.push_bytes(&seconds.to_be_bytes())?;
+            }
             DhcpOption::DhcpMessageType(mtype) => {
                 self.push_bytes(&[option_id, 1, mtype.0])?;
             }
@@ -326,64 +341,65 @@ impl<'a> DhcpMessageParser<'a> {
         let mut done = false;
         let mut data = self.options;
 
-        core::iter::from_fn(move || {
+        core::iter::from_fn(move || loop {
             if done || data.is_empty() {
                 return None;
             }
 
             let option_id = data[0];
-
-            // FIXME: get rid of indexing
-            #[allow(clippy::wildcard_in_or_patterns)]
             match option_id {
-                DhcpOption::ROUTER => {
-                    if data[1] != 4 {
-                        done = true;
-                        return Some(Err(MalformedPacket::MalformedOption(option_id)));
-                    }
-
-                    let ip = match IpV4Address::try_from_byte_slice(&data[2..6]) {
-                        Ok(ip) => *ip,
-                        Err(_) => {
-                            done = true;
-                            return Some(Err(MalformedPacket::MalformedOption(option_id)));
-                        }
-                    };
-                    data = &data[6..];
-                    Some(Ok(DhcpOption::Router(ip)))
-                }
-                DhcpOption::DHCP_MESSAGE_TYPE => {
-                    let message_type = data[2];
-                    data = &data[3..];
-                    Some(Ok(DhcpOption::DhcpMessageType(options::DhcpMessageType::new(message_type))))
-                }
-                DhcpOption::DHCP_SERVER_IDENTIFIER => {
-                    if data[1] != 4 {
-                        done = true;
-                        return Some(Err(MalformedPacket::MalformedOption(option_id)));
-                    }
-
-                    let ip = match IpV4Address::try_from_byte_slice(&data[2..6]) {
-                        Ok(ip) => *ip,
-                        Err(_) => {
-                            done = true;
-                            return Some(Err(MalformedPacket::MalformedOption(option_id)));
-                        }
-                    };
-                    data = &data[6..];
-                    Some(Ok(DhcpOption::DhcpServerIdentifier(ip)))
+                DhcpOption::PAD => {
+                    data = &data[1..];
+                    continue;
                 }
                 DhcpOption::END_OF_OPTIONS => {
                     done = true;
-                    None
-                }
-                _ => {
-                    let len = data[1] as usize;
-                    let option_data = &data[2..][..len];
-                    data = &data[2 + len..];
-                    Some(Ok(DhcpOption::Unknown(option_id, option_data)))
+                    return None;
                 }
+                _ => {}
             }
+
+            // Every other option is followed by its length and then that many
+            // bytes of data
+            let option_data = match data.get(1).and_then(|&len| data.get(2..2 + len as usize)) {
+                Some(option_data) => option_data,
+                None => {
+                    done = true;
+                    return Some(Err(MalformedPacket::Truncated));
+                }
+            };
+            data = &data[2 + option_data.len()..];
+
+            let ip = || match option_data {
+                &[a, b, c, d] => Ok(IpV4Address::new(a, b, c, d)),
+                _ => Err(MalformedPacket::MalformedOption(option_id)),
+            };
+
+            let option = match option_id {
+                DhcpOption::SUBNET_MASK => ip().map(DhcpOption::SubnetMask),
+                DhcpOption::ROUTER => ip().map(DhcpOption::Router),
+                DhcpOption::DOMAIN_NAME_SERVER => match option_data.len() {
+                    len if len == 0 || len % 4 != 0 => Err(MalformedPacket::MalformedOption(option_id)),
+                    _ => IpV4Address::try_slice_from_bytes(option_data)
+                        .map(|servers| DhcpOption::DomainNameServer(options::DomainNameServerList::new(servers)))
+                        .map_err(|_| MalformedPacket::MalformedOption(option_id)),
+                },
+                DhcpOption::IP_ADDRESS_LEASE_TIME => match option_data {
+                    &[a, b, c, d] => Ok(DhcpOption::IpAddressLeaseTime(u32::from_be_bytes([a, b, c, d]))),
+                    _ => Err(MalformedPacket::MalformedOption(option_id)),
+                },
+                DhcpOption::DHCP_MESSAGE_TYPE => match option_data {
+                    &[message_type] => options::DhcpMessageType::try_from(message_type)
+                        .map(DhcpOption::DhcpMessageType)
+                        .map_err(|_| MalformedPacket::MalformedOption(option_id)),
+                    _ => Err(MalformedPacket::MalformedOption(option_id)),
+                },
+                DhcpOption::DHCP_SERVER_IDENTIFIER => ip().map(DhcpOption::DhcpServerIdentifier),
+                _ => Ok(DhcpOption::Unknown(option_id, option_data)),
+            };
+
+            done = option.is_err();
+            return Some(option);
         })
     }
 }
@@ -395,7 +411,189 @@ impl core::ops::Deref for DhcpMessageParser<'_> {
     }
 }
 
+impl TryFrom<&DhcpMessageParser<'_>> for Configuration {
+    type Error = MalformedPacket;
+
+    /// Gather the settings given to the client by a DHCPACK. The subnet mask
+    /// and router options are required, while the DNS servers and lease time
+    /// are left empty if they're missing.
+    fn try_from(parser: &DhcpMessageParser<'_>) -> Result<Self, Self::Error> {
+        match parser.message_type()? {
+            options::DhcpMessageType::ACK => {}
+            message_type => return Err(MalformedPacket::UnexpectedMessageType(message_type)),
+        }
+
+        let mut netmask = None;
+        let mut gateway = None;
+        let mut dns_servers = std::vec::Vec::new();
+        let mut lease_time = None;
+
+        for option in parser.options() {
+            match option? {
+                // Only the first router is used, the rest are in order of
+                // preference
+                DhcpOption::SubnetMask(mask) => netmask = Some(mask),
+                DhcpOption::Router(router) => gateway = gateway.or(Some(router)),
+                DhcpOption::DomainNameServer(servers) => dns_servers.extend(servers.servers()),
+                DhcpOption::IpAddressLeaseTime(u32::MAX) => lease_time = None,
+                DhcpOption::IpAddressLeaseTime(seconds) => lease_time = Some(Duration::from_secs(u64::from(seconds))),
+                _ => {}
+            }
+        }
+
+        Ok(Configuration {
+            address: parser.your_ip_address,
+            netmask: netmask.ok_or(MalformedPacket::MissingOption(DhcpOption::SUBNET_MASK))?,
+            gateway: gateway.ok_or(MalformedPacket::MissingOption(DhcpOption::ROUTER))?,
+            dns_servers,
+            lease_time,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MalformedPacket {
     MissingDhcpMessageType,
     MalformedOption(u8),
+    /// The options end partway through an option
+    Truncated,
+    /// An option required by the message is missing
+    MissingOption(u8),
+    /// The message isn't the type the operation needs, e.g. trying to read the
+    /// configuration out of a DHCPNAK
+    UnexpectedMessageType(options::DhcpMessageType),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    fn packet(options: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0; core::mem::size_of::<DhcpMessage>()];
+        packet.extend_from_slice(options);
+        packet
+    }
+
+    fn ids(parser: &DhcpMessageParser<'_>) -> Vec<Result<u8, MalformedPacket>> {
+        parser.options().map(|option| option.map(|option| option.option_id())).collect()
+    }
+
+    #[test_case]
+    fn parses_options() {
+        let options: [&[u8]; 7] = [
+            &[DhcpOption::PAD],
+            &[DhcpOption::SUBNET_MASK, 4, 255, 255, 255, 0],
+            &[DhcpOption::DHCP_MESSAGE_TYPE, 1, 2],
+            &[DhcpOption::ROUTER, 4, 10, 0, 2, 2],
+            &[99, 3, 1, 2, 3],
+            &[DhcpOption::END_OF_OPTIONS],
+            // Anything after the end is ignored
+            &[DhcpOption::ROUTER],
+        ];
+        let packet = packet(&options.concat());
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+
+        let mut parsed = parser.options().map(Result::unwrap);
+        assert!(
+            matches!(parsed.next(), Some(DhcpOption::SubnetMask(mask)) if mask == IpV4Address::new(255, 255, 255, 0))
+        );
+        assert!(matches!(parsed.next(), Some(DhcpOption::DhcpMessageType(options::DhcpMessageType::OFFER))));
+        assert!(matches!(parsed.next(), Some(DhcpOption::Router(router)) if router == IpV4Address::new(10, 0, 2, 2)));
+        assert!(matches!(parsed.next(), Some(DhcpOption::Unknown(99, &[1, 2, 3]))));
+        assert!(parsed.next().is_none());
+    }
+
+    #[test_case]
+    fn truncated_options_are_an_error() {
+        let truncated: [&[u8]; 5] = [
+            &[DhcpOption::ROUTER],
+            &[DhcpOption::ROUTER, 4, 10, 0, 2],
+            &[DhcpOption::DHCP_MESSAGE_TYPE, 1],
+            &[DhcpOption::SUBNET_MASK, 4, 255, 255, 255, 0, 99, 200, 1],
+            &[99, 255],
+        ];
+
+        for options in truncated {
+            let packet = packet(options);
+            let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+            assert_eq!(ids(&parser).last(), Some(&Err(MalformedPacket::Truncated)), "{:?}", options);
+            assert_eq!(parser.message_type().err(), Some(MalformedPacket::MissingDhcpMessageType));
+        }
+
+        // Options with the wrong length for their type are still malformed
+        let packet = packet(&[DhcpOption::ROUTER, 3, 10, 0, 2, DhcpOption::DHCP_MESSAGE_TYPE, 1, 2]);
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+        assert_eq!(ids(&parser), [Err(MalformedPacket::MalformedOption(DhcpOption::ROUTER))]);
+    }
+
+    fn ack(options: &[&[u8]]) -> Vec<u8> {
+        let mut packet = packet(&options.concat());
+        let message = DhcpMessage::from_bytes_mut::<{ core::mem::size_of::<DhcpMessage>() }>(
+            (&mut packet[..core::mem::size_of::<DhcpMessage>()]).try_into().unwrap(),
+        );
+        message.operation = DhcpOperation::BOOT_REPLY;
+        message.your_ip_address = IpV4Address::new(10, 0, 2, 15);
+        message.magic_cookie = MagicCookie::new();
+        packet
+    }
+
+    #[test_case]
+    fn configuration_from_ack() {
+        // What QEMU's user networking sends back
+        let packet = ack(&[
+            &[DhcpOption::DHCP_MESSAGE_TYPE, 1, 5],
+            &[DhcpOption::DHCP_SERVER_IDENTIFIER, 4, 10, 0, 2, 2],
+            &[DhcpOption::IP_ADDRESS_LEASE_TIME, 4, 0, 1, 81, 128],
+            &[DhcpOption::SUBNET_MASK, 4, 255, 255, 255, 0],
+            &[DhcpOption::ROUTER, 8, 10, 0, 2, 2, 10, 0, 2, 1],
+            &[DhcpOption::DOMAIN_NAME_SERVER, 8, 10, 0, 2, 3, 1, 1, 1, 1],
+            &[DhcpOption::END_OF_OPTIONS],
+        ]);
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+        let config = Configuration::try_from(&parser).unwrap();
+
+        assert_eq!(config.address, IpV4Address::new(10, 0, 2, 15));
+        assert_eq!(config.netmask, IpV4Address::new(255, 255, 255, 0));
+        assert_eq!(config.default_route().gateway, IpV4Address::new(10, 0, 2, 2));
+        assert_eq!(config.dns_servers, [IpV4Address::new(10, 0, 2, 3), IpV4Address::new(1, 1, 1, 1)]);
+        assert_eq!(config.lease_time, Some(Duration::from_secs(86400)));
+    }
+
+    #[test_case]
+    fn incomplete_configuration() {
+        let packet = ack(&[&[DhcpOption::DHCP_MESSAGE_TYPE, 1, 5], &[DhcpOption::SUBNET_MASK, 4, 255, 255, 255, 0]]);
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+        assert_eq!(Configuration::try_from(&parser), Err(MalformedPacket::MissingOption(DhcpOption::ROUTER)));
+
+        let packet = ack(&[&[DhcpOption::DHCP_MESSAGE_TYPE, 1, 5], &[DhcpOption::ROUTER, 4, 10, 0, 2, 2]]);
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+        assert_eq!(Configuration::try_from(&parser), Err(MalformedPacket::MissingOption(DhcpOption::SUBNET_MASK)));
+
+        // An infinite lease, but from an offer rather than an ack
+        let offer: [&[u8]; 4] = [
+            &[DhcpOption::SUBNET_MASK, 4, 255, 255, 255, 0],
+            &[DhcpOption::ROUTER, 4, 10, 0, 2, 2],
+            &[DhcpOption::IP_ADDRESS_LEASE_TIME, 4, 255, 255, 255, 255],
+            &[DhcpOption::DHCP_MESSAGE_TYPE, 1, 2],
+        ];
+        let packet = ack(&offer);
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+        assert_eq!(
+            Configuration::try_from(&parser),
+            Err(MalformedPacket::UnexpectedMessageType(options::DhcpMessageType::OFFER))
+        );
+
+        let packet = ack(&[offer[0], offer[1], offer[2], &[DhcpOption::DHCP_MESSAGE_TYPE, 1, 5]]);
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+        assert_eq!(Configuration::try_from(&parser).unwrap().lease_time, None);
+
+        // DNS server lists have to be a whole number of addresses
+        let packet = ack(&[&[DhcpOption::DHCP_MESSAGE_TYPE, 1, 5], &[DhcpOption::DOMAIN_NAME_SERVER, 3, 1, 1, 1]]);
+        let parser = DhcpMessageParser::from_slice(&packet).unwrap();
+        assert_eq!(
+            Configuration::try_from(&parser),
+            Err(MalformedPacket::MalformedOption(DhcpOption::DOMAIN_NAME_SERVER))
+        );
+    }
 }
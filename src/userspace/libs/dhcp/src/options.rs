@@ -7,7 +7,7 @@
 
 use alchemy::PackedStruct;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, PackedStruct)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, PackedStruct)]
 #[repr(transparent)]
 pub struct DhcpMessageType(pub u8);
 
@@ -34,8 +34,58 @@ impl DhcpMessageType {
     pub fn new(message_type: u8) -> Self {
         Self(message_type)
     }
+
+    /// The name of the message type as it appears in RFC 2132 and its
+    /// extensions, or `None` if the message type is unassigned
+    pub fn as_str(self) -> Option<&'static str> {
+        Some(match self {
+            Self::DISCOVER => "DHCPDISCOVER",
+            Self::OFFER => "DHCPOFFER",
+            Self::REQUEST => "DHCPREQUEST",
+            Self::DECLINE => "DHCPDECLINE",
+            Self::ACK => "DHCPACK",
+            Self::NAK => "DHCPNAK",
+            Self::RELEASE => "DHCPRELEASE",
+            Self::INFORM => "DHCPINFORM",
+            Self::FORCE_RENEW => "DHCPFORCERENEW",
+            Self::LEASE_QUERY => "DHCPLEASEQUERY",
+            Self::LEASE_UNASSIGNED => "DHCPLEASEUNASSIGNED",
+            Self::LEASE_UNKNOWN => "DHCPLEASEUNKNOWN",
+            Self::LEASE_ACTIVE => "DHCPLEASEACTIVE",
+            Self::BULK_LEASE_QUERY => "DHCPBULKLEASEQUERY",
+            Self::LEASE_QUERY_DONE => "DHCPLEASEQUERYDONE",
+            Self::ACTIVE_LEASE_QUERY => "DHCPACTIVELEASEQUERY",
+            Self::LEASE_QUERY_STATUS => "DHCPLEASEQUERYSTATUS",
+            Self::TLS => "DHCPTLS",
+            _ => return None,
+        })
+    }
+}
+
+impl TryFrom<u8> for DhcpMessageType {
+    type Error = UnknownDhcpMessageType;
+
+    fn try_from(message_type: u8) -> Result<Self, Self::Error> {
+        match Self(message_type).as_str() {
+            Some(_) => Ok(Self(message_type)),
+            None => Err(UnknownDhcpMessageType(message_type)),
+        }
+    }
+}
+
+impl core::fmt::Debug for DhcpMessageType {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.as_str() {
+            Some(name) => f.write_str(name),
+            None => f.debug_tuple("DhcpMessageType").field(&self.0).finish(),
+        }
+    }
 }
 
+/// A DHCP message type value which isn't assigned to any message type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownDhcpMessageType(pub u8);
+
 pub struct DomainNameServerList<'a>(pub(crate) &'a [super::IpV4Address]);
 
 impl<'a> DomainNameServerList<'a> {
@@ -61,3 +111,54 @@ impl DhcpServerIdentifier {
         self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use std::string::String;
+
+    fn debug(message_type: DhcpMessageType) -> String {
+        let mut debug = String::new();
+        write!(debug, "{:?}", message_type).unwrap();
+        debug
+    }
+
+    #[test_case]
+    fn message_type_names() {
+        let names = [
+            (1, "DHCPDISCOVER"),
+            (2, "DHCPOFFER"),
+            (3, "DHCPREQUEST"),
+            (4, "DHCPDECLINE"),
+            (5, "DHCPACK"),
+            (6, "DHCPNAK"),
+            (7, "DHCPRELEASE"),
+            (8, "DHCPINFORM"),
+            (9, "DHCPFORCERENEW"),
+            (10, "DHCPLEASEQUERY"),
+            (11, "DHCPLEASEUNASSIGNED"),
+            (12, "DHCPLEASEUNKNOWN"),
+            (13, "DHCPLEASEACTIVE"),
+            (14, "DHCPBULKLEASEQUERY"),
+            (15, "DHCPLEASEQUERYDONE"),
+            (16, "DHCPACTIVELEASEQUERY"),
+            (17, "DHCPLEASEQUERYSTATUS"),
+            (18, "DHCPTLS"),
+        ];
+
+        for (value, name) in names {
+            let message_type = DhcpMessageType::try_from(value).unwrap();
+            assert_eq!(message_type.as_str(), Some(name));
+            assert_eq!(debug(message_type), name);
+        }
+    }
+
+    #[test_case]
+    fn unknown_message_types_are_rejected() {
+        assert_eq!(DhcpMessageType::try_from(0), Err(UnknownDhcpMessageType(0)));
+        assert_eq!(DhcpMessageType::try_from(99), Err(UnknownDhcpMessageType(99)));
+        assert_eq!(DhcpMessageType::new(99).as_str(), None);
+        assert_eq!(debug(DhcpMessageType::new(99)), "DhcpMessageType(99)");
+    }
+}
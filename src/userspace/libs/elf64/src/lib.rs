@@ -27,6 +27,44 @@ pub const FLAG_RISCV_FLOAT_ABI_MASK: Word = 0x0006;
 pub const FLAG_RISCV_RVE: Word = 0x0008;
 pub const FLAG_RISCV_TSO: Word = 0x0010;
 
+/// The note type of the ABI tag in notes owned by `"GNU"`
+pub const NT_GNU_ABI_TAG: Word = 1;
+/// The note type of the build ID in notes owned by `"GNU"`
+pub const NT_GNU_BUILD_ID: Word = 3;
+
+/// The reason some data couldn't be parsed as a RISC-V ELF
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The data is too short to contain an ELF header
+    Truncated,
+    /// The data doesn't start with the ELF magic bytes
+    BadMagic,
+    /// The ELF isn't 64-bit, contains the class found
+    WrongClass(u8),
+    /// The ELF isn't little endian, contains the data encoding found
+    WrongEndianness(u8),
+    /// The ELF isn't for RISC-V, contains the machine found
+    WrongMachine(Half),
+    /// The program header table extends past the end of the data
+    ProgramHeadersOutOfBounds,
+    /// The section header table extends past the end of the data
+    SectionHeadersOutOfBounds,
+}
+
+impl core::fmt::Display for ElfError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ElfError::Truncated => write!(f, "data is too short to contain an ELF header"),
+            ElfError::BadMagic => write!(f, "data doesn't start with the ELF magic"),
+            ElfError::WrongClass(class) => write!(f, "expected a 64-bit ELF, found class {}", class),
+            ElfError::WrongEndianness(data) => write!(f, "expected a little endian ELF, found data encoding {}", data),
+            ElfError::WrongMachine(machine) => write!(f, "expected a RISC-V ELF, found machine {}", machine),
+            ElfError::ProgramHeadersOutOfBounds => write!(f, "program header table is out of bounds"),
+            ElfError::SectionHeadersOutOfBounds => write!(f, "section header table is out of bounds"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Elf<'a> {
     data: &'a [u8],
@@ -34,8 +72,40 @@ pub struct Elf<'a> {
 }
 
 impl<'a> Elf<'a> {
-    pub fn new(data: &'a [u8]) -> Option<Self> {
-        Some(Self { data, header: Header::from_bytes(data)? })
+    pub fn new(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < core::mem::size_of::<Header>() {
+            return Err(ElfError::Truncated);
+        }
+
+        let header = Header::from_bytes(data).ok_or(ElfError::BadMagic)?;
+        if header.ident.class != Class::ElfClass64 as u8 {
+            return Err(ElfError::WrongClass(header.ident.class));
+        }
+
+        if header.ident.data != DataEncoding::ElfData2Lsb as u8 {
+            return Err(ElfError::WrongEndianness(header.ident.data));
+        }
+
+        if header.machine != MACHINE_RISCV {
+            return Err(ElfError::WrongMachine(header.machine));
+        }
+
+        let in_bounds = |offset: Off, count: Half, entry_size: usize| {
+            (count as usize)
+                .checked_mul(entry_size)
+                .and_then(|size| usize::try_from(offset).ok()?.checked_add(size))
+                .map_or(false, |end| end <= data.len())
+        };
+
+        if !in_bounds(header.ph_offset, header.ph_count, core::mem::size_of::<ProgramHeader>()) {
+            return Err(ElfError::ProgramHeadersOutOfBounds);
+        }
+
+        if !in_bounds(header.sh_offset, header.sh_count, core::mem::size_of::<SectionHeader>()) {
+            return Err(ElfError::SectionHeadersOutOfBounds);
+        }
+
+        Ok(Self { data, header })
     }
 
     pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
@@ -62,6 +132,30 @@ impl<'a> Elf<'a> {
         &self.data[header.offset as usize..][..header.file_size as usize]
     }
 
+    /// Iterate over the notes contained in all of the note segments
+    pub fn notes(&self) -> impl Iterator<Item = Note<'a>> + '_ {
+        self.program_headers().filter(|ph| ph.r#type == ProgramSegmentType::Note).flat_map(move |header| {
+            // Notes are 4-byte aligned, except for those in segments which
+            // explicitly ask for 8-byte alignment
+            let align = match header.align {
+                8 => 8,
+                _ => 4,
+            };
+
+            let mut data = self.program_segment_data(&header);
+            core::iter::from_fn(move || {
+                let (note, rest) = Note::parse(data, align)?;
+                data = rest;
+                Some(note)
+            })
+        })
+    }
+
+    /// The GNU build ID of the binary, if it was linked with one
+    pub fn build_id(&self) -> Option<&'a [u8]> {
+        self.notes().find(|note| note.name == b"GNU" && note.r#type == NT_GNU_BUILD_ID).map(|note| note.desc)
+    }
+
     pub fn relocations(&self) -> impl Iterator<Item = Relocation> + '_ {
         let dyn_header = self.program_headers().find(|ph| ph.r#type == ProgramSegmentType::Dynamic);
 
@@ -295,6 +389,43 @@ impl core::cmp::PartialEq<ProgramSegmentType> for Word {
     }
 }
 
+/// An entry in a note segment
+#[derive(Debug, Clone, Copy)]
+pub struct Note<'a> {
+    /// The owner of the note, without its NUL terminator
+    pub name: &'a [u8],
+    /// The owner-specific type of the note
+    pub r#type: Word,
+    /// The note contents
+    pub desc: &'a [u8],
+}
+
+impl<'a> Note<'a> {
+    /// Parse the note at the start of `data`, returning it along with the data
+    /// following it
+    fn parse(data: &'a [u8], align: usize) -> Option<(Self, &'a [u8])> {
+        let mut stream = ByteStream::new(data);
+        let name_size = stream.next::<Word>()? as usize;
+        let desc_size = stream.next::<Word>()? as usize;
+        let r#type = stream.next::<Word>()?;
+
+        let rest = stream.remaining();
+        let name = rest.get(..name_size)?;
+        let rest = rest.get(align_up(name_size, align)..)?;
+        let desc = rest.get(..desc_size)?;
+        // The padding after the last note in a segment may be left off
+        let rest = rest.get(align_up(desc_size, align)..).unwrap_or_default();
+
+        let name = name.strip_suffix(b"\0").unwrap_or(name);
+
+        Some((Self { name, r#type, desc }, rest))
+    }
+}
+
+fn align_up(n: usize, align: usize) -> usize {
+    (n + align - 1) & !(align - 1)
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(u32)]
 pub enum ProgramSegmentFlags {
@@ -388,3 +519,97 @@ streamable_struct! {
         pub addend: Sxword,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built from `testdata/build-id.s`, see there for the build command
+    static BUILD_ID_ELF: &[u8] = include_bytes!("../testdata/build-id.elf");
+
+    /// The test binary is built for the host, so patch the machine to pass
+    /// validation
+    fn build_id_elf() -> [u8; BUILD_ID_ELF.len()] {
+        let mut elf = [0; BUILD_ID_ELF.len()];
+        elf.copy_from_slice(BUILD_ID_ELF);
+        elf[18..20].copy_from_slice(&MACHINE_RISCV.to_le_bytes());
+        elf
+    }
+
+    fn patched(offset: usize, bytes: &[u8]) -> [u8; BUILD_ID_ELF.len()] {
+        let mut data = build_id_elf();
+        data[offset..][..bytes.len()].copy_from_slice(bytes);
+        data
+    }
+
+    #[test]
+    fn notes() {
+        let data = build_id_elf();
+        let elf = Elf::new(&data).unwrap();
+        let mut notes = elf.notes();
+
+        let build_id = notes.next().unwrap();
+        assert_eq!(build_id.name, b"GNU");
+        assert_eq!(build_id.r#type, NT_GNU_BUILD_ID);
+        assert_eq!(build_id.desc.len(), 20);
+
+        let abi_tag = notes.next().unwrap();
+        assert_eq!(abi_tag.name, b"GNU");
+        assert_eq!(abi_tag.r#type, NT_GNU_ABI_TAG);
+        assert_eq!(abi_tag.desc, [0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(notes.next().is_none());
+    }
+
+    #[test]
+    fn build_id() {
+        let data = build_id_elf();
+        let elf = Elf::new(&data).unwrap();
+        assert_eq!(
+            elf.build_id(),
+            Some(
+                &[
+                    0x64, 0x53, 0x52, 0x32, 0xEE, 0xE3, 0x55, 0xEE, 0xE5, 0x6E, 0xFE, 0xF6, 0x97, 0x15, 0xC4, 0x77,
+                    0x09, 0xA2, 0xC8, 0xF1
+                ][..]
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        assert_eq!(Elf::new(&build_id_elf()[..63]).unwrap_err(), ElfError::Truncated);
+        assert_eq!(Elf::new(&patched(0, b"\x7FELG")).unwrap_err(), ElfError::BadMagic);
+        assert_eq!(Elf::new(&patched(4, &[Class::ElfClass32 as u8])).unwrap_err(), ElfError::WrongClass(1));
+        assert_eq!(
+            Elf::new(&patched(5, &[DataEncoding::ElfData2Msb as u8])).unwrap_err(),
+            ElfError::WrongEndianness(2)
+        );
+        // `BUILD_ID_ELF` unpatched is x86-64
+        assert_eq!(Elf::new(BUILD_ID_ELF).unwrap_err(), ElfError::WrongMachine(62));
+    }
+
+    #[test]
+    fn rejects_out_of_bounds_tables() {
+        let len = BUILD_ID_ELF.len() as u64;
+
+        // Program header offset, then count
+        let data = patched(32, &len.to_le_bytes());
+        assert_eq!(Elf::new(&data).unwrap_err(), ElfError::ProgramHeadersOutOfBounds);
+        let data = patched(56, &u16::MAX.to_le_bytes());
+        assert_eq!(Elf::new(&data).unwrap_err(), ElfError::ProgramHeadersOutOfBounds);
+        let data = patched(32, &u64::MAX.to_le_bytes());
+        assert_eq!(Elf::new(&data).unwrap_err(), ElfError::ProgramHeadersOutOfBounds);
+
+        // Section header offset, then count
+        let data = patched(40, &(len - 1).to_le_bytes());
+        assert_eq!(Elf::new(&data).unwrap_err(), ElfError::SectionHeadersOutOfBounds);
+        let data = patched(60, &6u16.to_le_bytes());
+        assert_eq!(Elf::new(&data).unwrap_err(), ElfError::SectionHeadersOutOfBounds);
+
+        // Empty tables can start right at the end of the data
+        let mut data = patched(40, &len.to_le_bytes());
+        data[60..62].copy_from_slice(&0u16.to_le_bytes());
+        assert!(Elf::new(&data).is_ok());
+    }
+}
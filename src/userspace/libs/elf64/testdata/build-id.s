@@ -0,0 +1,25 @@
+# SPDX-License-Identifier: MPL-2.0
+# SPDX-FileCopyrightText: 2022 The vanadinite developers
+#
+# This Source Code Form is subject to the terms of the Mozilla Public License,
+# v. 2.0. If a copy of the MPL was not distributed with this file, You can
+# obtain one at https://mozilla.org/MPL/2.0/.
+
+# A minimal binary with both a GNU build ID and ABI tag note packed into the
+# same note segment, built with:
+#
+#   as build-id.s -o build-id.o
+#   ld --build-id=sha1 -static -nostdlib -s -z noseparate-code -o build-id.elf build-id.o
+
+    .section .note.ABI-tag, "a", @note
+    .balign 4
+    .long 4
+    .long 16
+    .long 1
+    .asciz "GNU"
+    .long 0, 4, 4, 0
+
+    .text
+    .globl _start
+_start:
+    ret
@@ -275,7 +275,7 @@ impl<'a> Deserializer<'a> for crate::parser::Parser<'a> {
     where
         F: FnMut(&mut Self) -> Result<(), DeserializeError>,
     {
-        self.parse::<parser::LeftBracket>().unwrap();
+        self.parse::<parser::LeftBracket>()?;
 
         while self.peek() != Some(']') {
             item_callback(self)?;
@@ -439,3 +439,76 @@ impl Deserialize for crate::Value {
         deserializer.deserialize_value()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{string::String, vec, vec::Vec};
+
+    crate::derive! {
+        #[derive(Debug, PartialEq)]
+        struct Profile {
+            name: String,
+            tags: Vec<String>,
+            nickname: Option<String>,
+        }
+    }
+
+    crate::derive! {
+        #[derive(Debug, PartialEq)]
+        struct Name {
+            name: String,
+        }
+    }
+
+    fn roundtrip(profile: &Profile) -> Profile {
+        crate::deserialize(&crate::to_bytes(profile)).unwrap()
+    }
+
+    #[test]
+    fn list_and_optional_fields() {
+        let profile = Profile {
+            name: String::from("init"),
+            tags: vec![String::from("server"), String::from("virtio")],
+            nickname: Some(String::from("pid1")),
+        };
+        assert_eq!(roundtrip(&profile), profile);
+
+        let profile = Profile { name: String::from("shell"), tags: Vec::new(), nickname: None };
+        assert_eq!(roundtrip(&profile), profile);
+
+        let parsed: Profile =
+            crate::deserialize(br#"{ "nickname": null, "tags": [ "a" , "b" ], "name": "x" }"#).unwrap();
+        assert_eq!(
+            parsed,
+            Profile { name: String::from("x"), tags: vec![String::from("a"), String::from("b")], nickname: None }
+        );
+    }
+
+    #[test]
+    fn missing_fields() {
+        // Optional fields can be left out entirely, but lists can't
+        let parsed: Profile = crate::deserialize(br#"{"name": "x", "tags": []}"#).unwrap();
+        assert_eq!(parsed.nickname, None);
+        assert!(matches!(
+            crate::deserialize::<Profile>(br#"{"name": "x"}"#),
+            Err(DeserializeError::MissingField("tags"))
+        ));
+
+        // Unknown list and null members are skipped
+        let profile = Profile { name: String::from("init"), tags: vec![String::from("server")], nickname: None };
+        let parsed: Name = crate::deserialize(&crate::to_bytes(&profile)).unwrap();
+        assert_eq!(parsed, Name { name: String::from("init") });
+    }
+
+    #[test]
+    fn nested_generics() {
+        assert_eq!(crate::deserialize::<Option<Vec<u8>>>(b"null").unwrap(), None);
+        assert_eq!(crate::deserialize::<Option<Vec<u8>>>(b"[1, 2]").unwrap(), Some(vec![1, 2]));
+        assert_eq!(crate::deserialize::<Vec<Option<u8>>>(b"[1, null, 2]").unwrap(), [Some(1), None, Some(2)]);
+        assert!(crate::deserialize::<Vec<u8>>(b"7").is_err());
+
+        let values: Vec<Option<i64>> = vec![Some(-1), None];
+        assert_eq!(crate::deserialize::<Vec<Option<i64>>>(&crate::to_bytes(&values)).unwrap(), values);
+    }
+}
@@ -11,6 +11,7 @@ extern crate alloc;
 
 pub mod deser;
 pub mod parser;
+pub mod stream;
 
 use alloc::{collections::BTreeMap, string::String, vec::Vec};
 use core::ops::{Deref, Index};
@@ -232,6 +233,10 @@ impl<'a> parser::Parseable<'a> for Value {
             '[' => Ok(Self::List(parser.parse::<List>()?)),
             '{' => Ok(Self::Object(parser.parse::<Object>()?)),
             't' | 'f' => Ok(Self::Bool(parser.parse::<bool>()?)),
+            'n' => {
+                "null".chars().try_for_each(|c| parser.eat(c))?;
+                Ok(Self::Null)
+            }
             c if c.is_ascii_alphanumeric() => Ok(Self::Number(parser.parse::<i64>()?)),
             c => Err(parser::ParseError::UnexpectedCharacter(c)),
         }
@@ -87,7 +87,7 @@ impl<'a> Parser<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum ParseError {
     IntegerValueTooLarge,
     InvalidUtf8,
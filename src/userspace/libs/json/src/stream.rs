@@ -0,0 +1,402 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::parser::ParseError;
+use alloc::{string::String, vec::Vec};
+
+/// A single piece of a JSON document, in the order it appears in the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    BeginObject,
+    EndObject,
+    BeginArray,
+    EndArray,
+    /// The name of the object member whose value is the next event
+    Key(String),
+    String(String),
+    Number(i64),
+    Bool(bool),
+    Null,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StreamError {
+    /// The input fed so far ends partway through a token, so more input needs
+    /// to be fed (or [`StreamParser::finish`] called) before the next event
+    /// can be produced
+    NeedMoreInput,
+    Invalid(ParseError),
+}
+
+impl From<ParseError> for StreamError {
+    fn from(error: ParseError) -> Self {
+        Self::Invalid(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Container {
+    Array,
+    Object,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expect {
+    Value,
+    ValueOrEndArray,
+    KeyOrEndObject,
+    Key,
+    Colon,
+    CommaOrEnd,
+    Done,
+}
+
+/// An incremental JSON parser which is fed its input in chunks and produces
+/// [`Event`]s as soon as each token is complete, so that large documents can
+/// be parsed without holding the whole input in memory. Only the bytes which
+/// haven't been turned into events yet are buffered, which is at most the
+/// token that was split across chunks.
+pub struct StreamParser {
+    buffer: Vec<u8>,
+    position: usize,
+    stack: Vec<Container>,
+    expect: Expect,
+    finished: bool,
+}
+
+impl StreamParser {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new(), position: 0, stack: Vec::new(), expect: Expect::Value, finished: false }
+    }
+
+    /// Append the next chunk of the document to the input
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.drain(..self.position);
+        self.position = 0;
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Mark the end of the input, which allows a trailing number to be
+    /// completed and turns any further [`StreamError::NeedMoreInput`] into
+    /// [`ParseError::UnexpectedEof`]
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    /// Produce the next event from the input fed so far, or `None` once the
+    /// top-level value has been fully parsed
+    pub fn next_event(&mut self) -> Result<Option<Event>, StreamError> {
+        loop {
+            self.skip_whitespace();
+
+            let c = match (self.peek(), self.expect) {
+                (None, Expect::Done) => return Ok(None),
+                (Some(c), Expect::Done) => return Err(unexpected(c)),
+                (None, _) => return Err(self.eof()),
+                (Some(c), _) => c,
+            };
+
+            match self.expect {
+                Expect::Value => return self.value(c).map(Some),
+                Expect::ValueOrEndArray if c == b']' => return Ok(Some(self.close())),
+                Expect::ValueOrEndArray => return self.value(c).map(Some),
+                Expect::KeyOrEndObject if c == b'}' => return Ok(Some(self.close())),
+                Expect::KeyOrEndObject | Expect::Key => return self.key(c).map(Some),
+                Expect::Colon if c == b':' => {
+                    self.position += 1;
+                    self.expect = Expect::Value;
+                }
+                Expect::CommaOrEnd => match (c, self.stack.last()) {
+                    (b',', Some(Container::Array)) => {
+                        self.position += 1;
+                        self.expect = Expect::Value;
+                    }
+                    (b',', Some(Container::Object)) => {
+                        self.position += 1;
+                        self.expect = Expect::Key;
+                    }
+                    (b']', Some(Container::Array)) | (b'}', Some(Container::Object)) => return Ok(Some(self.close())),
+                    _ => return Err(unexpected(c)),
+                },
+                _ => return Err(unexpected(c)),
+            }
+        }
+    }
+
+    fn value(&mut self, c: u8) -> Result<Event, StreamError> {
+        let event = match c {
+            b'{' => {
+                self.position += 1;
+                self.stack.push(Container::Object);
+                self.expect = Expect::KeyOrEndObject;
+                return Ok(Event::BeginObject);
+            }
+            b'[' => {
+                self.position += 1;
+                self.stack.push(Container::Array);
+                self.expect = Expect::ValueOrEndArray;
+                return Ok(Event::BeginArray);
+            }
+            b'"' => Event::String(self.string()?),
+            b't' => self.literal(b"true", Event::Bool(true))?,
+            b'f' => self.literal(b"false", Event::Bool(false))?,
+            b'n' => self.literal(b"null", Event::Null)?,
+            b'-' | b'0'..=b'9' => Event::Number(self.number()?),
+            c => return Err(unexpected(c)),
+        };
+
+        self.value_done();
+        Ok(event)
+    }
+
+    fn key(&mut self, c: u8) -> Result<Event, StreamError> {
+        if c != b'"' {
+            return Err(unexpected(c));
+        }
+
+        let key = self.string()?;
+        self.expect = Expect::Colon;
+
+        Ok(Event::Key(key))
+    }
+
+    fn close(&mut self) -> Event {
+        self.position += 1;
+        let event = match self.stack.pop() {
+            Some(Container::Array) => Event::EndArray,
+            Some(Container::Object) => Event::EndObject,
+            None => unreachable!("closed a container which was never opened"),
+        };
+
+        self.value_done();
+        event
+    }
+
+    fn value_done(&mut self) {
+        self.expect = match self.stack.is_empty() {
+            true => Expect::Done,
+            false => Expect::CommaOrEnd,
+        };
+    }
+
+    fn string(&mut self) -> Result<String, StreamError> {
+        let start = self.position + 1;
+        let Some(len) = self.buffer[start..].iter().position(|&b| b == b'"') else { return Err(self.eof()) };
+        let s = core::str::from_utf8(&self.buffer[start..start + len]).map_err(|_| ParseError::InvalidUtf8)?;
+        let s = String::from(s);
+
+        self.position = start + len + 1;
+        Ok(s)
+    }
+
+    fn literal(&mut self, literal: &[u8], event: Event) -> Result<Event, StreamError> {
+        let remaining = &self.buffer[self.position..];
+        match literal.iter().zip(remaining).find(|(expected, found)| expected != found) {
+            Some((_, &found)) => Err(unexpected(found)),
+            None if remaining.len() < literal.len() => Err(self.eof()),
+            None => {
+                self.position += literal.len();
+                Ok(event)
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, StreamError> {
+        let remaining = &self.buffer[self.position..];
+        let sign = usize::from(remaining[0] == b'-');
+        let len = sign + remaining[sign..].iter().take_while(|b| b.is_ascii_digit()).count();
+
+        // The number might continue in the next chunk
+        if len == remaining.len() && !self.finished {
+            return Err(StreamError::NeedMoreInput);
+        }
+
+        if len == sign {
+            return match remaining.get(len) {
+                Some(&c) => Err(unexpected(c)),
+                None => Err(ParseError::UnexpectedEof.into()),
+            };
+        }
+
+        // Only ASCII digits and a sign have been consumed, so this can't fail
+        let s = core::str::from_utf8(&remaining[..len]).unwrap();
+        let n = s.parse().map_err(|_| ParseError::IntegerValueTooLarge)?;
+
+        self.position += len;
+        Ok(n)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.buffer.get(self.position).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn eof(&self) -> StreamError {
+        match self.finished {
+            true => StreamError::Invalid(ParseError::UnexpectedEof),
+            false => StreamError::NeedMoreInput,
+        }
+    }
+}
+
+impl Default for StreamParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn unexpected(c: u8) -> StreamError {
+    StreamError::Invalid(ParseError::UnexpectedCharacter(c as char))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const DOCUMENT: &[u8] = br#"{
+        "name": "vanadinite",
+        "harts": [0, 1, -25, 1234567890],
+        "nested": {"empty": {}, "list": [[], [true, false, null]]},
+        "last": "done"
+    }"#;
+
+    fn parse_all(parser: &mut StreamParser, events: &mut Vec<Event>) -> Result<(), StreamError> {
+        loop {
+            match parser.next_event()? {
+                Some(event) => events.push(event),
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn parse_whole(input: &[u8]) -> Vec<Event> {
+        let mut parser = StreamParser::new();
+        let mut events = Vec::new();
+        parser.feed(input);
+        parser.finish();
+        parse_all(&mut parser, &mut events).unwrap();
+
+        events
+    }
+
+    fn parse_bytewise(input: &[u8]) -> Vec<Event> {
+        let mut parser = StreamParser::new();
+        let mut events = Vec::new();
+
+        for byte in input {
+            parser.feed(core::slice::from_ref(byte));
+            match parse_all(&mut parser, &mut events) {
+                Ok(()) | Err(StreamError::NeedMoreInput) => {}
+                Err(e) => panic!("unexpected error: {:?}", e),
+            }
+        }
+
+        parser.finish();
+        parse_all(&mut parser, &mut events).unwrap();
+
+        events
+    }
+
+    #[test]
+    fn bytewise_matches_whole_buffer() {
+        let whole = parse_whole(DOCUMENT);
+        assert_eq!(parse_bytewise(DOCUMENT), whole);
+
+        assert_eq!(
+            whole,
+            vec![
+                Event::BeginObject,
+                Event::Key(String::from("name")),
+                Event::String(String::from("vanadinite")),
+                Event::Key(String::from("harts")),
+                Event::BeginArray,
+                Event::Number(0),
+                Event::Number(1),
+                Event::Number(-25),
+                Event::Number(1234567890),
+                Event::EndArray,
+                Event::Key(String::from("nested")),
+                Event::BeginObject,
+                Event::Key(String::from("empty")),
+                Event::BeginObject,
+                Event::EndObject,
+                Event::Key(String::from("list")),
+                Event::BeginArray,
+                Event::BeginArray,
+                Event::EndArray,
+                Event::BeginArray,
+                Event::Bool(true),
+                Event::Bool(false),
+                Event::Null,
+                Event::EndArray,
+                Event::EndArray,
+                Event::EndObject,
+                Event::Key(String::from("last")),
+                Event::String(String::from("done")),
+                Event::EndObject,
+            ]
+        );
+    }
+
+    #[test]
+    fn split_tokens_need_more_input() {
+        let mut parser = StreamParser::new();
+        parser.feed(br#"["long stri"#);
+        assert_eq!(parser.next_event(), Ok(Some(Event::BeginArray)));
+        assert_eq!(parser.next_event(), Err(StreamError::NeedMoreInput));
+
+        // Only the partial string is kept around
+        parser.feed(b"");
+        assert_eq!(parser.buffer, br#""long stri"#);
+
+        parser.feed(br#"ng", 12"#);
+        assert_eq!(parser.next_event(), Ok(Some(Event::String(String::from("long string")))));
+        assert_eq!(parser.next_event(), Err(StreamError::NeedMoreInput));
+
+        parser.feed(b"3, tr");
+        assert_eq!(parser.next_event(), Ok(Some(Event::Number(123))));
+        assert_eq!(parser.next_event(), Err(StreamError::NeedMoreInput));
+
+        parser.feed(b"ue]");
+        assert_eq!(parser.next_event(), Ok(Some(Event::Bool(true))));
+        assert_eq!(parser.next_event(), Ok(Some(Event::EndArray)));
+        assert_eq!(parser.next_event(), Ok(None));
+    }
+
+    #[test]
+    fn top_level_number_needs_finish() {
+        let mut parser = StreamParser::new();
+        parser.feed(b"42");
+        assert_eq!(parser.next_event(), Err(StreamError::NeedMoreInput));
+
+        parser.finish();
+        assert_eq!(parser.next_event(), Ok(Some(Event::Number(42))));
+        assert_eq!(parser.next_event(), Ok(None));
+    }
+
+    #[test]
+    fn invalid_input_errors() {
+        let mut parser = StreamParser::new();
+        parser.feed(b"[1 2]");
+        assert_eq!(parser.next_event(), Ok(Some(Event::BeginArray)));
+        assert_eq!(parser.next_event(), Ok(Some(Event::Number(1))));
+        assert_eq!(parser.next_event(), Err(StreamError::Invalid(ParseError::UnexpectedCharacter('2'))));
+
+        let mut parser = StreamParser::new();
+        parser.feed(b"{\"a\": tru");
+        parser.finish();
+        assert_eq!(parser.next_event(), Ok(Some(Event::BeginObject)));
+        assert_eq!(parser.next_event(), Ok(Some(Event::Key(String::from("a")))));
+        assert_eq!(parser.next_event(), Err(StreamError::Invalid(ParseError::UnexpectedEof)));
+    }
+}
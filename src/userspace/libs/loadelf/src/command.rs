@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{load_elf, Elf};
+use filesystem::vidl::{Error as FilesystemError, FilesystemClient, OpenOptions};
+use librust::{
+    capabilities::{CapabilityPtr, CapabilityRights},
+    error::SyscallError,
+};
+use std::{path::Path, process::Child};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// The spawning task wasn't granted a `filesystem` capability
+    NoFilesystem,
+    /// There is no file at the given path
+    FileNotFound,
+    /// The file isn't an ELF that can be loaded
+    InvalidElf,
+    Filesystem(FilesystemError),
+    Syscall(SyscallError),
+}
+
+impl From<FilesystemError> for SpawnError {
+    fn from(error: FilesystemError) -> Self {
+        match error {
+            FilesystemError::FileNotFound => Self::FileNotFound,
+            error => Self::Filesystem(error),
+        }
+    }
+}
+
+impl From<SyscallError> for SpawnError {
+    fn from(error: SyscallError) -> Self {
+        Self::Syscall(error)
+    }
+}
+
+/// A builder for spawning a program read from the filesystem server
+pub struct Command {
+    path: String,
+    args: Vec<String>,
+    caps: Vec<(String, CapabilityPtr, CapabilityRights)>,
+}
+
+impl Command {
+    pub fn new(path: &str) -> Self {
+        Self { path: path.into(), args: Vec::new(), caps: Vec::new() }
+    }
+
+    /// Add an argument which the child will receive from [`std::env::args`]
+    pub fn arg(&mut self, arg: &str) -> &mut Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<'a>(&mut self, args: impl IntoIterator<Item = &'a str>) -> &mut Self {
+        self.args.extend(args.into_iter().map(String::from));
+        self
+    }
+
+    /// Grant a capability to the child under `name`
+    pub fn grant(&mut self, name: &str, cptr: CapabilityPtr, rights: CapabilityRights) -> &mut Self {
+        self.caps.push((name.into(), cptr, rights));
+        self
+    }
+
+    /// Read the program from the filesystem server, load it into a new
+    /// vmspace, and start it
+    pub fn spawn(&self) -> Result<Child, SpawnError> {
+        let filesystem = std::env::lookup_capability("filesystem").ok_or(SpawnError::NoFilesystem)?;
+        let client = FilesystemClient::new(filesystem.capability.cptr);
+        let mut file = client.open(&self.path, OpenOptions::ReadOnly)?;
+
+        let mut contents = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let len @ 1.. = file.read(&mut buffer[..])? else { break };
+            contents.extend_from_slice(&buffer[..len]);
+        }
+        file.close()?;
+
+        let name = Path::new(&self.path).file_name().unwrap_or(&self.path);
+        let elf = Elf::new(&contents).map_err(|_| SpawnError::InvalidElf)?;
+        let (mut space, env) = load_elf(name, &elf).map_err(|_| SpawnError::InvalidElf)?;
+
+        space.set_args(self.args.iter().map(String::as_str));
+        for (name, cptr, rights) in &self.caps {
+            space.grant(name, *cptr, *rights);
+        }
+
+        Ok(space.spawn_child(env)?)
+    }
+}
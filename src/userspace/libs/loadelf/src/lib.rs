@@ -5,6 +5,11 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+#[cfg(feature = "command")]
+mod command;
+
+#[cfg(feature = "command")]
+pub use command::{Command, SpawnError};
 pub use elf64::Elf;
 use elf64::{ProgramSegmentType, Relocation};
 use librust::syscalls::{mem::MemoryPermissions, vmspace::VmspaceSpawnEnv};
@@ -28,8 +33,9 @@ pub fn load_elf(name: &str, elf: &Elf) -> Result<(Vmspace, VmspaceSpawnEnv), ()>
         .find(|header| header.r#type == ProgramSegmentType::GnuRelro)
         .map(|header| header.vaddr as usize);
     let vmspace = Vmspace::new(name);
-    let mut task_load_base = 0;
-    let mut segment_offset = 0;
+    // The address the ELF's virtual address 0 ends up at, which is picked when
+    // the first segment is mapped
+    let mut load_base = None;
     let mut pc = 0;
     let elf_entry = elf.header.entry as usize;
 
@@ -50,24 +56,20 @@ pub fn load_elf(name: &str, elf: &Elf) -> Result<(Vmspace, VmspaceSpawnEnv), ()>
             (false, flags) => unreachable!("flags: {:#b}", flags),
         };
 
-        // Need to align-up the segment offset we were given here
-        let mut segment_load_base = round_up_to_next(segment_offset, align);
-        // Grab the bottom bits that we need to start writing data at
-        let segment_load_offset = vaddr & (align - 1);
-        // The total size in memory rounded up to the next alignment for the
-        // segment
-        let region_size = round_up_to_next(mem_size + segment_load_offset, align);
-
-        let mut object = vmspace.create_object(segment_offset as *const _, region_size, permissions).unwrap();
-
-        if task_load_base == 0 {
-            segment_load_base = object.vmspace_address() as usize;
-            task_load_base = object.vmspace_address() as usize;
-        }
-
         assert!(align.is_power_of_two(), "ELF segment alignment isn't a power of two!");
         assert!(mem_size >= file_size, "ELF segment has less data in memory than in the file?");
 
+        // Only the pages the segment covers are mapped with its permissions,
+        // the rest of its alignment is left to whatever else is placed there
+        let segment_page = vaddr & !(PAGE_SIZE - 1);
+        // Grab the bottom bits that we need to start writing data at
+        let segment_load_offset = vaddr - segment_page;
+        let region_size = round_up_to_next(mem_size + segment_load_offset, PAGE_SIZE);
+
+        let address = load_base.map_or(core::ptr::null(), |base: usize| (base + segment_page) as *const _);
+        let mut object = vmspace.create_object(address, region_size, permissions).unwrap();
+        let task_load_base = *load_base.get_or_insert(object.vmspace_address() as usize - segment_page);
+
         // Copy the segment data starting at the offset
         object.as_slice()[segment_load_offset..][..file_size].copy_from_slice(elf.program_segment_data(&header));
 
@@ -80,8 +82,7 @@ pub fn load_elf(name: &str, elf: &Elf) -> Result<(Vmspace, VmspaceSpawnEnv), ()>
         // The real PC needs calculated from the offset, so we check to see
         // if this is the segment that contains the entry point
         if raw_segment_range.contains(&elf_entry) {
-            let offset = elf_entry - raw_segment_start + segment_load_offset;
-            pc = segment_load_base + offset;
+            pc = task_load_base + elf_entry;
         }
 
         // Find any relocations and fix them up before we write the memory
@@ -93,7 +94,7 @@ pub fn load_elf(name: &str, elf: &Elf) -> Result<(Vmspace, VmspaceSpawnEnv), ()>
             match relocation {
                 Relocation::Rel(_) => todo!("rel relocations"),
                 Relocation::Rela(rela) => {
-                    let offset_into = rela.offset as usize - raw_segment_start + segment_load_offset;
+                    let offset_into = rela.offset as usize - segment_page;
 
                     match rela.r#type {
                         // RELATIVE
@@ -108,8 +109,6 @@ pub fn load_elf(name: &str, elf: &Elf) -> Result<(Vmspace, VmspaceSpawnEnv), ()>
                 }
             }
         }
-
-        segment_offset = segment_load_base + region_size;
     }
 
     let tls = elf.program_headers().find(|header| header.r#type == elf64::ProgramSegmentType::Tls).map(|header| {
@@ -144,12 +143,13 @@ pub fn load_elf(name: &str, elf: &Elf) -> Result<(Vmspace, VmspaceSpawnEnv), ()>
         tls_base_addr + 24
     });
 
-    let sp = vmspace
-        .create_object(core::ptr::null(), 16 * PAGE_SIZE, MemoryPermissions::READ | MemoryPermissions::WRITE)
-        .unwrap();
+    let sp = vmspace.create_stack(16 * PAGE_SIZE).unwrap();
     let sp = sp.vmspace_address() as usize + 16 * PAGE_SIZE;
 
-    Ok((vmspace, VmspaceSpawnEnv { pc, a0: 0, a1: 0, a2: 0, tp: tls.unwrap_or(0), sp }))
+    Ok((
+        vmspace,
+        VmspaceSpawnEnv { pc, a0: 0, a1: 0, a2: 0, tp: tls.unwrap_or(0), sp, channel_capacity: 0, memory_limit: 0 },
+    ))
 }
 
 pub fn round_up_to_next(n: usize, size: usize) -> usize {
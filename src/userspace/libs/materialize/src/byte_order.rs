@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Integers with a fixed byte order
+//!
+//! Plain integers are serialized in native byte order, which only works when
+//! both sides of the buffer are the same machine. The types from the `endian`
+//! crate are instead serialized as byte arrays in their own byte order, so
+//! fields using them are byte-exact for a protocol regardless of the host.
+
+use crate::{
+    deserialize::{Deserialize, DeserializeError},
+    primitives::Array,
+    serialize::{serializers::PrimitiveSerializer, Serialize, SerializeError},
+    CapabilityWithDescription, Serializable,
+};
+use endian::{
+    BigEndianI16, BigEndianI32, BigEndianI64, BigEndianIsize, BigEndianU16, BigEndianU32, BigEndianU64, BigEndianUsize,
+    LittleEndianI16, LittleEndianI32, LittleEndianI64, LittleEndianIsize, LittleEndianU16, LittleEndianU32,
+    LittleEndianU64, LittleEndianUsize,
+};
+
+macro_rules! fixed_endian_integer {
+    ($($t:ident($int:ty, $to_bytes:ident, $from_bytes:ident)),+ $(,)?) => {
+        $(
+            impl Serializable for $t {
+                type Primitive<'a> = Array<'a, u8, { core::mem::size_of::<$int>() }>;
+            }
+
+            impl Serialize for $t {
+                fn serialize<'a>(
+                    &self,
+                    serializer: <Self::Primitive<'a> as PrimitiveSerializer<'a>>::Serializer,
+                ) -> Result<(), SerializeError> {
+                    serializer.serialize_array(&self.$to_bytes())
+                }
+            }
+
+            impl<'de> Deserialize<'de> for $t {
+                #[inline]
+                fn deserialize(
+                    primitive: <Self as Serializable>::Primitive<'de>,
+                    capabilities: &[CapabilityWithDescription],
+                ) -> Result<Self, DeserializeError> {
+                    let bytes = <[u8; core::mem::size_of::<$int>()]>::deserialize(primitive, capabilities)?;
+                    Ok(Self::from_ne(<$int>::$from_bytes(bytes)))
+                }
+            }
+        )+
+    };
+}
+
+fixed_endian_integer! {
+    BigEndianU16(u16, to_be_bytes, from_be_bytes),
+    BigEndianI16(i16, to_be_bytes, from_be_bytes),
+    BigEndianU32(u32, to_be_bytes, from_be_bytes),
+    BigEndianI32(i32, to_be_bytes, from_be_bytes),
+    BigEndianU64(u64, to_be_bytes, from_be_bytes),
+    BigEndianI64(i64, to_be_bytes, from_be_bytes),
+    BigEndianUsize(usize, to_be_bytes, from_be_bytes),
+    BigEndianIsize(isize, to_be_bytes, from_be_bytes),
+    LittleEndianU16(u16, to_le_bytes, from_le_bytes),
+    LittleEndianI16(i16, to_le_bytes, from_le_bytes),
+    LittleEndianU32(u32, to_le_bytes, from_le_bytes),
+    LittleEndianI32(i32, to_le_bytes, from_le_bytes),
+    LittleEndianU64(u64, to_le_bytes, from_le_bytes),
+    LittleEndianI64(i64, to_le_bytes, from_le_bytes),
+    LittleEndianUsize(usize, to_le_bytes, from_le_bytes),
+    LittleEndianIsize(isize, to_le_bytes, from_le_bytes),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Deserializer, Serializer};
+
+    fn contains(buffer: &[u8], bytes: &[u8]) -> bool {
+        buffer.windows(bytes.len()).any(|window| window == bytes)
+    }
+
+    #[test_case]
+    fn big_endian_u32() {
+        let mut serializer = Serializer::new();
+        serializer.serialize(&(BigEndianU32::from_ne(0x0A00_020F), 0x55u8)).unwrap();
+        let buffer = serializer.into_buffer();
+
+        // The same bytes on any host, unlike a plain `u32`
+        assert!(contains(&buffer, &[0x0A, 0x00, 0x02, 0x0F]));
+        assert!(!contains(&buffer, &[0x0F, 0x02, 0x00, 0x0A]));
+
+        let (ip, byte) = Deserializer::new(&buffer, &[]).deserialize::<(BigEndianU32, u8)>().unwrap();
+        assert_eq!((ip.to_ne(), byte), (0x0A00_020F, 0x55));
+    }
+
+    #[test_case]
+    fn fixed_byte_orders() {
+        type Value = (BigEndianI16, LittleEndianU32, BigEndianU64);
+
+        let mut serializer = Serializer::new();
+        serializer
+            .serialize(&(
+                BigEndianI16::from_ne(-2),
+                LittleEndianU32::from_ne(0x1234_5678),
+                BigEndianU64::from_ne(0x0102_0304_0506_0708),
+            ))
+            .unwrap();
+        let buffer = serializer.into_buffer();
+
+        assert!(contains(&buffer, &[0xFF, 0xFE]));
+        assert!(contains(&buffer, &[0x78, 0x56, 0x34, 0x12]));
+        assert!(contains(&buffer, &[1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let (a, b, c) = Deserializer::new(&buffer, &[]).deserialize::<Value>().unwrap();
+        assert_eq!((a.to_ne(), b.to_ne(), c.to_ne()), (-2, 0x1234_5678, 0x0102_0304_0506_0708));
+    }
+}
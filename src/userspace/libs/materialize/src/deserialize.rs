@@ -8,7 +8,7 @@
 use librust::capabilities::CapabilityWithDescription;
 
 use crate::{
-    primitives::{AlignedReadBuffer, Fields, Primitive, Struct},
+    primitives::{AlignedReadBuffer, Bytes, Fields, Primitive, Struct},
     Serializable,
 };
 
@@ -22,24 +22,102 @@ pub enum DeserializeError {
     InvalidUtf8,
     InvalidCapabilityProperty,
     UnknownDiscriminantValue,
+    InvalidBool,
+    /// A struct field without a default is missing from the serialized struct
+    MissingField,
+    /// The buffer contains values nested more deeply, or lists longer, than
+    /// the [`Deserializer`] was configured to allow
+    LimitExceeded,
+    /// The buffer decodes to a value, but isn't the exact encoding the
+    /// serializer would have produced for it, see
+    /// [`Deserializer::validate_canonical`]
+    NonCanonical,
+}
+
+/// Bounds on the shape of the data a [`Deserializer`] will accept, so that a
+/// malicious or corrupt buffer can't make it do an unbounded amount of work
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub(crate) max_depth: usize,
+    pub(crate) max_list_length: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self { max_depth: Deserializer::DEFAULT_MAX_DEPTH, max_list_length: Deserializer::DEFAULT_MAX_LIST_LENGTH }
+    }
 }
 
 pub struct Deserializer<'a> {
     buffer: &'a [u8],
     capabilities: &'a [CapabilityWithDescription],
+    limits: Limits,
+    canonical: bool,
 }
 
 impl<'a> Deserializer<'a> {
+    /// The default maximum number of structs, enums, arrays, and lists a value
+    /// can be nested within
+    pub const DEFAULT_MAX_DEPTH: usize = 64;
+    /// The default maximum number of elements in a single list. List elements
+    /// must fit within the buffer, so this only needs to be lowered to bound
+    /// the work done for lists of zero-sized elements or large buffers.
+    pub const DEFAULT_MAX_LIST_LENGTH: usize = usize::MAX;
+
     pub fn new(buffer: &'a [u8], capabilities: &'a [CapabilityWithDescription]) -> Self {
-        Self { buffer, capabilities }
+        Self { buffer, capabilities, limits: Limits::default(), canonical: false }
+    }
+
+    /// Set the maximum number of structs, enums, arrays, and lists a value can
+    /// be nested within before deserializing fails with
+    /// [`DeserializeError::LimitExceeded`]
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.limits.max_depth = max_depth;
+        self
+    }
+
+    /// Set the maximum number of elements any list in the buffer can contain
+    /// before deserializing fails with [`DeserializeError::LimitExceeded`]
+    pub fn max_list_length(mut self, max_list_length: usize) -> Self {
+        self.limits.max_list_length = max_list_length;
+        self
+    }
+
+    /// Only accept the exact encoding the serializer produces for the value,
+    /// failing with [`DeserializeError::NonCanonical`] otherwise, so that two
+    /// buffers which deserialize to the same value are always byte-for-byte
+    /// identical. Out of line data must be laid out in order without overlap
+    /// or gaps, padding must be zeroed, and structs must have been serialized
+    /// with exactly the fields being deserialized.
+    ///
+    /// Regions are checked in the order they're read, which is the order the
+    /// serializer writes them in as long as every field is deserialized once,
+    /// front to back, like the derived [`Deserialize`] impls do. The value must
+    /// also take up the whole buffer, with nothing left over after it.
+    pub fn validate_canonical(mut self) -> Self {
+        self.canonical = true;
+        self
     }
 
     #[track_caller]
     pub fn deserialize<T: Deserialize<'a>>(self) -> Result<T, DeserializeError> {
-        T::deserialize(
-            <T::Primitive<'a> as Primitive>::extract(&mut AlignedReadBuffer::new(self.buffer))?,
-            self.capabilities,
-        )
+        let mut buffer = match self.canonical {
+            true => AlignedReadBuffer::canonical(self.buffer, self.limits, <T::Primitive<'a> as Primitive>::layout()),
+            false => AlignedReadBuffer::with_limits(self.buffer, self.limits),
+        };
+
+        let value = T::deserialize(<T::Primitive<'a> as Primitive>::extract(&mut buffer)?, self.capabilities)?;
+        buffer.finish()?;
+
+        Ok(value)
+    }
+
+    /// Deserialize a string which borrows directly from the input buffer
+    /// instead of being copied into an owned `String`, after checking that it's
+    /// valid UTF-8
+    #[track_caller]
+    pub fn deserialize_borrowed(self) -> Result<&'a str, DeserializeError> {
+        self.deserialize::<&'a str>()
     }
 }
 
@@ -60,6 +138,20 @@ impl<'de> Deserialize<'de> for () {
     }
 }
 
+impl<'de> Deserialize<'de> for bool {
+    #[inline]
+    fn deserialize(
+        primitive: <Self as Serializable>::Primitive<'de>,
+        _: &[CapabilityWithDescription],
+    ) -> Result<Self, DeserializeError> {
+        match primitive {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DeserializeError::InvalidBool),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for u8 {
     #[inline]
     fn deserialize(
@@ -170,6 +262,26 @@ impl<'de> Deserialize<'de> for &'de str {
     }
 }
 
+impl<'de> Deserialize<'de> for Bytes<'de> {
+    #[inline]
+    fn deserialize(
+        primitive: <Self as Serializable>::Primitive<'de>,
+        _: &[CapabilityWithDescription],
+    ) -> Result<Self, DeserializeError> {
+        Ok(primitive)
+    }
+}
+
+impl<'de> Deserialize<'de> for &'de [u8] {
+    #[inline]
+    fn deserialize(
+        primitive: <Self as Serializable>::Primitive<'de>,
+        _: &[CapabilityWithDescription],
+    ) -> Result<Self, DeserializeError> {
+        Ok(primitive.as_bytes())
+    }
+}
+
 impl<'de> Deserialize<'de> for alloc::string::String {
     #[inline]
     fn deserialize(
@@ -196,7 +308,7 @@ impl<'de, const LENGTH: usize, D: Deserialize<'de>> Deserialize<'de> for [D; LEN
         primitive: <Self as Serializable>::Primitive<'de>,
         capabilities: &[CapabilityWithDescription],
     ) -> Result<Self, DeserializeError> {
-        primitive.map(|p| Ok(D::deserialize(p, capabilities).unwrap()))
+        primitive.map(|p| D::deserialize(p, capabilities))
     }
 }
 
@@ -7,6 +7,7 @@
 
 #![no_std]
 #![allow(incomplete_features, clippy::unit_arg)]
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
 #![feature(
     alloc_layout_extra,
     allocator_api,
@@ -24,6 +25,7 @@ extern crate alloc;
 extern crate std;
 
 pub mod buffer;
+mod byte_order;
 pub mod deserialize;
 mod hash;
 pub mod primitives;
@@ -33,7 +35,7 @@ pub mod writer;
 pub use deserialize::{Deserialize, DeserializeError, Deserializer};
 pub use librust::capabilities::CapabilityWithDescription;
 pub use materialize_derive::*;
-pub use serialize::{Serialize, SerializeError, Serializer};
+pub use serialize::{BufferSerializer, Serialize, SerializeError, Serializer};
 
 const MINIMUM_ALIGNMENT: usize = core::mem::align_of::<u64>();
 
@@ -43,12 +45,66 @@ mod sealed {
 
 pub trait Serializable {
     type Primitive<'a>: primitives::Primitive<'a>;
+
+    /// The number of bytes serializing a value of this type into an empty
+    /// [`Serializer`] produces, computed from its primitive composition
+    fn serialized_size_hint() -> SizeHint {
+        let mut hint = SizeHint::Fixed(0);
+        hint.reserve(<Self::Primitive<'_> as primitives::Primitive<'_>>::layout());
+        <Self::Primitive<'_> as primitives::Primitive<'_>>::out_of_line_size(&mut hint);
+        hint
+    }
+}
+
+/// The size of a serialized value, see [`Serializable::serialized_size_hint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeHint {
+    /// Every value of the type serializes to exactly this many bytes
+    Fixed(usize),
+    /// The size depends on the value (e.g. the length of a list), but is at
+    /// least `minimum` bytes
+    Variable { minimum: usize },
+}
+
+impl SizeHint {
+    /// The exact size if fixed, or the minimum otherwise
+    pub const fn minimum(self) -> usize {
+        match self {
+            Self::Fixed(size) | Self::Variable { minimum: size } => size,
+        }
+    }
+
+    pub const fn is_fixed(self) -> bool {
+        matches!(self, Self::Fixed(_))
+    }
+
+    /// Account for space reserved with `layout`, mirroring
+    /// [`Serializer`]'s reservations
+    pub(crate) fn reserve(&mut self, layout: core::alloc::Layout) {
+        let size = self.size_mut();
+        *size = (*size + layout.align() - 1) / layout.align() * layout.align() + layout.size();
+    }
+
+    /// Mark the size as depending on the value being serialized
+    pub(crate) fn make_variable(&mut self) {
+        *self = Self::Variable { minimum: self.minimum() };
+    }
+
+    fn size_mut(&mut self) -> &mut usize {
+        match self {
+            Self::Fixed(size) | Self::Variable { minimum: size } => size,
+        }
+    }
 }
 
 impl Serializable for () {
     type Primitive<'a> = ();
 }
 
+impl Serializable for bool {
+    type Primitive<'a> = u8;
+}
+
 impl Serializable for u8 {
     type Primitive<'a> = u8;
 }
@@ -97,6 +153,10 @@ impl Serializable for alloc::string::String {
     type Primitive<'a> = &'a str;
 }
 
+impl Serializable for primitives::Bytes<'_> {
+    type Primitive<'a> = primitives::Bytes<'a>;
+}
+
 impl<T: Serializable> Serializable for alloc::vec::Vec<T> {
     type Primitive<'a> = primitives::List<'a, T::Primitive<'a>>;
 }
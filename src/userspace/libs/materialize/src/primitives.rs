@@ -7,8 +7,15 @@
 
 mod fields;
 
-use crate::{deserialize::DeserializeError, hash::FxHasher, sealed, serialize::serializers::PrimitiveSerializer};
-use core::{alloc::Layout, convert::TryFrom};
+use crate::{
+    deserialize::{DeserializeError, Limits},
+    hash::FxHasher,
+    sealed,
+    serialize::serializers::PrimitiveSerializer,
+    SizeHint,
+};
+use alloc::rc::Rc;
+use core::{alloc::Layout, cell::Cell, convert::TryFrom};
 
 pub(crate) unsafe trait Integer: Sized + Copy {}
 unsafe impl Integer for u8 {}
@@ -27,11 +34,96 @@ unsafe impl<const N: usize, I: Integer> Integer for [I; N] {}
 pub struct AlignedReadBuffer<'a> {
     buffer: &'a [u8],
     position: usize,
+    /// The number of structs, enums, arrays, and lists this buffer is nested
+    /// within
+    depth: usize,
+    limits: Limits,
+    /// Where the serializer would have reserved the next out of line region,
+    /// when checking that the buffer is in canonical form. Shared between all
+    /// of the buffers nested within the same value.
+    canonical: Option<Rc<Cell<usize>>>,
 }
 
 impl<'a> AlignedReadBuffer<'a> {
     pub(crate) fn new(buffer: &'a [u8]) -> Self {
-        Self { buffer, position: 0 }
+        Self::with_limits(buffer, Limits::default())
+    }
+
+    pub(crate) fn with_limits(buffer: &'a [u8], limits: Limits) -> Self {
+        Self { buffer, position: 0, depth: 0, limits, canonical: None }
+    }
+
+    /// Create a buffer which rejects anything but the exact encoding the
+    /// serializer produces for a value whose root primitive has `root` as its
+    /// layout, see [`crate::Deserializer::validate_canonical`]
+    pub(crate) fn canonical(buffer: &'a [u8], limits: Limits, root: Layout) -> Self {
+        Self { canonical: Some(Rc::new(Cell::new(root.size()))), ..Self::with_limits(buffer, limits) }
+    }
+
+    /// Create a buffer for reading the contents of a nested primitive at
+    /// `position`, after checking that `len` bytes at `position` are within the
+    /// input and that the nesting depth limit hasn't been reached
+    fn nested(&self, position: usize, len: usize) -> Result<Self, DeserializeError> {
+        self.checked_range(position, len)?;
+
+        if self.depth >= self.limits.max_depth {
+            return Err(DeserializeError::LimitExceeded);
+        }
+
+        Ok(Self {
+            buffer: self.buffer,
+            position,
+            depth: self.depth + 1,
+            limits: self.limits,
+            canonical: self.canonical.clone(),
+        })
+    }
+
+    /// In canonical mode, check that an out of line region with `layout` is at
+    /// `position` because that's where the serializer would have reserved it:
+    /// the serializer reserves regions one after another, in the same order
+    /// they're read, with zeroed padding between them
+    fn claim(&self, position: usize, layout: Layout) -> Result<(), DeserializeError> {
+        let Some(cursor) = &self.canonical else { return Ok(()) };
+
+        let start = cursor.get().checked_next_multiple_of(layout.align()).ok_or(DeserializeError::NonCanonical)?;
+        if position != start {
+            return Err(DeserializeError::NonCanonical);
+        }
+
+        self.zeroed(cursor.get(), start - cursor.get())?;
+        cursor.set(start + layout.size());
+
+        Ok(())
+    }
+
+    /// In canonical mode, check that the `len` bytes of padding at `position`
+    /// are zeroed
+    fn zeroed(&self, position: usize, len: usize) -> Result<(), DeserializeError> {
+        match &self.canonical {
+            Some(_) if self.checked_range(position, len)?.iter().any(|&b| b != 0) => {
+                Err(DeserializeError::NonCanonical)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// In canonical mode, check that the value took up the whole buffer, as
+    /// the serializer doesn't leave anything after the last region it reserves
+    pub(crate) fn finish(&self) -> Result<(), DeserializeError> {
+        match &self.canonical {
+            Some(cursor) if cursor.get() != self.buffer.len() => Err(DeserializeError::NonCanonical),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_canonical(&self) -> bool {
+        self.canonical.is_some()
+    }
+
+    fn checked_range(&self, position: usize, len: usize) -> Result<&'a [u8], DeserializeError> {
+        let end = position.checked_add(len).ok_or(DeserializeError::MalformedOffset)?;
+        self.buffer.get(position..end).ok_or(DeserializeError::MalformedOffset)
     }
 
     #[inline]
@@ -53,6 +145,11 @@ pub trait Primitive<'a>: sealed::Sealed + PrimitiveSerializer<'a> + Sized {
 
     fn extract(buffer: &mut AlignedReadBuffer<'a>) -> Result<Self, DeserializeError>;
     fn layout() -> Layout;
+
+    /// Add the space reserved after the primitive's [`Primitive::layout`] when
+    /// it's serialized, in the order the serializer reserves it
+    #[inline(always)]
+    fn out_of_line_size(_: &mut SizeHint) {}
 }
 
 impl<'a> Primitive<'a> for () {
@@ -70,6 +167,9 @@ impl<'a> Primitive<'a> for () {
 pub struct Struct<'a, F: Fields<'a>> {
     fields: core::marker::PhantomData<fn() -> F>,
     buffer: AlignedReadBuffer<'a>,
+    /// The end of the fields the struct was serialized with, which may be
+    /// fewer than `F` if it was serialized by an older version of the struct
+    end: usize,
 }
 
 impl<'a, F: Fields<'a>> Struct<'a, F> {
@@ -77,14 +177,36 @@ impl<'a, F: Fields<'a>> Struct<'a, F> {
 
     #[inline]
     pub fn field(&self) -> Result<F::Head, DeserializeError> {
+        if !self.has_field() {
+            return Err(DeserializeError::MissingField);
+        }
+
+        let head = <F::Head as Primitive>::layout();
+        self.buffer.zeroed(self.buffer.position, self.field_end() - head.size() - self.buffer.position)?;
+
         <F::Head as Primitive>::extract(&mut self.buffer.clone())
     }
 
+    /// Whether the serialized struct contains the current field, which it
+    /// won't if the field was added after the struct was serialized
+    #[inline]
+    pub fn has_field(&self) -> bool {
+        self.field_end() <= self.end
+    }
+
     #[inline]
     pub fn next(&self) -> Struct<'a, <F as Fields<'a>>::Next> {
         let mut buffer = self.buffer.clone();
-        buffer.position += <<F as Fields>::Head as Primitive>::layout().size();
-        Struct { buffer, fields: core::marker::PhantomData }
+        buffer.position = self.field_end();
+        Struct { buffer, fields: core::marker::PhantomData, end: self.end }
+    }
+
+    /// Where the current field ends, after aligning it the same way the
+    /// serializer does
+    #[inline]
+    fn field_end(&self) -> usize {
+        let layout = <<F as Fields>::Head as Primitive>::layout();
+        (self.buffer.position + layout.align() - 1) / layout.align() * layout.align() + layout.size()
     }
 
     #[inline]
@@ -98,20 +220,36 @@ impl<'a, F: Fields<'a>> Primitive<'a> for Struct<'a, F> {
     const ID: u64 = FxHasher::new().hash(Self::STRUCT_BASE_ID).hash(<<F as Fields>::Head as Primitive>::ID).finish();
 
     fn extract(buffer: &mut AlignedReadBuffer<'a>) -> Result<Self, DeserializeError> {
-        let [id, position] = buffer.read::<[u64; 2]>()?;
+        let [id, position, len] = buffer.read::<[u64; 3]>()?;
         let position = usize::try_from(position).map_err(|_| DeserializeError::MalformedOffset)?;
+        let len = usize::try_from(len).map_err(|_| DeserializeError::MalformedOffset)?;
 
         if id != Self::ID {
             return Err(DeserializeError::MismatchedId { wanted: Self::ID, found: id });
-        } else if buffer.buffer.get(position..position + F::layout().size()).is_none() {
-            return Err(DeserializeError::MalformedOffset);
         }
 
-        Ok(Struct { buffer: AlignedReadBuffer { buffer: buffer.buffer, position }, fields: core::marker::PhantomData })
+        let nested = buffer.nested(position, len)?;
+        if nested.is_canonical() {
+            // Only the exact set of fields being deserialized has one
+            // encoding, older or newer versions of the struct are rejected
+            if len != F::end(0) {
+                return Err(DeserializeError::NonCanonical);
+            }
+
+            nested.claim(position, F::layout())?;
+            nested.zeroed(position + len, F::layout().size() - len)?;
+        }
+
+        Ok(Struct { buffer: nested, fields: core::marker::PhantomData, end: position + len })
     }
 
     fn layout() -> Layout {
-        Layout::new::<[u64; 2]>()
+        Layout::new::<[u64; 3]>()
+    }
+
+    fn out_of_line_size(hint: &mut SizeHint) {
+        hint.reserve(F::layout());
+        F::out_of_line_size(hint);
     }
 }
 
@@ -121,7 +259,8 @@ impl<'a> Primitive<'a> for &'a str {
 
     fn extract(buffer: &mut AlignedReadBuffer<'a>) -> Result<Self, DeserializeError> {
         let [position, length] = buffer.read::<[usize; 2]>()?;
-        let buffer = buffer.buffer.get(position..position + length).ok_or(DeserializeError::MalformedOffset)?;
+        buffer.claim(position, Layout::array::<u8>(length).map_err(|_| DeserializeError::MalformedOffset)?)?;
+        let buffer = buffer.checked_range(position, length)?;
 
         if position == 0 {
             return Ok("");
@@ -133,6 +272,64 @@ impl<'a> Primitive<'a> for &'a str {
     fn layout() -> Layout {
         Layout::new::<[usize; 2]>()
     }
+
+    fn out_of_line_size(hint: &mut SizeHint) {
+        hint.make_variable();
+    }
+}
+
+/// A contiguous run of bytes which is serialized with a single copy and
+/// deserialized by borrowing directly from the input buffer, avoiding the
+/// per-element overhead of a [`List`] of `u8`s
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes<'a>(pub &'a [u8]);
+
+impl<'a> Bytes<'a> {
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn as_slice(&self) -> &'a [u8] {
+        self.0
+    }
+}
+
+impl<'a> core::ops::Deref for Bytes<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<'a> From<&'a [u8]> for Bytes<'a> {
+    fn from(bytes: &'a [u8]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl sealed::Sealed for Bytes<'_> {}
+impl<'a> Primitive<'a> for Bytes<'a> {
+    const ID: u64 = 0x3c1a9e5b7f62d0a4;
+
+    fn extract(buffer: &mut AlignedReadBuffer<'a>) -> Result<Self, DeserializeError> {
+        let [position, length] = buffer.read::<[usize; 2]>()?;
+        buffer.claim(position, Layout::array::<u8>(length).map_err(|_| DeserializeError::MalformedOffset)?)?;
+
+        if length == 0 {
+            return Ok(Self(&[]));
+        }
+
+        buffer.checked_range(position, length).map(Self)
+    }
+
+    fn layout() -> Layout {
+        Layout::new::<[usize; 2]>()
+    }
+
+    fn out_of_line_size(hint: &mut SizeHint) {
+        hint.make_variable();
+    }
 }
 
 pub struct Array<'a, P: Primitive<'a>, const LENGTH: usize> {
@@ -188,30 +385,28 @@ impl<'a, const LENGTH: usize, P: Primitive<'a>> Primitive<'a> for Array<'a, P, L
 
     fn extract(buffer: &mut AlignedReadBuffer<'a>) -> Result<Self, DeserializeError> {
         let [position, length] = buffer.read::<[usize; 2]>()?;
-
-        if buffer
-            .buffer
-            .get(
-                position
-                    ..position
-                        + (P::layout()
-                            .repeat(length)
-                            .map_err(|_| DeserializeError::BufferTooSmall)?
-                            .0
-                            .pad_to_align()
-                            .size()),
-            )
-            .is_none()
-        {
-            return Err(DeserializeError::MalformedOffset);
+        if buffer.is_canonical() && length != LENGTH {
+            return Err(DeserializeError::NonCanonical);
         }
 
-        Ok(Array { buffer: AlignedReadBuffer { buffer: buffer.buffer, position }, fields: core::marker::PhantomData })
+        let layout = P::layout().repeat(length).map_err(|_| DeserializeError::BufferTooSmall)?.0.pad_to_align();
+        let nested = buffer.nested(position, layout.size())?;
+        nested.claim(position, layout)?;
+
+        Ok(Array { buffer: nested, fields: core::marker::PhantomData })
     }
 
     fn layout() -> Layout {
         Layout::new::<[usize; 2]>()
     }
+
+    fn out_of_line_size(hint: &mut SizeHint) {
+        // Layouts of primitives are never large enough for this to overflow
+        hint.reserve(P::layout().repeat(LENGTH).unwrap().0.pad_to_align());
+        for _ in 0..LENGTH {
+            P::out_of_line_size(hint);
+        }
+    }
 }
 
 pub struct List<'a, P: Primitive<'a>> {
@@ -240,6 +435,15 @@ impl<'a, P: Primitive<'a> + 'a> List<'a, P> {
     }
 }
 
+impl<'a> List<'a, u8> {
+    /// The elements of a list of `u8`s are laid out contiguously, so they can
+    /// be borrowed directly from the buffer
+    pub fn as_bytes(&self) -> &'a [u8] {
+        // The range was already checked when the list was extracted
+        self.buffer.checked_range(self.buffer.position, self.length).unwrap_or(&[])
+    }
+}
+
 impl<'a, P: Primitive<'a>> sealed::Sealed for List<'a, P> {}
 impl<'a, P: Primitive<'a>> Primitive<'a> for List<'a, P> {
     const ID: u64 = FxHasher::new().hash(0xf3685126faa78352).hash(P::ID).finish();
@@ -247,33 +451,26 @@ impl<'a, P: Primitive<'a>> Primitive<'a> for List<'a, P> {
     fn extract(buffer: &mut AlignedReadBuffer<'a>) -> Result<Self, DeserializeError> {
         let [position, length] = buffer.read::<[usize; 2]>()?;
 
-        if buffer
-            .buffer
-            .get(
-                position
-                    ..position
-                        + (P::layout()
-                            .repeat(length)
-                            .map_err(|_| DeserializeError::BufferTooSmall)?
-                            .0
-                            .pad_to_align()
-                            .size()),
-            )
-            .is_none()
-        {
-            return Err(DeserializeError::MalformedOffset);
+        if length > buffer.limits.max_list_length {
+            return Err(DeserializeError::LimitExceeded);
         }
 
-        Ok(List {
-            buffer: AlignedReadBuffer { buffer: buffer.buffer, position },
-            length,
-            fields: core::marker::PhantomData,
-        })
+        let layout = P::layout().repeat(length).map_err(|_| DeserializeError::BufferTooSmall)?.0.pad_to_align();
+        let nested = buffer.nested(position, layout.size())?;
+        nested.claim(position, layout)?;
+
+        Ok(List { buffer: nested, length, fields: core::marker::PhantomData })
     }
 
     fn layout() -> Layout {
         Layout::new::<[usize; 2]>()
     }
+
+    fn out_of_line_size(hint: &mut SizeHint) {
+        // An empty list still aligns its (empty) element reservation
+        hint.reserve(P::layout().repeat(0).unwrap().0.pad_to_align());
+        hint.make_variable();
+    }
 }
 
 pub struct Enum<'a, DISCRIMINANT: Primitive<'a>> {
@@ -295,7 +492,10 @@ impl<'a, DISCRIMINANT: Primitive<'a>> Enum<'a, DISCRIMINANT> {
             return Err(DeserializeError::MismatchedId { wanted: self.associated_data_id, found: P::ID });
         }
 
-        P::extract(&mut AlignedReadBuffer { buffer: self.buffer.buffer, position: self.associated_data_position })
+        let mut buffer = self.buffer.nested(self.associated_data_position, 0)?;
+        buffer.claim(self.associated_data_position, P::layout())?;
+
+        P::extract(&mut buffer)
     }
 }
 
@@ -316,6 +516,9 @@ impl<'a, DISCRIMINANT: Primitive<'a>> Primitive<'a> for Enum<'a, DISCRIMINANT> {
         // FIXME: need to add padding size here? don't think so but
         buffer.position += DISCRIMINANT::layout().size();
 
+        let padding = Self::layout().size() - Layout::new::<[u64; 3]>().size() - DISCRIMINANT::layout().size();
+        buffer.zeroed(buffer.position, padding)?;
+
         Ok(Self {
             associated_data_position,
             associated_data_id,
@@ -327,6 +530,11 @@ impl<'a, DISCRIMINANT: Primitive<'a>> Primitive<'a> for Enum<'a, DISCRIMINANT> {
     fn layout() -> Layout {
         Layout::new::<[u64; 3]>().extend(DISCRIMINANT::layout()).unwrap().0.pad_to_align()
     }
+
+    fn out_of_line_size(hint: &mut SizeHint) {
+        // The associated data's type differs between variants
+        hint.make_variable();
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -488,17 +696,41 @@ pub trait Fields<'a>: Sized + sealed::Sealed {
     fn layout() -> Layout {
         <Self::Head as Primitive>::layout().extend(<Self::Next as Fields>::layout()).unwrap().0.pad_to_align()
     }
+
+    /// Where the last field ends when the fields are serialized starting at
+    /// `offset`, which unlike [`Fields::layout`] doesn't include any trailing
+    /// padding
+    #[inline(always)]
+    fn end(offset: usize) -> usize {
+        let head = <Self::Head as Primitive>::layout();
+        <Self::Next as Fields>::end((offset + head.align() - 1) / head.align() * head.align() + head.size())
+    }
+
+    /// The out of line space reserved by each field, in order
+    #[inline(always)]
+    fn out_of_line_size(hint: &mut SizeHint) {
+        <Self::Head as Primitive>::out_of_line_size(hint);
+        <Self::Next as Fields>::out_of_line_size(hint);
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    #[test]
+    #[test_case]
     fn struct_extract() {
         type TestStruct<'a> = Struct<'a, (u64, u32, u8, &'a str)>;
-        let buffer =
-            [<TestStruct as Primitive>::ID, 16, 0xDEADF00DBEEFBABEu64, 0x000000F0C0BB0000, 48, 8, 0x7465657954534554];
+        let buffer = [
+            <TestStruct as Primitive>::ID,
+            24,
+            32,
+            0xDEADF00DBEEFBABEu64,
+            0x000000F0C0BB0000,
+            56,
+            8,
+            0x7465657954534554,
+        ];
         let mut buf =
             AlignedReadBuffer::new(unsafe { core::slice::from_raw_parts(buffer.as_ptr().cast(), buffer.len() * 8) });
         let strukt = TestStruct::extract(&mut buf).unwrap();
@@ -507,4 +739,19 @@ mod test {
         assert_eq!(strukt.next().next().field(), Ok(0xF0));
         assert_eq!(strukt.next().next().next().field(), Ok("TESTyeet"));
     }
+
+    #[test_case]
+    fn struct_missing_trailing_field() {
+        // Serialized before the string field was added
+        type TestStruct<'a> = Struct<'a, (u64, u32, u8, &'a str)>;
+        let buffer =
+            [<TestStruct as Primitive>::ID, 24, 13, 0xDEADF00DBEEFBABEu64, 0x000000F0C0BB0000, 0xAAAAAAAAAAAAAAAA];
+        let mut buf =
+            AlignedReadBuffer::new(unsafe { core::slice::from_raw_parts(buffer.as_ptr().cast(), buffer.len() * 8) });
+        let strukt = TestStruct::extract(&mut buf).unwrap();
+        assert!(strukt.next().next().has_field());
+        assert_eq!(strukt.next().next().field(), Ok(0xF0));
+        assert!(!strukt.next().next().next().has_field());
+        assert_eq!(strukt.next().next().next().field(), Err(DeserializeError::MissingField));
+    }
 }
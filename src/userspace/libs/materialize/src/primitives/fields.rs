@@ -53,6 +53,12 @@ impl<'a> Fields<'a> for () {
     fn layout() -> core::alloc::Layout {
         core::alloc::Layout::new::<()>()
     }
+
+    fn end(offset: usize) -> usize {
+        offset
+    }
+
+    fn out_of_line_size(_: &mut crate::SizeHint) {}
 }
 
 fields!(A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z);
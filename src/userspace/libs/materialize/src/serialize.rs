@@ -13,6 +13,11 @@ use crate::{
     Serializable,
 };
 use alloc::alloc::Layout;
+use core::{
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+};
 use librust::capabilities::Capability;
 use serializers::PrimitiveSerializer;
 
@@ -21,6 +26,8 @@ pub enum SerializeError {
     AllocationError,
     NotEnoughSpace,
     MisalignedPosition,
+    /// The value doesn't fit in the buffer given to [`Serializer::with_buffer`]
+    BufferTooSmall,
 }
 
 impl From<core::alloc::AllocError> for SerializeError {
@@ -68,22 +75,108 @@ impl ReservationToken {
     }
 }
 
+/// The memory a [`Serializer`] writes into
+enum SerializerBuffer {
+    Heap(AlignedHeapBuffer),
+    /// A caller-provided buffer of which the first `len` bytes are in use. This
+    /// is only ever created by [`Serializer::with_buffer`], whose
+    /// [`BufferSerializer`] keeps the buffer borrowed for as long as the
+    /// serializer is alive.
+    Borrowed {
+        buffer: NonNull<[u8]>,
+        len: usize,
+    },
+}
+
+impl SerializerBuffer {
+    fn resize(&mut self, new_len: usize) -> Result<(), SerializeError> {
+        match self {
+            Self::Heap(buffer) => Ok(buffer.resize(new_len, 0)?),
+            Self::Borrowed { buffer, len } => {
+                if new_len > buffer.len() {
+                    return Err(SerializeError::BufferTooSmall);
+                }
+
+                // Padding is zeroed the same way it is in heap buffers, rather
+                // than leaking whatever the caller had in the buffer before
+                if new_len > *len {
+                    unsafe { buffer.as_mut()[*len..new_len].fill(0) };
+                }
+
+                *len = new_len;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Deref for SerializerBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Heap(buffer) => &buffer[..],
+            Self::Borrowed { buffer, len } => unsafe { &buffer.as_ref()[..*len] },
+        }
+    }
+}
+
+impl DerefMut for SerializerBuffer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Heap(buffer) => &mut buffer[..],
+            Self::Borrowed { buffer, len } => unsafe { &mut buffer.as_mut()[..*len] },
+        }
+    }
+}
+
 pub struct Serializer {
-    buffer: AlignedHeapBuffer,
+    buffer: SerializerBuffer,
     capabilities: alloc::vec::Vec<Capability>,
 }
 
 impl Serializer {
     pub fn new() -> Self {
-        Self { buffer: AlignedHeapBuffer::new(), capabilities: alloc::vec::Vec::new() }
+        Self { buffer: SerializerBuffer::Heap(AlignedHeapBuffer::new()), capabilities: alloc::vec::Vec::new() }
+    }
+
+    /// Create a serializer which writes into `buffer` instead of allocating
+    /// its own, e.g. to serialize straight into shared memory. Any
+    /// capabilities in the serialized values are still collected, see
+    /// [`BufferSerializer::into_capabilities`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buffer` isn't aligned to 8 bytes, which serialized integers
+    /// rely on
+    pub fn with_buffer(buffer: &mut [u8]) -> BufferSerializer<'_> {
+        assert_eq!(buffer.as_ptr() as usize % crate::MINIMUM_ALIGNMENT, 0, "misaligned serialization buffer");
+
+        BufferSerializer {
+            serializer: Self {
+                buffer: SerializerBuffer::Borrowed { buffer: NonNull::from(buffer), len: 0 },
+                capabilities: alloc::vec::Vec::new(),
+            },
+            _buffer: PhantomData,
+        }
     }
 
     pub fn into_buffer(self) -> AlignedHeapBuffer {
-        self.buffer
+        self.into_parts().0
     }
 
     pub fn into_parts(self) -> (AlignedHeapBuffer, alloc::vec::Vec<Capability>) {
-        (self.buffer, self.capabilities)
+        match self.buffer {
+            SerializerBuffer::Heap(buffer) => (buffer, self.capabilities),
+            SerializerBuffer::Borrowed { .. } => {
+                unreachable!("serializers with borrowed buffers are never handed out by value")
+            }
+        }
+    }
+
+    /// The number of bytes serialized so far, including padding
+    pub fn position(&self) -> usize {
+        self.buffer.len()
     }
 
     pub fn serialize<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), SerializeError> {
@@ -103,7 +196,7 @@ impl Serializer {
         self.align_to(layout.align())?;
 
         let current_len = self.buffer.len();
-        self.buffer.resize(current_len + layout.size(), 0)?;
+        self.buffer.resize(current_len + layout.size())?;
 
         Ok(ReservationToken { position: current_len, length: layout.size() })
     }
@@ -119,16 +212,16 @@ impl Serializer {
         Ok(unsafe { &mut *self.buffer.as_mut_ptr().add(tkn.position).cast() })
     }
 
-    // #[track_caller]
-    // pub(crate) fn write_bytes(&mut self, token: &mut ReservationToken, bytes: &[u8]) -> Result<(), SerializeError> {
-    //     if bytes.len() > token.length {
-    //         return Err(SerializeError::NotEnoughSpace);
-    //     }
+    #[track_caller]
+    pub(crate) fn write_bytes(&mut self, token: &mut ReservationToken, bytes: &[u8]) -> Result<(), SerializeError> {
+        if bytes.len() > token.length {
+            return Err(SerializeError::NotEnoughSpace);
+        }
 
-    //     self.buffer[token.position..][..bytes.len()].copy_from_slice(bytes);
-    //     *token = ReservationToken { position: token.position + bytes.len(), length: token.length - bytes.len() };
-    //     Ok(())
-    // }
+        self.buffer[token.position..][..bytes.len()].copy_from_slice(bytes);
+        *token = ReservationToken { position: token.position + bytes.len(), length: token.length - bytes.len() };
+        Ok(())
+    }
 
     fn align_to(&mut self, align: usize) -> Result<(), SerializeError> {
         let current_len = self.buffer.len();
@@ -138,7 +231,7 @@ impl Serializer {
         }
 
         let padding = align - (current_len % align);
-        self.buffer.resize(current_len + padding, 0)?;
+        self.buffer.resize(current_len + padding)?;
 
         Ok(())
     }
@@ -150,11 +243,60 @@ impl Default for Serializer {
     }
 }
 
+/// A [`Serializer`] writing into a borrowed buffer, see
+/// [`Serializer::with_buffer`]
+pub struct BufferSerializer<'buf> {
+    serializer: Serializer,
+    _buffer: PhantomData<&'buf mut [u8]>,
+}
+
+impl BufferSerializer<'_> {
+    /// Serialize `value` after anything serialized so far, returning the number
+    /// of bytes of the buffer now in use. If the value doesn't fit,
+    /// [`SerializeError::BufferTooSmall`] is returned and the contents of the
+    /// buffer past the previous position are unspecified.
+    pub fn serialize<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<usize, SerializeError> {
+        self.serializer.serialize(value)?;
+        Ok(self.serializer.position())
+    }
+
+    /// The number of bytes serialized so far, including padding
+    pub fn position(&self) -> usize {
+        self.serializer.position()
+    }
+
+    /// The capabilities referenced by the serialized values, which need to be
+    /// sent alongside the buffer
+    pub fn into_capabilities(self) -> alloc::vec::Vec<Capability> {
+        self.serializer.capabilities
+    }
+}
+
 pub trait Serialize: Serializable {
     fn serialize<'a>(
         &self,
         serializer: <Self::Primitive<'a> as PrimitiveSerializer<'a>>::Serializer,
     ) -> Result<(), SerializeError>;
+
+    /// Serialize the elements of a list or array into `token`, which has room
+    /// for all of them. `u8` overrides this to copy the whole slice at once.
+    #[doc(hidden)]
+    fn serialize_elements(
+        elements: &[Self],
+        serializer: &mut Serializer,
+        mut token: ReservationToken,
+    ) -> Result<(), SerializeError>
+    where
+        Self: Sized,
+    {
+        for element in elements {
+            let (element_token, rest) = token.split(<Self::Primitive<'_> as Primitive<'_>>::layout())?;
+            token = rest;
+            serializer.serialize_into(element_token, element)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Serialize for () {
@@ -166,6 +308,15 @@ impl Serialize for () {
     }
 }
 
+impl Serialize for bool {
+    fn serialize<'a>(
+        &self,
+        serializer: <Self::Primitive<'a> as PrimitiveSerializer<'a>>::Serializer,
+    ) -> Result<(), SerializeError> {
+        Ok(*serializer = *self as u8)
+    }
+}
+
 impl Serialize for u8 {
     fn serialize<'a>(
         &self,
@@ -173,6 +324,14 @@ impl Serialize for u8 {
     ) -> Result<(), SerializeError> {
         Ok(*serializer = *self)
     }
+
+    fn serialize_elements(
+        elements: &[Self],
+        serializer: &mut Serializer,
+        mut token: ReservationToken,
+    ) -> Result<(), SerializeError> {
+        serializer.write_bytes(&mut token, elements)
+    }
 }
 
 impl Serialize for i8 {
@@ -274,6 +433,15 @@ impl Serialize for alloc::string::String {
     }
 }
 
+impl Serialize for crate::primitives::Bytes<'_> {
+    fn serialize<'a>(
+        &self,
+        serializer: <Self::Primitive<'a> as PrimitiveSerializer<'a>>::Serializer,
+    ) -> Result<(), SerializeError> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
 impl<T: Serialize, const LENGTH: usize> Serialize for [T; LENGTH] {
     fn serialize<'a>(
         &self,
@@ -384,103 +552,44 @@ mod test {
     use super::*;
     use crate::{
         deserialize::{Deserialize, Deserializer},
-        primitives::{AlignedReadBuffer, Array, List, Struct},
-        DeserializeError, Serializable, Serialize,
+        primitives::{AlignedReadBuffer, Array, Bytes, List, Struct},
+        DeserializeError, Serializable, Serialize, SizeHint,
     };
+    use librust::capabilities::CapabilityRights;
     use materialize_derive::Deserialize;
 
-    #[test]
+    #[test_case]
     fn roundtrip_struct() {
-        struct MyCoolStruct;
-
-        impl Serializable for MyCoolStruct {
-            type Primitive<'a> = Struct<'a, (u64, u32, u8, &'a str)>;
-        }
-
-        impl Serialize for MyCoolStruct {
-            fn serialize<'a>(
-                &self,
-                serializer: <Self::Primitive<'a> as PrimitiveSerializer<'a>>::Serializer,
-            ) -> Result<(), SerializeError> {
-                let serializer = serializer.serialize_field(&0xDEADF00DBEEFBABEu64)?;
-                let serializer = serializer.serialize_field(&0xC0BB0000u32)?;
-                let serializer = serializer.serialize_field(&0xF0u8)?;
-                serializer.serialize_field("TESTyeet")?;
-
-                Ok(())
-            }
-        }
-
-        impl<'de> Deserialize<'de> for MyCoolStruct {
-            // type Primitive = Struct<'de, (u64, u32, u8, &'de str)>;
-
-            fn deserialize(strukt: <Self as Serializable>::Primitive<'de>) -> Result<Self, DeserializeError> {
-                assert_eq!(strukt.field(), Ok(0xDEADF00DBEEFBABE));
-                assert_eq!(strukt.next().field(), Ok(0xC0BB0000));
-                assert_eq!(strukt.next().next().field(), Ok(0xF0));
-                assert_eq!(strukt.next().next().next().field(), Ok("TESTyeet"));
-                Ok(Self)
-            }
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
+        struct MyCoolStruct {
+            a: u64,
+            b: u32,
+            c: u8,
+            d: std::string::String,
         }
 
+        let strukt = MyCoolStruct { a: 0xDEADF00DBEEFBABE, b: 0xC0BB0000, c: 0xF0, d: "TESTyeet".into() };
         let mut serializer = Serializer::new();
-        serializer.serialize(&MyCoolStruct).unwrap();
-        let mut deserializer = Deserializer::new(&serializer.buffer[..], &[]);
-        deserializer.deserialize::<MyCoolStruct>().unwrap();
+        serializer.serialize(&strukt).unwrap();
+        let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+        assert_eq!(deserializer.deserialize::<MyCoolStruct>(), Ok(strukt));
     }
 
-    #[test]
+    #[test_case]
     fn complex_struct() {
-        #[derive(Debug, PartialEq)]
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
         struct ComplexStruct {
             frabs: [LittleStruct; 5],
         }
 
-        impl Serializable for ComplexStruct {
-            type Primitive<'a> = Struct<'a, (<[LittleStruct; 5] as Serializable>::Primitive<'a>,)>;
-        }
-
-        impl Serialize for ComplexStruct {
-            fn serialize<'a>(
-                &self,
-                serializer: <Self::Primitive<'a> as PrimitiveSerializer<'a>>::Serializer,
-            ) -> Result<(), SerializeError> {
-                serializer.serialize_field(&self.frabs)?;
-                Ok(())
-            }
-        }
-
-        impl<'de> Deserialize<'de> for ComplexStruct {
-            fn deserialize(primitive: <Self as Serializable>::Primitive<'de>) -> Result<Self, DeserializeError> {
-                Ok(Self { frabs: <_>::deserialize(primitive.field()?)? })
-            }
-        }
-
-        #[derive(Debug, PartialEq)]
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
         struct LittleStruct {
             frab: u32,
         }
 
-        impl Serializable for LittleStruct {
-            type Primitive<'a> = Struct<'a, (u32,)>;
-        }
-
-        impl Serialize for LittleStruct {
-            fn serialize<'a>(
-                &self,
-                serializer: <Self::Primitive<'a> as PrimitiveSerializer<'a>>::Serializer,
-            ) -> Result<(), SerializeError> {
-                serializer.serialize_field(&self.frab)?;
-                Ok(())
-            }
-        }
-
-        impl<'de> Deserialize<'de> for LittleStruct {
-            fn deserialize(primitive: <Self as Serializable>::Primitive<'de>) -> Result<Self, DeserializeError> {
-                Ok(Self { frab: primitive.field()? })
-            }
-        }
-
         let mut serializer = Serializer::new();
         let strukt = ComplexStruct {
             frabs: [
@@ -497,13 +606,13 @@ mod test {
         assert_eq!(deserializer.deserialize::<ComplexStruct>(), Ok(strukt));
     }
 
-    #[test]
+    #[test_case]
     fn vec() {
         #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
         #[materialize(reexport_path = "crate")]
         struct Padding(u32, u8);
 
-        let v = std::vec![Padding(0xAA55AA55, 0xFF), Padding(0x22DD22DD, 0x01)];
+        let v = alloc::vec![Padding(0xAA55AA55, 0xFF), Padding(0x22DD22DD, 0x01)];
         let mut serializer = Serializer::new();
         serializer.serialize(&v).unwrap();
         pretty_print_buffer(&serializer.buffer);
@@ -511,6 +620,274 @@ mod test {
         assert_eq!(deserializer.deserialize::<std::vec::Vec<Padding>>(), Ok(v));
     }
 
+    #[test_case]
+    fn bytes() {
+        let payload = (0..64 * 1024).map(|i| i as u8).collect::<std::vec::Vec<u8>>();
+        let odd = [0xAA; 7];
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&(Bytes::new(&payload), Bytes::new(&odd), 0xDEADF00DBEEFBABEu64)).unwrap();
+
+        // The payload is copied into the buffer exactly once, next to the
+        // struct headers and padding
+        let buffer = &serializer.buffer[..];
+        assert!(buffer.len() < payload.len() + odd.len() + 128);
+
+        let deserializer = Deserializer::new(buffer, &[]);
+        let (bytes, odd_bytes, trailer) = deserializer.deserialize::<(Bytes<'_>, Bytes<'_>, u64)>().unwrap();
+        assert_eq!(bytes.as_slice(), &payload[..]);
+        assert_eq!(odd_bytes.as_slice(), &odd[..]);
+        assert_eq!(trailer, 0xDEADF00DBEEFBABE);
+
+        // Deserializing borrows from the input buffer instead of copying
+        assert!(buffer.as_ptr_range().contains(&bytes.as_ptr()));
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&alloc::vec![1u8, 2, 3]).unwrap();
+        let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+        assert_eq!(deserializer.deserialize::<&[u8]>(), Ok(&[1u8, 2, 3][..]));
+    }
+
+    #[test_case]
+    fn byte_lists() {
+        let payload = (0..64 * 1024).map(|i| i as u8).collect::<std::vec::Vec<u8>>();
+
+        // A list of bytes is the list header followed directly by the bytes,
+        // the same encoding as `Bytes`
+        let mut serializer = Serializer::new();
+        serializer.serialize(&payload).unwrap();
+        assert_eq!(serializer.buffer.len(), core::mem::size_of::<[usize; 2]>() + payload.len());
+
+        let mut bytes_serializer = Serializer::new();
+        bytes_serializer.serialize(&Bytes::new(&payload)).unwrap();
+        assert_eq!(serializer.buffer[..], bytes_serializer.buffer[..]);
+
+        let mut slice_serializer = Serializer::new();
+        slice_serializer.serialize(&payload[..]).unwrap();
+        assert_eq!(serializer.buffer[..], slice_serializer.buffer[..]);
+
+        let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+        assert_eq!(deserializer.deserialize::<std::vec::Vec<u8>>(), Ok(payload.clone()));
+
+        // Fixed size byte arrays are copied in bulk as well
+        let mut serializer = Serializer::new();
+        serializer.serialize(&[0xAAu8; 13]).unwrap();
+        assert_eq!(serializer.buffer.len(), core::mem::size_of::<[usize; 2]>() + 13);
+        let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+        assert_eq!(deserializer.deserialize::<[u8; 13]>(), Ok([0xAA; 13]));
+    }
+
+    #[test_case]
+    fn bools() {
+        for value in [true, false] {
+            let mut serializer = Serializer::new();
+            serializer.serialize(&(value, 0xAAu8)).unwrap();
+            let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+            assert_eq!(deserializer.deserialize::<(bool, u8)>(), Ok((value, 0xAA)));
+        }
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&2u8).unwrap();
+        let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+        assert_eq!(deserializer.deserialize::<bool>(), Err(DeserializeError::InvalidBool));
+    }
+
+    #[test_case]
+    fn empty_array() {
+        let mut serializer = Serializer::new();
+        serializer.serialize(&([0u32; 0], 0x5555u16, [true; 0])).unwrap();
+        let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+        assert_eq!(deserializer.deserialize::<([u32; 0], u16, [bool; 0])>(), Ok(([], 0x5555, [])));
+    }
+
+    #[test_case]
+    fn borrowed_str() {
+        let mut serializer = Serializer::new();
+        serializer.serialize(&("/etc/motd", 0x55AAu32)).unwrap();
+        let buffer = &serializer.buffer[..];
+
+        // Borrowing points straight into the buffer instead of copying
+        let (path, flags) = Deserializer::new(buffer, &[]).deserialize::<(&str, u32)>().unwrap();
+        assert_eq!((path, flags), ("/etc/motd", 0x55AA));
+        assert!(buffer.as_ptr_range().contains(&path.as_ptr()));
+
+        // Asking for an owned `String` copies out of the buffer instead
+        let (owned, _) = Deserializer::new(buffer, &[]).deserialize::<(std::string::String, u32)>().unwrap();
+        assert_eq!(owned, path);
+        assert!(!buffer.as_ptr_range().contains(&owned.as_ptr()));
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&"pindakaas").unwrap();
+        let buffer = &serializer.buffer[..];
+        let borrowed: &str = Deserializer::new(buffer, &[]).deserialize_borrowed().unwrap();
+        assert_eq!(borrowed, "pindakaas");
+        assert!(buffer.as_ptr_range().contains(&borrowed.as_ptr()));
+    }
+
+    #[test_case]
+    fn truncated_buffers() {
+        type Value = (std::string::String, std::vec::Vec<(u8, isize)>, Option<u32>);
+
+        let mut serializer = Serializer::new();
+        serializer
+            .serialize(&(std::string::String::from("pindakaas"), alloc::vec![(1u8, -1isize), (2, -2)], Some(5u32)))
+            .unwrap();
+        let buffer = &serializer.buffer[..];
+
+        assert!(Deserializer::new(&[], &[]).deserialize::<Value>().is_err());
+        for len in 0..buffer.len() {
+            // Only checking that this doesn't panic, trailing padding can be
+            // cut off without losing anything
+            let _ = Deserializer::new(&buffer[..len], &[]).deserialize::<Value>();
+        }
+    }
+
+    #[test_case]
+    fn corrupt_offsets() {
+        let mut serializer = Serializer::new();
+        serializer.serialize(&("pindakaas", alloc::vec![1u64, 2, 3], Bytes::new(&[0xAA; 9]))).unwrap();
+
+        let original = serializer.buffer.to_vec();
+
+        for word in 0..original.len() / 8 {
+            for garbage in [usize::MAX, usize::MAX / 2, 0x1000] {
+                serializer.buffer.copy_from_slice(&original);
+                serializer.buffer[word * 8..][..8].copy_from_slice(&garbage.to_ne_bytes());
+
+                // Only checking that this doesn't panic or read out of bounds
+                let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
+                let _ = deserializer.deserialize::<(&str, std::vec::Vec<u64>, Bytes<'_>)>();
+            }
+        }
+    }
+
+    #[test_case]
+    fn depth_limit() {
+        let mut serializer = Serializer::new();
+        serializer.serialize(&Some(Some(Some(0x55u8)))).unwrap();
+        let buffer = &serializer.buffer[..];
+
+        assert_eq!(
+            Deserializer::new(buffer, &[]).deserialize::<Option<Option<Option<u8>>>>(),
+            Ok(Some(Some(Some(0x55))))
+        );
+        assert_eq!(
+            Deserializer::new(buffer, &[]).max_depth(1).deserialize::<Option<Option<Option<u8>>>>(),
+            Err(DeserializeError::LimitExceeded)
+        );
+    }
+
+    #[test_case]
+    fn list_length_limit() {
+        let mut serializer = Serializer::new();
+        serializer.serialize(&alloc::vec![1u32, 2, 3, 4]).unwrap();
+        let buffer = &serializer.buffer[..];
+
+        assert_eq!(
+            Deserializer::new(buffer, &[]).max_list_length(3).deserialize::<std::vec::Vec<u32>>(),
+            Err(DeserializeError::LimitExceeded)
+        );
+        assert_eq!(
+            Deserializer::new(buffer, &[]).max_list_length(4).deserialize::<std::vec::Vec<u32>>(),
+            Ok(alloc::vec![1, 2, 3, 4])
+        );
+    }
+
+    #[test_case]
+    fn canonical() {
+        type Value<'a> = (&'a str, std::vec::Vec<u64>, Bytes<'a>, Option<u32>, &'a str);
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&("pindakaas", alloc::vec![1u64, 2, 3], Bytes::new(&[0xAA; 9]), Some(5u32), "")).unwrap();
+        assert_eq!(
+            Deserializer::new(&serializer.buffer[..], &[]).validate_canonical().deserialize::<Value<'_>>(),
+            Ok(("pindakaas", alloc::vec![1, 2, 3], Bytes::new(&[0xAA; 9]), Some(5), ""))
+        );
+
+        // The second string reuses the bytes of the first
+        type Strings<'a> = <(&'a str, &'a str) as Serializable>::Primitive<'a>;
+        let mut words = [<Strings<'_> as Primitive>::ID, 24, 32, 56, 4, 56, 4, u64::from_ne_bytes(*b"abcd\0\0\0\0")];
+        let bytes = |words: &[u64; 8]| unsafe { core::slice::from_raw_parts(words.as_ptr().cast::<u8>(), 64) }.to_vec();
+
+        let overlapping = bytes(&words);
+        assert_eq!(Deserializer::new(&overlapping, &[]).deserialize::<(&str, &str)>(), Ok(("abcd", "abcd")));
+        assert_eq!(
+            Deserializer::new(&overlapping, &[]).validate_canonical().deserialize::<(&str, &str)>(),
+            Err(DeserializeError::NonCanonical)
+        );
+
+        // Which is only accepted laid out the way the serializer does it
+        words[5] = 60;
+        words[7] = u64::from_ne_bytes(*b"abcdabcd");
+        let canonical = bytes(&words);
+        let mut serializer = Serializer::new();
+        serializer.serialize(&("abcd", "abcd")).unwrap();
+        assert_eq!(&serializer.buffer[..], &canonical[..]);
+        assert_eq!(
+            Deserializer::new(&canonical, &[]).validate_canonical().deserialize::<(&str, &str)>(),
+            Ok(("abcd", "abcd"))
+        );
+
+        // Garbage in the padding between fields
+        let mut serializer = Serializer::new();
+        serializer.serialize(&(1u8, 2u64)).unwrap();
+        serializer.buffer[25] = 0xFF;
+        assert_eq!(Deserializer::new(&serializer.buffer[..], &[]).deserialize::<(u8, u64)>(), Ok((1, 2)));
+        assert_eq!(
+            Deserializer::new(&serializer.buffer[..], &[]).validate_canonical().deserialize::<(u8, u64)>(),
+            Err(DeserializeError::NonCanonical)
+        );
+
+        // Extra fields are ignored normally, but not in canonical mode
+        let mut serializer = Serializer::new();
+        serializer.serialize(&(1u32, 2u8)).unwrap();
+        assert_eq!(Deserializer::new(&serializer.buffer[..], &[]).deserialize::<(u32,)>(), Ok((1,)));
+        assert_eq!(
+            Deserializer::new(&serializer.buffer[..], &[]).validate_canonical().deserialize::<(u32,)>(),
+            Err(DeserializeError::NonCanonical)
+        );
+
+        // The same goes for anything after the end of the value
+        let mut trailing = canonical.clone();
+        trailing.extend_from_slice(&[0; 8]);
+        assert_eq!(Deserializer::new(&trailing, &[]).deserialize::<(&str, &str)>(), Ok(("abcd", "abcd")));
+        assert_eq!(
+            Deserializer::new(&trailing, &[]).validate_canonical().deserialize::<(&str, &str)>(),
+            Err(DeserializeError::NonCanonical)
+        );
+    }
+
+    #[test_case]
+    fn borrowed_buffer() {
+        let value = (0xDEADF00DBEEFBABEu64, alloc::vec![1u16, 2, 3], "pindakaas");
+        let cap = Capability::new(librust::capabilities::CapabilityPtr::new(5), CapabilityRights::READ);
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&(&value, cap)).unwrap();
+        let expected = &serializer.buffer[..];
+
+        // Backed by `u64`s to get the alignment serializers expect
+        let mut words = alloc::vec![0xAAAA_AAAA_AAAA_AAAAu64; (expected.len() + 7) / 8];
+        let storage = unsafe { core::slice::from_raw_parts_mut(words.as_mut_ptr().cast::<u8>(), expected.len()) };
+
+        let mut exact = Serializer::with_buffer(storage);
+        assert_eq!(exact.serialize(&(&value, cap)).unwrap(), expected.len());
+        let caps = exact.into_capabilities();
+        assert_eq!(caps.len(), 1);
+        assert_eq!(caps[0].cptr, cap.cptr);
+        assert_eq!(storage, expected);
+
+        Serializer::with_buffer(storage).serialize(&value).unwrap();
+        let deserializer = Deserializer::new(storage, &[]);
+        assert_eq!(deserializer.deserialize::<(u64, std::vec::Vec<u16>, &str)>(), Ok(value.clone()));
+
+        let too_small = &mut storage[..expected.len() - 1];
+        assert!(matches!(
+            Serializer::with_buffer(too_small).serialize(&(&value, cap)),
+            Err(SerializeError::BufferTooSmall)
+        ));
+    }
+
     fn pretty_print_buffer(b: &[u8]) {
         for (i, chunk) in b.chunks(8).enumerate() {
             std::print!("{:<02x}:    ", i * 8);
@@ -521,7 +898,7 @@ mod test {
         }
     }
 
-    #[test]
+    #[test_case]
     fn enoom() {
         #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
         #[materialize(reexport_path = "crate")]
@@ -565,8 +942,127 @@ mod test {
         assert_eq!(deserializer.deserialize::<Fraz2>(), Ok(Fraz2::Baz { my_special_int: 0x55AA55AA }));
 
         let mut serializer = Serializer::new();
-        serializer.serialize(&Fraz2::Yeet(std::vec![(1, -1), (2, -2), (3, -3)]));
+        serializer.serialize(&Fraz2::Yeet(alloc::vec![(1, -1), (2, -2), (3, -3)]));
         let deserializer = Deserializer::new(&serializer.buffer[..], &[]);
-        assert_eq!(deserializer.deserialize::<Fraz2>(), Ok(Fraz2::Yeet(std::vec![(1, -1), (2, -2), (3, -3)])));
+        assert_eq!(deserializer.deserialize::<Fraz2>(), Ok(Fraz2::Yeet(alloc::vec![(1, -1), (2, -2), (3, -3)])));
+    }
+
+    #[test_case]
+    fn fixed_size_hint() {
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
+        struct Fixed {
+            a: u8,
+            b: u64,
+            c: [u16; 3],
+            d: (u32, i8),
+        }
+
+        // 24 byte header, 56 bytes of fields, 6 bytes of array elements, then
+        // the tuple's 8 bytes of fields aligned to 4
+        assert_eq!(Fixed::serialized_size_hint(), SizeHint::Fixed(96));
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&Fixed { a: 1, b: 2, c: [3, 4, 5], d: (6, 7) }).unwrap();
+        assert_eq!(serializer.position(), 96);
+    }
+
+    #[test_case]
+    fn variable_size_hint() {
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
+        struct WithList {
+            id: u32,
+            items: std::vec::Vec<u16>,
+        }
+
+        let hint = WithList::serialized_size_hint();
+        assert_eq!(hint, SizeHint::Variable { minimum: 48 });
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&WithList { id: 1, items: alloc::vec![] }).unwrap();
+        assert_eq!(serializer.position(), hint.minimum());
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&WithList { id: 1, items: alloc::vec![1, 2, 3] }).unwrap();
+        assert_eq!(serializer.position(), hint.minimum() + 6);
+    }
+
+    #[test_case]
+    fn defaulted_fields() {
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
+        struct OpenV1 {
+            path: std::string::String,
+            flags: u32,
+        }
+
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
+        struct OpenV2 {
+            path: std::string::String,
+            flags: u32,
+            #[materialize(default)]
+            mode: u16,
+            #[materialize(default)]
+            tags: std::vec::Vec<u8>,
+        }
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&OpenV1 { path: std::string::String::from("/etc/motd"), flags: 5 }).unwrap();
+        let old = &serializer.buffer[..];
+
+        // Buffers from before the fields were added get their defaults
+        assert_eq!(
+            Deserializer::new(old, &[]).deserialize::<OpenV2>(),
+            Ok(OpenV2 { path: std::string::String::from("/etc/motd"), flags: 5, mode: 0, tags: alloc::vec![] })
+        );
+
+        let new =
+            OpenV2 { path: std::string::String::from("/etc/motd"), flags: 5, mode: 0o644, tags: alloc::vec![1, 2] };
+        let mut serializer = Serializer::new();
+        serializer.serialize(&new).unwrap();
+        assert_eq!(Deserializer::new(&serializer.buffer[..], &[]).deserialize::<OpenV2>(), Ok(new));
+
+        // Older receivers ignore the fields they don't know about
+        assert_eq!(
+            Deserializer::new(&serializer.buffer[..], &[]).deserialize::<OpenV1>(),
+            Ok(OpenV1 { path: std::string::String::from("/etc/motd"), flags: 5 })
+        );
+
+        // Fields without a default are still required
+        assert_eq!(
+            Deserializer::new(&serializer.buffer[..], &[])
+                .deserialize::<(std::string::String, u32, u16, std::vec::Vec<u8>, u64)>(),
+            Err(DeserializeError::MissingField)
+        );
+    }
+
+    #[test_case]
+    fn skipped_fields() {
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
+        struct Cached {
+            key: u32,
+            #[materialize(skip)]
+            cached: Option<std::string::String>,
+            value: u64,
+        }
+
+        #[derive(Debug, PartialEq, Serializable, Deserialize, Serialize)]
+        #[materialize(reexport_path = "crate")]
+        struct CachedTuple(u32, #[materialize(skip)] Option<u8>, u64);
+
+        assert_eq!(Cached::serialized_size_hint(), <(u32, u64)>::serialized_size_hint());
+
+        let mut serializer = Serializer::new();
+        serializer.serialize(&Cached { key: 1, cached: Some(std::string::String::from("one")), value: 2 }).unwrap();
+        let buffer = &serializer.buffer[..];
+        assert_eq!(
+            Deserializer::new(buffer, &[]).deserialize::<Cached>(),
+            Ok(Cached { key: 1, cached: None, value: 2 })
+        );
+        assert_eq!(Deserializer::new(buffer, &[]).deserialize::<(u32, u64)>(), Ok((1, 2)));
+        assert_eq!(Deserializer::new(buffer, &[]).deserialize::<CachedTuple>(), Ok(CachedTuple(1, None, 2)));
     }
 }
@@ -7,7 +7,7 @@
 
 use super::{ReservationToken, Serialize, SerializeError, Serializer};
 use crate::{
-    primitives::{Array, Capability, Enum, Fields, List, Primitive, Struct},
+    primitives::{Array, Bytes, Capability, Enum, Fields, List, Primitive, Struct},
     sealed,
 };
 
@@ -45,6 +45,7 @@ impl<'a, F: Fields<'a>> PrimitiveSerializer<'a> for Struct<'a, F> {
         let field_token = serializer.reserve_space(F::layout())?;
         *serializer.integer(&mut token)? = Self::ID;
         *serializer.integer(&mut token)? = field_token.position() as u64;
+        *serializer.integer(&mut token)? = F::end(0) as u64;
 
         Ok(StructSerializer { field_token, serializer, _fields: core::marker::PhantomData })
     }
@@ -57,14 +58,8 @@ pub struct ArraySerializer<'a, const LENGTH: usize> {
 
 impl<'a, const LENGTH: usize> ArraySerializer<'a, LENGTH> {
     pub fn serialize_array<T: Serialize>(self, array: &[T; LENGTH]) -> Result<(), SerializeError> {
-        let Self { mut data_token, serializer } = self;
-        for item in array {
-            let (token, rest) = data_token.split(<T::Primitive<'_> as Primitive<'_>>::layout())?;
-            data_token = rest;
-            serializer.serialize_into(token, item)?;
-        }
-
-        Ok(())
+        let Self { data_token, serializer } = self;
+        T::serialize_elements(array, serializer, data_token)
     }
 }
 
@@ -91,7 +86,7 @@ pub struct ListSerializer<'a> {
 impl<'a> ListSerializer<'a> {
     pub fn serialize_list<T: Serialize>(self, slice: &[T]) -> Result<(), SerializeError> {
         let Self { mut token, serializer } = self;
-        let mut data_token = serializer.reserve_space(
+        let data_token = serializer.reserve_space(
             <T::Primitive<'_> as Primitive<'_>>::layout()
                 .repeat(slice.len())
                 .map_err(|_| SerializeError::NotEnoughSpace)?
@@ -102,13 +97,7 @@ impl<'a> ListSerializer<'a> {
         *serializer.integer(&mut token)? = data_token.position();
         *serializer.integer(&mut token)? = slice.len();
 
-        for item in slice {
-            let (token, rest) = data_token.split(<T::Primitive<'_> as Primitive<'_>>::layout())?;
-            data_token = rest;
-            serializer.serialize_into(token, item)?;
-        }
-
-        Ok(())
+        T::serialize_elements(slice, serializer, data_token)
     }
 }
 
@@ -196,6 +185,30 @@ impl<'a> PrimitiveSerializer<'a> for &'_ str {
     }
 }
 
+pub struct BytesSerializer<'a> {
+    token: ReservationToken,
+    serializer: &'a mut Serializer,
+}
+
+impl<'a> BytesSerializer<'a> {
+    pub fn serialize_bytes(self, bytes: &[u8]) -> Result<(), SerializeError> {
+        let Self { mut token, serializer } = self;
+        let data_token = serializer.reserve_space(core::alloc::Layout::for_value(bytes))?;
+        *serializer.integer(&mut token)? = data_token.position();
+        *serializer.integer(&mut token)? = bytes.len();
+        serializer.buffer_for(data_token)?.copy_from_slice(bytes);
+
+        Ok(())
+    }
+}
+
+impl<'a> PrimitiveSerializer<'a> for Bytes<'_> {
+    type Serializer = BytesSerializer<'a>;
+    fn construct(serializer: &'a mut Serializer, token: ReservationToken) -> Result<Self::Serializer, SerializeError> {
+        Ok(BytesSerializer { token, serializer })
+    }
+}
+
 impl<'a> PrimitiveSerializer<'a> for () {
     type Serializer = ();
     fn construct(_: &mut Serializer, _: ReservationToken) -> Result<Self::Serializer, SerializeError> {
@@ -7,8 +7,8 @@
 
 use proc_macro2::Ident;
 use syn::{
-    punctuated::Punctuated, Attribute, Data, DataEnum, DataStruct, DeriveInput, Expr, ExprLit, Lit, LitStr, Meta, Path,
-    Token,
+    punctuated::Punctuated, Attribute, Data, DataEnum, DataStruct, DeriveInput, Expr, ExprLit, Field, Lit, LitStr,
+    Meta, Path, Token,
 };
 
 #[proc_macro_derive(Serializable, attributes(materialize))]
@@ -58,6 +58,7 @@ fn derive_serializable_struct(input: &DeriveInput, strukt: &DataStruct) -> proc_
     let field_primitives = strukt
         .fields
         .iter()
+        .filter(|field| field_kind(field) != FieldKind::Skip)
         .map(|field| {
             let ty = &field.ty;
             quote::quote!(<#ty as #crate_path::Serializable>::Primitive<'a>)
@@ -87,7 +88,8 @@ fn derive_serialize_struct(input: &DeriveInput, strukt: &DataStruct) -> proc_mac
         None => quote::quote!(materialize),
     };
 
-    let field_serializes = strukt.fields.iter().enumerate().map(|(i, field)| match &field.ident {
+    let serialized_fields = strukt.fields.iter().enumerate().filter(|(_, field)| field_kind(field) != FieldKind::Skip);
+    let field_serializes = serialized_fields.map(|(i, field)| match &field.ident {
         Some(ident) => quote::quote!(let _serializer = _serializer.serialize_field(&self.#ident)?;),
         None => {
             let i = proc_macro2::Literal::usize_unsuffixed(i);
@@ -122,16 +124,37 @@ fn derive_deserialize_struct(input: &DeriveInput, strukt: &DataStruct) -> proc_m
         None => quote::quote!(materialize),
     };
 
-    let is_tuple = strukt.fields.iter().any(|field| field.ident.is_none());
-    let field_deserializes = strukt.fields.iter().enumerate().map(|(i, field)| match &field.ident {
-        Some(ident) => {
-            let ty = &field.ty;
-            quote::quote!(let (#ident, _strukt) = _strukt.advance().and_then(|(f, s)| Ok((<#ty as #crate_path::Deserialize<'de>>::deserialize(f, _capabilities)?, s)))?;)
+    // Older versions of the struct can only be missing fields from the end
+    let mut defaulted = false;
+    for field in &strukt.fields {
+        match field_kind(field) {
+            FieldKind::Default => defaulted = true,
+            FieldKind::Required if defaulted => {
+                return quote::quote!(compile_error!("`materialize(default)` fields must come after all other fields"))
+                    .into()
+            }
+            _ => {}
         }
-        None => {
-            let ident = quote::format_ident!("_{}", i);
-            let ty = &field.ty;
-            quote::quote!(let (#ident, _strukt) = _strukt.advance().and_then(|(f, s)| Ok((<#ty as #crate_path::Deserialize<'de>>::deserialize(f, _capabilities)?, s)))?;)
+    }
+
+    let is_tuple = strukt.fields.iter().any(|field| field.ident.is_none());
+    let field_deserializes = strukt.fields.iter().enumerate().map(|(i, field)| {
+        let ident = match &field.ident {
+            Some(ident) => ident.clone(),
+            None => quote::format_ident!("_{}", i),
+        };
+
+        let ty = &field.ty;
+        let advance = quote::quote!(_strukt.advance().and_then(|(f, s)| Ok((<#ty as #crate_path::Deserialize<'de>>::deserialize(f, _capabilities)?, s)))?);
+        match field_kind(field) {
+            FieldKind::Required => quote::quote!(let (#ident, _strukt) = #advance;),
+            FieldKind::Default => quote::quote! {
+                let (#ident, _strukt) = match _strukt.has_field() {
+                    true => #advance,
+                    false => (<#ty as ::core::default::Default>::default(), _strukt.next()),
+                };
+            },
+            FieldKind::Skip => quote::quote!(let #ident = <#ty as ::core::default::Default>::default();),
         }
     });
 
@@ -372,6 +395,29 @@ struct DeriveAttr {
     value: Option<LitStr>,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FieldKind {
+    Required,
+    /// `#[materialize(default)]`: defaulted when deserializing a struct which
+    /// was serialized before the field was added
+    Default,
+    /// `#[materialize(skip)]`: never serialized, always defaulted
+    Skip,
+}
+
+fn field_kind(field: &Field) -> FieldKind {
+    let mut kind = FieldKind::Required;
+    for attr in filter_attrs(&field.attrs) {
+        if attr.ident == "skip" {
+            return FieldKind::Skip;
+        } else if attr.ident == "default" {
+            kind = FieldKind::Default;
+        }
+    }
+
+    kind
+}
+
 fn filter_attrs(attrs: &[Attribute]) -> impl Iterator<Item = DeriveAttr> + '_ {
     attrs
         .iter()
@@ -384,6 +430,7 @@ fn filter_attrs(attrs: &[Attribute]) -> impl Iterator<Item = DeriveAttr> + '_ {
         })
         .flat_map(|nm| {
             nm.into_iter().filter_map(|meta| match meta {
+                Meta::Path(path) => Some(DeriveAttr { ident: path.get_ident()?.clone(), value: None }),
                 Meta::NameValue(nv) => Some(DeriveAttr {
                     ident: nv.path.get_ident()?.clone(),
                     value: match nv.value {
@@ -0,0 +1,116 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+/// An incremental Internet checksum (RFC 1071), the one's complement of the one's
+/// complement sum of big endian 16-bit words. The data added is treated as one
+/// continuous stream, so it can be added in pieces of any length, and an odd
+/// trailing byte is padded with zero when the checksum is finished.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checksum {
+    sum: u64,
+    /// The first half of a word split across calls to [`Checksum::add_bytes`]
+    odd_byte: Option<u8>,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Self { sum: 0, odd_byte: None }
+    }
+
+    pub fn add_bytes(&mut self, mut bytes: &[u8]) {
+        if let Some(high) = self.odd_byte.take() {
+            match bytes.split_first() {
+                Some((&low, rest)) => {
+                    self.sum += u64::from(u16::from_be_bytes([high, low]));
+                    bytes = rest;
+                }
+                None => {
+                    self.odd_byte = Some(high);
+                    return;
+                }
+            }
+        }
+
+        let mut words = bytes.chunks_exact(2);
+        self.sum += words.by_ref().map(|word| u64::from(u16::from_be_bytes([word[0], word[1]]))).sum::<u64>();
+
+        if let [last] = words.remainder() {
+            self.odd_byte = Some(*last);
+        }
+    }
+
+    pub fn add_u16(&mut self, n: u16) {
+        self.add_bytes(&n.to_be_bytes());
+    }
+
+    /// Fold the carries back into the sum and take its complement
+    pub fn finish(self) -> u16 {
+        let mut sum = self.sum + self.odd_byte.map_or(0, |high| u64::from(high) << 8);
+        while sum > 0xFFFF {
+            sum = (sum & 0xFFFF) + (sum >> 16);
+        }
+
+        !(sum as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksum(bytes: &[u8]) -> u16 {
+        let mut checksum = Checksum::new();
+        checksum.add_bytes(bytes);
+        checksum.finish()
+    }
+
+    // Example from RFC 1071 section 3
+    const RFC_1071: [u8; 8] = [0x00, 0x01, 0xF2, 0x03, 0xF4, 0xF5, 0xF6, 0xF7];
+
+    #[test]
+    fn known_vectors() {
+        assert_eq!(checksum(&RFC_1071), !0xDDF2);
+        assert_eq!(checksum(&[]), 0xFFFF);
+
+        // The sum of all ones is negative zero
+        assert_eq!(checksum(&[0xFF, 0xFF]), 0x0000);
+
+        let mut checksum = Checksum::new();
+        checksum.add_u16(0x0001);
+        checksum.add_u16(0xF203);
+        checksum.add_bytes(&RFC_1071[4..]);
+        assert_eq!(checksum.finish(), !0xDDF2);
+    }
+
+    #[test]
+    fn end_around_carry() {
+        // 0xFFFF + 0x0002 = 0x1_0001, which folds to 0x0002
+        assert_eq!(checksum(&[0xFF, 0xFF, 0x00, 0x02]), !0x0002);
+
+        // Enough words to carry more than once
+        let bytes = [0xFF; 2 * 70_000];
+        assert_eq!(checksum(&bytes), 0x0000);
+    }
+
+    #[test]
+    fn odd_lengths() {
+        // A trailing odd byte is the high half of a zero-padded word
+        assert_eq!(checksum(&[0x01]), !0x0100);
+        assert_eq!(checksum(&[0x00, 0x01, 0xF2]), !0xF201);
+
+        // Words split across calls are put back together
+        for split in 0..=RFC_1071.len() {
+            for split2 in split..=RFC_1071.len() {
+                let mut checksum = Checksum::new();
+                checksum.add_bytes(&RFC_1071[..split]);
+                checksum.add_bytes(&RFC_1071[split..split2]);
+                checksum.add_bytes(&RFC_1071[split2..]);
+                assert_eq!(checksum.finish(), !0xDDF2, "split at {} and {}", split, split2);
+            }
+        }
+    }
+}
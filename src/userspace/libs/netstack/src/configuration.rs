@@ -0,0 +1,74 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::ipv4::IpV4Address;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+/// The settings for an interface, e.g. as learned from a DHCP server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Configuration {
+    pub address: IpV4Address,
+    pub netmask: IpV4Address,
+    pub gateway: IpV4Address,
+    pub dns_servers: Vec<IpV4Address>,
+    /// How long the address can be used for, or `None` if it doesn't expire
+    pub lease_time: Option<Duration>,
+}
+
+impl Configuration {
+    /// The route through the gateway for anything that isn't on the local
+    /// network
+    pub fn default_route(&self) -> Route {
+        Route {
+            destination: IpV4Address::new(0, 0, 0, 0),
+            netmask: IpV4Address::new(0, 0, 0, 0),
+            gateway: self.gateway,
+        }
+    }
+
+    /// Whether `ip` is on the same network as the interface, and so can be
+    /// reached without going through the gateway
+    pub fn is_local(&self, ip: IpV4Address) -> bool {
+        let mask = u32::from_be_bytes(self.netmask.to_bytes());
+        let network = u32::from_be_bytes(self.address.to_bytes()) & mask;
+
+        u32::from_be_bytes(ip.to_bytes()) & mask == network
+    }
+}
+
+/// Packets for addresses which match `destination` under `netmask` are sent to
+/// `gateway`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub destination: IpV4Address,
+    pub netmask: IpV4Address,
+    pub gateway: IpV4Address,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        let config = Configuration {
+            address: IpV4Address::new(10, 0, 2, 15),
+            netmask: IpV4Address::new(255, 255, 255, 0),
+            gateway: IpV4Address::new(10, 0, 2, 2),
+            dns_servers: Vec::new(),
+            lease_time: None,
+        };
+
+        let route = config.default_route();
+        assert_eq!((route.destination, route.netmask), (IpV4Address::new(0, 0, 0, 0), IpV4Address::new(0, 0, 0, 0)));
+        assert_eq!(route.gateway, config.gateway);
+
+        assert!(config.is_local(IpV4Address::new(10, 0, 2, 200)));
+        assert!(!config.is_local(IpV4Address::new(10, 0, 3, 1)));
+    }
+}
@@ -20,6 +20,8 @@ impl EthernetHeader {
     pub const IPV4_FRAME: [u8; 2] = [0x08, 0x00];
     pub const ARP_FRAME: [u8; 2] = [0x08, 0x06];
     pub const IPV6_FRAME: [u8; 2] = [0x86, 0xDD];
+    /// The TPID which takes the place of the EtherType in 802.1Q tagged frames
+    pub const VLAN_TAGGED_FRAME: [u8; 2] = [0x81, 0x00];
 
     pub fn split_slice_ref(slice: &[u8]) -> Result<(&EthernetHeader, &[u8], &Fcs), BufferTooSmall> {
         if slice.len() < (core::mem::size_of::<EthernetHeader>() + core::mem::size_of::<Fcs>()) {
@@ -42,6 +44,120 @@ impl EthernetHeader {
     }
 }
 
+/// An 802.1Q tag, which follows the source MAC address of a tagged frame and
+/// shifts the real EtherType back by [`VlanTag::LEN`] bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VlanTag {
+    /// The 802.1p priority code point, `0..=7`
+    pub priority: u8,
+    pub drop_eligible: bool,
+    /// The VLAN identifier, `0..=4095`
+    pub id: u16,
+}
+
+impl VlanTag {
+    /// The length of the tag on the wire, including the TPID
+    pub const LEN: usize = 4;
+
+    pub fn new(id: u16, priority: u8) -> Self {
+        Self { priority: priority & 0b111, drop_eligible: false, id: id & 0xFFF }
+    }
+
+    /// Decode the tag control information field
+    pub fn from_tci(tci: u16) -> Self {
+        Self { priority: (tci >> 13) as u8, drop_eligible: tci & (1 << 12) != 0, id: tci & 0xFFF }
+    }
+
+    /// Encode the tag control information field
+    pub fn tci(self) -> u16 {
+        ((self.priority as u16 & 0b111) << 13) | ((self.drop_eligible as u16) << 12) | (self.id & 0xFFF)
+    }
+}
+
+/// A received frame split into its parts, with any VLAN tag decoded
+#[derive(Debug, Clone, Copy)]
+pub struct EthernetFrame<'a> {
+    pub header: &'a EthernetHeader,
+    pub vlan: Option<VlanTag>,
+    /// The EtherType of the payload, which for tagged frames is the one
+    /// following the tag rather than [`EthernetHeader::frame_type`]
+    pub frame_type: [u8; 2],
+    pub payload: &'a [u8],
+    pub fcs: &'a Fcs,
+}
+
+impl<'a> EthernetFrame<'a> {
+    pub fn parse(slice: &'a [u8]) -> Result<Self, BufferTooSmall> {
+        let (header, payload, fcs) = EthernetHeader::split_slice_ref(slice)?;
+        if header.frame_type != EthernetHeader::VLAN_TAGGED_FRAME {
+            return Ok(Self { header, vlan: None, frame_type: header.frame_type, payload, fcs });
+        }
+
+        // The TPID was already consumed as the header's frame type
+        let [tci_hi, tci_lo, type_hi, type_lo, payload @ ..] = payload else { return Err(BufferTooSmall) };
+        let vlan = VlanTag::from_tci(u16::from_be_bytes([*tci_hi, *tci_lo]));
+
+        Ok(Self { header, vlan: Some(vlan), frame_type: [*type_hi, *type_lo], payload, fcs })
+    }
+
+    /// The offset of the payload from the start of the frame
+    pub fn payload_offset(&self) -> usize {
+        core::mem::size_of::<EthernetHeader>() + self.vlan.map_or(0, |_| VlanTag::LEN)
+    }
+}
+
+/// Writes the header of an outgoing frame, optionally with a VLAN tag
+#[derive(Debug, Clone, Copy)]
+pub struct EthernetFrameBuilder {
+    destination_mac: MacAddress,
+    source_mac: MacAddress,
+    frame_type: [u8; 2],
+    vlan: Option<VlanTag>,
+}
+
+impl EthernetFrameBuilder {
+    pub fn new(destination_mac: MacAddress, source_mac: MacAddress, frame_type: [u8; 2]) -> Self {
+        Self { destination_mac, source_mac, frame_type, vlan: None }
+    }
+
+    pub fn vlan(mut self, tag: VlanTag) -> Self {
+        self.vlan = Some(tag);
+        self
+    }
+
+    /// The number of bytes written before the payload
+    pub fn header_len(&self) -> usize {
+        core::mem::size_of::<EthernetHeader>() + self.vlan.map_or(0, |_| VlanTag::LEN)
+    }
+
+    /// Write the header to the start of `buffer`, returning the space left for
+    /// the payload. As with [`EthernetHeader::split_slice_mut`], room for the
+    /// [`Fcs`] is left at the end of the buffer.
+    pub fn build<'a>(&self, buffer: &'a mut [u8]) -> Result<&'a mut [u8], BufferTooSmall> {
+        let (header, payload, _) = EthernetHeader::split_slice_mut(buffer)?;
+        header.destination_mac = self.destination_mac;
+        header.source_mac = self.source_mac;
+
+        match self.vlan {
+            None => {
+                header.frame_type = self.frame_type;
+                Ok(payload)
+            }
+            Some(tag) => {
+                header.frame_type = EthernetHeader::VLAN_TAGGED_FRAME;
+                if payload.len() < VlanTag::LEN {
+                    return Err(BufferTooSmall);
+                }
+
+                let (tag_bytes, payload) = payload.split_at_mut(VlanTag::LEN);
+                tag_bytes[..2].copy_from_slice(&tag.tci().to_be_bytes());
+                tag_bytes[2..].copy_from_slice(&self.frame_type);
+                Ok(payload)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PackedStruct)]
 #[repr(transparent)]
 pub struct Fcs([u8; 4]);
@@ -108,3 +224,67 @@ static CRC32_TABLE: [u32; 256] = [
     0x24B4A3A6, 0xBAD03605, 0xCDD70693, 0x54DE5729, 0x23D967BF, 0xB3667A2E, 0xC4614AB8, 0x5D681B02, 0x2A6F2B94,
     0xB40BBE37, 0xC30C8EA1, 0x5A05DF1B, 0x2D02EF8D,
 ];
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    const DESTINATION: MacAddress = MacAddress::BROADCAST;
+    const SOURCE: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+    #[test]
+    fn untagged_frame() {
+        let mut frame = [0; 64];
+        let payload = EthernetFrameBuilder::new(DESTINATION, MacAddress::new(SOURCE), EthernetHeader::ARP_FRAME)
+            .build(&mut frame)
+            .unwrap();
+        payload[0] = 0xAA;
+
+        assert_eq!(&frame[12..15], &[0x08, 0x06, 0xAA]);
+
+        let parsed = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(parsed.vlan, None);
+        assert_eq!(parsed.frame_type, EthernetHeader::ARP_FRAME);
+        assert_eq!(parsed.header.source_mac, MacAddress::new(SOURCE));
+        assert_eq!(parsed.payload_offset(), 14);
+        assert_eq!(parsed.payload.len(), 64 - 14 - 4);
+        assert_eq!(parsed.payload[0], 0xAA);
+    }
+
+    #[test]
+    fn tagged_frame() {
+        let mut frame = [0; 64];
+        let tag = VlanTag { priority: 5, drop_eligible: true, id: 0x123 };
+        let payload = EthernetFrameBuilder::new(DESTINATION, MacAddress::new(SOURCE), EthernetHeader::IPV4_FRAME)
+            .vlan(tag)
+            .build(&mut frame)
+            .unwrap();
+        payload[0] = 0x45;
+
+        // TPID, then PCP/DEI/VID, then the real EtherType
+        assert_eq!(&frame[12..19], &[0x81, 0x00, 0xB1, 0x23, 0x08, 0x00, 0x45]);
+
+        let parsed = EthernetFrame::parse(&frame).unwrap();
+        assert_eq!(parsed.vlan, Some(tag));
+        assert_eq!(parsed.frame_type, EthernetHeader::IPV4_FRAME);
+        assert_eq!(parsed.header.frame_type, EthernetHeader::VLAN_TAGGED_FRAME);
+        assert_eq!(parsed.payload_offset(), 18);
+        assert_eq!(parsed.payload.len(), 64 - 18 - 4);
+        assert_eq!(parsed.payload[0], 0x45);
+    }
+
+    #[test]
+    fn truncated_tag() {
+        let mut frame = [0; 20];
+        frame[12..14].copy_from_slice(&EthernetHeader::VLAN_TAGGED_FRAME);
+        assert!(EthernetFrame::parse(&frame).is_err());
+    }
+
+    #[test]
+    fn tci_round_trip() {
+        let tag = VlanTag::new(4095, 7);
+        assert_eq!(tag.tci(), 0xEFFF);
+        assert_eq!(VlanTag::from_tci(tag.tci()), tag);
+    }
+}
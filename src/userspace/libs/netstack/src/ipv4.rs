@@ -5,7 +5,11 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::{BufferTooSmall, Length16};
+mod fragmentation;
+
+pub use fragmentation::{FragmentError, Fragmentation};
+
+use crate::{checksum::Checksum, BufferTooSmall, Length16};
 use alchemy::PackedStruct;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -100,24 +104,20 @@ impl IpV4Header {
     }
 
     pub fn generate_checksum(&mut self) {
-        let mut checksum = 0u16;
+        let bytes = self.as_bytes();
+        let mut checksum = Checksum::new();
 
-        let iter = self.as_bytes().array_chunks::<2>().copied().enumerate();
-        for (i, bytes) in iter {
-            // Skip checksum
-            if i == 10 {
-                continue;
-            }
+        // Skip the checksum field itself
+        checksum.add_bytes(&bytes[..10]);
+        checksum.add_bytes(&bytes[12..]);
 
-            let n = u16::from_be_bytes(bytes);
-            let (new_checksum, overflow) = checksum.overflowing_add(n);
-            match overflow {
-                true => checksum = new_checksum.overflowing_add(1).0,
-                false => checksum = new_checksum,
-            }
-        }
+        self.header_checksum.set(checksum.finish());
+    }
 
-        self.header_checksum.set(!checksum);
+    /// Whether the packet is only part of a datagram, and needs reassembled
+    /// with [`Fragmentation`] before its payload can be used
+    pub fn is_fragment(&self) -> bool {
+        self.flags_fragment_offset & Flag::MORE_FRAGMENTS || self.flags_fragment_offset.fragment_offset() != 0
     }
 }
 
@@ -163,6 +163,14 @@ impl Identification {
     pub fn new() -> Self {
         Self([0; 2])
     }
+
+    pub fn from_u16(id: u16) -> Self {
+        Self(id.to_be_bytes())
+    }
+
+    pub fn get(self) -> u16 {
+        u16::from_be_bytes(self.0)
+    }
 }
 
 impl Default for Identification {
@@ -176,10 +184,17 @@ impl Default for Identification {
 pub struct FlagsFragmentOffset([u8; 2]);
 
 impl FlagsFragmentOffset {
+    /// The flags occupy the top 3 bits, followed by the offset of the fragment
+    /// into the datagram in units of 8 bytes
     pub fn new(flag: Flag, fragment_offset: u16) -> Self {
-        let value = (fragment_offset << 3) | flag.0 as u16;
+        let value = (u16::from(flag.0) << 13) | (fragment_offset & 0x1FFF);
         Self(value.to_be_bytes())
     }
+
+    /// The offset of the fragment into the datagram in units of 8 bytes
+    pub fn fragment_offset(self) -> u16 {
+        u16::from_be_bytes(self.0) & 0x1FFF
+    }
 }
 
 pub struct Flag(u8);
@@ -187,14 +202,14 @@ pub struct Flag(u8);
 impl Flag {
     pub const NONE: Self = Self(0);
     pub const DONT_FRAGMENT: Self = Self(1 << 1);
-    pub const MORE_FRAGMENTS: Self = Self(1 << 2);
+    pub const MORE_FRAGMENTS: Self = Self(1 << 0);
 }
 
 impl core::ops::BitAnd<Flag> for FlagsFragmentOffset {
     type Output = bool;
 
     fn bitand(self, rhs: Flag) -> Self::Output {
-        (self.0[0] & rhs.0) == rhs.0
+        ((self.0[0] >> 5) & rhs.0) == rhs.0
     }
 }
 
@@ -259,5 +274,23 @@ mod tests {
 
         header.generate_checksum();
         assert_eq!(header.header_checksum.get(), 0xB861);
+
+        // Regenerating doesn't include the old checksum
+        header.generate_checksum();
+        assert_eq!(header.header_checksum.get(), 0xB861);
+    }
+
+    #[test]
+    fn flags_and_fragment_offset() {
+        let flags = FlagsFragmentOffset([0x40, 0x00]);
+        assert!(flags & Flag::DONT_FRAGMENT);
+        assert!(!(flags & Flag::MORE_FRAGMENTS));
+        assert_eq!(flags.fragment_offset(), 0);
+
+        let flags = FlagsFragmentOffset::new(Flag::MORE_FRAGMENTS, 185);
+        assert_eq!(flags.0, [0x20, 0xB9]);
+        assert!(flags & Flag::MORE_FRAGMENTS);
+        assert!(!(flags & Flag::DONT_FRAGMENT));
+        assert_eq!(flags.fragment_offset(), 185);
     }
 }
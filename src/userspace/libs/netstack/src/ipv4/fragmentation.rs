@@ -0,0 +1,354 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::{Flag, IpV4Address, IpV4Header, Protocol};
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{ops::Range, time::Duration};
+
+/// The largest payload an IPv4 datagram can carry, which bounds how far into
+/// a datagram a fragment can reach
+const MAX_PAYLOAD: usize = u16::MAX as usize - core::mem::size_of::<IpV4Header>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentError {
+    /// The packet is shorter than the length in its header
+    Truncated,
+    /// The fragment's offset or length can't be part of a valid datagram, or
+    /// disagrees with the fragments received before it
+    Malformed,
+    /// The fragment reaches further into its datagram than the buffer is
+    /// allowed to hold
+    TooLarge,
+}
+
+/// Fragments belong to the same datagram when all of these match (RFC 791)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct DatagramKey {
+    source: IpV4Address,
+    destination: IpV4Address,
+    identification: u16,
+    protocol: Protocol,
+}
+
+struct PartialDatagram {
+    data: Vec<u8>,
+    /// The byte ranges of `data` received so far, sorted and merged
+    received: Vec<Range<usize>>,
+    /// The length of the whole datagram, known once its last fragment arrives
+    len: Option<usize>,
+    first_seen: Duration,
+}
+
+impl PartialDatagram {
+    fn new(first_seen: Duration) -> Self {
+        Self { data: Vec::new(), received: Vec::new(), len: None, first_seen }
+    }
+
+    /// Check that a fragment ending at `end` fits with what's been received
+    fn check(&self, end: usize, last: bool) -> Result<(), FragmentError> {
+        let consistent = match (self.len, last) {
+            (Some(len), true) => end == len,
+            (Some(len), false) => end <= len,
+            (None, true) => self.received.iter().all(|received| received.end <= end),
+            (None, false) => true,
+        };
+
+        match consistent {
+            true => Ok(()),
+            false => Err(FragmentError::Malformed),
+        }
+    }
+
+    fn insert(&mut self, offset: usize, bytes: &[u8], last: bool) {
+        let end = offset + bytes.len();
+        if last {
+            self.len = Some(end);
+        }
+
+        if self.data.len() < end {
+            self.data.resize(end, 0);
+        }
+
+        // Overlapping fragments overwrite whatever arrived before them
+        self.data[offset..end].copy_from_slice(bytes);
+
+        let mut merged = offset..end;
+        self.received.retain(|received| match received.start <= merged.end && merged.start <= received.end {
+            true => {
+                merged = merged.start.min(received.start)..merged.end.max(received.end);
+                false
+            }
+            false => true,
+        });
+
+        let index = self.received.partition_point(|received| received.start < merged.start);
+        self.received.insert(index, merged);
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!((self.len, self.received.as_slice()), (Some(len), [received]) if *received == (0..len))
+    }
+}
+
+/// A buffer for reassembling fragmented IPv4 datagrams. Fragments are
+/// collected until every byte of their datagram has arrived, and datagrams
+/// which are still incomplete after the timeout are dropped. The memory used
+/// by incomplete datagrams is capped, with the oldest being dropped to make
+/// room for new fragments, so a flood of fragments that are never completed
+/// can't exhaust memory.
+pub struct Fragmentation {
+    datagrams: BTreeMap<DatagramKey, PartialDatagram>,
+    buffered: usize,
+    max_buffered: usize,
+    timeout: Duration,
+}
+
+impl Fragmentation {
+    /// The same reassembly timeout used by Linux
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Create a buffer which holds at most `max_buffered` bytes of incomplete
+    /// datagrams, dropping them `timeout` after their first fragment arrived
+    pub fn new(max_buffered: usize, timeout: Duration) -> Self {
+        Self { datagrams: BTreeMap::new(), buffered: 0, max_buffered, timeout }
+    }
+
+    /// Add the packet with `header` and `payload` (everything following the
+    /// header) received at `now`, returning the payload of the whole datagram
+    /// once its last missing fragment arrives. Packets which aren't fragments
+    /// are returned as-is, though callers can avoid copying them by checking
+    /// [`IpV4Header::is_fragment`] first.
+    pub fn insert(
+        &mut self,
+        header: &IpV4Header,
+        payload: &[u8],
+        now: Duration,
+    ) -> Result<Option<Vec<u8>>, FragmentError> {
+        let len = usize::from(header.len.get())
+            .checked_sub(core::mem::size_of::<IpV4Header>())
+            .ok_or(FragmentError::Truncated)?;
+        let payload = payload.get(..len).ok_or(FragmentError::Truncated)?;
+
+        if !header.is_fragment() {
+            return Ok(Some(payload.to_vec()));
+        }
+
+        let offset = usize::from(header.flags_fragment_offset.fragment_offset()) * 8;
+        let last = !(header.flags_fragment_offset & Flag::MORE_FRAGMENTS);
+        let end = offset + payload.len();
+
+        // Every fragment but the last carries a multiple of 8 bytes, otherwise
+        // the next fragment's offset couldn't follow it
+        if end > MAX_PAYLOAD || (!last && payload.len() % 8 != 0) {
+            return Err(FragmentError::Malformed);
+        } else if end > self.max_buffered {
+            return Err(FragmentError::TooLarge);
+        }
+
+        self.expire(now);
+
+        let key = DatagramKey {
+            source: header.source_ip,
+            destination: header.destination_ip,
+            identification: header.identification.get(),
+            protocol: header.protocol,
+        };
+
+        let buffered = match self.datagrams.get(&key) {
+            Some(datagram) => {
+                datagram.check(end, last)?;
+                datagram.data.len()
+            }
+            None => 0,
+        };
+
+        let growth = end.saturating_sub(buffered);
+        while self.buffered + growth > self.max_buffered {
+            self.evict_oldest(&key);
+        }
+
+        let datagram = self.datagrams.entry(key).or_insert_with(|| PartialDatagram::new(now));
+        datagram.insert(offset, payload, last);
+        self.buffered += growth;
+
+        if !datagram.is_complete() {
+            return Ok(None);
+        }
+
+        let datagram = self.datagrams.remove(&key).unwrap();
+        self.buffered -= datagram.data.len();
+        Ok(Some(datagram.data))
+    }
+
+    /// Drop the datagrams which haven't been completed within the timeout,
+    /// returning how many were dropped
+    pub fn expire(&mut self, now: Duration) -> usize {
+        let (timeout, before) = (self.timeout, self.datagrams.len());
+        let buffered = &mut self.buffered;
+        self.datagrams.retain(|_, datagram| match now.saturating_sub(datagram.first_seen) < timeout {
+            true => true,
+            false => {
+                *buffered -= datagram.data.len();
+                false
+            }
+        });
+
+        before - self.datagrams.len()
+    }
+
+    /// The number of bytes held for incomplete datagrams
+    pub fn buffered(&self) -> usize {
+        self.buffered
+    }
+
+    /// Drop the oldest incomplete datagram other than `keep`
+    fn evict_oldest(&mut self, keep: &DatagramKey) {
+        let oldest = self
+            .datagrams
+            .iter()
+            .filter(|(key, _)| *key != keep)
+            .min_by_key(|(_, datagram)| datagram.first_seen)
+            .map(|(key, _)| *key);
+
+        // `keep` alone never holds more than `max_buffered` bytes, so there's
+        // always another datagram to drop while over the limit
+        let datagram = self.datagrams.remove(&oldest.unwrap()).unwrap();
+        self.buffered -= datagram.data.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+    use crate::{
+        ipv4::{DscpEcn, FlagsFragmentOffset, Identification, IpV4HeaderChecksum, VersionIhl},
+        Length16,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn packet(id: u16, offset: usize, more_fragments: bool, payload: &[u8]) -> IpV4Header {
+        let flag = match more_fragments {
+            true => Flag::MORE_FRAGMENTS,
+            false => Flag::NONE,
+        };
+
+        IpV4Header {
+            version_ihl: VersionIhl::new(),
+            dscp_ecn: DscpEcn::new(),
+            len: Length16::new((core::mem::size_of::<IpV4Header>() + payload.len()) as u16),
+            identification: Identification::from_u16(id),
+            flags_fragment_offset: FlagsFragmentOffset::new(flag, (offset / 8) as u16),
+            ttl: 64,
+            protocol: Protocol::UDP,
+            header_checksum: IpV4HeaderChecksum::new(),
+            source_ip: IpV4Address::new(10, 0, 2, 2),
+            destination_ip: IpV4Address::new(10, 0, 2, 15),
+        }
+    }
+
+    fn datagram(len: usize) -> Vec<u8> {
+        (0..len).map(|n| n as u8).collect()
+    }
+
+    #[test]
+    fn in_order() {
+        let mut fragmentation = Fragmentation::new(4096, TIMEOUT);
+        let data = datagram(1500);
+        let (first, second) = data.split_at(1480);
+
+        let now = Duration::from_secs(1);
+        assert_eq!(fragmentation.insert(&packet(7, 0, true, first), first, now), Ok(None));
+        assert_eq!(fragmentation.buffered(), 1480);
+        assert_eq!(fragmentation.insert(&packet(7, 1480, false, second), second, now), Ok(Some(data)));
+        assert_eq!(fragmentation.buffered(), 0);
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut fragmentation = Fragmentation::new(4096, TIMEOUT);
+        let data = datagram(1500);
+        let (first, second) = data.split_at(1480);
+
+        let now = Duration::from_secs(1);
+        assert_eq!(fragmentation.insert(&packet(7, 1480, false, second), second, now), Ok(None));
+
+        // A fragment of another datagram with the same ID from someone else
+        // isn't mixed in
+        let mut other = packet(7, 0, true, first);
+        other.source_ip = IpV4Address::new(10, 0, 2, 3);
+        assert_eq!(fragmentation.insert(&other, first, now), Ok(None));
+
+        assert_eq!(fragmentation.insert(&packet(7, 0, true, first), first, now), Ok(Some(data)));
+        assert_eq!(fragmentation.buffered(), 1480);
+    }
+
+    #[test]
+    fn missing_fragment_times_out() {
+        let mut fragmentation = Fragmentation::new(4096, TIMEOUT);
+        let data = datagram(1500);
+        let (first, second) = data.split_at(1480);
+
+        assert_eq!(fragmentation.insert(&packet(7, 0, true, first), first, Duration::from_secs(1)), Ok(None));
+        assert_eq!(fragmentation.expire(Duration::from_secs(30)), 0);
+        assert_eq!(fragmentation.expire(Duration::from_secs(31)), 1);
+        assert_eq!(fragmentation.buffered(), 0);
+
+        // The last fragment arriving afterwards starts a new datagram instead
+        // of completing the dropped one
+        let now = Duration::from_secs(32);
+        assert_eq!(fragmentation.insert(&packet(7, 1480, false, second), second, now), Ok(None));
+    }
+
+    #[test]
+    fn buffered_memory_is_capped() {
+        let mut fragmentation = Fragmentation::new(2048, TIMEOUT);
+        let data = datagram(1024);
+
+        assert_eq!(fragmentation.insert(&packet(1, 0, true, &data), &data, Duration::from_secs(1)), Ok(None));
+        assert_eq!(fragmentation.insert(&packet(2, 0, true, &data), &data, Duration::from_secs(2)), Ok(None));
+        assert_eq!(fragmentation.buffered(), 2048);
+
+        // Making room drops the oldest datagram
+        assert_eq!(fragmentation.insert(&packet(3, 0, true, &data), &data, Duration::from_secs(3)), Ok(None));
+        assert_eq!(fragmentation.buffered(), 2048);
+
+        // The rest of the dropped datagram arriving starts over, making room by
+        // dropping everything else
+        let last = [0xFF; 8];
+        assert_eq!(fragmentation.insert(&packet(1, 1024, false, &last), &last, Duration::from_secs(4)), Ok(None));
+        assert_eq!(fragmentation.buffered(), 1032);
+
+        // No single datagram can use more than the whole buffer
+        assert_eq!(
+            fragmentation.insert(&packet(4, 2048, false, &last), &last, Duration::from_secs(5)),
+            Err(FragmentError::TooLarge)
+        );
+    }
+
+    #[test]
+    fn invalid_fragments() {
+        let mut fragmentation = Fragmentation::new(4096, TIMEOUT);
+        let now = Duration::from_secs(1);
+        let data = datagram(100);
+
+        // Unfragmented packets are returned as-is
+        assert_eq!(fragmentation.insert(&packet(1, 0, false, &data), &data, now), Ok(Some(data.clone())));
+        assert_eq!(fragmentation.insert(&packet(1, 0, false, &data), &data[..50], now), Err(FragmentError::Truncated));
+
+        // Only the last fragment can have a length that isn't a multiple of 8
+        assert_eq!(fragmentation.insert(&packet(2, 0, true, &data), &data, now), Err(FragmentError::Malformed));
+
+        // Fragments can't extend past the end given by the last fragment
+        assert_eq!(fragmentation.insert(&packet(3, 16, false, &data[..8]), &data[..8], now), Ok(None));
+        assert_eq!(
+            fragmentation.insert(&packet(3, 24, true, &data[..8]), &data[..8], now),
+            Err(FragmentError::Malformed)
+        );
+    }
+}
@@ -9,9 +9,16 @@
 #![allow(incomplete_features)]
 #![feature(generic_arg_infer, generic_const_exprs, split_array, array_chunks)]
 
+extern crate alloc;
+
+mod configuration;
+
+pub use configuration::{Configuration, Route};
+
 use alchemy::PackedStruct;
 
 pub mod arp;
+pub mod checksum;
 pub mod ethernet;
 pub mod ipv4;
 pub mod udp;
@@ -5,7 +5,11 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
-use crate::{ipv4::IpV4Header, BufferTooSmall, Length16};
+use crate::{
+    checksum::Checksum,
+    ipv4::{IpV4Address, IpV4Header, Protocol},
+    BufferTooSmall, Length16,
+};
 use alchemy::PackedStruct;
 
 #[derive(Debug, Clone, Copy, PackedStruct)]
@@ -36,8 +40,54 @@ impl UdpHeader {
         Ok((Self::from_bytes_mut::<{ core::mem::size_of::<Self>() }>(header), payload))
     }
 
-    pub fn generate_ipv4_checksum(&mut self, _ip_header: &IpV4Header, _data: &[u8]) {
-        todo!("generate IPv4 checksum from pseudoheader")
+    /// Fill in the checksum for this header followed by `data`, sent from and
+    /// to the addresses in `ip_header`
+    pub fn generate_ipv4_checksum(&mut self, ip_header: &IpV4Header, data: &[u8]) {
+        let len = core::mem::size_of::<Self>() + data.len();
+        let mut sum = pseudo_header_sum(ip_header.source_ip, ip_header.destination_ip, len);
+        sum.add_bytes(without_checksum(self.as_bytes()));
+        sum.add_bytes(data);
+
+        self.checksum.set(finish(sum));
+    }
+}
+
+/// Compute the checksum of a UDP datagram sent over IPv4, which covers a
+/// pseudo-header made up of the source and destination addresses, protocol, and
+/// UDP length, followed by the UDP header and data in `datagram`. The checksum
+/// field in the header is treated as zero, so this can be used to both generate
+/// and verify checksums.
+pub fn checksum(src: IpV4Address, dst: IpV4Address, datagram: &[u8]) -> u16 {
+    let (header, data) = datagram.split_at(datagram.len().min(core::mem::size_of::<UdpHeader>()));
+    let mut sum = pseudo_header_sum(src, dst, datagram.len());
+    sum.add_bytes(without_checksum(header));
+    sum.add_bytes(data);
+
+    finish(sum)
+}
+
+fn pseudo_header_sum(src: IpV4Address, dst: IpV4Address, len: usize) -> Checksum {
+    let [protocol] = Protocol::UDP.into_bytes();
+    let mut sum = Checksum::new();
+    sum.add_bytes(&src.to_bytes());
+    sum.add_bytes(&dst.to_bytes());
+    sum.add_u16(u16::from(protocol));
+    sum.add_u16(len as u16);
+
+    sum
+}
+
+/// The UDP header up to the checksum field
+fn without_checksum(header: &[u8]) -> &[u8] {
+    header.get(..6).unwrap_or(header)
+}
+
+fn finish(sum: Checksum) -> u16 {
+    // An all-zero checksum means no checksum was computed, so an actual result
+    // of zero is sent as all ones instead
+    match sum.finish() {
+        0 => 0xFFFF,
+        checksum => checksum,
     }
 }
 
@@ -67,6 +117,14 @@ impl UdpChecksum {
     pub fn zero(&mut self) {
         self.0 = [0; 2];
     }
+
+    pub fn get(self) -> u16 {
+        u16::from_be_bytes(self.0)
+    }
+
+    pub fn set(&mut self, checksum: u16) {
+        self.0 = checksum.to_be_bytes();
+    }
 }
 
 impl Default for UdpChecksum {
@@ -74,3 +132,51 @@ impl Default for UdpChecksum {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use super::*;
+
+    // A datagram sent by Linux from 10.0.2.15:68 to 10.0.2.2:67 containing
+    // `vanadinite!`
+    const DATAGRAM: [u8; 19] = [
+        0x00, 0x44, 0x00, 0x43, 0x00, 0x13, 0x9A, 0x35, b'v', b'a', b'n', b'a', b'd', b'i', b'n', b'i', b't', b'e',
+        b'!',
+    ];
+
+    #[test]
+    fn checksum_matches_captured_datagram() {
+        let (source, destination) = (IpV4Address::new(10, 0, 2, 15), IpV4Address::new(10, 0, 2, 2));
+        assert_eq!(checksum(source, destination, &DATAGRAM), 0x9A35);
+
+        // The checksum field itself doesn't contribute to the result
+        let mut zeroed = DATAGRAM;
+        zeroed[6..8].fill(0);
+        assert_eq!(checksum(source, destination, &zeroed), 0x9A35);
+
+        let mut bytes = DATAGRAM;
+        let (header, data) = UdpHeader::split_slice_mut(&mut bytes).unwrap();
+        header.checksum.zero();
+        let mut ip_header = [0; core::mem::size_of::<IpV4Header>()];
+        let (ip_header, _) = IpV4Header::split_slice_mut(&mut ip_header).unwrap();
+        ip_header.source_ip = source;
+        ip_header.destination_ip = destination;
+
+        header.generate_ipv4_checksum(ip_header, data);
+        assert_eq!(header.checksum.get(), 0x9A35);
+    }
+
+    #[test]
+    fn zero_checksum_is_sent_as_all_ones() {
+        let sum = |words: &[u16]| {
+            let mut sum = Checksum::new();
+            words.iter().for_each(|&word| sum.add_u16(word));
+            sum
+        };
+
+        assert_eq!(finish(sum(&[0xFFFF])), 0xFFFF);
+        assert_eq!(finish(sum(&[0xFFFE, 0xFFFE, 2])), 0xFFFF);
+        assert_eq!(finish(sum(&[])), 0xFFFF);
+    }
+}
@@ -62,12 +62,14 @@ impl Present {
         let mut value = None;
 
         // SAFETY: We're single threaded and block the current thread until the
-        // future finishes executing
+        // future finishes executing, and `_abandon` removes the task if a panic
+        // unwinds out of here before it does
         let waiting_on = unsafe {
             GLOBAL_EXECUTOR.borrow_mut().push_unchecked(async {
                 value = Some(f.await);
             })
         };
+        let _abandon = AbandonOnUnwind(waiting_on);
 
         loop {
             let mut executor = GLOBAL_EXECUTOR.borrow_mut();
@@ -88,6 +90,15 @@ impl Present {
         }
     }
 
+    /// Run `f` to completion, along with any other tasks that become ready in
+    /// the meantime, returning its output.
+    ///
+    /// A panic in `f` or any other task polled here isn't caught by the
+    /// executor: the panic handler in `std` logs the message and exits the
+    /// task with [`std::process::PANIC_EXIT_CODE`]. Where panics unwind
+    /// instead, the panic propagates to the caller of `block_on`, and `f` is
+    /// dropped and removed from the executor so no task is left borrowing the
+    /// caller's stack.
     pub fn block_on<F>(&self, f: F) -> F::Output
     where
         F: Future,
@@ -96,6 +107,28 @@ impl Present {
     }
 }
 
+/// Run `f` to completion on the global executor, see [`Present::block_on`]
+pub fn block_on<F>(f: F) -> F::Output
+where
+    F: Future,
+{
+    Present::new().block_on(f)
+}
+
+/// Removes the task [`Present::block_on`] is waiting on from the executor when
+/// dropped, which only finds it there if a panic unwound past the loop driving it
+struct AbandonOnUnwind(u64);
+
+impl Drop for AbandonOnUnwind {
+    fn drop(&mut self) {
+        // The executor is only left borrowed if the panic came from the
+        // executor itself, in which case it's in no state to be cleaned up.
+        // Dropping the task can wake others, so it's done after the borrow.
+        let task = GLOBAL_EXECUTOR.try_borrow_mut().ok().and_then(|mut executor| executor.remove(self.0));
+        drop(task);
+    }
+}
+
 pub fn spawn<F>(f: F) -> JoinHandle<F::Output>
 where
     F: Future + Send + Sync + 'static,
@@ -147,4 +180,35 @@ impl PresentExecutor {
             self.ready_tasks.push(task);
         }
     }
+
+    pub(crate) fn remove(&mut self, id: u64) -> Option<Task> {
+        match self.ready_tasks.iter().position(|task| task.task_id == id) {
+            Some(index) => Some(self.ready_tasks.remove(index)),
+            None => self.waiting_tasks.remove(&id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::test_util::yield_now;
+
+    // The executor is global, so everything shares a single test rather than
+    // racing each other on it
+    #[test_case]
+    fn block_on() {
+        assert_eq!(super::block_on(async { 5 }), 5);
+
+        // Other tasks are driven while waiting, and their output makes it back
+        let handle = spawn(async {
+            yield_now().await;
+            String::from("spawned")
+        });
+        assert_eq!(super::block_on(async { handle.join().await + " task" }), "spawned task");
+
+        let executor = GLOBAL_EXECUTOR.borrow();
+        assert!(executor.ready_tasks.is_empty() && executor.waiting_tasks.is_empty());
+        drop(executor);
+    }
 }
@@ -31,7 +31,7 @@ impl EventRegistry {
     }
 
     pub(crate) fn unregister_interest(&self, block_type: BlockType) {
-        assert!(self.interest.borrow_mut().remove(&block_type).is_none());
+        assert!(self.interest.borrow_mut().remove(&block_type).is_some());
     }
 
     pub(crate) fn add_interested_event(&self, block_type: BlockType) {
@@ -60,6 +60,24 @@ impl EventRegistry {
     pub(crate) fn unregister(&self, block_type: BlockType) -> Option<Waker> {
         self.waiting_for_event.borrow_mut().remove(&block_type)
     }
+
+    /// Remove the waker registered for `block_type` only if it's `waker`, so a
+    /// future being dropped doesn't take the registration of whatever replaced
+    /// it
+    pub(crate) fn unregister_waker(&self, block_type: BlockType, waker: &Waker) {
+        let mut waiting = self.waiting_for_event.borrow_mut();
+        if waiting.get(&block_type).map_or(false, |registered| registered.will_wake(waker)) {
+            waiting.remove(&block_type);
+        }
+    }
+
+    /// Record that `block_type` occurred and wake whatever is waiting on it
+    pub(crate) fn notify(&self, block_type: BlockType) {
+        self.add_interested_event(block_type);
+        if let Some(waker) = self.unregister(block_type) {
+            waker.wake();
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -75,21 +93,11 @@ pub struct Reactor;
 impl Reactor {
     pub fn wait() {
         match read_kernel_message() {
-            KernelMessage::InterruptOccurred(id) => {
-                EVENT_REGISTRY.add_interested_event(BlockType::Interrupt(id));
-                if let Some(waker) = EVENT_REGISTRY.unregister(BlockType::Interrupt(id)) {
-                    waker.wake();
-                }
-            }
+            KernelMessage::InterruptOccurred(id) => EVENT_REGISTRY.notify(BlockType::Interrupt(id)),
             KernelMessage::NewChannelMessage(cptr) => {
                 let saw = SEEN_IPC_CHANNELS.borrow().get(&cptr).is_some();
                 match saw {
-                    true => {
-                        EVENT_REGISTRY.add_interested_event(BlockType::IpcChannelMessage(cptr));
-                        if let Some(waker) = EVENT_REGISTRY.unregister(BlockType::IpcChannelMessage(cptr)) {
-                            waker.wake();
-                        }
-                    }
+                    true => EVENT_REGISTRY.notify(BlockType::IpcChannelMessage(cptr)),
                     false => {
                         SEEN_IPC_CHANNELS.borrow_mut().insert(cptr, ());
                         NEW_IPC_CHANNELS.borrow_mut().push(cptr);
@@ -102,6 +110,7 @@ impl Reactor {
                     }
                 }
             }
+            KernelMessage::TimerExpired => crate::time::TIMERS.wake_expired(std::time::Instant::now()),
         }
     }
 }
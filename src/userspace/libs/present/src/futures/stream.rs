@@ -5,11 +5,14 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+mod buffered;
+mod filter;
 mod from_iter;
 mod helpers;
 mod map;
 mod merge;
 mod next;
+mod take;
 mod then;
 
 use core::{future::Future, pin::Pin};
@@ -55,6 +58,37 @@ pub trait StreamExt: Stream {
         map::Map { stream: self, map: f }
     }
 
+    /// Only yield the items for which `predicate` returns `true`
+    fn filter<F>(self, predicate: F) -> filter::Filter<Self, F>
+    where
+        Self: Sized,
+        F: FnMut(&Self::Item) -> bool,
+    {
+        filter::Filter { stream: self, predicate }
+    }
+
+    /// End the stream after at most `n` items
+    fn take(self, n: usize) -> take::Take<Self>
+    where
+        Self: Sized,
+    {
+        take::Take { stream: self, remaining: n }
+    }
+
+    /// Run up to `limit` of the futures produced by this stream at the same
+    /// time, yielding their outputs in the order the futures were produced
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero
+    fn buffered(self, limit: usize) -> buffered::Buffered<Self>
+    where
+        Self: Sized,
+        Self::Item: Future,
+    {
+        buffered::Buffered::new(self, limit)
+    }
+
     fn merge<S>(self, other: S) -> merge::Merge<Self, S>
     where
         Self: Sized,
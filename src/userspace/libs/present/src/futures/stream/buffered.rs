@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::{future::Future, pin::Pin};
+use std::task::{Context, Poll};
+
+use super::Stream;
+
+/// A future produced by the underlying stream, along with its output once it
+/// has completed but is still waiting for the futures before it
+struct InFlight<Fut: Future> {
+    future: Pin<Box<Fut>>,
+    output: Option<Fut::Output>,
+}
+
+#[must_use = "`Future`s must be awaited or polled to do anything"]
+pub struct Buffered<S: Stream>
+where
+    S::Item: Future,
+{
+    stream: S,
+    in_flight: VecDeque<InFlight<S::Item>>,
+    limit: usize,
+    stream_done: bool,
+}
+
+impl<S: Stream> Buffered<S>
+where
+    S::Item: Future,
+{
+    pub(super) fn new(stream: S, limit: usize) -> Self {
+        assert!(limit > 0, "`buffered` needs a limit of at least one future");
+        Self { stream, in_flight: VecDeque::with_capacity(limit), limit, stream_done: false }
+    }
+}
+
+impl<S: Stream> Stream for Buffered<S>
+where
+    S::Item: Future,
+{
+    type Item = <S::Item as Future>::Output;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let mut stream = unsafe { Pin::new_unchecked(&mut this.stream) };
+
+        while !this.stream_done && this.in_flight.len() < this.limit {
+            match stream.as_mut().poll_next(context) {
+                Poll::Ready(Some(future)) => {
+                    this.in_flight.push_back(InFlight { future: Box::pin(future), output: None })
+                }
+                Poll::Ready(None) => this.stream_done = true,
+                Poll::Pending => break,
+            }
+        }
+
+        for in_flight in this.in_flight.iter_mut().filter(|in_flight| in_flight.output.is_none()) {
+            if let Poll::Ready(output) = in_flight.future.as_mut().poll(context) {
+                in_flight.output = Some(output);
+            }
+        }
+
+        // Outputs are yielded in the order the stream produced the futures, so
+        // later futures finishing first have to wait here until it's their turn
+        match this.in_flight.front_mut() {
+            Some(InFlight { output: output @ Some(_), .. }) => {
+                let output = output.take();
+                this.in_flight.pop_front();
+                Poll::Ready(output)
+            }
+            Some(_) => Poll::Pending,
+            None if this.stream_done => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::Stream;
+
+#[derive(Debug)]
+#[must_use = "`Future`s must be awaited or polled to do anything"]
+pub struct Filter<S: Stream, F: FnMut(&S::Item) -> bool> {
+    pub(super) stream: S,
+    pub(super) predicate: F,
+}
+
+impl<S: Stream, F: FnMut(&S::Item) -> bool> Filter<S, F> {
+    pub fn into_stream(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Stream, F: FnMut(&S::Item) -> bool> Stream for Filter<S, F> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Self::Item>> {
+        let (mut stream, predicate) = unsafe {
+            let this = self.get_unchecked_mut();
+            (Pin::new_unchecked(&mut this.stream), &mut this.predicate)
+        };
+
+        // Keep pulling items until one passes or the stream has nothing ready,
+        // rather than returning `Pending` without having registered a wakeup
+        loop {
+            match stream.as_mut().poll_next(context) {
+                Poll::Ready(Some(t)) if predicate(&t) => return Poll::Ready(Some(t)),
+                Poll::Ready(Some(_)) => continue,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
@@ -0,0 +1,53 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::pin::Pin;
+use std::task::{Context, Poll};
+
+use super::Stream;
+
+#[derive(Debug)]
+#[must_use = "`Future`s must be awaited or polled to do anything"]
+pub struct Take<S: Stream> {
+    pub(super) stream: S,
+    pub(super) remaining: usize,
+}
+
+impl<S: Stream> Take<S> {
+    pub fn into_stream(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Stream> Stream for Take<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Self::Item>> {
+        let (stream, remaining) = unsafe {
+            let this = self.get_unchecked_mut();
+            (Pin::new_unchecked(&mut this.stream), &mut this.remaining)
+        };
+
+        // The underlying stream isn't polled again once the limit is reached,
+        // so it never registers for a wakeup that nobody is waiting on
+        if *remaining == 0 {
+            return Poll::Ready(None);
+        }
+
+        match stream.poll_next(context) {
+            Poll::Ready(Some(t)) => {
+                *remaining -= 1;
+                Poll::Ready(Some(t))
+            }
+            Poll::Ready(None) => {
+                *remaining = 0;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
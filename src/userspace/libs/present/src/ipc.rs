@@ -13,9 +13,14 @@ use core::{future::Future, pin::Pin};
 use librust::{
     capabilities::{Capability, CapabilityPtr, CapabilityWithDescription},
     error::SyscallError,
-    syscalls::channel::{self, ChannelMessage, ChannelReadFlags, ReadResult, KERNEL_CHANNEL},
+    syscalls::channel::{self, ChannelMessage, ChannelReadFlags, ChannelWriteFlags, ReadResult, KERNEL_CHANNEL},
 };
-use std::task::{Context, Poll};
+use std::task::{Context, Poll, Waker};
+
+#[cfg(not(test))]
+use librust::syscalls::channel::read_message;
+#[cfg(test)]
+use tests::read_message;
 
 // TODO: fix all this garbage
 
@@ -66,8 +71,9 @@ impl IpcChannel {
         Self(cptr)
     }
 
-    pub async fn read(&self, cap_buffer: &mut [CapabilityWithDescription]) -> Result<ReadResult, SyscallError> {
-        IpcRead(self, cap_buffer).await
+    /// Read the next message sent over the channel, see [`IpcRead`]
+    pub fn read<'a>(&'a self, cap_buffer: &'a mut [CapabilityWithDescription]) -> IpcRead<'a> {
+        IpcRead { channel: self, cap_buffer, waker: None }
     }
 
     pub async fn read_with_all_caps(&self) -> Result<(ChannelMessage, Vec<CapabilityWithDescription>), SyscallError> {
@@ -76,20 +82,23 @@ impl IpcChannel {
 
         if capabilities_remaining > 0 {
             caps.resize(capabilities_remaining, CapabilityWithDescription::default());
-            let _ = channel::read_message(self.0, &mut caps[..], ChannelReadFlags::NONBLOCKING)?;
+            let _ = read_message(self.0, &mut caps[..], ChannelReadFlags::NONBLOCKING)?;
         }
 
         Ok((message, caps))
     }
 
     pub fn send(&self, msg: ChannelMessage, caps: &[Capability]) -> Result<(), SyscallError> {
-        channel::send_message(self.0, msg, caps)
+        channel::send_message(self.0, msg, caps, ChannelWriteFlags::NONE)
     }
 }
 
 impl Drop for IpcChannel {
     fn drop(&mut self) {
         EVENT_REGISTRY.unregister_interest(BlockType::IpcChannelMessage(self.0));
+        // Nothing can be waiting on the channel anymore, but a dropped stream
+        // may have left its waker behind
+        EVENT_REGISTRY.unregister(BlockType::IpcChannelMessage(self.0));
     }
 }
 
@@ -112,14 +121,14 @@ impl Stream for IpcMessageStream {
     fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
 
-        match channel::read_message(this.channel.0, &mut [], ChannelReadFlags::NONBLOCKING) {
+        match read_message(this.channel.0, &mut [], ChannelReadFlags::NONBLOCKING) {
             Ok(rr) => {
                 let ReadResult { message, capabilities_remaining, .. } = rr;
                 let mut caps = Vec::new();
 
                 if capabilities_remaining > 0 {
                     caps.resize(capabilities_remaining, CapabilityWithDescription::default());
-                    let _ = channel::read_message(this.channel.0, &mut caps[..], ChannelReadFlags::NONBLOCKING)?;
+                    let _ = read_message(this.channel.0, &mut caps[..], ChannelReadFlags::NONBLOCKING)?;
                 }
 
                 Poll::Ready(Some(Ok((message, caps))))
@@ -133,7 +142,30 @@ impl Stream for IpcMessageStream {
     }
 }
 
-pub struct IpcRead<'a>(&'a IpcChannel, &'a mut [CapabilityWithDescription]);
+/// A pending read from an [`IpcChannel`]
+///
+/// Messages are only taken from the channel by the poll which returns them, so
+/// the future can be cancelled at any point by dropping it (e.g. when a
+/// [`crate::time::timeout`] elapses) or calling [`IpcRead::abort`], and the
+/// message it would have received is left for the next read instead. Its
+/// waker is unregistered when it completes or is cancelled, so no wakeups are
+/// left pointing at a future that no longer exists.
+pub struct IpcRead<'a> {
+    channel: &'a IpcChannel,
+    cap_buffer: &'a mut [CapabilityWithDescription],
+    waker: Option<Waker>,
+}
+
+impl IpcRead<'_> {
+    /// Stop waiting for a message, which is the same as dropping the future
+    pub fn abort(self) {}
+
+    fn unregister(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            EVENT_REGISTRY.unregister_waker(BlockType::IpcChannelMessage(self.channel.0), &waker);
+        }
+    }
+}
 
 impl<'a> Future for IpcRead<'a> {
     type Output = Result<ReadResult, SyscallError>;
@@ -141,18 +173,103 @@ impl<'a> Future for IpcRead<'a> {
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
 
-        match channel::read_message(this.0 .0, this.1, ChannelReadFlags::NONBLOCKING) {
-            Ok(rr) => Poll::Ready(Ok(rr)),
+        match read_message(this.channel.0, this.cap_buffer, ChannelReadFlags::NONBLOCKING) {
             Err(SyscallError::WouldBlock) => {
-                EVENT_REGISTRY.register(BlockType::IpcChannelMessage(this.0 .0), cx.waker().clone());
+                EVENT_REGISTRY.register(BlockType::IpcChannelMessage(this.channel.0), cx.waker().clone());
+                this.waker = Some(cx.waker().clone());
                 Poll::Pending
             }
-            Err(e) => Poll::Ready(Err(e)),
+            result => {
+                this.unregister();
+                Poll::Ready(result)
+            }
         }
     }
 }
 
+impl Drop for IpcRead<'_> {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
+
 pub async fn read_kernel_message() -> channel::KernelMessage {
     let kernel_chan = IpcChannel::new(KERNEL_CHANNEL);
     channel::KernelMessage::construct(kernel_chan.read(&mut []).await.unwrap().message.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sync::test_util::Tasks,
+        time::{timeout, Elapsed},
+    };
+    use std::{
+        collections::{BTreeMap, VecDeque},
+        sync::SyncRefCell,
+        time::Duration,
+    };
+
+    static MESSAGES: SyncRefCell<BTreeMap<CapabilityPtr, VecDeque<ChannelMessage>>> = SyncRefCell::new(BTreeMap::new());
+
+    /// Stands in for the read syscall, returning the messages queued by
+    /// [`deliver`]
+    pub(super) fn read_message(
+        cptr: CapabilityPtr,
+        _: &mut [CapabilityWithDescription],
+        _: ChannelReadFlags,
+    ) -> Result<ReadResult, SyscallError> {
+        match MESSAGES.borrow_mut().get_mut(&cptr).and_then(VecDeque::pop_front) {
+            Some(message) => Ok(ReadResult { message, capabilities_read: 0, capabilities_remaining: 0 }),
+            None => Err(SyscallError::WouldBlock),
+        }
+    }
+
+    /// Queue a message and notify the reactor like the kernel would
+    fn deliver(cptr: CapabilityPtr, message: ChannelMessage) {
+        MESSAGES.borrow_mut().entry(cptr).or_default().push_back(message);
+        EVENT_REGISTRY.notify(BlockType::IpcChannelMessage(cptr));
+    }
+
+    // The event registry is global, so everything shares a single test rather
+    // than racing each other on it
+    #[test_case]
+    fn cancelled_reads_dont_lose_messages() {
+        let cptr = CapabilityPtr::new(100);
+        let channel = IpcChannel::new(cptr);
+
+        // The message arrives once the read has timed out and been retried
+        let mut received = None;
+        let task = async {
+            assert!(matches!(timeout(Duration::ZERO, channel.read(&mut [])).await, Err(Elapsed)));
+            received = Some(channel.read(&mut []).await.unwrap().message.0);
+        };
+        let mut tasks = Tasks::new(vec![Box::pin(task)]);
+        assert!(tasks.poll_woken());
+        deliver(cptr, ChannelMessage([1; 7]));
+        tasks.run_to_completion();
+        assert_eq!(received, Some([1; 7]));
+
+        // Cancelling a read doesn't unregister a newer read's waker
+        let mut received = None;
+        let cancelled = async {
+            let _ = channel.read(&mut []).await;
+        };
+        let task = async { received = Some(channel.read(&mut []).await.unwrap().message.0) };
+        let mut tasks = Tasks::new(vec![Box::pin(cancelled), Box::pin(task)]);
+        assert!(tasks.poll_woken());
+        tasks.cancel(0);
+        deliver(cptr, ChannelMessage([2; 7]));
+        tasks.run_to_completion();
+        assert_eq!(received, Some([2; 7]));
+
+        // Aborting a read leaves the message for the next one
+        deliver(cptr, ChannelMessage([3; 7]));
+        channel.read(&mut []).abort();
+        let mut received = None;
+        let task = async { received = Some(channel.read(&mut []).await.unwrap().message.0) };
+        Tasks::new(vec![Box::pin(task)]).run_to_completion();
+        assert_eq!(received, Some([3; 7]));
+    }
+}
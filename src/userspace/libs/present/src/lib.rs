@@ -6,6 +6,7 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 #![feature(sync_unsafe_cell)]
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
 
 pub mod executor;
 pub mod futures;
@@ -13,9 +14,11 @@ pub mod interrupt;
 pub mod ipc;
 pub mod join;
 pub mod sync;
+pub mod time;
 pub mod waker;
 
-pub use executor::{spawn, Present};
+pub use executor::{block_on, spawn, Present};
+pub use futures::stream;
 pub use present_macros::main;
 
 #[macro_export]
@@ -8,6 +8,14 @@
 use std::sync::atomic::AtomicU64;
 
 pub mod mpsc;
+mod mutex;
 pub mod oneshot;
+mod rwlock;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod waitqueue;
+
+pub use mutex::{AsyncMutex, AsyncMutexGuard, AsyncMutexLock};
+pub use rwlock::{AsyncRwLock, AsyncRwLockAcquire, AsyncRwLockReadGuard, AsyncRwLockWriteGuard};
 
 static CHANNEL_ID: AtomicU64 = AtomicU64::new(0);
@@ -12,10 +12,17 @@ use std::{sync::{SyncRc, SyncRefCell}, task::{Context, Poll}};
 #[derive(Debug)]
 pub struct Sender<T: Send + 'static> {
     inner: SyncRc<SyncRefCell<VecDeque<T>>>,
+    receiver_dropped: SyncRc<SyncRefCell<bool>>,
     id: u64,
 }
 
 impl<T: Send + 'static> Sender<T> {
+    /// Whether the [`Receiver`] has been dropped, so sent values will never be
+    /// received
+    pub fn is_closed(&self) -> bool {
+        *self.receiver_dropped.borrow()
+    }
+
     pub fn send(&self, value: T) {
         self.inner.borrow_mut().push_back(value);
         if let Some(waker) = EVENT_REGISTRY.unregister(BlockType::AsyncChannel(self.id)) {
@@ -28,6 +35,7 @@ impl<T: Send + 'static> Clone for Sender<T> {
     fn clone(&self) -> Self {
         Self {
             inner: SyncRc::clone(&self.inner),
+            receiver_dropped: SyncRc::clone(&self.receiver_dropped),
             id: self.id
         }
     }
@@ -36,9 +44,19 @@ impl<T: Send + 'static> Clone for Sender<T> {
 #[derive(Debug)]
 pub struct Receiver<T: Send + 'static> {
     inner: SyncRc<SyncRefCell<VecDeque<T>>>,
+    receiver_dropped: SyncRc<SyncRefCell<bool>>,
     id: u64,
 }
 
+impl<T: Send + 'static> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        *self.receiver_dropped.borrow_mut() = true;
+        // A pending `recv` or stream left its waker behind, which would
+        // otherwise be woken by the next send long after it was cancelled
+        EVENT_REGISTRY.unregister(BlockType::AsyncChannel(self.id));
+    }
+}
+
 impl<T: Send + 'static> Receiver<T> {
     pub async fn recv(&self) -> T {
         ReceiverRecv(self).await
@@ -47,6 +65,12 @@ impl<T: Send + 'static> Receiver<T> {
 
 struct ReceiverRecv<'a, T: Send + 'static>(&'a Receiver<T>);
 
+impl<T: Send + 'static> Drop for ReceiverRecv<'_, T> {
+    fn drop(&mut self) {
+        EVENT_REGISTRY.unregister(BlockType::AsyncChannel(self.0.id));
+    }
+}
+
 impl<T: Send + 'static> Future for ReceiverRecv<'_, T> {
     type Output = T;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -99,6 +123,61 @@ impl<T: Send + 'static> Stream for ReceiverStream<T> {
 pub fn unbounded<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
     let id = super::CHANNEL_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
     let inner = SyncRc::new(SyncRefCell::new(VecDeque::new()));
+    let receiver_dropped = SyncRc::new(SyncRefCell::new(false));
+
+    (
+        Sender { inner: SyncRc::clone(&inner), receiver_dropped: SyncRc::clone(&receiver_dropped), id },
+        Receiver { inner, receiver_dropped, id },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{futures::stream::StreamExt, sync::test_util::Tasks};
+    use core::cell::RefCell;
+
+    // Both cases share the global event registry, so they can't run in
+    // parallel as separate tests
+    #[test_case]
+    fn receiver_stream() {
+        let (tx, rx) = unbounded::<u32>();
+        let id = rx.id;
+        let received = RefCell::new(Vec::new());
+
+        let mut stream = rx.into_stream().map(|n| n * 10).take(3);
+        let mut tasks = Tasks::new(vec![Box::pin(async {
+            while let Some(n) = stream.next().await {
+                received.borrow_mut().push(n);
+            }
+        })]);
 
-    (Sender { inner: SyncRc::clone(&inner), id }, Receiver { inner, id })
+        tasks.poll_woken();
+        tx.send(1);
+        tasks.poll_woken();
+        tx.send(2);
+        tx.send(3);
+        tx.send(4);
+        tasks.run_to_completion();
+
+        // `take` stops polling once it has seen enough, leaving the rest queued
+        assert_eq!(*received.borrow(), [10, 20, 30]);
+        assert!(!tx.is_closed());
+        drop(stream);
+        assert!(tx.is_closed());
+        assert!(EVENT_REGISTRY.unregister(BlockType::AsyncChannel(id)).is_none());
+
+        // Cancelling a task waiting on the stream removes its waker
+        let (tx, rx) = unbounded::<u32>();
+        let id = rx.id;
+        let mut stream = rx.into_stream();
+        let mut tasks = Tasks::new(vec![Box::pin(async move {
+            stream.next().await;
+        })]);
+
+        tasks.poll_woken();
+        tasks.cancel(0);
+        assert!(tx.is_closed());
+        assert!(EVENT_REGISTRY.unregister(BlockType::AsyncChannel(id)).is_none());
+    }
 }
@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::waitqueue::WaitQueue;
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+};
+use std::{
+    sync::SyncRefCell,
+    task::{Context, Poll},
+};
+
+/// A mutual exclusion lock which can be held across `.await` points. Tasks
+/// waiting on the lock yield to the executor instead of spinning, and acquire
+/// it in the order they started waiting.
+pub struct AsyncMutex<T: ?Sized> {
+    state: SyncRefCell<MutexState>,
+    value: UnsafeCell<T>,
+}
+
+struct MutexState {
+    locked: bool,
+    waiters: WaitQueue<()>,
+}
+
+impl<T> AsyncMutex<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: SyncRefCell::new(MutexState { locked: false, waiters: WaitQueue::new() }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> AsyncMutex<T> {
+    /// Wait until the lock is free, then acquire it
+    pub fn lock(&self) -> AsyncMutexLock<'_, T> {
+        AsyncMutexLock { mutex: self, id: None }
+    }
+
+    /// Acquire the lock if it's free and no other task is waiting on it
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        let mut state = self.state.borrow_mut();
+        match state.locked || !state.waiters.is_next(None) {
+            true => None,
+            false => {
+                state.locked = true;
+                Some(AsyncMutexGuard { mutex: self })
+            }
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> core::fmt::Debug for AsyncMutex<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AsyncMutex").field("locked", &self.state.borrow().locked).finish_non_exhaustive()
+    }
+}
+
+// Tasks are only ever run on the executor's thread, so these only need to
+// uphold the same guarantees as a regular mutex
+unsafe impl<T: ?Sized + Send> Send for AsyncMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for AsyncMutex<T> {}
+
+/// The future returned by [`AsyncMutex::lock`]
+pub struct AsyncMutexLock<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+    /// Our place in the wait queue, once we've had to wait
+    id: Option<u64>,
+}
+
+impl<'a, T: ?Sized> Future for AsyncMutexLock<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        let mut state = mutex.state.borrow_mut();
+
+        if !state.locked && state.waiters.is_next(self.id) {
+            state.locked = true;
+            if let Some(id) = self.id.take() {
+                state.waiters.remove(id);
+            }
+
+            return Poll::Ready(AsyncMutexGuard { mutex });
+        }
+
+        match self.id {
+            Some(id) => state.waiters.update(id, cx.waker()),
+            None => self.id = Some(state.waiters.push((), cx.waker())),
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncMutexLock<'_, T> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else { return };
+        let mut state = self.mutex.state.borrow_mut();
+
+        // If we were woken but cancelled before taking the lock, pass the
+        // wakeup along so the lock isn't left free with tasks still waiting
+        if state.waiters.remove(id) && !state.locked {
+            state.waiters.wake_next();
+        }
+    }
+}
+
+/// Provides access to the value protected by an [`AsyncMutex`], releasing the
+/// lock and waking the next waiting task when dropped
+pub struct AsyncMutexGuard<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<T: ?Sized> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.mutex.state.borrow_mut();
+        state.locked = false;
+        state.waiters.wake_next();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::test_util::{yield_now, Tasks};
+
+    #[test_case]
+    fn contending_tasks_both_acquire() {
+        let mutex = AsyncMutex::new(Vec::new());
+
+        let task = |n: u32| {
+            let mutex = &mutex;
+            async move {
+                let mut guard = mutex.lock().await;
+                guard.push(n);
+                // Hold the lock across a yield so the other task has to wait
+                yield_now().await;
+                guard.push(n);
+            }
+        };
+
+        Tasks::new(vec![Box::pin(task(1)), Box::pin(task(2))]).run_to_completion();
+        assert_eq!(mutex.into_inner(), [1, 1, 2, 2]);
+    }
+
+    #[test_case]
+    fn waiters_acquire_in_order() {
+        let mutex = AsyncMutex::new(Vec::new());
+        let guard = mutex.try_lock().unwrap();
+
+        let mutex = &mutex;
+        let mut tasks = Tasks::new((0..4).map(|n| Box::pin(async move { mutex.lock().await.push(n) }) as _).collect());
+        tasks.poll_woken();
+
+        // Nobody can skip ahead of the tasks which are already waiting
+        drop(guard);
+        assert!(mutex.try_lock().is_none());
+
+        tasks.run_to_completion();
+        assert_eq!(*mutex.try_lock().unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test_case]
+    fn cancelled_waiter_passes_on_wakeup() {
+        let mutex = AsyncMutex::new(0);
+        let guard = mutex.try_lock().unwrap();
+
+        let mut tasks = Tasks::new(vec![
+            Box::pin(async { *mutex.lock().await += 1 }),
+            Box::pin(async { *mutex.lock().await += 10 }),
+        ]);
+        tasks.poll_woken();
+
+        drop(guard);
+        tasks.cancel(0);
+        tasks.run_to_completion();
+
+        assert_eq!(mutex.into_inner(), 10);
+    }
+}
@@ -0,0 +1,298 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::waitqueue::WaitQueue;
+use core::{
+    cell::UnsafeCell,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+};
+use std::{
+    sync::SyncRefCell,
+    task::{Context, Poll},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// A reader-writer lock which can be held across `.await` points. Tasks
+/// waiting on the lock yield to the executor instead of spinning, and acquire
+/// it in the order they started waiting, so a steady stream of readers can't
+/// starve a writer.
+pub struct AsyncRwLock<T: ?Sized> {
+    state: SyncRefCell<RwLockState>,
+    value: UnsafeCell<T>,
+}
+
+struct RwLockState {
+    readers: usize,
+    writer: bool,
+    waiters: WaitQueue<Access>,
+}
+
+impl RwLockState {
+    fn can_acquire(&self, access: Access, id: Option<u64>) -> bool {
+        let free = match access {
+            Access::Read => !self.writer,
+            Access::Write => !self.writer && self.readers == 0,
+        };
+
+        free && self.waiters.is_next(id)
+    }
+
+    fn acquire(&mut self, access: Access, id: Option<u64>) {
+        match access {
+            Access::Read => self.readers += 1,
+            Access::Write => self.writer = true,
+        }
+
+        if let Some(id) = id {
+            self.waiters.remove(id);
+
+            // Any readers queued directly behind us can share the lock too
+            if access == Access::Read && self.waiters.next_kind() == Some(Access::Read) {
+                self.waiters.wake_next();
+            }
+        }
+    }
+}
+
+impl<T> AsyncRwLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: SyncRefCell::new(RwLockState { readers: 0, writer: false, waiters: WaitQueue::new() }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> AsyncRwLock<T> {
+    /// Wait until there are no writers, then acquire shared access
+    pub fn read(&self) -> AsyncRwLockAcquire<'_, T, AsyncRwLockReadGuard<'_, T>> {
+        AsyncRwLockAcquire { lock: self, access: Access::Read, id: None, guard: core::marker::PhantomData }
+    }
+
+    /// Wait until there are no readers or writers, then acquire exclusive
+    /// access
+    pub fn write(&self) -> AsyncRwLockAcquire<'_, T, AsyncRwLockWriteGuard<'_, T>> {
+        AsyncRwLockAcquire { lock: self, access: Access::Write, id: None, guard: core::marker::PhantomData }
+    }
+
+    /// Acquire shared access if there are no writers and no tasks are waiting
+    pub fn try_read(&self) -> Option<AsyncRwLockReadGuard<'_, T>> {
+        self.try_acquire(Access::Read).then(|| AsyncRwLockReadGuard { lock: self })
+    }
+
+    /// Acquire exclusive access if the lock is free and no tasks are waiting
+    pub fn try_write(&self) -> Option<AsyncRwLockWriteGuard<'_, T>> {
+        self.try_acquire(Access::Write).then(|| AsyncRwLockWriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    fn try_acquire(&self, access: Access) -> bool {
+        let mut state = self.state.borrow_mut();
+        match state.can_acquire(access, None) {
+            true => {
+                state.acquire(access, None);
+                true
+            }
+            false => false,
+        }
+    }
+}
+
+impl<T: Default> Default for AsyncRwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized> core::fmt::Debug for AsyncRwLock<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("AsyncRwLock")
+            .field("readers", &state.readers)
+            .field("writer", &state.writer)
+            .finish_non_exhaustive()
+    }
+}
+
+// Tasks are only ever run on the executor's thread, so these only need to
+// uphold the same guarantees as a regular reader-writer lock
+unsafe impl<T: ?Sized + Send> Send for AsyncRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for AsyncRwLock<T> {}
+
+/// The future returned by [`AsyncRwLock::read`] and [`AsyncRwLock::write`]
+pub struct AsyncRwLockAcquire<'a, T: ?Sized, G> {
+    lock: &'a AsyncRwLock<T>,
+    access: Access,
+    /// Our place in the wait queue, once we've had to wait
+    id: Option<u64>,
+    guard: core::marker::PhantomData<fn() -> G>,
+}
+
+impl<'a, T: ?Sized, G: From<&'a AsyncRwLock<T>>> Future for AsyncRwLockAcquire<'a, T, G> {
+    type Output = G;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let lock = self.lock;
+        let mut state = lock.state.borrow_mut();
+
+        if state.can_acquire(self.access, self.id) {
+            state.acquire(self.access, self.id.take());
+            return Poll::Ready(G::from(lock));
+        }
+
+        match self.id {
+            Some(id) => state.waiters.update(id, cx.waker()),
+            None => self.id = Some(state.waiters.push(self.access, cx.waker())),
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<T: ?Sized, G> Drop for AsyncRwLockAcquire<'_, T, G> {
+    fn drop(&mut self) {
+        let Some(id) = self.id else { return };
+        let mut state = self.lock.state.borrow_mut();
+
+        // If we were woken but cancelled before taking the lock, pass the
+        // wakeup along so the next waiter gets a chance to acquire it
+        if state.waiters.remove(id) {
+            state.waiters.wake_next();
+        }
+    }
+}
+
+/// Provides shared access to the value protected by an [`AsyncRwLock`]
+pub struct AsyncRwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a AsyncRwLock<T>,
+}
+
+impl<'a, T: ?Sized> From<&'a AsyncRwLock<T>> for AsyncRwLockReadGuard<'a, T> {
+    fn from(lock: &'a AsyncRwLock<T>) -> Self {
+        Self { lock }
+    }
+}
+
+impl<T: ?Sized> Deref for AsyncRwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncRwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.borrow_mut();
+        state.readers -= 1;
+        if state.readers == 0 {
+            state.waiters.wake_next();
+        }
+    }
+}
+
+/// Provides exclusive access to the value protected by an [`AsyncRwLock`]
+pub struct AsyncRwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a AsyncRwLock<T>,
+}
+
+impl<'a, T: ?Sized> From<&'a AsyncRwLock<T>> for AsyncRwLockWriteGuard<'a, T> {
+    fn from(lock: &'a AsyncRwLock<T>) -> Self {
+        Self { lock }
+    }
+}
+
+impl<T: ?Sized> Deref for AsyncRwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for AsyncRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncRwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut state = self.lock.state.borrow_mut();
+        state.writer = false;
+        state.waiters.wake_next();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::test_util::{yield_now, Tasks};
+
+    #[test_case]
+    fn readers_share_and_writers_wait() {
+        let lock = AsyncRwLock::new(Vec::new());
+        let lock = &lock;
+
+        let reader = move |n: u32| async move {
+            let guard = lock.read().await;
+            yield_now().await;
+            assert!(guard.len() <= 1);
+            n
+        };
+        let writer = async move {
+            let mut guard = lock.write().await;
+            guard.push(1);
+            yield_now().await;
+            guard.push(2);
+        };
+
+        let mut tasks = Tasks::new(vec![
+            Box::pin(async move { assert_eq!(reader(0).await, 0) }),
+            Box::pin(async move { assert_eq!(reader(1).await, 1) }),
+            Box::pin(writer),
+            Box::pin(async move { assert_eq!(*lock.read().await, [1, 2]) }),
+        ]);
+
+        // Both readers hold the lock at once, while the writer and the reader
+        // queued behind it wait
+        tasks.poll_woken();
+        assert_eq!(lock.state.borrow().readers, 2);
+        assert!(lock.try_read().is_none());
+
+        tasks.run_to_completion();
+        assert_eq!(*lock.try_write().unwrap(), [1, 2]);
+    }
+
+    #[test_case]
+    fn contending_writers_both_acquire() {
+        let lock = AsyncRwLock::new(0);
+        let lock = &lock;
+        let writer = move || async move {
+            let mut guard = lock.write().await;
+            yield_now().await;
+            *guard += 1;
+        };
+
+        Tasks::new(vec![Box::pin(writer()), Box::pin(writer())]).run_to_completion();
+        assert_eq!(*lock.try_read().unwrap(), 2);
+    }
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::{future::Future, pin::Pin};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Wake},
+};
+
+pub(crate) type TestFuture<'a> = Pin<Box<dyn Future<Output = ()> + 'a>>;
+
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// A minimal stand-in for the executor which only polls tasks again once
+/// they've been woken, so lost wakeups show up as a stall
+pub(crate) struct Tasks<'a>(Vec<(TestFuture<'a>, Arc<Woken>)>);
+
+impl<'a> Tasks<'a> {
+    pub(crate) fn new(futures: Vec<TestFuture<'a>>) -> Self {
+        Self(futures.into_iter().map(|future| (future, Arc::new(Woken(AtomicBool::new(true))))).collect())
+    }
+
+    /// Poll each task that has been woken since it was last polled
+    pub(crate) fn poll_woken(&mut self) -> bool {
+        let mut progressed = false;
+        self.0.retain_mut(|(future, woken)| {
+            if !woken.0.swap(false, Ordering::AcqRel) {
+                return true;
+            }
+
+            progressed = true;
+            future.as_mut().poll(&mut Context::from_waker(&Arc::clone(woken).into())).is_pending()
+        });
+
+        progressed
+    }
+
+    /// Drop a task before it completes
+    pub(crate) fn cancel(&mut self, index: usize) {
+        self.0.remove(index);
+    }
+
+    pub(crate) fn run_to_completion(mut self) {
+        while !self.0.is_empty() {
+            assert!(self.poll_woken(), "tasks are still waiting, but none of them were woken");
+        }
+    }
+}
+
+/// Yield to the executor once, waking immediately
+pub(crate) async fn yield_now() {
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            match core::mem::replace(&mut self.0, true) {
+                true => Poll::Ready(()),
+                false => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    YieldNow(false).await
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::task::Waker;
+
+/// A FIFO queue of tasks waiting to acquire a lock, along with what kind of
+/// access each of them wants
+pub(crate) struct WaitQueue<K: Copy> {
+    next_id: u64,
+    waiters: VecDeque<Waiter<K>>,
+}
+
+struct Waiter<K> {
+    id: u64,
+    kind: K,
+    waker: Waker,
+}
+
+impl<K: Copy> WaitQueue<K> {
+    pub(crate) fn new() -> Self {
+        Self { next_id: 0, waiters: VecDeque::new() }
+    }
+
+    /// Add a new waiter to the back of the queue, returning its ID
+    pub(crate) fn push(&mut self, kind: K, waker: &Waker) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.waiters.push_back(Waiter { id, kind, waker: waker.clone() });
+
+        id
+    }
+
+    /// Whether a waiter with the given ID (or a new waiter, if `None`) is next
+    /// in line to acquire the lock
+    pub(crate) fn is_next(&self, id: Option<u64>) -> bool {
+        match (id, self.waiters.front()) {
+            (None, front) => front.is_none(),
+            (Some(id), Some(front)) => front.id == id,
+            (Some(_), None) => false,
+        }
+    }
+
+    /// The kind of access the next waiter in line wants
+    pub(crate) fn next_kind(&self) -> Option<K> {
+        self.waiters.front().map(|waiter| waiter.kind)
+    }
+
+    /// Replace the waker for an existing waiter, in case the task was polled
+    /// with a different one
+    pub(crate) fn update(&mut self, id: u64, waker: &Waker) {
+        if let Some(waiter) = self.waiters.iter_mut().find(|waiter| waiter.id == id) {
+            if !waiter.waker.will_wake(waker) {
+                waiter.waker = waker.clone();
+            }
+        }
+    }
+
+    /// Remove a waiter from the queue, returning whether it was next in line
+    pub(crate) fn remove(&mut self, id: u64) -> bool {
+        match self.waiters.iter().position(|waiter| waiter.id == id) {
+            Some(index) => {
+                self.waiters.remove(index);
+                index == 0
+            }
+            None => false,
+        }
+    }
+
+    /// Wake the next waiter in line so it can try to acquire the lock
+    pub(crate) fn wake_next(&self) {
+        if let Some(waiter) = self.waiters.front() {
+            waiter.waker.wake_by_ref();
+        }
+    }
+}
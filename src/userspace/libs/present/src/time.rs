@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::{future::Future, pin::Pin};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        SyncRefCell,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+pub(crate) static TIMERS: Timers = Timers::new();
+static TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The futures waiting on a deadline, keyed by the deadline and a unique ID so
+/// multiple futures can wait on the same deadline. Only the earliest deadline
+/// is requested from the kernel at any one time.
+pub(crate) struct Timers {
+    waiting: SyncRefCell<BTreeMap<(Instant, u64), Waker>>,
+}
+
+impl Timers {
+    const fn new() -> Self {
+        Self { waiting: SyncRefCell::new(BTreeMap::new()) }
+    }
+
+    fn register(&self, deadline: Instant, id: u64, waker: Waker) {
+        let mut waiting = self.waiting.borrow_mut();
+        let was_earliest = waiting.keys().next().map_or(true, |earliest| (deadline, id) < *earliest);
+        waiting.insert((deadline, id), waker);
+
+        if was_earliest {
+            std::time::notify_at(deadline);
+        }
+    }
+
+    fn cancel(&self, deadline: Instant, id: u64) {
+        let mut waiting = self.waiting.borrow_mut();
+        if waiting.remove(&(deadline, id)).is_some() && waiting.is_empty() {
+            std::time::cancel_notification();
+        }
+    }
+
+    /// Wake every future whose deadline has passed, and request a notification
+    /// for the next deadline, if any
+    pub(crate) fn wake_expired(&self, now: Instant) {
+        let mut waiting = self.waiting.borrow_mut();
+        let pending = waiting.split_off(&(now, u64::MAX));
+        let expired = core::mem::replace(&mut *waiting, pending);
+
+        if let Some((deadline, _)) = waiting.keys().next() {
+            std::time::notify_at(*deadline);
+        }
+
+        drop(waiting);
+        expired.into_values().for_each(Waker::wake);
+    }
+}
+
+/// Wait until `duration` has elapsed
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Wait until `deadline` has passed
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, id: TIMER_ID.fetch_add(1, Ordering::Relaxed), registered: false }
+}
+
+/// See [`sleep`] and [`sleep_until`]
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    id: u64,
+    registered: bool,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Instant::now() >= self.deadline {
+            TIMERS.cancel(self.deadline, self.id);
+            self.registered = false;
+            return Poll::Ready(());
+        }
+
+        TIMERS.register(self.deadline, self.id, cx.waker().clone());
+        self.registered = true;
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if self.registered {
+            TIMERS.cancel(self.deadline, self.id);
+        }
+    }
+}
+
+/// The error returned by [`timeout`] when the future didn't complete in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// Run `future`, giving up if it hasn't completed once `duration` has elapsed
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout { future, sleep: sleep(duration) }
+}
+
+/// See [`timeout`]
+pub struct Timeout<F: Future> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: neither field is moved out of the pinned `Timeout`
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        // Prefer the future's output if both are ready
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{mpsc, test_util::Tasks};
+
+    #[test_case]
+    fn recv_times_out() {
+        let (_tx, rx) = mpsc::unbounded::<u32>();
+        let mut result = None;
+
+        let task = async { result = Some(timeout(Duration::ZERO, rx.recv()).await) };
+        Tasks::new(vec![Box::pin(task)]).run_to_completion();
+
+        assert_eq!(result, Some(Err(Elapsed)));
+    }
+
+    #[test_case]
+    fn ready_future_beats_timeout() {
+        let (tx, rx) = mpsc::unbounded();
+        tx.send(5);
+        let mut result = None;
+
+        let task = async { result = Some(timeout(Duration::ZERO, rx.recv()).await) };
+        Tasks::new(vec![Box::pin(task)]).run_to_completion();
+
+        assert_eq!(result, Some(Ok(5)));
+    }
+}
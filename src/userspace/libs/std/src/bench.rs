@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Raw hardware counters for benchmarking
+//!
+//! The cycle and instruction counters are per-hart, and a task can be moved to
+//! another hart between two readings, so the difference between them is only
+//! meaningful if the task is pinned to a single hart with
+//! [`librust::syscalls::task::set_affinity`]. Reading a counter the kernel
+//! hasn't been configured to expose (with the `user-counters` kernel argument,
+//! see the README) faults.
+
+/// Read the number of cycles the current hart has executed
+#[inline(always)]
+pub fn read_cycles() -> u64 {
+    let value: u64;
+
+    unsafe { core::arch::asm!("csrr {}, cycle", out(reg) value, options(nomem, nostack)) };
+
+    value
+}
+
+/// Read the platform timer, which ticks at the rate given by the devicetree's
+/// `timebase-frequency`. Unlike the other counters, it's synchronized between
+/// harts, so it doesn't need the task to be pinned to one.
+#[inline(always)]
+pub fn read_time() -> u64 {
+    let value: u64;
+
+    unsafe { core::arch::asm!("csrr {}, time", out(reg) value, options(nomem, nostack)) };
+
+    value
+}
+
+/// Read the number of instructions the current hart has retired
+#[inline(always)]
+pub fn read_instructions_retired() -> u64 {
+    let value: u64;
+
+    unsafe { core::arch::asm!("csrr {}, instret", out(reg) value, options(nomem, nostack)) };
+
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use librust::{
+        syscalls::{misc::current_hart, task::set_affinity},
+        task::HartMask,
+    };
+
+    #[test_case]
+    fn cycles_increase() {
+        set_affinity(HartMask::empty().with(current_hart())).unwrap();
+
+        let mut last = read_cycles();
+        for _ in 0..1000 {
+            let now = read_cycles();
+            assert!(now >= last);
+            last = now;
+        }
+
+        let start = read_cycles();
+        let mut counter = 0u64;
+        for _ in 0..1000 {
+            counter = core::hint::black_box(counter + 1);
+        }
+
+        assert!(read_cycles() > start);
+    }
+
+    #[test_case]
+    fn time_never_goes_backwards() {
+        let mut last = read_time();
+        for _ in 0..1000 {
+            let now = read_time();
+            assert!(now >= last);
+            last = now;
+        }
+    }
+}
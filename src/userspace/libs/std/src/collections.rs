@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+extern crate alloc;
+
+pub mod hash_set;
+
+pub use ::collections::hash::{FxBuildHasher, FxHasher};
+pub use ::collections::hash_map;
+pub use alloc::collections::*;
+pub use hash_set::HashSet;
+
+/// A [`hash_map::HashMap`] which allocates from the global allocator and uses
+/// [`FxBuildHasher`] by default. FxHash is fast but not resistant to HashDoS,
+/// so prefer a different hasher or a [`BTreeMap`] if the keys are controlled
+/// by an untrusted task.
+pub type HashMap<K, V, S = FxBuildHasher> = hash_map::HashMap<crate::alloc::Global, K, V, S>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alloc::Global;
+
+    #[test_case]
+    fn hash_map_insert_get() {
+        let mut map: HashMap<String, usize> = HashMap::new(Global);
+
+        for (i, key) in ["virtio-blk", "virtio-net", "ns16550a", "goldfish-rtc"].into_iter().enumerate() {
+            assert_eq!(map.insert(String::from(key), i), Ok(None));
+        }
+
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get("virtio-net"), Some(&1));
+        assert_eq!(map.insert(String::from("virtio-net"), 10), Ok(Some(1)));
+        assert_eq!(map.get("virtio-net"), Some(&10));
+        assert_eq!(map.remove("ns16550a"), Some(2));
+        assert_eq!(map.get("ns16550a"), None);
+        assert_eq!(map.get("plic"), None);
+    }
+}
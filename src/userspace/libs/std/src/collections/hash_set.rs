@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use super::{hash_map, FxBuildHasher};
+use crate::alloc::{AllocError, Global};
+use core::{
+    borrow::Borrow,
+    hash::{BuildHasher, Hash},
+};
+
+/// A set of values stored as the keys of a [`hash_map::HashMap`], so like the
+/// map it returns an error instead of panicking when it fails to allocate. Uses
+/// [`FxBuildHasher`] by default, which has the same HashDoS caveat as
+/// [`super::HashMap`].
+pub struct HashSet<T, S = FxBuildHasher>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    map: hash_map::HashMap<Global, T, (), S>,
+}
+
+impl<T, S> HashSet<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    pub fn new() -> Self {
+        Self { map: hash_map::HashMap::new(Global) }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Result<Self, AllocError> {
+        Ok(Self { map: hash_map::HashMap::with_capacity(Global, capacity)? })
+    }
+}
+
+impl<T, S> Default for HashSet<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, S> HashSet<T, S>
+where
+    T: Eq + Hash,
+    S: BuildHasher,
+{
+    pub fn with_hasher(hash_builder: S) -> Self {
+        Self { map: hash_map::HashMap::with_hasher(Global, hash_builder) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Insert `value` into the set, returning `true` if it wasn't already
+    /// present
+    pub fn insert(&mut self, value: T) -> Result<bool, AllocError> {
+        Ok(self.map.insert(value, ())?.is_none())
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.get(value).is_some()
+    }
+
+    /// Remove `value` from the set, returning `true` if it was present
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.remove(value).is_some()
+    }
+
+    pub fn reserve(&mut self, additional: usize) -> Result<(), AllocError> {
+        self.map.reserve(additional)
+    }
+
+    /// Iterate over the values in the set in an unspecified order
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.map.iter().map(|(value, _)| value)
+    }
+
+    /// The values in either set, without allocating
+    pub fn union<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = &'a T> + 'a {
+        self.iter().chain(other.iter().filter(move |value| !self.contains(*value)))
+    }
+
+    /// The values in both sets, without allocating
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> impl Iterator<Item = &'a T> + 'a {
+        self.iter().filter(move |value| other.contains(*value))
+    }
+}
+
+impl<T, S> core::fmt::Debug for HashSet<T, S>
+where
+    T: Eq + Hash + core::fmt::Debug,
+    S: BuildHasher,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted<'a>(values: impl Iterator<Item = &'a u32>) -> Vec<u32> {
+        let mut values: Vec<u32> = values.copied().collect();
+        values.sort_unstable();
+        values
+    }
+
+    #[test_case]
+    fn membership() {
+        let mut set: HashSet<String> = HashSet::new();
+        assert!(set.is_empty());
+
+        assert_eq!(set.insert(String::from("virtio-blk")), Ok(true));
+        assert_eq!(set.insert(String::from("virtio-net")), Ok(true));
+        assert_eq!(set.insert(String::from("virtio-blk")), Ok(false));
+        assert_eq!(set.len(), 2);
+
+        assert!(set.contains("virtio-net"));
+        assert!(!set.contains("ns16550a"));
+        assert!(set.remove("virtio-net"));
+        assert!(!set.remove("virtio-net"));
+        assert!(!set.contains("virtio-net"));
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test_case]
+    fn set_operations() {
+        let mut a: HashSet<u32> = HashSet::new();
+        let mut b: HashSet<u32> = HashSet::new();
+        for n in [1, 2, 3, 4] {
+            a.insert(n).unwrap();
+        }
+        for n in [3, 4, 5] {
+            b.insert(n).unwrap();
+        }
+
+        assert_eq!(sorted(a.union(&b)), [1, 2, 3, 4, 5]);
+        assert_eq!(sorted(b.union(&a)), [1, 2, 3, 4, 5]);
+        assert_eq!(sorted(a.intersection(&b)), [3, 4]);
+        assert_eq!(sorted(b.intersection(&a)), [3, 4]);
+
+        let empty: HashSet<u32> = HashSet::new();
+        assert_eq!(sorted(a.union(&empty)), [1, 2, 3, 4]);
+        assert_eq!(a.intersection(&empty).count(), 0);
+    }
+}
@@ -6,22 +6,68 @@
 // obtain one at https://mozilla.org/MPL/2.0/.
 
 use crate::{collections::BTreeMap, string::String};
-use librust::capabilities::CapabilityWithDescription;
+use librust::capabilities::{CapabilityDescription, CapabilityWithDescription};
 
 use crate::sync::SyncRefCell;
 
 #[no_mangle]
 static mut ARGS: [usize; 2] = [0; 2];
 
-pub fn args() -> &'static [&'static str] {
-    let [argc, argv] = unsafe { ARGS };
+/// The name of the capability used to pass a JSON-serialized `Vec<String>` of
+/// arguments to a newly spawned task
+pub const ARGS_CAPABILITY: &str = "args";
 
-    match [argc, argv] {
-        [0, _] | [_, 0] => &[],
-        [argc, argv] => unsafe { core::slice::from_raw_parts(argv as *const &str, argc) },
+static ARGS_CACHE: SyncRefCell<Option<Vec<String>>> = SyncRefCell::new(None);
+
+/// Returns an iterator over the arguments the current task was spawned with.
+/// Arguments are either passed by the kernel in `a0`/`a1` (for `init`) or by
+/// the spawning task as an [`ARGS_CAPABILITY`] memory capability, and are
+/// materialized on the first call. If no arguments were provided, or the
+/// spawning task passed arguments which couldn't be deserialized, the iterator
+/// is empty.
+pub fn args() -> Args {
+    let mut cache = ARGS_CACHE.borrow_mut();
+    let args = cache.get_or_insert_with(|| {
+        let [argc, argv] = unsafe { ARGS };
+
+        match [argc, argv] {
+            [0, _] | [_, 0] => match lookup_capability(ARGS_CAPABILITY) {
+                Some(CapabilityWithDescription {
+                    description: CapabilityDescription::Memory { ptr, len, permissions: _ },
+                    ..
+                }) => json::deserialize(unsafe { core::slice::from_raw_parts(ptr, len) }).unwrap_or_default(),
+                _ => Vec::new(),
+            },
+            [argc, argv] => unsafe { core::slice::from_raw_parts(argv as *const &str, argc) }
+                .iter()
+                .map(|arg| String::from(*arg))
+                .collect(),
+        }
+    });
+
+    Args { inner: args.clone().into_iter() }
+}
+
+/// An iterator over the arguments of the current task, see [`args`]
+#[derive(Debug)]
+pub struct Args {
+    inner: crate::vec::IntoIter<String>,
+}
+
+impl Iterator for Args {
+    type Item = String;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
     }
 }
 
+impl ExactSizeIterator for Args {}
+
 #[no_mangle]
 static mut A2: usize = 0;
 
@@ -30,6 +76,18 @@ pub fn a2() -> usize {
     unsafe { A2 }
 }
 
+#[cfg(feature = "init")]
+pub(crate) static mut INITRD: [usize; 2] = [0; 2];
+
+/// The initrd the bootloader loaded, which the kernel maps into `init`
+#[cfg(feature = "init")]
+pub fn initrd() -> Option<&'static [u8]> {
+    match unsafe { INITRD } {
+        [0, _] => None,
+        [start, len] => Some(unsafe { core::slice::from_raw_parts(start as *const u8, len) }),
+    }
+}
+
 pub(crate) static CAP_MAP: SyncRefCell<BTreeMap<String, CapabilityWithDescription>> = SyncRefCell::new(BTreeMap::new());
 
 pub fn lookup_capability(service: &str) -> Option<CapabilityWithDescription> {
@@ -5,6 +5,312 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::sync::SyncRefCell;
+
+/// The buffered standard output used by the [`print!`] and [`println!`]
+/// macros
+static STDOUT: SyncRefCell<BufWriter<Stdout>> = SyncRefCell::new(BufWriter::new(Stdout, FlushPolicy::Line));
+
+/// The number of bytes [`BufWriter`] holds onto before flushing when using
+/// [`FlushPolicy::Full`]
+pub const DEFAULT_BUFFER_CAPACITY: usize = 1024;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The general category of an I/O [`Error`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    InvalidInput,
+    /// The data being read wasn't valid for the operation, e.g. non-UTF-8
+    /// data read by [`Read::read_to_string`]
+    InvalidData,
+    /// The reader ran out of data before the operation could complete
+    UnexpectedEof,
+    /// The writer stopped accepting data before everything was written
+    WriteZero,
+    /// The operation isn't supported by the reader or writer
+    Unsupported,
+    /// There's no space left to store any more data
+    StorageFull,
+    Other,
+}
+
+impl core::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ErrorKind::NotFound => write!(f, "entity not found"),
+            ErrorKind::PermissionDenied => write!(f, "permission denied"),
+            ErrorKind::AlreadyExists => write!(f, "entity already exists"),
+            ErrorKind::InvalidInput => write!(f, "invalid input parameter"),
+            ErrorKind::InvalidData => write!(f, "invalid data"),
+            ErrorKind::UnexpectedEof => write!(f, "unexpected end of file"),
+            ErrorKind::WriteZero => write!(f, "write zero"),
+            ErrorKind::Unsupported => write!(f, "unsupported"),
+            ErrorKind::StorageFull => write!(f, "no storage space"),
+            ErrorKind::Other => write!(f, "other error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Error {
+    kind: ErrorKind,
+}
+
+impl Error {
+    pub const fn new(kind: ErrorKind) -> Self {
+        Self { kind }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(&self.kind, f)
+    }
+}
+
+/// A source of bytes
+pub trait Read {
+    /// Read some bytes into `buffer`, returning how many were read. A return
+    /// value of `0` means the end of the data has been reached, or `buffer` was
+    /// empty.
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize>;
+
+    /// Read until the end of the data, appending everything to `buffer` and
+    /// returning the number of bytes read
+    fn read_to_end(&mut self, buffer: &mut Vec<u8>) -> Result<usize> {
+        let start = buffer.len();
+        let mut chunk = [0; 512];
+
+        loop {
+            match self.read(&mut chunk)? {
+                0 => break,
+                len => buffer.extend_from_slice(&chunk[..len]),
+            }
+        }
+
+        Ok(buffer.len() - start)
+    }
+
+    /// Read until the end of the data, appending everything to `buffer`. If the
+    /// data isn't valid UTF-8, an [`ErrorKind::InvalidData`] error is returned
+    /// and `buffer` is left unchanged.
+    fn read_to_string(&mut self, buffer: &mut String) -> Result<usize> {
+        let mut bytes = Vec::new();
+        let len = self.read_to_end(&mut bytes)?;
+        let s = core::str::from_utf8(&bytes).map_err(|_| Error::new(ErrorKind::InvalidData))?;
+        buffer.push_str(s);
+
+        Ok(len)
+    }
+
+    /// Fill the entirety of `buffer`, returning an
+    /// [`ErrorKind::UnexpectedEof`] error if the data runs out first
+    fn read_exact(&mut self, mut buffer: &mut [u8]) -> Result<()> {
+        while !buffer.is_empty() {
+            match self.read(buffer)? {
+                0 => return Err(Error::new(ErrorKind::UnexpectedEof)),
+                len => buffer = &mut buffer[len..],
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A sink for bytes
+pub trait Write {
+    /// Write some of `buffer`, returning how many bytes were written
+    fn write(&mut self, buffer: &[u8]) -> Result<usize>;
+
+    /// Make sure everything written so far has reached its destination
+    fn flush(&mut self) -> Result<()>;
+
+    /// Write the entirety of `buffer`, returning an [`ErrorKind::WriteZero`]
+    /// error if the writer stops accepting data first
+    fn write_all(&mut self, mut buffer: &[u8]) -> Result<()> {
+        while !buffer.is_empty() {
+            match self.write(buffer)? {
+                0 => return Err(Error::new(ErrorKind::WriteZero)),
+                len => buffer = &buffer[len..],
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where to move to with [`Seek::seek`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// A reader or writer with a position that can be moved
+pub trait Seek {
+    /// Move to the given position, returning the new position from the start
+    fn seek(&mut self, position: SeekFrom) -> Result<u64>;
+
+    fn rewind(&mut self) -> Result<()> {
+        self.seek(SeekFrom::Start(0))?;
+        Ok(())
+    }
+
+    fn stream_position(&mut self) -> Result<u64> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
+
+impl<R: Read + ?Sized> Read for &'_ mut R {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        (**self).read(buffer)
+    }
+}
+
+impl<W: Write + ?Sized> Write for &'_ mut W {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        (**self).write(buffer)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+}
+
+impl<S: Seek + ?Sized> Seek for &'_ mut S {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        (**self).seek(position)
+    }
+}
+
+impl Read for &'_ [u8] {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let len = usize::min(buffer.len(), self.len());
+        let (read, rest) = self.split_at(len);
+        buffer[..len].copy_from_slice(read);
+        *self = rest;
+
+        Ok(len)
+    }
+}
+
+impl Write for Vec<u8> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        self.extend_from_slice(buffer);
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Wraps an in-memory buffer to give it a position, so it can be used with
+/// [`Read`], [`Write`], and [`Seek`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cursor<T> {
+    inner: T,
+    position: u64,
+}
+
+impl<T> Cursor<T> {
+    pub const fn new(inner: T) -> Self {
+        Self { inner, position: 0 }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsRef<[u8]>> Cursor<T> {
+    fn remaining(&self) -> &[u8] {
+        let inner = self.inner.as_ref();
+        &inner[usize::min(self.position as usize, inner.len())..]
+    }
+}
+
+impl<T: AsRef<[u8]>> Read for Cursor<T> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let len = self.remaining().read(buffer)?;
+        self.position += len as u64;
+
+        Ok(len)
+    }
+}
+
+impl Write for Cursor<Vec<u8>> {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let start = self.position as usize;
+        // Writing past the end fills the gap with zeroes
+        if self.inner.len() < start {
+            self.inner.resize(start, 0);
+        }
+
+        let overwritten = usize::min(self.inner.len() - start, buffer.len());
+        self.inner[start..start + overwritten].copy_from_slice(&buffer[..overwritten]);
+        self.inner.extend_from_slice(&buffer[overwritten..]);
+        self.position += buffer.len() as u64;
+
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: AsRef<[u8]>> Seek for Cursor<T> {
+    fn seek(&mut self, position: SeekFrom) -> Result<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(position) => {
+                self.position = position;
+                return Ok(position);
+            }
+            SeekFrom::End(offset) => (self.inner.as_ref().len() as u64, offset),
+            SeekFrom::Current(offset) => (self.position, offset),
+        };
+
+        match base.checked_add_signed(offset) {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(Error::new(ErrorKind::InvalidInput)),
+        }
+    }
+}
+
+/// The unbuffered standard output. Most output should go through [`print!`]
+/// and [`println!`] instead, which buffer according to the policy set with
+/// [`set_stdout_policy`].
 pub struct Stdout;
 impl core::fmt::Write for Stdout {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
@@ -12,3 +318,327 @@ impl core::fmt::Write for Stdout {
         Ok(())
     }
 }
+
+impl Write for Stdout {
+    fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        match librust::syscalls::io::debug_print(buffer) {
+            Ok(()) => Ok(buffer.len()),
+            Err(_) => Err(Error::new(ErrorKind::Other)),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The standard input
+///
+/// Console input is currently only seen by the `stdio` server, which has no
+/// way for other tasks to request it yet, so reading always fails with
+/// [`ErrorKind::Unsupported`].
+pub struct Stdin;
+
+impl Read for Stdin {
+    fn read(&mut self, _: &mut [u8]) -> Result<usize> {
+        Err(Error::new(ErrorKind::Unsupported))
+    }
+}
+
+/// When a [`BufWriter`] writes its buffered output to the underlying writer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Write everything through immediately
+    Unbuffered,
+    /// Flush whenever a newline is written
+    Line,
+    /// Flush once the buffer reaches [`DEFAULT_BUFFER_CAPACITY`] bytes
+    Full,
+}
+
+/// Buffers writes to the underlying writer so that many small writes turn
+/// into fewer, larger ones, according to its [`FlushPolicy`]
+pub struct BufWriter<W: core::fmt::Write> {
+    inner: W,
+    buffer: String,
+    policy: FlushPolicy,
+}
+
+impl<W: core::fmt::Write> BufWriter<W> {
+    pub const fn new(inner: W, policy: FlushPolicy) -> Self {
+        Self { inner, buffer: String::new(), policy }
+    }
+
+    pub fn policy(&self) -> FlushPolicy {
+        self.policy
+    }
+
+    /// Change the flush policy, flushing any buffered output first
+    pub fn set_policy(&mut self, policy: FlushPolicy) -> core::fmt::Result {
+        self.flush()?;
+        self.policy = policy;
+        Ok(())
+    }
+
+    /// Write any buffered output to the underlying writer
+    pub fn flush(&mut self) -> core::fmt::Result {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let result = self.inner.write_str(&self.buffer);
+        self.buffer.clear();
+        result
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// The output that has been written but not yet flushed
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+}
+
+impl<W: core::fmt::Write> core::fmt::Write for BufWriter<W> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        match self.policy {
+            FlushPolicy::Unbuffered => {
+                self.flush()?;
+                self.inner.write_str(s)
+            }
+            FlushPolicy::Line => match s.rfind('\n') {
+                Some(end) => {
+                    let (line, rest) = s.split_at(end + 1);
+                    self.buffer.push_str(line);
+                    self.flush()?;
+                    self.buffer.push_str(rest);
+                    Ok(())
+                }
+                None => {
+                    self.buffer.push_str(s);
+                    Ok(())
+                }
+            },
+            FlushPolicy::Full => {
+                self.buffer.push_str(s);
+                match self.buffer.len() >= DEFAULT_BUFFER_CAPACITY {
+                    true => self.flush(),
+                    false => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// A string with an inline buffer of `N` bytes, for formatting small strings
+/// with [`write!`] without allocating. Writes which don't fit are truncated to
+/// the last whole character that does, and return an error so the rest of the
+/// formatting is skipped. What was written up to that point is kept.
+#[derive(Clone, Copy)]
+pub struct StackString<const N: usize> {
+    buffer: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> StackString<N> {
+    pub const fn new() -> Self {
+        Self { buffer: [0; N], len: 0, truncated: false }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Safety: only whole `str`s and the characters of them which fit are
+        // ever copied into the buffer
+        unsafe { core::str::from_utf8_unchecked(&self.buffer[..self.len]) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Whether a write didn't fit and was cut short
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.truncated = false;
+    }
+}
+
+impl<const N: usize> Default for StackString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> core::ops::Deref for StackString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> core::fmt::Write for StackString<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let mut end = usize::min(N - self.len, s.len());
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.buffer[self.len..][..end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+
+        match end == s.len() {
+            true => Ok(()),
+            false => {
+                self.truncated = true;
+                Err(core::fmt::Error)
+            }
+        }
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for StackString<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> core::fmt::Display for StackString<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+/// Write any buffered standard output
+pub fn flush() {
+    if let Ok(mut stdout) = STDOUT.try_borrow_mut() {
+        let _ = stdout.flush();
+    }
+}
+
+/// Change when standard output is flushed, flushing any buffered output first
+pub fn set_stdout_policy(policy: FlushPolicy) {
+    let _ = STDOUT.borrow_mut().set_policy(policy);
+}
+
+pub(crate) fn write_stdout(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    match STDOUT.try_borrow_mut() {
+        Ok(mut stdout) => {
+            let _ = stdout.write_fmt(args);
+        }
+        // Something being printed is itself printing (e.g. a panic in a
+        // `Display` impl), so skip the buffer rather than failing
+        Err(_) => {
+            let _ = Stdout.write_fmt(args);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write as _;
+
+    #[test_case]
+    fn line_buffered_until_newline_or_flush() {
+        let mut writer = BufWriter::new(String::new(), FlushPolicy::Line);
+
+        write!(writer, "hello").unwrap();
+        write!(writer, ", {}", "world").unwrap();
+        assert_eq!(writer.get_ref(), "");
+
+        write!(writer, "!\nnext").unwrap();
+        assert_eq!(writer.get_ref(), "hello, world!\n");
+        assert_eq!(writer.buffer(), "next");
+
+        writer.flush().unwrap();
+        assert_eq!(writer.get_ref(), "hello, world!\nnext");
+        assert_eq!(writer.buffer(), "");
+    }
+
+    #[test_case]
+    fn flush_policies() {
+        let mut writer = BufWriter::new(String::new(), FlushPolicy::Unbuffered);
+        write!(writer, "a").unwrap();
+        assert_eq!(writer.get_ref(), "a");
+
+        let mut writer = BufWriter::new(String::new(), FlushPolicy::Full);
+        write!(writer, "a\nb\n").unwrap();
+        assert_eq!(writer.get_ref(), "");
+        writer.write_str(&"c".repeat(DEFAULT_BUFFER_CAPACITY)).unwrap();
+        assert_eq!(writer.get_ref().len(), DEFAULT_BUFFER_CAPACITY + 4);
+
+        write!(writer, "d").unwrap();
+        writer.set_policy(FlushPolicy::Line).unwrap();
+        assert!(writer.get_ref().ends_with('d'));
+        assert_eq!(writer.policy(), FlushPolicy::Line);
+    }
+
+    #[test_case]
+    fn cursor_round_trip() {
+        let mut cursor = Cursor::new(Vec::new());
+        Write::write_all(&mut cursor, b"hello, world").unwrap();
+
+        cursor.seek(SeekFrom::Start(7)).unwrap();
+        Write::write_all(&mut cursor, b"there!").unwrap();
+        assert_eq!(cursor.stream_position(), Ok(13));
+
+        cursor.rewind().unwrap();
+        let mut contents = String::new();
+        assert_eq!(cursor.read_to_string(&mut contents), Ok(13));
+        assert_eq!(contents, "hello, there!");
+
+        cursor.seek(SeekFrom::End(-6)).unwrap();
+        let mut buffer = [0; 8];
+        assert_eq!(cursor.read_exact(&mut buffer).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(cursor.seek(SeekFrom::Current(-100)).unwrap_err().kind(), ErrorKind::InvalidInput);
+
+        let mut invalid = &[0xFF, 0xFE][..];
+        assert_eq!(invalid.read_to_string(&mut contents).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test_case]
+    fn stack_string() {
+        let mut string = StackString::<16>::new();
+        write!(string, "{}-{:04}", "tid", 42).unwrap();
+        assert_eq!(string.as_str(), "tid-0042");
+        assert!(!string.is_truncated());
+
+        write!(string, "{:>8}", 'x').unwrap();
+        assert_eq!((string.len(), string.capacity()), (16, 16));
+        assert_eq!(string.write_str(""), Ok(()));
+
+        string.clear();
+        assert!(string.is_empty());
+    }
+
+    #[test_case]
+    fn stack_string_truncates() {
+        let mut string = StackString::<8>::new();
+        assert!(write!(string, "{} {}", "hello", "world").is_err());
+        assert_eq!(&*string, "hello wo");
+        assert!(string.is_truncated());
+
+        // Characters are never split
+        let mut string = StackString::<4>::new();
+        assert!(write!(string, "ab\u{e9}\u{e9}").is_err());
+        assert_eq!(string.as_str(), "ab\u{e9}");
+        assert_eq!(format!("{:?}", string), "\"ab\u{e9}\"");
+    }
+}
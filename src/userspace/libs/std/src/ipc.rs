@@ -5,6 +5,8 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+pub mod typed;
+
 use librust::{
     error::SyscallError,
     syscalls::channel::{self, ReadResult},
@@ -13,7 +15,7 @@ use librust::{
 pub use librust::capabilities::{
     Capability, CapabilityDescription, CapabilityPtr, CapabilityRights, CapabilityWithDescription,
 };
-pub use librust::syscalls::channel::{ChannelMessage, ChannelReadFlags};
+pub use librust::syscalls::channel::{ChannelMessage, ChannelReadFlags, ChannelWriteFlags, Segment};
 
 #[derive(Debug)]
 pub struct IpcChannel {
@@ -49,6 +51,19 @@ impl IpcChannel {
     }
 
     pub fn send(&self, msg: ChannelMessage, caps: &[Capability]) -> Result<(), SyscallError> {
-        channel::send_message(self.cptr, msg, caps)
+        channel::send_message(self.cptr, msg, caps, ChannelWriteFlags::NONE)
+    }
+
+    /// Send a request that's going to be waited on for a reply, see
+    /// [`ChannelWriteFlags::EXPECTS_REPLY`]
+    pub fn send_request(&self, msg: ChannelMessage, caps: &[Capability]) -> Result<(), SyscallError> {
+        channel::send_message(self.cptr, msg, caps, ChannelWriteFlags::EXPECTS_REPLY)
+    }
+
+    /// Send a payload made up of `segments` of existing memory, see
+    /// [`channel::send_segmented`]. The receiver can put it back together with
+    /// [`typed::memory_segments`].
+    pub fn send_segmented(&self, msg: ChannelMessage, segments: &[Segment]) -> Result<(), SyscallError> {
+        channel::send_segmented(self.cptr, msg, segments, ChannelWriteFlags::NONE)
     }
 }
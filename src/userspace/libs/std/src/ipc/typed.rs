@@ -0,0 +1,186 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Validation of the capabilities received with a message
+//!
+//! Messages usually carry their payload in a memory capability as the first
+//! capability, or hand over a channel to talk on. These helpers check that the
+//! first capability is what's expected, so receivers can bail out with an
+//! [`IpcError`] instead of matching on the [`CapabilityDescription`] by hand.
+
+use super::{CapabilityDescription, CapabilityPtr, CapabilityWithDescription};
+use librust::syscalls::mem::MemoryPermissions;
+
+/// The kind of resource a capability refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapabilityKind {
+    Channel,
+    Memory,
+    MappedMmio,
+    SystemReset,
+    KernelLog,
+}
+
+impl CapabilityKind {
+    pub fn of(description: &CapabilityDescription) -> Self {
+        match description {
+            CapabilityDescription::Channel => Self::Channel,
+            CapabilityDescription::Memory { .. } => Self::Memory,
+            CapabilityDescription::MappedMmio { .. } => Self::MappedMmio,
+            CapabilityDescription::SystemReset => Self::SystemReset,
+            CapabilityDescription::KernelLog => Self::KernelLog,
+        }
+    }
+}
+
+/// The reason a received capability wasn't the one expected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpcError {
+    /// The message didn't come with any capabilities
+    MissingCapability,
+    /// The capability refers to a different kind of resource
+    WrongKind { expected: CapabilityKind, found: CapabilityKind },
+    /// The memory capability doesn't allow everything that was required
+    InsufficientPermissions { required: MemoryPermissions, found: MemoryPermissions },
+}
+
+impl core::fmt::Display for IpcError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            IpcError::MissingCapability => write!(f, "message is missing a capability"),
+            IpcError::WrongKind { expected, found } => {
+                write!(f, "expected a {:?} capability, found a {:?} capability", expected, found)
+            }
+            IpcError::InsufficientPermissions { required, found } => write!(
+                f,
+                "memory capability has permissions {:#b}, but {:#b} are required",
+                found.value(),
+                required.value()
+            ),
+        }
+    }
+}
+
+/// Expect the first capability in `caps` to be memory with at least the
+/// `required` permissions, returning the memory it refers to
+pub fn expect_memory_cap(caps: &[CapabilityWithDescription], required: MemoryPermissions) -> Result<&[u8], IpcError> {
+    match first(caps)?.description {
+        CapabilityDescription::Memory { ptr, len, permissions } => match permissions & required {
+            // SAFETY: the kernel maps the memory when it hands over the
+            // capability and describes where it was put, so it stays valid for
+            // as long as the capability is held
+            true => Ok(unsafe { core::slice::from_raw_parts(ptr, len) }),
+            false => Err(IpcError::InsufficientPermissions { required, found: permissions }),
+        },
+        ref description => {
+            Err(IpcError::WrongKind { expected: CapabilityKind::Memory, found: CapabilityKind::of(description) })
+        }
+    }
+}
+
+/// Iterate over the memory in `caps` in order, as sent by
+/// [`super::IpcChannel::send_segmented`], stopping at the first capability
+/// which isn't readable memory
+pub fn memory_segments(caps: &[CapabilityWithDescription]) -> impl Iterator<Item = Result<&[u8], IpcError>> + '_ {
+    let mut done = false;
+    caps.iter().map_while(move |cap| {
+        if done {
+            return None;
+        }
+
+        let segment = expect_memory_cap(core::slice::from_ref(cap), MemoryPermissions::READ);
+        done = segment.is_err();
+        Some(segment)
+    })
+}
+
+/// Expect the first capability in `caps` to be a channel, returning it
+pub fn expect_channel_cap(caps: &[CapabilityWithDescription]) -> Result<CapabilityPtr, IpcError> {
+    let cap = first(caps)?;
+    match cap.description {
+        CapabilityDescription::Channel => Ok(cap.capability.cptr),
+        ref description => {
+            Err(IpcError::WrongKind { expected: CapabilityKind::Channel, found: CapabilityKind::of(description) })
+        }
+    }
+}
+
+fn first(caps: &[CapabilityWithDescription]) -> Result<&CapabilityWithDescription, IpcError> {
+    caps.first().ok_or(IpcError::MissingCapability)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::{Capability, CapabilityRights};
+
+    fn cap(cptr: usize, description: CapabilityDescription) -> CapabilityWithDescription {
+        CapabilityWithDescription {
+            capability: Capability { cptr: CapabilityPtr::new(cptr), rights: CapabilityRights::READ },
+            description,
+        }
+    }
+
+    #[test_case]
+    fn memory_caps() {
+        let mut buffer = [1u8, 2, 3, 4];
+        let ptr = buffer.as_mut_ptr();
+        let memory = |permissions| cap(1, CapabilityDescription::Memory { ptr, len: 4, permissions });
+
+        let caps = [memory(MemoryPermissions::READ_WRITE), cap(2, CapabilityDescription::Channel)];
+        assert_eq!(expect_memory_cap(&caps, MemoryPermissions::READ_WRITE), Ok(&[1, 2, 3, 4][..]));
+        assert_eq!(expect_memory_cap(&caps, MemoryPermissions::READ), Ok(&[1, 2, 3, 4][..]));
+
+        let caps = [memory(MemoryPermissions::READ)];
+        assert_eq!(
+            expect_memory_cap(&caps, MemoryPermissions::READ_WRITE),
+            Err(IpcError::InsufficientPermissions {
+                required: MemoryPermissions::READ_WRITE,
+                found: MemoryPermissions::READ
+            })
+        );
+
+        let caps = [cap(2, CapabilityDescription::Channel), memory(MemoryPermissions::READ_WRITE)];
+        assert_eq!(
+            expect_memory_cap(&caps, MemoryPermissions::READ),
+            Err(IpcError::WrongKind { expected: CapabilityKind::Memory, found: CapabilityKind::Channel })
+        );
+        assert_eq!(expect_memory_cap(&[], MemoryPermissions::READ), Err(IpcError::MissingCapability));
+    }
+
+    #[test_case]
+    fn segments_in_order() {
+        let (mut first, mut second) = (*b"hello, ", *b"world!");
+        let memory = |buffer: &mut [u8]| {
+            let (ptr, len) = (buffer.as_mut_ptr(), buffer.len());
+            cap(1, CapabilityDescription::Memory { ptr, len, permissions: MemoryPermissions::READ })
+        };
+
+        let caps = [memory(&mut first), memory(&mut second)];
+        let payload: Vec<u8> = memory_segments(&caps).flat_map(Result::unwrap).copied().collect();
+        assert_eq!(payload, b"hello, world!");
+
+        let caps = [memory(&mut first), cap(2, CapabilityDescription::Channel), memory(&mut second)];
+        let mut segments = memory_segments(&caps);
+        assert_eq!(segments.next(), Some(Ok(&b"hello, "[..])));
+        assert!(matches!(segments.next(), Some(Err(IpcError::WrongKind { .. }))));
+        assert_eq!(segments.next(), None);
+    }
+
+    #[test_case]
+    fn channel_caps() {
+        let caps = [cap(7, CapabilityDescription::Channel)];
+        assert_eq!(expect_channel_cap(&caps), Ok(CapabilityPtr::new(7)));
+
+        let caps = [cap(7, CapabilityDescription::MappedMmio { ptr: core::ptr::null_mut(), len: 0, n_interrupts: 0 })];
+        assert_eq!(
+            expect_channel_cap(&caps),
+            Err(IpcError::WrongKind { expected: CapabilityKind::Channel, found: CapabilityKind::MappedMmio })
+        );
+        assert_eq!(expect_channel_cap(&[]), Err(IpcError::MissingCapability));
+    }
+}
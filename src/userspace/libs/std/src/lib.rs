@@ -20,25 +20,33 @@
 )]
 #![no_std]
 #![allow(incomplete_features)]
+#![cfg_attr(
+    test,
+    feature(custom_test_frameworks),
+    test_runner(crate::test::runner),
+    reexport_test_harness_main = "test_main"
+)]
 
+pub mod bench;
+pub mod collections;
 pub mod env;
 pub mod heap;
 pub mod io;
 pub mod ipc;
+pub mod path;
 pub mod prelude;
+pub mod process;
 pub mod rc;
 pub mod rt;
 pub mod sync;
 pub mod task;
 mod task_local;
+pub mod test;
+pub mod time;
 pub mod alloc {
     extern crate alloc;
     pub use alloc::alloc::*;
 }
-pub mod collections {
-    extern crate alloc;
-    pub use alloc::collections::*;
-}
 pub mod string {
     extern crate alloc;
     pub use alloc::string::*;
@@ -74,17 +82,13 @@ macro_rules! dbg {
 
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
-    use core::fmt::Write;
-    // FIXME: hack rn to make output less wacky
-    // let out = args.to_string();
-    // let _ = io::Stdout.write_str(&out);
-    let _ = io::Stdout.write_fmt(args);
+    io::write_stdout(args);
 }
 
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
     println!("PANIC: {}", info);
-    librust::syscalls::task::exit()
+    process::exit(process::PANIC_EXIT_CODE)
 }
 
 #[alloc_error_handler]
@@ -0,0 +1,378 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! `/`-separated filesystem paths, like those taken by the filesystem server
+//!
+//! Unlike the paths in Rust's `std`, these are always valid UTF-8 and there's
+//! only one separator, so [`Path`] is a thin wrapper around [`str`].
+
+extern crate alloc;
+
+use alloc::borrow::{Borrow, ToOwned};
+
+const SEPARATOR: char = '/';
+
+/// A single part of a [`Path`], see [`Path::components`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component<'a> {
+    /// The leading `/` of an absolute path
+    RootDir,
+    /// `.`
+    CurDir,
+    /// `..`
+    ParentDir,
+    /// Anything else, e.g. a file or directory name
+    Normal(&'a str),
+}
+
+impl<'a> Component<'a> {
+    /// The component as it appears in a path
+    pub fn as_str(self) -> &'a str {
+        match self {
+            Component::RootDir => "/",
+            Component::CurDir => ".",
+            Component::ParentDir => "..",
+            Component::Normal(name) => name,
+        }
+    }
+}
+
+/// A borrowed path, the [`str`] to [`PathBuf`]'s [`String`]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct Path(str);
+
+impl Path {
+    pub const fn new(path: &str) -> &Self {
+        // Safety: `Path` is `repr(transparent)` over `str`
+        unsafe { &*(path as *const str as *const Self) }
+    }
+
+    pub const fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.0.starts_with(SEPARATOR)
+    }
+
+    /// The components of the path, skipping any redundant separators. `.` and
+    /// `..` are kept as they are, use [`Path::normalize`] to resolve them.
+    pub fn components(&self) -> Components<'_> {
+        Components { root: self.is_absolute(), parts: self.0.split(SEPARATOR) }
+    }
+
+    /// The path without its last component, or `None` if the path is empty or
+    /// only the root. The parent of a single relative component is the empty
+    /// path.
+    pub fn parent(&self) -> Option<&Self> {
+        let trimmed = self.0.trim_end_matches(SEPARATOR);
+        if trimmed.is_empty() {
+            return None;
+        }
+
+        let parent = match trimmed.rsplit_once(SEPARATOR) {
+            Some((parent, _)) => parent.trim_end_matches(SEPARATOR),
+            None => "",
+        };
+
+        match parent.is_empty() && self.is_absolute() {
+            true => Some(Self::new(&self.0[..1])),
+            false => Some(Self::new(parent)),
+        }
+    }
+
+    /// The last component of the path, if it's a [`Component::Normal`]
+    pub fn file_name(&self) -> Option<&str> {
+        match self.components().next_back()? {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Append `path` to this path with a single separator between them. If
+    /// `path` is absolute, it replaces this path instead. See
+    /// [`PathBuf::push`].
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        let mut joined = self.to_path_buf();
+        joined.push(path);
+        joined
+    }
+
+    /// Resolve `.` and `..` components and remove redundant separators without
+    /// looking at the filesystem. `..` at the root stays at the root, while
+    /// leading `..`s of relative paths are kept. An empty result is `.` for
+    /// relative paths.
+    pub fn normalize(&self) -> PathBuf {
+        let mut normal: Vec<&str> = Vec::new();
+        for component in self.components() {
+            match component {
+                Component::RootDir | Component::CurDir => {}
+                Component::ParentDir => match normal.last() {
+                    Some(&"..") | None if !self.is_absolute() => normal.push(".."),
+                    Some(&"..") | None => {}
+                    Some(_) => {
+                        normal.pop();
+                    }
+                },
+                Component::Normal(name) => normal.push(name),
+            }
+        }
+
+        let mut path = String::with_capacity(self.0.len());
+        if self.is_absolute() {
+            path.push(SEPARATOR);
+        }
+
+        for (i, component) in normal.iter().enumerate() {
+            if i != 0 {
+                path.push(SEPARATOR);
+            }
+
+            path.push_str(component);
+        }
+
+        if path.is_empty() {
+            path.push('.');
+        }
+
+        PathBuf(path)
+    }
+
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf(String::from(&self.0))
+    }
+}
+
+impl core::fmt::Display for Path {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<Path> for Path {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for str {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<Path> for String {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<str> for Path {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ToOwned for Path {
+    type Owned = PathBuf;
+
+    fn to_owned(&self) -> Self::Owned {
+        self.to_path_buf()
+    }
+}
+
+/// An iterator over the [`Component`]s of a [`Path`]
+#[derive(Debug, Clone)]
+pub struct Components<'a> {
+    root: bool,
+    parts: core::str::Split<'a, char>,
+}
+
+fn component(part: &str) -> Option<Component<'_>> {
+    match part {
+        "" => None,
+        "." => Some(Component::CurDir),
+        ".." => Some(Component::ParentDir),
+        name => Some(Component::Normal(name)),
+    }
+}
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Component<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if core::mem::take(&mut self.root) {
+            return Some(Component::RootDir);
+        }
+
+        self.parts.by_ref().find_map(component)
+    }
+}
+
+impl DoubleEndedIterator for Components<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.parts.by_ref().rev().find_map(component) {
+            Some(component) => Some(component),
+            None => core::mem::take(&mut self.root).then_some(Component::RootDir),
+        }
+    }
+}
+
+/// An owned, growable path
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PathBuf(String);
+
+impl PathBuf {
+    pub const fn new() -> Self {
+        Self(String::new())
+    }
+
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.0)
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    /// Append `path`, adding a separator if there isn't already one at the end
+    /// of this path. An absolute `path` replaces this path instead.
+    pub fn push(&mut self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        match path.is_absolute() {
+            true => self.0.replace_range(.., path.as_str()),
+            false => {
+                if !self.0.is_empty() && !self.0.ends_with(SEPARATOR) {
+                    self.0.push(SEPARATOR);
+                }
+
+                self.0.push_str(path.as_str());
+            }
+        }
+    }
+
+    /// Truncate this path to its [`Path::parent`], returning `false` and
+    /// leaving it alone if there isn't one
+    pub fn pop(&mut self) -> bool {
+        match self.as_path().parent().map(|parent| parent.0.len()) {
+            Some(len) => {
+                self.0.truncate(len);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl core::ops::Deref for PathBuf {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_path()
+    }
+}
+
+impl core::fmt::Display for PathBuf {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl AsRef<Path> for PathBuf {
+    fn as_ref(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl Borrow<Path> for PathBuf {
+    fn borrow(&self) -> &Path {
+        self.as_path()
+    }
+}
+
+impl From<&str> for PathBuf {
+    fn from(path: &str) -> Self {
+        Self(String::from(path))
+    }
+}
+
+impl From<String> for PathBuf {
+    fn from(path: String) -> Self {
+        Self(path)
+    }
+}
+
+impl From<&Path> for PathBuf {
+    fn from(path: &Path) -> Self {
+        path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn join() {
+        assert_eq!(Path::new("/bin").join("init").as_str(), "/bin/init");
+        assert_eq!(Path::new("/bin/").join("init").as_str(), "/bin/init");
+        assert_eq!(Path::new("").join("init").as_str(), "init");
+        assert_eq!(Path::new("/bin").join("sbin/.."), PathBuf::from("/bin/sbin/.."));
+
+        // Absolute paths start over from the root
+        assert_eq!(Path::new("/bin").join("/etc/fstab").as_str(), "/etc/fstab");
+
+        let mut path = PathBuf::from("/usr/lib/");
+        path.push(String::from("libc.so"));
+        assert_eq!(path.as_str(), "/usr/lib/libc.so");
+        assert!(path.pop());
+        assert_eq!(path.as_str(), "/usr/lib");
+    }
+
+    #[test_case]
+    fn normalize() {
+        assert_eq!(Path::new("a//b/../c").normalize().as_str(), "a/c");
+        assert_eq!(Path::new("/./a/b/c/../../d/").normalize().as_str(), "/a/d");
+        assert_eq!(Path::new("//a").normalize().as_str(), "/a");
+
+        // There's nothing above the root, but relative paths can go up
+        assert_eq!(Path::new("/../a").normalize().as_str(), "/a");
+        assert_eq!(Path::new("../../a/..").normalize().as_str(), "../..");
+        assert_eq!(Path::new("a/..").normalize().as_str(), ".");
+        assert_eq!(Path::new("/a/..").normalize().as_str(), "/");
+    }
+
+    #[test_case]
+    fn components() {
+        let path = Path::new("/usr//lib/./libc.so");
+        assert_eq!(
+            path.components().collect::<Vec<_>>(),
+            [
+                Component::RootDir,
+                Component::Normal("usr"),
+                Component::Normal("lib"),
+                Component::CurDir,
+                Component::Normal("libc.so")
+            ]
+        );
+        assert_eq!(path.components().rev().nth(4), Some(Component::RootDir));
+        assert_eq!(Path::new("/").components().next_back(), Some(Component::RootDir));
+
+        assert_eq!(path.file_name(), Some("libc.so"));
+        assert_eq!(Path::new("usr/lib/").file_name(), Some("lib"));
+        assert_eq!(Path::new("usr/..").file_name(), None);
+        assert_eq!(Path::new("/").file_name(), None);
+
+        assert_eq!(path.parent(), Some(Path::new("/usr//lib/.")));
+        assert_eq!(Path::new("/usr//lib/").parent(), Some(Path::new("/usr")));
+        assert_eq!(Path::new("/usr").parent(), Some(Path::new("/")));
+        assert_eq!(Path::new("usr").parent(), Some(Path::new("")));
+        assert_eq!(Path::new("/").parent(), None);
+        assert_eq!(Path::new("").parent(), None);
+    }
+}
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::ipc::{ChannelReadFlags, IpcChannel};
+use librust::{capabilities::CapabilityPtr, error::SyscallError};
+
+/// The first word of the message a task sends over its exit channel when it
+/// exits, followed by the exit code
+pub const EXIT_MESSAGE_TAG: usize = usize::from_le_bytes(*b"vexit\0\0\0");
+
+/// The exit code used when a task panics
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+/// The status a child task exited with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus(i32);
+
+impl ExitStatus {
+    pub const fn code(self) -> i32 {
+        self.0
+    }
+
+    pub const fn success(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// Flush stdout, report `code` to the task's parent, and exit
+pub fn exit(code: i32) -> ! {
+    crate::io::flush();
+
+    // The parent may not be waiting on us, so don't block or fail if the
+    // message can't be delivered. `init` was started by the kernel and has
+    // nobody to report to.
+    #[cfg(not(feature = "init"))]
+    {
+        use crate::ipc::{ChannelMessage, ChannelWriteFlags};
+        use librust::syscalls::channel::{self, EXIT_CHANNEL};
+
+        let mut message = ChannelMessage::default();
+        message.0[0] = EXIT_MESSAGE_TAG;
+        message.0[1] = code as usize;
+
+        let _ = channel::send_message(EXIT_CHANNEL, message, &[], ChannelWriteFlags::NONBLOCKING);
+    }
+
+    #[cfg(feature = "init")]
+    let _ = code;
+
+    librust::syscalls::task::exit()
+}
+
+/// A handle to a spawned task. The channel the child reports its exit status
+/// on is deleted when the handle is dropped.
+#[derive(Debug)]
+pub struct Child {
+    channel: CapabilityPtr,
+    exit_channel: CapabilityPtr,
+}
+
+impl Child {
+    /// Create a [`Child`] from the channels returned when spawning a vmspace,
+    /// see [`crate::vmspace::Vmspace::spawn_child`]
+    pub fn new(channel: CapabilityPtr, exit_channel: CapabilityPtr) -> Self {
+        Self { channel, exit_channel }
+    }
+
+    /// The channel to the child task, which is left untouched by
+    /// [`Child::wait`]
+    pub fn channel(&self) -> CapabilityPtr {
+        self.channel
+    }
+
+    /// Block until the child exits, returning its exit status. Fails with
+    /// [`SyscallError::InvalidArgument`] if the child sent something other
+    /// than its exit status on its exit channel.
+    pub fn wait(self) -> Result<ExitStatus, SyscallError> {
+        let (message, _) = IpcChannel::new(self.exit_channel).read_with_all_caps(ChannelReadFlags::NONE)?;
+
+        match message.0[0] {
+            EXIT_MESSAGE_TAG => Ok(ExitStatus(message.0[1] as i32)),
+            _ => Err(SyscallError::InvalidArgument(0)),
+        }
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        // Safety: the exit channel is only ever used through this `Child`
+        let _ = unsafe { librust::syscalls::capabilities::delete(self.exit_channel) };
+    }
+}
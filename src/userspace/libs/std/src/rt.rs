@@ -35,15 +35,29 @@ unsafe extern "C" fn _start() -> ! {
 
 #[no_mangle]
 #[cfg_attr(feature = "init", link_section = ".rt.entry")]
-unsafe extern "C" fn _rust_start(argc: isize, argv: *const *const u8, a2: usize) -> ! {
+unsafe extern "C" fn _rust_start(argc: isize, argv: *const *const u8, a2: usize, a3: usize, a4: usize) -> ! {
+    #[cfg(not(test))]
     extern "C" {
         fn main(_: isize, _: *const *const u8) -> isize;
     }
 
+    // When `std` is the test binary, the `main` generated for the test harness
+    // is part of this crate, and declaring it here would clash with it
+    #[cfg(test)]
+    unsafe fn main(argc: isize, argv: *const *const u8) -> isize {
+        lang_start(crate::test_main, argc, argv, 0)
+    }
+
     A2 = a2;
+    #[cfg(feature = "init")]
+    {
+        crate::env::INITRD = [a3, a4];
+    }
+    #[cfg(not(feature = "init"))]
+    let _ = (a3, a4);
 
-    main(argc, argv);
-    librust::syscalls::task::exit()
+    let code = main(argc, argv);
+    crate::process::exit(code as i32)
 }
 
 extern "C" {
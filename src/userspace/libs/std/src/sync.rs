@@ -10,6 +10,8 @@ extern crate alloc;
 pub use alloc::sync::*;
 pub use core::sync::*;
 
+pub mod mpsc;
+
 /// A [`core::cell::RefCell`] that implements `Send` and `Sync` to be suitable
 /// for use in `static`s.
 #[derive(Debug)]
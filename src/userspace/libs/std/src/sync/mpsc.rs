@@ -0,0 +1,200 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Multi-producer, single-consumer channels
+//!
+//! Items are queued on a lock-free [`MpscQueue`], so sending never blocks.
+//! There's no way to be woken up by a sender yet, so a receiver waiting on an
+//! empty channel yields to the scheduler between checks.
+
+use crate::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use ::collections::mpsc_queue::{MpscQueue, Node, TryPop};
+use core::{marker::PhantomData, ptr::NonNull};
+
+/// Create a new channel, returning its sending and receiving halves. The
+/// [`Sender`] can be cloned to send from multiple places, while there is only
+/// ever one [`Receiver`].
+pub fn channel<T: Send>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: MpscQueue::new(),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+
+    (Sender { shared: Arc::clone(&shared) }, Receiver { shared, _not_sync: PhantomData })
+}
+
+struct Shared<T> {
+    queue: MpscQueue<Box<T>>,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        // Both halves are gone, so nothing can be pushing concurrently
+        while let Some(node) = unsafe { self.queue.pop() } {
+            drop(unsafe { Box::from_raw(node.as_ptr()) });
+        }
+    }
+}
+
+/// The error returned by [`Sender::send`] when the [`Receiver`] has been
+/// dropped, containing the value that couldn't be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+/// The error returned by [`Receiver::recv`] when the channel is empty and every
+/// [`Sender`] has been dropped, so nothing else can arrive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+/// The error returned by [`Receiver::try_recv`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// The channel is empty, but a [`Sender`] may still send to it
+    Empty,
+    /// The channel is empty and every [`Sender`] has been dropped
+    Disconnected,
+}
+
+/// The sending half of a channel, see [`channel`]
+pub struct Sender<T: Send> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T: Send> Sender<T> {
+    /// Send a value to the [`Receiver`], giving it back if the receiver has
+    /// been dropped
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if !self.shared.receiver_alive.load(Ordering::Acquire) {
+            return Err(SendError(value));
+        }
+
+        let node = NonNull::from(Box::leak(Box::new(Node::new(Box::new(value)))));
+        // SAFETY: the node was just allocated and is owned by the queue until
+        // it's popped, at which point it's turned back into a `Box`
+        unsafe { self.shared.queue.push(node) };
+
+        Ok(())
+    }
+}
+
+impl<T: Send> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self { shared: Arc::clone(&self.shared) }
+    }
+}
+
+impl<T: Send> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The receiving half of a channel, see [`channel`]
+pub struct Receiver<T: Send> {
+    shared: Arc<Shared<T>>,
+    /// The queue only supports a single consumer, so the receiver can be sent
+    /// elsewhere but not shared
+    _not_sync: PhantomData<core::cell::Cell<()>>,
+}
+
+impl<T: Send> Receiver<T> {
+    /// Receive the next value, waiting for one to be sent if the channel is
+    /// empty. Returns [`RecvError`] once the channel is empty and every
+    /// [`Sender`] has been dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        loop {
+            match self.try_recv() {
+                Ok(value) => return Ok(value),
+                Err(TryRecvError::Disconnected) => return Err(RecvError),
+                Err(TryRecvError::Empty) => {
+                    let _ = librust::syscalls::task::yield_now();
+                }
+            }
+        }
+    }
+
+    /// Receive the next value if there is one, without waiting
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        if let Some(value) = self.pop() {
+            return Ok(value);
+        }
+
+        if self.shared.senders.load(Ordering::Acquire) != 0 {
+            return Err(TryRecvError::Empty);
+        }
+
+        // The last sender may have sent a value right before it was dropped
+        self.pop().ok_or(TryRecvError::Disconnected)
+    }
+
+    /// An iterator which receives values until every [`Sender`] has been
+    /// dropped, see [`Receiver::recv`]
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        core::iter::from_fn(|| self.recv().ok())
+    }
+
+    fn pop(&self) -> Option<T> {
+        // SAFETY: `Receiver` isn't `Sync` or `Clone`, so this is the only
+        // consumer of the queue
+        match unsafe { self.shared.queue.try_pop() } {
+            TryPop::Node(node) => Some(*unsafe { Box::from_raw(node.as_ptr()) }.value),
+            // A send is part way through, so treat the channel as empty until
+            // it finishes
+            TryPop::Empty | TryPop::Inconsistent => None,
+        }
+    }
+}
+
+impl<T: Send> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn producer_consumer() {
+        let (tx, rx) = channel();
+        let tx2 = tx.clone();
+
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        for i in 0..3 {
+            tx.send(i).unwrap();
+            tx2.send(i + 100).unwrap();
+        }
+
+        assert_eq!(rx.recv(), Ok(0));
+        assert_eq!(rx.try_recv(), Ok(100));
+
+        // Values sent before the last sender is dropped are still received
+        drop(tx);
+        drop(tx2);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), [1, 101, 2, 102]);
+        assert_eq!(rx.recv(), Err(RecvError));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test_case]
+    fn receiver_dropped() {
+        let (tx, rx) = channel();
+        tx.send(String::from("queued")).unwrap();
+        drop(rx);
+
+        assert_eq!(tx.send(String::from("lost")), Err(SendError(String::from("lost"))));
+    }
+}
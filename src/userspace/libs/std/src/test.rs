@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A test runner for crates that only run on `vanadinite`
+//!
+//! `libtest` isn't available, so crates opt into this runner instead with
+//!
+//! ```ignore
+//! #![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
+//! ```
+//!
+//! and mark their tests with `#[test_case]`. `cargo xtask test_userspace`
+//! builds the resulting test binaries and has `init` run them under QEMU. A
+//! failing test panics, which exits the task with
+//! [`crate::process::PANIC_EXIT_CODE`] and skips the rest of the tests.
+
+/// A test function which prints its name and result
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        print!("test {} ... ", core::any::type_name::<T>());
+        self();
+        println!("ok");
+    }
+}
+
+/// Run every test in `tests` in order
+pub fn runner(tests: &[&dyn Testable]) {
+    println!("running {} tests", tests.len());
+
+    for test in tests {
+        test.run();
+    }
+
+    println!("test result: ok. {} passed", tests.len());
+}
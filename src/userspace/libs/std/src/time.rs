@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+pub use core::time::Duration;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// A measurement of a monotonically non-decreasing clock, backed by the
+/// platform timer. `Instant`s are only meaningful when compared to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// Read the current time
+    pub fn now() -> Self {
+        let reading = librust::syscalls::time::read_time();
+        Self(ticks_to_duration(reading.ticks, reading.frequency))
+    }
+
+    /// The amount of time elapsed since this `Instant` was measured
+    pub fn elapsed(&self) -> Duration {
+        Self::now().saturating_duration_since(*self)
+    }
+
+    /// The amount of time elapsed from `earlier` to `self`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `earlier` is later than `self`
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).expect("supplied instant is later than self")
+    }
+
+    /// The amount of time elapsed from `earlier` to `self`, or `None` if
+    /// `earlier` is later than `self`
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// The amount of time elapsed from `earlier` to `self`, or zero if
+    /// `earlier` is later than `self`
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Self)
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Self)
+    }
+}
+
+/// Ask the kernel to send a [`KernelMessage::TimerExpired`] once `deadline`
+/// has passed, replacing any previously requested notification
+///
+/// [`KernelMessage::TimerExpired`]: librust::syscalls::channel::KernelMessage::TimerExpired
+pub fn notify_at(deadline: Instant) {
+    let frequency = librust::syscalls::time::read_time().frequency;
+    // Zero cancels the timer, and deadlines at zero have already passed anyway
+    librust::syscalls::time::set_timer(duration_to_ticks(deadline.0, frequency).max(1));
+}
+
+/// Cancel a notification requested with [`notify_at`]
+pub fn cancel_notification() {
+    librust::syscalls::time::set_timer(0);
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.checked_add(rhs).expect("overflow when adding duration to instant")
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        self.checked_sub(rhs).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Self::Output {
+        self.duration_since(rhs)
+    }
+}
+
+fn ticks_to_duration(ticks: u64, frequency: u64) -> Duration {
+    // The kernel reports a frequency of zero before the timebase has been
+    // read from the devicetree, which can't happen once userspace is running
+    debug_assert_ne!(frequency, 0);
+    let frequency = u128::from(frequency.max(1));
+    let ticks = u128::from(ticks);
+
+    let secs = ticks / frequency;
+    let nanos = (ticks % frequency) * NANOS_PER_SEC / frequency;
+
+    Duration::new(secs as u64, nanos as u32)
+}
+
+/// Rounds up so a deadline converted to ticks is never early
+fn duration_to_ticks(duration: Duration, frequency: u64) -> u64 {
+    let ticks = (duration.as_nanos() * u128::from(frequency) + NANOS_PER_SEC - 1) / NANOS_PER_SEC;
+    u64::try_from(ticks).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn tick_conversion() {
+        assert_eq!(ticks_to_duration(10_000_000, 10_000_000), Duration::from_secs(1));
+        assert_eq!(ticks_to_duration(15_000_000, 10_000_000), Duration::from_millis(1500));
+        assert_eq!(ticks_to_duration(1, 10_000_000), Duration::from_nanos(100));
+        assert_eq!(ticks_to_duration(u64::MAX, 1_000_000_000), Duration::from_nanos(u64::MAX));
+
+        assert_eq!(duration_to_ticks(Duration::from_secs(1), 10_000_000), 10_000_000);
+        assert_eq!(duration_to_ticks(Duration::from_nanos(150), 10_000_000), 2);
+        assert_eq!(duration_to_ticks(Duration::MAX, 10_000_000), u64::MAX);
+    }
+
+    #[test_case]
+    fn now_is_monotonic() {
+        let first = Instant::now();
+        let second = Instant::now();
+        assert!(second >= first);
+    }
+
+    #[test_case]
+    fn busy_loop_elapses() {
+        let start = Instant::now();
+        let mut counter = 0u64;
+        while start.elapsed() == Duration::ZERO {
+            counter = core::hint::black_box(counter + 1);
+        }
+
+        assert!(start.elapsed() > Duration::ZERO);
+    }
+}
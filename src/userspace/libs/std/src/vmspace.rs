@@ -5,15 +5,16 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+use crate::process::Child;
 use core::marker::PhantomData;
 
 use librust::{
     capabilities::{Capability, CapabilityPtr, CapabilityRights},
     error::SyscallError,
     syscalls::{
-        channel::ChannelMessage,
+        channel::{ChannelMessage, ChannelWriteFlags},
         mem::MemoryPermissions,
-        vmspace::{self, VmspaceObjectId, VmspaceObjectMapping, VmspaceSpawnEnv},
+        vmspace::{self, PageSize, VmspaceObjectId, VmspaceObjectMapping, VmspaceSpawnEnv},
     },
 };
 
@@ -22,6 +23,7 @@ pub struct Vmspace {
     id: VmspaceObjectId,
     names: Vec<String>,
     caps_to_send: Vec<Capability>,
+    args: Vec<String>,
 }
 
 impl Vmspace {
@@ -29,7 +31,7 @@ impl Vmspace {
     pub fn new(name: &str) -> Self {
         let id = vmspace::create_vmspace().unwrap();
 
-        Self { name: name.to_string(), id, names: Vec::new(), caps_to_send: Vec::new() }
+        Self { name: name.to_string(), id, names: Vec::new(), caps_to_send: Vec::new(), args: Vec::new() }
     }
 
     pub fn create_object<'b>(
@@ -38,18 +40,90 @@ impl Vmspace {
         size: usize,
         permissions: MemoryPermissions,
     ) -> Result<VmspaceObject<'b, '_>, SyscallError> {
-        match vmspace::alloc_vmspace_object(self.id, VmspaceObjectMapping { address, size, permissions }) {
+        self.create_object_with_page_size(address, size, permissions, PageSize::Kilo)
+    }
+
+    /// Same as [`Self::create_object`], but maps the object with larger pages
+    /// where possible, see [`VmspaceObjectMapping::page_size`]
+    pub fn create_object_with_page_size<'b>(
+        &self,
+        address: *const u8,
+        size: usize,
+        permissions: MemoryPermissions,
+        page_size: PageSize,
+    ) -> Result<VmspaceObject<'b, '_>, SyscallError> {
+        self.alloc_object(VmspaceObjectMapping { address, size, permissions, page_size, guarded: false, lazy: false })
+    }
+
+    /// Create a zero-filled object whose pages are only allocated once the
+    /// spawned task accesses them, see [`VmspaceObjectMapping::lazy`]. Lazy
+    /// objects aren't mapped into the current task, so
+    /// [`VmspaceObject::as_slice`] is empty.
+    pub fn create_lazy_object<'b>(
+        &self,
+        address: *const u8,
+        size: usize,
+        permissions: MemoryPermissions,
+    ) -> Result<VmspaceObject<'b, '_>, SyscallError> {
+        self.alloc_object(VmspaceObjectMapping {
+            address,
+            size,
+            permissions,
+            page_size: PageSize::Kilo,
+            guarded: false,
+            lazy: true,
+        })
+    }
+
+    /// Create a read-write object to use as a stack, with unmapped guard pages
+    /// on either side so that overflowing it faults instead of corrupting
+    /// neighboring memory. Stacks are lazy objects, so only the pages the
+    /// spawned task actually uses are allocated.
+    pub fn create_stack<'b>(&self, size: usize) -> Result<VmspaceObject<'b, '_>, SyscallError> {
+        self.alloc_object(VmspaceObjectMapping {
+            address: core::ptr::null(),
+            size,
+            permissions: MemoryPermissions::READ | MemoryPermissions::WRITE,
+            page_size: PageSize::Kilo,
+            guarded: true,
+            lazy: true,
+        })
+    }
+
+    fn alloc_object<'b>(&self, mapping: VmspaceObjectMapping) -> Result<VmspaceObject<'b, '_>, SyscallError> {
+        let size = mapping.size;
+        match vmspace::alloc_vmspace_object(self.id, mapping) {
             Ok((ours, theirs)) => Ok(VmspaceObject {
                 vmspace_address: theirs,
-                mapped_memory: unsafe { core::slice::from_raw_parts_mut(ours, size) },
+                mapped_memory: match ours.is_null() {
+                    true => &mut [],
+                    false => unsafe { core::slice::from_raw_parts_mut(ours, size) },
+                },
                 _vmspace: PhantomData,
             }),
             Err(e) => Err(e),
         }
     }
 
+    /// Start a task running in the vmspace, returning the channel to it
     pub fn spawn(self, env: VmspaceSpawnEnv) -> Result<CapabilityPtr, SyscallError> {
-        let task_cptr = vmspace::spawn_vmspace(self.id, &self.name, env)?;
+        self.spawn_child(env).map(|child| child.channel())
+    }
+
+    /// Start a task running in the vmspace, returning a [`Child`] which can
+    /// also wait for it to exit
+    pub fn spawn_child(mut self, env: VmspaceSpawnEnv) -> Result<Child, SyscallError> {
+        let (task_cptr, exit_cptr) = vmspace::spawn_vmspace(self.id, &self.name, env)?;
+
+        if !self.args.is_empty() {
+            let serialized = json::to_bytes(&self.args);
+            let (cptr, ptr) = librust::syscalls::mem::allocate_shared_memory(
+                librust::units::Bytes(serialized.len()),
+                MemoryPermissions::READ | MemoryPermissions::WRITE,
+            )?;
+            unsafe { (*ptr)[..serialized.len()].copy_from_slice(&serialized) };
+            self.grant(crate::env::ARGS_CAPABILITY, cptr, CapabilityRights::READ);
+        }
 
         // FIXME: this is an inlined version of `temp_send_json`, replace this!
         let serialized = json::to_bytes(&self.names);
@@ -63,20 +137,32 @@ impl Vmspace {
                 task_cptr,
                 ChannelMessage::default(),
                 &[Capability { cptr, rights: CapabilityRights::READ }],
+                ChannelWriteFlags::NONE,
             )?;
         } else {
             let mut all_caps = vec![Capability { cptr, rights: CapabilityRights::READ }];
             all_caps.extend_from_slice(&self.caps_to_send);
-            librust::syscalls::channel::send_message(task_cptr, ChannelMessage::default(), &all_caps)?;
+            librust::syscalls::channel::send_message(
+                task_cptr,
+                ChannelMessage::default(),
+                &all_caps,
+                ChannelWriteFlags::NONE,
+            )?;
         }
 
-        Ok(task_cptr)
+        Ok(Child::new(task_cptr, exit_cptr))
     }
 
     pub fn grant(&mut self, name: &str, cptr: CapabilityPtr, rights: CapabilityRights) {
         self.names.push(name.into());
         self.caps_to_send.push(Capability { cptr, rights });
     }
+
+    /// Set the arguments that the spawned task will receive from
+    /// [`crate::env::args`]
+    pub fn set_args<'a>(&mut self, args: impl IntoIterator<Item = &'a str>) {
+        self.args = args.into_iter().map(String::from).collect();
+    }
 }
 
 #[derive(Debug)]
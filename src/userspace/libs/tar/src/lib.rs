@@ -28,24 +28,86 @@ impl<'a> Archive<'a> {
 
     pub fn files(&self) -> impl Iterator<Item = File<'a>> + '_ {
         let mut archive_index = 0;
-        core::iter::from_fn(move || {
-            if let Some(header) = self.data.get(archive_index..).and_then(FileHeader::from_bytes) {
-                let content_start = archive_index;
-                let content_end = content_start + header.file_size;
-                let mut content_padding = 512 - (header.file_size % 512);
-                if content_end % 512 != 0 {
-                    content_padding += 512;
-                }
-                archive_index = content_end + content_padding;
-
-                return Some(File { metadata: header, contents: self.data.get(content_start + 512..content_end)? });
+        let mut global_overrides = PaxOverrides::default();
+        let mut next_overrides = PaxOverrides::default();
+
+        core::iter::from_fn(move || loop {
+            let mut header = self.data.get(archive_index..).and_then(FileHeader::from_bytes)?;
+
+            if !matches!(header.type_flag, TypeFlag::GlobalExtendedHeader | TypeFlag::NextFileExtendedHeader) {
+                global_overrides.apply(&mut header);
+                core::mem::take(&mut next_overrides).apply(&mut header);
             }
 
-            None
+            let content_start = archive_index + 512;
+            let content_end = content_start.checked_add(header.file_size)?;
+            archive_index = content_start + round_up_to_block(header.file_size)?;
+
+            let contents = self.data.get(content_start..content_end)?;
+            match header.type_flag {
+                TypeFlag::GlobalExtendedHeader => global_overrides.parse_records(contents)?,
+                TypeFlag::NextFileExtendedHeader => next_overrides.parse_records(contents)?,
+                _ => return Some(File { metadata: header, contents }),
+            }
         })
     }
 }
 
+fn round_up_to_block(size: usize) -> Option<usize> {
+    Some(size.checked_add(511)? & !511)
+}
+
+/// Header values overridden by the records of a PAX extended header, which
+/// apply either to the next file (`x`) or every file following it (`g`)
+#[derive(Debug, Default, Clone, Copy)]
+struct PaxOverrides<'a> {
+    path: Option<&'a str>,
+    linkpath: Option<&'a str>,
+    size: Option<usize>,
+}
+
+impl<'a> PaxOverrides<'a> {
+    /// Parse the `<length> <key>=<value>\n` records of an extended header,
+    /// where `length` is the length of the entire record in decimal. Records
+    /// with unknown keys are ignored.
+    fn parse_records(&mut self, mut records: &'a [u8]) -> Option<()> {
+        while !records.is_empty() {
+            let space = records.iter().position(|b| *b == b' ')?;
+            let length = from_utf8(&records[..space])?.parse::<usize>().ok()?;
+            let record = records.get(space + 1..length)?;
+            records = &records[length..];
+
+            let (&b'\n', record) = record.split_last()? else { return None };
+            let equals = record.iter().position(|b| *b == b'=')?;
+            let (key, value) = (&record[..equals], &record[equals + 1..]);
+
+            match key {
+                b"path" => self.path = Some(from_utf8(value)?),
+                b"linkpath" => self.linkpath = Some(from_utf8(value)?),
+                b"size" => self.size = Some(from_utf8(value)?.parse().ok()?),
+                _ => {}
+            }
+        }
+
+        Some(())
+    }
+
+    fn apply(&self, header: &mut FileHeader<'a>) {
+        if let Some(path) = self.path {
+            header.filename = path;
+            header.file_name_prefix = "";
+        }
+
+        if let Some(linkpath) = self.linkpath {
+            header.linked_file_name = linkpath;
+        }
+
+        if let Some(size) = self.size {
+            header.file_size = size;
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct File<'a> {
     pub metadata: FileHeader<'a>,
@@ -140,9 +202,14 @@ fn from_utf8(bytes: &[u8]) -> Option<&str> {
     core::str::from_utf8(bytes).ok()
 }
 
+/// Numeric fields may be padded with spaces, and are left empty (all NULs) when
+/// they don't apply, e.g. the device numbers of a regular file
 fn from_octal_str(bytes: &[u8]) -> Option<usize> {
     let nul = bytes.iter().copied().position(|b| b == b'\0').unwrap_or(bytes.len());
-    usize::from_str_radix(from_utf8(&bytes[..nul])?, 8).ok()
+    match from_utf8(&bytes[..nul])?.trim_matches(' ') {
+        "" => Some(0),
+        digits => usize::from_str_radix(digits, 8).ok(),
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -184,3 +251,48 @@ pub enum TarError {
     InvalidArchive,
     BadOctalString,
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    // Created with Python's `tarfile` using `format=PAX_FORMAT`, and contains a
+    // global header with a `comment` record, followed by a file with a 152
+    // byte long path and a file with a short path
+    static PAX_TAR: &[u8] = include_bytes!("../testdata/pax.tar");
+
+    const LONG_PATH: &str = "servers/a-very-long-directory-name-that-does-not-fit/a-very-long-directory-name-that-does-not-fit/a-very-long-directory-name-that-does-not-fit/hello.txt";
+
+    #[test]
+    fn pax_long_path() {
+        let archive = Archive::new(PAX_TAR).unwrap();
+        let files = archive.files().collect::<Vec<_>>();
+
+        // Extended headers aren't exposed as files
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].metadata.filename, LONG_PATH);
+        assert_eq!(files[0].contents, b"hello from a long path\n");
+
+        // `x` headers only apply to the following file
+        assert_eq!(files[1].metadata.filename, "short.txt");
+        assert_eq!(files[1].contents, b"short\n");
+
+        assert_eq!(archive.file(LONG_PATH).unwrap().contents, b"hello from a long path\n");
+    }
+
+    #[test]
+    fn pax_records() {
+        let mut overrides = PaxOverrides::default();
+        overrides.parse_records(b"19 linkpath=target\n13 size=1024\n22 comment=vanadinite\n").unwrap();
+
+        assert_eq!(overrides.path, None);
+        assert_eq!(overrides.linkpath, Some("target"));
+        assert_eq!(overrides.size, Some(1024));
+
+        // The record length doesn't match where the record ends
+        assert_eq!(PaxOverrides::default().parse_records(b"12 size=1024\n"), None);
+    }
+}
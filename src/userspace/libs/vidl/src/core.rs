@@ -17,6 +17,7 @@ pub type U128 = u128;
 pub type I128 = i128;
 pub type USize = usize;
 pub type ISize = isize;
+pub type Bool = bool;
 
 pub type Unit = ();
 
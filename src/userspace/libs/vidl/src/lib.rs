@@ -23,6 +23,7 @@ pub use librust::{
 };
 
 pub mod core;
+pub mod stream;
 pub mod sync;
 pub mod materialize {
     pub use materialize::*;
@@ -40,5 +41,38 @@ pub mod internal {
         channel::{read_kernel_message, ChannelReadFlags, KernelMessage},
         mem::MemoryPermissions,
     };
-    pub use std::ipc::IpcChannel;
+    pub use std::ipc::{typed::expect_memory_cap, IpcChannel};
+
+    use crate::materialize::{Serializable, Serialize, SizeHint};
+    use librust::{capabilities::Capability, error::SyscallError, mem::SharedMemoryAllocation, units::Bytes};
+
+    /// Serialize `value` into a new shared memory allocation, returning it
+    /// along with the capabilities referenced by the value. Values with a fixed
+    /// size are serialized straight into the allocation, while others are
+    /// serialized on the heap first to find out how large it needs to be.
+    pub fn serialize_to_shared_memory<T: Serialize + ?Sized>(
+        value: &T,
+    ) -> Result<(SharedMemoryAllocation, std::vec::Vec<Capability>), SyscallError> {
+        match T::serialized_size_hint() {
+            SizeHint::Fixed(size) => {
+                let mut mem = SharedMemoryAllocation::public_rw(Bytes(size))?;
+                let caps = {
+                    let mut serializer = crate::materialize::Serializer::with_buffer(unsafe { mem.as_mut() });
+                    serializer.serialize(value).unwrap();
+                    serializer.into_capabilities()
+                };
+
+                Ok((mem, caps))
+            }
+            SizeHint::Variable { .. } => {
+                let mut serializer = crate::materialize::Serializer::new();
+                serializer.serialize(value).unwrap();
+                let (buffer, caps) = serializer.into_parts();
+                let mut mem = SharedMemoryAllocation::public_rw(Bytes(buffer.len()))?;
+                unsafe { mem.as_mut()[..buffer.len()].copy_from_slice(&buffer) };
+
+                Ok((mem, caps))
+            }
+        }
+    }
 }
@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Support for methods with a `stream<T>` return type
+//!
+//! Instead of a single reply, the server sends any number of item messages
+//! followed by one terminal message. Both have the method ID in the first word
+//! of the [`ChannelMessage`] and the message kind in the second: item messages
+//! ([`STREAM_ITEM`]) carry the serialized item in a memory capability in the
+//! same way a regular reply does, and the terminal message ([`STREAM_END`])
+//! carries no capabilities. The client must not make other calls on the same
+//! channel until it has seen the terminal message, which the borrow held by
+//! [`Stream`] and `AsyncStream` enforces.
+
+use crate::{
+    internal::{expect_memory_cap, read_kernel_message, MemoryPermissions},
+    materialize::{Deserialize, Deserializer, Serialize},
+};
+use core::marker::PhantomData;
+use librust::{
+    capabilities::{Capability, CapabilityPtr, CapabilityRights, CapabilityWithDescription},
+    error::SyscallError,
+    syscalls::channel::{ChannelMessage, ChannelReadFlags},
+};
+
+/// The message kind of an item in a stream
+pub const STREAM_ITEM: usize = 1;
+/// The message kind that ends a stream
+pub const STREAM_END: usize = 2;
+
+/// The server's end of a stream, given to the provider so it can push items
+/// either immediately or later on, e.g. as events happen. The stream is ended
+/// when this is dropped or [`StreamSender::finish`] is called.
+pub struct StreamSender<T: ?Sized> {
+    channel: std::ipc::IpcChannel,
+    method_id: usize,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<T: Serialize + ?Sized> StreamSender<T> {
+    #[doc(hidden)]
+    pub fn new(channel: CapabilityPtr, method_id: usize) -> Self {
+        Self { channel: std::ipc::IpcChannel::new(channel), method_id, _item: PhantomData }
+    }
+
+    /// Send the next item to the client
+    pub fn push(&self, item: &T) -> Result<(), SyscallError> {
+        let (mem, mut caps) = crate::internal::serialize_to_shared_memory(item)?;
+        caps.insert(0, Capability { cptr: mem.cptr, rights: CapabilityRights::READ });
+
+        self.channel.send(ChannelMessage([self.method_id, STREAM_ITEM, 0, 0, 0, 0, 0]), &caps[..])
+    }
+
+    /// End the stream, reporting whether the client could be told
+    pub fn finish(self) -> Result<(), SyscallError> {
+        let result = self.send_end();
+        core::mem::forget(self);
+        result
+    }
+}
+
+impl<T: ?Sized> StreamSender<T> {
+    fn send_end(&self) -> Result<(), SyscallError> {
+        self.channel.send(ChannelMessage([self.method_id, STREAM_END, 0, 0, 0, 0, 0]), &[])
+    }
+}
+
+impl<T: ?Sized> Drop for StreamSender<T> {
+    fn drop(&mut self) {
+        let _ = self.send_end();
+    }
+}
+
+/// Deserialize the item in a stream message, or `None` if it's the end of the
+/// stream
+fn read_item<T: for<'de> Deserialize<'de>>(
+    msg: ChannelMessage,
+    caps: std::vec::Vec<CapabilityWithDescription>,
+) -> Option<T> {
+    if msg.0[1] == STREAM_END {
+        return None;
+    }
+
+    let buffer = match expect_memory_cap(&caps, MemoryPermissions::READ_WRITE) {
+        Ok(buffer) => buffer,
+        Err(e) => panic!("Invalid stream item: {e}"),
+    };
+    let deserializer = Deserializer::new(buffer, &caps[1..]);
+    Some(deserializer.deserialize().expect("deserialize success"))
+}
+
+/// The client's end of a stream, yielding items until the server ends it
+pub struct Stream<'a, T> {
+    channel: &'a std::ipc::IpcChannel,
+    done: bool,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<'a, T> Stream<'a, T> {
+    #[doc(hidden)]
+    pub fn new(channel: &'a std::ipc::IpcChannel) -> Self {
+        Self { channel, done: false, _item: PhantomData }
+    }
+}
+
+impl<T: for<'de> Deserialize<'de>> Iterator for Stream<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let (msg, caps) = self.channel.read_with_all_caps(ChannelReadFlags::NONE).unwrap();
+        let _ = read_kernel_message();
+
+        let item = read_item(msg, caps);
+        self.done = item.is_none();
+        item
+    }
+}
+
+impl<T> Drop for Stream<'_, T> {
+    fn drop(&mut self) {
+        // Drain the rest of the stream so the replies to later calls don't get
+        // mixed up with its items
+        while !self.done {
+            let (msg, _) = self.channel.read_with_all_caps(ChannelReadFlags::NONE).unwrap();
+            let _ = read_kernel_message();
+            self.done = msg.0[1] == STREAM_END;
+        }
+    }
+}
+
+/// The asynchronous version of [`Stream`]. Unlike [`Stream`], this can't wait
+/// for the rest of the stream when it's dropped, so it should be read until it
+/// returns `None` before the client is used again.
+#[cfg(feature = "async")]
+pub struct AsyncStream<'a, T> {
+    channel: &'a present::ipc::IpcChannel,
+    done: bool,
+    _item: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "async")]
+impl<'a, T: for<'de> Deserialize<'de>> AsyncStream<'a, T> {
+    #[doc(hidden)]
+    pub fn new(channel: &'a present::ipc::IpcChannel) -> Self {
+        Self { channel, done: false, _item: PhantomData }
+    }
+
+    /// Wait for the next item, returning `None` once the server has ended the
+    /// stream
+    pub async fn next(&mut self) -> Option<T> {
+        if self.done {
+            return None;
+        }
+
+        let (msg, caps) = self.channel.read_with_all_caps().await.unwrap();
+
+        let item = read_item(msg, caps);
+        self.done = item.is_none();
+        item
+    }
+}
@@ -12,13 +12,14 @@ use alloc::{
     string::{String, ToString},
 };
 use comb::Parser;
-use parser::{AstNode, Enum, Method, Service, Struct, Type, TypeDefinition, Use};
+use parser::{lexer::Token, AstNode, Attribute, Enum, Method, Service, Struct, Type, TypeDefinition, Use};
 
 extern crate alloc;
 #[cfg(test)]
 extern crate std;
 
 mod parser;
+mod validate;
 
 #[derive(Default)]
 pub struct Compiler {
@@ -69,6 +70,8 @@ impl Compiler {
             }
         }
 
+        validate::validate(&ast)?;
+
         let mut compiled = CompiledVidl { output: String::new() };
 
         // if self.generate_async {
@@ -90,10 +93,9 @@ impl Compiler {
         for (i, method) in service.methods.iter().enumerate() {
             compiled.write_fmt(format_args!(
                 r"#[allow(non_upper_case_globals)]
-const {}_{}_ID: usize = {};
+const {}_ID: usize = {};
 ",
-                service.name.to_uppercase(),
-                method.name.to_uppercase(),
+                method_id(service, method),
                 i
             ));
         }
@@ -123,39 +125,46 @@ impl<T: {0}Provider> {0}<T> {{
         loop {{
             let vidl::internal::KernelMessage::NewChannelMessage(cptr) = vidl::internal::read_kernel_message() else {{ continue }};
             let channel = vidl::internal::IpcChannel::new(cptr);
-            let Ok((msg, mut caps)) = channel.read_with_all_caps(vidl::internal::ChannelReadFlags::NONBLOCKING) else {{ continue }};
-            if caps.is_empty() {{
-                // Need at least one cap for the RPC message
-                continue;
-            }}
+            let Ok((msg, caps)) = channel.read_with_all_caps(vidl::internal::ChannelReadFlags::NONBLOCKING) else {{ continue }};
 
-            // If its not a memory cap we got a problem
-            let vidl::CapabilityWithDescription {{
-                capability: _,
-                description: vidl::CapabilityDescription::Memory {{ ptr, len, permissions: vidl::internal::MemoryPermissions::READ_WRITE }},
-            }} = caps.remove(0) else {{ continue }};
-            let buffer = unsafe {{ core::slice::from_raw_parts(ptr, len) }};
+            // The RPC message itself is in the first cap
+            let Ok(buffer) = vidl::internal::expect_memory_cap(&caps, vidl::internal::MemoryPermissions::READ_WRITE) else {{ continue }};
+            let caps = &caps[1..];
 
             match msg.0[0] {{
 "#, service.name));
 
         for method in &service.methods {
             compiled.write_fmt(format_args!(
-                r#"                {}_{}_ID => {{
+                r#"                {}_ID => {{
                 let deserializer = vidl::materialize::Deserializer::new(buffer, &caps[..]);
                 let Ok(("#,
-                service.name.to_uppercase(),
-                method.name.to_uppercase()
+                method_id(service, method)
             ));
             for arg in &method.arguments {
                 compiled.write_fmt(format_args!("{},", arg.0));
             }
             compiled.write_str(")) = deserializer.deserialize::<(");
             for arg in &method.arguments {
-                self.lower_type(compiled, &arg.1, true)?;
+                self.lower_server_argument_type(compiled, &arg.1)?;
                 compiled.write_str(", ");
             }
             compiled.write_str(")>() else { continue };\n");
+
+            // Streaming methods reply through the `StreamSender` instead,
+            // which ends the stream once the provider drops it
+            if method.streaming {
+                compiled.write_fmt(format_args!("                let _ = self.0.{}(", method.name));
+                for arg in &method.arguments {
+                    compiled.write_fmt(format_args!("{}, ", arg.0));
+                }
+                compiled.write_fmt(format_args!(
+                    "vidl::stream::StreamSender::new(cptr, {}_ID));\n            }},\n",
+                    method_id(service, method)
+                ));
+                continue;
+            }
+
             compiled.write_fmt(format_args!("                if let Ok(response) = self.0.{}(", method.name));
             for (i, arg) in method.arguments.iter().enumerate() {
                 compiled.write_fmt(format_args!("{}", arg.0));
@@ -165,16 +174,11 @@ impl<T: {0}Provider> {0}<T> {{
             }
             compiled.write_fmt(format_args!(
                 r#") {{
-                    let mut serializer = vidl::materialize::Serializer::new();
-                    serializer.serialize(&response).unwrap();
-                    let (buffer, mut caps) = serializer.into_parts();
-                    let mut mem = vidl::SharedMemoryAllocation::public_rw(vidl::Bytes(buffer.len())).unwrap();
-                    unsafe {{ mem.as_mut()[..buffer.len()].copy_from_slice(&buffer) }};
+                    let (mem, mut caps) = vidl::internal::serialize_to_shared_memory(&response).unwrap();
                     caps.insert(0, vidl::Capability {{ cptr: mem.cptr, rights: vidl::CapabilityRights::READ }});
-                    let _ = channel.send(vidl::ChannelMessage([{}_{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]);
+                    let _ = channel.send(vidl::ChannelMessage([{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]);
                 }}"#,
-                service.name.to_uppercase(),
-                method.name.to_uppercase()
+                method_id(service, method)
             ));
 
             compiled.write_str("            },\n");
@@ -225,21 +229,27 @@ impl {0}Client {{
 
         for (i, arg) in method.arguments.iter().enumerate() {
             compiled.write_fmt(format_args!("{}: ", arg.0));
-            self.lower_type(compiled, &arg.1, true)?;
+            self.lower_server_argument_type(compiled, &arg.1)?;
             if i + 1 != method.arguments.len() {
                 compiled.write_str(", ");
             }
         }
 
-        compiled.write_str(")");
-
         match &method.return_type {
+            Some(ret_type) if method.streaming => {
+                if !method.arguments.is_empty() {
+                    compiled.write_str(", ");
+                }
+                compiled.write_str("stream: vidl::stream::StreamSender<");
+                self.lower_type(compiled, ret_type, true)?;
+                compiled.write_str(">) -> Result<(), Self::Error>");
+            }
             Some(ret_type) => {
-                compiled.write_str(" -> Result<");
+                compiled.write_str(") -> Result<");
                 self.lower_type(compiled, ret_type, true)?;
                 compiled.write_str(", Self::Error>")
             }
-            None => compiled.write_str(" -> Result<(), Self::Error>"),
+            None => compiled.write_str(") -> Result<(), Self::Error>"),
         }
 
         compiled.write_str(";");
@@ -264,40 +274,55 @@ impl {0}Client {{
 
         compiled.write_str(")");
 
-        if let Some(ret_type) = &method.return_type {
-            compiled.write_str(" ->");
-            self.lower_type(compiled, ret_type, true)?;
+        match &method.return_type {
+            Some(ret_type) if method.streaming => {
+                compiled.write_str(" -> vidl::stream::Stream<'_, ");
+                self.lower_type(compiled, ret_type, true)?;
+                compiled.write_str(">");
+            }
+            Some(ret_type) => {
+                compiled.write_str(" ->");
+                self.lower_type(compiled, ret_type, true)?;
+            }
+            None => {}
         }
 
         compiled.write_str(
             r" {
-        let mut serializer = vidl::materialize::Serializer::new();
-        serializer.serialize(&(",
+        let (mem, mut caps) = vidl::internal::serialize_to_shared_memory(&(",
         );
 
         for arg in &method.arguments {
             compiled.write_fmt(format_args!("&{},", arg.0));
         }
 
-        compiled.write_fmt(format_args!(r#")).unwrap();
-        let (buffer, mut caps) = serializer.into_parts();
-        let mut mem = vidl::SharedMemoryAllocation::public_rw(vidl::Bytes(buffer.len())).unwrap();
-        unsafe {{ mem.as_mut()[..buffer.len()].copy_from_slice(&buffer) }};
+        compiled.write_fmt(format_args!(
+            r#")).unwrap();
         caps.insert(0, vidl::Capability {{ cptr: mem.cptr, rights: vidl::CapabilityRights::READ }});
-        self.0.send(vidl::ChannelMessage([{}_{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]).unwrap();
-        let (_msg, mut caps) = self.0.read_with_all_caps(vidl::ChannelReadFlags::NONE).unwrap();
+        self.0.send_request(vidl::ChannelMessage([{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]).unwrap();
+"#,
+            method_id(service, method)
+        ));
+
+        if method.streaming {
+            compiled.write_str("        vidl::stream::Stream::new(&self.0)\n    }\n\n");
+            return Ok(());
+        }
+
+        compiled.write_str(
+            r#"        let (_msg, caps) = self.0.read_with_all_caps(vidl::ChannelReadFlags::NONE).unwrap();
         let _ = vidl::internal::read_kernel_message();
 
-        match caps.remove(0) {{
-            vidl::CapabilityWithDescription {{ capability: _, description: vidl::CapabilityDescription::Memory {{ ptr, len, permissions: vidl::internal::MemoryPermissions::READ_WRITE }} }} => {{
-                let deserializer = vidl::materialize::Deserializer::new(unsafe {{ core::slice::from_raw_parts(ptr, len) }}, &caps);
-                deserializer.deserialize().expect("deserialize success")
-            }}
-            _ => panic!("First cap in response not memory!"),
-        }}  
-    }}
+        let buffer = match vidl::internal::expect_memory_cap(&caps, vidl::internal::MemoryPermissions::READ_WRITE) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Invalid response: {e}"),
+        };
+        let deserializer = vidl::materialize::Deserializer::new(buffer, &caps[1..]);
+        deserializer.deserialize().expect("deserialize success")
+    }
     
-"#, service.name.to_uppercase(), method.name.to_uppercase()));
+"#,
+        );
 
         Ok(())
     }
@@ -312,47 +337,52 @@ impl {0}Client {{
             self.lower_method_server_async(compiled, method)?;
         }
         compiled.write_str("\n}\n\n");
-        compiled.write_fmt(format_args!(r#"pub struct Async{0}<T: Async{0}Provider>(T, vidl::present::IpcChannel);
+        compiled.write_fmt(format_args!(r#"pub struct Async{0}<T: Async{0}Provider>(T, vidl::present::IpcChannel, vidl::CapabilityPtr);
 
 impl<T: Async{0}Provider> Async{0}<T> {{
-    pub fn new(provider: T, channel: vidl::CapabilityPtr) -> Self {{ Self(provider, vidl::present::IpcChannel::new(channel)) }}
+    pub fn new(provider: T, channel: vidl::CapabilityPtr) -> Self {{ Self(provider, vidl::present::IpcChannel::new(channel), channel) }}
     pub async fn serve(&mut self) -> ! {{
         loop {{
-            let Ok((msg, mut caps)) = self.1.read_with_all_caps().await else {{ continue }};
-            if caps.is_empty() {{
-                // Need at least one cap for the RPC message
-                continue;
-            }}
+            let Ok((msg, caps)) = self.1.read_with_all_caps().await else {{ continue }};
 
-            // If its not a memory cap we got a problem
-            let buffer = {{
-                let vidl::CapabilityWithDescription {{
-                    capability: _,
-                    description: vidl::CapabilityDescription::Memory {{ ptr, len, permissions: vidl::internal::MemoryPermissions::READ_WRITE }},
-                }} = caps.remove(0) else {{ continue }};
-                unsafe {{ core::slice::from_raw_parts(ptr, len) }}
-            }};
+            // The RPC message itself is in the first cap
+            let Ok(buffer) = vidl::internal::expect_memory_cap(&caps, vidl::internal::MemoryPermissions::READ_WRITE) else {{ continue }};
+            let caps = &caps[1..];
 
             match msg.0[0] {{
 "#, service.name));
 
         for method in &service.methods {
             compiled.write_fmt(format_args!(
-                r#"                {}_{}_ID => {{
+                r#"                {}_ID => {{
                 let deserializer = vidl::materialize::Deserializer::new(buffer, &caps[..]);
                 let Ok(("#,
-                service.name.to_uppercase(),
-                method.name.to_uppercase()
+                method_id(service, method)
             ));
             for arg in &method.arguments {
                 compiled.write_fmt(format_args!("{},", arg.0));
             }
             compiled.write_str(")) = deserializer.deserialize::<(");
             for arg in &method.arguments {
-                self.lower_type(compiled, &arg.1, true)?;
+                self.lower_server_argument_type(compiled, &arg.1)?;
                 compiled.write_str(", ");
             }
             compiled.write_str(")>() else { continue };\n");
+
+            // Streaming methods reply through the `StreamSender` instead,
+            // which ends the stream once the provider drops it
+            if method.streaming {
+                compiled.write_fmt(format_args!("                let _ = self.0.{}(", method.name));
+                for arg in &method.arguments {
+                    compiled.write_fmt(format_args!("{}, ", arg.0));
+                }
+                compiled.write_fmt(format_args!(
+                    "vidl::stream::StreamSender::new(self.2, {}_ID)).await;\n            }},\n",
+                    method_id(service, method)
+                ));
+                continue;
+            }
+
             compiled.write_fmt(format_args!("                if let Ok(response) = self.0.{}(", method.name));
             for (i, arg) in method.arguments.iter().enumerate() {
                 compiled.write_fmt(format_args!("{}", arg.0));
@@ -362,16 +392,11 @@ impl<T: Async{0}Provider> Async{0}<T> {{
             }
             compiled.write_fmt(format_args!(
                 r#").await {{
-                    let mut serializer = vidl::materialize::Serializer::new();
-                    serializer.serialize(&response).unwrap();
-                    let (buffer, mut caps) = serializer.into_parts();
-                    let mut mem = vidl::SharedMemoryAllocation::public_rw(vidl::Bytes(buffer.len())).unwrap();
-                    unsafe {{ mem.as_mut()[..buffer.len()].copy_from_slice(&buffer) }};
+                    let (mem, mut caps) = vidl::internal::serialize_to_shared_memory(&response).unwrap();
                     caps.insert(0, vidl::Capability {{ cptr: mem.cptr, rights: vidl::CapabilityRights::READ }});
-                    let _ = self.1.send(vidl::ChannelMessage([{}_{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]);
+                    let _ = self.1.send(vidl::ChannelMessage([{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]);
                 }}"#,
-                service.name.to_uppercase(),
-                method.name.to_uppercase()
+                method_id(service, method)
             ));
 
             compiled.write_str("            },\n");
@@ -394,21 +419,27 @@ impl<T: Async{0}Provider> Async{0}<T> {{
 
         for (i, arg) in method.arguments.iter().enumerate() {
             compiled.write_fmt(format_args!("{}: ", arg.0));
-            self.lower_type(compiled, &arg.1, true)?;
+            self.lower_server_argument_type(compiled, &arg.1)?;
             if i + 1 != method.arguments.len() {
                 compiled.write_str(", ");
             }
         }
 
-        compiled.write_str(")");
-
         match &method.return_type {
+            Some(ret_type) if method.streaming => {
+                if !method.arguments.is_empty() {
+                    compiled.write_str(", ");
+                }
+                compiled.write_str("stream: vidl::stream::StreamSender<");
+                self.lower_type(compiled, ret_type, true)?;
+                compiled.write_str(">) -> Result<(), Self::Error>");
+            }
             Some(ret_type) => {
-                compiled.write_str(" -> Result<");
+                compiled.write_str(") -> Result<");
                 self.lower_type(compiled, ret_type, true)?;
                 compiled.write_str(", Self::Error>")
             }
-            None => compiled.write_str(" -> Result<(), Self::Error>"),
+            None => compiled.write_str(") -> Result<(), Self::Error>"),
         }
 
         compiled.write_str(";");
@@ -457,43 +488,68 @@ impl Async{0}Client {{
 
         compiled.write_str(")");
 
-        if let Some(ret_type) = &method.return_type {
-            compiled.write_str(" ->");
-            self.lower_type(compiled, ret_type, true)?;
+        match &method.return_type {
+            Some(ret_type) if method.streaming => {
+                compiled.write_str(" -> vidl::stream::AsyncStream<'_, ");
+                self.lower_type(compiled, ret_type, true)?;
+                compiled.write_str(">");
+            }
+            Some(ret_type) => {
+                compiled.write_str(" ->");
+                self.lower_type(compiled, ret_type, true)?;
+            }
+            None => {}
         }
 
         compiled.write_str(
             r" {
-        let mut serializer = vidl::materialize::Serializer::new();
-        serializer.serialize(&(",
+        let (mem, mut caps) = vidl::internal::serialize_to_shared_memory(&(",
         );
 
         for arg in &method.arguments {
             compiled.write_fmt(format_args!("&{},", arg.0));
         }
 
-        compiled.write_fmt(format_args!(r#")).unwrap();
-        let (buffer, mut caps) = serializer.into_parts();
-        let mut mem = vidl::SharedMemoryAllocation::public_rw(vidl::Bytes(buffer.len())).unwrap();
-        unsafe {{ mem.as_mut()[..buffer.len()].copy_from_slice(&buffer) }};
+        compiled.write_fmt(format_args!(
+            r#")).unwrap();
         caps.insert(0, vidl::Capability {{ cptr: mem.cptr, rights: vidl::CapabilityRights::READ }});
-        self.0.send(vidl::ChannelMessage([{}_{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]).unwrap();
-        let (_msg, mut caps) = self.0.read_with_all_caps().await.unwrap();
-
-        match caps.remove(0) {{
-            vidl::CapabilityWithDescription {{ capability: _, description: vidl::CapabilityDescription::Memory {{ ptr, len, permissions: vidl::internal::MemoryPermissions::READ_WRITE }} }} => {{
-                let deserializer = vidl::materialize::Deserializer::new(unsafe {{ core::slice::from_raw_parts(ptr, len) }}, &caps);
-                deserializer.deserialize().expect("deserialize success")
-            }}
-            _ => panic!("First cap in response not memory!"),
-        }}  
-    }}
+        self.0.send(vidl::ChannelMessage([{}_ID, 0, 0, 0, 0, 0, 0]), &caps[..]).unwrap();
+"#,
+            method_id(service, method)
+        ));
+
+        if method.streaming {
+            compiled.write_str("        vidl::stream::AsyncStream::new(&self.0)\n    }\n\n");
+            return Ok(());
+        }
+
+        compiled.write_str(
+            r#"        let (_msg, caps) = self.0.read_with_all_caps().await.unwrap();
+
+        let buffer = match vidl::internal::expect_memory_cap(&caps, vidl::internal::MemoryPermissions::READ_WRITE) {
+            Ok(buffer) => buffer,
+            Err(e) => panic!("Invalid response: {e}"),
+        };
+        let deserializer = vidl::materialize::Deserializer::new(buffer, &caps[1..]);
+        deserializer.deserialize().expect("deserialize success")
+    }
     
-"#, service.name.to_uppercase(), method.name.to_uppercase()));
+"#,
+        );
 
         Ok(())
     }
 
+    /// Server methods receive their arguments by value, except for strings
+    /// which borrow from the message buffer instead of being copied into a
+    /// `String`
+    fn lower_server_argument_type(&self, compiled: &mut CompiledVidl, ty: &Type) -> Result<(), CompileError> {
+        match ty {
+            Type::Str => self.lower_type(compiled, ty, false),
+            _ => self.lower_type(compiled, ty, true),
+        }
+    }
+
     fn lower_type(&self, compiled: &mut CompiledVidl, ty: &Type, in_return_position: bool) -> Result<(), CompileError> {
         match ty {
             Type::Path { path, generics } => {
@@ -564,7 +620,7 @@ impl Async{0}Client {{
     fn lower_typedef(
         &self,
         compiled: &mut CompiledVidl,
-        attributes: &[String],
+        attributes: &[Attribute],
         typedef: &TypeDefinition,
     ) -> Result<(), CompileError> {
         match typedef {
@@ -576,16 +632,15 @@ impl Async{0}Client {{
     fn lower_struct(
         &self,
         compiled: &mut CompiledVidl,
-        attributes: &[String],
+        attributes: &[Attribute],
         strukt: &Struct,
     ) -> Result<(), CompileError> {
-        let extra_traits = self.attributes_to_traits(attributes);
+        let traits = self.attributes_to_traits(attributes)?;
         compiled.write_fmt(format_args!(
-            r#"#[derive(Debug, vidl::materialize::Deserialize, vidl::materialize::Serializable, vidl::materialize::Serialize{})]
+            r#"#[derive({})]
 #[materialize(reexport_path = "vidl::materialize")]
 pub struct {}"#,
-            extra_traits,
-            strukt.name
+            traits, strukt.name
         ));
 
         if let Some(generics) = &strukt.generics {
@@ -609,16 +664,42 @@ pub struct {}"#,
         Ok(())
     }
 
-    fn lower_enum(&self, compiled: &mut CompiledVidl, attributes: &[String], enoom: &Enum) -> Result<(), CompileError> {
-        let extra_traits = self.attributes_to_traits(attributes);
+    fn lower_enum(
+        &self,
+        compiled: &mut CompiledVidl,
+        attributes: &[Attribute],
+        enoom: &Enum,
+    ) -> Result<(), CompileError> {
+        let traits = self.attributes_to_traits(attributes)?;
+        let repr = enoom.repr.as_deref().map(primitive_repr).transpose()?;
+        let unit_only = enoom.variants.iter().all(|variant| variant.associated_data.is_none());
+
+        if repr.is_none() && !unit_only && enoom.variants.iter().any(|variant| variant.discriminant.is_some()) {
+            return Err(CompileError::SourceError(SourceError {
+                kind: SourceErrorKind::Custom(alloc::format!(
+                    "enum `{}` has explicit discriminants on variants with data, which requires a repr",
+                    enoom.name
+                )),
+                span: None,
+            }));
+        }
+
+        // Enums without a repr still have `isize` discriminants in Rust
+        let discriminants = enum_discriminants(enoom, repr.unwrap_or("isize"))?;
+
         compiled.write_fmt(format_args!(
-            r#"#[derive(Debug, vidl::materialize::Deserialize, vidl::materialize::Serializable, vidl::materialize::Serialize{})]
+            r#"#[derive({})]
 #[materialize(reexport_path = "vidl::materialize")]
-pub enum {}"#,
-            extra_traits,
-            enoom.name
+"#,
+            traits,
         ));
 
+        if let Some(repr) = repr {
+            compiled.write_fmt(format_args!("#[repr({})]\n", repr));
+        }
+
+        compiled.write_fmt(format_args!("pub enum {}", enoom.name));
+
         if let Some(generics) = &enoom.generics {
             compiled.write_str("<");
             generics.iter().enumerate().for_each(|(i, ty)| {
@@ -656,34 +737,200 @@ pub enum {}"#,
                     }
                 }
             }
+            if let Some(discriminant) = variant.discriminant {
+                compiled.write_fmt(format_args!(" = {}", discriminant));
+            }
             compiled.write_str(",\n");
         }
         compiled.write_str("}\n\n");
+
+        if let Some(repr) = repr {
+            self.lower_enum_conversions(compiled, enoom, &discriminants, repr, unit_only);
+        }
+
         Ok(())
     }
 
-    fn attributes_to_traits(&self, attributes: &[String]) -> String {
-        let mut traits = String::new();
+    /// Generate the conversions between an enum with a `repr` and its
+    /// discriminant. Only unit-only enums can be constructed from a bare
+    /// discriminant, so `TryFrom` and `as_repr` are limited to those.
+    fn lower_enum_conversions(
+        &self,
+        compiled: &mut CompiledVidl,
+        enoom: &Enum,
+        discriminants: &[usize],
+        repr: &str,
+        unit_only: bool,
+    ) {
+        let generics = match &enoom.generics {
+            Some(generics) => alloc::format!("<{}>", generics.join(", ")),
+            None => String::new(),
+        };
+
+        compiled.write_fmt(format_args!(
+            "impl{0} core::convert::From<{1}{0}> for {2} {{\n    fn from(value: {1}{0}) -> Self {{\n        match value {{\n",
+            generics, enoom.name, repr
+        ));
+        for (variant, discriminant) in enoom.variants.iter().zip(discriminants) {
+            let pattern = match variant.associated_data {
+                Some(parser::VariantData::Struct(_)) => " { .. }",
+                Some(parser::VariantData::Tuple(_)) => "(..)",
+                None => "",
+            };
+            compiled.write_fmt(format_args!(
+                "            {}::{}{} => {},\n",
+                enoom.name, variant.name, pattern, discriminant
+            ));
+        }
+        compiled.write_str("        }\n    }\n}\n\n");
+
+        if !unit_only {
+            return;
+        }
+
+        compiled.write_fmt(format_args!(
+            "impl{0} {1}{0} {{\n    pub const fn as_repr(self) -> {2} {{\n        self as {2}\n    }}\n}}\n\n",
+            generics, enoom.name, repr
+        ));
+
+        compiled.write_fmt(format_args!(
+            "impl{0} core::convert::TryFrom<{2}> for {1}{0} {{\n    type Error = {2};\n\n    fn try_from(value: {2}) -> core::result::Result<Self, {2}> {{\n        match value {{\n",
+            generics, enoom.name, repr
+        ));
+        for (variant, discriminant) in enoom.variants.iter().zip(discriminants) {
+            compiled.write_fmt(format_args!(
+                "            {} => core::result::Result::Ok({}::{}),\n",
+                discriminant, enoom.name, variant.name
+            ));
+        }
+        compiled.write_str("            _ => core::result::Result::Err(value),\n        }\n    }\n}\n\n");
+    }
+
+    fn attributes_to_traits(&self, attributes: &[Attribute]) -> Result<String, CompileError> {
+        let mut debug = true;
+        let mut traits = alloc::vec::Vec::new();
+        // Attributes can ask for the same trait more than once, e.g. `@trivial`
+        // and `@derive(Clone)`, which Rust doesn't allow in a single `derive`
+        fn add(traits: &mut alloc::vec::Vec<String>, names: &[&str]) {
+            for name in names {
+                if !traits.iter().any(|t| t == name) {
+                    traits.push(String::from(*name));
+                }
+            }
+        }
 
         for attribute in attributes {
-            match &**attribute {
-                "trivial" => traits.push_str("Clone, Copy, "),
-                "comparable" => traits.push_str("PartialEq, Eq, "),
-                "orderable" => traits.push_str("PartialEq, Eq, PartialOrd, Ord, "),
+            match (&*attribute.name, &attribute.arguments) {
+                ("trivial", None) => add(&mut traits, &["Clone", "Copy"]),
+                ("comparable", None) => add(&mut traits, &["PartialEq", "Eq"]),
+                ("orderable", None) => add(&mut traits, &["PartialEq", "Eq", "PartialOrd", "Ord"]),
+                ("no_debug", None) => debug = false,
+                ("derive", Some(arguments)) => {
+                    for argument in arguments {
+                        match argument {
+                            Token::Identifier(ident) => add(&mut traits, &[ident]),
+                            _ => {
+                                return Err(CompileError::SourceError(SourceError {
+                                    kind: SourceErrorKind::Custom(alloc::format!(
+                                        "expected a trait name in `derive` attribute, found `{:?}`",
+                                        argument
+                                    )),
+                                    span: Some(attribute.span),
+                                }))
+                            }
+                        }
+                    }
+                }
+                ("derive", None) => {
+                    return Err(CompileError::SourceError(SourceError {
+                        kind: SourceErrorKind::Custom(String::from(
+                            "`derive` attribute requires a list of traits, e.g. `@derive(Hash)`",
+                        )),
+                        span: Some(attribute.span),
+                    }))
+                }
                 _ => {}
             }
         }
 
-        if !traits.is_empty() {
-            traits.insert_str(0, ", ");
-            traits.pop();
-            traits.pop();
+        let mut derives = alloc::vec::Vec::new();
+        if debug && !traits.iter().any(|name| name == "Debug") {
+            derives.push(String::from("Debug"));
         }
+        derives.extend(
+            ["vidl::materialize::Deserialize", "vidl::materialize::Serializable", "vidl::materialize::Serialize"]
+                .map(String::from),
+        );
+        derives.extend(traits);
 
-        traits
+        Ok(derives.join(", "))
     }
 }
 
+/// The discriminant of each of the enum's variants, which must fit in `repr`.
+/// Variants without an explicit discriminant follow the same rule as Rust: one
+/// greater than the previous variant's, starting at zero.
+fn enum_discriminants(enoom: &Enum, repr: &str) -> Result<alloc::vec::Vec<usize>, CompileError> {
+    let max = match repr {
+        "u8" => u8::MAX as u64,
+        "i8" => i8::MAX as u64,
+        "u16" => u16::MAX as u64,
+        "i16" => i16::MAX as u64,
+        "u32" => u32::MAX as u64,
+        "i32" => i32::MAX as u64,
+        "u64" | "usize" => u64::MAX,
+        _ => i64::MAX as u64,
+    };
+
+    let mut next = Some(0);
+    enoom
+        .variants
+        .iter()
+        .map(|variant| match variant.discriminant.or(next) {
+            Some(discriminant) if discriminant as u64 <= max => {
+                next = discriminant.checked_add(1);
+                Ok(discriminant)
+            }
+            _ => Err(CompileError::SourceError(SourceError {
+                kind: SourceErrorKind::Custom(alloc::format!(
+                    "discriminant of `{}::{}` is out of range for `{}`",
+                    enoom.name,
+                    variant.name,
+                    repr
+                )),
+                span: Some(variant.name_span),
+            })),
+        })
+        .collect()
+}
+
+/// Map a VIDL integer type name to the Rust primitive used for an enum repr
+fn primitive_repr(repr: &str) -> Result<&'static str, CompileError> {
+    match repr {
+        "U8" => Ok("u8"),
+        "I8" => Ok("i8"),
+        "U16" => Ok("u16"),
+        "I16" => Ok("i16"),
+        "U32" => Ok("u32"),
+        "I32" => Ok("i32"),
+        "U64" => Ok("u64"),
+        "I64" => Ok("i64"),
+        "USize" => Ok("usize"),
+        "ISize" => Ok("isize"),
+        _ => Err(CompileError::SourceError(SourceError {
+            kind: SourceErrorKind::Custom(alloc::format!("`{}` is not a valid enum repr", repr)),
+            span: None,
+        })),
+    }
+}
+
+/// The name of the constant holding the message ID of `method`, which has the
+/// `r#` of any raw identifiers stripped
+fn method_id(service: &Service, method: &Method) -> String {
+    let unraw = |name: &str| name.strip_prefix("r#").unwrap_or(name).to_uppercase();
+    alloc::format!("{}_{}", unraw(&service.name), unraw(&method.name))
+}
+
 pub struct CompiledVidl {
     output: String,
 }
@@ -768,6 +1015,17 @@ impl comb::error::Error for SourceError {
     fn unexpected_value<V: core::fmt::Debug>(value: V, span: Option<comb::Span>) -> Self {
         Self::custom(alloc::format!("unexpected value `{:?}`", value), span)
     }
+
+    fn label(self, label: &'static str, span: Option<comb::Span>) -> Self {
+        let message = match self.kind {
+            SourceErrorKind::Custom(message) => message,
+            SourceErrorKind::UnexpectedCharacter(c) => alloc::format!("unexpected character `{}`", c),
+            SourceErrorKind::UnexpectedEnd => String::from("unexpected end of input"),
+        };
+
+        // Prefer pointing at where the error actually happened
+        Self::custom(alloc::format!("{}: {}", label, message), self.span.or(span))
+    }
 }
 
 pub struct ErrorPrettyDisplay<'a> {
@@ -823,3 +1081,161 @@ impl core::fmt::Display for ErrorPrettyDisplay<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enum_discriminant_conversions() {
+        let source = "
+            @comparable
+            @trivial
+            enum Status: U8 {
+                Ok = 1,
+                Busy,
+                Failed = 10,
+            }
+
+            enum Event: U16 {
+                Started(USize) = 2,
+                Stopped,
+            }";
+
+        let compiled = Compiler::new(false).compile(source).unwrap().to_string();
+
+        assert!(compiled.contains("#[repr(u8)]\npub enum Status {\n    Ok = 1,\n    Busy,\n    Failed = 10,\n}"));
+        assert!(compiled.contains("impl core::convert::From<Status> for u8 {"));
+        assert!(compiled.contains("            Status::Busy => 2,\n"));
+        assert!(compiled.contains("    pub const fn as_repr(self) -> u8 {\n        self as u8\n    }"));
+        assert!(compiled.contains("impl core::convert::TryFrom<u8> for Status {"));
+        assert!(compiled.contains("            10 => core::result::Result::Ok(Status::Failed),\n"));
+        assert!(compiled.contains("            _ => core::result::Result::Err(value),\n"));
+
+        assert!(compiled.contains("#[repr(u16)]\npub enum Event {\n    Started(USize) = 2,\n    Stopped,\n}"));
+        assert!(compiled.contains("            Event::Started(..) => 2,\n            Event::Stopped => 3,\n"));
+        assert!(!compiled.contains("TryFrom<u16> for Event"));
+    }
+
+    #[test]
+    fn server_borrows_string_arguments() {
+        let source = "
+            service Filesystem {
+                fn open(path: String, flags: U32) -> String;
+                fn list(paths: [String]) -> U32;
+            }";
+
+        let compiled = Compiler::new(true).compile(source).unwrap().to_string();
+
+        assert!(compiled.contains(
+            "    fn open(&mut self, path: &vidl::core::Str, flags: U32) -> Result<vidl::core::String, Self::Error>;"
+        ));
+        assert!(compiled.contains("    async fn open(&mut self, path: &vidl::core::Str, flags: U32)"));
+        assert!(compiled.contains("let Ok((path,flags,)) = deserializer.deserialize::<(&vidl::core::Str, U32, )>()"));
+
+        // Strings nested in other types are still owned
+        assert!(compiled.contains("    fn list(&mut self, paths: vidl::core::Vec<vidl::core::String>)"));
+    }
+
+    #[test]
+    fn stream_methods() {
+        let source = "
+            service Logger {
+                fn logs(level: U8) -> stream<String>;
+                fn flush(level: U8);
+            }";
+
+        let compiled = Compiler::new(true).compile(source).unwrap().to_string();
+
+        // The client pulls items until the end of the stream...
+        assert!(
+            compiled.contains("    pub fn logs(&self, level: U8) -> vidl::stream::Stream<'_, vidl::core::String> {")
+        );
+        assert!(compiled.contains("        vidl::stream::Stream::new(&self.0)\n"));
+        assert!(compiled.contains("-> vidl::stream::AsyncStream<'_, vidl::core::String> {"));
+        assert!(compiled.contains("        vidl::stream::AsyncStream::new(&self.0)\n"));
+
+        // ...and the server is handed something to push them into
+        assert!(compiled.contains(
+            "    fn logs(&mut self, level: U8, stream: vidl::stream::StreamSender<vidl::core::String>) -> Result<(), Self::Error>;"
+        ));
+        assert!(compiled.contains("let _ = self.0.logs(level, vidl::stream::StreamSender::new(cptr, LOGGER_LOGS_ID));"));
+        assert!(compiled
+            .contains("let _ = self.0.logs(level, vidl::stream::StreamSender::new(self.2, LOGGER_LOGS_ID)).await;"));
+
+        // Regular methods are unaffected
+        assert!(compiled.contains("    fn flush(&mut self, level: U8) -> Result<(), Self::Error>;"));
+    }
+
+    #[test]
+    fn invalid_enum_repr() {
+        assert!(Compiler::new(false).compile("enum Status: String { Ok }").is_err());
+        assert!(Compiler::new(false).compile("enum Status: Foo { Ok }").is_err());
+        assert!(Compiler::new(false).compile("enum Event { Started(USize) = 2 }").is_err());
+    }
+
+    #[test]
+    fn out_of_range_discriminants() {
+        assert!(Compiler::new(false).compile("enum Status: U8 { Ok = 255 }").is_ok());
+        assert!(Compiler::new(false).compile("enum Status: I8 { Ok = 127 }").is_ok());
+
+        let error = |source| match Compiler::new(false).compile(source) {
+            Err(CompileError::SourceError(SourceError { kind: SourceErrorKind::Custom(error), .. })) => error,
+            result => panic!("expected an out of range discriminant error, got {:?}", result.map(|c| c.to_string())),
+        };
+
+        assert_eq!(error("enum Status: U8 { Ok = 256 }"), "discriminant of `Status::Ok` is out of range for `u8`");
+        assert_eq!(error("enum Status: I8 { Ok = 128 }"), "discriminant of `Status::Ok` is out of range for `i8`");
+        // Implicit discriminants count too
+        assert_eq!(
+            error("enum Status: U8 { Ok = 255, Busy }"),
+            "discriminant of `Status::Busy` is out of range for `u8`"
+        );
+        assert_eq!(
+            error("enum Status { Ok = 9223372036854775808 }"),
+            "discriminant of `Status::Ok` is out of range for `isize`"
+        );
+    }
+
+    #[test]
+    fn derive_attributes() {
+        let source = "
+            @no_debug
+            @trivial
+            @derive(Hash, Default)
+            struct Handle { id: U64 }
+
+            @derive(Hash,)
+            enum Kind { File, Directory }";
+
+        let compiled = Compiler::new(false).compile(source).unwrap().to_string();
+
+        assert!(compiled.contains(
+            "#[derive(vidl::materialize::Deserialize, vidl::materialize::Serializable, vidl::materialize::Serialize, Clone, Copy, Hash, Default)]\n#[materialize(reexport_path = \"vidl::materialize\")]\npub struct Handle {"
+        ));
+        assert!(compiled.contains(
+            "#[derive(Debug, vidl::materialize::Deserialize, vidl::materialize::Serializable, vidl::materialize::Serialize, Hash)]\n#[materialize(reexport_path = \"vidl::materialize\")]\npub enum Kind"
+        ));
+
+        // Traits asked for by more than one attribute are only derived once
+        let compiled = Compiler::new(false)
+            .compile("@trivial @comparable @orderable @derive(Clone, Copy, Debug) struct Handle { id: U64 }")
+            .unwrap()
+            .to_string();
+        assert!(compiled.contains(
+            "#[derive(vidl::materialize::Deserialize, vidl::materialize::Serializable, vidl::materialize::Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]"
+        ));
+
+        // Errors point at the offending attribute
+        let source = "@derive(Hash, 5) struct Handle { id: U64 }";
+        let Err(CompileError::SourceError(error)) = Compiler::new(false).compile(source) else { panic!() };
+        assert_eq!(error.span, Some(comb::Span { start: 0, end: 16 }));
+        let source = "@trivial @derive struct Handle { id: U64 }";
+        let Err(CompileError::SourceError(error)) = Compiler::new(false).compile(source) else { panic!() };
+        assert_eq!(error.span, Some(comb::Span { start: 9, end: 16 }));
+
+        assert!(Compiler::new(false).compile("@derive(Hash, 5) struct Handle { id: U64 }").is_err());
+        assert!(Compiler::new(false).compile("@derive(core::hash::Hash) struct Handle { id: U64 }").is_err());
+        assert!(Compiler::new(false).compile("@derive struct Handle { id: U64 }").is_err());
+    }
+}
@@ -7,8 +7,8 @@
 
 use alloc::string::String;
 use comb::{
-    combinators::{hinted_choice, sequence, single},
-    text::{ascii_alphabetic, ascii_alphanumeric, ascii_digit, string, whitespace},
+    combinators::{hinted_choice, maybe, sequence, single},
+    text::{ascii_alphabetic, ascii_alphanumeric, ascii_digit, string},
     Parser, Span,
 };
 
@@ -31,6 +31,7 @@ pub enum Token {
     Colon,
     Semicolon,
     Comma,
+    Equals,
 }
 
 impl Token {
@@ -54,6 +55,7 @@ pub enum Keyword {
     Enum,
     Fn,
     Service,
+    Stream,
     String,
     Struct,
     Use,
@@ -79,25 +81,32 @@ pub fn lexer() -> impl Parser<Error = crate::SourceError, Output = (Token, Span)
         (',', single(',').to(Token::Comma)),
         ('-', single('-').then(single('>')).to(Token::Arrow)),
         ('@', single('@').to(Token::At)),
+        ('=', single('=').to(Token::Equals)),
     ))
     .with_span()
-    .padded_by(whitespace())
+    .ws_padded()
 }
 
 fn number() -> impl Parser<Error = crate::SourceError, Output = Token, Input = char> {
     string(ascii_digit()).map(|s| Token::Number(s.parse().unwrap()))
 }
 
+/// An identifier or keyword, where a leading `r#` makes a raw identifier which
+/// is passed through to the generated Rust as-is, even if it's a keyword
 fn identifier() -> impl Parser<Error = crate::SourceError, Output = Token, Input = char> {
-    string((ascii_alphabetic(), ascii_alphanumeric().or(single('_')))).map(|s| match &*s {
-        "enum" => Token::Keyword(Keyword::Enum),
-        "fn" => Token::Keyword(Keyword::Fn),
-        "struct" => Token::Keyword(Keyword::Struct),
-        "service" => Token::Keyword(Keyword::Service),
-        "use" => Token::Keyword(Keyword::Use),
-        "String" => Token::Keyword(Keyword::String),
-        _ => Token::Identifier(s),
-    })
+    maybe(sequence(&['r', '#'])).then(string((ascii_alphabetic(), ascii_alphanumeric().or(single('_'))))).map(
+        |(raw, s)| match (raw, &*s) {
+            (Some(()), _) => Token::Identifier(alloc::format!("r#{}", s)),
+            (None, "enum") => Token::Keyword(Keyword::Enum),
+            (None, "fn") => Token::Keyword(Keyword::Fn),
+            (None, "struct") => Token::Keyword(Keyword::Struct),
+            (None, "service") => Token::Keyword(Keyword::Service),
+            (None, "stream") => Token::Keyword(Keyword::Stream),
+            (None, "use") => Token::Keyword(Keyword::Use),
+            (None, "String") => Token::Keyword(Keyword::String),
+            (None, _) => Token::Identifier(s),
+        },
+    )
 }
 
 #[cfg(test)]
@@ -141,6 +150,28 @@ service
         assert_eq!(lexer_parse(), Ok(Token::Keyword(Keyword::Service)));
     }
 
+    #[test]
+    fn comments() {
+        let syntax = "// A service\r\nservice Foo { // trailing\r\n\tfn bar();\n}\n// end of file";
+        let tokens = many0(lexer()).then_assert(end()).parse(&mut Stream::from_str(syntax)).unwrap();
+        let tokens = tokens.into_iter().map(|(token, _)| token).collect::<alloc::vec::Vec<_>>();
+
+        assert_eq!(
+            tokens,
+            [
+                Token::Keyword(Keyword::Service),
+                Token::Identifier(String::from("Foo")),
+                Token::LeftBrace,
+                Token::Keyword(Keyword::Fn),
+                Token::Identifier(String::from("bar")),
+                Token::LeftParenthesis,
+                Token::RightParenthesis,
+                Token::Semicolon,
+                Token::RightBrace,
+            ]
+        );
+    }
+
     #[test]
     fn single_char_ident() {
         let mut stream = Stream::from_str("-I;");
@@ -149,4 +180,19 @@ service
             .or(end().to((Some('a'), String::from("f"))));
         assert_eq!(lexer.parse(&mut stream), Ok((Some('-'), String::from("I"))));
     }
+
+    #[test]
+    fn raw_identifiers() {
+        let tokens = many0(lexer()).parse(&mut Stream::from_str("r#type: r;")).unwrap();
+        let tokens: alloc::vec::Vec<Token> = tokens.into_iter().map(|(token, _)| token).collect();
+        assert_eq!(
+            tokens,
+            &[
+                Token::Identifier(String::from("r#type")),
+                Token::Colon,
+                Token::Identifier(String::from("r")),
+                Token::Semicolon,
+            ]
+        );
+    }
 }
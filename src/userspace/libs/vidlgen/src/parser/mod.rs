@@ -12,14 +12,24 @@ use alloc::{boxed::Box, string::String, vec::Vec};
 use comb::{
     combinators::{delimited, hinted_choice, many1, maybe, single, single_by, until},
     recursive::recursive,
-    Parser,
+    Parser, Span,
 };
 
 #[derive(Debug, PartialEq)]
 pub enum AstNode {
     Service(Service),
     Use(Use),
-    TypeDefinition(Vec<String>, TypeDefinition),
+    TypeDefinition(Vec<Attribute>, TypeDefinition),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    /// The span of the whole attribute, e.g. `@derive(Hash, Default)`
+    pub span: Span,
+    /// The tokens between the parentheses following the attribute name, if
+    /// present, e.g. `@derive(Hash, Default)`
+    pub arguments: Option<Vec<Token>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -55,19 +65,26 @@ impl Use {
 #[derive(Debug, PartialEq)]
 pub struct Service {
     pub name: String,
+    pub name_span: Span,
     pub methods: Vec<Method>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Method {
     pub name: String,
-    pub arguments: Vec<(String, Type)>,
+    pub name_span: Span,
+    /// The name, type, and span of the name of each argument
+    pub arguments: Vec<(String, Type, Span)>,
     pub return_type: Option<Type>,
+    /// Whether the method returns a `stream<T>` of `return_type` instead of a
+    /// single value
+    pub streaming: bool,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Struct {
     pub name: String,
+    pub name_span: Span,
     pub generics: Option<Vec<String>>,
     pub fields: Vec<Field>,
 }
@@ -75,20 +92,25 @@ pub struct Struct {
 #[derive(Debug, PartialEq)]
 pub struct Field {
     pub name: String,
+    pub name_span: Span,
     pub ty: Type,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Enum {
     pub name: String,
+    pub name_span: Span,
     pub generics: Option<Vec<String>>,
+    pub repr: Option<String>,
     pub variants: Vec<Variant>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Variant {
     pub name: String,
+    pub name_span: Span,
     pub associated_data: Option<VariantData>,
+    pub discriminant: Option<usize>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -151,29 +173,44 @@ fn parse_use_grouped() -> impl Parser<Error = crate::SourceError, Output = Use,
 
 fn parse_service() -> impl Parser<Error = crate::SourceError, Output = AstNode, Input = Token> {
     single(Token::Keyword(Keyword::Service))
-        .then_to(parse_ident())
+        .then_to(parse_ident().with_span())
         .then(delimited(single(Token::LeftBrace), until(Token::RightBrace, parse_method()), single(Token::RightBrace)))
-        .map(|(name, methods)| AstNode::Service(Service { name, methods }))
+        .map(|((name, name_span), methods)| AstNode::Service(Service { name, name_span, methods }))
 }
 
 fn parse_method() -> impl Parser<Error = crate::SourceError, Output = Method, Input = Token> {
     single(Token::Keyword(Keyword::Fn))
-        .then_to(single_by(|t| matches!(t, Token::Identifier(_))).map(Token::into_identifier))
+        .then_to(parse_ident().with_span())
         .then(delimited(
             single(Token::LeftParenthesis),
             parse_argument().separated_by(single(Token::Comma)).allow_trailing(),
             single(Token::RightParenthesis),
         ))
-        .then(maybe(single(Token::Arrow).then_to(parse_type())))
+        .then(maybe(single(Token::Arrow).then_to(parse_return_type())))
         .then_assert(single(Token::Semicolon))
-        .map(|((name, arguments), return_type)| Method { name, arguments, return_type })
+        .map(|(((name, name_span), arguments), return_type)| {
+            let streaming = matches!(return_type, Some((_, true)));
+            Method { name, name_span, arguments, return_type: return_type.map(|(ty, _)| ty), streaming }
+        })
 }
 
-fn parse_argument() -> impl Parser<Error = crate::SourceError, Output = (String, Type), Input = Token> {
-    single_by(|t| matches!(t, Token::Identifier(_)))
-        .map(Token::into_identifier)
+/// Either a plain type or `stream<T>`, along with whether it was a stream
+fn parse_return_type() -> impl Parser<Error = crate::SourceError, Output = (Type, bool), Input = Token> {
+    delimited(
+        single(Token::Keyword(Keyword::Stream)).then(single(Token::LeftAngleBracket)),
+        parse_type().label("expected type inside `stream<...>`"),
+        single(Token::RightAngleBracket),
+    )
+    .map(|ty| (ty, true))
+    .or(parse_type().map(|ty| (ty, false)))
+}
+
+fn parse_argument() -> impl Parser<Error = crate::SourceError, Output = (String, Type, Span), Input = Token> {
+    parse_ident()
+        .with_span()
         .then_assert(single(Token::Colon))
-        .then(parse_type())
+        .then(parse_type().label("expected type after `:`"))
+        .map(|((name, span), ty)| (name, ty, span))
 }
 
 fn parse_type() -> impl Parser<Error = crate::SourceError, Output = Type, Input = Token> {
@@ -216,7 +253,7 @@ fn parse_ident() -> impl Parser<Error = crate::SourceError, Output = String, Inp
 }
 
 fn parse_attributes_then_type_definition() -> impl Parser<Error = crate::SourceError, Output = AstNode, Input = Token> {
-    many1(single(Token::At).then_to(parse_ident()))
+    many1(parse_attribute())
         .then(hinted_choice((
             (Token::Keyword(Keyword::Struct), parse_struct_definition().map(TypeDefinition::Struct)),
             (Token::Keyword(Keyword::Enum), parse_enum_definition().map(TypeDefinition::Enum)),
@@ -224,62 +261,77 @@ fn parse_attributes_then_type_definition() -> impl Parser<Error = crate::SourceE
         .map(|(attrs, def)| AstNode::TypeDefinition(attrs, def))
 }
 
+fn parse_attribute() -> impl Parser<Error = crate::SourceError, Output = Attribute, Input = Token> {
+    single(Token::At)
+        .then_to(parse_ident())
+        .then(maybe(delimited(
+            single(Token::LeftParenthesis),
+            single_by(|t| !matches!(t, Token::Comma | Token::RightParenthesis))
+                .separated_by(single(Token::Comma))
+                .allow_trailing(),
+            single(Token::RightParenthesis),
+        )))
+        .with_span()
+        .map(|((name, arguments), span)| Attribute { name, span, arguments })
+}
+
 fn parse_struct_definition() -> impl Parser<Error = crate::SourceError, Output = Struct, Input = Token> {
     single(Token::Keyword(Keyword::Struct))
-        .then_to(parse_ident())
+        .then_to(parse_ident().with_span())
         .then(maybe(delimited(
             single(Token::LeftAngleBracket),
             parse_ident().separated_by(single(Token::Comma)).allow_trailing(),
             single(Token::RightAngleBracket),
         )))
-        .then(delimited(
-            single(Token::LeftBrace),
-            parse_ident()
-                .then_assert(single(Token::Colon))
-                .then(parse_type())
-                .map(|(name, ty)| Field { name, ty })
-                .separated_by(single(Token::Comma))
-                .allow_trailing(),
-            single(Token::RightBrace),
-        ))
-        .map(|((name, generics), fields)| Struct { name, generics, fields })
+        .then(delimited(single(Token::LeftBrace), parse_fields(), single(Token::RightBrace)))
+        .map(|(((name, name_span), generics), fields)| Struct { name, name_span, generics, fields })
 }
 
 fn parse_enum_definition() -> impl Parser<Error = crate::SourceError, Output = Enum, Input = Token> {
     single(Token::Keyword(Keyword::Enum))
-        .then_to(parse_ident())
+        .then_to(parse_ident().with_span())
         .then(maybe(delimited(
             single(Token::LeftAngleBracket),
             parse_ident().separated_by(single(Token::Comma)).allow_trailing(),
             single(Token::RightAngleBracket),
         )))
+        .then(maybe(single(Token::Colon).then_to(parse_ident())))
         .then(delimited(
             single(Token::LeftBrace),
             parse_ident()
+                .with_span()
                 .then(maybe(parse_enum_variant_data()))
-                .map(|(name, associated_data)| Variant { name, associated_data })
+                .then(maybe(
+                    single(Token::Equals).then_to(single_by(|t| matches!(t, Token::Number(_))).map(Token::into_number)),
+                ))
+                .map(|(((name, name_span), associated_data), discriminant)| Variant {
+                    name,
+                    name_span,
+                    associated_data,
+                    discriminant,
+                })
                 .separated_by(single(Token::Comma))
                 .allow_trailing(),
             single(Token::RightBrace),
         ))
-        .map(|((name, generics), variants)| Enum { name, generics, variants })
+        .map(|((((name, name_span), generics), repr), variants)| Enum { name, name_span, generics, repr, variants })
+}
+
+fn parse_fields() -> impl Parser<Error = crate::SourceError, Output = Vec<Field>, Input = Token> {
+    parse_ident()
+        .with_span()
+        .then_assert(single(Token::Colon))
+        .then(parse_type().label("expected type after `:`"))
+        .map(|((name, name_span), ty)| Field { name, name_span, ty })
+        .separated_by(single(Token::Comma))
+        .allow_trailing()
 }
 
 fn parse_enum_variant_data() -> impl Parser<Error = crate::SourceError, Output = VariantData, Input = Token> {
     hinted_choice((
         (
             Token::LeftBrace,
-            delimited(
-                single(Token::LeftBrace),
-                parse_ident()
-                    .then_assert(single(Token::Colon))
-                    .then(parse_type())
-                    .map(|(name, ty)| Field { name, ty })
-                    .separated_by(single(Token::Comma))
-                    .allow_trailing(),
-                single(Token::RightBrace),
-            )
-            .map(VariantData::Struct),
+            delimited(single(Token::LeftBrace), parse_fields(), single(Token::RightBrace)).map(VariantData::Struct),
         ),
         (
             Token::LeftParenthesis,
@@ -320,39 +372,51 @@ mod test {
         let parser = parser();
         let mut stream = Stream::new(tokens.into_iter());
         let mut parse = move || parser.parse(&mut stream).unwrap();
+        let span = |name: &str| {
+            let start = syntax.find(name).unwrap();
+            comb::Span { start, end: start + name.len() }
+        };
 
         assert_eq!(
             parse(),
             AstNode::Service(Service {
                 name: String::from("MyService"),
+                name_span: span("MyService"),
                 methods: alloc::vec![
                     Method {
                         name: String::from("fump"),
+                        name_span: span("fump"),
                         arguments: alloc::vec![
                             (
                                 String::from("baz"),
-                                Type::Path { path: alloc::vec![String::from("U32")], generics: None }
+                                Type::Path { path: alloc::vec![String::from("U32")], generics: None },
+                                span("baz"),
                             ),
                             (
                                 String::from("aaa"),
-                                Type::Path { path: alloc::vec![String::from("U64")], generics: None }
+                                Type::Path { path: alloc::vec![String::from("U64")], generics: None },
+                                span("aaa"),
                             ),
                         ],
                         return_type: Some(Type::Path { path: alloc::vec![String::from("T")], generics: None }),
+                        streaming: false,
                     },
                     Method {
                         name: String::from("fraz"),
+                        name_span: span("fraz"),
                         arguments: alloc::vec![
                             (
                                 String::from("baz2"),
-                                Type::Path { path: alloc::vec![String::from("Yeet")], generics: None }
+                                Type::Path { path: alloc::vec![String::from("Yeet")], generics: None },
+                                span("baz2"),
                             ),
                             (
                                 String::from("aaa2"),
                                 Type::Slice(Box::new(Type::Slice(Box::new(Type::Slice(Box::new(Type::Path {
                                     path: alloc::vec![String::from("U64")],
                                     generics: None,
-                                }))))))
+                                })))))),
+                                span("aaa2"),
                             )
                         ],
                         return_type: Some(Type::Path {
@@ -362,9 +426,38 @@ mod test {
                                 Type::Path { path: alloc::vec![String::from("Bar")], generics: None }
                             ])
                         }),
+                        streaming: false,
                     },
                 ]
             })
         );
     }
+
+    #[test]
+    fn labelled_errors() {
+        let tokens = comb::combinators::many0(lexer()).parse(&mut Stream::from_str("baz: ;")).unwrap();
+        let error = parse_argument().parse(&mut Stream::new(tokens.into_iter())).unwrap_err();
+
+        match error.kind {
+            crate::SourceErrorKind::Custom(message) => {
+                assert!(message.starts_with("expected type after `:`: "), "{}", message)
+            }
+            kind => panic!("unexpected error kind: {:?}", kind),
+        }
+        assert_eq!(error.span, Some(comb::Span { start: 5, end: 6 }));
+    }
+
+    #[test]
+    fn error_span_is_source_bytes() {
+        // The argument that fails to parse starts at the 11th token, but the
+        // span should be its byte range in the source rather than its index in
+        // the token stream
+        let syntax = "service Foo {\n    fn bar(baz: U32, qux: ;) -> U8;\n}";
+        let tokens = comb::combinators::many0(lexer()).parse(&mut Stream::from_str(syntax)).unwrap();
+        assert_eq!(tokens[3].1, comb::Span { start: 18, end: 20 });
+
+        let error = parser().parse(&mut Stream::new(tokens.into_iter())).unwrap_err();
+        let start = syntax.find("qux").unwrap();
+        assert_eq!(error.span, Some(comb::Span { start, end: start + 3 }));
+    }
 }
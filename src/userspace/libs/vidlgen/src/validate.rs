@@ -0,0 +1,268 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checks run on the AST before it's lowered, so names which would produce
+//! broken Rust are reported against the VIDL source instead of as confusing
+//! errors in the generated code
+
+use crate::{
+    parser::{AstNode, Field, Service, TypeDefinition, VariantData},
+    CompileError, SourceError, SourceErrorKind,
+};
+use alloc::{collections::BTreeMap, format, string::String};
+use comb::Span;
+
+/// Words which can't be used as identifiers in Rust 2021
+const KEYWORDS: &[&str] = &[
+    "Self", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match",
+    "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "self", "static", "struct", "super", "trait",
+    "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Keywords which can't be used as raw identifiers either
+const NOT_RAW: &[&str] = &["Self", "crate", "self", "super"];
+
+/// Methods generated on the server and client types for every service
+const GENERATED_METHODS: &[&str] = &["new", "serve"];
+
+pub(crate) fn validate(ast: &[AstNode]) -> Result<(), CompileError> {
+    // Every top-level item, including the ones generated for each service,
+    // along with the name of the definition it came from
+    let mut items = Names::new("item");
+
+    for node in ast {
+        match node {
+            AstNode::Service(service) => {
+                validate_service(service)?;
+
+                for generated in ["", "Provider", "Client"] {
+                    for prefix in ["", "Async"] {
+                        let name = format!("{}{}{}", prefix, service.name, generated);
+                        items.insert_generated(&name, &service.name, service.name_span)?;
+                    }
+                }
+
+                for method in &service.methods {
+                    let id = format!("{}_ID", crate::method_id(service, method));
+                    items.insert_generated(&id, &method.name, method.name_span)?;
+                }
+            }
+            AstNode::TypeDefinition(_, TypeDefinition::Struct(strukt)) => {
+                identifier(&strukt.name, strukt.name_span)?;
+                items.insert(&strukt.name, strukt.name_span)?;
+                validate_fields(&strukt.fields, "field")?;
+            }
+            AstNode::TypeDefinition(_, TypeDefinition::Enum(enoom)) => {
+                identifier(&enoom.name, enoom.name_span)?;
+                items.insert(&enoom.name, enoom.name_span)?;
+
+                let mut variants = Names::new("variant");
+                for variant in &enoom.variants {
+                    identifier(&variant.name, variant.name_span)?;
+                    variants.insert(&variant.name, variant.name_span)?;
+
+                    if let Some(VariantData::Struct(fields)) = &variant.associated_data {
+                        validate_fields(fields, "field")?;
+                    }
+                }
+            }
+            AstNode::Use(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_service(service: &Service) -> Result<(), CompileError> {
+    identifier(&service.name, service.name_span)?;
+
+    let mut methods = Names::new("method");
+    for method in &service.methods {
+        identifier(&method.name, method.name_span)?;
+        methods.insert(&method.name, method.name_span)?;
+
+        if GENERATED_METHODS.contains(&unraw(&method.name)) {
+            return Err(error(
+                format!(
+                    "method `{}` collides with the `{}` method generated for service `{}`",
+                    method.name, method.name, service.name
+                ),
+                method.name_span,
+            ));
+        }
+
+        let mut arguments = Names::new("argument");
+        for (name, _, span) in &method.arguments {
+            identifier(name, *span)?;
+            arguments.insert(name, *span)?;
+
+            // The server is handed the `StreamSender` as an extra argument
+            if method.streaming && unraw(name) == "stream" {
+                return Err(error(
+                    format!("argument `{}` collides with the `stream` argument generated for streaming methods", name),
+                    *span,
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn validate_fields(fields: &[Field], kind: &'static str) -> Result<(), CompileError> {
+    let mut names = Names::new(kind);
+    for field in fields {
+        identifier(&field.name, field.name_span)?;
+        names.insert(&field.name, field.name_span)?;
+    }
+
+    Ok(())
+}
+
+/// Check that `name` can be used as an identifier in the generated Rust
+fn identifier(name: &str, span: Span) -> Result<(), CompileError> {
+    let is_raw = name.starts_with("r#");
+    let name = unraw(name);
+
+    match (is_raw, NOT_RAW.contains(&name), KEYWORDS.contains(&name)) {
+        (_, true, _) => Err(error(format!("`{}` is a Rust keyword and can't be used as a name", name), span)),
+        (false, _, true) => Err(error(
+            format!("`{0}` is a Rust keyword, use a raw identifier like `r#{0}` or pick another name", name),
+            span,
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn unraw(name: &str) -> &str {
+    name.strip_prefix("r#").unwrap_or(name)
+}
+
+fn error(message: String, span: Span) -> CompileError {
+    CompileError::SourceError(SourceError { kind: SourceErrorKind::Custom(message), span: Some(span) })
+}
+
+/// Names which must be unique within some scope, and where they came from
+struct Names<'a> {
+    kind: &'static str,
+    seen: BTreeMap<String, &'a str>,
+}
+
+impl<'a> Names<'a> {
+    fn new(kind: &'static str) -> Self {
+        Self { kind, seen: BTreeMap::new() }
+    }
+
+    fn insert(&mut self, name: &'a str, span: Span) -> Result<(), CompileError> {
+        self.insert_generated(name, name, span)
+    }
+
+    /// Record `name`, which is generated from `source` in the VIDL, returning
+    /// an error if it's already taken. Raw identifiers are the same name as
+    /// their plain form.
+    fn insert_generated(&mut self, name: &str, source: &'a str, span: Span) -> Result<(), CompileError> {
+        let Some(previous) = self.seen.insert(String::from(unraw(name)), source) else { return Ok(()) };
+
+        match previous == source {
+            true => Err(error(format!("duplicate {} `{}`", self.kind, source), span)),
+            false => Err(error(
+                format!("`{}` generates `{}`, which is already defined by `{}`", source, unraw(name), previous),
+                span,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Compiler, SourceErrorKind};
+    use alloc::string::{String, ToString};
+
+    fn error(source: &str) -> (String, comb::Span) {
+        match Compiler::new(false).compile(source) {
+            Err(crate::CompileError::SourceError(crate::SourceError {
+                kind: SourceErrorKind::Custom(message),
+                span: Some(span),
+            })) => (message, span),
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("`{}` compiled", source),
+        }
+    }
+
+    fn span_of(source: &str, needle: &str, nth: usize) -> comb::Span {
+        let start = source.match_indices(needle).nth(nth).unwrap().0;
+        comb::Span { start, end: start + needle.len() }
+    }
+
+    #[test]
+    fn duplicate_method() {
+        let source = "service Files {\n    fn open(path: String);\n    fn open(path: String, flags: U32);\n}";
+        let (message, span) = error(source);
+
+        assert_eq!(message, "duplicate method `open`");
+        assert_eq!(span, span_of(source, "open", 1));
+
+        let pretty = Compiler::new(false).compile(source).err().unwrap().display_with(source).to_string();
+        assert_eq!(pretty, "  2 |     fn open(path: String, flags: U32);\n    |        ^^^^ duplicate method `open`\n");
+    }
+
+    #[test]
+    fn keyword_field() {
+        let source = "struct Entry { name: String, type: U8 }";
+        let (message, span) = error(source);
+
+        assert_eq!(message, "`type` is a Rust keyword, use a raw identifier like `r#type` or pick another name");
+        assert_eq!(span, span_of(source, "type", 0));
+
+        // Following the suggestion works
+        let compiled = Compiler::new(false).compile("struct Entry { name: String, r#type: U8 }").unwrap();
+        assert!(compiled.to_string().contains("    pub r#type: U8,\n"));
+
+        let (message, _) = error("struct Entry { Self: U8 }");
+        assert_eq!(message, "`Self` is a Rust keyword and can't be used as a name");
+        let (message, _) = error("struct Entry { r#self: U8 }");
+        assert_eq!(message, "`self` is a Rust keyword and can't be used as a name");
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let (message, _) = error("struct Entry { name: String, name: U8 }");
+        assert_eq!(message, "duplicate field `name`");
+
+        let (message, _) = error("enum Kind { File, Directory, File }");
+        assert_eq!(message, "duplicate variant `File`");
+
+        let (message, _) = error("enum Kind { File { len: U64, len: U32 } }");
+        assert_eq!(message, "duplicate field `len`");
+
+        let (message, _) = error("service Files { fn open(path: String, path: String); }");
+        assert_eq!(message, "duplicate argument `path`");
+
+        let (message, _) = error("struct Files { a: U8 }\nenum Files { A }");
+        assert_eq!(message, "duplicate item `Files`");
+    }
+
+    #[test]
+    fn generated_collisions() {
+        // Both generate `FILES_OPEN_ID`
+        let source = "service Files { fn open(a: U8); fn OPEN(a: U8); }";
+        let (message, span) = error(source);
+        assert_eq!(message, "`OPEN` generates `FILES_OPEN_ID`, which is already defined by `open`");
+        assert_eq!(span, span_of(source, "OPEN", 0));
+
+        let (message, _) = error("struct FilesClient { a: U8 }\nservice Files { fn open(a: U8); }");
+        assert_eq!(message, "`Files` generates `FilesClient`, which is already defined by `FilesClient`");
+
+        let (message, _) = error("service Files { fn new(a: U8); }");
+        assert_eq!(message, "method `new` collides with the `new` method generated for service `Files`");
+
+        let (message, _) = error("service Logs { fn tail(r#stream: U8) -> stream<String>; }");
+        assert_eq!(message, "argument `r#stream` collides with the `stream` argument generated for streaming methods");
+        assert!(Compiler::new(false).compile("service Logs { fn tail(r#stream: U8) -> String; }").is_ok());
+    }
+}
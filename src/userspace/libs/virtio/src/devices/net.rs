@@ -176,6 +176,9 @@ impl NetDeviceFeatures {
     pub const GUEST_ANNOUNCE: Self = Self(1 << 21);
     pub const MULTIQUEUE: Self = Self(1 << 22);
     pub const CONTROL_MAC_ADDRESS: Self = Self(1 << 23);
+    /// Device complies with the modern (non-legacy) VirtIO interface, which is
+    /// required to be negotiated for version 2 MMIO devices
+    pub const VERSION_1: Self = Self(1 << 32);
     pub const HOST_USO: Self = Self(1 << 56);
     pub const HASH_REPORT: Self = Self(1 << 57);
     pub const GUEST_HEADER_LENGTH: Self = Self(1 << 59);
@@ -207,6 +210,7 @@ impl NetDeviceFeatures {
                 | Self::GUEST_ANNOUNCE.0
                 | Self::MULTIQUEUE.0
                 | Self::CONTROL_MAC_ADDRESS.0
+                | Self::VERSION_1.0
                 | Self::HOST_USO.0
                 | Self::HASH_REPORT.0
                 | Self::GUEST_HEADER_LENGTH.0
@@ -315,6 +319,9 @@ pub struct VirtIoNetHeaderTx<const N: usize> {
     pub gso_size: u16,
     pub checksum_start: u16,
     pub checksum_offset: u16,
+    /// Always present once [`NetDeviceFeatures::VERSION_1`] has been
+    /// negotiated, even without [`NetDeviceFeatures::MERGE_RXBUFFERS`]
+    pub num_buffers: u16,
     pub data: [u8; N],
 }
 
@@ -327,6 +334,9 @@ pub struct VirtIoNetHeaderRx<const N: usize> {
     pub gso_size: u16,
     pub checksum_start: u16,
     pub checksum_offset: u16,
+    /// Always present once [`NetDeviceFeatures::VERSION_1`] has been
+    /// negotiated, even without [`NetDeviceFeatures::MERGE_RXBUFFERS`]
+    pub num_buffers: u16,
     pub data: [u8; N],
 }
 
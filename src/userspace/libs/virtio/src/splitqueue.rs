@@ -165,6 +165,19 @@ impl AvailableQueue {
             core::ptr::write_volatile(&mut self.queue.index, queue_index.wrapping_add(1));
         }
     }
+
+    /// Ask the device not to send interrupts when it uses buffers. This is
+    /// only a hint, so the device may still interrupt, and any buffers used
+    /// while interrupts are suppressed must be checked for after turning them
+    /// back on.
+    pub fn suppress_interrupts(&mut self, suppress: bool) {
+        let flags = match suppress {
+            true => VirtqueueAvailable::NO_INTERRUPT,
+            false => 0,
+        };
+
+        unsafe { core::ptr::write_volatile(&mut self.queue.flags, flags) };
+    }
 }
 
 #[repr(C)]
@@ -174,6 +187,11 @@ struct VirtqueueAvailable {
     ring: [u16],
 }
 
+impl VirtqueueAvailable {
+    /// `VIRTQ_AVAIL_F_NO_INTERRUPT`
+    const NO_INTERRUPT: u16 = 1;
+}
+
 pub struct UsedQueue {
     queue: DmaRegion<VirtqueueUsed>,
     last_seen: u16,
@@ -200,6 +218,19 @@ impl UsedQueue {
         }
     }
 
+    /// Whether there are no used elements left to [`UsedQueue::pop`]
+    pub fn is_empty(&self) -> bool {
+        self.last_seen == unsafe { core::ptr::read_volatile(&self.queue.index) }
+    }
+
+    /// Whether the device has asked not to be notified of new available
+    /// buffers, e.g. because it's already processing the queue. The driver
+    /// must perform a full memory barrier between updating the available ring
+    /// and checking this.
+    pub fn notifications_suppressed(&self) -> bool {
+        unsafe { core::ptr::read_volatile(&self.queue.flags) & VirtqueueUsed::NO_NOTIFY != 0 }
+    }
+
     pub fn drain(&mut self) -> impl Iterator<Item = VirtqueueUsedElement> + '_ {
         // FIXME: should this be a fused iterator or no?
         core::iter::from_fn(move || self.pop())
@@ -213,6 +244,11 @@ pub struct VirtqueueUsed {
     ring: [VirtqueueUsedElement],
 }
 
+impl VirtqueueUsed {
+    /// `VIRTQ_USED_F_NO_NOTIFY`
+    const NO_NOTIFY: u16 = 1;
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub struct VirtqueueUsedElement {
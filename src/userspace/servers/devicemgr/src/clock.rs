@@ -0,0 +1,93 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::pci::cells;
+use fdt::{node::FdtNode, Fdt};
+
+/// Extension trait for determining the rate of the clock driving a device
+/// node
+pub trait FdtNodeClockExt {
+    /// The frequency in Hz of the clock driving the device. This is either the
+    /// node's own `clock-frequency` property, or the rate of the first clock
+    /// referenced by its `clocks` property, which is looked up in any
+    /// `assigned-clock-rates` for it, falling back to the clock provider's
+    /// `clock-frequency`. Returns `None` if the frequency can't be determined.
+    fn clock_frequency(&self, fdt: &Fdt<'_>) -> Option<u64>;
+}
+
+impl FdtNodeClockExt for FdtNode<'_, '_> {
+    fn clock_frequency(&self, fdt: &Fdt<'_>) -> Option<u64> {
+        if let Some(frequency) = self.property("clock-frequency") {
+            return read_frequency(frequency.value);
+        }
+
+        let mut clocks = cells(self.property("clocks")?.value);
+        let phandle = clocks.next()?;
+        let provider = fdt.find_phandle(phandle)?;
+        let specifier = clocks.take(clock_cells(&provider)).collect::<Vec<_>>();
+
+        assigned_rate(fdt, self, phandle, &specifier)
+            .or_else(|| assigned_rate(fdt, &provider, phandle, &specifier))
+            .or_else(|| read_frequency(provider.property("clock-frequency")?.value))
+    }
+}
+
+/// Look up the rate assigned to the clock identified by `phandle` and
+/// `specifier` in the `assigned-clocks` and `assigned-clock-rates` properties
+/// of `node`
+fn assigned_rate(fdt: &Fdt<'_>, node: &FdtNode<'_, '_>, phandle: u32, specifier: &[u32]) -> Option<u64> {
+    let mut assigned_clocks = cells(node.property("assigned-clocks")?.value);
+    let mut rates = cells(node.property("assigned-clock-rates")?.value);
+
+    loop {
+        let assigned_phandle = assigned_clocks.next()?;
+        let n_cells = clock_cells(&fdt.find_phandle(assigned_phandle)?);
+        let assigned_specifier = assigned_clocks.by_ref().take(n_cells).collect::<Vec<_>>();
+        let rate = rates.next()?;
+
+        // A rate of zero means the rate of that clock is left unchanged
+        if assigned_phandle == phandle && assigned_specifier == specifier && rate != 0 {
+            break Some(u64::from(rate));
+        }
+    }
+}
+
+fn clock_cells(provider: &FdtNode<'_, '_>) -> usize {
+    provider.property("#clock-cells").and_then(|p| p.as_usize()).unwrap_or(0)
+}
+
+fn read_frequency(value: &[u8]) -> Option<u64> {
+    match value.len() {
+        4 => Some(u64::from(u32::from_be_bytes(value.try_into().ok()?))),
+        8 => Some(u64::from_be_bytes(value.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compiled from `testdata/clocks.dts`
+    static CLOCKS_DTB: &[u8] = include_bytes!("../testdata/clocks.dtb");
+
+    fn uart_clock_frequency(fdt: &Fdt<'_>, address: &str) -> Option<u64> {
+        let path = format!("/soc/serial@{}", address);
+        fdt.find_node(&path).unwrap().clock_frequency(fdt)
+    }
+
+    #[test_case]
+    fn uart_clock_frequency_from_clock_provider() {
+        let fdt = Fdt::new(CLOCKS_DTB).unwrap();
+
+        assert_eq!(uart_clock_frequency(&fdt, "10000000"), Some(1_843_200));
+        assert_eq!(uart_clock_frequency(&fdt, "10001000"), Some(100_000_000));
+        assert_eq!(uart_clock_frequency(&fdt, "10002000"), Some(3_686_400));
+        assert_eq!(uart_clock_frequency(&fdt, "10003000"), None);
+        assert_eq!(uart_clock_frequency(&fdt, "10004000"), None);
+    }
+}
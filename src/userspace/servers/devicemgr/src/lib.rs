@@ -5,4 +5,6 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
+
 vidl::vidl_include!("devicemgr");
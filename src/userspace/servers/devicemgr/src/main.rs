@@ -5,7 +5,14 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
+
+mod clock;
+mod pci;
+
+use clock::FdtNodeClockExt;
 use librust::capabilities::{Capability, CapabilityRights};
+use pci::FdtPciExt;
 
 pub struct Provider<'a> {
     fdt: &'a fdt::Fdt<'a>,
@@ -41,11 +48,10 @@ impl devicemgr::DevicemgrProvider for Provider<'_> {
 }
 
 fn main() {
-    let args = std::env::args();
     let ptr = std::env::a2() as *const u8;
     let fdt = unsafe { fdt::Fdt::from_ptr(ptr) }.unwrap();
 
-    if args.contains(&"debug") {
+    if std::env::args().any(|arg| arg == "debug") {
         for node in fdt.all_nodes() {
             println!("{}: ", node.name);
             for prop in node.properties() {
@@ -56,6 +62,33 @@ fn main() {
                     _ => println!("    {}={:?}", prop.name, prop.value),
                 }
             }
+
+            if node.property("clocks").is_some() {
+                if let Some(frequency) = node.clock_frequency(&fdt) {
+                    println!("    (clock frequency: {} Hz)", frequency);
+                }
+            }
+        }
+
+        if let Some(pci) = fdt.pci() {
+            println!("[devicemgr] PCI host bridge {} (buses: {:?})", pci.node().name, pci.bus_range());
+            match pci.regions() {
+                Ok(regions) => {
+                    for region in regions {
+                        println!(
+                            "    {:?} (prefetchable: {}): PCI {:#x} -> CPU {:#x} (size: {:#x})",
+                            region.space, region.prefetchable, region.pci_address, region.cpu_address, region.size
+                        );
+                    }
+                }
+                Err(e) => println!("    Failed to read PCI regions: {:?}", e),
+            }
+
+            for device in 0..32 {
+                if let Some(int) = pci.interrupt_for(device, 1) {
+                    println!("    Device {} INTA -> {} (parent: {})", device, int.interrupt, int.parent_phandle);
+                }
+            }
         }
     }
     librust::syscalls::task::enable_notifications();
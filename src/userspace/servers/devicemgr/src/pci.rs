@@ -0,0 +1,288 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use fdt::{node::FdtNode, Fdt};
+
+const PCI_HOST_COMPATIBLE: &[&str] = &["pci-host-ecam-generic", "pci-host-cam-generic"];
+const PCI_ADDRESS_CELLS: usize = 3;
+
+/// Extension trait for locating the PCI host bridge in the devicetree
+pub trait FdtPciExt {
+    /// Returns a view of the PCI host bridge node, or `None` if the
+    /// devicetree doesn't contain one
+    fn pci(&self) -> Option<Pci<'_, '_>>;
+}
+
+impl<'a> FdtPciExt for Fdt<'a> {
+    fn pci(&self) -> Option<Pci<'_, '_>> {
+        let node = self.find_compatible(PCI_HOST_COMPATIBLE)?;
+        Some(Pci { fdt: self, node })
+    }
+}
+
+/// A PCI host bridge devicetree node
+#[derive(Clone, Copy)]
+pub struct Pci<'b, 'a> {
+    fdt: &'b Fdt<'a>,
+    node: FdtNode<'b, 'a>,
+}
+
+impl<'b, 'a> Pci<'b, 'a> {
+    /// The underlying devicetree node
+    pub fn node(self) -> FdtNode<'b, 'a> {
+        self.node
+    }
+
+    /// The inclusive range of bus numbers decoded by the host bridge
+    pub fn bus_range(self) -> Option<core::ops::RangeInclusive<u32>> {
+        let mut cells = cells(self.node.property("bus-range")?.value);
+        Some(cells.next()?..=cells.next()?)
+    }
+
+    /// The address regions that the host bridge forwards transactions for,
+    /// decoded from the `ranges` property. Fails if the CPU addresses or sizes
+    /// are too large to fit in a `u64`.
+    pub fn regions(self) -> Result<impl Iterator<Item = PciRegion> + 'b, PciError> {
+        let parent_address_cells = check_cells(self.parent_address_cells())?;
+        let size_cells = check_cells(self.node.cell_sizes().size_cells)?;
+        let entry_cells = PCI_ADDRESS_CELLS + parent_address_cells + size_cells;
+
+        Ok(self.node.property("ranges").map(|p| p.value).unwrap_or_default().chunks_exact(entry_cells * 4).map(
+            move |entry| {
+                let mut cells = cells(entry);
+                let phys_hi = cells.next().unwrap();
+                let pci_address = read_u64(&mut cells, 2);
+                let cpu_address = read_u64(&mut cells, parent_address_cells);
+                let size = read_u64(&mut cells, size_cells);
+
+                PciRegion {
+                    space: PciAddressSpace::from_phys_hi(phys_hi),
+                    prefetchable: phys_hi & (1 << 30) != 0,
+                    pci_address,
+                    cpu_address,
+                    size,
+                }
+            },
+        ))
+    }
+
+    /// Look up the interrupt routed to the given device number and interrupt
+    /// pin (1 = INTA, 4 = INTD) on bus 0, applying the `interrupt-map-mask` to
+    /// match an entry in the `interrupt-map`
+    pub fn interrupt_for(self, device: u8, pin: u8) -> Option<PciInterrupt> {
+        let interrupt_cells = self.node.property("#interrupt-cells").and_then(|p| p.as_usize()).unwrap_or(1);
+
+        let mut mask = [u32::MAX; PCI_ADDRESS_CELLS + 1];
+        if let Some(map_mask) = self.node.property("interrupt-map-mask") {
+            for (mask, cell) in mask.iter_mut().zip(cells(map_mask.value)) {
+                *mask = cell;
+            }
+        }
+
+        let wanted = [(u32::from(device) << 11) & mask[0], 0, 0, u32::from(pin) & mask[PCI_ADDRESS_CELLS]];
+        let map = self.node.property("interrupt-map")?.value;
+        let mut cells = cells(map).peekable();
+
+        while cells.peek().is_some() {
+            let mut child = [0; PCI_ADDRESS_CELLS + 1];
+            for cell in &mut child[..PCI_ADDRESS_CELLS] {
+                *cell = cells.next()?;
+            }
+            child[PCI_ADDRESS_CELLS] = cells.next()?;
+            for _ in 1..interrupt_cells {
+                cells.next()?;
+            }
+
+            let parent_phandle = cells.next()?;
+            let parent = self.fdt.find_phandle(parent_phandle)?;
+            let parent_address_cells = parent.property("#address-cells").and_then(|p| p.as_usize()).unwrap_or(0);
+            let parent_interrupt_cells = parent.interrupt_cells().unwrap_or(1);
+
+            for _ in 0..parent_address_cells {
+                cells.next()?;
+            }
+
+            let interrupt = cells.next()?;
+            for _ in 1..parent_interrupt_cells {
+                cells.next()?;
+            }
+
+            let masked = [child[0] & mask[0], child[1] & mask[1], child[2] & mask[2], child[3] & mask[3]];
+            if masked == wanted {
+                return Some(PciInterrupt { parent_phandle, interrupt });
+            }
+        }
+
+        None
+    }
+
+    /// The `#address-cells` of the bus the host bridge sits on, which sizes
+    /// the CPU address of each `ranges` entry. Node names point into the
+    /// devicetree blob, so they identify the host bridge among its parent's
+    /// children.
+    fn parent_address_cells(self) -> usize {
+        self.fdt
+            .all_nodes()
+            .find(|node| node.children().any(|child| core::ptr::eq(child.name, self.node.name)))
+            .map(|parent| parent.cell_sizes().address_cells)
+            .unwrap_or(2)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciError {
+    /// An `#address-cells` or `#size-cells` value larger than 2, which would
+    /// overflow a `u64`
+    UnsupportedCellCount(usize),
+}
+
+/// The PCI address space a region belongs to, decoded from the `ss` bits of
+/// the `phys.hi` cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciAddressSpace {
+    Configuration,
+    Io,
+    Memory32,
+    Memory64,
+}
+
+impl PciAddressSpace {
+    fn from_phys_hi(phys_hi: u32) -> Self {
+        match (phys_hi >> 24) & 0b11 {
+            0b00 => Self::Configuration,
+            0b01 => Self::Io,
+            0b10 => Self::Memory32,
+            _ => Self::Memory64,
+        }
+    }
+}
+
+/// A region of PCI address space mapped into the CPU's physical address space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciRegion {
+    pub space: PciAddressSpace,
+    pub prefetchable: bool,
+    pub pci_address: u64,
+    pub cpu_address: u64,
+    pub size: u64,
+}
+
+/// An interrupt routed from a PCI device to an interrupt controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciInterrupt {
+    /// The phandle of the interrupt controller the interrupt is routed to
+    pub parent_phandle: u32,
+    /// The interrupt number on the parent interrupt controller
+    pub interrupt: u32,
+}
+
+pub(crate) fn cells(bytes: &[u8]) -> impl Iterator<Item = u32> + '_ {
+    bytes.chunks_exact(4).map(|cell| u32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]]))
+}
+
+fn check_cells(count: usize) -> Result<usize, PciError> {
+    match count {
+        0..=2 => Ok(count),
+        _ => Err(PciError::UnsupportedCellCount(count)),
+    }
+}
+
+fn read_u64(cells: &mut impl Iterator<Item = u32>, count: usize) -> u64 {
+    cells.take(count).fold(0, |value, cell| (value << 32) | u64::from(cell))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compiled from `testdata/qemu-virt-pci.dts`
+    static QEMU_VIRT_DTB: &[u8] = include_bytes!("../testdata/qemu-virt-pci.dtb");
+
+    #[test_case]
+    fn qemu_virt_host_bridge() {
+        let fdt = Fdt::new(QEMU_VIRT_DTB).unwrap();
+        let pci = fdt.pci().unwrap();
+
+        assert_eq!(pci.node().name, "pci@30000000");
+        assert_eq!(pci.bus_range(), Some(0..=0xFF));
+
+        let regions: Vec<_> = pci.regions().unwrap().collect();
+        assert_eq!(
+            regions,
+            [
+                PciRegion {
+                    space: PciAddressSpace::Io,
+                    prefetchable: false,
+                    pci_address: 0,
+                    cpu_address: 0x0300_0000,
+                    size: 0x1_0000,
+                },
+                PciRegion {
+                    space: PciAddressSpace::Memory32,
+                    prefetchable: false,
+                    pci_address: 0x4000_0000,
+                    cpu_address: 0x4000_0000,
+                    size: 0x4000_0000,
+                },
+                PciRegion {
+                    space: PciAddressSpace::Memory64,
+                    prefetchable: false,
+                    pci_address: 0x4_0000_0000,
+                    cpu_address: 0x4_0000_0000,
+                    size: 0x4_0000_0000,
+                },
+            ]
+        );
+    }
+
+    #[test_case]
+    fn cpu_addresses_use_the_parent_bus_cells() {
+        // Compiled from `testdata/pci-narrow-bus.dts`
+        static NARROW_BUS_DTB: &[u8] = include_bytes!("../testdata/pci-narrow-bus.dtb");
+
+        let fdt = Fdt::new(NARROW_BUS_DTB).unwrap();
+        let regions: Vec<_> = fdt.pci().unwrap().regions().unwrap().collect();
+        assert_eq!(
+            regions,
+            [
+                PciRegion {
+                    space: PciAddressSpace::Io,
+                    prefetchable: false,
+                    pci_address: 0,
+                    cpu_address: 0x0300_0000,
+                    size: 0x1_0000,
+                },
+                PciRegion {
+                    space: PciAddressSpace::Memory32,
+                    prefetchable: false,
+                    pci_address: 0x4000_0000,
+                    cpu_address: 0x4000_0000,
+                    size: 0x4000_0000,
+                },
+            ]
+        );
+    }
+
+    #[test_case]
+    fn qemu_virt_interrupts_are_swizzled() {
+        let fdt = Fdt::new(QEMU_VIRT_DTB).unwrap();
+        let pci = fdt.pci().unwrap();
+        let interrupt = |device, pin| pci.interrupt_for(device, pin).map(|int| int.interrupt);
+
+        assert_eq!(pci.interrupt_for(0, 1), Some(PciInterrupt { parent_phandle: 3, interrupt: 0x20 }));
+        assert_eq!(interrupt(1, 1), Some(0x21));
+        assert_eq!(interrupt(3, 2), Some(0x20));
+        // Only the low two bits of the device number are matched
+        assert_eq!(interrupt(5, 1), Some(0x21));
+    }
+
+    #[test_case]
+    fn oversized_cells_are_rejected() {
+        assert_eq!(check_cells(2), Ok(2));
+        assert_eq!(check_cells(3), Err(PciError::UnsupportedCellCount(3)));
+    }
+}
@@ -52,6 +52,15 @@ struct VirtIoBlockDeviceInner {
     queued_commands: BTreeMap<SplitqueueIndex<VirtqueueDescriptor>, QueuedCommand>,
     waiting_requests: VecDeque<WaitingCommands>,
     block_cache: LruCache<std::alloc::Global, SectorIndex, DataBlock, FxBuildHasher>,
+    stats: InterruptStats,
+}
+
+/// How many interrupts the device has sent compared to how many commands
+/// they've completed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterruptStats {
+    pub interrupts: usize,
+    pub completions: usize,
 }
 
 pub struct VirtIoBlockDevice {
@@ -107,6 +116,7 @@ impl VirtIoBlockDevice {
                 queued_commands: BTreeMap::new(),
                 waiting_requests: VecDeque::new(),
                 block_cache: LruCache::new(std::alloc::Global, NonZeroUsize::new(64).unwrap()),
+                stats: InterruptStats::default(),
             }
         }));
 
@@ -126,6 +136,10 @@ impl VirtIoBlockDevice {
 
         Ok(Self { inner, data_drop })
     }
+
+    pub fn interrupt_stats(&self) -> InterruptStats {
+        self.inner.borrow().stats
+    }
 }
 
 impl BlockDevice for VirtIoBlockDevice {
@@ -137,50 +151,76 @@ impl BlockDevice for VirtIoBlockDevice {
 
     fn handle_interrupt(&self) {
         let mut this = self.inner.borrow_mut();
-        let VirtIoBlockDeviceInner { queue, command_buffer, data_buffer, device, queued_commands, .. } = &mut *this;
-
-        while let Some(used) = queue.used.pop() {
-            let desc1 = SplitqueueIndex::new(used.start_index as u16);
-            let desc2 = queue.descriptors.read(desc1).next;
-            let desc3 = queue.descriptors.read(desc2).next;
-
-            librust::mem::fence(librust::mem::FenceMode::Full);
-            device.header.interrupt_ack.acknowledge_buffer_used();
-
-            // println!("Reclaming desc1={desc1:?} desc2={desc2:?} desc3={desc3:?}");
-
-            let Some(QueuedCommand { command_index, data_index, kind }) = queued_commands.remove(&desc1) else { break };
-
-            let command = command_buffer.get(command_index).unwrap();
-            let data_block = data_buffer.get(data_index).unwrap();
+        let VirtIoBlockDeviceInner { queue, command_buffer, data_buffer, device, queued_commands, stats, .. } =
+            &mut *this;
+
+        // Acknowledging before draining the used ring means any command which
+        // completes after this raises a new interrupt instead of being missed
+        librust::mem::fence(librust::mem::FenceMode::Full);
+        device.header.interrupt_ack.acknowledge_buffer_used();
+        stats.interrupts += 1;
+
+        // Everything completing while the ring is drained is handled by this
+        // interrupt, so the device is asked not to send more until it's empty
+        queue.available.suppress_interrupts(true);
+
+        loop {
+            while let Some(used) = queue.used.pop() {
+                let desc1 = SplitqueueIndex::new(used.start_index as u16);
+                let desc2 = queue.descriptors.read(desc1).next;
+                let desc3 = queue.descriptors.read(desc2).next;
+
+                // println!("Reclaming desc1={desc1:?} desc2={desc2:?} desc3={desc3:?}");
+
+                let Some(QueuedCommand { command_index, data_index, kind }) = queued_commands.remove(&desc1) else {
+                    println!("[filesystem] Device used an unknown descriptor: {desc1:?}");
+                    continue;
+                };
+                stats.completions += 1;
+
+                let command = command_buffer.get(command_index).unwrap();
+                let data_block = data_buffer.get(data_index).unwrap();
+
+                let command = command.get();
+                let command_status =
+                    CommandStatus::from_u8(unsafe { (*command.as_ptr()).status }).unwrap().into_result();
+
+                if let Err(e) = command_status {
+                    println!("[filesystem] Disk error: {e:?}");
+                }
 
-            let command = command.get();
-            let command_status = CommandStatus::from_u8(unsafe { (*command.as_ptr()).status }).unwrap().into_result();
+                match kind {
+                    QueuedCommandKind::Flush => {}
+                    QueuedCommandKind::Read(tx) => match command_status {
+                        Ok(_) => tx.send(unsafe { Ok(DataBlock::new(data_index, data_block.get(), &self.data_drop)) }),
+                        Err(_) => tx.send(Err(DeviceError::ReadError)),
+                    },
+                    QueuedCommandKind::Write(tx) => {
+                        match command_status {
+                            Ok(_) => tx.send(Ok(())),
+                            Err(_) => tx.send(Err(DeviceError::ReadError)),
+                        }
+
+                        data_buffer.dealloc(data_index);
+                    }
+                }
 
-            if let Err(e) = command_status {
-                println!("[filesystem] Disk error: {e:?}");
+                queue.free_descriptor(desc1);
+                queue.free_descriptor(desc2);
+                queue.free_descriptor(desc3);
+                command_buffer.dealloc(command_index);
             }
 
-            match kind {
-                QueuedCommandKind::Flush => {}
-                QueuedCommandKind::Read(tx) => match command_status {
-                    Ok(_) => tx.send(unsafe { Ok(DataBlock::new(data_index, data_block.get(), &self.data_drop)) }),
-                    Err(_) => tx.send(Err(DeviceError::ReadError)),
-                },
-                QueuedCommandKind::Write(tx) => {
-                    match command_status {
-                        Ok(_) => tx.send(Ok(())),
-                        Err(_) => tx.send(Err(DeviceError::ReadError)),
-                    }
+            // Commands completing between the last pop and interrupts being
+            // turned back on won't raise one, so check once more
+            queue.available.suppress_interrupts(false);
+            librust::mem::fence(librust::mem::FenceMode::Full);
 
-                    data_buffer.dealloc(data_index);
-                }
+            if queue.used.is_empty() {
+                break;
             }
 
-            queue.free_descriptor(desc1);
-            queue.free_descriptor(desc2);
-            queue.free_descriptor(desc3);
-            command_buffer.dealloc(command_index);
+            queue.available.suppress_interrupts(true);
         }
 
         // TODO: check for waiting commands
@@ -228,6 +268,7 @@ impl BlockDevice for VirtIoBlockDevice {
             queued_commands,
             waiting_requests,
             block_cache,
+            ..
         } = &mut *this;
 
         if let Some(data) = block_cache.get(&sector) {}
@@ -302,9 +343,12 @@ impl BlockDevice for VirtIoBlockDevice {
         queued_commands.insert(desc1, QueuedCommand { command_index, data_index, kind: QueuedCommandKind::Read(tx) });
 
         // Fence the MMIO register write since its not guaranteed to be in the
-        // same order relative to RAM read/writes
-        librust::mem::fence(librust::mem::FenceMode::Write);
-        device.header.queue_notify.notify(0);
+        // same order relative to RAM read/writes, which also orders checking
+        // whether the device wants to be notified at all after the push
+        librust::mem::fence(librust::mem::FenceMode::Full);
+        if !queue.used.notifications_suppressed() {
+            device.header.queue_notify.notify(0);
+        }
 
         Box::pin(async move { rx.recv().await })
     }
@@ -329,6 +373,7 @@ impl BlockDevice for VirtIoBlockDevice {
             queued_commands,
             waiting_requests,
             block_cache,
+            ..
         } = &mut *this;
 
         let (data_index, ptr) = DataBlock::leak(block);
@@ -391,9 +436,12 @@ impl BlockDevice for VirtIoBlockDevice {
         queued_commands.insert(desc1, QueuedCommand { command_index, data_index, kind: QueuedCommandKind::Write(tx) });
 
         // Fence the MMIO register write since its not guaranteed to be in the
-        // same order relative to RAM read/writes
-        librust::mem::fence(librust::mem::FenceMode::Write);
-        device.header.queue_notify.notify(0);
+        // same order relative to RAM read/writes, which also orders checking
+        // whether the device wants to be notified at all after the push
+        librust::mem::fence(librust::mem::FenceMode::Full);
+        if !queue.used.notifications_suppressed() {
+            device.header.queue_notify.notify(0);
+        }
 
         Box::pin(async move { rx.recv().await })
     }
@@ -510,3 +558,49 @@ impl DataBuffer {
 //         Ok(())
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use librust::syscalls::io::{complete_interrupt, query_mmio_cap};
+    use present::{futures::stream::StreamExt, interrupt::Interrupt};
+
+    /// How many reads are submitted at once, which fits in the queue without
+    /// any of them waiting for descriptors
+    const READS: usize = 16;
+
+    // `cargo xtask test_userspace` attaches a scratch disk for this, which
+    // `init` doesn't start the filesystem server on when running tests
+    #[test_case]
+    fn completions_are_batched() {
+        let virtiomgr = std::env::lookup_capability("virtiomgr").expect("no `virtiomgr` capability").capability.cptr;
+        let device = virtiomgr::VirtIoMgrClient::new(virtiomgr)
+            .request(virtio::DeviceType::BlockDevice as u32)
+            .pop()
+            .expect("no virtio block device to test with");
+        let (mmio, _) = query_mmio_cap(device.capability.cptr, &mut []).unwrap();
+        let block_device =
+            VirtIoBlockDevice::new(unsafe { &*mmio.address().cast::<virtio::devices::block::VirtIoBlockDevice>() })
+                .unwrap();
+
+        // Interest has to be registered before anything can complete
+        let mut interrupt = Interrupt::new(device.interrupts[0]);
+        let reads = (0..READS).map(|sector| block_device.read(SectorIndex::new(sector as u64))).collect::<Vec<_>>();
+
+        present::block_on(async {
+            while block_device.interrupt_stats().completions < READS {
+                let n = interrupt.next().await.unwrap();
+                block_device.handle_interrupt();
+                complete_interrupt(n).unwrap();
+            }
+
+            for read in reads {
+                assert!(read.await.is_ok());
+            }
+        });
+
+        let stats = block_device.interrupt_stats();
+        assert_eq!(stats.completions, READS);
+        assert!(stats.interrupts < READS, "{} interrupts for {} reads", stats.interrupts, READS);
+    }
+}
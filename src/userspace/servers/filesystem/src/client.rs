@@ -29,16 +29,18 @@ struct ClientProvider {
 impl filesystem::vidl::raw::AsyncFilesystemProvider for ClientProvider {
     type Error = ();
 
-    async fn open(&mut self, path: String, options: OpenOptions) -> Result<Result<File, Error>, Self::Error> {
+    async fn open(&mut self, path: &str, options: OpenOptions) -> Result<Result<File, Error>, Self::Error> {
         let fs = &self.filesystems[0];
         let root = fs.root();
-        let file = match fs.open(root, Path::new(&path), options.to_file_permissions()).await {
+        let file = match fs.open(root, Path::new(path), options.to_file_permissions()).await {
             Ok(file) => file,
             Err(e) => {
                 return match e {
                     FilesystemError::DeviceError(_) => Ok(Err(Error::IoError)),
+                    FilesystemError::OutOfSpace => Ok(Err(Error::OutOfSpace)),
                     FilesystemError::DirectoryNotFound => Ok(Err(Error::FileNotFound)),
                     FilesystemError::FileNotFound => Ok(Err(Error::FileNotFound)),
+                    FilesystemError::AlreadyExists => Ok(Err(Error::AlreadyExists)),
                     FilesystemError::InvalidPath => Ok(Err(Error::InvalidPath)),
                     FilesystemError::InvalidRoot | FilesystemError::InvalidFileId | FilesystemError::InternalError => {
                         Err(())
@@ -70,8 +72,10 @@ impl filesystem::vidl::raw::AsyncFilesystemProvider for ClientProvider {
             Ok(_) => Ok(Ok(())),
             Err(e) => match e {
                 FilesystemError::DeviceError(_) => Ok(Err(Error::IoError)),
+                FilesystemError::OutOfSpace => Ok(Err(Error::OutOfSpace)),
                 FilesystemError::DirectoryNotFound => Ok(Err(Error::FileNotFound)),
                 FilesystemError::FileNotFound => Ok(Err(Error::FileNotFound)),
+                FilesystemError::AlreadyExists => Ok(Err(Error::AlreadyExists)),
                 FilesystemError::InvalidPath => Ok(Err(Error::InvalidPath)),
                 FilesystemError::InvalidRoot | FilesystemError::InvalidFileId | FilesystemError::InternalError => {
                     Err(())
@@ -93,8 +97,10 @@ impl filesystem::vidl::raw::AsyncFilesystemProvider for ClientProvider {
             Err(e) => {
                 return match e {
                     FilesystemError::DeviceError(_) => Ok(Err(Error::IoError)),
+                    FilesystemError::OutOfSpace => Ok(Err(Error::OutOfSpace)),
                     FilesystemError::DirectoryNotFound => Ok(Err(Error::FileNotFound)),
                     FilesystemError::FileNotFound => Ok(Err(Error::FileNotFound)),
+                    FilesystemError::AlreadyExists => Ok(Err(Error::AlreadyExists)),
                     FilesystemError::InvalidPath => Ok(Err(Error::InvalidPath)),
                     FilesystemError::InvalidRoot | FilesystemError::InvalidFileId | FilesystemError::InternalError => {
                         Err(())
@@ -108,6 +114,30 @@ impl filesystem::vidl::raw::AsyncFilesystemProvider for ClientProvider {
 
         Ok(Ok(len))
     }
+
+    async fn write(&mut self, handle: FileHandle, len: usize) -> Result<Result<usize, Error>, Self::Error> {
+        let opened_file = match self.opened_files.get(&handle) {
+            Some(opened_file) => opened_file,
+            None => return Ok(Err(Error::InvalidHandle)),
+        };
+
+        let data = opened_file.buffer.read()[..usize::min(len, opened_file.buffer.len())].to_vec();
+        match opened_file.filesystem.write_file_block(FileId::clone(&opened_file.id), data).await {
+            Ok(written) => Ok(Ok(written)),
+            Err(e) => match e {
+                FilesystemError::DeviceError(_) => Ok(Err(Error::IoError)),
+                FilesystemError::OutOfSpace => Ok(Err(Error::OutOfSpace)),
+                FilesystemError::DirectoryNotFound => Ok(Err(Error::FileNotFound)),
+                FilesystemError::FileNotFound => Ok(Err(Error::FileNotFound)),
+                FilesystemError::AlreadyExists => Ok(Err(Error::AlreadyExists)),
+                FilesystemError::InvalidPath => Ok(Err(Error::InvalidPath)),
+                FilesystemError::InvalidRoot | FilesystemError::InvalidFileId | FilesystemError::InternalError => {
+                    Err(())
+                }
+                FilesystemError::OperationNotSupported => Ok(Err(Error::OperationNotSupported)),
+            },
+        }
+    }
 }
 
 pub async fn serve_client(cptr: CapabilityPtr, filesystems: SyncRc<[SyncRc<dyn Filesystem>]>) {
@@ -5,7 +5,7 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
-use alchemy::PackedStruct;
+use alchemy::{OnlyValidBitPatterns, PackedStruct};
 use endian::{LittleEndianU16, LittleEndianU32};
 
 use super::{
@@ -28,6 +28,15 @@ use std::{
     sync::{SyncRc, SyncRefCell},
 };
 
+// FIXME: don't assume sector byte size
+const SECTOR_SIZE: u64 = 512;
+const FAT_ENTRIES_PER_SECTOR: u64 = SECTOR_SIZE / 4;
+const DIRECTORY_ENTRIES_PER_SECTOR: usize = SECTOR_SIZE as usize / 32;
+
+/// Byte offsets of the 13 UCS-2 characters in a long filename entry
+const LONG_FILENAME_CHAR_OFFSETS: [usize; 13] =
+    [0x01, 0x03, 0x05, 0x07, 0x09, 0x0E, 0x10, 0x12, 0x14, 0x16, 0x18, 0x1C, 0x1E];
+
 /// Filesystem driver for the FAT32 filesystem
 pub struct Fat32 {
     inner: SyncRc<SyncRefCell<Fat32Inner>>,
@@ -38,18 +47,47 @@ pub struct Fat32 {
 struct DirectoryData {
     filename: [u8; 11],
     attributes: DirectoryAttributes,
-    _reserved1: [u8; 8],
+    _reserved1: u8,
+    creation_time_tenths: u8,
+    creation_time: LittleEndianU16,
+    creation_date: LittleEndianU16,
+    access_date: LittleEndianU16,
     start_cluster_high: LittleEndianU16,
-    _reserved2: [u8; 4],
+    modify_time: LittleEndianU16,
+    modify_date: LittleEndianU16,
     start_cluster_low: LittleEndianU16,
     file_size: LittleEndianU32,
 }
 
 impl DirectoryData {
+    /// A new short filename entry created at `now`
+    fn new(filename: [u8; 11], attributes: DirectoryAttributes, start_cluster: Cluster, now: Timestamp) -> Self {
+        let mut entry = Self::zeroed();
+        entry.filename = filename;
+        entry.attributes = attributes;
+        entry.creation_time = LittleEndianU16::from_ne(now.time);
+        entry.creation_date = LittleEndianU16::from_ne(now.date);
+        entry.set_start_cluster(start_cluster);
+        entry.set_modified(now);
+
+        entry
+    }
+
     fn start_cluster(&self) -> Cluster {
         Cluster((u64::from(self.start_cluster_high.to_ne()) << 16) | u64::from(self.start_cluster_low.to_ne()))
     }
 
+    fn set_start_cluster(&mut self, cluster: Cluster) {
+        self.start_cluster_high = LittleEndianU16::from_ne((cluster.0 >> 16) as u16);
+        self.start_cluster_low = LittleEndianU16::from_ne(cluster.0 as u16);
+    }
+
+    fn set_modified(&mut self, now: Timestamp) {
+        self.modify_time = LittleEndianU16::from_ne(now.time);
+        self.modify_date = LittleEndianU16::from_ne(now.date);
+        self.access_date = LittleEndianU16::from_ne(now.date);
+    }
+
     fn entry_kind(&self) -> DirectoryEntryKind {
         match self.filename[0] {
             0x00 => DirectoryEntryKind::EndOfEntries,
@@ -62,21 +100,8 @@ impl DirectoryData {
         if self.attributes & DirectoryAttributes::LONG_FILENAME {
             // Very Long Filename entries use UCS-2...
             let entry_bytes = self.as_bytes();
-            let filename_characters = [
-                char::from_u32(u32::from(entry_bytes[0x01])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x03])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x05])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x07])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x09])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x0E])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x10])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x12])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x14])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x16])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x18])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x1C])).unwrap_or('\u{0}'),
-                char::from_u32(u32::from(entry_bytes[0x1E])).unwrap_or('\u{0}'),
-            ];
+            let filename_characters = LONG_FILENAME_CHAR_OFFSETS
+                .map(|offset| char::from_u32(u32::from(entry_bytes[offset])).unwrap_or('\u{0}'));
 
             return Some(filename_characters.into_iter().filter(|c| !matches!(c, '\u{0}' | '\u{FF}')));
         }
@@ -143,6 +168,9 @@ impl Cluster {
 struct FatEntry(LittleEndianU32);
 
 impl FatEntry {
+    /// Marks the last cluster of a chain
+    const END_OF_CHAIN: u32 = 0x0FFFFFFF;
+
     const fn kind(self) -> FatEntryKind {
         match self.0.to_ne() & 0x0FFFFFFF {
             0x00000000 => FatEntryKind::Unused,
@@ -159,11 +187,122 @@ enum FatEntryKind {
     Unused,
 }
 
+/// The `FSINFO` sector, which caches how many clusters are free and where to
+/// start looking for them
+#[derive(Debug, Clone, Copy, PackedStruct)]
+#[repr(C)]
+struct FsInfo {
+    lead_signature: LittleEndianU32,
+    _reserved1: [u8; 480],
+    struct_signature: LittleEndianU32,
+    /// Last known number of free clusters, `0xFFFFFFFF` if unknown
+    free_count: LittleEndianU32,
+    /// Cluster to start searching for free clusters at, `0xFFFFFFFF` if
+    /// unknown
+    next_free: LittleEndianU32,
+    _reserved2: [u8; 12],
+    trail_signature: LittleEndianU32,
+}
+
+impl FsInfo {
+    const LEAD_SIGNATURE: u32 = 0x41615252;
+    const STRUCT_SIGNATURE: u32 = 0x61417272;
+    const TRAIL_SIGNATURE: u32 = 0xAA550000;
+    const UNKNOWN: u32 = 0xFFFFFFFF;
+
+    fn is_valid(&self) -> bool {
+        self.lead_signature.to_ne() == Self::LEAD_SIGNATURE
+            && self.struct_signature.to_ne() == Self::STRUCT_SIGNATURE
+            && self.trail_signature.to_ne() == Self::TRAIL_SIGNATURE
+    }
+}
+
+assert_struct_size!(FsInfo, 512);
+
+/// A date and time as stored in directory entries, which have a two second
+/// resolution and a range of 1980 through 2107
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    date: u16,
+    time: u16,
+}
+
+impl Timestamp {
+    /// Midnight on 1980-01-01, the earliest time that can be stored
+    pub const EPOCH: Self = Self::new(1980, 1, 1, 0, 0, 0);
+
+    /// Create a new [`Timestamp`], rounding `second` down to an even number
+    pub const fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Self {
+        Self {
+            date: ((year - 1980) << 9) | ((month as u16) << 5) | day as u16,
+            time: ((hour as u16) << 11) | ((minute as u16) << 5) | (second as u16 / 2),
+        }
+    }
+}
+
+/// Where the FATs are and which of them need to be kept up to date
+#[derive(Debug, Clone, Copy)]
+struct FatTables {
+    start: SectorIndex,
+    /// Size of each FAT in sectors
+    size: u64,
+    count: u64,
+    /// The only FAT in use if mirroring is disabled
+    active: Option<u64>,
+    /// Number of clusters in the data region, which the FATs may have more
+    /// entries than
+    cluster_count: u64,
+    fs_info: SectorIndex,
+}
+
+impl FatTables {
+    /// The FAT which is read from
+    fn primary(&self) -> SectorIndex {
+        self.start + self.active.unwrap_or(0) * self.size
+    }
+
+    /// The FATs which changes are written to
+    fn copies(&self) -> impl Iterator<Item = SectorIndex> + Send + Sync {
+        let (start, size) = (self.start, self.size);
+        match self.active {
+            Some(active) => active..active + 1,
+            None => 0..self.count,
+        }
+        .map(move |n| start + n * size)
+    }
+
+    fn clusters(&self) -> core::ops::Range<u64> {
+        2..self.cluster_count + 2
+    }
+}
+
+/// The location of a directory entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct EntryLocation {
+    /// The first sector of the directory the entry is in, which the directory
+    /// is locked by
+    directory: SectorIndex,
+    /// The sector the entry is in, which can be in any cluster of the directory
+    sector: SectorIndex,
+    /// Index of the entry within its sector
+    index: usize,
+}
+
+impl EntryLocation {
+    fn offset(self) -> usize {
+        self.index * core::mem::size_of::<DirectoryData>()
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 struct OpenFileInfo {
     total_size: u64,
     total_read: u64,
     current_cluster: Cluster,
+    start_cluster: Cluster,
+    /// The cluster writes continue in, if it's been looked up already
+    last_cluster: Option<Cluster>,
+    entry: EntryLocation,
 }
 
 struct Fat32Inner {
@@ -173,13 +312,17 @@ struct Fat32Inner {
     first_sector: SectorIndex,
     #[allow(dead_code)]
     last_sector: SectorIndex,
-    fat_start: SectorIndex,
+    fat: FatTables,
     clusters_start: SectorIndex,
     sectors_per_cluster: u64,
     root_directory_first_cluster: Cluster,
     roots: BTreeMap<Root, PathBuf>,
     open_files: BTreeMap<FileId, OpenFileInfo>,
     sensitive_sector_waitlist: WaitList<SectorIndex>,
+    /// Locks open files by their directory entry, so reads and writes to the
+    /// same file, even through different [`FileId`]s, don't interleave
+    file_waitlist: WaitList<EntryLocation>,
+    clock: fn() -> Timestamp,
 }
 
 impl Fat32 {
@@ -191,23 +334,51 @@ impl Fat32 {
         last_sector: SectorIndex,
     ) -> Self {
         let reserved_sectors = u64::from(bpb.reserved_sector_count.get());
-        let total_fat_size = u64::from(bpb.num_fats) * u64::from(bpb.fat32_fat_size.get());
+        let fat_size = u64::from(bpb.fat32_fat_size.get());
+        let total_fat_size = u64::from(bpb.num_fats) * fat_size;
+        let total_sectors = match bpb.fat16_total_sectors.get() {
+            0 => u64::from(bpb.fat32_total_sectors.get()),
+            n => u64::from(n),
+        };
+        let sectors_per_cluster = u64::from(bpb.sectors_per_cluster);
+        let cluster_count = ((total_sectors - reserved_sectors - total_fat_size) / sectors_per_cluster)
+            .min(fat_size * FAT_ENTRIES_PER_SECTOR - 2);
+
         Self {
             inner: SyncRc::new(SyncRefCell::new(Fat32Inner {
                 block_device,
                 first_sector,
                 last_sector,
-                fat_start: first_sector + reserved_sectors,
+                fat: FatTables {
+                    start: first_sector + reserved_sectors,
+                    size: fat_size,
+                    count: u64::from(bpb.num_fats),
+                    active: match bpb.extended_flags.fat_mirroring() {
+                        true => None,
+                        false => Some(u64::from(bpb.extended_flags.active_fat())),
+                    },
+                    cluster_count,
+                    fs_info: first_sector + u64::from(bpb.fs_info_sector.get()),
+                },
                 clusters_start: first_sector + reserved_sectors + total_fat_size,
-                sectors_per_cluster: u64::from(bpb.sectors_per_cluster),
+                sectors_per_cluster,
                 root_directory_first_cluster: Cluster(u64::from(bpb.root_cluster.get())),
                 roots: BTreeMap::new(),
                 open_files: BTreeMap::new(),
                 sensitive_sector_waitlist: WaitList::new(),
+                file_waitlist: WaitList::new(),
+                // FIXME: there's no real-time clock to get the date from yet
+                clock: || Timestamp::EPOCH,
             })),
         }
     }
 
+    /// Set the function used to timestamp created and modified directory
+    /// entries, which defaults to always returning [`Timestamp::EPOCH`]
+    pub fn set_clock(&self, clock: fn() -> Timestamp) {
+        self.inner_mut().clock = clock;
+    }
+
     fn cloned(&self) -> Self {
         Self { inner: SyncRc::clone(&self.inner) }
     }
@@ -219,6 +390,327 @@ impl Fat32 {
     fn inner_mut(&self) -> RefMut<'_, Fat32Inner> {
         self.inner.borrow_mut()
     }
+
+    fn insert_open_file(&self, directory_data: &DirectoryData, entry: EntryLocation) -> FileId {
+        let mut me = self.inner_mut();
+        let next_file_id = me.open_files.last_key_value().map(|(k, _)| FileId(k.0 + 1)).unwrap_or(FileId(0));
+        let start_cluster = directory_data.start_cluster();
+        me.open_files.insert(
+            next_file_id.clone(),
+            OpenFileInfo {
+                total_size: u64::from(directory_data.file_size.to_ne()),
+                total_read: 0,
+                current_cluster: start_cluster,
+                start_cluster,
+                last_cluster: None,
+                entry,
+            },
+        );
+
+        next_file_id
+    }
+
+    /// Create an empty file at `path`, returning it opened
+    pub fn create_file(&self, root: Root, path: &Path) -> BoxedFuture<'static, Result<FileId, FilesystemError>> {
+        let this = self.cloned();
+        let create = self.create_entry(root, path, false);
+        Box::pin(async move {
+            let (directory_data, location) = create.await?;
+            Ok(this.insert_open_file(&directory_data, location))
+        })
+    }
+
+    /// Create an empty directory at `path`
+    pub fn create_dir(&self, root: Root, path: &Path) -> BoxedFuture<'static, Result<(), FilesystemError>> {
+        let create = self.create_entry(root, path, true);
+        Box::pin(async move { create.await.map(|_| ()) })
+    }
+
+    /// Append `data` to the end of an open file, allocating clusters for it as
+    /// needed, and returning how many bytes were written. If the filesystem
+    /// fills up partway through, the data that fit is kept and the write is
+    /// cut short, so [`FilesystemError::OutOfSpace`] is only returned when
+    /// nothing could be written.
+    pub fn write_file(&self, file: FileId, data: Vec<u8>) -> BoxedFuture<'static, Result<usize, FilesystemError>> {
+        let this = self.cloned();
+        let me = this.inner();
+        let entry = match me.open_files.get(&file) {
+            Some(info) => info.entry,
+            None => return Box::pin(core::future::ready(Err(FilesystemError::InvalidFileId))),
+        };
+        let fat = me.fat;
+        let first_cluster_sector = me.clusters_start;
+        let sectors_per_cluster = me.sectors_per_cluster;
+        let now = (me.clock)();
+        let waitlist = me.sensitive_sector_waitlist.clone();
+        let file_waitlist = me.file_waitlist.clone();
+        let device = SyncRc::clone(&me.block_device);
+        drop(me);
+
+        Box::pin(async move {
+            let _token = file_waitlist.acquire(entry).await;
+            // Only look at the file once it's locked, so this write continues
+            // from where the last one left off
+            let mut info = match this.inner().open_files.get(&file) {
+                Some(info) => *info,
+                None => return Err(FilesystemError::InvalidFileId),
+            };
+
+            let cluster_byte_size = sectors_per_cluster * SECTOR_SIZE;
+            // File sizes are stored as a `u32`
+            let data = &data[..data.len().min((u64::from(u32::MAX) - info.total_size) as usize)];
+            if data.is_empty() {
+                return Ok(0);
+            }
+
+            let mut last_cluster = match (info.last_cluster, info.start_cluster) {
+                (Some(cluster), _) => Some(cluster),
+                (None, Cluster(0)) => None,
+                (None, start) => Some(find_last_cluster(&*device, &fat, start).await?),
+            };
+
+            let mut written = 0;
+            while written < data.len() {
+                let cluster_offset = info.total_size % cluster_byte_size;
+                let cluster = match last_cluster {
+                    Some(cluster) if cluster_offset != 0 || info.total_size == 0 => cluster,
+                    previous => match alloc_cluster(&*device, &fat, &waitlist, previous).await {
+                        Ok(cluster) => {
+                            if previous.is_none() {
+                                info.start_cluster = cluster;
+                            }
+
+                            last_cluster = Some(cluster);
+                            cluster
+                        }
+                        Err(FilesystemError::OutOfSpace) if written != 0 => break,
+                        Err(e) => return Err(e),
+                    },
+                };
+
+                let sector =
+                    cluster.to_sector(first_cluster_sector, sectors_per_cluster) + cluster_offset / SECTOR_SIZE;
+                let sector_offset = (cluster_offset % SECTOR_SIZE) as usize;
+                let amount = (SECTOR_SIZE as usize - sector_offset).min(data.len() - written);
+
+                // Partially written sectors need to keep what's already there
+                let mut block = match amount == SECTOR_SIZE as usize {
+                    true => device.alloc_data_block().await,
+                    false => device.read(sector).await?,
+                };
+                block[sector_offset..][..amount].copy_from_slice(&data[written..][..amount]);
+                device.write(sector, block).await?;
+
+                written += amount;
+                info.total_size += amount as u64;
+            }
+
+            update_entry(&*device, &waitlist, info.entry, |entry| {
+                entry.set_start_cluster(info.start_cluster);
+                entry.file_size = LittleEndianU32::from_ne(info.total_size as u32);
+                entry.set_modified(now);
+            })
+            .await?;
+
+            let mut me = this.inner_mut();
+            for open_file_info in me.open_files.values_mut().filter(|open_file_info| open_file_info.entry == entry) {
+                // Files created empty have nowhere to start reading from until
+                // the first write
+                if open_file_info.current_cluster == Cluster(0) {
+                    open_file_info.current_cluster = info.start_cluster;
+                }
+
+                open_file_info.total_size = info.total_size;
+                open_file_info.start_cluster = info.start_cluster;
+                open_file_info.last_cluster = last_cluster;
+            }
+
+            Ok(written)
+        })
+    }
+
+    fn create_entry(
+        &self,
+        root: Root,
+        path: &Path,
+        directory: bool,
+    ) -> BoxedFuture<'static, Result<(DirectoryData, EntryLocation), FilesystemError>> {
+        let path = match self.inner().roots.get(&root) {
+            Some(root_path) => root_path.join(path),
+            None => return Box::pin(core::future::ready(Err(FilesystemError::InvalidRoot))),
+        };
+
+        let me = self.inner();
+        let root_directory_cluster = me.root_directory_first_cluster;
+        let first_cluster_sector = me.clusters_start;
+        let sectors_per_cluster = me.sectors_per_cluster;
+        let fat = me.fat;
+        let now = (me.clock)();
+        let waitlist = me.sensitive_sector_waitlist.clone();
+        let device = SyncRc::clone(&me.block_device);
+        drop(me);
+
+        Box::pin(async move {
+            let (Some(name), Some(parent)) = (path.file_name(), path.parent()) else {
+                return Err(FilesystemError::InvalidPath);
+            };
+
+            if !is_valid_long_name(name) {
+                return Err(FilesystemError::InvalidPath);
+            }
+
+            let parent_cluster = match parent.compontents().all(str::is_empty) {
+                true => root_directory_cluster,
+                false => {
+                    match find_path(
+                        &*device,
+                        &fat,
+                        first_cluster_sector,
+                        root_directory_cluster,
+                        sectors_per_cluster,
+                        parent,
+                    )
+                    .await?
+                    {
+                        Some((directory_data, _)) if directory_data.attributes & DirectoryAttributes::SUBDIRECTORY => {
+                            directory_data.start_cluster()
+                        }
+                        _ => return Err(FilesystemError::DirectoryNotFound),
+                    }
+                }
+            };
+
+            let directory_sector = parent_cluster.to_sector(first_cluster_sector, sectors_per_cluster);
+            let _token = waitlist.acquire(directory_sector).await;
+
+            let exists = with_directory_entries(
+                &*device,
+                &fat,
+                first_cluster_sector,
+                sectors_per_cluster,
+                parent_cluster,
+                |vlfn, entry, _| {
+                    let matches = match vlfn {
+                        Some(long_name) => long_name.eq_ignore_ascii_case(name),
+                        None => short_name_eq(&entry.filename, name),
+                    };
+
+                    core::future::ready(match matches {
+                        true => ControlFlow::Break(()),
+                        false => ControlFlow::Continue(()),
+                    })
+                },
+            )
+            .await?;
+
+            if exists.is_some() {
+                return Err(FilesystemError::AlreadyExists);
+            }
+
+            let chain = cluster_chain(&*device, &fat, parent_cluster).await?;
+            let mut blocks = Vec::new();
+            for cluster in &chain {
+                let first_sector = cluster.to_sector(first_cluster_sector, sectors_per_cluster);
+                for sector in (0..sectors_per_cluster).map(|i| first_sector + i) {
+                    blocks.push((sector, device.read(sector).await?));
+                }
+            }
+
+            let entry_count = name.len().div_ceil(LONG_FILENAME_CHAR_OFFSETS.len()) + 1;
+            let mut end = blocks.len() * DIRECTORY_ENTRIES_PER_SECTOR;
+            let mut taken = Vec::new();
+            let mut free_run = 0;
+            let mut slot = None;
+            let entries = blocks.iter().flat_map(|(_, block)| DirectoryData::try_slice_from_bytes(block).unwrap());
+            for (i, entry) in entries.enumerate() {
+                match entry.entry_kind() {
+                    // Everything past the end is free
+                    DirectoryEntryKind::EndOfEntries => {
+                        end = i;
+                        break;
+                    }
+                    DirectoryEntryKind::Deleted => {
+                        free_run += 1;
+                        if slot.is_none() && free_run == entry_count {
+                            slot = Some(i + 1 - entry_count);
+                        }
+                    }
+                    DirectoryEntryKind::Present => {
+                        free_run = 0;
+                        if entry.long_filename_chars().is_none() {
+                            taken.push(entry.filename);
+                        }
+                    }
+                }
+            }
+
+            // Without a big enough gap the entries go at the end, which may be
+            // past the end of the directory's last cluster
+            let slot = slot.unwrap_or(end - free_run);
+            let Some(short_name) = short_name(name, &taken) else { return Err(FilesystemError::InvalidPath) };
+
+            // Grow the directory before allocating anything for the new entry,
+            // so running out of space can't leave a cluster that nothing
+            // points to. New clusters are zeroed before anything else happens
+            // so the chain never contains garbage entries.
+            let mut last_cluster = *chain.last().unwrap();
+            while blocks.len() * DIRECTORY_ENTRIES_PER_SECTOR < slot + entry_count {
+                last_cluster = alloc_cluster(&*device, &fat, &waitlist, Some(last_cluster)).await?;
+                let first_sector = last_cluster.to_sector(first_cluster_sector, sectors_per_cluster);
+                for sector in (0..sectors_per_cluster).map(|i| first_sector + i) {
+                    let mut block = device.alloc_data_block().await;
+                    block.fill(0);
+                    device.write(sector, block).await?;
+
+                    let mut block = device.alloc_data_block().await;
+                    block.fill(0);
+                    blocks.push((sector, block));
+                }
+            }
+
+            let (attributes, start_cluster) = match directory {
+                true => {
+                    let cluster = alloc_cluster(&*device, &fat, &waitlist, None).await?;
+                    // `..` entries point at cluster 0 for the root directory
+                    let parent = match parent_cluster == root_directory_cluster {
+                        true => Cluster(0),
+                        false => parent_cluster,
+                    };
+
+                    init_directory(&*device, first_cluster_sector, sectors_per_cluster, cluster, parent, now).await?;
+                    (DirectoryAttributes::SUBDIRECTORY, cluster)
+                }
+                false => (DirectoryAttributes::ARCHIVE, Cluster(0)),
+            };
+
+            let entry = DirectoryData::new(short_name, attributes, start_cluster, now);
+            let mut new_entries = long_name_entries(name, &short_name);
+            new_entries.push(entry);
+
+            let location = |blocks: &[(SectorIndex, DataBlock)], index: usize| EntryLocation {
+                directory: directory_sector,
+                sector: blocks[index / DIRECTORY_ENTRIES_PER_SECTOR].0,
+                index: index % DIRECTORY_ENTRIES_PER_SECTOR,
+            };
+
+            for (i, new_entry) in new_entries.iter().enumerate() {
+                let location = location(&blocks, slot + i);
+                let (_, block) = &mut blocks[(slot + i) / DIRECTORY_ENTRIES_PER_SECTOR];
+                block[location.offset()..][..core::mem::size_of::<DirectoryData>()]
+                    .copy_from_slice(new_entry.as_bytes());
+            }
+
+            let entry_location = location(&blocks, slot + entry_count - 1);
+            let touched = slot / DIRECTORY_ENTRIES_PER_SECTOR..=(slot + entry_count - 1) / DIRECTORY_ENTRIES_PER_SECTOR;
+            for (i, (sector, block)) in blocks.into_iter().enumerate() {
+                if touched.contains(&i) {
+                    device.write(sector, block).await?;
+                }
+            }
+
+            Ok((entry, entry_location))
+        })
+    }
 }
 
 impl Filesystem for Fat32 {
@@ -257,9 +749,9 @@ impl Filesystem for Fat32 {
         &self,
         root: Root,
         path: &Path,
-        permissions: FilePermissions,
+        _: FilePermissions,
     ) -> BoxedFuture<'static, Result<FileId, FilesystemError>> {
-        todo!()
+        self.create_file(root, path)
     }
 
     fn open(
@@ -278,30 +770,23 @@ impl Filesystem for Fat32 {
         let root_directory_cluster = me.root_directory_first_cluster;
         let first_cluster_sector = me.clusters_start;
         let sectors_per_cluster = me.sectors_per_cluster;
+        let fat = me.fat;
         let device = SyncRc::clone(&me.block_device);
         drop(me);
 
         Box::pin(async move {
             let maybe_found =
-                find_path(&*device, first_cluster_sector, root_directory_cluster, sectors_per_cluster, &path).await?;
-
-            let (cluster_start, file_size) = match maybe_found {
-                Some(directory_data) => match directory_data.attributes & DirectoryAttributes::SUBDIRECTORY {
-                    true => return Err(FilesystemError::FileNotFound),
-                    false => (directory_data.start_cluster(), u64::from(directory_data.file_size.to_ne())),
+                find_path(&*device, &fat, first_cluster_sector, root_directory_cluster, sectors_per_cluster, &path)
+                    .await?;
+
+            match maybe_found {
+                Some((directory_data, location)) => match directory_data.attributes & DirectoryAttributes::SUBDIRECTORY
+                {
+                    true => Err(FilesystemError::FileNotFound),
+                    false => Ok(this.insert_open_file(&directory_data, location)),
                 },
-                None => return Err(FilesystemError::FileNotFound),
-            };
-
-            let mut me = this.inner_mut();
-            let next_file_id = me.open_files.last_key_value().map(|(k, _)| FileId(k.0 + 1)).unwrap_or(FileId(0));
-            me.open_files.insert(
-                next_file_id.clone(),
-                OpenFileInfo { total_size: file_size, total_read: 0, current_cluster: cluster_start },
-            );
-            drop(me);
-
-            Ok(next_file_id)
+                None => Err(FilesystemError::FileNotFound),
+            }
         })
     }
 
@@ -318,23 +803,26 @@ impl Filesystem for Fat32 {
     ) -> BoxedFuture<'static, Result<Option<(usize, DataBlock)>, FilesystemError>> {
         let this = self.cloned();
         let me = this.inner();
-        let mut open_file_info = match me.open_files.get(&file) {
-            Some(OpenFileInfo { current_cluster: Cluster::END_OF_CLUSTER_CHAIN, .. }) => {
-                return Box::pin(core::future::ready(Ok(None)))
-            }
-            Some(OpenFileInfo { total_size, total_read, .. }) if total_read == total_size => {
-                return Box::pin(core::future::ready(Ok(None)))
-            }
-            Some(info) => *info,
+        let entry = match me.open_files.get(&file) {
+            Some(info) => info.entry,
             None => return Box::pin(core::future::ready(Err(FilesystemError::InvalidFileId))),
         };
-        let fat_start = me.fat_start;
+        let fat_start = me.fat.primary();
         let first_cluster_sector = me.clusters_start;
         let sectors_per_cluster = me.sectors_per_cluster;
+        let file_waitlist = me.file_waitlist.clone();
         let device = SyncRc::clone(&me.block_device);
         drop(me);
 
         Box::pin(async move {
+            let _token = file_waitlist.acquire(entry).await;
+            let mut open_file_info = match this.inner().open_files.get(&file) {
+                Some(OpenFileInfo { current_cluster: Cluster::END_OF_CLUSTER_CHAIN, .. }) => return Ok(None),
+                Some(OpenFileInfo { total_size, total_read, .. }) if total_read == total_size => return Ok(None),
+                Some(info) => *info,
+                None => return Err(FilesystemError::InvalidFileId),
+            };
+
             let cluster_byte_size = sectors_per_cluster * /* FIXME: don't assume sector byte size */ 512;
             if open_file_info.total_read % cluster_byte_size == 0 && open_file_info.total_read != 0 {
                 // println!("\n\n\n\n\n{cluster_byte_size} - {open_file_info:?}\n\n\n\n\n\n");
@@ -346,8 +834,9 @@ impl Filesystem for Fat32 {
                 match fat_entry.kind() {
                     FatEntryKind::Cluster(next_cluster) => open_file_info.current_cluster = next_cluster,
                     FatEntryKind::LastClusterOfFile => {
-                        this.inner_mut().open_files.get_mut(&file).unwrap().current_cluster =
-                            Cluster::END_OF_CLUSTER_CHAIN;
+                        if let Some(info) = this.inner_mut().open_files.get_mut(&file) {
+                            info.current_cluster = Cluster::END_OF_CLUSTER_CHAIN;
+                        }
                         return Ok(None);
                     }
                     FatEntryKind::Unused => {
@@ -361,7 +850,9 @@ impl Filesystem for Fat32 {
             }
 
             let sector_offset = match sectors_per_cluster > 1 {
-                true => open_file_info.total_read / /* FIXME: don't assume sector byte size */ 512,
+                true => {
+                    (open_file_info.total_read % cluster_byte_size) / /* FIXME: don't assume sector byte size */ 512
+                }
                 false => 0,
             };
 
@@ -378,12 +869,21 @@ impl Filesystem for Fat32 {
             };
 
             open_file_info.total_read += amount_read;
-            *this.inner_mut().open_files.get_mut(&file).unwrap() = open_file_info;
+
+            // Writes only ever change the rest of the info, which reads don't
+            if let Some(info) = this.inner_mut().open_files.get_mut(&file) {
+                info.current_cluster = open_file_info.current_cluster;
+                info.total_read = open_file_info.total_read;
+            }
 
             Ok(Some((amount_read as usize, data)))
         })
     }
 
+    fn write_file_block(&self, file: FileId, data: Vec<u8>) -> BoxedFuture<'static, Result<usize, FilesystemError>> {
+        self.write_file(file, data)
+    }
+
     fn exists(&self, root: Root, path: &Path) -> BoxedFuture<'static, Result<Option<FileType>, FilesystemError>> {
         let path = match self.inner().roots.get(&root) {
             Some(root_path) => root_path.join(path),
@@ -395,15 +895,17 @@ impl Filesystem for Fat32 {
         let root_directory_cluster = me.root_directory_first_cluster;
         let first_cluster_sector = me.clusters_start;
         let sectors_per_cluster = me.sectors_per_cluster;
+        let fat = me.fat;
         let device = SyncRc::clone(&me.block_device);
         drop(me);
 
         Box::pin(async move {
             let maybe_found =
-                find_path(&*device, first_cluster_sector, root_directory_cluster, sectors_per_cluster, &path).await?;
+                find_path(&*device, &fat, first_cluster_sector, root_directory_cluster, sectors_per_cluster, &path)
+                    .await?;
 
             Ok(match maybe_found {
-                Some(directory_data) => match directory_data.attributes & DirectoryAttributes::SUBDIRECTORY {
+                Some((directory_data, _)) => match directory_data.attributes & DirectoryAttributes::SUBDIRECTORY {
                     true => Some(FileType::Directory),
                     false => Some(FileType::File),
                 },
@@ -423,30 +925,39 @@ impl Filesystem for Fat32 {
         let root_directory_cluster = me.root_directory_first_cluster;
         let first_cluster_sector = me.clusters_start;
         let sectors_per_cluster = me.sectors_per_cluster;
+        let fat = me.fat;
         let device = SyncRc::clone(&me.block_device);
         drop(me);
 
         Box::pin(async move {
-            let Some(directory) =
-                find_path(&*device, first_cluster_sector, root_directory_cluster, sectors_per_cluster, &path).await? else {
-                    return Err(FilesystemError::DirectoryNotFound);
-                };
+            let Some((directory, _)) =
+                find_path(&*device, &fat, first_cluster_sector, root_directory_cluster, sectors_per_cluster, &path)
+                    .await?
+            else {
+                return Err(FilesystemError::DirectoryNotFound);
+            };
 
             let mut info = Vec::new();
-            let directory_sector = directory.start_cluster().to_sector(first_cluster_sector, sectors_per_cluster);
-            with_directory_entries(&*device, directory_sector, sectors_per_cluster, |name, dir_info| {
-                if let Some(name) = name {
-                    info.push(FileInfo {
-                        filename: name,
-                        file_type: match dir_info.attributes & DirectoryAttributes::SUBDIRECTORY {
-                            true => FileType::Directory,
-                            false => FileType::File,
-                        },
-                    });
-                }
+            with_directory_entries(
+                &*device,
+                &fat,
+                first_cluster_sector,
+                sectors_per_cluster,
+                directory.start_cluster(),
+                |name, dir_info, _| {
+                    if let Some(name) = name {
+                        info.push(FileInfo {
+                            filename: name,
+                            file_type: match dir_info.attributes & DirectoryAttributes::SUBDIRECTORY {
+                                true => FileType::Directory,
+                                false => FileType::File,
+                            },
+                        });
+                    }
 
-                core::future::ready(ControlFlow::Continue::<(), ()>(()))
-            })
+                    core::future::ready(ControlFlow::Continue::<(), ()>(()))
+                },
+            )
             .await?;
 
             Ok(info)
@@ -456,11 +967,12 @@ impl Filesystem for Fat32 {
 
 async fn find_path(
     device: &dyn BlockDevice,
+    fat: &FatTables,
     first_cluster_sector: SectorIndex,
     root_directory_cluster: Cluster,
     sectors_per_cluster: u64,
     path: &Path,
-) -> Result<Option<DirectoryData>, FilesystemError> {
+) -> Result<Option<(DirectoryData, EntryLocation)>, FilesystemError> {
     let Some(filename) = path.file_name() else { return Err(FilesystemError::InvalidPath) };
     let Some(parent) = path.parent() else { return Err(FilesystemError::InvalidPath) };
     let mut directories = parent.compontents();
@@ -470,10 +982,13 @@ async fn find_path(
 
     let mut cluster_start = root_directory_cluster;
     for component in directories {
-        // Cluster numbering starts at 2
-        let directory_cluster = cluster_start.to_sector(first_cluster_sector, sectors_per_cluster);
-        let matching_entry =
-            with_directory_entries(device, directory_cluster, sectors_per_cluster, |vlfn, directory_data| async move {
+        let matching_entry = with_directory_entries(
+            device,
+            fat,
+            first_cluster_sector,
+            sectors_per_cluster,
+            cluster_start,
+            |vlfn, directory_data, _| async move {
                 // We hit a file, skip over it
                 if !(directory_data.attributes & DirectoryAttributes::SUBDIRECTORY) {
                     return ControlFlow::Continue(());
@@ -491,7 +1006,8 @@ async fn find_path(
                 }
 
                 ControlFlow::Continue(())
-            });
+            },
+        );
 
         match matching_entry.await? {
             Some(cluster) => cluster_start = cluster,
@@ -500,77 +1016,651 @@ async fn find_path(
     }
 
     // Loop over the directory the file should be contained within
-    let directory_cluster = cluster_start.to_sector(first_cluster_sector, sectors_per_cluster);
-    let matching_file =
-        with_directory_entries(device, directory_cluster, sectors_per_cluster, |vlfn, directory_data| async move {
+    let matching_file = with_directory_entries(
+        device,
+        fat,
+        first_cluster_sector,
+        sectors_per_cluster,
+        cluster_start,
+        |vlfn, directory_data, location| async move {
             match vlfn {
                 Some(name) if name == filename => {
-                    return ControlFlow::Break(directory_data);
+                    return ControlFlow::Break((directory_data, location));
                 }
                 _ => {
                     if &directory_data.filename[..] == filename.as_bytes() {
-                        return ControlFlow::Break(directory_data);
+                        return ControlFlow::Break((directory_data, location));
                     }
                 }
             }
 
             ControlFlow::Continue(())
-        });
+        },
+    );
 
-    Ok(matching_file.await?)
+    matching_file.await
 }
 
+/// Call `f` with each entry of the directory starting at `start`, following the
+/// directory's cluster chain, until `f` breaks with a value
 async fn with_directory_entries<T, F, Fut>(
     device: &dyn BlockDevice,
-    directory_start: SectorIndex,
+    fat: &FatTables,
+    first_cluster_sector: SectorIndex,
     sectors_per_cluster: u64,
+    start: Cluster,
     mut f: F,
-) -> Result<Option<T>, DeviceError>
+) -> Result<Option<T>, FilesystemError>
 where
     T: 'static,
-    F: FnMut(Option<String>, DirectoryData) -> Fut,
+    F: FnMut(Option<String>, DirectoryData, EntryLocation) -> Fut,
     Fut: Future<Output = ControlFlow<T>>,
 {
+    let directory_start = start.to_sector(first_cluster_sector, sectors_per_cluster);
     let mut vlfn = String::new();
-    // Iterate over each sector in the cluster of directory data
-    for i in 0..sectors_per_cluster {
-        let cluster_data_block = device.read(directory_start + i).await?;
-        let directory_entries = DirectoryData::try_slice_from_bytes(&cluster_data_block).unwrap();
-
-        for directory_data in directory_entries {
-            // Insert long filename extension data if needbe
-            if let Some(chars) = directory_data.long_filename_chars() {
-                // FIXME: find a better way to do this
-                for (i, c) in chars.enumerate() {
-                    vlfn.insert(i, c);
+    let mut cluster = start;
+    // A chain can't be longer than there are clusters, so anything longer
+    // than that has a loop in it
+    for _ in fat.clusters() {
+        let cluster_start = cluster.to_sector(first_cluster_sector, sectors_per_cluster);
+        // Iterate over each sector in the cluster of directory data
+        for sector in (0..sectors_per_cluster).map(|i| cluster_start + i) {
+            let cluster_data_block = device.read(sector).await?;
+            let directory_entries = DirectoryData::try_slice_from_bytes(&cluster_data_block).unwrap();
+
+            for (index, directory_data) in directory_entries.iter().enumerate() {
+                // Insert long filename extension data if needbe
+                if let Some(chars) = directory_data.long_filename_chars() {
+                    // FIXME: find a better way to do this
+                    for (i, c) in chars.enumerate() {
+                        vlfn.insert(i, c);
+                    }
+
+                    continue;
                 }
 
-                continue;
-            }
+                match directory_data.entry_kind() {
+                    // We've hit the end of the directory entries
+                    DirectoryEntryKind::EndOfEntries => return Ok(None),
+                    // Skip deleted entries
+                    DirectoryEntryKind::Deleted => {
+                        vlfn.clear();
+                        continue;
+                    }
+                    DirectoryEntryKind::Present => {}
+                }
 
-            match directory_data.entry_kind() {
-                // We've hit the end of the directory entries
-                DirectoryEntryKind::EndOfEntries => return Ok(None),
-                // Skip deleted entries
-                DirectoryEntryKind::Deleted => {
-                    vlfn.clear();
-                    continue;
+                let name = match vlfn.is_empty() {
+                    true => None,
+                    false => Some(vlfn.clone()),
+                };
+
+                let location = EntryLocation { directory: directory_start, sector, index };
+                if let ControlFlow::Break(val) = f(name, *directory_data, location).await {
+                    return Ok(Some(val));
                 }
-                DirectoryEntryKind::Present => {}
+
+                vlfn.clear();
             }
+        }
 
-            let name = match vlfn.is_empty() {
-                true => None,
-                false => Some(vlfn.clone()),
-            };
+        match read_fat_entry(device, fat, cluster).await?.kind() {
+            FatEntryKind::Cluster(next) => cluster = next,
+            FatEntryKind::LastClusterOfFile => return Ok(None),
+            FatEntryKind::Unused => break,
+        }
+    }
 
-            if let ControlFlow::Break(val) = f(name, *directory_data).await {
-                return Ok(Some(val));
-            }
+    println!("[filesystem] Detected bad FAT chain starting at cluster {}!", start.0);
+    Err(FilesystemError::InternalError)
+}
+
+async fn read_fat_entry(device: &dyn BlockDevice, fat: &FatTables, cluster: Cluster) -> Result<FatEntry, DeviceError> {
+    let data = device.read(fat.primary() + cluster.0 / FAT_ENTRIES_PER_SECTOR).await?;
+    Ok(FatEntry::try_slice_from_bytes(&data).unwrap()[(cluster.0 % FAT_ENTRIES_PER_SECTOR) as usize])
+}
 
-            vlfn.clear();
+/// Set the entry for `cluster` in every FAT that's in use
+async fn write_fat_entry(
+    device: &dyn BlockDevice,
+    fat: &FatTables,
+    cluster: Cluster,
+    value: u32,
+) -> Result<(), DeviceError> {
+    let offset = (cluster.0 % FAT_ENTRIES_PER_SECTOR) as usize * core::mem::size_of::<FatEntry>();
+    for table in fat.copies() {
+        let sector = table + cluster.0 / FAT_ENTRIES_PER_SECTOR;
+        let mut data = device.read(sector).await?;
+        let entry = FatEntry::try_from_mut_byte_slice(&mut data[offset..]).unwrap();
+        // The upper four bits are reserved and need to be preserved
+        entry.0 = LittleEndianU32::from_ne((entry.0.to_ne() & 0xF0000000) | (value & 0x0FFFFFFF));
+        device.write(sector, data).await?;
+    }
+
+    Ok(())
+}
+
+async fn find_last_cluster(
+    device: &dyn BlockDevice,
+    fat: &FatTables,
+    start: Cluster,
+) -> Result<Cluster, FilesystemError> {
+    let mut cluster = start;
+    // A chain can't be longer than there are clusters, so anything longer
+    // than that has a loop in it
+    for _ in fat.clusters() {
+        match read_fat_entry(device, fat, cluster).await?.kind() {
+            FatEntryKind::Cluster(next) => cluster = next,
+            FatEntryKind::LastClusterOfFile => return Ok(cluster),
+            FatEntryKind::Unused => break,
+        }
+    }
+
+    println!("[filesystem] Detected bad FAT chain starting at cluster {}!", start.0);
+    Err(FilesystemError::InternalError)
+}
+
+/// The clusters in the chain starting at `start`, in order
+async fn cluster_chain(
+    device: &dyn BlockDevice,
+    fat: &FatTables,
+    start: Cluster,
+) -> Result<Vec<Cluster>, FilesystemError> {
+    let mut chain = vec![start];
+    for _ in fat.clusters() {
+        match read_fat_entry(device, fat, *chain.last().unwrap()).await?.kind() {
+            FatEntryKind::Cluster(next) => chain.push(next),
+            FatEntryKind::LastClusterOfFile => return Ok(chain),
+            FatEntryKind::Unused => break,
+        }
+    }
+
+    println!("[filesystem] Detected bad FAT chain starting at cluster {}!", start.0);
+    Err(FilesystemError::InternalError)
+}
+
+/// Search the FAT for an unused cluster, starting at `hint` and wrapping
+/// around to the beginning of the FAT
+async fn find_free_cluster(
+    device: &dyn BlockDevice,
+    fat: &FatTables,
+    hint: u64,
+) -> Result<Option<Cluster>, DeviceError> {
+    let mut cached: Option<(SectorIndex, DataBlock)> = None;
+    for cluster in (hint..fat.clusters().end).chain(fat.clusters().start..hint) {
+        let sector = fat.primary() + cluster / FAT_ENTRIES_PER_SECTOR;
+        if cached.as_ref().map(|(cached_sector, _)| *cached_sector) != Some(sector) {
+            cached = Some((sector, device.read(sector).await?));
+        }
+
+        let data = &cached.as_ref().unwrap().1;
+        let entry = FatEntry::try_slice_from_bytes(data).unwrap()[(cluster % FAT_ENTRIES_PER_SECTOR) as usize];
+        if entry.kind() == FatEntryKind::Unused {
+            return Ok(Some(Cluster(cluster)));
         }
     }
 
     Ok(None)
 }
+
+/// Allocate a cluster, marking it as the end of its chain and linking it to
+/// the end of `previous`'s chain if there is one, and update the `FSINFO`
+/// sector's free cluster bookkeeping
+async fn alloc_cluster(
+    device: &dyn BlockDevice,
+    fat: &FatTables,
+    waitlist: &WaitList<SectorIndex>,
+    previous: Option<Cluster>,
+) -> Result<Cluster, FilesystemError> {
+    // Holding the `FSINFO` sector keeps the FAT from changing underneath us
+    let _token = waitlist.acquire(fat.fs_info).await;
+
+    let mut fs_info_data = device.read(fat.fs_info).await?;
+    let fs_info = *FsInfo::try_from_byte_slice(&fs_info_data).unwrap();
+    let hint = match fs_info.is_valid() {
+        true => u64::from(fs_info.next_free.to_ne()),
+        false => u64::from(FsInfo::UNKNOWN),
+    };
+    let hint = match fat.clusters().contains(&hint) {
+        true => hint,
+        false => fat.clusters().start,
+    };
+
+    let Some(cluster) = find_free_cluster(device, fat, hint).await? else {
+        return Err(FilesystemError::OutOfSpace);
+    };
+
+    // Link the new cluster only once it's terminated, so the chain is never
+    // left pointing at a free cluster
+    write_fat_entry(device, fat, cluster, FatEntry::END_OF_CHAIN).await?;
+    if let Some(previous) = previous {
+        write_fat_entry(device, fat, previous, cluster.0 as u32).await?;
+    }
+
+    if fs_info.is_valid() {
+        let fs_info = FsInfo::try_from_mut_byte_slice(&mut fs_info_data).unwrap();
+        if fs_info.free_count.to_ne() != FsInfo::UNKNOWN {
+            fs_info.free_count = LittleEndianU32::from_ne(fs_info.free_count.to_ne().saturating_sub(1));
+        }
+
+        fs_info.next_free = LittleEndianU32::from_ne(cluster.0 as u32 + 1);
+        device.write(fat.fs_info, fs_info_data).await?;
+    }
+
+    Ok(cluster)
+}
+
+/// Zero a newly allocated directory cluster and add its `.` and `..` entries
+async fn init_directory(
+    device: &dyn BlockDevice,
+    first_cluster_sector: SectorIndex,
+    sectors_per_cluster: u64,
+    cluster: Cluster,
+    parent: Cluster,
+    now: Timestamp,
+) -> Result<(), DeviceError> {
+    let directory_sector = cluster.to_sector(first_cluster_sector, sectors_per_cluster);
+    for i in 0..sectors_per_cluster {
+        let mut data = device.alloc_data_block().await;
+        data.fill(0);
+
+        if i == 0 {
+            let dot = DirectoryData::new(*b".          ", DirectoryAttributes::SUBDIRECTORY, cluster, now);
+            let dot_dot = DirectoryData::new(*b"..         ", DirectoryAttributes::SUBDIRECTORY, parent, now);
+            let entries = [dot, dot_dot];
+            let entries = DirectoryData::bytes_of_slice(&entries);
+            data[..entries.len()].copy_from_slice(entries);
+        }
+
+        device.write(directory_sector + i, data).await?;
+    }
+
+    Ok(())
+}
+
+async fn update_entry(
+    device: &dyn BlockDevice,
+    waitlist: &WaitList<SectorIndex>,
+    location: EntryLocation,
+    f: impl FnOnce(&mut DirectoryData),
+) -> Result<(), DeviceError> {
+    // Directories are locked as a whole while entries are being created in
+    // them
+    let _token = waitlist.acquire(location.directory).await;
+
+    let mut data = device.read(location.sector).await?;
+    f(DirectoryData::try_from_mut_byte_slice(&mut data[location.offset()..]).unwrap());
+    device.write(location.sector, data).await
+}
+
+/// Whether a name can be given to a new entry. Only ASCII is allowed since
+/// long filenames are only read back a byte per character.
+fn is_valid_long_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..")
+        && name.len() <= 255
+        && name.bytes().all(|b| b.is_ascii() && !b.is_ascii_control() && !b"\"*/:<>?\\|".contains(&b))
+}
+
+/// Generate a unique 8.3 name for an entry, e.g. `NOTES~1 TXT` for
+/// `notes.txt`. The numeric tail is always added since the long filename is
+/// what's actually used.
+fn short_name(name: &str, taken: &[[u8; 11]]) -> Option<[u8; 11]> {
+    let (base, extension) = match name.rsplit_once('.') {
+        Some((base, extension)) if !base.is_empty() => (base, extension),
+        _ => (name, ""),
+    };
+
+    let sanitize = |part: &str| -> Vec<u8> {
+        part.bytes()
+            .filter(|b| !matches!(b, b' ' | b'.'))
+            .map(|b| match b {
+                b'a'..=b'z' => b.to_ascii_uppercase(),
+                b'A'..=b'Z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'(' | b')' | b'-' | b'@' => b,
+                b'^' | b'_' | b'`' | b'{' | b'}' | b'~' => b,
+                _ => b'_',
+            })
+            .collect()
+    };
+
+    let (base, extension) = (sanitize(base), sanitize(extension));
+    let mut short_name = [b' '; 11];
+    let extension_len = extension.len().min(3);
+    short_name[8..][..extension_len].copy_from_slice(&extension[..extension_len]);
+
+    for n in 1..1_000_000 {
+        let tail = format!("~{n}");
+        let base_len = base.len().min(8 - tail.len());
+        short_name[..8].fill(b' ');
+        short_name[..base_len].copy_from_slice(&base[..base_len]);
+        short_name[base_len..][..tail.len()].copy_from_slice(tail.as_bytes());
+
+        if !taken.contains(&short_name) {
+            return Some(short_name);
+        }
+    }
+
+    None
+}
+
+/// Whether the 8.3 name of an entry is `name`, ignoring case
+fn short_name_eq(short_name: &[u8; 11], name: &str) -> bool {
+    let trim = |part: &[u8]| part.iter().rposition(|&b| b != b' ').map_or(0, |end| end + 1);
+    let (base, extension) = short_name.split_at(8);
+    let (base, extension) = (&base[..trim(base)], &extension[..trim(extension)]);
+
+    match name.rsplit_once('.') {
+        Some((name_base, name_extension)) if !extension.is_empty() => {
+            name_base.as_bytes().eq_ignore_ascii_case(base) && name_extension.as_bytes().eq_ignore_ascii_case(extension)
+        }
+        _ => extension.is_empty() && name.as_bytes().eq_ignore_ascii_case(base),
+    }
+}
+
+/// The long filename entries for `name`, in the order they're stored before
+/// the short filename entry
+fn long_name_entries(name: &str, short_name: &[u8; 11]) -> Vec<DirectoryData> {
+    let checksum = short_name.iter().fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b));
+    let parts: Vec<&[u8]> = name.as_bytes().chunks(LONG_FILENAME_CHAR_OFFSETS.len()).collect();
+
+    parts
+        .iter()
+        .enumerate()
+        .rev()
+        .map(|(i, part)| {
+            let mut bytes = [0; core::mem::size_of::<DirectoryData>()];
+            // The last part is marked with bit 6 of its sequence number
+            bytes[0] = (i as u8 + 1) | if i == parts.len() - 1 { 0x40 } else { 0 };
+            bytes[11] = DirectoryAttributes::LONG_FILENAME.0;
+            bytes[13] = checksum;
+
+            // Names are null terminated if they don't fill the last part, and
+            // padded with `0xFFFF` after that
+            let chars = part.iter().map(|&b| u16::from(b)).chain(core::iter::once(0)).chain(core::iter::repeat(0xFFFF));
+            for (offset, c) in LONG_FILENAME_CHAR_OFFSETS.into_iter().zip(chars) {
+                bytes[offset..][..2].copy_from_slice(&c.to_le_bytes());
+            }
+
+            DirectoryData::from_bytes(bytes)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystems::bpb::{UnalignedU16, UnalignedU32};
+    use core::ptr::NonNull;
+    use units::data::Bytes;
+
+    const SECTORS_PER_FAT: u64 = 1;
+    const RESERVED_SECTORS: u64 = 2;
+
+    /// A block device backed by memory
+    struct RamDisk {
+        sectors: SyncRefCell<Vec<[u8; SECTOR_SIZE as usize]>>,
+        drop: SyncRc<dyn Fn(usize, NonNull<[u8]>)>,
+    }
+
+    impl RamDisk {
+        fn block(&self, sector: &[u8]) -> DataBlock {
+            // `u64`s for the alignment `DataBlock` expects
+            let buffer = Box::into_raw(Box::new([0u64; SECTOR_SIZE as usize / 8])).cast::<u8>();
+            let ptr = NonNull::new(core::ptr::slice_from_raw_parts_mut(buffer, SECTOR_SIZE as usize)).unwrap();
+            let mut block = unsafe { DataBlock::new(0, ptr, &self.drop) };
+            block.copy_from_slice(sector);
+
+            block
+        }
+
+        fn sector(&self, sector: u64) -> [u8; SECTOR_SIZE as usize] {
+            self.sectors.borrow()[sector as usize]
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        fn block_size(&self) -> Bytes {
+            Bytes::new(SECTOR_SIZE)
+        }
+
+        fn handle_interrupt(&self) {}
+
+        fn read_only(&self) -> bool {
+            false
+        }
+
+        fn alloc_data_block(&self) -> BoxedFuture<'static, DataBlock> {
+            Box::pin(core::future::ready(self.block(&[0xCC; SECTOR_SIZE as usize])))
+        }
+
+        fn flush(&self, _: core::ops::Range<SectorIndex>) -> BoxedFuture<'static, ()> {
+            Box::pin(core::future::ready(()))
+        }
+
+        fn read(&self, sector: SectorIndex) -> BoxedFuture<'static, Result<DataBlock, DeviceError>> {
+            Box::pin(core::future::ready(Ok(self.block(&self.sector(sector.get())))))
+        }
+
+        fn write(&self, sector: SectorIndex, block: DataBlock) -> BoxedFuture<'static, Result<(), DeviceError>> {
+            self.sectors.borrow_mut()[sector.get() as usize].copy_from_slice(&block);
+            Box::pin(core::future::ready(Ok(())))
+        }
+    }
+
+    /// Format a volume with two FATs, an empty root directory in cluster 2, and
+    /// `clusters` clusters in total
+    fn format(clusters: u64, sectors_per_cluster: u8) -> (SyncRc<RamDisk>, Fat32) {
+        let total_sectors = RESERVED_SECTORS + 2 * SECTORS_PER_FAT + clusters * u64::from(sectors_per_cluster);
+        let disk = SyncRc::new(RamDisk {
+            sectors: SyncRefCell::new(vec![[0; SECTOR_SIZE as usize]; total_sectors as usize]),
+            drop: SyncRc::new(|_, ptr: NonNull<[u8]>| unsafe {
+                drop(Box::from_raw(ptr.as_ptr().cast::<[u64; SECTOR_SIZE as usize / 8]>()))
+            }),
+        });
+
+        let mut bpb = BiosParameterBlock::zeroed();
+        bpb.bytes_per_sector = UnalignedU16::new(SECTOR_SIZE as u16);
+        bpb.sectors_per_cluster = sectors_per_cluster;
+        bpb.reserved_sector_count = UnalignedU16::new(RESERVED_SECTORS as u16);
+        bpb.num_fats = 2;
+        bpb.fat32_total_sectors = UnalignedU32::new(total_sectors as u32);
+        bpb.fat32_fat_size = UnalignedU32::new(SECTORS_PER_FAT as u32);
+        bpb.root_cluster = UnalignedU32::new(2);
+        bpb.fs_info_sector = UnalignedU16::new(1);
+
+        let mut fs_info = FsInfo::zeroed();
+        fs_info.lead_signature = LittleEndianU32::from_ne(FsInfo::LEAD_SIGNATURE);
+        fs_info.struct_signature = LittleEndianU32::from_ne(FsInfo::STRUCT_SIGNATURE);
+        fs_info.trail_signature = LittleEndianU32::from_ne(FsInfo::TRAIL_SIGNATURE);
+        fs_info.free_count = LittleEndianU32::from_ne(clusters as u32 - 1);
+        fs_info.next_free = LittleEndianU32::from_ne(3);
+
+        let mut sectors = disk.sectors.borrow_mut();
+        sectors[1].copy_from_slice(fs_info.as_bytes());
+        for fat in 0..2 {
+            let entries = [0x0FFFFFF8u32, 0x0FFFFFFF, FatEntry::END_OF_CHAIN];
+            for (i, entry) in entries.into_iter().enumerate() {
+                sectors[(RESERVED_SECTORS + fat * SECTORS_PER_FAT) as usize][i * 4..][..4]
+                    .copy_from_slice(&entry.to_le_bytes());
+            }
+        }
+        drop(sectors);
+
+        let mut fat32 = Fat32::new(
+            SyncRc::clone(&disk) as SyncRc<dyn BlockDevice>,
+            &bpb,
+            SectorIndex::new(0),
+            SectorIndex::new(total_sectors),
+        );
+        fat32.set_root(Path::new("/"));
+
+        (disk, fat32)
+    }
+
+    fn fat_entry(disk: &RamDisk, fat: u64, cluster: u64) -> u32 {
+        let sector = disk.sector(RESERVED_SECTORS + fat * SECTORS_PER_FAT);
+        u32::from_le_bytes(sector[cluster as usize * 4..][..4].try_into().unwrap())
+    }
+
+    fn fs_info(disk: &RamDisk) -> FsInfo {
+        FsInfo::from_bytes(disk.sector(1))
+    }
+
+    fn read_to_end(fat32: &Fat32, file: FileId) -> Vec<u8> {
+        let mut contents = Vec::new();
+        while let Some((len, data)) = present::block_on(fat32.read_file_block(file.clone())).unwrap() {
+            contents.extend_from_slice(&data[..len]);
+        }
+
+        contents
+    }
+
+    #[test_case]
+    fn create_write_and_read_back() {
+        let (disk, fat32) = format(16, 2);
+        fat32.set_clock(|| Timestamp::new(2022, 6, 15, 13, 37, 42));
+        let root = fat32.root();
+
+        present::block_on(fat32.create_dir(root.clone(), Path::new("documents"))).unwrap();
+        let file = present::block_on(fat32.create_file(root.clone(), Path::new("documents/notes.txt"))).unwrap();
+
+        // Writes which don't line up with sectors or clusters
+        let contents: Vec<u8> = (0..2500u32).map(|n| (n % 251) as u8).collect();
+        assert_eq!(present::block_on(fat32.write_file(file.clone(), contents[..700].to_vec())).unwrap(), 700);
+        assert_eq!(present::block_on(fat32.write_file(file.clone(), contents[700..].to_vec())).unwrap(), 1800);
+        present::block_on(fat32.close(file)).unwrap();
+
+        let file = present::block_on(fat32.open(root.clone(), Path::new("documents/notes.txt"), FilePermissions::READ))
+            .unwrap();
+        assert_eq!(read_to_end(&fat32, file), contents);
+
+        let listing = present::block_on(fat32.list_directory(root.clone(), Path::new("documents"))).unwrap();
+        assert_eq!(listing, [FileInfo { filename: String::from("notes.txt"), file_type: FileType::File }]);
+        assert!(matches!(
+            present::block_on(fat32.create_file(root.clone(), Path::new("documents/NOTES.TXT"))),
+            Err(FilesystemError::AlreadyExists)
+        ));
+
+        // The directory took cluster 3 and the file's 2500 bytes the next 3
+        // clusters of 1024 bytes, chained the same way in both FATs
+        let (entry, _) = present::block_on(find_path(
+            &*disk,
+            &fat32.inner().fat,
+            SectorIndex::new(RESERVED_SECTORS + 2 * SECTORS_PER_FAT),
+            Cluster(2),
+            2,
+            Path::new("/documents/notes.txt"),
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(entry.start_cluster(), Cluster(4));
+        assert_eq!(entry.file_size.to_ne(), 2500);
+        assert_eq!(entry.modify_date.to_ne(), Timestamp::new(2022, 6, 15, 0, 0, 0).date);
+        assert_eq!(entry.modify_time.to_ne(), Timestamp::new(1980, 1, 1, 13, 37, 42).time);
+
+        for fat in 0..2 {
+            assert_eq!(fat_entry(&disk, fat, 3), FatEntry::END_OF_CHAIN);
+            assert_eq!(fat_entry(&disk, fat, 4), 5);
+            assert_eq!(fat_entry(&disk, fat, 5), 6);
+            assert_eq!(fat_entry(&disk, fat, 6), FatEntry::END_OF_CHAIN);
+            assert_eq!(fat_entry(&disk, fat, 7), 0);
+        }
+
+        let fs_info = fs_info(&disk);
+        assert_eq!(fs_info.free_count.to_ne(), 15 - 4);
+        assert_eq!(fs_info.next_free.to_ne(), 7);
+    }
+
+    #[test_case]
+    fn overlapping_writes() {
+        let (_, fat32) = format(16, 1);
+        let root = fat32.root();
+
+        let file = present::block_on(fat32.create_file(root.clone(), Path::new("log.txt"))).unwrap();
+        let reader = present::block_on(fat32.open(root, Path::new("log.txt"), FilePermissions::READ)).unwrap();
+
+        // Both writes are started before either runs, so the second one has to
+        // continue from where the first one leaves off
+        let first = fat32.write_file(file.clone(), vec![b'a'; 700]);
+        let second = fat32.write_file(file.clone(), vec![b'b'; 100]);
+        assert_eq!(present::block_on(first).unwrap(), 700);
+        assert_eq!(present::block_on(second).unwrap(), 100);
+        present::block_on(fat32.close(file)).unwrap();
+
+        // Other handles to the file see what was written through this one
+        let mut expected = vec![b'a'; 700];
+        expected.extend_from_slice(&[b'b'; 100]);
+        assert_eq!(read_to_end(&fat32, reader), expected);
+    }
+
+    #[test_case]
+    fn out_of_space() {
+        let (disk, fat32) = format(4, 1);
+        let root = fat32.root();
+
+        let file = present::block_on(fat32.create_file(root.clone(), Path::new("big.bin"))).unwrap();
+        let first = present::block_on(fat32.write_file(file.clone(), vec![0xAB; 2048])).unwrap();
+        assert_eq!(first, 3 * SECTOR_SIZE as usize);
+
+        assert!(matches!(
+            present::block_on(fat32.write_file(file.clone(), vec![0xCD; 16])),
+            Err(FilesystemError::OutOfSpace)
+        ));
+        assert!(matches!(
+            present::block_on(fat32.create_dir(root.clone(), Path::new("more"))),
+            Err(FilesystemError::OutOfSpace)
+        ));
+
+        // Nothing is left half done when the space runs out
+        assert_eq!(fs_info(&disk).free_count.to_ne(), 0);
+        assert_eq!(fat_entry(&disk, 0, 5), FatEntry::END_OF_CHAIN);
+        assert_eq!(fat_entry(&disk, 1, 5), FatEntry::END_OF_CHAIN);
+        assert!(present::block_on(fat32.exists(root.clone(), Path::new("more"))).unwrap().is_none());
+
+        present::block_on(fat32.close(file)).unwrap();
+        let file = present::block_on(fat32.open(root, Path::new("big.bin"), FilePermissions::READ)).unwrap();
+        assert_eq!(read_to_end(&fat32, file), [0xAB; 3 * SECTOR_SIZE as usize]);
+    }
+
+    #[test_case]
+    fn directories_grow() {
+        let (disk, fat32) = format(16, 1);
+        let root = fat32.root();
+        present::block_on(fat32.create_dir(root.clone(), Path::new("dir"))).unwrap();
+
+        // Each file takes a long and a short filename entry, and a cluster
+        // only fits 16 entries
+        let names: Vec<String> = (0..20).map(|n| format!("file{n}.txt")).collect();
+        for name in &names {
+            let file = present::block_on(fat32.create_file(root.clone(), Path::new(&format!("dir/{name}")))).unwrap();
+            present::block_on(fat32.close(file)).unwrap();
+        }
+
+        // A name long enough to need more entries than fit in a cluster
+        let long_name = "a".repeat(250);
+        let long_path = format!("dir/{long_name}");
+        let file = present::block_on(fat32.create_file(root.clone(), Path::new(&long_path))).unwrap();
+        assert_eq!(present::block_on(fat32.write_file(file.clone(), b"grown".to_vec())).unwrap(), 5);
+        present::block_on(fat32.close(file)).unwrap();
+
+        let listing = present::block_on(fat32.list_directory(root.clone(), Path::new("dir"))).unwrap();
+        let listed: Vec<&str> = listing.iter().map(|info| info.filename.as_str()).collect();
+        let expected: Vec<&str> = names.iter().map(String::as_str).chain([long_name.as_str()]).collect();
+        assert_eq!(listed, expected);
+
+        // `.`, `..`, and the 40 entries for the short names fill 3 clusters,
+        // and the 20 + 1 entries for the long name spill into a 4th, so the
+        // directory grew from cluster 3 into clusters 4 through 6 before the
+        // file's data was written to cluster 7
+        for fat in 0..2 {
+            assert_eq!(fat_entry(&disk, fat, 3), 4);
+            assert_eq!(fat_entry(&disk, fat, 4), 5);
+            assert_eq!(fat_entry(&disk, fat, 5), 6);
+            assert_eq!(fat_entry(&disk, fat, 6), FatEntry::END_OF_CHAIN);
+            assert_eq!(fat_entry(&disk, fat, 7), FatEntry::END_OF_CHAIN);
+        }
+
+        // The entry in the new cluster was updated by the write
+        let file = present::block_on(fat32.open(root, Path::new(&long_path), FilePermissions::READ)).unwrap();
+        assert_eq!(read_to_end(&fat32, file), b"grown");
+    }
+}
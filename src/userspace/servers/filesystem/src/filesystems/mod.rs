@@ -21,6 +21,7 @@ pub mod path;
 
 #[derive(Debug)]
 pub enum FilesystemError {
+    AlreadyExists,
     DeviceError(DeviceError),
     DirectoryNotFound,
     FileNotFound,
@@ -29,6 +30,7 @@ pub enum FilesystemError {
     InvalidPath,
     InvalidRoot,
     OperationNotSupported,
+    OutOfSpace,
 }
 
 impl From<DeviceError> for FilesystemError {
@@ -122,6 +124,10 @@ pub trait Filesystem: Send + Sync {
         file: FileId,
     ) -> BoxedFuture<'static, Result<Option<(usize, DataBlock)>, FilesystemError>>;
 
+    /// Write `data` to the file after anything previously written, returning
+    /// how many bytes were written
+    fn write_file_block(&self, file: FileId, data: Vec<u8>) -> BoxedFuture<'static, Result<usize, FilesystemError>>;
+
     fn exists(&self, root: Root, path: &Path) -> BoxedFuture<'static, Result<Option<FileType>, FilesystemError>>;
     fn list_directory(&self, root: Root, path: &Path) -> BoxedFuture<'static, Result<Vec<FileInfo>, FilesystemError>>;
 }
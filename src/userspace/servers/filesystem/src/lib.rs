@@ -9,6 +9,7 @@
 
 #![feature(allocator_api, async_fn_in_trait, inline_const)]
 #![allow(incomplete_features)]
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
 #![warn(missing_docs)]
 
 macro_rules! assert_struct_size {
@@ -41,6 +42,7 @@ pub mod waitlist;
 /// VIDL interface
 pub mod vidl {
     pub use raw::{Error, OpenOptions};
+    use std::path::Path;
     use vidl::CapabilityPtr;
 
     pub mod raw {
@@ -123,6 +125,21 @@ pub mod vidl {
             Ok(read)
         }
 
+        /// Write as much of `buffer` as fits in the file's shared buffer,
+        /// returning how many bytes were written. Files are opened for either
+        /// reading or writing, so this discards any data buffered by
+        /// [`File::read`].
+        pub fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+            if buffer.is_empty() {
+                return Ok(0);
+            }
+
+            self.cursor = BufferCursor::default();
+            let len = self.file.buffer.copy_from_slice(buffer);
+
+            self.client.write(self.file.handle, len)
+        }
+
         pub fn close(self) -> Result<(), Error> {
             // Safety: we `core::mem::forget(self)` so only the cloned copy will
             // get dropped
@@ -135,6 +152,36 @@ pub mod vidl {
         }
     }
 
+    impl std::io::Read for File {
+        fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+            File::read(self, buffer).map_err(Into::into)
+        }
+    }
+
+    impl std::io::Write for File {
+        fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+            File::write(self, buffer).map_err(Into::into)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            // Writes go straight to the server
+            Ok(())
+        }
+    }
+
+    impl From<Error> for std::io::Error {
+        fn from(error: Error) -> Self {
+            std::io::Error::new(match error {
+                Error::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+                Error::FileNotFound => std::io::ErrorKind::NotFound,
+                Error::InvalidPath => std::io::ErrorKind::InvalidInput,
+                Error::OperationNotSupported => std::io::ErrorKind::Unsupported,
+                Error::OutOfSpace => std::io::ErrorKind::StorageFull,
+                Error::InvalidHandle | Error::IoError => std::io::ErrorKind::Other,
+            })
+        }
+    }
+
     impl Drop for File {
         fn drop(&mut self) {
             // If there's an error closing a file, don't panic
@@ -152,8 +199,10 @@ pub mod vidl {
             Self { client: raw::FilesystemClient::new(cptr), cptr }
         }
 
-        pub fn open<'a>(&'a self, path: &str, options: OpenOptions) -> Result<File, Error> {
-            let file = self.client.open(path, options)?;
+        /// Open the file at `path`, which is normalized first (see
+        /// [`Path::normalize`])
+        pub fn open(&self, path: impl AsRef<Path>, options: OpenOptions) -> Result<File, Error> {
+            let file = self.client.open(path.as_ref().normalize().as_str(), options)?;
             Ok(File { client: raw::FilesystemClient::new(self.cptr), file, cursor: BufferCursor::default() })
         }
     }
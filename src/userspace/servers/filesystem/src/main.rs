@@ -7,6 +7,7 @@
 
 #![feature(async_fn_in_trait)]
 #![allow(incomplete_features)]
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
 
 mod client;
 
@@ -22,17 +22,25 @@ impl<T: Copy + Ord> WaitList<T> {
     }
 
     pub async fn acquire(&self, key: T) -> AcquiredToken<T> {
-        let mut me = self.map.borrow_mut();
-
-        match me.get_mut(&key) {
-            Some(waitlist) => {
-                let (tx, rx) = oneshot();
-                waitlist.push_back(tx);
-                drop(me);
-
-                rx.recv().await;
+        // The borrow is scoped so it isn't held across the `await`, which would
+        // make the future `!Send`
+        let wait = {
+            let mut me = self.map.borrow_mut();
+            match me.get_mut(&key) {
+                Some(waitlist) => {
+                    let (tx, rx) = oneshot();
+                    waitlist.push_back(tx);
+                    Some(rx)
+                }
+                None => {
+                    me.insert(key, VecDeque::new());
+                    None
+                }
             }
-            None => drop(me.insert(key, VecDeque::new())),
+        };
+
+        if let Some(rx) = wait {
+            rx.recv().await;
         }
 
         AcquiredToken(key, SyncRc::clone(&self.map))
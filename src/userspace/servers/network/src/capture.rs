@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::drivers::{DriverError, NetworkDriver};
+use netstack::MacAddress;
+use network::tap::{Direction, TapFrame};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::SyncRefCell,
+    time::Duration,
+};
+
+pub static PACKET_TAPS: PacketTaps = PacketTaps::new();
+
+/// The maximum number of bytes of encoded frames a single tap holds onto
+/// before the oldest frames start being dropped
+const TAP_CAPACITY: usize = 256 * 1024;
+
+pub struct PacketTaps {
+    taps: SyncRefCell<BTreeMap<u64, TapRing>>,
+    next_id: SyncRefCell<u64>,
+}
+
+impl PacketTaps {
+    const fn new() -> Self {
+        Self { taps: SyncRefCell::new(BTreeMap::new()), next_id: SyncRefCell::new(0) }
+    }
+
+    /// Begin mirroring frames into a new tap, returning its ID
+    pub fn open(&self) -> u64 {
+        let mut next_id = self.next_id.borrow_mut();
+        let id = *next_id;
+        *next_id += 1;
+
+        self.taps.borrow_mut().insert(id, TapRing::new(TAP_CAPACITY));
+        id
+    }
+
+    /// Stop mirroring frames into the given tap and discard any frames which
+    /// haven't been drained
+    pub fn close(&self, id: u64) {
+        self.taps.borrow_mut().remove(&id);
+    }
+
+    /// Remove as many whole captured frames as fit in `max_len` bytes from the
+    /// tap, returning them encoded as a tap stream, or `None` if the tap
+    /// doesn't exist
+    pub fn drain(&self, id: u64, max_len: usize) -> Option<Vec<u8>> {
+        Some(self.taps.borrow_mut().get_mut(&id)?.drain(max_len))
+    }
+
+    fn mirror(&self, direction: Direction, frame: &[u8]) {
+        let mut taps = self.taps.borrow_mut();
+        if taps.is_empty() {
+            return;
+        }
+
+        let timestamp = timestamp();
+        for ring in taps.values_mut() {
+            ring.push(TapFrame { timestamp, direction, data: frame });
+        }
+    }
+}
+
+/// The current time of the platform timer, converted from ticks using its
+/// timebase frequency
+fn timestamp() -> Duration {
+    let reading = librust::syscalls::time::read_time();
+    let nanos = u128::from(reading.ticks) * 1_000_000_000 / u128::from(reading.frequency.max(1));
+
+    Duration::from_nanos(nanos as u64)
+}
+
+/// A bounded queue of encoded frames which drops the oldest frames when full
+struct TapRing {
+    frames: VecDeque<Vec<u8>>,
+    len: usize,
+    capacity: usize,
+}
+
+impl TapRing {
+    fn new(capacity: usize) -> Self {
+        Self { frames: VecDeque::new(), len: 0, capacity }
+    }
+
+    fn push(&mut self, frame: TapFrame<'_>) {
+        if frame.encoded_len() > self.capacity {
+            return;
+        }
+
+        while self.len + frame.encoded_len() > self.capacity {
+            let Some(dropped) = self.frames.pop_front() else { break };
+            self.len -= dropped.len();
+        }
+
+        let mut encoded = Vec::with_capacity(frame.encoded_len());
+        frame.encode_into(&mut encoded);
+        self.len += encoded.len();
+        self.frames.push_back(encoded);
+    }
+
+    fn drain(&mut self, max_len: usize) -> Vec<u8> {
+        let mut drained = Vec::new();
+        while let Some(frame) = self.frames.front() {
+            if drained.len() + frame.len() > max_len {
+                break;
+            }
+
+            let frame = self.frames.pop_front().unwrap();
+            self.len -= frame.len();
+            drained.extend_from_slice(&frame);
+        }
+
+        drained
+    }
+}
+
+/// Wraps a [`NetworkDriver`] so that every frame it receives or transmits is
+/// mirrored into the open [`PACKET_TAPS`]
+pub struct TappedDriver<D: NetworkDriver>(D);
+
+impl<D: NetworkDriver> TappedDriver<D> {
+    pub fn new(driver: D) -> Self {
+        Self(driver)
+    }
+}
+
+impl<D: NetworkDriver> NetworkDriver for TappedDriver<D> {
+    fn mac(&self) -> MacAddress {
+        self.0.mac()
+    }
+
+    fn process_interrupt(&mut self, interrupt_id: usize) -> Result<Option<&[u8]>, DriverError> {
+        let packet = self.0.process_interrupt(interrupt_id)?;
+        if let Some(packet) = packet {
+            PACKET_TAPS.mirror(Direction::Received, packet);
+        }
+
+        Ok(packet)
+    }
+
+    fn tx_raw(&mut self, raw: &dyn Fn(&mut [u8]) -> Option<usize>) -> Result<(), DriverError> {
+        self.0.tx_raw(&|buffer| {
+            let written = raw(buffer)?;
+            PACKET_TAPS.mirror(Direction::Transmitted, &buffer[..written]);
+            Some(written)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netstack::{
+        ethernet::EthernetHeader,
+        ipv4::{IpV4Address, IpV4Header, IpV4Socket},
+        udp::UdpHeader,
+    };
+    use network::tap::TapFrames;
+
+    struct LoopbackDriver;
+
+    impl NetworkDriver for LoopbackDriver {
+        fn mac(&self) -> MacAddress {
+            MacAddress::new([0x52, 0x54, 0x00, 0x12, 0x34, 0x56])
+        }
+
+        fn process_interrupt(&mut self, _: usize) -> Result<Option<&[u8]>, DriverError> {
+            Ok(None)
+        }
+
+        fn tx_raw(&mut self, raw: &dyn Fn(&mut [u8]) -> Option<usize>) -> Result<(), DriverError> {
+            raw(&mut [0; 1514][..]).map(drop).ok_or(DriverError::DataTooLong)
+        }
+    }
+
+    #[test_case]
+    fn sent_udp_datagram_is_tapped() {
+        let mut driver = TappedDriver::new(LoopbackDriver);
+        let id = PACKET_TAPS.open();
+
+        driver
+            .tx_udp4(
+                IpV4Socket::new(IpV4Address::new(10, 0, 2, 15), 1234),
+                (MacAddress::BROADCAST, IpV4Socket::new(IpV4Address::new(10, 0, 2, 2), 4321)),
+                &|buffer| {
+                    buffer[..5].copy_from_slice(b"hello");
+                    Some(5)
+                },
+            )
+            .unwrap();
+
+        let stream = PACKET_TAPS.drain(id, 4096).unwrap();
+        let frames = TapFrames::new(&stream).collect::<Vec<_>>();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].direction, Direction::Transmitted);
+
+        let headers_len = core::mem::size_of::<EthernetHeader>()
+            + core::mem::size_of::<IpV4Header>()
+            + core::mem::size_of::<UdpHeader>();
+        assert_eq!(&frames[0].data[headers_len..], b"hello");
+
+        // Once the tap is closed, frames are no longer mirrored into it
+        PACKET_TAPS.close(id);
+        driver.tx_raw(&|_| Some(0)).unwrap();
+        assert_eq!(PACKET_TAPS.drain(id, 4096), None);
+    }
+}
@@ -5,9 +5,9 @@
 // v. 2.0. If a copy of the MPL was not distributed with this file, You can
 // obtain one at https://mozilla.org/MPL/2.0/.
 
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, time::Duration};
 
-use crate::{ClientMessage, ControlMessage, PortType};
+use crate::{capture::PACKET_TAPS, ClientMessage, ControlMessage, PortType};
 use librust::capabilities::CapabilityPtr;
 use netstack::ipv4::{IpV4Address, IpV4Socket};
 use network::NetworkError;
@@ -17,12 +17,27 @@ use vidl::sync::SharedBuffer;
 struct BoundPort {
     packet_rx: Receiver<ClientMessage>,
     buffer: SharedBuffer,
+    recv_timeout: Option<Duration>,
+}
+
+struct Tap {
+    id: u64,
+    buffer: SharedBuffer,
 }
 
 struct ClientProvider {
     control_tx: Sender<ControlMessage>,
     packet_tx: Sender<(u16, IpV4Socket, Vec<u8>)>,
     bound_ports: BTreeMap<u16, BoundPort>,
+    taps: BTreeMap<usize, Tap>,
+}
+
+impl Drop for ClientProvider {
+    fn drop(&mut self) {
+        for tap in self.taps.values() {
+            PACKET_TAPS.close(tap.id);
+        }
+    }
 }
 
 impl network::raw::AsyncNetworkProvider for ClientProvider {
@@ -31,13 +46,33 @@ impl network::raw::AsyncNetworkProvider for ClientProvider {
     async fn bind_udp(
         &mut self,
         socket: network::IpV4Socket,
+    ) -> Result<Result<vidl::sync::SharedBuffer, NetworkError>, Self::Error> {
+        self.bind_udp_with_options(socket, network::raw::BindOptions { reuse_address: false, recv_timeout_ms: None })
+            .await
+    }
+
+    async fn bind_udp_with_options(
+        &mut self,
+        socket: network::IpV4Socket,
+        options: network::raw::BindOptions,
     ) -> Result<Result<vidl::sync::SharedBuffer, NetworkError>, Self::Error> {
         if self.bound_ports.get(&socket.port).is_some() {
-            return Ok(Err(NetworkError::AlreadyBound));
+            if !options.reuse_address {
+                return Ok(Err(NetworkError::AlreadyBound));
+            }
+
+            // Dropping our end marks the port as unowned so the network task
+            // lets us take it over again
+            self.bound_ports.remove(&socket.port);
         }
 
         let (packet_tx, packet_rx) = present::sync::mpsc::unbounded();
-        self.control_tx.send(ControlMessage::NewClient { port: socket.port, port_type: PortType::Udp, tx: packet_tx });
+        self.control_tx.send(ControlMessage::NewClient {
+            port: socket.port,
+            port_type: PortType::Udp,
+            tx: packet_tx,
+            reuse_address: options.reuse_address,
+        });
 
         match packet_rx.recv().await {
             ClientMessage::PortInUse => {
@@ -49,12 +84,24 @@ impl network::raw::AsyncNetworkProvider for ClientProvider {
 
         let buffer = SharedBuffer::new(4096).unwrap();
         let buffer2 = unsafe { buffer.clone() };
+        let recv_timeout = options.recv_timeout_ms.map(Duration::from_millis);
 
-        self.bound_ports.insert(socket.port, BoundPort { packet_rx, buffer });
+        self.bound_ports.insert(socket.port, BoundPort { packet_rx, buffer, recv_timeout });
 
         Ok(Ok(buffer2))
     }
 
+    async fn set_recv_timeout(
+        &mut self,
+        socket: network::IpV4Socket,
+        timeout_ms: Option<u64>,
+    ) -> Result<Result<(), NetworkError>, Self::Error> {
+        let Some(bound_port) = self.bound_ports.get_mut(&socket.port) else { return Ok(Err(NetworkError::NotBound)) };
+        bound_port.recv_timeout = timeout_ms.map(Duration::from_millis);
+
+        Ok(Ok(()))
+    }
+
     async fn send(
         &mut self,
         socket: network::IpV4Socket,
@@ -84,7 +131,14 @@ impl network::raw::AsyncNetworkProvider for ClientProvider {
         socket: network::IpV4Socket,
     ) -> Result<Result<network::raw::RecvInfo, NetworkError>, Self::Error> {
         let Some(bound_port) = self.bound_ports.get_mut(&socket.port) else { return Ok(Err(NetworkError::NotBound)) };
-        let ClientMessage::Received { from, data } = bound_port.packet_rx.recv().await else { unreachable!() };
+        let message = match bound_port.recv_timeout {
+            Some(timeout) => match present::time::timeout(timeout, bound_port.packet_rx.recv()).await {
+                Ok(message) => message,
+                Err(_) => return Ok(Err(NetworkError::TimedOut)),
+            },
+            None => bound_port.packet_rx.recv().await,
+        };
+        let ClientMessage::Received { from, data } = message else { unreachable!() };
         // TODO: check for data size
         bound_port.buffer.copy_from_slice(&data[..]);
 
@@ -93,6 +147,38 @@ impl network::raw::AsyncNetworkProvider for ClientProvider {
             len: data.len(),
         }))
     }
+
+    async fn tap(&mut self, interface: usize) -> Result<Result<vidl::sync::SharedBuffer, NetworkError>, Self::Error> {
+        // Only a single interface is currently supported
+        if interface != 0 {
+            return Ok(Err(NetworkError::NoSuchInterface));
+        }
+
+        if let Some(tap) = self.taps.remove(&interface) {
+            PACKET_TAPS.close(tap.id);
+        }
+
+        let buffer = SharedBuffer::new(16 * 4096).unwrap();
+        let buffer2 = unsafe { buffer.clone() };
+
+        self.taps.insert(interface, Tap { id: PACKET_TAPS.open(), buffer });
+
+        Ok(Ok(buffer2))
+    }
+
+    async fn drain_tap(&mut self, interface: usize) -> Result<Result<usize, NetworkError>, Self::Error> {
+        let Some(tap) = self.taps.get_mut(&interface) else { return Ok(Err(NetworkError::NotTapped)) };
+        let Some(frames) = PACKET_TAPS.drain(tap.id, tap.buffer.len()) else { return Ok(Err(NetworkError::NotTapped)) };
+
+        Ok(Ok(tap.buffer.copy_from_slice(&frames)))
+    }
+
+    async fn untap(&mut self, interface: usize) -> Result<Result<(), NetworkError>, Self::Error> {
+        let Some(tap) = self.taps.remove(&interface) else { return Ok(Err(NetworkError::NotTapped)) };
+        PACKET_TAPS.close(tap.id);
+
+        Ok(Ok(()))
+    }
 }
 
 pub async fn handle_client(
@@ -100,7 +186,10 @@ pub async fn handle_client(
     packet_tx: Sender<(u16, IpV4Socket, Vec<u8>)>,
     cptr: CapabilityPtr,
 ) {
-    network::raw::AsyncNetwork::new(ClientProvider { control_tx, packet_tx, bound_ports: BTreeMap::new() }, cptr)
-        .serve()
-        .await;
+    network::raw::AsyncNetwork::new(
+        ClientProvider { control_tx, packet_tx, bound_ports: BTreeMap::new(), taps: BTreeMap::new() },
+        cptr,
+    )
+    .serve()
+    .await;
 }
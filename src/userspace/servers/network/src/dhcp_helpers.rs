@@ -32,7 +32,12 @@ pub fn discover(mac: MacAddress) -> Vec<u8> {
     dhcp_message.boot_file_name = [0; 128];
 
     dhcp_message.push_option(DhcpOption::DhcpMessageType(DhcpMessageType::DISCOVER));
-    dhcp_message.push_option(DhcpOption::ParameterRequestList(&[DhcpOption::ROUTER]));
+    dhcp_message.push_option(DhcpOption::ParameterRequestList(&[
+        DhcpOption::SUBNET_MASK,
+        DhcpOption::ROUTER,
+        DhcpOption::DOMAIN_NAME_SERVER,
+        DhcpOption::IP_ADDRESS_LEASE_TIME,
+    ]));
 
     let len = dhcp_message.finish();
     bytes.truncate(len);
@@ -64,7 +64,7 @@ pub trait NetworkDriver {
             udp_hdr.checksum.zero();
 
             udp_hdr.len = Length16::new((size_of::<UdpHeader>() + payload_size) as u16);
-            udp_hdr.checksum.zero();
+            udp_hdr.generate_ipv4_checksum(ipv4_hdr, &payload[..payload_size]);
 
             ipv4_hdr.len = Length16::new((size_of::<IpV4Header>() + size_of::<UdpHeader>() + payload_size) as u16);
             ipv4_hdr.generate_checksum();
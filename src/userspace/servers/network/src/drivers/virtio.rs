@@ -33,6 +33,10 @@ pub struct VirtIoNetDevice {
     rx_buffer_map: BTreeMap<SplitqueueIndex<VirtqueueDescriptor>, usize>,
     tx_data_buffer: TxDataBuffer,
     tx_buffer_map: BTreeMap<SplitqueueIndex<VirtqueueDescriptor>, usize>,
+    /// The receive descriptor whose frame was last handed out by
+    /// `process_interrupt`, which is given back to the device on the next call
+    /// once the caller is done with the frame
+    pending_rx: Option<SplitqueueIndex<VirtqueueDescriptor>>,
 }
 
 impl VirtIoNetDevice {
@@ -61,6 +65,12 @@ impl VirtIoNetDevice {
             rx_buffer_map.insert(descriptor, index);
         }
 
+        // Only the modern MMIO register layout is supported, which QEMU only
+        // provides for `virtio-mmio.force-legacy=false`
+        if device.header.version.read() != 2 {
+            return Err(VirtIoDeviceError::FeaturesNotRecognized);
+        }
+
         device.header.status.reset();
 
         device.header.status.set_flag(StatusFlag::Acknowledge);
@@ -74,6 +84,12 @@ impl VirtIoNetDevice {
         let available_features = NetDeviceFeatures::new(available_features);
         let mut selected_features = NetDeviceFeatures::none();
 
+        // Modern devices refuse `FeaturesOk` unless this was negotiated
+        selected_features |= NetDeviceFeatures::VERSION_1;
+        if !(available_features & NetDeviceFeatures::VERSION_1) {
+            return Err(VirtIoDeviceError::FeaturesNotRecognized);
+        }
+
         // We require a valid MAC address
         selected_features |= NetDeviceFeatures::MAC_ADDRESS;
         if !(available_features & NetDeviceFeatures::MAC_ADDRESS) {
@@ -150,7 +166,16 @@ impl VirtIoNetDevice {
 
         device.header.queue_notify.notify(0);
 
-        Ok(Self { device, receive_queue, transmit_queue, rx_data_buffer, rx_buffer_map, tx_data_buffer, tx_buffer_map })
+        Ok(Self {
+            device,
+            receive_queue,
+            transmit_queue,
+            rx_data_buffer,
+            rx_buffer_map,
+            tx_data_buffer,
+            tx_buffer_map,
+            pending_rx: None,
+        })
     }
 
     pub fn mac_address(&self) -> MacAddress {
@@ -160,6 +185,27 @@ impl VirtIoNetDevice {
     pub fn link_status(&self) -> LinkStatus {
         unsafe { self.device.link_status() }
     }
+
+    /// Make the receive buffer for `descriptor` available to the device again
+    fn recycle_rx(&mut self, descriptor: SplitqueueIndex<VirtqueueDescriptor>) {
+        let index = self.rx_buffer_map[&descriptor];
+        let buffer = self.rx_data_buffer.get(index).unwrap();
+
+        self.receive_queue.descriptors.write(
+            descriptor,
+            VirtqueueDescriptor {
+                address: buffer.physical_address(),
+                length: core::mem::size_of::<VirtIoNetHeaderRx<MAX_PACKET_LENGTH>>() as u32,
+                flags: DescriptorFlags::WRITE,
+                next: SplitqueueIndex::new(0),
+            },
+        );
+        self.receive_queue.available.push(descriptor);
+
+        librust::mem::fence(librust::mem::FenceMode::Write);
+
+        self.device.header.queue_notify.notify(0);
+    }
 }
 
 struct TxDataBuffer {
@@ -209,12 +255,6 @@ impl RxDataBuffer {
         Some((index, self.buffer.get(index).unwrap()))
     }
 
-    fn dealloc(&mut self, index: usize) {
-        let index = u16::try_from(index).expect("invalid index supplied");
-        assert!(!self.free_indices.contains(&index));
-        self.free_indices.push_back(index);
-    }
-
     fn get(&mut self, index: usize) -> Option<DmaElement<'_, VirtIoNetHeaderRx<MAX_PACKET_LENGTH>>> {
         self.buffer.get(index)
     }
@@ -225,23 +265,33 @@ impl super::NetworkDriver for VirtIoNetDevice {
         self.mac_address()
     }
 
+    /// Returns at most one received frame per call, so this should be called
+    /// until it returns `None` to handle every frame behind an interrupt
     fn process_interrupt(&mut self, _: usize) -> Result<Option<&[u8]>, super::DriverError> {
         self.device.header.interrupt_ack.acknowledge_buffer_used();
 
-        if let Some(used) = self.transmit_queue.used.pop() {
+        // The previously returned frame can no longer be borrowed, so its
+        // buffer can be given back to the device
+        if let Some(descr) = self.pending_rx.take() {
+            self.recycle_rx(descr);
+        }
+
+        while let Some(used) = self.transmit_queue.used.pop() {
             let descr = SplitqueueIndex::new(used.start_index as u16);
             let index = self.tx_buffer_map.remove(&descr).unwrap();
             self.tx_data_buffer.dealloc(index);
+            self.transmit_queue.free_descriptor(descr);
         }
 
         if let Some(used) = self.receive_queue.used.pop() {
             let descr = SplitqueueIndex::new(used.start_index as u16);
-            let data_len = self.receive_queue.descriptors.read(descr).length as usize
-                - core::mem::size_of::<VirtIoNetHeaderRx<0>>();
-            let index = self.rx_buffer_map.remove(&descr).unwrap();
-            // Free index so we have it whenever the caller is done with it
-            // TODO: need to add it back to the available queue
-            self.rx_data_buffer.dealloc(index);
+            // The used length is how much the device actually wrote, including
+            // the header
+            let data_len = (used.length as usize)
+                .saturating_sub(core::mem::size_of::<VirtIoNetHeaderRx<0>>())
+                .min(MAX_PACKET_LENGTH);
+            let index = self.rx_buffer_map[&descr];
+            self.pending_rx = Some(descr);
 
             let buffer = self.rx_data_buffer.get(index).unwrap();
             let buffer = unsafe { &*buffer.get().as_ptr() };
@@ -264,6 +314,7 @@ impl super::NetworkDriver for VirtIoNetDevice {
         header.header_len = 0;
         header.checksum_offset = 0;
         header.checksum_start = 0;
+        header.num_buffers = 0;
 
         let descr = self.transmit_queue.alloc_descriptor().unwrap();
         self.transmit_queue.descriptors.write(
@@ -7,6 +7,7 @@
 
 #![feature(async_fn_in_trait)]
 #![allow(incomplete_features)]
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
 
 pub mod raw {
     use core::str::FromStr;
@@ -39,7 +40,10 @@ pub mod raw {
     }
 }
 
-pub use raw::{IpV4Address, IpV4Socket, NetworkError};
+pub mod tap;
+
+use core::time::Duration;
+pub use raw::{BindOptions, IpV4Address, IpV4Socket, NetworkError};
 use vidl::{sync::SharedBuffer, CapabilityPtr};
 
 pub struct UdpSocket {
@@ -56,6 +60,26 @@ impl UdpSocket {
         Ok(Self { client, buffer, socket })
     }
 
+    /// Bind `socket`, optionally taking over a port left behind by a socket
+    /// which has since been closed (see [`BindOptions::reuse_address`])
+    pub fn bind_with_options(
+        network_cptr: CapabilityPtr,
+        socket: IpV4Socket,
+        options: BindOptions,
+    ) -> Result<Self, NetworkError> {
+        let client = raw::NetworkClient::new(network_cptr);
+        let buffer = client.bind_udp_with_options(socket, options)?;
+
+        Ok(Self { client, buffer, socket })
+    }
+
+    /// Set how long [`UdpSocket::recv`] waits for a packet before returning
+    /// [`NetworkError::TimedOut`], or `None` to wait forever
+    pub fn set_recv_timeout(&mut self, timeout: Option<Duration>) -> Result<(), NetworkError> {
+        let timeout_ms = timeout.map(|timeout| u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX));
+        self.client.set_recv_timeout(self.socket, timeout_ms)
+    }
+
     pub fn send(&mut self, recipient: IpV4Socket, data: &[u8]) -> Result<(), NetworkError> {
         let copied = self.buffer.copy_from_slice(data);
         self.client.send(self.socket, recipient, copied)
@@ -67,3 +91,34 @@ impl UdpSocket {
         Ok((info.from, &buf[..usize::min(info.len, buf.len())]))
     }
 }
+
+/// Mirrors every Ethernet frame received and transmitted on a network interface
+/// until dropped
+pub struct PacketTap {
+    client: raw::NetworkClient,
+    buffer: SharedBuffer,
+    interface: usize,
+}
+
+impl PacketTap {
+    pub fn new(network_cptr: CapabilityPtr, interface: usize) -> Result<Self, NetworkError> {
+        let client = raw::NetworkClient::new(network_cptr);
+        let buffer = client.tap(interface)?;
+
+        Ok(Self { client, buffer, interface })
+    }
+
+    /// Drain the frames captured since the last call. Frames which don't fit
+    /// in the shared buffer are kept for the next drain.
+    pub fn drain(&mut self) -> Result<tap::TapFrames<'_>, NetworkError> {
+        let len = self.client.drain_tap(self.interface)?;
+        let buf = self.buffer.read();
+        Ok(tap::TapFrames::new(&buf[..usize::min(len, buf.len())]))
+    }
+}
+
+impl Drop for PacketTap {
+    fn drop(&mut self) {
+        let _ = self.client.untap(self.interface);
+    }
+}
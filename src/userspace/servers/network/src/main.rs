@@ -7,11 +7,14 @@
 
 #![feature(async_fn_in_trait)]
 #![allow(incomplete_features)]
+#![cfg_attr(test, feature(custom_test_frameworks), test_runner(std::test::runner))]
 
 mod arp;
+mod capture;
 mod client;
 mod dhcp_helpers;
 mod drivers;
+mod ports;
 
 use crate::{arp::ARP_CACHE, drivers::NetworkDriver};
 use alchemy::PackedStruct;
@@ -19,10 +22,10 @@ use dhcp::{options::DhcpMessageType, DhcpMessageParser, DhcpOption};
 use librust::capabilities::CapabilityPtr;
 use netstack::{
     arp::{ArpHeader, ArpOperation, ArpPacket, HardwareType},
-    ethernet::EthernetHeader,
+    ethernet::{EthernetFrame, EthernetHeader},
     ipv4::{IpV4Address, IpV4Header, IpV4Socket, Protocol},
     udp::UdpHeader,
-    MacAddress,
+    Configuration, MacAddress,
 };
 use present::{
     futures::stream::{IntoStream, StreamExt},
@@ -36,7 +39,7 @@ pub enum ControlMessage {
     ClientDisconnect { port: u16 },
     NewInterfaceIp(IpV4Address),
     NewDefaultGateway(IpV4Address),
-    NewClient { port: u16, port_type: PortType, tx: Sender<ClientMessage> },
+    NewClient { port: u16, port_type: PortType, tx: Sender<ClientMessage>, reuse_address: bool },
 }
 
 #[derive(Debug)]
@@ -75,13 +78,15 @@ async fn main() {
     let (info, _) = librust::syscalls::io::query_mmio_cap(device.capability.cptr, &mut []).unwrap();
 
     let interrupt_id = device.interrupts[0];
-    let mut net_device = drivers::virtio::VirtIoNetDevice::new(unsafe {
-        &*(info.address() as *const virtio::devices::net::VirtIoNetDevice)
-    })
-    .unwrap();
+    let mut net_device = capture::TappedDriver::new(
+        drivers::virtio::VirtIoNetDevice::new(unsafe {
+            &*(info.address() as *const virtio::devices::net::VirtIoNetDevice)
+        })
+        .unwrap(),
+    );
 
     let (packet_tx, packet_recv): (Sender<(u16, IpV4Socket, Vec<u8>)>, _) = present::sync::mpsc::unbounded();
-    let mut ports: BTreeMap<u16, (PortType, Sender<ClientMessage>)> = BTreeMap::new();
+    let mut ports = ports::PortTable::new();
 
     let interrupt = present::interrupt::Interrupt::new(interrupt_id);
 
@@ -93,7 +98,7 @@ async fn main() {
     let (arp_packet_task_tx, arp_packet_nic_rx): (Sender<Vec<u8>>, _) = present::sync::mpsc::unbounded();
     let (arp_packet_nic_tx, arp_packet_task_rx): (Sender<Vec<u8>>, _) = present::sync::mpsc::unbounded();
 
-    ports.insert(68, (PortType::Udp, dhcp_packet_nic_tx));
+    ports.bind(68, PortType::Udp, dhcp_packet_nic_tx, false).unwrap();
     let this_mac = net_device.mac();
 
     let mut interface_ips = Vec::new();
@@ -102,10 +107,8 @@ async fn main() {
 
     let dhcp_control_tx = control_tx.clone();
     present::spawn(async move {
-        let mut our_ip;
-        let mut router_ip;
         dhcp_packet_task_tx.send(dhcp_helpers::discover(this_mac));
-        loop {
+        let config = loop {
             let response: Vec<u8> = match dhcp_packet_task_rx.recv().await {
                 ClientMessage::Received { data, .. } => data,
                 _ => continue,
@@ -129,7 +132,7 @@ async fn main() {
                 }
             };
 
-            our_ip = message.your_ip_address;
+            let our_ip = message.your_ip_address;
             let dhcp_server_ip = match message.find_option(|option| match option {
                 DhcpOption::DhcpServerIdentifier(server_ip) => Some(server_ip),
                 _ => None,
@@ -138,14 +141,6 @@ async fn main() {
                 None => continue,
             };
 
-            router_ip = match message.find_option(|option| match option {
-                DhcpOption::Router(router_ip) => Some(router_ip),
-                _ => None,
-            }) {
-                Some(router_ip) => router_ip,
-                None => continue,
-            };
-
             dhcp_packet_task_tx.send(dhcp_helpers::request(this_mac, dhcp_server_ip, our_ip));
 
             let response: Vec<u8> = match dhcp_packet_task_rx.recv().await {
@@ -157,14 +152,22 @@ async fn main() {
                 Ok(response) => {
                     let mac: MacAddress = response.message.client_hardware_address.cast::<MacAddress>();
                     match response.message_type() {
-                        Ok(DhcpMessageType::ACK) if mac == this_mac => break,
+                        Ok(DhcpMessageType::ACK) if mac == this_mac => match Configuration::try_from(&response) {
+                            Ok(config) => break config,
+                            Err(e) => {
+                                println!("Bad DHCPACK: {:?}", e);
+                                continue;
+                            }
+                        },
                         _ => continue,
                     }
                 }
                 Err(_) => continue,
             }
-        }
+        };
 
+        println!("[network] DHCP configuration: {:?}", config);
+        let (our_ip, router_ip) = (config.address, config.default_route().gateway);
         dhcp_control_tx.send(ControlMessage::NewInterfaceIp(our_ip));
         dhcp_control_tx.send(ControlMessage::NewDefaultGateway(router_ip));
 
@@ -243,13 +246,19 @@ async fn main() {
         .merge(IntoStream::into_stream(dhcp_packet_nic_rx).map(Event::DhcpResponse))
         .merge(IntoStream::into_stream(control_rx).map(Event::ControlMessage));
 
+    let mut malformed_frames = 0usize;
     present::pin!(stream);
     while let Some(event) = stream.next().await {
         match event {
             Event::Interrupt(interrupt_id) => {
-                if let Ok(Some(packet)) = net_device.process_interrupt(interrupt_id) {
-                    let (eth_header, payload, _) = EthernetHeader::split_slice_ref(packet).unwrap();
-                    match eth_header.frame_type {
+                while let Ok(Some(packet)) = net_device.process_interrupt(interrupt_id) {
+                    let Ok(EthernetFrame { frame_type, payload, .. }) = EthernetFrame::parse(packet) else {
+                        malformed_frames += 1;
+                        println!("dropping malformed ethernet frame ({} so far)", malformed_frames);
+                        continue;
+                    };
+
+                    match frame_type {
                         EthernetHeader::ARP_FRAME => {
                             arp_packet_nic_tx.send(payload.to_vec());
                         }
@@ -260,7 +269,7 @@ async fn main() {
                                 Protocol::UDP => {
                                     let (udp_header, payload) = UdpHeader::split_slice_ref(payload).unwrap();
                                     let port = udp_header.destination_port.get();
-                                    if let Some((PortType::Udp, sender)) = ports.get(&port) {
+                                    if let Some((PortType::Udp, sender)) = ports.get(port) {
                                         sender.send(ClientMessage::Received {
                                             from: IpV4Socket::new(ipv4_header.source_ip, udp_header.source_port.get()),
                                             data: payload
@@ -288,7 +297,7 @@ async fn main() {
             Event::PacketToSend { port: outgoing_port, socket: dst_socket, data: pkt_data } => {
                 if !interface_ips.is_empty() && default_gateway.is_some() {
                     if let Some(mac) = ARP_CACHE.lookup(default_gateway.unwrap()) {
-                        if let Some((port_type, _)) = ports.get(&outgoing_port) {
+                        if let Some((port_type, _)) = ports.get(outgoing_port) {
                             match port_type {
                                 PortType::Udp => {
                                     net_device
@@ -345,18 +354,17 @@ async fn main() {
                     .unwrap();
             }
             Event::ControlMessage(control_message) => match control_message {
-                ControlMessage::ClientDisconnect { port } => drop(ports.remove(&port)),
+                ControlMessage::ClientDisconnect { port } => drop(ports.remove(port)),
                 ControlMessage::NewInterfaceIp(ip) => {
                     interface_ips.push(ip);
                     println!("[network] New IP on network interface: {}", ip);
                 }
                 ControlMessage::NewDefaultGateway(ip) => default_gateway = Some(ip),
-                ControlMessage::NewClient { port, port_type, tx } => {
-                    if ports.get(&port).is_some() {
-                        tx.send(ClientMessage::PortInUse);
-                    } else {
-                        tx.send(ClientMessage::PortBound);
-                        ports.insert(port, (port_type, tx));
+                ControlMessage::NewClient { port, port_type, tx, reuse_address } => {
+                    let response_tx = tx.clone();
+                    match ports.bind(port, port_type, tx, reuse_address) {
+                        Ok(()) => response_tx.send(ClientMessage::PortBound),
+                        Err(ports::PortInUse) => response_tx.send(ClientMessage::PortInUse),
                     }
                 }
             },
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use crate::{ClientMessage, PortType};
+use present::sync::mpsc::Sender;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortInUse;
+
+/// The ports bound on the interface and where packets received on them go
+#[derive(Debug, Default)]
+pub struct PortTable {
+    ports: BTreeMap<u16, (PortType, Sender<ClientMessage>)>,
+}
+
+impl PortTable {
+    pub fn new() -> Self {
+        Self { ports: BTreeMap::new() }
+    }
+
+    pub fn get(&self, port: u16) -> Option<&(PortType, Sender<ClientMessage>)> {
+        self.ports.get(&port)
+    }
+
+    pub fn remove(&mut self, port: u16) -> Option<(PortType, Sender<ClientMessage>)> {
+        self.ports.remove(&port)
+    }
+
+    /// Bind `port`, sending packets received on it to `tx`. If `reuse_address`
+    /// is set, a port whose previous owner has gone away (so nothing is left
+    /// to receive its packets) can be taken over, otherwise any existing
+    /// binding causes the bind to fail.
+    pub fn bind(
+        &mut self,
+        port: u16,
+        port_type: PortType,
+        tx: Sender<ClientMessage>,
+        reuse_address: bool,
+    ) -> Result<(), PortInUse> {
+        match self.ports.get(&port) {
+            Some((_, existing)) if !reuse_address || !existing.is_closed() => Err(PortInUse),
+            _ => {
+                self.ports.insert(port, (port_type, tx));
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use present::sync::mpsc;
+
+    #[test_case]
+    fn rebind() {
+        let mut ports = PortTable::new();
+
+        let (tx, rx) = mpsc::unbounded();
+        ports.bind(1234, PortType::Udp, tx, false).unwrap();
+
+        // The owner is still around, so the port can't be taken over
+        let (tx, _) = mpsc::unbounded();
+        assert_eq!(ports.bind(1234, PortType::Udp, tx, true), Err(PortInUse));
+
+        drop(rx);
+
+        // Even with no owner, rebinding is opt-in
+        let (tx, _) = mpsc::unbounded();
+        assert_eq!(ports.bind(1234, PortType::Udp, tx, false), Err(PortInUse));
+
+        let (tx, rx) = mpsc::unbounded();
+        ports.bind(1234, PortType::Udp, tx, true).unwrap();
+        assert!(!ports.get(1234).unwrap().1.is_closed());
+        drop(rx);
+    }
+}
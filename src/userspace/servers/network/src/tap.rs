@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use core::time::Duration;
+
+/// The size of the header preceding each frame in a tap stream: an 8 byte
+/// little endian timestamp in nanoseconds, a 1 byte [`Direction`], 3 bytes of
+/// padding, and a 4 byte little endian frame length
+pub const FRAME_HEADER_LEN: usize = 16;
+
+/// Whether a captured frame was received or transmitted by the interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Direction {
+    Received = 0,
+    Transmitted = 1,
+}
+
+/// A single Ethernet frame captured by a packet tap
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapFrame<'a> {
+    /// When the frame was captured, measured by the platform timer since it
+    /// started counting
+    pub timestamp: Duration,
+    pub direction: Direction,
+    pub data: &'a [u8],
+}
+
+impl TapFrame<'_> {
+    /// The number of bytes the frame takes up in a tap stream
+    pub fn encoded_len(&self) -> usize {
+        FRAME_HEADER_LEN + self.data.len()
+    }
+
+    /// Append the encoded frame to `out`
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.timestamp.as_nanos() as u64).to_le_bytes());
+        out.extend_from_slice(&[self.direction as u8, 0, 0, 0]);
+        out.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(self.data);
+    }
+}
+
+/// An iterator over the frames in a drained tap stream
+pub struct TapFrames<'a> {
+    stream: &'a [u8],
+}
+
+impl<'a> TapFrames<'a> {
+    pub fn new(stream: &'a [u8]) -> Self {
+        Self { stream }
+    }
+}
+
+impl<'a> Iterator for TapFrames<'a> {
+    type Item = TapFrame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.stream.get(..FRAME_HEADER_LEN)?;
+        let timestamp = Duration::from_nanos(u64::from_le_bytes(header[..8].try_into().unwrap()));
+        let direction = match header[8] {
+            0 => Direction::Received,
+            1 => Direction::Transmitted,
+            _ => return None,
+        };
+        let len = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        let data = self.stream.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len)?;
+
+        self.stream = &self.stream[FRAME_HEADER_LEN + len..];
+        Some(TapFrame { timestamp, direction, data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn frame_roundtrip() {
+        let frames = [
+            TapFrame { timestamp: Duration::ZERO, direction: Direction::Received, data: &[1, 2, 3] },
+            TapFrame { timestamp: Duration::from_micros(100), direction: Direction::Transmitted, data: &[] },
+            TapFrame { timestamp: Duration::new(5, 1), direction: Direction::Transmitted, data: &[0xFF; 64] },
+        ];
+
+        let mut stream = Vec::new();
+        for frame in &frames {
+            frame.encode_into(&mut stream);
+        }
+
+        assert_eq!(stream.len(), frames.iter().map(TapFrame::encoded_len).sum::<usize>());
+        assert!(TapFrames::new(&stream).eq(frames));
+        assert_eq!(TapFrames::new(&stream[..stream.len() - 1]).count(), 2);
+    }
+}
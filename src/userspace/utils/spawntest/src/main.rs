@@ -0,0 +1,38 @@
+// SPDX-License-Identifier: MPL-2.0
+// SPDX-FileCopyrightText: 2022 The vanadinite developers
+//
+// This Source Code Form is subject to the terms of the Mozilla Public License,
+// v. 2.0. If a copy of the MPL was not distributed with this file, You can
+// obtain one at https://mozilla.org/MPL/2.0/.
+
+use loadelf::{Command, SpawnError};
+
+const CHILD_EXIT_CODE: i32 = 42;
+const CHILD_ARGS: [&str; 2] = ["child", "second argument"];
+
+// Expects to find itself at `/spawntest` on the filesystem, and re-spawns
+// itself with a `child` argument to act as the child task, which checks it
+// received all of its arguments
+fn main() {
+    if std::env::args().next().as_deref() == Some("child") {
+        // A failed assertion exits with `PANIC_EXIT_CODE`, which the parent
+        // will catch
+        assert_eq!(std::env::args().collect::<Vec<_>>(), CHILD_ARGS);
+        std::process::exit(CHILD_EXIT_CODE);
+    }
+
+    let child = match Command::new("/spawntest").args(CHILD_ARGS).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            println!("spawntest: couldn't spawn `/spawntest`: {:?}", e);
+            std::process::exit(1);
+        }
+    };
+    let status = child.wait().unwrap();
+    assert_eq!(status.code(), CHILD_EXIT_CODE);
+
+    assert_eq!(Command::new("/does-not-exist").spawn().unwrap_err(), SpawnError::FileNotFound);
+    assert_eq!(Command::new("/fat.txt").spawn().unwrap_err(), SpawnError::InvalidElf);
+
+    println!("spawntest: child exited with {}", status.code());
+}
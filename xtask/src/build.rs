@@ -8,7 +8,7 @@
 use crate::{Result, VanadiniteBuildOptions};
 use anyhow::Context;
 use clap::{Subcommand, ValueEnum};
-use std::{fs, io::Write};
+use std::{fs, io::Write, path::Path};
 use tar::{Builder, Header};
 use xshell::{cmd, Shell};
 
@@ -119,29 +119,7 @@ pub fn build(shell: &Shell, target: BuildTarget, quiet: bool) -> Result<()> {
             let out = fs::File::create(init_tar)?;
             let mut archive = Builder::new(out);
 
-            for (bin, path) in walkdir::WalkDir::new("src/userspace/target/riscv64gc-unknown-none-elf/release/")
-                .max_depth(1)
-                .into_iter()
-                .filter_entry(|e| !e.file_name().to_str().map(|s| s.starts_with('.')).unwrap_or(false))
-                .filter_map(|e| e.ok())
-                .map(|e| e.into_path())
-                .filter(|e| e.is_file() && e.extension().is_none())
-                .map(|p| (fs::read(&p), p))
-            {
-                let mut header = Header::new_ustar();
-                let bin = std::io::Cursor::new(bin?);
-                let metadata = fs::metadata(&path)?;
-                let filename = path.file_name().unwrap();
-
-                tracing::debug!("Adding {} to archive", filename.to_str().unwrap());
-
-                header.set_device_major(0)?;
-                header.set_device_minor(0)?;
-                header.set_metadata(&metadata);
-                header.set_cksum();
-
-                archive.append_data(&mut header, filename, bin)?;
-            }
+            append_userspace_binaries(&mut archive)?;
 
             archive.finish()?;
         }
@@ -283,6 +261,40 @@ pub fn build(shell: &Shell, target: BuildTarget, quiet: bool) -> Result<()> {
     Ok(())
 }
 
+/// Add every userspace executable from the last release build to `archive`
+pub fn append_userspace_binaries(archive: &mut Builder<fs::File>) -> Result<()> {
+    for path in walkdir::WalkDir::new("src/userspace/target/riscv64gc-unknown-none-elf/release/")
+        .max_depth(1)
+        .into_iter()
+        .filter_entry(|e| !e.file_name().to_str().map(|s| s.starts_with('.')).unwrap_or(false))
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|e| e.is_file() && e.extension().is_none())
+    {
+        append_executable(archive, path.file_name().unwrap(), &path)?;
+    }
+
+    Ok(())
+}
+
+/// Add the executable at `path` to `archive` under `name`
+pub fn append_executable(archive: &mut Builder<fs::File>, name: impl AsRef<Path>, path: &Path) -> Result<()> {
+    let mut header = Header::new_ustar();
+    let bin = std::io::Cursor::new(fs::read(path)?);
+    let metadata = fs::metadata(path)?;
+
+    tracing::debug!("Adding {} to archive", name.as_ref().display());
+
+    header.set_device_major(0)?;
+    header.set_device_minor(0)?;
+    header.set_metadata(&metadata);
+    header.set_cksum();
+
+    archive.append_data(&mut header, name, bin)?;
+
+    Ok(())
+}
+
 fn check_llvm_tools(shell: &Shell) -> anyhow::Result<()> {
     match cmd!(shell, "rust-objdump --help").quiet().ignore_stdout().ignore_stderr().run() {
         Err(_) => {
@@ -37,6 +37,9 @@ enum Arguments {
     Run(RunOptions),
     /// Test `vanadinite`
     Test(RunOptions),
+    /// Test the userspace libraries and servers, running the ones that need
+    /// `vanadinite` under QEMU
+    TestUserspace(RunOptions),
 }
 
 #[derive(ValueEnum, Clone, Copy)]
@@ -102,6 +105,7 @@ fn main() -> Result<()> {
         Arguments::Clean { target } => clean(&shell, target)?,
         Arguments::Run(target) => runner::run(&shell, target)?,
         Arguments::Test(target) => runner::test(&shell, target)?,
+        Arguments::TestUserspace(target) => runner::test_userspace(&shell, target)?,
     }
 
     Ok(())
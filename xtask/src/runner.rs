@@ -10,7 +10,13 @@ use crate::{
     Result, SbiImpl, Simulator, VanadiniteBuildOptions,
 };
 use clap::Parser;
-use std::path::PathBuf;
+use std::{
+    fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::Stdio,
+};
+use tar::{Builder, Header};
 use xshell::{cmd, Shell};
 
 #[derive(Parser)]
@@ -31,6 +37,11 @@ pub struct RunOptions {
     #[clap(long)]
     drive_file: Option<PathBuf>,
 
+    /// Path to a tar archive of servers for `init` to load instead of the ones
+    /// it was built with, e.g. `build/initfs.tar` after a userspace rebuild
+    #[clap(long)]
+    initrd: Option<PathBuf>,
+
     /// Arguments passed to the kernel
     //#[clap(setting = clap::ArgSettings::AllowEmptyValues)]
     #[clap(long, default_value = "")]
@@ -67,6 +78,7 @@ impl Default for RunOptions {
             debug_log: None,
             debug: false,
             drive_file: None,
+            initrd: None,
             kernel_args: String::new(),
             no_build: false,
             ram: 512,
@@ -102,6 +114,10 @@ pub fn run(shell: &Shell, options: RunOptions) -> Result<()> {
     let cpu_count = options.cpus.to_string();
     let ram = options.ram.to_string();
     let kernel_args = options.kernel_args;
+    let initrd = match &options.initrd {
+        Some(path) => vec![String::from("-initrd"), format!("{}", path.display())],
+        None => vec![],
+    };
 
     let enable_virtio_block_device = match (options.vanadinite_options.platform, &options.drive_file) {
         (Platform::Virt, Some(path)) => vec![
@@ -156,6 +172,7 @@ pub fn run(shell: &Shell, options: RunOptions) -> Result<()> {
                     -device virtio-net-device,netdev=net1
                     -object filter-dump,id=f1,netdev=net1,file=testing_files/nettraffic.dat
                     -kernel {kernel_path}
+                    {initrd...}
                     {debug...}
                     {debug_log...}
             ").run()?;
@@ -192,6 +209,10 @@ pub fn test(shell: &Shell, mut options: RunOptions) -> Result<()> {
     let cpu_count = options.cpus.to_string();
     let ram = options.ram.to_string();
     let kernel_args = options.kernel_args;
+    let initrd = match &options.initrd {
+        Some(path) => vec![String::from("-initrd"), format!("{}", path.display())],
+        None => vec![],
+    };
 
     let debug_log = match &options.debug_log {
         Some(path) => vec![
@@ -215,8 +236,132 @@ pub fn test(shell: &Shell, mut options: RunOptions) -> Result<()> {
             -append {kernel_args}
             -bios build/opensbi-riscv64-generic-fw_jump.bin 
             -kernel src/kernel/target/riscv64imac-unknown-none-elf/debug/vanadinite
+            {initrd...}
             {debug_log...}
     ").run()?;
 
     Ok(())
 }
+
+/// Userspace crates that don't depend on `librust`, whose tests run on the host
+const HOST_TESTED_CRATES: &[&str] =
+    &["alchemy", "bytestream", "checksum", "comb", "elf64", "endian", "fat32", "json", "netstack", "tar", "vidlgen"];
+
+/// Userspace crates that need `vanadinite` to run, whose tests are packed into
+/// the initrd and run by `init`
+const VANADINITE_TESTED_CRATES: &[&str] =
+    &["devicemgr", "dhcp", "filesystem", "materialize", "network", "present", "std"];
+
+pub fn test_userspace(shell: &Shell, mut options: RunOptions) -> Result<()> {
+    // Running from the root directory keeps the userspace cargo config, and
+    // with it the RISC-V target, from applying to the host tests
+    let host_crates = HOST_TESTED_CRATES.iter().flat_map(|&name| ["-p", name]).collect::<Vec<_>>();
+    cmd!(shell, "cargo test --manifest-path src/userspace/Cargo.toml --lib {host_crates...}").run()?;
+    cmd!(shell, "cargo test --manifest-path src/shared/Cargo.toml --lib -p collections").run()?;
+
+    options.vanadinite_options.platform = Platform::Virt;
+    if !options.no_build {
+        build::build(shell, BuildTarget::Vanadinite(options.vanadinite_options.clone()), options.quiet)?;
+    }
+
+    let test_binaries = build_vanadinite_tests(shell)?;
+    let test_tar = std::env::current_dir()?.join("build/testfs.tar");
+    let mut archive = Builder::new(fs::File::create(&test_tar)?);
+    build::append_userspace_binaries(&mut archive)?;
+    for (name, path) in &test_binaries {
+        build::append_executable(&mut archive, name, path)?;
+    }
+
+    // `init` checks that this reads back intact from the initrd
+    let initrd_check = b"vanadinite initrd check\n";
+    let mut header = Header::new_ustar();
+    header.set_size(initrd_check.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive.append_data(&mut header, "initrd-check", &initrd_check[..])?;
+    archive.finish()?;
+
+    let cpu_count = options.cpus.to_string();
+    let ram = options.ram.to_string();
+    let kernel_args = format!("{} init=test user-counters=cycle,time,instret", options.kernel_args);
+    let kernel_args = kernel_args.trim_start();
+
+    // The virtio block device tests need a disk to read from, so a zeroed one
+    // is made for them if none was given
+    let drive_file = match options.drive_file {
+        Some(path) => path,
+        None => {
+            let path = std::env::current_dir()?.join("build/testdisk.img");
+            fs::write(&path, vec![0u8; 64 * 512])?;
+            path
+        }
+    };
+    let drive = format!("file={},if=none,format=raw,id=hd", drive_file.display());
+
+    let kernel_path = match options.vanadinite_options.debug_build {
+        true => "src/kernel/target/riscv64imac-unknown-none-elf/debug/vanadinite",
+        false => "src/kernel/target/riscv64imac-unknown-none-elf/release/vanadinite",
+    };
+
+    #[rustfmt::skip]
+    let qemu = cmd!(shell, "
+        qemu-system-riscv64
+            -machine virt
+            -cpu rv64
+            -smp {cpu_count}
+            -m {ram}M
+            -append {kernel_args}
+            -global virtio-mmio.force-legacy=false
+            -drive {drive}
+            -device virtio-blk-device,drive=hd
+            -kernel {kernel_path}
+            -initrd {test_tar}
+            -serial mon:stdio
+            -nographic
+    ");
+
+    // The SBI shutdown `init` does when it's done doesn't carry the result
+    // back out of QEMU, so look for its summary of the test runs instead
+    let mut qemu = std::process::Command::from(qemu).stdout(Stdio::piped()).spawn()?;
+    let mut summary = None;
+    for line in BufReader::new(qemu.stdout.take().unwrap()).lines() {
+        let line = line?;
+        println!("{}", line);
+
+        if let Some(result) = line.trim_end().strip_prefix("[init] test result: ") {
+            summary = Some(result.to_string());
+        }
+    }
+    qemu.wait()?;
+
+    match summary {
+        Some(summary) if summary.ends_with(" 0 failed") => Ok(()),
+        Some(summary) => Err(anyhow::anyhow!("Userspace tests failed: {}", summary)),
+        None => Err(anyhow::anyhow!("QEMU exited before the userspace tests finished")),
+    }
+}
+
+/// Build the test binaries for [`VANADINITE_TESTED_CRATES`], returning the name
+/// each should be given in the initrd along with its path
+fn build_vanadinite_tests(shell: &Shell) -> Result<Vec<(String, PathBuf)>> {
+    let _dir = shell.push_dir("src/userspace");
+    let crates = VANADINITE_TESTED_CRATES.iter().flat_map(|&name| ["-p", name]).collect::<Vec<_>>();
+    let messages = cmd!(shell, "cargo test --no-run --release --message-format=json {crates...}").read()?;
+
+    let mut binaries = Vec::new();
+    for message in messages.lines().filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok()) {
+        // Binaries are also built for any integration tests, so only take the
+        // test harnesses themselves
+        if message["profile"]["test"].as_bool() != Some(true) {
+            continue;
+        }
+
+        if let (Some(name), Some(kind), Some(executable)) =
+            (message["target"]["name"].as_str(), message["target"]["kind"][0].as_str(), message["executable"].as_str())
+        {
+            binaries.push((format!("test-{}-{}", name, kind), PathBuf::from(executable)));
+        }
+    }
+
+    Ok(binaries)
+}